@@ -0,0 +1,14 @@
+//! Arbitrary bytes as a block from a peer: `cargo +nightly fuzz run execute_block`.
+
+#![no_main]
+
+use std::sync::OnceLock;
+
+use blockchain::{fuzzing, Runtime};
+use libfuzzer_sys::fuzz_target;
+
+static GENESIS: OnceLock<Runtime> = OnceLock::new();
+
+fuzz_target!(|data: &[u8]| {
+	fuzzing::execute_block(GENESIS.get_or_init(fuzzing::genesis), data);
+});
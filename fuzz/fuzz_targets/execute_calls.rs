@@ -0,0 +1,14 @@
+//! Arbitrary bytes as the calls of signed extrinsics: `cargo +nightly fuzz run execute_calls`.
+
+#![no_main]
+
+use std::sync::OnceLock;
+
+use blockchain::{fuzzing, Runtime};
+use libfuzzer_sys::fuzz_target;
+
+static GENESIS: OnceLock<Runtime> = OnceLock::new();
+
+fuzz_target!(|data: &[u8]| {
+	fuzzing::execute_calls(GENESIS.get_or_init(fuzzing::genesis), data);
+});
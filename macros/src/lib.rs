@@ -0,0 +1,579 @@
+//! Macros of the runtime. The crate has no parser dependencies, so the input is read from its
+//! tokens, which is enough for the plain enums and impl blocks the macros are used on.
+
+use std::iter::Peekable;
+
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+
+/// Implements `support::Dispatch` for the `Pallet` of a `Call` enum: every variant is dispatched
+/// to the method of the pallet named after it in snake case, with the origin and then the fields
+/// of the variant, e.g. `SetKeys { keys }` to `self.set_keys(origin, keys)`. The origin is the
+/// `system::OriginFor` of the first generic parameter, the config of the pallet.
+#[proc_macro_derive(Dispatchable)]
+pub fn derive_dispatchable(input: TokenStream) -> TokenStream {
+	match dispatchable(input) {
+		Ok(output) => output.parse().expect("The generated impl is valid Rust"),
+		Err(message) => format!("compile_error!({message:?});").parse().expect("A valid error"),
+	}
+}
+
+fn dispatchable(input: TokenStream) -> Result<String, &'static str> {
+	let mut tokens = input.into_iter().peekable();
+	// Attributes and the visibility come first.
+	loop {
+		match tokens.next() {
+			Some(TokenTree::Ident(ident)) if ident.to_string() == "enum" => break,
+			Some(TokenTree::Ident(ident)) if ["struct", "union"].contains(&&*ident.to_string()) => {
+				return Err("Dispatchable can only be derived for enums");
+			},
+			Some(_) => {},
+			None => return Err("Dispatchable can only be derived for enums"),
+		}
+	}
+	let Some(TokenTree::Ident(name)) = tokens.next() else {
+		return Err("Expected the name of the enum");
+	};
+
+	let generics = generics(&mut tokens);
+	let mut where_clause = Vec::new();
+	let body = loop {
+		match tokens.next() {
+			Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => {
+				break group.stream();
+			},
+			Some(token) => where_clause.push(token),
+			None => return Err("Expected the variants of the enum"),
+		}
+	};
+
+	let params = param_names(&generics);
+	let Some(config) = params.first() else {
+		return Err("Dispatchable needs the config of the pallet as the first generic parameter");
+	};
+
+	let mut arms = String::new();
+	for variant in split_commas(body) {
+		let mut variant = skip_attributes(variant).into_iter();
+		let Some(TokenTree::Ident(variant_name)) = variant.next() else {
+			return Err("Expected the name of a variant");
+		};
+		let fields = match variant.next() {
+			Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => {
+				let mut fields = Vec::new();
+				for field in split_commas(group.stream()) {
+					let field = skip_attributes(field).into_iter().find_map(|token| match token {
+						TokenTree::Ident(ident) if ident.to_string() != "pub" => Some(ident),
+						_ => None,
+					});
+					fields.push(field.ok_or("Expected the name of a field")?.to_string());
+				}
+				fields
+			},
+			Some(TokenTree::Group(_)) => return Err("Dispatchable needs named fields"),
+			_ => Vec::new(),
+		};
+		let method = snake_case(&variant_name.to_string());
+		let args: String = fields.iter().map(|field| format!(", {field}")).collect();
+		arms += &format!(
+			"{name}::{variant_name} {{ {} }} => self.{method}(origin{args}),",
+			fields.join(", "),
+		);
+	}
+
+	let (generics, params) = (to_string(generics), params.join(", "));
+	let where_clause = to_string(where_clause);
+	Ok(format!(
+		"impl<{generics}> crate::support::Dispatch for Pallet<{params}> {where_clause} {{
+			type Call = {name}<{params}>;
+			type Origin = crate::system::OriginFor<{config}>;
+
+			fn dispatch(
+				&mut self,
+				origin: Self::Origin,
+				call: Self::Call,
+			) -> crate::support::DispatchResult {{
+				match call {{ {arms} }}
+			}}
+		}}"
+	))
+}
+
+/// Declares the calls of a pallet on an `impl` block of its `Pallet`, each a function with a
+/// `#[call(index = .., weight = ..)]` attribute taking the fields of the call, and generates
+///
+/// - the `Call` enum, a variant per function named after it in camel case, with its encoding,
+///   `index`, `weight` and `metadata`,
+/// - the dispatch function, taking the parameters in `dispatch(..)`: the `Dispatch` impl of the
+///   pallet if it only takes the `origin`, and else an inherent `dispatch` method, e.g. for
+///   pallets that need other pallets to dispatch,
+/// - and the `Error` enum of the variants in `errors(..)`, each with its message, which it
+///   converts into, as `DispatchResult`s carry messages.
+///
+/// The functions aren't kept as methods: their bodies are the arms of the dispatch function, so
+/// they see its parameters, `self` and the fields of the call, and may call methods of the same
+/// name. The weight expressions see the fields by reference. The doc comments of the block
+/// document the dispatch function, and those of the functions the variants.
+///
+/// ```ignore
+/// #[pallet(dispatch(origin: OriginFor<T>), errors(NotFound = "Not found"))]
+/// impl<T: Config> Pallet<T> {
+///     /// Root only: overwrite the balance of an account.
+///     #[call(index = 2, weight = 5_000)]
+///     fn set_balance(who: T::AccountId, amount: T::Balance) -> DispatchResult {
+///         support::ensure_root(origin)?;
+///         self.set_balance(&who, amount);
+///         Ok(())
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn pallet(args: TokenStream, item: TokenStream) -> TokenStream {
+	match pallet_calls(args, item) {
+		Ok(output) => output.parse().expect("The generated items are valid Rust"),
+		Err(message) => format!("compile_error!({message:?});").parse().expect("A valid error"),
+	}
+}
+
+/// A parameter of the dispatch function or a field of a call.
+struct Param {
+	name: String,
+	ty: String,
+}
+
+struct PalletCall {
+	docs: String,
+	name: String,
+	index: String,
+	weight: String,
+	fields: Vec<Param>,
+	/// The return type of the function, which its body is checked against.
+	result: String,
+	body: String,
+}
+
+fn pallet_calls(args: TokenStream, item: TokenStream) -> Result<String, &'static str> {
+	let (mut dispatch, mut errors) = (Vec::new(), Vec::new());
+	for arg in split_commas(args) {
+		let mut arg = arg.into_iter();
+		let (Some(TokenTree::Ident(name)), Some(TokenTree::Group(group)), None) =
+			(arg.next(), arg.next(), arg.next())
+		else {
+			return Err("Expected `dispatch(..)` or `errors(..)`");
+		};
+		let items = split_commas(group.stream());
+		match &*name.to_string() {
+			"dispatch" => dispatch = items.into_iter().map(param).collect::<Result<_, _>>()?,
+			"errors" => errors = items.into_iter().map(error).collect::<Result<_, _>>()?,
+			_ => return Err("Expected `dispatch(..)` or `errors(..)`"),
+		}
+	}
+	let Some(origin) = dispatch.iter().find(|param| param.name == "origin") else {
+		return Err("The dispatch function needs an `origin`");
+	};
+
+	let mut tokens = item.into_iter().peekable();
+	let mut docs = Vec::new();
+	loop {
+		match tokens.next() {
+			Some(TokenTree::Ident(ident)) if ident.to_string() == "impl" => break,
+			Some(token) => docs.push(token),
+			None => return Err("#[pallet] goes on an impl block of the pallet"),
+		}
+	}
+	let generics = generics(&mut tokens);
+	let (mut pallet, mut where_clause) = (Vec::new(), Vec::new());
+	let body = loop {
+		match tokens.next() {
+			Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => {
+				break group.stream();
+			},
+			Some(token) if !where_clause.is_empty() || token.to_string() == "where" => {
+				where_clause.push(token);
+			},
+			Some(token) => pallet.push(token),
+			None => return Err("Expected the calls of the pallet"),
+		}
+	};
+	let params = param_names(&generics).join(", ");
+	let (generics, pallet) = (to_string(generics), to_string(pallet));
+	let (where_clause, docs) = (to_string(where_clause), to_string(docs));
+	let calls = calls(body)?;
+	if calls.is_empty() {
+		return Err("#[pallet] needs at least one call");
+	}
+
+	let (mut variants, mut encode, mut decode) = (String::new(), String::new(), String::new());
+	let (mut indices, mut weights, mut metadata) = (String::new(), String::new(), String::new());
+	let mut arms = String::new();
+	for PalletCall { docs, name, index, weight, fields, result, body } in &calls {
+		let names: Vec<_> = fields.iter().map(|field| field.name.as_str()).collect();
+		let pattern = format!("Call::{name} {{ {} }}", names.join(", "));
+		let typed: Vec<_> =
+			fields.iter().map(|field| format!("{}: {}", field.name, field.ty)).collect();
+		variants += &format!("{docs} {name} {{ {} }},", typed.join(", "));
+		let encoded: String = fields
+			.iter()
+			.map(|field| format!("crate::codec::Encode::encode_to({}, dest);", field.name))
+			.collect();
+		encode += &format!(
+			"{pattern} => {{ crate::codec::Encode::encode_to(&({index} as u8), dest); {encoded} }},"
+		);
+		let decoded: Vec<_> = fields
+			.iter()
+			.map(|field| format!("{}: crate::codec::Decode::decode(input)?", field.name))
+			.collect();
+		decode += &format!("{index} => Call::{name} {{ {} }},", decoded.join(", "));
+		indices += &format!("Call::{name} {{ .. }} => {index},");
+		weights += &format!("#[allow(unused_variables)] {pattern} => {weight},");
+		let fields: Vec<_> = fields
+			.iter()
+			.map(|field| format!("crate::metadata::field::<{}>({:?})", field.ty, field.name))
+			.collect();
+		metadata +=
+			&format!("crate::metadata::variant({name:?}, {index}, vec![{}]),", fields.join(", "));
+		arms += &format!("{pattern} => {{ let result: {result} = {body}; result }},");
+	}
+
+	let mut output = format!(
+		"#[derive(Debug, Clone)]
+		pub enum Call<{generics}> {where_clause} {{ {variants} }}
+
+		impl<{generics}> crate::codec::Encode for Call<{params}> {where_clause} {{
+			fn encode_to(&self, dest: &mut Vec<u8>) {{
+				match self {{ {encode} }}
+			}}
+		}}
+
+		impl<{generics}> crate::codec::Decode for Call<{params}> {where_clause} {{
+			fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {{
+				Ok(match <u8 as crate::codec::Decode>::decode(input)? {{
+					{decode}
+					_ => return Err(\"Invalid call\"),
+				}})
+			}}
+		}}
+
+		impl<{generics}> Call<{params}> {where_clause} {{
+			/// The index of the call in the pallet, the first byte of its encoding.
+			pub fn index(&self) -> u8 {{
+				match self {{ {indices} }}
+			}}
+
+			pub fn weight(&self) -> crate::support::Weight {{
+				match self {{ {weights} }}
+			}}
+
+			/// The calls as listed in the metadata of the pallet.
+			pub fn metadata() -> Vec<crate::metadata::VariantMetadata> {{
+				vec![{metadata}]
+			}}
+		}}"
+	);
+	if dispatch.len() == 1 {
+		output += &format!(
+			"{docs}
+			impl<{generics}> crate::support::Dispatch for {pallet} {where_clause} {{
+				type Call = Call<{params}>;
+				type Origin = {};
+
+				fn dispatch(
+					&mut self,
+					origin: Self::Origin,
+					call: Self::Call,
+				) -> crate::support::DispatchResult {{
+					match call {{ {arms} }}
+				}}
+			}}",
+			origin.ty
+		);
+	} else {
+		let dispatch: Vec<_> =
+			dispatch.iter().map(|param| format!("{}: {}", param.name, param.ty)).collect();
+		output += &format!(
+			"impl<{generics}> {pallet} {where_clause} {{
+				{docs}
+				pub fn dispatch(
+					&mut self,
+					{},
+					call: Call<{params}>,
+				) -> crate::support::DispatchResult {{
+					match call {{ {arms} }}
+				}}
+			}}",
+			dispatch.join(", ")
+		);
+	}
+	if !errors.is_empty() {
+		let variants: String = errors
+			.iter()
+			.map(|(name, message)| format!("#[doc = {message}] {name},"))
+			.collect();
+		let messages: String = errors
+			.iter()
+			.map(|(name, message)| format!("Error::{name} => {message},"))
+			.collect();
+		let all: Vec<_> = errors.iter().map(|(name, _)| format!("Error::{name}")).collect();
+		output += &format!(
+			"#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+			pub enum Error {{ {variants} }}
+
+			impl Error {{
+				/// Every error, in the order of the metadata of the pallet, which
+				/// `ModuleError` indexes them by.
+				pub const ALL: &'static [Error] = &[{}];
+
+				pub fn message(self) -> &'static str {{
+					match self {{ {messages} }}
+				}}
+
+				/// The messages of the errors as listed in the metadata of the pallet.
+				pub fn metadata() -> Vec<&'static str> {{
+					Self::ALL.iter().map(|error| error.message()).collect()
+				}}
+			}}
+
+			impl From<Error> for &'static str {{
+				fn from(error: Error) -> Self {{
+					error.message()
+				}}
+			}}",
+			all.join(", ")
+		);
+	}
+	Ok(output)
+}
+
+/// A parameter of the dispatch function, or a field of a call: `name: type`.
+fn param(tokens: Vec<TokenTree>) -> Result<Param, &'static str> {
+	let mut tokens = tokens.into_iter();
+	let (Some(TokenTree::Ident(name)), Some(colon)) = (tokens.next(), tokens.next()) else {
+		return Err("Expected `name: type`");
+	};
+	if !is_punct(&colon, ':') {
+		return Err("Expected `name: type`");
+	}
+	Ok(Param { name: name.to_string(), ty: to_string(tokens) })
+}
+
+/// An error of `errors(..)`: `Name = "message"`.
+fn error(tokens: Vec<TokenTree>) -> Result<(String, String), &'static str> {
+	match &tokens[..] {
+		[TokenTree::Ident(name), equals, TokenTree::Literal(message)] if is_punct(equals, '=') => {
+			Ok((name.to_string(), message.to_string()))
+		},
+		_ => Err("Expected `Name = \"message\"`"),
+	}
+}
+
+/// The functions in the body of a `#[pallet]` impl block.
+fn calls(body: TokenStream) -> Result<Vec<PalletCall>, &'static str> {
+	let mut tokens = body.into_iter().peekable();
+	let mut calls = Vec::new();
+	while tokens.peek().is_some() {
+		let (mut docs, mut attribute) = (Vec::new(), None);
+		while tokens.peek().is_some_and(|token| is_punct(token, '#')) {
+			let pound = tokens.next().expect("Peeked");
+			let Some(TokenTree::Group(group)) = tokens.next() else {
+				return Err("Expected an attribute");
+			};
+			let mut inner = group.stream().into_iter();
+			match (inner.next(), inner.next()) {
+				(Some(TokenTree::Ident(ident)), Some(TokenTree::Group(args)))
+					if ident.to_string() == "call" =>
+				{
+					attribute = Some(args.stream());
+				},
+				_ => docs.extend([pound, TokenTree::Group(group)]),
+			}
+		}
+		let mut name = None;
+		let (mut fields, mut after_fn) = (None, false);
+		let mut result: Option<Vec<TokenTree>> = None;
+		let body = loop {
+			match tokens.next() {
+				Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => {
+					break group;
+				},
+				Some(TokenTree::Group(group))
+					if group.delimiter() == Delimiter::Parenthesis && fields.is_none() =>
+				{
+					let params = split_commas(group.stream()).into_iter().map(param);
+					fields = Some(params.collect::<Result<Vec<_>, _>>()?);
+				},
+				Some(TokenTree::Ident(ident)) if after_fn && name.is_none() => {
+					name = Some(ident.to_string());
+				},
+				Some(TokenTree::Ident(ident)) if ident.to_string() == "fn" => after_fn = true,
+				Some(token) => match &mut result {
+					Some(result) => result.push(token),
+					// The return type follows the `>` of the arrow.
+					None if fields.is_some() && is_punct(&token, '>') => result = Some(Vec::new()),
+					None => {},
+				},
+				None => return Err("Expected the body of a call"),
+			}
+		};
+		let (Some(name), Some(fields)) = (name, fields) else {
+			return Err("Expected a function");
+		};
+		let result = result.ok_or("Calls return a `DispatchResult`")?;
+		let attribute = attribute.ok_or("Calls need a `#[call(index = .., weight = ..)]`")?;
+		let (mut index, mut weight) = (None, None);
+		for arg in split_commas(attribute) {
+			let mut arg = arg.into_iter();
+			let (Some(TokenTree::Ident(key)), Some(equals)) = (arg.next(), arg.next()) else {
+				return Err("Expected `index = ..` or `weight = ..`");
+			};
+			match &*key.to_string() {
+				"index" if is_punct(&equals, '=') => index = Some(to_string(arg)),
+				"weight" if is_punct(&equals, '=') => weight = Some(to_string(arg)),
+				_ => return Err("Expected `index = ..` or `weight = ..`"),
+			}
+		}
+		calls.push(PalletCall {
+			docs: to_string(docs),
+			name: camel_case(&name),
+			index: index.ok_or("Calls need an index")?,
+			weight: weight.ok_or("Calls need a weight")?,
+			fields,
+			result: to_string(result),
+			body: TokenTree::Group(body).to_string(),
+		});
+	}
+	Ok(calls)
+}
+
+/// The generic parameters `tokens` continue with, if any, without the angle brackets.
+fn generics(tokens: &mut Peekable<impl Iterator<Item = TokenTree>>) -> Vec<TokenTree> {
+	let mut generics = Vec::new();
+	if tokens.peek().is_some_and(|token| is_punct(token, '<')) {
+		tokens.next();
+		let mut depth = 1;
+		for token in tokens.by_ref() {
+			if let TokenTree::Punct(punct) = &token {
+				match punct.as_char() {
+					'<' => depth += 1,
+					'>' => depth -= 1,
+					_ => {},
+				}
+			}
+			if depth == 0 {
+				break;
+			}
+			generics.push(token);
+		}
+	}
+	generics
+}
+
+/// The names of the parameters in `generics`, as the arguments of the type they are declared on.
+fn param_names(generics: &[TokenTree]) -> Vec<String> {
+	split_commas(generics.to_vec())
+		.into_iter()
+		.map(|param| {
+			let name = param
+				.into_iter()
+				.take_while(|token| !is_punct(token, ':') && !is_punct(token, '='));
+			to_string(name.filter(|token| token.to_string() != "const"))
+		})
+		.collect()
+}
+
+fn is_punct(token: &TokenTree, c: char) -> bool {
+	matches!(token, TokenTree::Punct(punct) if punct.as_char() == c)
+}
+
+fn to_string(tokens: impl IntoIterator<Item = TokenTree>) -> String {
+	tokens.into_iter().collect::<TokenStream>().to_string()
+}
+
+/// Split `tokens` at the commas that aren't between angle brackets, e.g. of generic types.
+fn split_commas(tokens: impl IntoIterator<Item = TokenTree>) -> Vec<Vec<TokenTree>> {
+	let (mut parts, mut part, mut depth) = (Vec::new(), Vec::new(), 0);
+	let mut after_dash = false;
+	for token in tokens {
+		let mut dash = false;
+		if let TokenTree::Punct(punct) = &token {
+			match punct.as_char() {
+				'<' => depth += 1,
+				// Not the end of a generic type, but the arrow of a function type.
+				'>' if after_dash => {},
+				'>' => depth -= 1,
+				'-' => dash = true,
+				',' if depth == 0 => {
+					parts.push(std::mem::take(&mut part));
+					after_dash = false;
+					continue;
+				},
+				_ => {},
+			}
+		}
+		after_dash = dash;
+		part.push(token);
+	}
+	if !part.is_empty() {
+		parts.push(part);
+	}
+	parts
+}
+
+/// `tokens` without the attributes they start with, e.g. doc comments.
+fn skip_attributes(tokens: Vec<TokenTree>) -> Vec<TokenTree> {
+	let mut tokens = tokens.into_iter().peekable();
+	while tokens.peek().is_some_and(|token| is_punct(token, '#')) {
+		tokens.next();
+		tokens.next();
+	}
+	tokens.collect()
+}
+
+/// `name` in snake case, keeping acronyms together, e.g. `ForceSetURL` is `force_set_url`.
+fn snake_case(name: &str) -> String {
+	let chars: Vec<char> = name.chars().collect();
+	let mut snake = String::new();
+	for (i, &c) in chars.iter().enumerate() {
+		if c.is_uppercase() && i > 0 {
+			let after_lower = !chars[i - 1].is_uppercase();
+			let before_lower = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+			if after_lower || (chars[i - 1].is_uppercase() && before_lower) {
+				snake.push('_');
+			}
+		}
+		snake.extend(c.to_lowercase());
+	}
+	snake
+}
+
+/// `name` in camel case, e.g. `force_set_url` is `ForceSetUrl`.
+fn camel_case(name: &str) -> String {
+	let words = name.split('_').filter(|word| !word.is_empty());
+	words
+		.map(|word| {
+			let mut chars = word.chars();
+			chars
+				.next()
+				.map_or(String::new(), |first| first.to_uppercase().chain(chars).collect())
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::{camel_case, snake_case};
+
+	#[test]
+	fn variants_map_to_snake_case_methods() {
+		assert_eq!(snake_case("Set"), "set");
+		assert_eq!(snake_case("ScheduleNamed"), "schedule_named");
+		assert_eq!(snake_case("ForceSetURL"), "force_set_url");
+		assert_eq!(snake_case("URLTransfer"), "url_transfer");
+		assert_eq!(snake_case("SetV2Keys"), "set_v2_keys");
+	}
+
+	#[test]
+	fn call_functions_map_to_camel_case_variants() {
+		assert_eq!(camel_case("transfer"), "Transfer");
+		assert_eq!(camel_case("transfer_many"), "TransferMany");
+		assert_eq!(camel_case("set_v2_keys"), "SetV2Keys");
+	}
+}
@@ -0,0 +1,346 @@
+//! Accounts of the runtime are 32-byte public keys, written as SS58 addresses: the base58
+//! encoding of a network prefix, the key and a checksum. A mistyped address or one meant for
+//! another network is rejected, instead of sending funds to an account nobody controls.
+//! Transfers address their recipient with a `MultiAddress`, which may also be a public key or a
+//! registered name.
+//!
+//! The pallets are generic over the account type, so their tests still use plain strings.
+
+use std::{fmt, str::FromStr};
+
+use crate::{
+	codec::{Decode, Encode},
+	hashing,
+	metadata::{Registry, TypeDef, TypeInfo, TypeVariant},
+	signature::MultiSigner,
+};
+
+/// The network prefix of the addresses of this chain, the generic one of Substrate chains.
+pub const SS58_PREFIX: u16 = 42;
+
+const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const CHECKSUM_LEN: usize = 2;
+
+/// An account of the runtime. It is 32 bytes, so cloning one, e.g. for every nonce bump or
+/// transfer, copies the bytes without allocating.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AccountId32([u8; 32]);
+
+impl AccountId32 {
+	pub const fn new(public: [u8; 32]) -> Self {
+		Self(public)
+	}
+
+	/// The account of a pallet, which no key controls: `modl` followed by `id`, zero-padded.
+	pub fn from_pallet_id(id: &[u8; 8]) -> Self {
+		let mut bytes = [0; 32];
+		bytes[..4].copy_from_slice(b"modl");
+		bytes[4..12].copy_from_slice(id);
+		Self(bytes)
+	}
+
+	pub fn to_ss58(&self, prefix: u16) -> String {
+		let mut address = String::new();
+		self.write_ss58(prefix, &mut address).expect("Writing to a string succeeds");
+		address
+	}
+
+	/// `to_ss58` into `out`, without allocating, as accounts are written for every extrinsic
+	/// logged, see `logging::span`.
+	fn write_ss58(&self, prefix: u16, out: &mut impl fmt::Write) -> fmt::Result {
+		let mut bytes = [0; 2 + 32 + CHECKSUM_LEN];
+		let (prefix, prefix_len) = encode_prefix(prefix);
+		let prefix = &prefix[..prefix_len];
+		let body_len = prefix.len() + 32;
+		bytes[..prefix.len()].copy_from_slice(prefix);
+		bytes[prefix.len()..body_len].copy_from_slice(&self.0);
+		let checksum = checksum(&bytes[..body_len]);
+		bytes[body_len..body_len + CHECKSUM_LEN].copy_from_slice(&checksum);
+		base58_write(&bytes[..body_len + CHECKSUM_LEN], out)
+	}
+
+	/// The account and network prefix of an SS58 address.
+	pub fn from_ss58(address: &str) -> Result<(Self, u16), &'static str> {
+		let bytes = base58_decode(address).ok_or("Invalid address")?;
+		let prefix_len = match bytes.first() {
+			Some(0..=63) => 1,
+			Some(64..=127) => 2,
+			_ => return Err("Invalid address"),
+		};
+		if bytes.len() != prefix_len + 32 + CHECKSUM_LEN {
+			return Err("Invalid address length");
+		}
+		let (body, checksum_bytes) = bytes.split_at(prefix_len + 32);
+		if checksum(body) != checksum_bytes {
+			return Err("Invalid address checksum");
+		}
+		let prefix = match prefix_len {
+			1 => body[0] as u16,
+			_ => {
+				let lower = (body[0] << 2) | (body[1] >> 6);
+				let upper = body[1] & 0b0011_1111;
+				lower as u16 | (upper as u16) << 8
+			},
+		};
+		let public = body[prefix_len..].try_into().expect("Length checked above");
+		Ok((Self(public), prefix))
+	}
+}
+
+/// Prefixes below 64 take one byte, the others up to 16383 take two.
+/// The prefix is the first byte, or the first two, of the array.
+fn encode_prefix(prefix: u16) -> ([u8; 2], usize) {
+	match prefix {
+		0..=63 => ([prefix as u8, 0], 1),
+		_ => (
+			[
+				((prefix & 0b1111_1100) >> 2) as u8 | 0b0100_0000,
+				(prefix >> 8) as u8 | ((prefix & 0b11) << 6) as u8,
+			],
+			2,
+		),
+	}
+}
+
+fn checksum(body: &[u8]) -> [u8; CHECKSUM_LEN] {
+	let mut preimage = [0; 7 + 2 + 32];
+	preimage[..7].copy_from_slice(b"SS58PRE");
+	preimage[7..7 + body.len()].copy_from_slice(body);
+	let hash = hashing::blake2b::<64>(&preimage[..7 + body.len()]);
+	[hash[0], hash[1]]
+}
+
+/// The base58 encoding of `bytes`, of an address at most, written to `out`.
+fn base58_write(bytes: &[u8], out: &mut impl fmt::Write) -> fmt::Result {
+	// Base 58 digits, least significant first: 58 is more than 2^5, so there are fewer digits
+	// than 8 / 5 of the bytes.
+	let mut digits = [0u8; (2 + 32 + CHECKSUM_LEN) * 8 / 5];
+	let mut len = 0;
+	for &byte in bytes {
+		let mut carry = byte as u32;
+		for digit in digits[..len].iter_mut() {
+			carry += (*digit as u32) << 8;
+			*digit = (carry % 58) as u8;
+			carry /= 58;
+		}
+		while carry > 0 {
+			digits[len] = (carry % 58) as u8;
+			len += 1;
+			carry /= 58;
+		}
+	}
+	// Every leading zero byte is written as a leading `1`.
+	for _ in bytes.iter().take_while(|&&byte| byte == 0) {
+		out.write_char('1')?;
+	}
+	for &digit in digits[..len].iter().rev() {
+		out.write_char(ALPHABET[digit as usize] as char)?;
+	}
+	Ok(())
+}
+
+fn base58_decode(text: &str) -> Option<Vec<u8>> {
+	// Bytes, least significant first.
+	let mut bytes: Vec<u8> = Vec::new();
+	for c in text.bytes() {
+		let mut carry = ALPHABET.iter().position(|&a| a == c)? as u32;
+		for byte in bytes.iter_mut() {
+			carry += *byte as u32 * 58;
+			*byte = carry as u8;
+			carry >>= 8;
+		}
+		while carry > 0 {
+			bytes.push(carry as u8);
+			carry >>= 8;
+		}
+	}
+	let zeros = text.bytes().take_while(|&c| c == b'1').count();
+	Some(std::iter::repeat_n(0, zeros).chain(bytes.into_iter().rev()).collect())
+}
+
+impl From<[u8; 32]> for AccountId32 {
+	fn from(public: [u8; 32]) -> Self {
+		Self(public)
+	}
+}
+
+impl AsRef<[u8]> for AccountId32 {
+	fn as_ref(&self) -> &[u8] {
+		&self.0
+	}
+}
+
+impl fmt::Display for AccountId32 {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.write_ss58(SS58_PREFIX, f)
+	}
+}
+
+impl fmt::Debug for AccountId32 {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Display::fmt(self, f)
+	}
+}
+
+/// Only addresses of this network are accepted.
+impl FromStr for AccountId32 {
+	type Err = &'static str;
+
+	fn from_str(address: &str) -> Result<Self, Self::Err> {
+		match Self::from_ss58(address)? {
+			(account, SS58_PREFIX) => Ok(account),
+			_ => Err("Address of another network"),
+		}
+	}
+}
+
+impl Encode for AccountId32 {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		self.0.encode_to(dest);
+	}
+}
+
+impl Decode for AccountId32 {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(Self(Decode::decode(input)?))
+	}
+}
+
+impl TypeInfo for AccountId32 {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		TypeDef::Composite(vec![registry.unnamed::<[u8; 32]>()])
+	}
+}
+
+/// How a call addresses an account: by the account itself, the public key it is derived from,
+/// or a name registered with the names pallet. The runtime resolves addresses with its
+/// `AccountLookup` when dispatching.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MultiAddress {
+	Id(AccountId32),
+	PublicKey(MultiSigner),
+	Name(Vec<u8>),
+}
+
+impl From<AccountId32> for MultiAddress {
+	fn from(account: AccountId32) -> Self {
+		MultiAddress::Id(account)
+	}
+}
+
+/// A name following `@`, e.g. `@alice`, or else an SS58 address of this network, so a mistyped
+/// address is still rejected rather than taken for a name.
+impl FromStr for MultiAddress {
+	type Err = &'static str;
+
+	fn from_str(address: &str) -> Result<Self, Self::Err> {
+		match address.strip_prefix('@') {
+			Some(name) => Ok(MultiAddress::Name(name.as_bytes().to_vec())),
+			None => address.parse().map(MultiAddress::Id),
+		}
+	}
+}
+
+impl Encode for MultiAddress {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			MultiAddress::Id(account) => (0u8, account).encode_to(dest),
+			MultiAddress::PublicKey(signer) => (1u8, signer).encode_to(dest),
+			MultiAddress::Name(name) => (2u8, name).encode_to(dest),
+		}
+	}
+}
+
+impl Decode for MultiAddress {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		match u8::decode(input)? {
+			0 => Ok(MultiAddress::Id(Decode::decode(input)?)),
+			1 => Ok(MultiAddress::PublicKey(Decode::decode(input)?)),
+			2 => Ok(MultiAddress::Name(Decode::decode(input)?)),
+			_ => Err("Invalid address"),
+		}
+	}
+}
+
+impl TypeInfo for MultiAddress {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		TypeDef::Variant(vec![
+			TypeVariant { name: "Id", index: 0, fields: vec![registry.unnamed::<AccountId32>()] },
+			TypeVariant {
+				name: "PublicKey",
+				index: 1,
+				fields: vec![registry.unnamed::<MultiSigner>()],
+			},
+			TypeVariant { name: "Name", index: 2, fields: vec![registry.unnamed::<Vec<u8>>()] },
+		])
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{AccountId32, MultiAddress};
+	use crate::{
+		codec::{decode_all, Encode},
+		genesis::{self, GenesisBuilder},
+		names,
+		signature::Scheme,
+		wallet, RuntimeCall,
+	};
+
+	const ALICE: &str = "d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d";
+
+	fn alice() -> AccountId32 {
+		let public = crate::hashing::from_hex(ALICE).unwrap();
+		AccountId32::new(public.try_into().unwrap())
+	}
+
+	#[test]
+	fn addresses_match_substrate() {
+		let alice = alice();
+		assert_eq!(alice.to_string(), "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY");
+		assert_eq!(alice.to_ss58(0), "15oF4uVJwmo4TdGW7VfQxNLavjCXviqxT9S1MgbjMNHr6Sp5");
+		let two_byte_prefix = "VdvKmYJfD4VXA9fzz1SbmCo2eYHSzUFbaDCZSuaNKJAe8YNg6";
+		assert_eq!(alice.to_ss58(1284), two_byte_prefix);
+		assert_eq!(AccountId32::from_ss58(two_byte_prefix), Ok((alice, 1284)));
+		let zero = AccountId32::new([0; 32]);
+		assert_eq!(zero.to_string(), "5C4hrfjw9DjXZTzV3MwzrrAr9P1MJhSrvWGWqi1eSuyUpnhM");
+		assert_eq!(zero.to_string().parse(), Ok(zero));
+	}
+
+	#[test]
+	fn mistyped_addresses_are_rejected() {
+		let address = alice().to_string();
+		assert_eq!(address.parse(), Ok(alice()));
+		let typo = address.replacen('G', "H", 1);
+		assert_eq!(typo.parse::<AccountId32>(), Err("Invalid address checksum"));
+		assert_eq!("5Grwva0".parse::<AccountId32>(), Err("Invalid address"));
+		assert_eq!(address[..40].parse::<AccountId32>(), Err("Invalid address length"));
+		let polkadot = alice().to_ss58(0);
+		assert_eq!(polkadot.parse::<AccountId32>(), Err("Address of another network"));
+	}
+
+	#[test]
+	fn transfers_address_recipients_by_account_key_or_name() {
+		let (alice, bob) = (wallet::dev_account("alice"), wallet::dev_account("bob"));
+		let mut state =
+			GenesisBuilder::new().endow(&alice, 1_000).endow(&bob, 100).build().unwrap();
+		let register = names::Call::Register { name: b"bob".to_vec() };
+		let first = genesis::signed(&state, "bob", [RuntimeCall::Names(register)]);
+		state.execute_block(genesis::block(&state, "charlie", first).unwrap()).unwrap();
+
+		let by_name: MultiAddress = "@bob".parse().unwrap();
+		assert_eq!(by_name, MultiAddress::Name(b"bob".to_vec()));
+		assert_eq!("@".parse::<MultiAddress>(), Ok(MultiAddress::Name(Vec::new())));
+		assert_eq!(bob.to_string().parse(), Ok(MultiAddress::Id(bob.clone())));
+		assert_eq!("5Grwva0".parse::<MultiAddress>(), Err("Invalid address"));
+		let by_key = MultiAddress::PublicKey(Scheme::Sr25519.signer(&wallet::dev_seed("bob")));
+		assert_eq!(decode_all::<MultiAddress>(&by_key.encode()), Ok(by_key.clone()));
+		let before = state.balances.balance(&bob);
+		let unknown = MultiAddress::Name(b"carol".to_vec());
+		let calls =
+			[by_name, by_key, bob.clone().into(), unknown].map(|to| wallet::transfer(to, 10));
+		let second = genesis::signed(&state, "alice", calls);
+		state.execute_block(genesis::block(&state, "charlie", second).unwrap()).unwrap();
+		assert_eq!(state.balances.balance(&bob), before + 30);
+	}
+}
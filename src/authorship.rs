@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+
+use crate::{
+	balances,
+	metadata::{storage, PalletMetadata},
+	migrations::{self, StorageVersion},
+	support::DispatchResult,
+	trie::{self, Storage},
+};
+
+pub trait Config: balances::Config {
+	/// The amount minted to the author of every block.
+	const BLOCK_REWARD: Self::Balance;
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+/// Keeps track of who produces the blocks, and pays them for it.
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	/// The author of the block being executed, or of the last one once it is done.
+	author: Option<T::AccountId>,
+	blocks_authored: BTreeMap<T::AccountId, u32>,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self { author: None, blocks_authored: BTreeMap::new() }
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Authorship",
+			index: None,
+			calls: Vec::new(),
+			storage: vec![
+				storage::<Option<T::AccountId>>("author"),
+				storage::<BTreeMap<T::AccountId, u32>>("blocks_authored"),
+			],
+			events: Vec::new(),
+			errors: Vec::new(),
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Authorship", STORAGE_VERSION);
+		if let Some(author) = &self.author {
+			trie::insert_value(storage, "Authorship", "author", author);
+		}
+		trie::insert_entries(storage, "Authorship", "blocks_authored", &self.blocks_authored);
+	}
+
+	/// Restore the author and blocks authored `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult {
+		migrations::ensure_version(storage, "Authorship", STORAGE_VERSION)?;
+		self.author = trie::decode_value(storage, "Authorship", "author")?;
+		self.blocks_authored = trie::decode_entries(storage, "Authorship", "blocks_authored")?
+			.into_iter()
+			.collect();
+		Ok(())
+	}
+
+	pub fn author(&self) -> Option<&T::AccountId> {
+		self.author.as_ref()
+	}
+
+	pub fn blocks_authored(&self, who: &T::AccountId) -> u32 {
+		*self.blocks_authored.get(who).unwrap_or(&0)
+	}
+
+	/// Called at the start of every block with the author from its header, which is paid the
+	/// block reward.
+	pub fn on_initialize(&mut self, balances: &mut balances::Pallet<T>, author: T::AccountId) {
+		// The reward can only fail to be minted once the author's balance is about to
+		// overflow, in which case it is forgone.
+		if let Ok(reward) = balances.deposit(&author, T::BLOCK_REWARD) {
+			balances.mint(reward);
+		}
+		*self.blocks_authored.entry(author.clone()).or_default() += 1;
+		self.author = Some(author);
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{balances, mock::Test};
+
+	impl super::Config for Test {
+		const BLOCK_REWARD: u128 = 5;
+	}
+
+	#[test]
+	fn authors_are_rewarded() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut balances: balances::Pallet<Test> = balances::Pallet::new();
+		let mut authorship: super::Pallet<Test> = super::Pallet::new();
+		assert_eq!(authorship.author(), None);
+
+		authorship.on_initialize(&mut balances, alice.clone());
+		authorship.on_initialize(&mut balances, alice.clone());
+		authorship.on_initialize(&mut balances, bob.clone());
+
+		assert_eq!(authorship.author(), Some(&bob));
+		assert_eq!(authorship.blocks_authored(&alice), 2);
+		assert_eq!(balances.balance(&alice), 10);
+		assert_eq!(balances.balance(&bob), 5);
+	}
+}
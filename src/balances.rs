@@ -1,47 +1,847 @@
-use std::collections::BTreeMap;
+use core::fmt::Debug;
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	sync::{Arc, Mutex, PoisonError},
+};
 
-use num::{CheckedAdd, CheckedSub, Zero};
+use num::{Bounded, CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, Zero};
 
-use crate::{support, system};
+use crate::{
+	codec::{Decode, Encode},
+	metadata::{field, storage, variant, PalletMetadata},
+	migrations::{self, StorageVersion},
+	overlay::OverlayMap,
+	support,
+	system::{self, OriginFor, RefChange},
+	trie::{self, storage_key, Storage},
+	weights,
+};
 
 pub trait Config: system::Config {
-	type Balance: Zero + CheckedSub + CheckedAdd + Copy + PartialOrd;
+	type Balance: Debug
+		+ Encode
+		+ Decode
+		+ Zero
+		+ From<u8>
+		+ From<u64>
+		+ TryInto<u64>
+		+ CheckedSub
+		+ CheckedAdd
+		+ CheckedMul
+		+ CheckedDiv
+		+ Bounded
+		+ Copy
+		+ PartialOrd;
+	/// How transfers address their recipient, e.g. by name, see `support::Lookup`.
+	type Address: Debug + Clone + Encode + Decode;
+	/// Why funds are held, from the `HoldReason`s of the pallets as `construct_runtime`
+	/// aggregates them.
+	type RuntimeHoldReason: Debug + Copy + Ord + Encode + Decode;
+	/// The smallest free balance a transfer may leave its sender with. What would be left below
+	/// it is dust, which is removed as `dust_removal` says and the account reaped.
+	const EXISTENTIAL_DEPOSIT: Self::Balance;
+
+	fn dust_removal() -> DustRemoval<Self::AccountId>;
+}
+
+/// Where the dust of reaped accounts goes, instead of being stranded in them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DustRemoval<AccountId> {
+	/// Burn it, reducing the total issuance.
+	Burn,
+	/// Credit it to an account, e.g. the treasury pot.
+	Credit(AccountId),
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+/// Identifies the pallet that placed a lock on an account.
+pub type LockIdentifier = [u8; 8];
+
+/// The total issuance, shared with the imbalances of the pallet, so they still adjust it when
+/// they are dropped.
+type Issuance<Balance> = Arc<Mutex<Balance>>;
+
+/// The total issuance saturates, so the balances no longer add up to it once it would overflow,
+/// which `assert_invariants` reports.
+fn increase<Balance: CheckedAdd + Bounded + Copy>(issuance: &Issuance<Balance>, amount: Balance) {
+	let mut total = issuance.lock().unwrap_or_else(PoisonError::into_inner);
+	*total = total.checked_add(&amount).unwrap_or_else(Balance::max_value);
+}
+
+fn decrease<Balance: CheckedSub + Zero + Copy>(issuance: &Issuance<Balance>, amount: Balance) {
+	let mut total = issuance.lock().unwrap_or_else(PoisonError::into_inner);
+	*total = total.checked_sub(&amount).unwrap_or_else(Zero::zero);
 }
 
+/// Funds taken out of accounts, or issued, that no account holds yet, e.g. a fee. The total
+/// issuance still counts them until they are resolved: credited to an account with `resolve`,
+/// or burned. Dropping them burns them, as `burn` does.
+#[must_use = "Imbalances must be resolved"]
 #[derive(Debug)]
-pub struct Pallet<T: Config> {
-	balances: BTreeMap<T::AccountId, T::Balance>,
+pub struct NegativeImbalance<T: Config> {
+	amount: T::Balance,
+	/// `None` for the empty imbalance, which has nothing to adjust.
+	issuance: Option<Issuance<T::Balance>>,
+}
+
+/// Funds credited to an account that weren't taken out of any, e.g. a block reward. The total
+/// issuance doesn't count them until they are resolved, which dropping them does, as `mint`
+/// does.
+#[must_use = "Imbalances must be resolved"]
+#[derive(Debug)]
+pub struct PositiveImbalance<T: Config> {
+	amount: T::Balance,
+	issuance: Issuance<T::Balance>,
+}
+
+impl<T: Config> NegativeImbalance<T> {
+	pub fn zero() -> Self {
+		Self { amount: T::Balance::zero(), issuance: None }
+	}
+
+	pub fn peek(&self) -> T::Balance {
+		self.amount
+	}
+
+	/// Split off up to `amount`, returning it and the rest.
+	pub fn split(self, amount: T::Balance) -> (Self, Self) {
+		let (total, issuance) = self.take();
+		let first = if amount < total { amount } else { total };
+		let rest = total.checked_sub(&first).unwrap_or_else(Zero::zero);
+		(Self { amount: first, issuance: issuance.clone() }, Self { amount: rest, issuance })
+	}
+
+	pub fn merge(self, other: Self) -> Self {
+		let ((mine, issuance), (other, other_issuance)) = (self.take(), other.take());
+		// Both are counted in the total issuance, so their sum fits.
+		let amount = mine.checked_add(&other).unwrap_or_else(T::Balance::max_value);
+		Self { amount, issuance: issuance.or(other_issuance) }
+	}
+
+	/// The funds, which are left for the caller to account for.
+	fn take(mut self) -> (T::Balance, Option<Issuance<T::Balance>>) {
+		(core::mem::replace(&mut self.amount, Zero::zero()), self.issuance.take())
+	}
 }
 
-pub enum Call<T: Config> {
-	Transfer { to: T::AccountId, amount: T::Balance },
+impl<T: Config> PositiveImbalance<T> {
+	pub fn peek(&self) -> T::Balance {
+		self.amount
+	}
 }
 
-impl<T: Config> support::Dispatch for Pallet<T> {
-	type Call = Call<T>;
-	type Caller = T::AccountId;
+impl<T: Config> Drop for NegativeImbalance<T> {
+	fn drop(&mut self) {
+		if let Some(issuance) = &self.issuance {
+			decrease(issuance, self.amount);
+		}
+	}
+}
 
-	fn dispatch(&mut self, caller: Self::Caller, call: Self::Call) -> support::DispatchResult {
-        match call {
-            Call::Transfer { to, amount } => {
-                self.transfer(caller, to, amount)?;
-            }
-        }
-        Ok(())
-    }
+impl<T: Config> Drop for PositiveImbalance<T> {
+	fn drop(&mut self) {
+		increase(&self.issuance, self.amount);
+	}
+}
+
+/// What happened to the balances of accounts, so their history can be told from the events
+/// alone.
+#[derive(Debug)]
+pub enum Event<T: Config> {
+	/// The dust of `account` was burned.
+	DustLost {
+		account: T::AccountId,
+		amount: T::Balance,
+	},
+	/// The dust of `account` was credited to `to`.
+	DustSwept {
+		account: T::AccountId,
+		to: T::AccountId,
+		amount: T::Balance,
+	},
+	/// `amount` moved from `from` to the free balance of `to`, out of the free balance of
+	/// `from` unless it was repatriated from its reserved balance.
+	Transferred {
+		from: T::AccountId,
+		to: T::AccountId,
+		amount: T::Balance,
+	},
+	/// `amount` was added to the total issuance, e.g. a block reward. Together with `Burned`,
+	/// it tells every change of the total issuance.
+	Minted {
+		amount: T::Balance,
+	},
+	/// `amount` was taken out of the total issuance, e.g. the part of a fee nobody is paid.
+	Burned {
+		amount: T::Balance,
+	},
+	Reserved {
+		who: T::AccountId,
+		amount: T::Balance,
+	},
+	Unreserved {
+		who: T::AccountId,
+		amount: T::Balance,
+	},
+	/// `amount` was taken out of the free balance of `who` into an imbalance, e.g. to pay a
+	/// fee. Whether it is burned or paid to someone shows once it is resolved.
+	Withdrawn {
+		who: T::AccountId,
+		amount: T::Balance,
+	},
+	/// `amount` was taken out of the reserved balance of `who` into an imbalance.
+	Slashed {
+		who: T::AccountId,
+		amount: T::Balance,
+	},
+	/// `amount` was credited to the free balance of `who` without being transferred to it:
+	/// funds resolved from an imbalance, such as a tip out of a fee, or newly minted ones.
+	Deposited {
+		who: T::AccountId,
+		amount: T::Balance,
+	},
+}
+
+impl<T: Config> Event<T> {
+	/// The name of the variant, as in the metadata.
+	pub fn name(&self) -> &'static str {
+		match self {
+			Event::DustLost { .. } => "DustLost",
+			Event::DustSwept { .. } => "DustSwept",
+			Event::Transferred { .. } => "Transferred",
+			Event::Minted { .. } => "Minted",
+			Event::Burned { .. } => "Burned",
+			Event::Reserved { .. } => "Reserved",
+			Event::Unreserved { .. } => "Unreserved",
+			Event::Withdrawn { .. } => "Withdrawn",
+			Event::Slashed { .. } => "Slashed",
+			Event::Deposited { .. } => "Deposited",
+		}
+	}
+
+	/// The accounts the event is about.
+	pub fn accounts(&self) -> Vec<&T::AccountId> {
+		match self {
+			Event::DustLost { account, .. } => vec![account],
+			Event::DustSwept { account, to, .. } => vec![account, to],
+			Event::Transferred { from, to, .. } => vec![from, to],
+			Event::Minted { .. } | Event::Burned { .. } => Vec::new(),
+			Event::Deposited { who, .. }
+			| Event::Reserved { who, .. }
+			| Event::Unreserved { who, .. }
+			| Event::Withdrawn { who, .. }
+			| Event::Slashed { who, .. } => vec![who],
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct Pallet<T: Config> {
+	balances: OverlayMap<T::AccountId, T::Balance>,
+	reserved: OverlayMap<T::AccountId, T::Balance>,
+	/// The parts of the reserved balances that are held for a reason. Only `release` gives
+	/// them back, so pallets holding for different reasons can't take each other's funds.
+	holds: OverlayMap<T::AccountId, BTreeMap<T::RuntimeHoldReason, T::Balance>>,
+	/// Locks overlap: the free balance can't drop below the largest lock of the account.
+	locks: OverlayMap<T::AccountId, BTreeMap<LockIdentifier, T::Balance>>,
+	/// All free and reserved balances together, and the negative imbalances not resolved yet.
+	/// Moving funds between accounts can't change it, only resolving imbalances does, e.g.
+	/// burning what `withdraw` took.
+	total_issuance: Issuance<T::Balance>,
+	events: Vec<Event<T>>,
+	/// The references to accounts taken or dropped since the runtime last applied them to
+	/// system, see `refs`.
+	ref_changes: Vec<RefChange<T::AccountId>>,
+}
+
+/// The recipients of transfers are resolved with `lookup`.
+#[support::pallet(
+	dispatch(
+		origin: OriginFor<T>,
+		lookup: &impl support::Lookup<Source = T::Address, Target = T::AccountId>,
+	),
+	errors(
+		BalanceIsLocked = "Balance is locked",
+		InsufficientBalance = "Insufficient balance",
+		InsufficientReservedBalance = "Insufficient reserved balance",
+		Overflow = "Overflow when adding balance",
+		InsufficientHeldBalance = "Insufficient held balance",
+	),
+)]
+impl<T: Config> Pallet<T> {
+	#[call(index = 0, weight = weights::balances::TRANSFER)]
+	fn transfer(to: T::Address, amount: T::Balance) -> support::DispatchResult {
+		let caller = support::ensure_signed(origin)?;
+		self.transfer(caller, lookup.lookup(to)?, amount)
+	}
+
+	/// Root only: move funds between any two accounts.
+	#[call(index = 1, weight = weights::balances::FORCE_TRANSFER)]
+	fn force_transfer(
+		from: T::AccountId,
+		to: T::AccountId,
+		amount: T::Balance,
+	) -> support::DispatchResult {
+		support::ensure_root(origin)?;
+		self.transfer(from, to, amount)
+	}
+
+	/// Root only: overwrite the balance of an account.
+	#[call(index = 2, weight = weights::balances::SET_BALANCE)]
+	fn set_balance(who: T::AccountId, amount: T::Balance) -> support::DispatchResult {
+		support::ensure_root(origin)?;
+		self.set_balance(&who, amount);
+		Ok(())
+	}
+
+	/// Pay several recipients at once, e.g. a payroll: either all of them are paid or none is.
+	#[call(index = 3, weight = weights::balances::TRANSFER * recipients.len() as support::Weight)]
+	fn transfer_many(recipients: Vec<(T::Address, T::Balance)>) -> support::DispatchResult {
+		let caller = support::ensure_signed(origin)?;
+		let recipients = recipients
+			.into_iter()
+			.map(|(to, amount)| Ok((lookup.lookup(to)?, amount)))
+			.collect::<Result<_, &'static str>>()?;
+		self.transfer_many(caller, recipients)
+	}
 }
 
 impl<T: Config> Pallet<T> {
 	pub fn new() -> Self {
-		return Self { balances: BTreeMap::new() };
+		Self {
+			balances: OverlayMap::new(),
+			reserved: OverlayMap::new(),
+			holds: OverlayMap::new(),
+			locks: OverlayMap::new(),
+			total_issuance: Arc::new(Mutex::new(T::Balance::zero())),
+			events: Vec::new(),
+			ref_changes: Vec::new(),
+		}
 	}
 
+	pub fn metadata() -> PalletMetadata {
+		let who_amount = || vec![field::<T::AccountId>("who"), field::<T::Balance>("amount")];
+		PalletMetadata {
+			name: "Balances",
+			index: None,
+			calls: Call::<T>::metadata(),
+			storage: vec![
+				storage::<OverlayMap<T::AccountId, T::Balance>>("balances"),
+				storage::<OverlayMap<T::AccountId, T::Balance>>("reserved"),
+				storage::<OverlayMap<T::AccountId, BTreeMap<T::RuntimeHoldReason, T::Balance>>>(
+					"holds",
+				),
+				storage::<OverlayMap<T::AccountId, BTreeMap<LockIdentifier, T::Balance>>>("locks"),
+			],
+			events: vec![
+				variant(
+					"DustLost",
+					0,
+					vec![field::<T::AccountId>("account"), field::<T::Balance>("amount")],
+				),
+				variant(
+					"DustSwept",
+					1,
+					vec![
+						field::<T::AccountId>("account"),
+						field::<T::AccountId>("to"),
+						field::<T::Balance>("amount"),
+					],
+				),
+				variant(
+					"Transferred",
+					2,
+					vec![
+						field::<T::AccountId>("from"),
+						field::<T::AccountId>("to"),
+						field::<T::Balance>("amount"),
+					],
+				),
+				variant("Minted", 3, vec![field::<T::Balance>("amount")]),
+				variant("Burned", 4, vec![field::<T::Balance>("amount")]),
+				variant("Reserved", 5, who_amount()),
+				variant("Unreserved", 6, who_amount()),
+				variant("Withdrawn", 7, who_amount()),
+				variant("Slashed", 8, who_amount()),
+				variant("Deposited", 9, who_amount()),
+			],
+			errors: Error::metadata(),
+		}
+	}
+
+	/// Overwrite the free balance of `who`, issuing the difference into it or burning it. A
+	/// balance below the existential deposit is removed as dust.
 	pub fn set_balance(&mut self, who: &T::AccountId, amount: T::Balance) {
-		self.balances.insert(who.clone(), amount);
+		let old = self.balance(who);
+		if amount > old {
+			let minted = self.issue(amount.checked_sub(&old).unwrap_or(amount));
+			// The new balance is `amount`, so it fits.
+			let _ = self.resolve(who, minted);
+		} else if old > amount {
+			let burned = self.take_free(who, old.checked_sub(&amount).unwrap_or(old));
+			self.events.push(Event::Withdrawn { who: who.clone(), amount: burned.peek() });
+			self.burn(burned);
+		}
+		self.remove_dust(who);
+	}
+
+	fn increase_issuance(&mut self, amount: T::Balance) {
+		increase(&self.total_issuance, amount);
+	}
+
+	/// Create `amount` out of nothing, to be resolved into accounts.
+	pub fn issue(&mut self, amount: T::Balance) -> NegativeImbalance<T> {
+		self.increase_issuance(amount);
+		if !amount.is_zero() {
+			self.events.push(Event::Minted { amount });
+		}
+		self.negative_imbalance(amount)
+	}
+
+	fn negative_imbalance(&self, amount: T::Balance) -> NegativeImbalance<T> {
+		NegativeImbalance { amount, issuance: Some(self.total_issuance.clone()) }
+	}
+
+	/// Destroy the funds of `imbalance`.
+	pub fn burn(&mut self, imbalance: NegativeImbalance<T>) {
+		let amount = imbalance.peek();
+		drop(imbalance);
+		if !amount.is_zero() {
+			self.events.push(Event::Burned { amount });
+		}
+	}
+
+	/// Count the funds of `imbalance` in the total issuance.
+	pub fn mint(&mut self, imbalance: PositiveImbalance<T>) {
+		drop(imbalance);
+	}
+
+	/// Credit the funds of `imbalance` to the free balance of `who`. They are burned if they
+	/// don't fit.
+	pub fn resolve(
+		&mut self,
+		who: &T::AccountId,
+		imbalance: NegativeImbalance<T>,
+	) -> support::DispatchResult {
+		let amount = imbalance.peek();
+		if let Err(imbalance) = self.credit(who, imbalance) {
+			self.burn(imbalance);
+			return Err(Error::Overflow.into());
+		}
+		if !amount.is_zero() {
+			self.events.push(Event::Deposited { who: who.clone(), amount });
+		}
+		Ok(())
+	}
+
+	/// Credit the funds of `imbalance` to the free balance of `who`, or hand them back if they
+	/// don't fit.
+	fn credit(
+		&mut self,
+		who: &T::AccountId,
+		imbalance: NegativeImbalance<T>,
+	) -> Result<(), NegativeImbalance<T>> {
+		let Some(new_free) = self.balance(who).checked_add(&imbalance.peek()) else {
+			return Err(imbalance);
+		};
+		let _ = imbalance.take();
+		self.set_free(who, new_free);
+		Ok(())
+	}
+
+	/// Take `amount` out of the free balance of `who`, which must hold it, without checking its
+	/// locks.
+	fn take_free(&mut self, who: &T::AccountId, amount: T::Balance) -> NegativeImbalance<T> {
+		let new_free = self.balance(who).checked_sub(&amount).unwrap_or_else(Zero::zero);
+		self.set_free(who, new_free);
+		self.negative_imbalance(amount)
+	}
+
+	/// Set the free balance of `who`, without minting or burning. Accounts without any balance
+	/// are reaped, so they take no space in the state.
+	fn set_free(&mut self, who: &T::AccountId, amount: T::Balance) {
+		let refs = self.refs(who);
+		if amount.is_zero() {
+			self.balances.remove(who);
+		} else {
+			self.balances.insert(who.clone(), amount);
+		}
+		self.note_refs(who, refs);
+	}
+
+	fn set_reserved(&mut self, who: &T::AccountId, amount: T::Balance) {
+		let refs = self.refs(who);
+		if amount.is_zero() {
+			self.reserved.remove(who);
+		} else {
+			self.reserved.insert(who.clone(), amount);
+		}
+		self.note_refs(who, refs);
+	}
+
+	/// The references the balances of `who` hold in system: whether they provide for the
+	/// account, as it has a free or reserved balance, and whether its reserves and its locks
+	/// consume it, so it can't be reaped while it has either.
+	fn refs(&self, who: &T::AccountId) -> (bool, bool, bool) {
+		let reserves = !self.reserved_balance(who).is_zero();
+		let provides = reserves || !self.balance(who).is_zero();
+		(provides, reserves, provides && self.locks.get(who).is_some())
+	}
+
+	/// Note the references `who` took or dropped since `refs` returned `before`, providers
+	/// before consumers are taken and after they are dropped.
+	fn note_refs(&mut self, who: &T::AccountId, before: (bool, bool, bool)) {
+		let (provides, reserves, locks) = self.refs(who);
+		if provides && !before.0 {
+			self.ref_changes.push(RefChange::IncProviders(who.clone()));
+		}
+		for (had, has) in [(before.1, reserves), (before.2, locks)] {
+			if has && !had {
+				self.ref_changes.push(RefChange::IncConsumers(who.clone()));
+			} else if had && !has {
+				self.ref_changes.push(RefChange::DecConsumers(who.clone()));
+			}
+		}
+		if before.0 && !provides {
+			self.ref_changes.push(RefChange::DecProviders(who.clone()));
+		}
+	}
+
+	/// Hand the references noted so far to the caller, to apply them to system.
+	pub fn take_ref_changes(&mut self) -> Vec<RefChange<T::AccountId>> {
+		core::mem::take(&mut self.ref_changes)
+	}
+
+	/// Take what `executed`, a copy of `base` that executed extrinsics touching only
+	/// `accounts` besides crediting `credited`, changed since. Credits add up, so the free
+	/// balances of `credited` take what `executed` added to them, as the total issuance takes
+	/// what it issued or burned. `self` is `base` or another copy merged into it.
+	pub fn merge(
+		&mut self,
+		base: &Self,
+		executed: &Self,
+		accounts: &BTreeSet<T::AccountId>,
+		credited: &[T::AccountId],
+	) {
+		for account in accounts {
+			self.balances.copy_entry(&executed.balances, account);
+			self.reserved.copy_entry(&executed.reserved, account);
+			self.holds.copy_entry(&executed.holds, account);
+			self.locks.copy_entry(&executed.locks, account);
+		}
+		for account in credited {
+			let added = executed.balance(account).checked_sub(&base.balance(account));
+			let free = self.balance(account).checked_add(&added.unwrap_or_else(Zero::zero));
+			self.set_free(account, free.unwrap_or_else(T::Balance::max_value));
+		}
+		let (before, after) = (base.total_issuance(), executed.total_issuance());
+		match after.checked_sub(&before) {
+			Some(issued) => increase(&self.total_issuance, issued),
+			None => decrease(
+				&self.total_issuance,
+				before.checked_sub(&after).unwrap_or_else(Zero::zero),
+			),
+		}
+	}
+
+	pub fn total_issuance(&self) -> T::Balance {
+		*self.total_issuance.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	/// Panics unless the free and reserved balances add up to the total issuance and accounts
+	/// without any balance are reaped. Tests and fuzzing check it after every operation.
+	pub fn assert_invariants(&self) {
+		let mut sum = T::Balance::zero();
+		for amount in self.balances.values().chain(self.reserved.values()) {
+			assert!(!amount.is_zero(), "Accounts without balance must be reaped");
+			sum = sum.checked_add(amount).expect("Balances overflow");
+		}
+		for (who, holds) in &self.holds {
+			assert!(!holds.is_empty(), "Accounts without holds must be removed");
+			assert!(holds.values().all(|amount| !amount.is_zero()), "Empty holds must be removed");
+			assert!(
+				self.total_on_hold(who) <= self.reserved_balance(who),
+				"Holds must be reserved"
+			);
+		}
+		assert_eq!(sum, self.total_issuance(), "Balances must add up to the total issuance");
+	}
+
+	/// Merge the changes of the block into the committed storage, see `overlay`.
+	pub fn commit(&mut self) {
+		self.balances.commit();
+		self.reserved.commit();
+		self.holds.commit();
+		self.locks.commit();
+	}
+
+	/// The entries of the state root: the free and reserved balances, holds and locks.
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Balances", STORAGE_VERSION);
+		for (who, amount) in &self.balances {
+			storage.insert(storage_key("Balances", "free", who), amount.encode());
+		}
+		for (who, amount) in &self.reserved {
+			storage.insert(storage_key("Balances", "reserved", who), amount.encode());
+		}
+		for (who, holds) in &self.holds {
+			for (reason, amount) in holds {
+				storage.insert(storage_key("Balances", "holds", &(who, reason)), amount.encode());
+			}
+		}
+		for (who, locks) in &self.locks {
+			for (id, amount) in locks {
+				storage.insert(storage_key("Balances", "locks", &(who, id)), amount.encode());
+			}
+		}
+	}
+
+	/// Restore the balances `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> support::DispatchResult {
+		migrations::ensure_version(storage, "Balances", STORAGE_VERSION)?;
+		self.balances = trie::decode_entries(storage, "Balances", "free")?.into_iter().collect();
+		self.reserved =
+			trie::decode_entries(storage, "Balances", "reserved")?.into_iter().collect();
+		let mut holds = BTreeMap::<_, BTreeMap<_, _>>::new();
+		for ((who, reason), amount) in trie::decode_entries(storage, "Balances", "holds")? {
+			holds.entry(who).or_default().insert(reason, amount);
+		}
+		self.holds = holds.into_iter().collect();
+		let mut locks = BTreeMap::<_, BTreeMap<_, _>>::new();
+		for ((who, id), amount) in trie::decode_entries(storage, "Balances", "locks")? {
+			locks.entry(who).or_default().insert(id, amount);
+		}
+		self.locks = locks.into_iter().collect();
+		self.total_issuance = Arc::new(Mutex::new(T::Balance::zero()));
+		let amounts: Vec<T::Balance> =
+			self.balances.values().chain(self.reserved.values()).copied().collect();
+		amounts.into_iter().for_each(|amount| self.increase_issuance(amount));
+		Ok(())
 	}
 
 	pub fn balance(&self, who: &T::AccountId) -> T::Balance {
-		return *self.balances.get(who).unwrap_or(&T::Balance::zero());
+		*self.balances.get(who).unwrap_or(&T::Balance::zero())
+	}
+
+	pub fn reserved_balance(&self, who: &T::AccountId) -> T::Balance {
+		*self.reserved.get(who).unwrap_or(&T::Balance::zero())
+	}
+
+	/// The part of the reserved balance of `who` held for `reason`.
+	pub fn balance_on_hold(&self, reason: T::RuntimeHoldReason, who: &T::AccountId) -> T::Balance {
+		let held = self.holds.get(who).and_then(|holds| holds.get(&reason));
+		*held.unwrap_or(&T::Balance::zero())
+	}
+
+	/// The part of the reserved balance of `who` held for any reason.
+	pub fn total_on_hold(&self, who: &T::AccountId) -> T::Balance {
+		let holds = self.holds.get(who).into_iter().flat_map(|holds| holds.values());
+		holds.fold(T::Balance::zero(), |sum, amount| {
+			sum.checked_add(amount).unwrap_or_else(T::Balance::max_value)
+		})
+	}
+
+	/// The part of the free balance of `who` that is frozen by locks.
+	pub fn locked_balance(&self, who: &T::AccountId) -> T::Balance {
+		self.locks
+			.get(who)
+			.into_iter()
+			.flat_map(|locks| locks.values())
+			.fold(T::Balance::zero(), |max, amount| if *amount > max { *amount } else { max })
+	}
+
+	/// Lock `amount` of the free balance of `who`, replacing any previous lock with the same `id`.
+	pub fn set_lock(&mut self, id: LockIdentifier, who: &T::AccountId, amount: T::Balance) {
+		let refs = self.refs(who);
+		self.locks.get_or_insert_with(who.clone(), Default::default).insert(id, amount);
+		self.note_refs(who, refs);
+	}
+
+	pub fn remove_lock(&mut self, id: LockIdentifier, who: &T::AccountId) {
+		let refs = self.refs(who);
+		if let Some(locks) = self.locks.get_mut(who) {
+			locks.remove(&id);
+			if locks.is_empty() {
+				self.locks.remove(who);
+			}
+		}
+		self.note_refs(who, refs);
+		self.remove_dust(who);
+	}
+
+	/// Take `amount` from the free balance of `who`, respecting its locks.
+	fn withdrawn_balance(
+		&self,
+		who: &T::AccountId,
+		amount: T::Balance,
+	) -> Result<T::Balance, &'static str> {
+		let new_free = self.balance(who).checked_sub(&amount).ok_or(Error::InsufficientBalance)?;
+		if new_free < self.locked_balance(who) {
+			return Err(Error::BalanceIsLocked.into());
+		}
+		Ok(new_free)
+	}
+
+	/// Move `amount` from the free balance of `who` to its reserved balance.
+	pub fn reserve(&mut self, who: &T::AccountId, amount: T::Balance) -> support::DispatchResult {
+		let new_free = self.withdrawn_balance(who, amount)?;
+		let new_reserved =
+			self.reserved_balance(who).checked_add(&amount).ok_or(Error::Overflow)?;
+
+		self.set_free(who, new_free);
+		self.set_reserved(who, new_reserved);
+		self.events.push(Event::Reserved { who: who.clone(), amount });
+		self.remove_dust(who);
+		Ok(())
+	}
+
+	/// Take `amount` from the reserved balance of `who`, leaving what is on hold.
+	fn unreserved_balance(
+		&self,
+		who: &T::AccountId,
+		amount: T::Balance,
+	) -> Result<T::Balance, &'static str> {
+		let reserved = self.reserved_balance(who);
+		let not_held = reserved.checked_sub(&self.total_on_hold(who)).unwrap_or_else(Zero::zero);
+		if amount > not_held {
+			return Err(Error::InsufficientReservedBalance.into());
+		}
+		Ok(reserved.checked_sub(&amount).unwrap_or_else(Zero::zero))
+	}
+
+	/// Move `amount` from the free balance of `who` to its reserved balance, on hold for
+	/// `reason`.
+	pub fn hold(
+		&mut self,
+		reason: T::RuntimeHoldReason,
+		who: &T::AccountId,
+		amount: T::Balance,
+	) -> support::DispatchResult {
+		let held = self.balance_on_hold(reason, who).checked_add(&amount).ok_or(Error::Overflow)?;
+		self.reserve(who, amount)?;
+		self.set_hold(reason, who, held);
+		Ok(())
+	}
+
+	/// Move `amount` held for `reason` from the reserved balance of `who` back to its free
+	/// balance.
+	pub fn release(
+		&mut self,
+		reason: T::RuntimeHoldReason,
+		who: &T::AccountId,
+		amount: T::Balance,
+	) -> support::DispatchResult {
+		let held = self
+			.balance_on_hold(reason, who)
+			.checked_sub(&amount)
+			.ok_or(Error::InsufficientHeldBalance)?;
+		let new_free = self.balance(who).checked_add(&amount).ok_or(Error::Overflow)?;
+		let new_reserved =
+			self.reserved_balance(who).checked_sub(&amount).unwrap_or_else(Zero::zero);
+
+		self.set_hold(reason, who, held);
+		self.set_free(who, new_free);
+		self.set_reserved(who, new_reserved);
+		self.events.push(Event::Unreserved { who: who.clone(), amount });
+		self.remove_dust(who);
+		Ok(())
+	}
+
+	fn set_hold(&mut self, reason: T::RuntimeHoldReason, who: &T::AccountId, amount: T::Balance) {
+		let holds = self.holds.get_or_insert_with(who.clone(), Default::default);
+		if amount.is_zero() {
+			holds.remove(&reason);
+		} else {
+			holds.insert(reason, amount);
+		}
+		if holds.is_empty() {
+			self.holds.remove(who);
+		}
+	}
+
+	/// Move `amount` from the reserved balance of `who` back to its free balance. Funds on hold
+	/// are left, see `release`.
+	pub fn unreserve(&mut self, who: &T::AccountId, amount: T::Balance) -> support::DispatchResult {
+		let new_reserved = self.unreserved_balance(who, amount)?;
+		let new_free = self.balance(who).checked_add(&amount).ok_or(Error::Overflow)?;
+
+		self.set_free(who, new_free);
+		self.set_reserved(who, new_reserved);
+		self.events.push(Event::Unreserved { who: who.clone(), amount });
+		self.remove_dust(who);
+		Ok(())
+	}
+
+	/// Whether `withdraw` would succeed, without withdrawing anything.
+	pub fn can_withdraw(&self, who: &T::AccountId, amount: T::Balance) -> support::DispatchResult {
+		self.withdrawn_balance(who, amount).map(|_| ())
+	}
+
+	/// Remove `amount` from the free balance of `who`, e.g. to pay a fee.
+	pub fn withdraw(
+		&mut self,
+		who: &T::AccountId,
+		amount: T::Balance,
+	) -> Result<NegativeImbalance<T>, &'static str> {
+		self.withdrawn_balance(who, amount)?;
+		let imbalance = self.take_free(who, amount);
+		if !amount.is_zero() {
+			self.events.push(Event::Withdrawn { who: who.clone(), amount });
+		}
+		self.remove_dust(who);
+		Ok(imbalance)
+	}
+
+	/// Add `amount` to the free balance of `who`, without taking it out of anywhere.
+	pub fn deposit(
+		&mut self,
+		who: &T::AccountId,
+		amount: T::Balance,
+	) -> Result<PositiveImbalance<T>, &'static str> {
+		let new_free = self.balance(who).checked_add(&amount).ok_or(Error::Overflow)?;
+		self.set_free(who, new_free);
+		if !amount.is_zero() {
+			self.events.push(Event::Deposited { who: who.clone(), amount });
+			self.events.push(Event::Minted { amount });
+		}
+		Ok(PositiveImbalance { amount, issuance: self.total_issuance.clone() })
+	}
+
+	/// Remove up to `amount` from the reserved balance of `who` that isn't on hold, returning
+	/// what was slashed.
+	pub fn slash_reserved(
+		&mut self,
+		who: &T::AccountId,
+		amount: T::Balance,
+	) -> NegativeImbalance<T> {
+		let reserved = self.reserved_balance(who);
+		let not_held = reserved.checked_sub(&self.total_on_hold(who)).unwrap_or_else(Zero::zero);
+		let slashed = if amount < not_held { amount } else { not_held };
+		self.set_reserved(who, reserved.checked_sub(&slashed).unwrap_or(reserved));
+		if !slashed.is_zero() {
+			self.events.push(Event::Slashed { who: who.clone(), amount: slashed });
+		}
+		self.remove_dust(who);
+		self.negative_imbalance(slashed)
+	}
+
+	/// Move `amount` from the reserved balance of `who` that isn't on hold to the free balance
+	/// of `beneficiary`.
+	pub fn repatriate_reserved(
+		&mut self,
+		who: &T::AccountId,
+		beneficiary: &T::AccountId,
+		amount: T::Balance,
+	) -> support::DispatchResult {
+		let new_reserved = self.unreserved_balance(who, amount)?;
+		let new_free = self.balance(beneficiary).checked_add(&amount).ok_or(Error::Overflow)?;
+		self.set_free(beneficiary, new_free);
+		self.set_reserved(who, new_reserved);
+		let (from, to) = (who.clone(), beneficiary.clone());
+		self.events.push(Event::Transferred { from, to, amount });
+		self.remove_dust(who);
+		Ok(())
 	}
 
 	pub fn transfer(
@@ -50,48 +850,185 @@ impl<T: Config> Pallet<T> {
 		to: T::AccountId,
 		amount: T::Balance,
 	) -> Result<(), &'static str> {
-		let caller_balance = self.balance(&caller);
+		let new_caller_balance = self.withdrawn_balance(&caller, amount)?;
+		if caller == to {
+			return Ok(());
+		}
 		let to_balance = self.balance(&to);
 
-		let new_caller_balance =
-			caller_balance.checked_sub(&amount).ok_or("Insufficient balance")?;
+		let new_to_balance = to_balance.checked_add(&amount).ok_or(Error::Overflow)?;
 
-		let new_to_balance =
-			to_balance.checked_add(&amount).ok_or("Overflow when adding balance")?;
+		self.set_free(&caller, new_caller_balance);
+		self.set_free(&to, new_to_balance);
+		self.events.push(Event::Transferred { from: caller.clone(), to, amount });
+		self.remove_dust(&caller);
 
-		self.set_balance(&caller, new_caller_balance);
-		self.set_balance(&to, new_to_balance);
+		Ok(())
+	}
+
+	/// Transfer to each of `recipients`, after checking that the caller can afford all of them
+	/// and that none of the recipients would overflow, so either all transfers succeed or none.
+	pub fn transfer_many(
+		&mut self,
+		caller: T::AccountId,
+		recipients: Vec<(T::AccountId, T::Balance)>,
+	) -> support::DispatchResult {
+		let mut received = BTreeMap::<&T::AccountId, T::Balance>::new();
+		let mut total = T::Balance::zero();
+		for (to, amount) in &recipients {
+			total = total.checked_add(amount).ok_or(Error::Overflow)?;
+			let sum = received.entry(to).or_insert_with(T::Balance::zero);
+			*sum = sum.checked_add(amount).ok_or(Error::Overflow)?;
+		}
+		let new_caller_balance = self.withdrawn_balance(&caller, total)?;
+		// What the caller sends itself stays where it is.
+		let kept = received.remove(&caller).unwrap_or_else(T::Balance::zero);
+		let mut new_balances = Vec::new();
+		for (to, amount) in received {
+			let new_balance = self.balance(to).checked_add(&amount).ok_or(Error::Overflow)?;
+			new_balances.push((to.clone(), new_balance));
+		}
 
+		let new_caller_balance = new_caller_balance.checked_add(&kept).ok_or(Error::Overflow)?;
+		self.set_free(&caller, new_caller_balance);
+		for (to, new_balance) in new_balances {
+			self.set_free(&to, new_balance);
+		}
+		for (to, amount) in recipients.into_iter().filter(|(to, _)| to != &caller) {
+			self.events.push(Event::Transferred { from: caller.clone(), to, amount });
+		}
+		self.remove_dust(&caller);
 		Ok(())
 	}
-}
 
-#[cfg(test)]
-mod tests {
-	use std::u128;
+	/// Reap `who` if its free balance is below the existential deposit and nothing else keeps
+	/// it alive, i.e. it has nothing reserved or locked, removing the dust as configured. Every
+	/// operation that lowers a free balance or drops what keeps an account alive ends with it,
+	/// and the account loses the provider of its balance, so system reaps it with its nonce. The
+	/// account dust is credited to is never reaped itself.
+	fn remove_dust(&mut self, who: &T::AccountId) {
+		let dust = self.balance(who);
+		if dust.is_zero() || dust >= T::EXISTENTIAL_DEPOSIT {
+			return;
+		}
+		if !self.reserved_balance(who).is_zero() || !self.locked_balance(who).is_zero() {
+			return;
+		}
+		let receiver = match T::dust_removal() {
+			DustRemoval::Credit(to) if &to == who => return,
+			DustRemoval::Credit(to) => Some(to),
+			DustRemoval::Burn => None,
+		};
+		let imbalance = self.take_free(who, dust);
+		let account = who.clone();
+		// Dust that can't be credited, e.g. as the receiver would overflow, is burned.
+		let unresolved = match receiver {
+			Some(to) => match self.credit(&to, imbalance) {
+				Ok(()) => {
+					self.events.push(Event::DustSwept { account, to, amount: dust });
+					return;
+				},
+				Err(imbalance) => imbalance,
+			},
+			None => imbalance,
+		};
+		self.events.push(Event::DustLost { account, amount: dust });
+		self.burn(unresolved);
+	}
 
-	use crate::system;
+	pub fn events(&self) -> &[Event<T>] {
+		&self.events
+	}
 
-	struct TestConfig;
+	/// Hand the events deposited so far to the caller.
+	pub fn take_events(&mut self) -> Vec<Event<T>> {
+		core::mem::take(&mut self.events)
+	}
+}
 
-	impl system::Config for TestConfig {
-		type AccountId = String;
-		type BlockNumber = u32;
-		type Nonce = u32;
+impl<T: Config> Clone for Event<T> {
+	fn clone(&self) -> Self {
+		match self {
+			Event::DustLost { account, amount } => {
+				Event::DustLost { account: account.clone(), amount: *amount }
+			},
+			Event::DustSwept { account, to, amount } => {
+				Event::DustSwept { account: account.clone(), to: to.clone(), amount: *amount }
+			},
+			Event::Transferred { from, to, amount } => {
+				Event::Transferred { from: from.clone(), to: to.clone(), amount: *amount }
+			},
+			Event::Minted { amount } => Event::Minted { amount: *amount },
+			Event::Burned { amount } => Event::Burned { amount: *amount },
+			Event::Reserved { who, amount } => {
+				Event::Reserved { who: who.clone(), amount: *amount }
+			},
+			Event::Unreserved { who, amount } => {
+				Event::Unreserved { who: who.clone(), amount: *amount }
+			},
+			Event::Withdrawn { who, amount } => {
+				Event::Withdrawn { who: who.clone(), amount: *amount }
+			},
+			Event::Slashed { who, amount } => Event::Slashed { who: who.clone(), amount: *amount },
+			Event::Deposited { who, amount } => {
+				Event::Deposited { who: who.clone(), amount: *amount }
+			},
+		}
 	}
+}
 
-	impl super::Config for TestConfig {
-		type Balance = u128;
+/// Cloned to run code that may have to be reverted, e.g. a contract that traps.
+impl<T: Config> Clone for Pallet<T> {
+	fn clone(&self) -> Self {
+		Self {
+			balances: self.balances.clone(),
+			reserved: self.reserved.clone(),
+			holds: self.holds.clone(),
+			locks: self.locks.clone(),
+			// The imbalances taken out of `self` adjust its issuance, not the one of the clone.
+			total_issuance: Arc::new(Mutex::new(self.total_issuance())),
+			events: self.events.clone(),
+			ref_changes: self.ref_changes.clone(),
+		}
 	}
 
+	/// Keeps the issuance of `self`, with the value of `source`, so the imbalances taken out of
+	/// `self` still adjust it.
+	fn clone_from(&mut self, source: &Self) {
+		let issuance = self.total_issuance.clone();
+		*self = source.clone();
+		*issuance.lock().unwrap_or_else(PoisonError::into_inner) = source.total_issuance();
+		self.total_issuance = issuance;
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{
+		codec::Encode,
+		democracy,
+		genesis::{self, GenesisBuilder},
+		mock::{HoldReason, MockRuntimeBuilder, Rng, Test},
+		proof_of_existence,
+		support::{with_transaction, IdentityLookup, RawOrigin},
+		treasury, wallet, Runtime, RuntimeEvent, TRANSACTION_FEE, TREASURY_FEE_SHARE,
+	};
+
 	#[test]
 	fn transfer_balance() {
 		let alice: String = "alice".to_string();
 		let bob: String = "bob".to_string();
-		let mut balances: super::Pallet<TestConfig> = super::Pallet::new();
-
-		balances.set_balance(&alice, 100);
-		balances.set_balance(&bob, 100);
+		let mut balances = MockRuntimeBuilder::new()
+			.with_balance("alice", 100)
+			.with_balance("bob", 100)
+			.build()
+			.balances;
 
 		let _ = balances.transfer(alice.clone(), bob.clone(), 10);
 
@@ -103,21 +1040,91 @@ mod tests {
 	fn transfer_balance_insufficient() {
 		let alice: String = "alice".to_string();
 		let bob: String = "bob".to_string();
-		let mut balances: super::Pallet<TestConfig> = super::Pallet::new();
-
-		balances.set_balance(&alice, 100);
-		balances.set_balance(&bob, 0);
+		let mut balances = MockRuntimeBuilder::new().with_balance("alice", 100).build().balances;
 
 		let result = balances.transfer(alice.clone(), bob.clone(), 200);
 
 		assert_eq!(result, Err("Insufficient balance"));
 	}
 
+	#[test]
+	fn transfer_many_pays_all_recipients_or_none() {
+		let alice: String = "alice".to_string();
+		let bob: String = "bob".to_string();
+		let charlie: String = "charlie".to_string();
+		let mut balances = MockRuntimeBuilder::new().with_balance("alice", 100).build().balances;
+		let lookup = IdentityLookup::default();
+		let pay = |recipients: &[(&String, u128)]| super::Call::TransferMany {
+			recipients: recipients.iter().map(|(to, amount)| ((*to).clone(), *amount)).collect(),
+		};
+
+		let signed = RawOrigin::Signed(alice.clone());
+		let result = balances.dispatch(signed.clone(), &lookup, pay(&[(&bob, 60), (&charlie, 60)]));
+		assert_eq!(result, Err("Insufficient balance"));
+		assert_eq!(balances.balance(&alice), 100);
+
+		let recipients = [(&bob, 10), (&charlie, 20), (&bob, 5), (&alice, 15)];
+		assert_eq!(balances.dispatch(signed.clone(), &lookup, pay(&recipients)), Ok(()));
+		assert_eq!(balances.balance(&alice), 65);
+		assert_eq!(balances.balance(&bob), 15);
+		assert_eq!(balances.balance(&charlie), 20);
+		balances.assert_invariants();
+
+		balances.set_balance(&charlie, u128::MAX);
+		let result = balances.dispatch(signed, &lookup, pay(&[(&bob, 10), (&charlie, 10)]));
+		assert_eq!(result, Err("Overflow when adding balance"));
+		assert_eq!(balances.balance(&bob), 15);
+	}
+
+	#[test]
+	fn balance_changes_emit_events() {
+		let alice: String = "alice".to_string();
+		let bob: String = "bob".to_string();
+		let mut balances: super::Pallet<Test> = super::Pallet::new();
+
+		balances.set_balance(&alice, 100);
+		assert_eq!(balances.transfer(alice.clone(), bob.clone(), 30), Ok(()));
+		assert_eq!(balances.reserve(&alice, 20), Ok(()));
+		assert_eq!(balances.unreserve(&alice, 5), Ok(()));
+		let fee = balances.withdraw(&bob, 10).unwrap();
+		let (tip, rest) = fee.split(4);
+		assert_eq!(balances.resolve(&alice, tip), Ok(()));
+		balances.burn(rest);
+		let slashed = balances.slash_reserved(&alice, 5);
+		balances.burn(slashed);
+		balances.set_balance(&bob, 10);
+		let events: Vec<_> = balances
+			.take_events()
+			.iter()
+			.map(|event| {
+				let accounts: Vec<_> = event.accounts().into_iter().cloned().collect();
+				(event.name(), accounts)
+			})
+			.collect();
+		let expected = [
+			("Minted", vec![]),
+			("Deposited", vec![alice.clone()]),
+			("Transferred", vec![alice.clone(), bob.clone()]),
+			("Reserved", vec![alice.clone()]),
+			("Unreserved", vec![alice.clone()]),
+			// The fee shows where it went once it is resolved, not when it is withdrawn, and as
+			// it already existed, the tip out of it isn't minted.
+			("Withdrawn", vec![bob.clone()]),
+			("Deposited", vec![alice.clone()]),
+			("Burned", vec![]),
+			("Slashed", vec![alice]),
+			("Burned", vec![]),
+			("Withdrawn", vec![bob]),
+			("Burned", vec![]),
+		];
+		assert_eq!(events, expected);
+	}
+
 	#[test]
 	fn transfer_balance_overflow() {
 		let alice: String = "alice".to_string();
 		let bob: String = "bob".to_string();
-		let mut balances: super::Pallet<TestConfig> = super::Pallet::new();
+		let mut balances: super::Pallet<Test> = super::Pallet::new();
 
 		balances.set_balance(&alice, 100);
 		balances.set_balance(&bob, u128::MAX);
@@ -129,11 +1136,374 @@ mod tests {
 
 	#[test]
 	fn init_balances() {
-		let mut balances: super::Pallet<TestConfig> = super::Pallet::new();
+		let mut balances: super::Pallet<Test> = super::Pallet::new();
 
 		assert_eq!(balances.balance(&"alice".to_string()), 0);
 		balances.set_balance(&"alice".to_string(), 100);
 		assert_eq!(balances.balance(&"alice".to_string()), 100);
 		assert_eq!(balances.balance(&"bob".to_string()), 0);
 	}
+
+	#[test]
+	fn dust_is_removed_whenever_a_free_balance_drops() {
+		let alice: String = "alice".to_string();
+		let bob: String = "bob".to_string();
+		let mut balances: super::Pallet<Test> = super::Pallet::new();
+
+		balances.set_balance(&alice, 100);
+		assert_eq!(balances.transfer(alice.clone(), bob.clone(), 97), Ok(()));
+		assert_eq!(balances.balance(&alice), 0);
+		assert_eq!(balances.total_issuance(), 97);
+		let events = balances.take_events();
+		assert!(matches!(
+			&events[..],
+			[.., super::Event::DustLost { account, amount: 3 }, super::Event::Burned { amount: 3 }]
+				if account == &alice
+		));
+
+		// Reserved funds keep the account alive.
+		assert_eq!(balances.reserve(&bob, 50), Ok(()));
+		assert_eq!(balances.transfer(bob.clone(), alice.clone(), 45), Ok(()));
+		assert_eq!(balances.balance(&bob), 2);
+		let events = balances.take_events();
+		assert!(!events.iter().any(|event| matches!(event, super::Event::DustLost { .. })));
+		balances.assert_invariants();
+
+		// Nor do locks, until they are removed.
+		balances.set_lock(*b"vesting ", &alice, 1);
+		let fee = balances.withdraw(&alice, 44).unwrap();
+		balances.burn(fee);
+		assert_eq!(balances.balance(&alice), 1);
+		balances.remove_lock(*b"vesting ", &alice);
+		assert_eq!(balances.balance(&alice), 0);
+
+		assert_eq!(balances.unreserve(&bob, 1), Ok(()));
+		assert_eq!(balances.balance(&bob), 3);
+		let slashed = balances.slash_reserved(&bob, 49);
+		balances.burn(slashed);
+		assert_eq!(balances.balance(&bob), 0);
+		balances.set_balance(&alice, 4);
+		assert_eq!(balances.balance(&alice), 0);
+		assert_eq!(balances.total_issuance(), 0);
+		balances.assert_invariants();
+	}
+
+	#[test]
+	fn the_runtime_sweeps_dust_to_the_treasury() {
+		let alice = wallet::dev_account("alice");
+		let bob = wallet::dev_account("bob");
+		let mut state = GenesisBuilder::new().endow(&alice, 1_000).build().unwrap();
+		let pot = <Runtime as treasury::Config>::account_id();
+		// The fee leaves 990, of which the transfer leaves 3.
+		let transfer = genesis::signed(&state, "alice", [wallet::transfer(bob.clone(), 987)]);
+		state
+			.execute_block(genesis::block(&state, "charlie", transfer).unwrap())
+			.unwrap();
+		assert_eq!(state.balances.balance(&alice), 0);
+		assert_eq!(state.balances.balance(&bob), 987);
+		let fee_share = TREASURY_FEE_SHARE.mul_floor(TRANSACTION_FEE);
+		assert_eq!(state.balances.balance(&pot), fee_share + 3);
+		let swept = state.system.events().iter().any(|record| {
+			matches!(
+				&record.event,
+				RuntimeEvent::Balances(super::Event::DustSwept { account, to, amount: 3 })
+					if account == &alice && to == &pot
+			)
+		});
+		assert!(swept);
+	}
+
+	#[test]
+	fn balances_provide_for_accounts_and_reserves_and_locks_consume_them() {
+		let alice = wallet::dev_account("alice");
+		let bob = wallet::dev_account("bob");
+		let mut state = GenesisBuilder::new().endow(&alice, 1_000).build().unwrap();
+		let refs = |state: &Runtime, who| {
+			state.system.account(who).map(|info| (info.providers, info.consumers))
+		};
+		assert_eq!(refs(&state, &alice), Some((1, 0)));
+
+		state.balances.reserve(&alice, 100).unwrap();
+		state.balances.set_lock(*b"vesting ", &alice, 50);
+		state.note_changes();
+		assert_eq!(refs(&state, &alice), Some((1, 2)));
+		assert_eq!(state.system.dec_providers(&alice), Err("Account has consumers"));
+
+		// Once its balance is gone, the account is reaped by system, nonce included.
+		state.system.inc_nonce(&alice).unwrap();
+		state.balances.unreserve(&alice, 100).unwrap();
+		state.balances.remove_lock(*b"vesting ", &alice);
+		state.balances.transfer(alice.clone(), bob.clone(), 1_000).unwrap();
+		state.note_changes();
+		assert_eq!(refs(&state, &alice), None);
+		assert_eq!(state.system.get_nonce(&alice), 0);
+		// The references are part of the state.
+		let restored = Runtime::import_state(&state.export_state()).unwrap();
+		assert_eq!(refs(&restored, &bob), Some((1, 0)));
+	}
+
+	#[test]
+	fn imbalances_are_resolved_into_accounts_or_burned() {
+		let alice: String = "alice".to_string();
+		let bob: String = "bob".to_string();
+		let mut balances: super::Pallet<Test> = super::Pallet::new();
+
+		let issued = balances.issue(100);
+		assert_eq!(balances.total_issuance(), 100);
+		assert_eq!(balances.resolve(&alice, issued), Ok(()));
+		let fee = balances.withdraw(&alice, 30).unwrap();
+		// Withdrawn funds are still counted until they are burned.
+		assert_eq!(balances.total_issuance(), 100);
+		let (tip, rest) = fee.split(10);
+		assert_eq!((tip.peek(), rest.peek()), (10, 20));
+		assert_eq!(balances.resolve(&bob, tip), Ok(()));
+		balances.burn(rest);
+		assert_eq!(balances.total_issuance(), 80);
+		balances.assert_invariants();
+
+		let reward = balances.deposit(&bob, 5).unwrap();
+		assert_eq!(balances.balance(&bob), 15);
+		balances.mint(reward);
+		balances.assert_invariants();
+
+		let slashed = balances.slash_reserved(&alice, 10).merge(super::NegativeImbalance::zero());
+		assert_eq!(slashed.peek(), 0);
+		balances.set_balance(&bob, u128::MAX);
+		let issued = balances.issue(1);
+		assert_eq!(balances.resolve(&bob, issued), Err("Overflow when adding balance"));
+	}
+
+	#[test]
+	fn dropped_imbalances_adjust_the_issuance() {
+		let alice: String = "alice".to_string();
+		let mut balances: super::Pallet<Test> = super::Pallet::new();
+
+		drop(balances.issue(100));
+		assert_eq!(balances.total_issuance(), 0);
+		drop(balances.deposit(&alice, 50).unwrap());
+		assert_eq!(balances.total_issuance(), 50);
+		let (fee, rest) = balances.withdraw(&alice, 30).unwrap().split(10);
+		drop(fee);
+		assert_eq!(balances.total_issuance(), 40);
+		assert_eq!(balances.resolve(&alice, rest), Ok(()));
+		assert_eq!(balances.total_issuance(), 40);
+		balances.assert_invariants();
+
+		// A transaction that fails in between puts the issuance back, but not the imbalance.
+		let fee = balances.withdraw(&alice, 10).unwrap();
+		let result = with_transaction(&mut balances, |balances| {
+			balances.transfer(alice.clone(), "bob".to_string(), 1_000)
+		});
+		assert_eq!(result, Err("Insufficient balance"));
+		balances.burn(fee);
+		assert_eq!(balances.total_issuance(), 30);
+		balances.assert_invariants();
+	}
+
+	#[test]
+	fn holds_for_different_reasons_are_kept_apart() {
+		let alice: String = "alice".to_string();
+		let bob: String = "bob".to_string();
+		let claim = HoldReason::from(proof_of_existence::HoldReason::ClaimDeposit);
+		let proposal = HoldReason::from(democracy::HoldReason::ProposalDeposit);
+		let mut balances: super::Pallet<Test> = super::Pallet::new();
+
+		balances.set_balance(&alice, 100);
+		assert_eq!(balances.hold(claim, &alice, 10), Ok(()));
+		assert_eq!(balances.hold(proposal, &alice, 20), Ok(()));
+		assert_eq!(balances.reserve(&alice, 5), Ok(()));
+		assert_eq!(balances.reserved_balance(&alice), 35);
+		assert_eq!(balances.total_on_hold(&alice), 30);
+
+		// Only what isn't on hold can be unreserved, slashed or repatriated.
+		assert_eq!(balances.unreserve(&alice, 6), Err("Insufficient reserved balance"));
+		let result = balances.repatriate_reserved(&alice, &bob, 6);
+		assert_eq!(result, Err("Insufficient reserved balance"));
+		let slashed = balances.slash_reserved(&alice, 50);
+		assert_eq!(slashed.peek(), 5);
+		balances.burn(slashed);
+		assert_eq!(balances.release(claim, &alice, 20), Err("Insufficient held balance"));
+		assert_eq!(balances.release(claim, &alice, 10), Ok(()));
+		assert_eq!(balances.balance_on_hold(claim, &alice), 0);
+		assert_eq!(balances.balance_on_hold(proposal, &alice), 20);
+		assert_eq!(balances.balance(&alice), 75);
+		balances.assert_invariants();
+
+		balances.set_lock(*b"vesting ", &alice, 40);
+		let mut storage = Default::default();
+		balances.storage_entries(&mut storage);
+		let mut restored: super::Pallet<Test> = super::Pallet::new();
+		assert_eq!(restored.restore_entries(&storage), Ok(()));
+		assert_eq!(restored.balance_on_hold(proposal, &alice), 20);
+		assert_eq!(restored.locked_balance(&alice), 40);
+	}
+
+	#[test]
+	fn reserve_and_unreserve() {
+		let alice: String = "alice".to_string();
+		let mut balances: super::Pallet<Test> = super::Pallet::new();
+
+		balances.set_balance(&alice, 100);
+
+		assert_eq!(balances.reserve(&alice, 150), Err("Insufficient balance"));
+		assert_eq!(balances.reserve(&alice, 60), Ok(()));
+		assert_eq!(balances.balance(&alice), 40);
+		assert_eq!(balances.reserved_balance(&alice), 60);
+
+		assert_eq!(balances.unreserve(&alice, 70), Err("Insufficient reserved balance"));
+		assert_eq!(balances.unreserve(&alice, 60), Ok(()));
+		assert_eq!(balances.balance(&alice), 100);
+		assert_eq!(balances.reserved_balance(&alice), 0);
+	}
+
+	#[test]
+	fn locks_restrict_free_balance() {
+		let alice: String = "alice".to_string();
+		let bob: String = "bob".to_string();
+		let mut balances: super::Pallet<Test> = super::Pallet::new();
+
+		balances.set_balance(&alice, 100);
+		balances.set_lock(*b"vesting ", &alice, 60);
+		balances.set_lock(*b"staking ", &alice, 30);
+		assert_eq!(balances.locked_balance(&alice), 60);
+
+		assert_eq!(balances.transfer(alice.clone(), bob.clone(), 50), Err("Balance is locked"));
+		assert_eq!(balances.reserve(&alice, 50), Err("Balance is locked"));
+		assert_eq!(balances.transfer(alice.clone(), bob.clone(), 40), Ok(()));
+
+		balances.remove_lock(*b"vesting ", &alice);
+		assert_eq!(balances.locked_balance(&alice), 30);
+		assert_eq!(balances.transfer(alice.clone(), bob.clone(), 30), Ok(()));
+		assert_eq!(balances.balance(&alice), 30);
+	}
+
+	#[test]
+	fn privileged_calls_require_root() {
+		let alice: String = "alice".to_string();
+		let bob: String = "bob".to_string();
+		let mut balances: super::Pallet<Test> = super::Pallet::new();
+		let lookup = IdentityLookup::default();
+
+		balances.set_balance(&alice, 100);
+
+		let result = balances.dispatch(
+			RawOrigin::Signed(bob.clone()),
+			&lookup,
+			super::Call::ForceTransfer { from: alice.clone(), to: bob.clone(), amount: 50 },
+		);
+		assert_eq!(result, Err("Bad origin"));
+
+		let result = balances.dispatch(
+			RawOrigin::Root,
+			&lookup,
+			super::Call::ForceTransfer { from: alice.clone(), to: bob.clone(), amount: 50 },
+		);
+		assert_eq!(result, Ok(()));
+		assert_eq!(balances.balance(&alice), 50);
+		assert_eq!(balances.balance(&bob), 50);
+
+		let _ = balances.dispatch(
+			RawOrigin::Root,
+			&lookup,
+			super::Call::SetBalance { who: bob.clone(), amount: 7 },
+		);
+		assert_eq!(balances.balance(&bob), 7);
+
+		let result = balances.dispatch(
+			RawOrigin::Root,
+			&lookup,
+			super::Call::Transfer { to: alice.clone(), amount: 1 },
+		);
+		assert_eq!(result, Err("Bad origin"));
+	}
+
+	#[test]
+	fn metadata_matches_call_encoding() {
+		let alice = "alice".to_string();
+		let metadata = super::Pallet::<Test>::metadata();
+		let calls: [super::Call<Test>; 4] = [
+			super::Call::Transfer { to: alice.clone(), amount: 1 },
+			super::Call::ForceTransfer { from: alice.clone(), to: alice.clone(), amount: 1 },
+			super::Call::SetBalance { who: alice.clone(), amount: 1 },
+			super::Call::TransferMany { recipients: vec![(alice, 1)] },
+		];
+
+		assert_eq!(metadata.calls.len(), calls.len());
+		for (call, variant) in calls.iter().zip(&metadata.calls) {
+			assert_eq!(call.encode()[0], variant.index);
+		}
+		assert_eq!(metadata.calls[0].fields[1].name, "amount");
+		assert_eq!(metadata.calls[0].fields[1].ty, "u128");
+	}
+
+	/// The total issuance the `Minted` and `Burned` events in `balances` add up to.
+	fn issuance_of_events(balances: &super::Pallet<Test>) -> u128 {
+		balances.events().iter().fold(0, |issuance, event| match event {
+			super::Event::Minted { amount } => issuance + amount,
+			super::Event::Burned { amount } => issuance - amount,
+			_ => issuance,
+		})
+	}
+
+	#[test]
+	fn minted_and_burned_events_add_up_to_the_issuance() {
+		let [alice, bob, treasury] = ["alice", "bob", "treasury"].map(String::from);
+		let mut balances: super::Pallet<Test> = super::Pallet::new();
+
+		balances.set_balance(&alice, 100);
+		drop(balances.deposit(&bob, 20).unwrap());
+		// A fee paid to the treasury moves funds that already exist.
+		let (tip, rest) = balances.withdraw(&alice, 30).unwrap().split(10);
+		assert_eq!(balances.resolve(&treasury, tip), Ok(()));
+		balances.burn(rest);
+		assert!(!balances
+			.events()
+			.iter()
+			.any(|event| { matches!(event, super::Event::Minted { amount: 10 }) }));
+		// Dust is burned.
+		assert_eq!(balances.transfer(bob.clone(), alice.clone(), 17), Ok(()));
+		balances.set_balance(&bob, 0);
+
+		assert_eq!(issuance_of_events(&balances), balances.total_issuance());
+		balances.assert_invariants();
+	}
+
+	#[test]
+	fn random_operations_keep_the_invariants() {
+		let accounts = ["alice", "bob", "charlie"].map(String::from);
+		let mut rng = Rng(0x5eed);
+		for _ in 0..200 {
+			let mut balances = super::Pallet::<Test>::new();
+			for _ in 0..50 {
+				let who = &accounts[rng.below(3)];
+				let other = &accounts[rng.below(3)];
+				let amount = rng.below(120) as u128;
+				// Failing operations must leave everything as it was, so their results don't
+				// matter here.
+				let _ = match rng.below(9) {
+					0 => balances.transfer(who.clone(), other.clone(), amount),
+					1 => {
+						balances.set_balance(who, amount);
+						Ok(())
+					},
+					2 => balances.deposit(who, amount).map(|minted| balances.mint(minted)),
+					3 => balances.withdraw(who, amount).map(|taken| balances.burn(taken)),
+					4 => balances.reserve(who, amount),
+					5 => balances.unreserve(who, amount),
+					6 => {
+						let slashed = balances.slash_reserved(who, amount);
+						balances.resolve(other, slashed)
+					},
+					7 => balances.repatriate_reserved(who, other, amount),
+					// Reaps the account, unless something is reserved.
+					_ => {
+						balances.set_balance(who, 0);
+						Ok(())
+					},
+				};
+				balances.assert_invariants();
+				assert_eq!(issuance_of_events(&balances), balances.total_issuance());
+			}
+		}
+	}
 }
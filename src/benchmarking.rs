@@ -0,0 +1,345 @@
+//! Benchmarks of the calls whose weights are in `weights`. Every benchmark builds the worst case
+//! for its call on a state of a given size, e.g. a transfer to a new account next to a million
+//! existing ones, then dispatches the call on copies of that state and times it. The median of
+//! the runs is the weight of the call, and `weights_source` writes them as the `weights` module.
+//!
+//! `block` times whole blocks instead, so what executing an extrinsic costs besides its call,
+//! e.g. verifying its signature and bumping the nonce, shows up as well. It also counts the
+//! allocations executing an extrinsic makes, when `CountingAllocator` is the global allocator.
+
+use std::{
+	alloc::{GlobalAlloc, Layout, System},
+	sync::atomic::{AtomicU64, Ordering},
+	time::{Duration, Instant},
+};
+
+use crate::{
+	codec::Encode,
+	genesis::{self, GenesisBuilder},
+	hashing, proof_of_existence,
+	support::{Dispatch, RawOrigin, Weight},
+	types, wallet, Runtime, RuntimeCall,
+};
+
+/// The most a benchmarked account needs to pay for its call.
+const FUNDS: types::Balance = 1_000_000;
+
+pub struct Benchmark {
+	/// The names of the pallet and call, as in the metadata.
+	pub pallet: &'static str,
+	pub call: &'static str,
+	/// Fill `state` with `size` items of the worst case for the call, and return the call.
+	pub setup: fn(state: &mut Runtime, size: u32) -> (types::RuntimeOrigin, RuntimeCall),
+}
+
+/// The weight measured for a call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Measurement {
+	pub pallet: &'static str,
+	pub call: &'static str,
+	pub weight: Weight,
+}
+
+pub const BENCHMARKS: &[Benchmark] = &[
+	Benchmark { pallet: "Balances", call: "Transfer", setup: transfer },
+	Benchmark { pallet: "Balances", call: "ForceTransfer", setup: force_transfer },
+	Benchmark { pallet: "Balances", call: "SetBalance", setup: set_balance },
+	Benchmark { pallet: "ProofOfExistence", call: "CreateClaim", setup: create_claim },
+	Benchmark { pallet: "ProofOfExistence", call: "RevokeClaim", setup: revoke_claim },
+	Benchmark { pallet: "ProofOfExistence", call: "TransferClaim", setup: transfer_claim },
+	Benchmark { pallet: "ProofOfExistence", call: "Attest", setup: attest },
+	Benchmark { pallet: "ProofOfExistence", call: "UpdateClaim", setup: update_claim },
+];
+
+/// A distinct account for every `index`, none of them a dev account.
+fn account(index: u32) -> types::AccountId {
+	types::AccountId::new(hashing::blake2_256(&("benchmark", index).encode()))
+}
+
+/// `size` funded accounts, so lookups in the balances go through as many entries.
+fn accounts(state: &mut Runtime, size: u32) {
+	for index in 0..size {
+		state.balances.set_balance(&account(index), FUNDS);
+	}
+}
+
+/// `size` claims of as many accounts, so lookups in the claims go through as many entries.
+fn claims(state: &mut Runtime, size: u32) {
+	accounts(state, size);
+	for index in 0..size {
+		let claim = format!("claim {index}");
+		let Runtime { balances, proof_of_existence, .. } = state;
+		proof_of_existence
+			.create_claim(balances, account(index), claim)
+			.expect("Funded accounts can claim");
+	}
+}
+
+/// A claim of `account(0)`, with the longest note.
+fn own_claim(state: &mut Runtime, size: u32) -> types::Content {
+	claims(state, size.max(1));
+	let Runtime { balances, proof_of_existence, .. } = state;
+	let (claim, note) = ("own claim".to_string(), vec![0; max_note_length()]);
+	proof_of_existence
+		.create_claim_with_note(balances, account(0), claim.clone(), Some(note))
+		.expect("Funded accounts can claim");
+	claim
+}
+
+fn max_note_length() -> usize {
+	<Runtime as proof_of_existence::Config>::MAX_NOTE_LENGTH
+}
+
+fn signed(index: u32) -> types::RuntimeOrigin {
+	RawOrigin::Signed(account(index))
+}
+
+/// Into a new account, which adds an entry to the balances.
+fn transfer(state: &mut Runtime, size: u32) -> (types::RuntimeOrigin, RuntimeCall) {
+	accounts(state, size.max(1));
+	let call = crate::balances::Call::Transfer { to: account(size.max(1)).into(), amount: 1 };
+	(signed(0), RuntimeCall::Balances(call))
+}
+
+fn force_transfer(state: &mut Runtime, size: u32) -> (types::RuntimeOrigin, RuntimeCall) {
+	accounts(state, size.max(1));
+	let call = crate::balances::Call::ForceTransfer {
+		from: account(0),
+		to: account(size.max(1)),
+		amount: 1,
+	};
+	(RawOrigin::Root, RuntimeCall::Balances(call))
+}
+
+fn set_balance(state: &mut Runtime, size: u32) -> (types::RuntimeOrigin, RuntimeCall) {
+	accounts(state, size);
+	let call = crate::balances::Call::SetBalance { who: account(size), amount: FUNDS };
+	(RawOrigin::Root, RuntimeCall::Balances(call))
+}
+
+fn create_claim(state: &mut Runtime, size: u32) -> (types::RuntimeOrigin, RuntimeCall) {
+	claims(state, size.max(1));
+	let note = Some(vec![0; max_note_length()]);
+	let call = proof_of_existence::Call::CreateClaim { claim: "new claim".to_string(), note };
+	(signed(0), RuntimeCall::ProofOfExistence(call))
+}
+
+fn revoke_claim(state: &mut Runtime, size: u32) -> (types::RuntimeOrigin, RuntimeCall) {
+	let claim = own_claim(state, size);
+	(signed(0), RuntimeCall::ProofOfExistence(proof_of_existence::Call::RevokeClaim { claim }))
+}
+
+/// To an account without claims, which adds an entry to the claims of accounts.
+fn transfer_claim(state: &mut Runtime, size: u32) -> (types::RuntimeOrigin, RuntimeCall) {
+	let claim = own_claim(state, size);
+	let call = proof_of_existence::Call::TransferClaim { claim, to: account(size.max(1)) };
+	(signed(0), RuntimeCall::ProofOfExistence(call))
+}
+
+fn attest(state: &mut Runtime, size: u32) -> (types::RuntimeOrigin, RuntimeCall) {
+	let claim = own_claim(state, size);
+	(signed(1), RuntimeCall::ProofOfExistence(proof_of_existence::Call::Attest { claim }))
+}
+
+fn update_claim(state: &mut Runtime, size: u32) -> (types::RuntimeOrigin, RuntimeCall) {
+	let old = own_claim(state, size);
+	let call = proof_of_existence::Call::UpdateClaim { old, new: "new revision".to_string() };
+	(signed(0), RuntimeCall::ProofOfExistence(call))
+}
+
+/// Run `benchmark` `repeats` times on a state of `size` items. Every run dispatches on a fresh
+/// copy of the state, which isn't timed.
+pub fn run(benchmark: &Benchmark, size: u32, repeats: u32) -> Result<Measurement, &'static str> {
+	let mut state = Runtime::new();
+	let (origin, call) = (benchmark.setup)(&mut state, size);
+	let mut runs = Vec::new();
+	for _ in 0..repeats.max(1) {
+		let (mut state, origin, call) = (state.clone(), origin.clone(), call.clone());
+		let start = Instant::now();
+		state.dispatch(origin, call)?;
+		runs.push(start.elapsed().as_nanos());
+	}
+	runs.sort_unstable();
+	let weight = Weight::try_from(runs[runs.len() / 2]).unwrap_or(Weight::MAX).max(1);
+	Ok(Measurement { pallet: benchmark.pallet, call: benchmark.call, weight })
+}
+
+/// The system allocator, counting the allocations made through it, for `block`. The binary
+/// installs it as the global allocator.
+pub struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+		unsafe { System.alloc(layout) }
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		unsafe { System.dealloc(ptr, layout) }
+	}
+
+	unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+		ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+		unsafe { System.realloc(ptr, layout, new_size) }
+	}
+}
+
+/// The allocations made so far, which stay at 0 unless `CountingAllocator` is the global
+/// allocator.
+pub fn allocations() -> u64 {
+	ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+/// What `block` measures, per extrinsic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockMeasurement {
+	pub execution: Duration,
+	/// The allocations, and reallocations, executing the extrinsic makes.
+	pub allocations: u64,
+}
+
+/// The time executing a block of `transfers` transfers takes per extrinsic, the median of
+/// `repeats` runs, and the allocations it makes per extrinsic.
+pub fn block(transfers: u32, repeats: u32) -> Result<BlockMeasurement, &'static str> {
+	let (alice, bob) = (wallet::dev_account("alice"), wallet::dev_account("bob"));
+	let state = GenesisBuilder::new().endow(&alice, FUNDS).authority(&alice).build()?;
+	let calls = (0..transfers).map(|_| wallet::transfer(bob.clone(), 1));
+	let block = genesis::block(&state, "alice", genesis::signed(&state, "alice", calls))?;
+	// An empty block, for what executing a block costs besides its extrinsics.
+	let empty = genesis::block(&state, "alice", Vec::new())?;
+	let allocations = |block: &types::Block| {
+		let (mut state, block) = (state.clone(), block.clone());
+		let before = allocations();
+		state.execute_block(block)?;
+		Ok::<_, &'static str>(allocations() - before)
+	};
+	let allocations = allocations(&block)?.saturating_sub(allocations(&empty)?);
+
+	let mut runs = Vec::new();
+	for _ in 0..repeats.max(1) {
+		let (mut state, block) = (state.clone(), block.clone());
+		let start = Instant::now();
+		state.execute_block(block)?;
+		runs.push(start.elapsed());
+	}
+	runs.sort_unstable();
+	Ok(BlockMeasurement {
+		execution: runs[runs.len() / 2] / transfers.max(1),
+		allocations: allocations / u64::from(transfers.max(1)),
+	})
+}
+
+/// `name` in snake case, e.g. `ProofOfExistence` is `proof_of_existence`.
+fn snake_case(name: &str) -> String {
+	let mut snake = String::new();
+	for (i, c) in name.chars().enumerate() {
+		if c.is_uppercase() && i > 0 {
+			snake.push('_');
+		}
+		snake.extend(c.to_lowercase());
+	}
+	snake
+}
+
+/// The documentation and imports `weights_source` starts the module with.
+const WEIGHTS_HEADER: &str = "\
+//! The weights of the benchmarked calls, one unit being a nanosecond of execution, so a block
+//! of `MAX_BLOCK_WEIGHT` takes 20ms to execute. The weights decide how full blocks are, and so
+//! how fees move, see `transaction_payment`.
+//!
+//! Generated by `blockchain benchmark`, see `benchmarking`: regenerate it on the reference
+//! machine with `blockchain benchmark > src/weights.rs` after changing a benchmarked call.
+
+use crate::support::Weight;
+";
+
+/// The `weights` module with the weights of `measurements`, a module for every pallet.
+pub fn weights_source(measurements: &[Measurement]) -> String {
+	let mut source = WEIGHTS_HEADER.to_string();
+	let mut pallet = None;
+	for measurement in measurements {
+		if pallet != Some(measurement.pallet) {
+			if pallet.is_some() {
+				source += "}\n";
+			}
+			source += &format!(
+				"\npub mod {} {{\n\tuse super::Weight;\n\n",
+				snake_case(measurement.pallet)
+			);
+			pallet = Some(measurement.pallet);
+		}
+		let name = snake_case(measurement.call).to_uppercase();
+		let weight = group_digits(measurement.weight);
+		source += &format!("\tpub const {name}: Weight = {weight};\n");
+	}
+	if pallet.is_some() {
+		source += "}\n";
+	}
+	source
+}
+
+/// `weight` as a Rust literal with an underscore every three digits, e.g. `10_000`.
+fn group_digits(weight: Weight) -> String {
+	let digits = weight.to_string();
+	let mut grouped = String::new();
+	for (i, digit) in digits.chars().enumerate() {
+		if i > 0 && (digits.len() - i).is_multiple_of(3) {
+			grouped.push('_');
+		}
+		grouped.push(digit);
+	}
+	grouped
+}
+
+#[cfg(test)]
+mod test {
+	use super::{
+		block, group_digits, run, weights_source, CountingAllocator, Measurement, BENCHMARKS,
+	};
+	use crate::weights;
+
+	#[global_allocator]
+	static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+	#[test]
+	fn every_benchmark_dispatches_its_call() {
+		let measurements: Vec<Measurement> =
+			BENCHMARKS.iter().map(|benchmark| run(benchmark, 10, 3).unwrap()).collect();
+		assert!(measurements.iter().all(|measurement| measurement.weight > 0));
+		let measurement = block(10, 3).unwrap();
+		assert!(measurement.execution > std::time::Duration::ZERO);
+		// Naming and filtering the call, and the logging span of the extrinsic, allocate
+		// nothing: what is left is mostly the copy of the state the call is dispatched on, see
+		// `support::with_transaction`, and what the transfer itself writes.
+		assert!(measurement.allocations > 0);
+		assert!(measurement.allocations <= 25, "{} allocations", measurement.allocations);
+
+		assert_eq!(group_digits(1_234_567), "1_234_567");
+	}
+
+	#[test]
+	fn the_weights_module_is_generated() {
+		let weights = [
+			weights::balances::TRANSFER,
+			weights::balances::FORCE_TRANSFER,
+			weights::balances::SET_BALANCE,
+			weights::proof_of_existence::CREATE_CLAIM,
+			weights::proof_of_existence::REVOKE_CLAIM,
+			weights::proof_of_existence::TRANSFER_CLAIM,
+			weights::proof_of_existence::ATTEST,
+			weights::proof_of_existence::UPDATE_CLAIM,
+		];
+		let measurements: Vec<Measurement> = BENCHMARKS
+			.iter()
+			.zip(weights)
+			.map(|(benchmark, weight)| Measurement {
+				pallet: benchmark.pallet,
+				call: benchmark.call,
+				weight,
+			})
+			.collect();
+		assert_eq!(weights_source(&measurements), include_str!("weights.rs"));
+	}
+}
@@ -0,0 +1,510 @@
+use std::collections::BTreeMap;
+
+use num::CheckedAdd;
+
+use crate::{
+	balances,
+	codec::{Decode, Encode},
+	metadata::{field, storage, variant, PalletMetadata},
+	migrations::{self, StorageVersion},
+	support::{ensure_root, ensure_signed, DispatchResult, Weight},
+	system::OriginFor,
+	treasury,
+	trie::{self, Storage},
+};
+
+pub type BountyIndex = u32;
+
+/// Tips are paid out of the treasury pot, so the treasury is part of the configuration.
+pub trait Config: treasury::Config {
+	/// How long (in blocks) an awarded bounty can be challenged before it is paid out.
+	const CHALLENGE_PERIOD: Self::BlockNumber;
+	const MAX_DESCRIPTION_LENGTH: usize;
+	/// The largest tip the treasury pays at once.
+	const MAX_TIP: Self::Balance;
+
+	/// Check the origin of tips, root by default.
+	fn ensure_tip_origin(origin: OriginFor<Self>) -> DispatchResult {
+		ensure_root(origin)
+	}
+}
+
+#[derive(Debug, Clone)]
+pub enum Call<T: Config> {
+	/// Post a bounty, reserving its value from the caller, who becomes its curator.
+	PostBounty { value: T::Balance, description: Vec<u8> },
+	/// Claim an open bounty as the worker.
+	Claim { bounty: BountyIndex },
+	/// Approve the work of the worker as the curator, starting the challenge period.
+	Award { bounty: BountyIndex },
+	/// Pay out an awarded bounty once its challenge period is over.
+	ClaimPayout { bounty: BountyIndex },
+	/// Close an unclaimed bounty as the curator, getting its value back.
+	CloseBounty { bounty: BountyIndex },
+	/// Root only: reject the award of a bounty during its challenge period, reopening it.
+	Challenge { bounty: BountyIndex },
+	/// `ensure_tip_origin` only: pay a tip to `who` out of the treasury pot.
+	Tip { who: T::AccountId, amount: T::Balance },
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::PostBounty { value, description } => (0u8, value, description).encode_to(dest),
+			Call::Claim { bounty } => (1u8, bounty).encode_to(dest),
+			Call::Award { bounty } => (2u8, bounty).encode_to(dest),
+			Call::ClaimPayout { bounty } => (3u8, bounty).encode_to(dest),
+			Call::CloseBounty { bounty } => (4u8, bounty).encode_to(dest),
+			Call::Challenge { bounty } => (5u8, bounty).encode_to(dest),
+			Call::Tip { who, amount } => (6u8, who, amount).encode_to(dest),
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::PostBounty {
+				value: Decode::decode(input)?,
+				description: Decode::decode(input)?,
+			},
+			1 => Call::Claim { bounty: Decode::decode(input)? },
+			2 => Call::Award { bounty: Decode::decode(input)? },
+			3 => Call::ClaimPayout { bounty: Decode::decode(input)? },
+			4 => Call::CloseBounty { bounty: Decode::decode(input)? },
+			5 => Call::Challenge { bounty: Decode::decode(input)? },
+			6 => Call::Tip { who: Decode::decode(input)?, amount: Decode::decode(input)? },
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::PostBounty { .. } => 0,
+			Call::Claim { .. } => 1,
+			Call::Award { .. } => 2,
+			Call::ClaimPayout { .. } => 3,
+			Call::CloseBounty { .. } => 4,
+			Call::Challenge { .. } => 5,
+			Call::Tip { .. } => 6,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::PostBounty { .. } | Call::ClaimPayout { .. } | Call::CloseBounty { .. } => 10_000,
+			Call::Tip { .. } => 10_000,
+			Call::Claim { .. } | Call::Award { .. } | Call::Challenge { .. } => 5_000,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BountyStatus<AccountId, BlockNumber> {
+	Open,
+	Claimed {
+		worker: AccountId,
+	},
+	/// Awarded to `worker`, who can claim the payout from `unlock_at` on.
+	PendingPayout {
+		worker: AccountId,
+		unlock_at: BlockNumber,
+	},
+}
+
+impl<AccountId: Encode, BlockNumber: Encode> Encode for BountyStatus<AccountId, BlockNumber> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			BountyStatus::Open => 0u8.encode_to(dest),
+			BountyStatus::Claimed { worker } => (1u8, worker).encode_to(dest),
+			BountyStatus::PendingPayout { worker, unlock_at } => {
+				(2u8, worker, unlock_at).encode_to(dest)
+			},
+		}
+	}
+}
+
+impl<AccountId: Decode, BlockNumber: Decode> Decode for BountyStatus<AccountId, BlockNumber> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => BountyStatus::Open,
+			1 => BountyStatus::Claimed { worker: Decode::decode(input)? },
+			2 => BountyStatus::PendingPayout {
+				worker: Decode::decode(input)?,
+				unlock_at: Decode::decode(input)?,
+			},
+			_ => return Err("Invalid bounty status"),
+		})
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Bounty<T: Config> {
+	pub curator: T::AccountId,
+	pub value: T::Balance,
+	pub description: Vec<u8>,
+	pub status: BountyStatus<T::AccountId, T::BlockNumber>,
+}
+
+impl<T: Config> Encode for Bounty<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.curator, &self.value, &self.description, &self.status).encode_to(dest);
+	}
+}
+
+impl<T: Config> Decode for Bounty<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (curator, value, description, status) = Decode::decode(input)?;
+		Ok(Bounty { curator, value, description, status })
+	}
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	bounties: BTreeMap<BountyIndex, Bounty<T>>,
+	bounty_count: BountyIndex,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self { bounties: BTreeMap::new(), bounty_count: 0 }
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Bounties",
+			index: None,
+			calls: vec![
+				variant(
+					"PostBounty",
+					0,
+					vec![field::<T::Balance>("value"), field::<Vec<u8>>("description")],
+				),
+				variant("Claim", 1, vec![field::<BountyIndex>("bounty")]),
+				variant("Award", 2, vec![field::<BountyIndex>("bounty")]),
+				variant("ClaimPayout", 3, vec![field::<BountyIndex>("bounty")]),
+				variant("CloseBounty", 4, vec![field::<BountyIndex>("bounty")]),
+				variant("Challenge", 5, vec![field::<BountyIndex>("bounty")]),
+				variant(
+					"Tip",
+					6,
+					vec![field::<T::AccountId>("who"), field::<T::Balance>("amount")],
+				),
+			],
+			storage: vec![
+				storage::<BTreeMap<BountyIndex, Bounty<T>>>("bounties"),
+				storage::<BountyIndex>("bounty_count"),
+			],
+			events: Vec::new(),
+			errors: vec![
+				"Bounty already claimed",
+				"Bounty does not exist",
+				"Bounty is not awarded",
+				"Bounty is not claimed",
+				"Caller is not the curator",
+				"Caller is not the worker",
+				"Challenge period not over",
+				"Curator cannot claim its own bounty",
+				"Description too long",
+				"Tip too large",
+			],
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Bounties", STORAGE_VERSION);
+		trie::insert_entries(storage, "Bounties", "bounties", &self.bounties);
+		trie::insert_value(storage, "Bounties", "bounty_count", &self.bounty_count);
+	}
+
+	/// Restore the bounties `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult {
+		migrations::ensure_version(storage, "Bounties", STORAGE_VERSION)?;
+		self.bounties =
+			trie::decode_entries(storage, "Bounties", "bounties")?.into_iter().collect();
+		trie::restore_value(storage, "Bounties", "bounty_count", &mut self.bounty_count)?;
+		Ok(())
+	}
+
+	/// Bounties are funded with reserves and awards unlock after a period, so calls are
+	/// dispatched together with the balances pallet and the current block number.
+	pub fn dispatch(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		now: T::BlockNumber,
+		origin: OriginFor<T>,
+		call: Call<T>,
+	) -> DispatchResult {
+		match call {
+			Call::PostBounty { value, description } => self
+				.post_bounty(balances, ensure_signed(origin)?, value, description)
+				.map(|_| ()),
+			Call::Claim { bounty } => self.claim(ensure_signed(origin)?, bounty),
+			Call::Award { bounty } => self.award(now, ensure_signed(origin)?, bounty),
+			Call::ClaimPayout { bounty } => {
+				self.claim_payout(balances, now, ensure_signed(origin)?, bounty)
+			},
+			Call::CloseBounty { bounty } => {
+				self.close_bounty(balances, ensure_signed(origin)?, bounty)
+			},
+			Call::Challenge { bounty } => {
+				ensure_root(origin)?;
+				self.challenge(bounty)
+			},
+			Call::Tip { who, amount } => {
+				T::ensure_tip_origin(origin)?;
+				self.tip(balances, who, amount)
+			},
+		}
+	}
+
+	pub fn bounty(&self, bounty: BountyIndex) -> Option<&Bounty<T>> {
+		self.bounties.get(&bounty)
+	}
+
+	pub fn post_bounty(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		curator: T::AccountId,
+		value: T::Balance,
+		description: Vec<u8>,
+	) -> Result<BountyIndex, &'static str> {
+		if description.len() > T::MAX_DESCRIPTION_LENGTH {
+			return Err("Description too long");
+		}
+		balances.reserve(&curator, value)?;
+
+		let bounty = self.bounty_count;
+		self.bounty_count += 1;
+		self.bounties
+			.insert(bounty, Bounty { curator, value, description, status: BountyStatus::Open });
+		Ok(bounty)
+	}
+
+	pub fn claim(&mut self, worker: T::AccountId, bounty: BountyIndex) -> DispatchResult {
+		let info = self.bounties.get_mut(&bounty).ok_or("Bounty does not exist")?;
+		if info.status != BountyStatus::Open {
+			return Err("Bounty already claimed");
+		}
+		if info.curator == worker {
+			return Err("Curator cannot claim its own bounty");
+		}
+		info.status = BountyStatus::Claimed { worker };
+		Ok(())
+	}
+
+	pub fn award(
+		&mut self,
+		now: T::BlockNumber,
+		caller: T::AccountId,
+		bounty: BountyIndex,
+	) -> DispatchResult {
+		let info = self.bounties.get_mut(&bounty).ok_or("Bounty does not exist")?;
+		if info.curator != caller {
+			return Err("Caller is not the curator");
+		}
+		let BountyStatus::Claimed { worker } = &info.status else {
+			return Err("Bounty is not claimed");
+		};
+		let unlock_at = now.checked_add(&T::CHALLENGE_PERIOD).unwrap_or(now);
+		info.status = BountyStatus::PendingPayout { worker: worker.clone(), unlock_at };
+		Ok(())
+	}
+
+	pub fn challenge(&mut self, bounty: BountyIndex) -> DispatchResult {
+		let info = self.bounties.get_mut(&bounty).ok_or("Bounty does not exist")?;
+		if !matches!(info.status, BountyStatus::PendingPayout { .. }) {
+			return Err("Bounty is not awarded");
+		}
+		info.status = BountyStatus::Open;
+		Ok(())
+	}
+
+	pub fn claim_payout(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		now: T::BlockNumber,
+		caller: T::AccountId,
+		bounty: BountyIndex,
+	) -> DispatchResult {
+		let info = self.bounties.get(&bounty).ok_or("Bounty does not exist")?;
+		let BountyStatus::PendingPayout { worker, unlock_at } = &info.status else {
+			return Err("Bounty is not awarded");
+		};
+		if *worker != caller {
+			return Err("Caller is not the worker");
+		}
+		if now < *unlock_at {
+			return Err("Challenge period not over");
+		}
+
+		balances.repatriate_reserved(&info.curator, worker, info.value)?;
+		self.bounties.remove(&bounty);
+		Ok(())
+	}
+
+	pub fn close_bounty(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		caller: T::AccountId,
+		bounty: BountyIndex,
+	) -> DispatchResult {
+		let info = self.bounties.get(&bounty).ok_or("Bounty does not exist")?;
+		if info.curator != caller {
+			return Err("Caller is not the curator");
+		}
+		if info.status != BountyStatus::Open {
+			return Err("Bounty already claimed");
+		}
+
+		balances.unreserve(&info.curator, info.value)?;
+		self.bounties.remove(&bounty);
+		Ok(())
+	}
+
+	pub fn tip(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		who: T::AccountId,
+		amount: T::Balance,
+	) -> DispatchResult {
+		if amount > T::MAX_TIP {
+			return Err("Tip too large");
+		}
+		balances.transfer(<T as treasury::Config>::account_id(), who, amount)
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{BountyStatus, Call};
+	use crate::{
+		balances,
+		support::{Percent, RawOrigin},
+	};
+
+	struct TestConfig;
+
+	impl crate::system::Config for TestConfig {
+		type AccountId = String;
+		type BlockNumber = u32;
+		type Nonce = u32;
+		type RuntimeEvent = ();
+		const MAX_BLOCK_WEIGHT: u64 = 1_000_000;
+		const MAX_EXTRINSICS_PER_ACCOUNT: u32 = 10;
+	}
+
+	impl balances::Config for TestConfig {
+		type Balance = u128;
+		type Address = String;
+		type RuntimeHoldReason = crate::mock::HoldReason;
+		const EXISTENTIAL_DEPOSIT: u128 = 1;
+
+		fn dust_removal() -> balances::DustRemoval<String> {
+			balances::DustRemoval::Burn
+		}
+	}
+
+	impl crate::treasury::Config for TestConfig {
+		const PROPOSAL_BOND: Percent = Percent(5);
+		const PROPOSAL_BOND_MINIMUM: u128 = 1;
+		const SPEND_PERIOD: u32 = 10;
+		const BURN: Percent = Percent(0);
+		const MAX_APPROVALS: usize = 2;
+
+		fn account_id() -> String {
+			"treasury".to_string()
+		}
+	}
+
+	impl super::Config for TestConfig {
+		const CHALLENGE_PERIOD: u32 = 3;
+		const MAX_DESCRIPTION_LENGTH: usize = 16;
+		const MAX_TIP: u128 = 20;
+	}
+
+	#[test]
+	fn awarded_bounty_pays_out_after_challenge_period() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut balances: balances::Pallet<TestConfig> = balances::Pallet::new();
+		let mut bounties: super::Pallet<TestConfig> = super::Pallet::new();
+		balances.set_balance(&alice, 100);
+
+		assert_eq!(
+			bounties.post_bounty(&mut balances, alice.clone(), 50, b"fix bug".to_vec()),
+			Ok(0)
+		);
+		assert_eq!(balances.reserved_balance(&alice), 50);
+		assert_eq!(bounties.award(1, alice.clone(), 0), Err("Bounty is not claimed"));
+		assert_eq!(bounties.claim(alice.clone(), 0), Err("Curator cannot claim its own bounty"));
+		assert_eq!(bounties.claim(bob.clone(), 0), Ok(()));
+		assert_eq!(bounties.claim("charlie".to_string(), 0), Err("Bounty already claimed"));
+		assert_eq!(bounties.award(1, bob.clone(), 0), Err("Caller is not the curator"));
+		assert_eq!(bounties.award(1, alice.clone(), 0), Ok(()));
+
+		assert_eq!(
+			bounties.claim_payout(&mut balances, 3, bob.clone(), 0),
+			Err("Challenge period not over")
+		);
+		assert_eq!(bounties.claim_payout(&mut balances, 4, bob.clone(), 0), Ok(()));
+		assert_eq!(balances.balance(&bob), 50);
+		assert_eq!(balances.reserved_balance(&alice), 0);
+		assert!(bounties.bounty(0).is_none());
+	}
+
+	#[test]
+	fn challenged_award_reopens_bounty() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut balances: balances::Pallet<TestConfig> = balances::Pallet::new();
+		let mut bounties: super::Pallet<TestConfig> = super::Pallet::new();
+		balances.set_balance(&alice, 100);
+
+		let _ = bounties.post_bounty(&mut balances, alice.clone(), 50, b"fix bug".to_vec());
+		let _ = bounties.claim(bob.clone(), 0);
+		let _ = bounties.award(1, alice.clone(), 0);
+		assert_eq!(
+			bounties.dispatch(
+				&mut balances,
+				1,
+				RawOrigin::Signed(bob),
+				Call::Challenge { bounty: 0 }
+			),
+			Err("Bad origin")
+		);
+		assert_eq!(
+			bounties.dispatch(&mut balances, 1, RawOrigin::Root, Call::Challenge { bounty: 0 }),
+			Ok(())
+		);
+		assert_eq!(bounties.bounty(0).map(|bounty| &bounty.status), Some(&BountyStatus::Open));
+
+		assert_eq!(bounties.close_bounty(&mut balances, alice.clone(), 0), Ok(()));
+		assert_eq!(balances.balance(&alice), 100);
+	}
+
+	#[test]
+	fn tips_come_from_the_treasury() {
+		let treasury = "treasury".to_string();
+		let bob = "bob".to_string();
+		let mut balances: balances::Pallet<TestConfig> = balances::Pallet::new();
+		let mut bounties: super::Pallet<TestConfig> = super::Pallet::new();
+		balances.set_balance(&treasury, 100);
+
+		assert_eq!(bounties.tip(&mut balances, bob.clone(), 21), Err("Tip too large"));
+		assert_eq!(bounties.tip(&mut balances, bob.clone(), 20), Ok(()));
+		assert_eq!(balances.balance(&treasury), 80);
+		assert_eq!(balances.balance(&bob), 20);
+	}
+}
@@ -0,0 +1,546 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use num::{CheckedAdd, CheckedSub, Zero};
+
+use crate::{
+	balances,
+	codec::{Decode, Encode},
+	hashing::{self, Hash},
+	metadata::{field, storage, variant, PalletMetadata, Registry, TypeDef, TypeInfo},
+	migrations::{self, StorageVersion},
+	support::{ensure_root, ensure_signed, DispatchResult, Weight},
+	system::OriginFor,
+	trie::{self, Storage},
+};
+
+/// Identifies a chain on the other side of the bridge.
+pub type ChainId = u32;
+pub type MessageNonce = u64;
+
+pub trait Config: balances::Config {
+	/// The maximum number of relayers.
+	const MAX_RELAYERS: usize;
+
+	/// The account holding the funds locked while they are bridged to other chains.
+	fn account_id() -> Self::AccountId;
+}
+
+/// A transfer from another chain, minted here once enough relayers approved it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InboundMessage<AccountId, Balance> {
+	pub source_chain: ChainId,
+	pub nonce: MessageNonce,
+	pub recipient: AccountId,
+	pub amount: Balance,
+}
+
+impl<AccountId: Encode, Balance: Encode> Encode for InboundMessage<AccountId, Balance> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(self.source_chain, self.nonce, &self.recipient, &self.amount).encode_to(dest);
+	}
+}
+
+impl<AccountId: Decode, Balance: Decode> Decode for InboundMessage<AccountId, Balance> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (source_chain, nonce, recipient, amount) = Decode::decode(input)?;
+		Ok(InboundMessage { source_chain, nonce, recipient, amount })
+	}
+}
+
+impl<AccountId: TypeInfo, Balance: TypeInfo> TypeInfo for InboundMessage<AccountId, Balance> {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		TypeDef::Composite(vec![
+			registry.field::<ChainId>("source_chain"),
+			registry.field::<MessageNonce>("nonce"),
+			registry.field::<AccountId>("recipient"),
+			registry.field::<Balance>("amount"),
+		])
+	}
+}
+
+pub type InboundMessageOf<T> =
+	InboundMessage<<T as crate::system::Config>::AccountId, <T as balances::Config>::Balance>;
+
+#[derive(Debug, Clone)]
+pub enum Call<T: Config> {
+	/// Lock funds here to have them minted to `recipient` on `dest_chain`.
+	Lock { dest_chain: ChainId, recipient: Vec<u8>, amount: T::Balance },
+	/// Approve an inbound message as a relayer.
+	Relay { message: InboundMessageOf<T> },
+	/// Burn wrapped funds to have them released to `recipient` on the chain they came from.
+	BurnWrapped { chain: ChainId, recipient: Vec<u8>, amount: T::Balance },
+	/// Root only: replace the relayers and the number of approvals a message needs.
+	SetRelayers { relayers: Vec<T::AccountId>, threshold: u32 },
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::Lock { dest_chain, recipient, amount } => {
+				(0u8, dest_chain, recipient, amount).encode_to(dest)
+			},
+			Call::Relay { message } => (1u8, message).encode_to(dest),
+			Call::BurnWrapped { chain, recipient, amount } => {
+				(2u8, chain, recipient, amount).encode_to(dest)
+			},
+			Call::SetRelayers { relayers, threshold } => (3u8, relayers, threshold).encode_to(dest),
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::Lock {
+				dest_chain: Decode::decode(input)?,
+				recipient: Decode::decode(input)?,
+				amount: Decode::decode(input)?,
+			},
+			1 => Call::Relay { message: Decode::decode(input)? },
+			2 => Call::BurnWrapped {
+				chain: Decode::decode(input)?,
+				recipient: Decode::decode(input)?,
+				amount: Decode::decode(input)?,
+			},
+			3 => Call::SetRelayers {
+				relayers: Decode::decode(input)?,
+				threshold: Decode::decode(input)?,
+			},
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::Lock { .. } => 0,
+			Call::Relay { .. } => 1,
+			Call::BurnWrapped { .. } => 2,
+			Call::SetRelayers { .. } => 3,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::Lock { .. } | Call::Relay { .. } | Call::BurnWrapped { .. } => 10_000,
+			Call::SetRelayers { relayers, .. } => 5_000 + 1_000 * relayers.len() as Weight,
+		}
+	}
+}
+
+/// What relayers watch for, and what they get told back.
+#[derive(Debug, Clone)]
+pub enum Event<T: Config> {
+	Locked {
+		nonce: MessageNonce,
+		sender: T::AccountId,
+		dest_chain: ChainId,
+		recipient: Vec<u8>,
+		amount: T::Balance,
+	},
+	Burned {
+		nonce: MessageNonce,
+		sender: T::AccountId,
+		chain: ChainId,
+		recipient: Vec<u8>,
+		amount: T::Balance,
+	},
+	Minted {
+		source_chain: ChainId,
+		nonce: MessageNonce,
+		recipient: T::AccountId,
+		amount: T::Balance,
+	},
+}
+
+impl<T: Config> Event<T> {
+	/// The name of the variant, as in the metadata.
+	pub fn name(&self) -> &'static str {
+		match self {
+			Event::Locked { .. } => "Locked",
+			Event::Burned { .. } => "Burned",
+			Event::Minted { .. } => "Minted",
+		}
+	}
+
+	/// The accounts of this chain the event is about.
+	pub fn accounts(&self) -> Vec<&T::AccountId> {
+		match self {
+			Event::Locked { sender, .. } | Event::Burned { sender, .. } => vec![sender],
+			Event::Minted { recipient, .. } => vec![recipient],
+		}
+	}
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	/// Kept sorted.
+	relayers: Vec<T::AccountId>,
+	threshold: u32,
+	outbound_nonce: MessageNonce,
+	/// The relayers that approved each inbound message, by message hash.
+	approvals: BTreeMap<Hash, Vec<T::AccountId>>,
+	executed: BTreeSet<(ChainId, MessageNonce)>,
+	/// Wrapped funds, by the chain they came from.
+	wrapped: BTreeMap<ChainId, BTreeMap<T::AccountId, T::Balance>>,
+	events: Vec<Event<T>>,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self {
+			relayers: Vec::new(),
+			threshold: 0,
+			outbound_nonce: 0,
+			approvals: BTreeMap::new(),
+			executed: BTreeSet::new(),
+			wrapped: BTreeMap::new(),
+			events: Vec::new(),
+		}
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Bridge",
+			index: None,
+			calls: vec![
+				variant(
+					"Lock",
+					0,
+					vec![
+						field::<ChainId>("dest_chain"),
+						field::<Vec<u8>>("recipient"),
+						field::<T::Balance>("amount"),
+					],
+				),
+				variant("Relay", 1, vec![field::<InboundMessageOf<T>>("message")]),
+				variant(
+					"BurnWrapped",
+					2,
+					vec![
+						field::<ChainId>("chain"),
+						field::<Vec<u8>>("recipient"),
+						field::<T::Balance>("amount"),
+					],
+				),
+				variant(
+					"SetRelayers",
+					3,
+					vec![field::<Vec<T::AccountId>>("relayers"), field::<u32>("threshold")],
+				),
+			],
+			storage: vec![
+				storage::<Vec<T::AccountId>>("relayers"),
+				storage::<u32>("threshold"),
+				storage::<MessageNonce>("outbound_nonce"),
+				storage::<BTreeMap<Hash, Vec<T::AccountId>>>("approvals"),
+				storage::<BTreeSet<(ChainId, MessageNonce)>>("executed"),
+				storage::<BTreeMap<ChainId, BTreeMap<T::AccountId, T::Balance>>>("wrapped"),
+			],
+			events: vec![
+				variant(
+					"Locked",
+					0,
+					vec![
+						field::<MessageNonce>("nonce"),
+						field::<T::AccountId>("sender"),
+						field::<ChainId>("dest_chain"),
+						field::<Vec<u8>>("recipient"),
+						field::<T::Balance>("amount"),
+					],
+				),
+				variant(
+					"Burned",
+					1,
+					vec![
+						field::<MessageNonce>("nonce"),
+						field::<T::AccountId>("sender"),
+						field::<ChainId>("chain"),
+						field::<Vec<u8>>("recipient"),
+						field::<T::Balance>("amount"),
+					],
+				),
+				variant(
+					"Minted",
+					2,
+					vec![
+						field::<ChainId>("source_chain"),
+						field::<MessageNonce>("nonce"),
+						field::<T::AccountId>("recipient"),
+						field::<T::Balance>("amount"),
+					],
+				),
+			],
+			errors: vec![
+				"Already approved",
+				"Caller is not a relayer",
+				"Insufficient wrapped balance",
+				"Invalid threshold",
+				"Message already executed",
+				"Overflow when adding balance",
+				"Too many relayers",
+			],
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Bridge", STORAGE_VERSION);
+		trie::insert_value(storage, "Bridge", "relayers", &self.relayers);
+		trie::insert_value(storage, "Bridge", "threshold", &self.threshold);
+		trie::insert_value(storage, "Bridge", "outbound_nonce", &self.outbound_nonce);
+		trie::insert_entries(storage, "Bridge", "approvals", &self.approvals);
+		trie::insert_entries(
+			storage,
+			"Bridge",
+			"executed",
+			self.executed.iter().map(|id| (id, ())),
+		);
+		let wrapped = self.wrapped.iter().flat_map(|(chain, balances)| {
+			balances.iter().map(move |(who, amount)| ((chain, who), amount))
+		});
+		trie::insert_entries(storage, "Bridge", "wrapped", wrapped);
+	}
+
+	/// Restore the relayers, messages and wrapped funds `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult {
+		migrations::ensure_version(storage, "Bridge", STORAGE_VERSION)?;
+		trie::restore_value(storage, "Bridge", "relayers", &mut self.relayers)?;
+		trie::restore_value(storage, "Bridge", "threshold", &mut self.threshold)?;
+		trie::restore_value(storage, "Bridge", "outbound_nonce", &mut self.outbound_nonce)?;
+		self.approvals =
+			trie::decode_entries(storage, "Bridge", "approvals")?.into_iter().collect();
+		let executed = trie::decode_entries::<_, ()>(storage, "Bridge", "executed")?;
+		self.executed = executed.into_iter().map(|(id, ())| id).collect();
+		let mut wrapped = BTreeMap::<_, BTreeMap<_, _>>::new();
+		for ((chain, who), amount) in trie::decode_entries(storage, "Bridge", "wrapped")? {
+			wrapped.entry(chain).or_default().insert(who, amount);
+		}
+		self.wrapped = wrapped;
+		Ok(())
+	}
+
+	/// Locking moves native funds, so calls are dispatched together with the balances pallet.
+	pub fn dispatch(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		origin: OriginFor<T>,
+		call: Call<T>,
+	) -> DispatchResult {
+		match call {
+			Call::Lock { dest_chain, recipient, amount } => {
+				self.lock(balances, ensure_signed(origin)?, dest_chain, recipient, amount)
+			},
+			Call::Relay { message } => self.relay(ensure_signed(origin)?, message),
+			Call::BurnWrapped { chain, recipient, amount } => {
+				self.burn_wrapped(ensure_signed(origin)?, chain, recipient, amount)
+			},
+			Call::SetRelayers { relayers, threshold } => {
+				ensure_root(origin)?;
+				self.set_relayers(relayers, threshold)
+			},
+		}
+	}
+
+	pub fn relayers(&self) -> &[T::AccountId] {
+		&self.relayers
+	}
+
+	pub fn threshold(&self) -> u32 {
+		self.threshold
+	}
+
+	pub fn wrapped_balance(&self, chain: ChainId, who: &T::AccountId) -> T::Balance {
+		self.wrapped
+			.get(&chain)
+			.and_then(|balances| balances.get(who))
+			.copied()
+			.unwrap_or(T::Balance::zero())
+	}
+
+	pub fn events(&self) -> &[Event<T>] {
+		&self.events
+	}
+
+	/// Hand the events deposited so far to the caller, e.g. a relayer following the chain.
+	pub fn take_events(&mut self) -> Vec<Event<T>> {
+		core::mem::take(&mut self.events)
+	}
+
+	pub fn set_relayers(
+		&mut self,
+		mut relayers: Vec<T::AccountId>,
+		threshold: u32,
+	) -> DispatchResult {
+		relayers.sort();
+		relayers.dedup();
+		if relayers.len() > T::MAX_RELAYERS {
+			return Err("Too many relayers");
+		}
+		if threshold == 0 || threshold as usize > relayers.len() {
+			return Err("Invalid threshold");
+		}
+
+		// Approvals from the previous set no longer count.
+		self.approvals.clear();
+		self.relayers = relayers;
+		self.threshold = threshold;
+		Ok(())
+	}
+
+	pub fn lock(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		sender: T::AccountId,
+		dest_chain: ChainId,
+		recipient: Vec<u8>,
+		amount: T::Balance,
+	) -> DispatchResult {
+		balances.transfer(sender.clone(), T::account_id(), amount)?;
+
+		let nonce = self.next_nonce();
+		self.events.push(Event::Locked { nonce, sender, dest_chain, recipient, amount });
+		Ok(())
+	}
+
+	pub fn burn_wrapped(
+		&mut self,
+		sender: T::AccountId,
+		chain: ChainId,
+		recipient: Vec<u8>,
+		amount: T::Balance,
+	) -> DispatchResult {
+		let balance = self
+			.wrapped_balance(chain, &sender)
+			.checked_sub(&amount)
+			.ok_or("Insufficient wrapped balance")?;
+		self.set_wrapped_balance(chain, &sender, balance);
+
+		let nonce = self.next_nonce();
+		self.events.push(Event::Burned { nonce, sender, chain, recipient, amount });
+		Ok(())
+	}
+
+	/// Approve `message`; it is minted as soon as `threshold` relayers approved the same
+	/// message.
+	pub fn relay(&mut self, relayer: T::AccountId, message: InboundMessageOf<T>) -> DispatchResult {
+		if self.relayers.binary_search(&relayer).is_err() {
+			return Err("Caller is not a relayer");
+		}
+		if self.executed.contains(&(message.source_chain, message.nonce)) {
+			return Err("Message already executed");
+		}
+
+		let hash = hashing::blake2_256(&message.encode());
+		let approvals = self.approvals.entry(hash).or_default();
+		if approvals.contains(&relayer) {
+			return Err("Already approved");
+		}
+		approvals.push(relayer);
+		if (approvals.len() as u32) < self.threshold {
+			return Ok(());
+		}
+
+		let InboundMessage { source_chain, nonce, recipient, amount } = message;
+		let balance = self
+			.wrapped_balance(source_chain, &recipient)
+			.checked_add(&amount)
+			.ok_or("Overflow when adding balance")?;
+		self.set_wrapped_balance(source_chain, &recipient, balance);
+
+		self.approvals.retain(|hash_, _| *hash_ != hash);
+		self.executed.insert((source_chain, nonce));
+		self.events.push(Event::Minted { source_chain, nonce, recipient, amount });
+		Ok(())
+	}
+
+	fn set_wrapped_balance(&mut self, chain: ChainId, who: &T::AccountId, amount: T::Balance) {
+		self.wrapped.entry(chain).or_default().insert(who.clone(), amount);
+	}
+
+	fn next_nonce(&mut self) -> MessageNonce {
+		let nonce = self.outbound_nonce;
+		self.outbound_nonce += 1;
+		nonce
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{Event, InboundMessage};
+	use crate::{balances, mock::Test};
+
+	impl super::Config for Test {
+		const MAX_RELAYERS: usize = 3;
+
+		fn account_id() -> String {
+			"bridge".to_string()
+		}
+	}
+
+	fn message(amount: u128) -> InboundMessage<String, u128> {
+		InboundMessage { source_chain: 2, nonce: 0, recipient: "alice".to_string(), amount }
+	}
+
+	#[test]
+	fn lock_and_burn_emit_events() {
+		let alice = "alice".to_string();
+		let mut balances: balances::Pallet<Test> = balances::Pallet::new();
+		let mut bridge: super::Pallet<Test> = super::Pallet::new();
+		balances.set_balance(&alice, 100);
+
+		assert_eq!(bridge.lock(&mut balances, alice.clone(), 2, b"0xal1ce".to_vec(), 60), Ok(()));
+		assert_eq!(balances.balance(&"bridge".to_string()), 60);
+		assert_eq!(
+			bridge.burn_wrapped(alice.clone(), 2, b"0xal1ce".to_vec(), 1),
+			Err("Insufficient wrapped balance")
+		);
+
+		let events = bridge.take_events();
+		assert!(matches!(
+			events.as_slice(),
+			[Event::Locked { nonce: 0, dest_chain: 2, amount: 60, .. }]
+		));
+		assert!(bridge.events().is_empty());
+	}
+
+	#[test]
+	fn inbound_messages_need_threshold_approvals() {
+		let relayers = ["r1", "r2", "r3"].map(|who| who.to_string());
+		let alice = "alice".to_string();
+		let mut bridge: super::Pallet<Test> = super::Pallet::new();
+
+		assert_eq!(bridge.set_relayers(relayers.to_vec(), 4), Err("Invalid threshold"));
+		assert_eq!(bridge.set_relayers(relayers.to_vec(), 2), Ok(()));
+		assert_eq!(bridge.relay(alice.clone(), message(50)), Err("Caller is not a relayer"));
+
+		assert_eq!(bridge.relay(relayers[0].clone(), message(50)), Ok(()));
+		assert_eq!(bridge.relay(relayers[0].clone(), message(50)), Err("Already approved"));
+		// A relayer approving different content doesn't count towards the same message.
+		assert_eq!(bridge.relay(relayers[1].clone(), message(5_000)), Ok(()));
+		assert_eq!(bridge.wrapped_balance(2, &alice), 0);
+
+		assert_eq!(bridge.relay(relayers[2].clone(), message(50)), Ok(()));
+		assert_eq!(bridge.wrapped_balance(2, &alice), 50);
+		assert_eq!(
+			bridge.relay(relayers[0].clone(), message(5_000)),
+			Err("Message already executed")
+		);
+
+		assert_eq!(bridge.burn_wrapped(alice.clone(), 2, b"0xal1ce".to_vec(), 20), Ok(()));
+		assert_eq!(bridge.wrapped_balance(2, &alice), 30);
+		assert!(matches!(
+			bridge.events(),
+			[Event::Minted { amount: 50, .. }, Event::Burned { nonce: 0, amount: 20, .. }]
+		));
+	}
+}
@@ -0,0 +1,595 @@
+//! The blocks a node knows about form a tree: several blocks may build on the same parent, e.g.
+//! when two authors produce a block at the same height. The longest chain is the best one, and
+//! its state is the one the node serves. When a fork becomes longer than the best chain, the
+//! node reorganizes onto it by executing the blocks of the fork. Forks can't revert the
+//! finalized block though, see the `finality` module.
+//!
+//! The states of executed blocks are kept, so a reorg only executes the blocks after the last
+//! common block whose state is still there. Which ones are kept depends on the pruning mode.
+//!
+//! Blocks on forks are executed when they are imported too, so an invalid one is rejected
+//! before it is kept. Forks that fall `MAX_FORK_DEPTH` blocks behind the best block are
+//! dropped, and no more than `MAX_FORK_BLOCKS` blocks are kept off the best chain, so peers
+//! can't fill the memory of the node with side chains.
+
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+	str::FromStr,
+};
+
+use crate::{
+	codec::Encode,
+	hashing::{self, Hash},
+	import_queue::{self, ImportError, ImportedBlock},
+	types, Runtime,
+};
+
+/// How far the tip of a fork may fall behind the best block before the fork is dropped.
+pub const MAX_FORK_DEPTH: types::BlockNumber = 64;
+/// The most blocks kept that aren't on the best chain.
+pub const MAX_FORK_BLOCKS: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportOutcome {
+	/// The block extends the best chain.
+	NewBest(ImportedBlock),
+	/// The block made a fork the best chain: the blocks of the old best chain from the common
+	/// ancestor on were retracted and the ones of the fork enacted, both oldest first.
+	Reorg {
+		retracted: Vec<Hash>,
+		enacted: Vec<Hash>,
+	},
+	/// The block is kept on a fork that isn't longer than the best chain.
+	Fork(Hash),
+	AlreadyKnown,
+}
+
+/// Which states a node keeps besides the one of the best block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pruning {
+	/// Keep the states of all blocks, including the genesis state.
+	Archive,
+	/// Keep the states of the last `n` blocks before the best one, and of the finalized one.
+	Last(types::BlockNumber),
+	/// Keep the states of the finalized block and the blocks after it.
+	Finalized,
+}
+
+/// Parses the `--pruning` argument of the node: `archive`, `finalized` or a number of blocks.
+impl FromStr for Pruning {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"archive" => Ok(Pruning::Archive),
+			"finalized" => Ok(Pruning::Finalized),
+			n => n.parse().map(Pruning::Last).map_err(|_| "Invalid pruning mode"),
+		}
+	}
+}
+
+pub struct Chain {
+	/// The states after each block, the genesis state being stored under `genesis`. The best
+	/// state is always there.
+	states: BTreeMap<Hash, Runtime>,
+	/// The block the genesis state is at: `GENESIS_PARENT_HASH`, or the best block of the
+	/// snapshot the node started from.
+	genesis: Hash,
+	genesis_number: types::BlockNumber,
+	pruning: Pruning,
+	blocks: BTreeMap<Hash, types::Block>,
+	best: Hash,
+	finalized: Hash,
+}
+
+pub fn block_hash(block: &types::Block) -> Hash {
+	hashing::blake2_256(&block.header.encode())
+}
+
+impl Chain {
+	pub fn new(genesis: Runtime) -> Self {
+		Self::with_pruning(genesis, Pruning::Archive)
+	}
+
+	/// Panics if the hash of the best block of `genesis` is unknown, which a runtime imported
+	/// from a snapshot always knows.
+	pub fn with_pruning(mut genesis: Runtime, pruning: Pruning) -> Self {
+		// A genesis set up by hand may not have applied its account references yet.
+		genesis.note_changes();
+		let hash = import_queue::best_hash(&genesis).expect("Genesis block hash is unknown");
+		Self {
+			genesis: hash,
+			genesis_number: genesis.system.block_number(),
+			states: BTreeMap::from([(hash, genesis)]),
+			pruning,
+			blocks: BTreeMap::new(),
+			best: hash,
+			finalized: hash,
+		}
+	}
+
+	/// The state at the best block.
+	pub fn state(&self) -> &Runtime {
+		&self.states[&self.best]
+	}
+
+	/// The state after block `hash`, unless it was pruned or the block wasn't executed.
+	pub fn state_at(&self, hash: &Hash) -> Option<&Runtime> {
+		self.states.get(hash)
+	}
+
+	pub fn best_hash(&self) -> Hash {
+		self.best
+	}
+
+	pub fn best_number(&self) -> types::BlockNumber {
+		self.number(&self.best)
+	}
+
+	/// The last finalized block, which every future best chain contains.
+	pub fn finalized_head(&self) -> Hash {
+		self.finalized
+	}
+
+	pub fn finalized_number(&self) -> types::BlockNumber {
+		self.number(&self.finalized)
+	}
+
+	pub fn block(&self, hash: &Hash) -> Option<&types::Block> {
+		self.blocks.get(hash)
+	}
+
+	pub fn number(&self, hash: &Hash) -> types::BlockNumber {
+		self.blocks
+			.get(hash)
+			.map_or(self.genesis_number, |block| block.header.block_number)
+	}
+
+	/// The hashes of the blocks from the first block up to `hash`.
+	pub fn route(&self, mut hash: Hash) -> Vec<Hash> {
+		let mut route = Vec::new();
+		while let Some(block) = self.blocks.get(&hash) {
+			route.push(hash);
+			hash = block.header.parent_hash;
+		}
+		route.reverse();
+		route
+	}
+
+	/// The blocks of the best chain numbered `from` to `to`, both included.
+	pub fn best_chain_blocks(
+		&self,
+		from: types::BlockNumber,
+		to: types::BlockNumber,
+	) -> Vec<types::Block> {
+		let route = self.route(self.best);
+		let blocks = route.iter().map(|hash| &self.blocks[hash]);
+		blocks
+			.filter(|block| (from..=to).contains(&block.header.block_number))
+			.cloned()
+			.collect()
+	}
+
+	/// Whether the block `hash` builds on `ancestor`, or is `ancestor`.
+	pub fn is_descendant(&self, hash: Hash, ancestor: Hash) -> bool {
+		ancestor == self.genesis || self.route(hash).contains(&ancestor)
+	}
+
+	/// Finalize the block `hash` of the best chain, dropping the forks that don't contain it.
+	pub fn finalize(&mut self, hash: Hash) -> Result<(), &'static str> {
+		if !self.is_descendant(self.best, hash) {
+			return Err("Block is not on the best chain");
+		}
+		if !self.is_descendant(hash, self.finalized) {
+			return Err("Block is older than the finalized block");
+		}
+		self.finalized = hash;
+		let route: HashSet<Hash> = self.route(hash).into_iter().collect();
+		// Whether each block builds on `hash`, found by walking up to a block whose answer is
+		// known already, so every block is visited once.
+		let mut builds_on = HashMap::from([(hash, true)]);
+		for &block in self.blocks.keys() {
+			let (mut walked, mut ancestor) = (Vec::new(), block);
+			let answer = loop {
+				if let Some(&answer) = builds_on.get(&ancestor) {
+					break answer;
+				}
+				match self.blocks.get(&ancestor) {
+					Some(known) => {
+						walked.push(ancestor);
+						ancestor = known.header.parent_hash;
+					},
+					None => break false,
+				}
+			};
+			builds_on.extend(walked.into_iter().map(|walked| (walked, answer)));
+		}
+		let pruned: Vec<_> = self
+			.blocks
+			.keys()
+			.filter(|block| !route.contains(*block) && !builds_on[*block])
+			.copied()
+			.collect();
+		for block in pruned {
+			self.blocks.remove(&block);
+			self.states.remove(&block);
+		}
+		self.prune_states();
+		Ok(())
+	}
+
+	/// Make the block `hash` the best one even if a fork is longer, e.g. once a supermajority
+	/// voted for it. The blocks after it become the best ones again as they are imported.
+	pub fn set_best(&mut self, hash: Hash, local_now: types::Moment) -> Result<(), ImportError> {
+		if !self.is_descendant(hash, self.finalized) {
+			return Err(ImportError::RevertsFinalized);
+		}
+		let states = self.execute_route(hash, local_now).map_err(|(_, e)| e)?;
+		self.states.extend(states);
+		self.best = hash;
+		self.prune_states();
+		Ok(())
+	}
+
+	/// Drop the states the pruning mode doesn't keep.
+	fn prune_states(&mut self) {
+		let best = self.best_number();
+		let finalized = self.finalized_number();
+		let keep = |hash: &Hash, number: types::BlockNumber| match self.pruning {
+			Pruning::Archive => true,
+			Pruning::Last(n) => *hash == self.finalized || number.saturating_add(n) >= best,
+			Pruning::Finalized => number >= finalized,
+		};
+		let pruned: Vec<_> = self
+			.states
+			.keys()
+			.filter(|hash| **hash != self.best && !keep(hash, self.number(hash)))
+			.copied()
+			.collect();
+		for hash in pruned {
+			self.states.remove(&hash);
+		}
+	}
+
+	/// Import a block received from the network. Blocks on a fork are verified and executed
+	/// as well, so a bad one and the blocks building on it are rejected, and the state of a
+	/// fork is at hand when it becomes the best chain. A fork block is rejected with
+	/// `TooManyForks` once `MAX_FORK_BLOCKS` blocks are kept off the best chain.
+	pub fn import(
+		&mut self,
+		block: types::Block,
+		local_now: types::Moment,
+	) -> Result<ImportOutcome, ImportError> {
+		let hash = block_hash(&block);
+		if self.blocks.contains_key(&hash) {
+			return Ok(ImportOutcome::AlreadyKnown);
+		}
+		let parent = block.header.parent_hash;
+		if parent == self.best {
+			let mut state = self.state().clone();
+			let imported = import_queue::import(&mut state, block.clone(), local_now)?;
+			self.blocks.insert(hash, block);
+			self.states.insert(hash, state);
+			self.best = hash;
+			self.prune_forks();
+			self.prune_states();
+			return Ok(ImportOutcome::NewBest(imported));
+		}
+
+		let known_parent = parent == self.genesis || self.blocks.contains_key(&parent);
+		if !known_parent || block.header.block_number != self.number(&parent) + 1 {
+			return Err(ImportError::UnknownParent);
+		}
+		if !self.is_descendant(parent, self.finalized) {
+			return Err(ImportError::RevertsFinalized);
+		}
+		let number = block.header.block_number;
+		if number <= self.best_number() && self.fork_block_count() >= MAX_FORK_BLOCKS {
+			return Err(ImportError::TooManyForks);
+		}
+		self.blocks.insert(hash, block);
+
+		let states = match self.execute_route(hash, local_now) {
+			Ok(states) => states,
+			Err((bad, e)) => {
+				// Blocks building on a bad block are bad as well.
+				let descendants: Vec<_> = self
+					.blocks
+					.keys()
+					.filter(|hash| self.route(**hash).contains(&bad))
+					.copied()
+					.collect();
+				for hash in descendants {
+					self.blocks.remove(&hash);
+				}
+				return Err(e);
+			},
+		};
+		self.states.extend(states);
+		if number <= self.best_number() {
+			self.prune_states();
+			return Ok(ImportOutcome::Fork(hash));
+		}
+		let old = self.route(self.best);
+		let new = self.route(hash);
+		let common = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+		self.best = hash;
+		self.prune_forks();
+		self.prune_states();
+		Ok(ImportOutcome::Reorg {
+			retracted: old[common..].to_vec(),
+			enacted: new[common..].to_vec(),
+		})
+	}
+
+	/// The number of blocks that aren't on the best chain, which has a block at every number
+	/// after the genesis state.
+	fn fork_block_count(&self) -> usize {
+		self.blocks.len() - (self.best_number() - self.genesis_number) as usize
+	}
+
+	/// The blocks that aren't on the best chain.
+	fn fork_blocks(&self) -> Vec<Hash> {
+		let best: BTreeSet<_> = self.route(self.best).into_iter().collect();
+		self.blocks.keys().filter(|hash| !best.contains(*hash)).copied().collect()
+	}
+
+	/// Drop the forks whose blocks are all `MAX_FORK_DEPTH` blocks behind the best block or
+	/// more, which are unlikely to become the best chain.
+	fn prune_forks(&mut self) {
+		if self.fork_block_count() == 0 {
+			return;
+		}
+		let best = self.best_number();
+		let mut forks = self.fork_blocks();
+		// Children before their parents, so a parent is kept if one of its children is.
+		forks.sort_by_key(|hash| core::cmp::Reverse(self.number(hash)));
+		let mut kept = BTreeSet::new();
+		for hash in forks {
+			let recent = self.number(&hash).saturating_add(MAX_FORK_DEPTH) >= best;
+			if recent || kept.contains(&hash) {
+				kept.insert(self.blocks[&hash].header.parent_hash);
+			} else {
+				self.blocks.remove(&hash);
+				self.states.remove(&hash);
+			}
+		}
+	}
+
+	/// Execute the chain up to `hash` from the last block with a state, returning the states
+	/// after each executed block, or tell which block failed.
+	fn execute_route(
+		&self,
+		hash: Hash,
+		local_now: types::Moment,
+	) -> Result<Vec<(Hash, Runtime)>, (Hash, ImportError)> {
+		let route = self.route(hash);
+		let start = route.iter().rposition(|block| self.states.contains_key(block));
+		let base = match start {
+			Some(start) => &route[start],
+			None => &self.genesis,
+		};
+		// The state of the finalized block is always kept, and the route contains it.
+		let mut state = self.states[base].clone();
+
+		let mut states = Vec::new();
+		for hash in &route[start.map_or(0, |start| start + 1)..] {
+			import_queue::import(&mut state, self.blocks[hash].clone(), local_now)
+				.map_err(|e| (*hash, e))?;
+			states.push((*hash, state.clone()));
+		}
+		Ok(states)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{Chain, ImportOutcome};
+	use crate::{
+		balances,
+		import_queue::{self, ImportError},
+		support, timestamp, types,
+		wallet::{dev_account, dev_signer},
+		Runtime, RuntimeCall,
+	};
+
+	/// The block on top of `state` with `extrinsics` after the timestamp, and the state after
+	/// it.
+	fn block(state: &Runtime, extrinsics: Vec<types::Extrinsic>) -> (types::Block, Runtime) {
+		let number = state.system.block_number() + 1;
+		let now = timestamp::Call::Set { now: number as u64 * 1_000 };
+		let mut all = vec![support::Extrinsic {
+			caller: None,
+			call: RuntimeCall::Timestamp(now),
+			signature: None,
+		}];
+		all.extend(extrinsics);
+		let block = import_queue::build_block(state, &dev_account("alice"), all).unwrap();
+		let mut next = state.clone();
+		next.execute_block(block.clone()).unwrap();
+		(block, next)
+	}
+
+	/// A transfer of alice to put in a block on `state`.
+	fn transfer(state: &Runtime, to: &str) -> types::Extrinsic {
+		let call = RuntimeCall::Balances(balances::Call::Transfer {
+			to: dev_account(to).into(),
+			amount: 20,
+		});
+		let nonce = state.system.get_nonce(&dev_account("alice"));
+		dev_signer("alice").sign(call, nonce, state.genesis_hash())
+	}
+
+	fn setup() -> (Chain, Runtime) {
+		let mut genesis = Runtime::new();
+		genesis.balances.set_balance(&dev_account("alice"), 100);
+		genesis.note_changes();
+		(Chain::new(genesis.clone()), genesis)
+	}
+
+	/// Import a block that must be valid and return its hash.
+	fn import(chain: &mut Chain, block: types::Block) -> [u8; 32] {
+		let hash = super::block_hash(&block);
+		chain.import(block, 10_000).unwrap();
+		hash
+	}
+
+	#[test]
+	fn longest_chain_wins() {
+		let (mut chain, genesis) = setup();
+
+		let (a1, _) = block(&genesis, vec![transfer(&genesis, "bob")]);
+		let a1_hash = super::block_hash(&a1);
+		assert!(matches!(chain.import(a1, 10_000), Ok(ImportOutcome::NewBest(_))));
+
+		let (b1, b1_state) = block(&genesis, vec![transfer(&genesis, "charlie")]);
+		let b1_hash = super::block_hash(&b1);
+		assert_eq!(chain.import(b1, 10_000), Ok(ImportOutcome::Fork(b1_hash)));
+		assert_eq!(chain.best_hash(), a1_hash);
+
+		let (b2, _) = block(&b1_state, Vec::new());
+		let b2_hash = super::block_hash(&b2);
+		assert_eq!(
+			chain.import(b2, 10_000),
+			Ok(ImportOutcome::Reorg { retracted: vec![a1_hash], enacted: vec![b1_hash, b2_hash] })
+		);
+		assert_eq!(chain.best_number(), 2);
+		assert_eq!(chain.state().balances.balance(&dev_account("bob")), 0);
+		assert_eq!(chain.state().balances.balance(&dev_account("charlie")), 20);
+	}
+
+	#[test]
+	fn bad_forks_are_dropped() {
+		let (mut chain, genesis) = setup();
+		let (a1, a1_state) = block(&genesis, Vec::new());
+		let a1_hash = super::block_hash(&a1);
+		let _ = chain.import(a1, 10_000);
+
+		let (mut orphan, _) = block(&a1_state, Vec::new());
+		orphan.header.parent_hash = [1; 32];
+		assert_eq!(chain.import(orphan, 10_000), Err(ImportError::UnknownParent));
+
+		// The root doesn't match the extrinsics, which shows as the fork block is executed.
+		let (b1, b1_state) = block(&genesis, vec![transfer(&genesis, "bob")]);
+		let b1 = types::Block { extrinsics: Vec::new(), ..b1 };
+		let b1_hash = super::block_hash(&b1);
+		assert_eq!(chain.import(b1, 10_000), Err(ImportError::BadExtrinsicsRoot));
+		let (mut b2, _) = block(&b1_state, Vec::new());
+		b2.header.parent_hash = b1_hash;
+		let result = chain.import(b2, 10_000);
+		assert_eq!(result, Err(ImportError::UnknownParent));
+		assert!(chain.block(&b1_hash).is_none());
+		assert_eq!(chain.best_hash(), a1_hash);
+		assert_eq!(chain.state().system.block_number(), 1);
+	}
+
+	#[test]
+	fn stale_forks_are_dropped() {
+		let (mut chain, genesis) = setup();
+		let (a1, mut state) = block(&genesis, Vec::new());
+		chain.import(a1, 10_000).unwrap();
+		let (b1, _) = block(&genesis, vec![transfer(&genesis, "bob")]);
+		let b1_hash = super::block_hash(&b1);
+		assert_eq!(chain.import(b1, 10_000), Ok(ImportOutcome::Fork(b1_hash)));
+		assert!(chain.state_at(&b1_hash).is_some());
+
+		for _ in 0..super::MAX_FORK_DEPTH {
+			let (next, next_state) = block(&state, Vec::new());
+			chain.import(next, 1_000_000).unwrap();
+			state = next_state;
+		}
+		assert!(chain.block(&b1_hash).is_some());
+		let (next, _) = block(&state, Vec::new());
+		chain.import(next, 1_000_000).unwrap();
+		assert!(chain.block(&b1_hash).is_none());
+		assert!(chain.state_at(&b1_hash).is_none());
+	}
+
+	#[test]
+	fn states_are_pruned() {
+		let (_, genesis) = setup();
+		let mut chain = Chain::with_pruning(genesis.clone(), "1".parse().unwrap());
+		let (a1, a1_state) = block(&genesis, Vec::new());
+		let (a2, a2_state) = block(&a1_state, Vec::new());
+		let (a3, _) = block(&a2_state, Vec::new());
+		let a1 = import(&mut chain, a1);
+		let a2 = import(&mut chain, a2);
+		import(&mut chain, a3);
+		assert!(chain.state_at(&a1).is_none());
+		assert!(chain.state_at(&a2).is_some());
+		// The unfinalized genesis state is kept, so forks can still be executed.
+		let (b2, b2_state) = block(&a1_state, vec![transfer(&a1_state, "bob")]);
+		let (b3, b3_state) = block(&b2_state, Vec::new());
+		let (b4, _) = block(&b3_state, Vec::new());
+		import(&mut chain, b2);
+		let b3 = import(&mut chain, b3);
+		let b4 = import(&mut chain, b4);
+		assert_eq!(chain.best_hash(), b4);
+		assert_eq!(chain.state().balances.balance(&dev_account("bob")), 20);
+
+		chain.finalize(b3).unwrap();
+		assert!(chain.state_at(&import_queue::GENESIS_PARENT_HASH).is_none());
+		assert!(chain.state_at(&b3).is_some());
+		assert_eq!("archive".parse(), Ok(super::Pruning::Archive));
+		assert_eq!("full".parse::<super::Pruning>(), Err("Invalid pruning mode"));
+	}
+
+	#[test]
+	fn mortal_extrinsics_expire() {
+		use crate::{support::InvalidTransaction, wallet::UnsignedExtrinsic};
+
+		let (alice, (_, genesis)) = (dev_account("alice"), setup());
+		let (_, s1) = block(&genesis, Vec::new());
+		let call = RuntimeCall::Balances(balances::Call::Transfer {
+			to: dev_account("bob").into(),
+			amount: 20,
+		});
+		let unsigned = UnsignedExtrinsic::new(alice.clone(), call, 0, s1.genesis_hash());
+		let sign = |unsigned: UnsignedExtrinsic| {
+			let signature = dev_signer("alice").sign_payload(&unsigned.signing_payload());
+			unsigned.attach(signature).unwrap()
+		};
+		let mortal = sign(unsigned.clone().mortal(1, s1.recent_block_hash(1).unwrap(), 2));
+		assert!(s1.validate_extrinsic(&mortal).is_ok());
+
+		// It can be included in blocks 2 and 3, but not later.
+		let (_, s2) = block(&s1, Vec::new());
+		let (_, included) = block(&s2, vec![mortal.clone()]);
+		assert_eq!(included.system.get_nonce(&alice), 1);
+		let (_, s3) = block(&s2, Vec::new());
+		assert_eq!(s3.validate_extrinsic(&mortal).err(), Some(InvalidTransaction::Stale));
+		let (_, skipped) = block(&s3, vec![mortal]);
+		assert_eq!(skipped.system.get_nonce(&alice), 0);
+
+		// Birth blocks must be known, and of the same fork.
+		let unborn = sign(unsigned.clone().mortal(5, [5; 32], 10));
+		let result = s3.validate_extrinsic(&unborn).err();
+		assert_eq!(result, Some(InvalidTransaction::AncientBirthBlock));
+		let forked = sign(unsigned.mortal(1, [1; 32], 10));
+		assert_eq!(s3.validate_extrinsic(&forked).err(), Some(InvalidTransaction::BadProof));
+	}
+
+	#[test]
+	fn tips_go_to_the_author() {
+		use crate::wallet::UnsignedExtrinsic;
+
+		let (_, mut genesis) = setup();
+		genesis.balances.set_balance(&dev_account("bob"), 100);
+		let transfer = |tip| {
+			let call = RuntimeCall::Balances(balances::Call::Transfer {
+				to: dev_account("charlie").into(),
+				amount: 20,
+			});
+			let unsigned =
+				UnsignedExtrinsic::new(dev_account("bob"), call, 0, genesis.genesis_hash());
+			let unsigned = unsigned.with_tip(tip);
+			let signature = dev_signer("bob").sign_payload(&unsigned.signing_payload());
+			unsigned.attach(signature).unwrap()
+		};
+		let (_, untipped) = block(&genesis, vec![transfer(0)]);
+		let (_, tipped) = block(&genesis, vec![transfer(5)]);
+		let balance = |state: &Runtime, who| state.balances.balance(&dev_account(who));
+		assert_eq!(balance(&untipped, "bob") - balance(&tipped, "bob"), 5);
+		assert_eq!(balance(&tipped, "alice") - balance(&untipped, "alice"), 5);
+	}
+}
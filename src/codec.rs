@@ -0,0 +1,350 @@
+//! A minimal SCALE-style binary encoding, used to get a canonical byte representation of calls
+//! and other runtime types (e.g. to hash them).
+//!
+//! Integers are little-endian, collections and strings are prefixed with their compact-encoded
+//! length, `Option` uses a `0`/`1` tag byte and enums start with their variant index.
+//!
+//! `Decode` reads values back, e.g. from a state snapshot.
+
+use std::cell::Cell;
+
+/// How many boxes a decoded value may nest, e.g. calls dispatching calls. Boxes are how values
+/// nest, so this bounds the recursion of decoding untrusted bytes, which would otherwise
+/// overflow the stack.
+pub const MAX_BOX_DEPTH: u32 = 32;
+
+thread_local! {
+	/// The boxes being decoded on this thread.
+	static BOX_DEPTH: Cell<u32> = const { Cell::new(0) };
+}
+
+pub trait Encode {
+	fn encode_to(&self, dest: &mut Vec<u8>);
+
+	fn encode(&self) -> Vec<u8> {
+		let mut dest = Vec::new();
+		self.encode_to(&mut dest);
+		dest
+	}
+}
+
+/// A variable-length encoded unsigned integer; small values take a single byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compact(pub u64);
+
+impl Encode for Compact {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self.0 {
+			0..=0x3f => dest.push((self.0 as u8) << 2),
+			0x40..=0x3fff => dest.extend_from_slice(&(((self.0 as u16) << 2) | 0b01).to_le_bytes()),
+			0x4000..=0x3fff_ffff => {
+				dest.extend_from_slice(&(((self.0 as u32) << 2) | 0b10).to_le_bytes())
+			},
+			_ => {
+				let bytes = self.0.to_le_bytes();
+				let len = 8 - self.0.leading_zeros() as usize / 8;
+				dest.push((((len - 4) as u8) << 2) | 0b11);
+				dest.extend_from_slice(&bytes[..len]);
+			},
+		}
+	}
+}
+
+macro_rules! impl_encode_for_int {
+	($($t:ty),*) => {
+		$(
+			impl Encode for $t {
+				fn encode_to(&self, dest: &mut Vec<u8>) {
+					dest.extend_from_slice(&self.to_le_bytes());
+				}
+			}
+		)*
+	};
+}
+
+impl_encode_for_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl Encode for bool {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		dest.push(*self as u8);
+	}
+}
+
+impl Encode for () {
+	fn encode_to(&self, _dest: &mut Vec<u8>) {}
+}
+
+impl Encode for str {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		Compact(self.len() as u64).encode_to(dest);
+		dest.extend_from_slice(self.as_bytes());
+	}
+}
+
+impl Encode for String {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		self.as_str().encode_to(dest);
+	}
+}
+
+impl<T: Encode> Encode for [T] {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		Compact(self.len() as u64).encode_to(dest);
+		for item in self {
+			item.encode_to(dest);
+		}
+	}
+}
+
+impl<T: Encode> Encode for Vec<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		self.as_slice().encode_to(dest);
+	}
+}
+
+/// Fixed-size arrays have a known length, so no prefix is written.
+impl<T: Encode, const N: usize> Encode for [T; N] {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		for item in self {
+			item.encode_to(dest);
+		}
+	}
+}
+
+impl<T: Encode> Encode for Option<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			None => dest.push(0),
+			Some(value) => {
+				dest.push(1);
+				value.encode_to(dest);
+			},
+		}
+	}
+}
+
+impl<T: Encode + ?Sized> Encode for Box<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		self.as_ref().encode_to(dest);
+	}
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(*self).encode_to(dest);
+	}
+}
+
+macro_rules! impl_encode_for_tuple {
+	($($name:ident),+) => {
+		impl<$($name: Encode),+> Encode for ($($name,)+) {
+			#[allow(non_snake_case)]
+			fn encode_to(&self, dest: &mut Vec<u8>) {
+				let ($($name,)+) = self;
+				$($name.encode_to(dest);)+
+			}
+		}
+	};
+}
+
+impl_encode_for_tuple!(A);
+impl_encode_for_tuple!(A, B);
+impl_encode_for_tuple!(A, B, C);
+impl_encode_for_tuple!(A, B, C, D);
+impl_encode_for_tuple!(A, B, C, D, E);
+impl_encode_for_tuple!(A, B, C, D, E, F);
+
+pub trait Decode: Sized {
+	/// Read a value from the front of `input`, advancing it past the value.
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str>;
+}
+
+/// Decode a value that must take all of `bytes`.
+pub fn decode_all<T: Decode>(mut bytes: &[u8]) -> Result<T, &'static str> {
+	let value = T::decode(&mut bytes)?;
+	match bytes.is_empty() {
+		true => Ok(value),
+		false => Err("Trailing bytes"),
+	}
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], &'static str> {
+	if input.len() < len {
+		return Err("Unexpected end of input");
+	}
+	let (bytes, rest) = input.split_at(len);
+	*input = rest;
+	Ok(bytes)
+}
+
+impl Decode for Compact {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let first = take(input, 1)?[0];
+		let value = match first & 0b11 {
+			0b00 => (first >> 2) as u64,
+			0b01 => (u16::from_le_bytes([first, take(input, 1)?[0]]) >> 2) as u64,
+			0b10 => {
+				let rest = take(input, 3)?;
+				(u32::from_le_bytes([first, rest[0], rest[1], rest[2]]) >> 2) as u64
+			},
+			_ => {
+				let len = (first >> 2) as usize + 4;
+				if len > 8 {
+					return Err("Compact out of range");
+				}
+				let mut bytes = [0; 8];
+				bytes[..len].copy_from_slice(take(input, len)?);
+				u64::from_le_bytes(bytes)
+			},
+		};
+		Ok(Compact(value))
+	}
+}
+
+macro_rules! impl_decode_for_int {
+	($($t:ty),*) => {
+		$(
+			impl Decode for $t {
+				fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+					let bytes = take(input, std::mem::size_of::<$t>())?;
+					Ok(<$t>::from_le_bytes(bytes.try_into().expect("Length checked by take")))
+				}
+			}
+		)*
+	};
+}
+
+impl_decode_for_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl Decode for bool {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		match u8::decode(input)? {
+			0 => Ok(false),
+			1 => Ok(true),
+			_ => Err("Invalid bool"),
+		}
+	}
+}
+
+impl Decode for () {
+	fn decode(_input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(())
+	}
+}
+
+impl Decode for String {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let len = Compact::decode(input)?.0 as usize;
+		String::from_utf8(take(input, len)?.to_vec()).map_err(|_| "Invalid string")
+	}
+}
+
+impl<T: Decode> Decode for Vec<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let len = Compact::decode(input)?.0;
+		// Every item takes at least a byte, which bounds what a bad length can allocate.
+		let mut items = Vec::with_capacity((len as usize).min(input.len()));
+		for _ in 0..len {
+			items.push(T::decode(input)?);
+		}
+		Ok(items)
+	}
+}
+
+impl<T: Decode, const N: usize> Decode for [T; N] {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let items = (0..N).map(|_| T::decode(input)).collect::<Result<Vec<_>, _>>()?;
+		items.try_into().map_err(|_| "Invalid array length")
+	}
+}
+
+impl<T: Decode> Decode for Option<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		match u8::decode(input)? {
+			0 => Ok(None),
+			1 => Ok(Some(T::decode(input)?)),
+			_ => Err("Invalid option"),
+		}
+	}
+}
+
+impl<T: Decode> Decode for Box<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let depth = BOX_DEPTH.get();
+		if depth >= MAX_BOX_DEPTH {
+			return Err("Too deeply nested");
+		}
+		BOX_DEPTH.set(depth + 1);
+		let value = T::decode(input);
+		BOX_DEPTH.set(depth);
+		value.map(Box::new)
+	}
+}
+
+macro_rules! impl_decode_for_tuple {
+	($($name:ident),+) => {
+		impl<$($name: Decode),+> Decode for ($($name,)+) {
+			fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+				Ok(($($name::decode(input)?,)+))
+			}
+		}
+	};
+}
+
+impl_decode_for_tuple!(A);
+impl_decode_for_tuple!(A, B);
+impl_decode_for_tuple!(A, B, C);
+impl_decode_for_tuple!(A, B, C, D);
+impl_decode_for_tuple!(A, B, C, D, E);
+impl_decode_for_tuple!(A, B, C, D, E, F);
+
+#[cfg(test)]
+mod test {
+	use super::{Compact, Decode, Encode};
+
+	#[test]
+	fn encode_integers_little_endian() {
+		assert_eq!(1u8.encode(), vec![1]);
+		assert_eq!(0x0102u16.encode(), vec![2, 1]);
+		assert_eq!(1u32.encode(), vec![1, 0, 0, 0]);
+		assert_eq!(true.encode(), vec![1]);
+	}
+
+	#[test]
+	fn encode_compact() {
+		assert_eq!(Compact(0).encode(), vec![0x00]);
+		assert_eq!(Compact(1).encode(), vec![0x04]);
+		assert_eq!(Compact(63).encode(), vec![0xfc]);
+		assert_eq!(Compact(64).encode(), vec![0x01, 0x01]);
+		assert_eq!(Compact(16383).encode(), vec![0xfd, 0xff]);
+		assert_eq!(Compact(16384).encode(), vec![0x02, 0x00, 0x01, 0x00]);
+		assert_eq!(Compact(1 << 30).encode(), vec![0x03, 0x00, 0x00, 0x00, 0x40]);
+		assert_eq!(
+			Compact(u64::MAX).encode(),
+			vec![0x13, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+		);
+	}
+
+	#[test]
+	fn encode_collections() {
+		assert_eq!("abc".to_string().encode(), vec![12, b'a', b'b', b'c']);
+		assert_eq!(vec![1u16, 2u16].encode(), vec![8, 1, 0, 2, 0]);
+		assert_eq!([7u8; 2].encode(), vec![7, 7]);
+		assert_eq!(None::<u8>.encode(), vec![0]);
+		assert_eq!(Some(5u8).encode(), vec![1, 5]);
+		assert_eq!((1u8, "a").encode(), vec![1, 4, b'a']);
+	}
+
+	#[test]
+	fn decode_what_was_encoded() {
+		for n in [0, 1, 63, 64, 16383, 16384, 1 << 30, u64::MAX] {
+			assert_eq!(super::decode_all(&Compact(n).encode()), Ok(Compact(n)));
+		}
+		let value = (7u32, "abc".to_string(), Some(vec![1u128, 2]), [3u8; 2], true);
+		assert_eq!(super::decode_all(&value.encode()), Ok(value));
+
+		assert_eq!(u32::decode(&mut &[1, 0][..]), Err("Unexpected end of input"));
+		assert_eq!(super::decode_all::<u8>(&[1, 2]), Err("Trailing bytes"));
+		assert_eq!(super::decode_all::<Option<u8>>(&[2]), Err("Invalid option"));
+	}
+}
@@ -0,0 +1,478 @@
+use core::fmt::Debug;
+use std::collections::BTreeMap;
+
+use num::CheckedAdd;
+
+use crate::{
+	codec::{Decode, Encode},
+	hashing::{self, Hash},
+	metadata::{field, storage, variant, PalletMetadata},
+	migrations::{self, StorageVersion},
+	support::{ensure_root, ensure_signed, DispatchResult, RawOrigin, Weight},
+	system::{self, OriginFor},
+	trie::{self, Storage},
+};
+
+pub type ProposalIndex = u32;
+pub type MemberCount = u32;
+
+/// An approved motion: its call, with the `Collective` origin it is dispatched with, which
+/// tells how many of the members approved it.
+pub type Approved<T> = (OriginFor<T>, <T as Config>::RuntimeCall);
+
+pub trait Config: system::Config {
+	/// The aggregated call type that motions dispatch once approved.
+	type RuntimeCall: Debug + Clone + Encode;
+	/// How long (in blocks) a motion can be voted on before it can be closed regardless.
+	const MOTION_DURATION: Self::BlockNumber;
+	/// The maximum number of members of the collective.
+	const MAX_MEMBERS: usize;
+	/// The maximum number of motions open at the same time.
+	const MAX_PROPOSALS: usize;
+}
+
+#[derive(Debug, Clone)]
+pub enum Call<T: Config> {
+	Propose {
+		threshold: MemberCount,
+		call: Box<T::RuntimeCall>,
+	},
+	Vote {
+		proposal: Hash,
+		index: ProposalIndex,
+		approve: bool,
+	},
+	Close {
+		proposal: Hash,
+		index: ProposalIndex,
+	},
+	/// Root only: replace the members of the collective.
+	SetMembers {
+		members: Vec<T::AccountId>,
+	},
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::Propose { threshold, call } => (0u8, threshold, call).encode_to(dest),
+			Call::Vote { proposal, index, approve } => {
+				(1u8, proposal, index, approve).encode_to(dest)
+			},
+			Call::Close { proposal, index } => (2u8, proposal, index).encode_to(dest),
+			Call::SetMembers { members } => (3u8, members).encode_to(dest),
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T>
+where
+	T::RuntimeCall: Decode,
+{
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::Propose { threshold: Decode::decode(input)?, call: Decode::decode(input)? },
+			1 => Call::Vote {
+				proposal: Decode::decode(input)?,
+				index: Decode::decode(input)?,
+				approve: Decode::decode(input)?,
+			},
+			2 => Call::Close { proposal: Decode::decode(input)?, index: Decode::decode(input)? },
+			3 => Call::SetMembers { members: Decode::decode(input)? },
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::Propose { .. } => 0,
+			Call::Vote { .. } => 1,
+			Call::Close { .. } => 2,
+			Call::SetMembers { .. } => 3,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::Propose { .. } => 10_000,
+			Call::Vote { .. } => 5_000,
+			// Closing may hand back the motion's call, whose weight is not known up front.
+			Call::Close { .. } => 20_000,
+			Call::SetMembers { members } => 5_000 + 1_000 * members.len() as Weight,
+		}
+	}
+}
+
+/// The votes cast on an open motion.
+#[derive(Debug, Clone)]
+pub struct Votes<T: Config> {
+	pub index: ProposalIndex,
+	/// The number of approvals needed for the motion to pass.
+	pub threshold: MemberCount,
+	pub ayes: Vec<T::AccountId>,
+	pub nays: Vec<T::AccountId>,
+	pub end: T::BlockNumber,
+}
+
+impl<T: Config> Encode for Votes<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(self.index, self.threshold, &self.ayes, &self.nays, &self.end).encode_to(dest);
+	}
+}
+
+impl<T: Config> Decode for Votes<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (index, threshold, ayes, nays, end) = Decode::decode(input)?;
+		Ok(Votes { index, threshold, ayes, nays, end })
+	}
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	/// Kept sorted, like multisig signatories.
+	members: Vec<T::AccountId>,
+	proposals: Vec<Hash>,
+	proposal_of: BTreeMap<Hash, T::RuntimeCall>,
+	voting: BTreeMap<Hash, Votes<T>>,
+	proposal_count: ProposalIndex,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self {
+			members: Vec::new(),
+			proposals: Vec::new(),
+			proposal_of: BTreeMap::new(),
+			voting: BTreeMap::new(),
+			proposal_count: 0,
+		}
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Collective",
+			index: None,
+			calls: vec![
+				variant(
+					"Propose",
+					0,
+					vec![field::<MemberCount>("threshold"), field::<Box<T::RuntimeCall>>("call")],
+				),
+				variant(
+					"Vote",
+					1,
+					vec![
+						field::<Hash>("proposal"),
+						field::<ProposalIndex>("index"),
+						field::<bool>("approve"),
+					],
+				),
+				variant(
+					"Close",
+					2,
+					vec![field::<Hash>("proposal"), field::<ProposalIndex>("index")],
+				),
+				variant("SetMembers", 3, vec![field::<Vec<T::AccountId>>("members")]),
+			],
+			storage: vec![
+				storage::<Vec<T::AccountId>>("members"),
+				storage::<Vec<Hash>>("proposals"),
+				storage::<BTreeMap<Hash, T::RuntimeCall>>("proposal_of"),
+				storage::<BTreeMap<Hash, Votes<T>>>("voting"),
+				storage::<ProposalIndex>("proposal_count"),
+			],
+			events: Vec::new(),
+			errors: vec![
+				"Caller is not a member",
+				"Duplicate proposal",
+				"Duplicate vote",
+				"Proposal does not exist",
+				"Too early to close",
+				"Too many members",
+				"Too many proposals",
+				"Wrong proposal index",
+			],
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Collective", STORAGE_VERSION);
+		trie::insert_value(storage, "Collective", "members", &self.members);
+		trie::insert_value(storage, "Collective", "proposals", &self.proposals);
+		trie::insert_entries(storage, "Collective", "proposal_of", &self.proposal_of);
+		trie::insert_entries(storage, "Collective", "voting", &self.voting);
+		trie::insert_value(storage, "Collective", "proposal_count", &self.proposal_count);
+	}
+
+	/// Restore the members and motions `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult
+	where
+		T::RuntimeCall: Decode,
+	{
+		migrations::ensure_version(storage, "Collective", STORAGE_VERSION)?;
+		trie::restore_value(storage, "Collective", "members", &mut self.members)?;
+		trie::restore_value(storage, "Collective", "proposals", &mut self.proposals)?;
+		self.proposal_of = trie::decode_entries(storage, "Collective", "proposal_of")?
+			.into_iter()
+			.collect();
+		self.voting = trie::decode_entries(storage, "Collective", "voting")?.into_iter().collect();
+		trie::restore_value(storage, "Collective", "proposal_count", &mut self.proposal_count)?;
+		Ok(())
+	}
+
+	/// Returns the call of an approved motion, to be dispatched by the runtime with the
+	/// Collective origin.
+	pub fn dispatch(
+		&mut self,
+		now: T::BlockNumber,
+		origin: OriginFor<T>,
+		call: Call<T>,
+	) -> Result<Option<Approved<T>>, &'static str> {
+		match call {
+			Call::Propose { threshold, call } => {
+				self.propose(now, ensure_signed(origin)?, threshold, *call)
+			},
+			Call::Vote { proposal, index, approve } => {
+				self.vote(ensure_signed(origin)?, proposal, index, approve).map(|_| None)
+			},
+			Call::Close { proposal, index } => {
+				ensure_signed(origin)?;
+				self.close(now, proposal, index)
+			},
+			Call::SetMembers { members } => {
+				ensure_root(origin)?;
+				self.set_members(members).map(|_| None)
+			},
+		}
+	}
+
+	pub fn members(&self) -> &[T::AccountId] {
+		&self.members
+	}
+
+	pub fn is_member(&self, who: &T::AccountId) -> bool {
+		self.members.binary_search(who).is_ok()
+	}
+
+	pub fn proposals(&self) -> &[Hash] {
+		&self.proposals
+	}
+
+	pub fn voting(&self, proposal: &Hash) -> Option<&Votes<T>> {
+		self.voting.get(proposal)
+	}
+
+	/// Replace the members, dropping the votes of the outgoing ones from the open motions.
+	pub fn set_members(&mut self, mut members: Vec<T::AccountId>) -> DispatchResult {
+		if members.len() > T::MAX_MEMBERS {
+			return Err("Too many members");
+		}
+		members.sort();
+		members.dedup();
+
+		for votes in self.voting.values_mut() {
+			votes.ayes.retain(|who| members.binary_search(who).is_ok());
+			votes.nays.retain(|who| members.binary_search(who).is_ok());
+		}
+		self.members = members;
+		Ok(())
+	}
+
+	/// Propose a motion, counting the vote of the proposer. With a threshold below 2 the call
+	/// is approved right away.
+	pub fn propose(
+		&mut self,
+		now: T::BlockNumber,
+		caller: T::AccountId,
+		threshold: MemberCount,
+		call: T::RuntimeCall,
+	) -> Result<Option<Approved<T>>, &'static str> {
+		if !self.is_member(&caller) {
+			return Err("Caller is not a member");
+		}
+		if threshold < 2 {
+			return Ok(Some((self.origin(1), call)));
+		}
+
+		let proposal = hashing::blake2_256(&call.encode());
+		if self.proposal_of.contains_key(&proposal) {
+			return Err("Duplicate proposal");
+		}
+		if self.proposals.len() >= T::MAX_PROPOSALS {
+			return Err("Too many proposals");
+		}
+
+		let index = self.proposal_count;
+		self.proposal_count += 1;
+		let end = now.checked_add(&T::MOTION_DURATION).unwrap_or(now);
+		self.proposals.push(proposal);
+		self.proposal_of.insert(proposal, call);
+		self.voting.insert(
+			proposal,
+			Votes { index, threshold, ayes: vec![caller], nays: Vec::new(), end },
+		);
+		Ok(None)
+	}
+
+	/// Vote on an open motion; a member can change its vote but not cast the same one twice.
+	pub fn vote(
+		&mut self,
+		caller: T::AccountId,
+		proposal: Hash,
+		index: ProposalIndex,
+		approve: bool,
+	) -> DispatchResult {
+		if !self.is_member(&caller) {
+			return Err("Caller is not a member");
+		}
+		let votes = self.voting.get_mut(&proposal).ok_or("Proposal does not exist")?;
+		if votes.index != index {
+			return Err("Wrong proposal index");
+		}
+
+		let (same, other) = if approve {
+			(&mut votes.ayes, &mut votes.nays)
+		} else {
+			(&mut votes.nays, &mut votes.ayes)
+		};
+		if same.contains(&caller) {
+			return Err("Duplicate vote");
+		}
+		other.retain(|who| *who != caller);
+		same.push(caller);
+		Ok(())
+	}
+
+	/// Close a motion: it is approved once it has enough ayes, and disapproved once it can no
+	/// longer reach the threshold or its voting period is over.
+	pub fn close(
+		&mut self,
+		now: T::BlockNumber,
+		proposal: Hash,
+		index: ProposalIndex,
+	) -> Result<Option<Approved<T>>, &'static str> {
+		let votes = self.voting.get(&proposal).ok_or("Proposal does not exist")?;
+		if votes.index != index {
+			return Err("Wrong proposal index");
+		}
+
+		let ayes = votes.ayes.len() as MemberCount;
+		let nays = votes.nays.len() as MemberCount;
+		let members = self.members.len() as MemberCount;
+		let approved = ayes >= votes.threshold;
+		let disapproved = members.saturating_sub(nays) < votes.threshold || now >= votes.end;
+		if !approved && !disapproved {
+			return Err("Too early to close");
+		}
+
+		self.voting.remove(&proposal);
+		self.proposals.retain(|hash| *hash != proposal);
+		let call = self.proposal_of.remove(&proposal);
+		Ok(call.filter(|_| approved).map(|call| (self.origin(ayes), call)))
+	}
+
+	/// The origin of a motion `ayes` of the current members approved.
+	fn origin(&self, ayes: MemberCount) -> OriginFor<T> {
+		RawOrigin::Collective { ayes, members: self.members.len() as MemberCount }
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{codec::Encode, hashing, mock::Test, support::RawOrigin};
+
+	impl super::Config for Test {
+		type RuntimeCall = &'static str;
+		const MOTION_DURATION: u32 = 5;
+		const MAX_MEMBERS: usize = 3;
+		const MAX_PROPOSALS: usize = 2;
+	}
+
+	fn collective_origin(ayes: u32) -> RawOrigin<String> {
+		RawOrigin::Collective { ayes, members: 3 }
+	}
+
+	fn council() -> super::Pallet<Test> {
+		let mut collective = super::Pallet::new();
+		let members = ["charlie", "alice", "bob"].map(|who| who.to_string()).to_vec();
+		assert_eq!(collective.set_members(members), Ok(()));
+		collective
+	}
+
+	#[test]
+	fn members_are_set_by_root() {
+		let alice = "alice".to_string();
+		let mut collective = council();
+
+		assert_eq!(collective.members(), &["alice", "bob", "charlie"]);
+		assert_eq!(
+			collective.dispatch(
+				0,
+				RawOrigin::Signed(alice.clone()),
+				super::Call::SetMembers { members: vec![] }
+			),
+			Err("Bad origin")
+		);
+		let too_many = ["a", "b", "c", "d"].map(|who| who.to_string()).to_vec();
+		assert_eq!(collective.set_members(too_many), Err("Too many members"));
+		assert_eq!(
+			collective.propose(0, "dave".to_string(), 2, "call"),
+			Err("Caller is not a member")
+		);
+	}
+
+	#[test]
+	fn motion_passes_with_threshold() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let proposal = hashing::blake2_256(&"call".encode());
+		let mut collective = council();
+
+		assert_eq!(
+			collective.propose(0, alice.clone(), 1, "instant"),
+			Ok(Some((collective_origin(1), "instant")))
+		);
+		assert_eq!(collective.propose(0, alice.clone(), 2, "call"), Ok(None));
+		assert_eq!(collective.propose(0, bob.clone(), 2, "call"), Err("Duplicate proposal"));
+		assert_eq!(collective.vote(alice.clone(), proposal, 0, true), Err("Duplicate vote"));
+		assert_eq!(collective.vote(bob.clone(), proposal, 1, true), Err("Wrong proposal index"));
+		assert_eq!(collective.close(1, proposal, 0), Err("Too early to close"));
+
+		assert_eq!(collective.vote(bob.clone(), proposal, 0, true), Ok(()));
+		assert_eq!(collective.close(1, proposal, 0), Ok(Some((collective_origin(2), "call"))));
+		assert!(collective.proposals().is_empty());
+		assert_eq!(collective.close(1, proposal, 0), Err("Proposal does not exist"));
+	}
+
+	#[test]
+	fn motion_fails_without_enough_ayes() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let charlie = "charlie".to_string();
+		let proposal = hashing::blake2_256(&"call".encode());
+		let mut collective = council();
+
+		let _ = collective.propose(0, alice.clone(), 3, "call");
+		assert_eq!(collective.vote(bob.clone(), proposal, 0, true), Ok(()));
+		assert_eq!(collective.vote(bob.clone(), proposal, 0, false), Ok(()));
+		assert_eq!(collective.voting(&proposal).map(|votes| votes.ayes.len()), Some(1));
+		assert_eq!(collective.close(1, proposal, 0), Ok(None));
+
+		// Without enough votes, a motion can only be closed once it expires.
+		let _ = collective.propose(0, alice.clone(), 2, "call");
+		assert_eq!(collective.vote(charlie.clone(), proposal, 1, false), Ok(()));
+		assert_eq!(collective.close(4, proposal, 1), Err("Too early to close"));
+		assert_eq!(collective.close(5, proposal, 1), Ok(None));
+	}
+}
@@ -0,0 +1,389 @@
+//! `construct_runtime!`: the runtime is a list of pallets, and the aggregate `Runtime`,
+//! `RuntimeCall`, `RuntimeEvent` and `RuntimeHoldReason` types and the wiring between them are generated from it, so
+//! adding a pallet is adding one entry.
+//!
+//! Every entry names the module of the pallet, which is also the field of the runtime, and the
+//! variant of the pallet in the aggregate types. Pallets with calls give their index in
+//! `RuntimeCall` and how the runtime dispatches them, as a closure over the runtime, the origin
+//! and the call, since pallets borrow the others they use, e.g. balances. A call weighs whatever
+//! its `weight` says unless the entry overrides it, e.g. to add the calls it dispatches. The
+//! dispatch closure returns a `DispatchResult`, or a `DispatchResultWithPostInfo` for calls
+//! reporting that they used less weight than declared or don't pay a fee. Pallets
+//! with events have them moved to system by `collect_events`, so the runtime needs a `system`
+//! pallet. Their events have a `name` and the `accounts` they are about, which subscriptions
+//! filter on. Pallets with calls that hold funds name the reasons they hold them for, which
+//! balances keeps apart, see `balances::Pallet::hold`.
+//!
+//! ```ignore
+//! construct_runtime! {
+//!     pub struct Runtime {
+//!         system: System,
+//!         balances: Balances {
+//!             call: Call<Runtime> = 0,
+//!             dispatch: |runtime, origin, call| runtime.balances.dispatch(origin, call),
+//!         },
+//!         proof_of_existence: ProofOfExistence {
+//!             call: Call<Runtime> = 1,
+//!             event: Event<Runtime>,
+//!             hold_reason: HoldReason,
+//!             weight: |call| call.weight(),
+//!             dispatch: |runtime, origin, call| { .. },
+//!         },
+//!     }
+//! }
+//! ```
+
+#[macro_export]
+macro_rules! construct_runtime {
+	(
+		$(#[$attr:meta])*
+		$vis:vis struct $runtime:ident {
+			$(
+				$module:ident: $name:ident $({
+					call: $call:ident $(<$call_param:ident>)? = $index:literal,
+					$(event: $event:ident $(<$event_param:ident>)?,)?
+					$(hold_reason: $hold_reason:ident,)?
+					$(weight: |$weight_call:pat_param| $weight:expr,)?
+					dispatch: |
+						$dispatch_runtime:pat_param,
+						$dispatch_origin:pat_param,
+						$dispatch_call:pat_param
+					| $dispatch:expr $(,)?
+				})?
+			),* $(,)?
+		}
+	) => {
+		$(#[$attr])*
+		#[derive(Debug)]
+		$vis struct $runtime {
+			$(pub $module: $module::Pallet<$runtime>,)*
+		}
+
+		// `clone_from` goes pallet by pallet, so a pallet can keep what refers into it, see
+		// `support::with_transaction`.
+		impl Clone for $runtime {
+			fn clone(&self) -> Self {
+				Self { $($module: self.$module.clone(),)* }
+			}
+
+			fn clone_from(&mut self, source: &Self) {
+				$(self.$module.clone_from(&source.$module);)*
+			}
+		}
+
+		#[derive(Debug, Clone)]
+		pub enum RuntimeCall {
+			$($($name($module::$call $(<$call_param>)?),)?)*
+		}
+
+		impl $crate::codec::Encode for RuntimeCall {
+			fn encode_to(&self, dest: &mut Vec<u8>) {
+				match self {
+					$($(RuntimeCall::$name(call) => {
+						$crate::codec::Encode::encode_to(&($index as u8, call), dest)
+					},)?)*
+				}
+			}
+		}
+
+		impl $crate::codec::Decode for RuntimeCall {
+			fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+				Ok(match <u8 as $crate::codec::Decode>::decode(input)? {
+					$($($index => RuntimeCall::$name($crate::codec::Decode::decode(input)?),)?)*
+					_ => return Err("Invalid pallet index"),
+				})
+			}
+		}
+
+		impl RuntimeCall {
+			/// The indices of the pallet and of the call in it, the first two bytes of the
+			/// encoding of the call, without encoding it.
+			pub fn indices(&self) -> (u8, u8) {
+				match self {
+					$($(RuntimeCall::$name(call) => ($index, call.index()),)?)*
+				}
+			}
+
+			/// The weight of the call, including the calls it dispatches right away, e.g. through
+			/// sudo.
+			pub fn weight(&self) -> $crate::support::Weight {
+				match self {
+					$($(RuntimeCall::$name(call) => {
+						$crate::construct_runtime!(
+							@weight call $(|$weight_call| $weight)?
+						)
+					},)?)*
+				}
+			}
+
+			/// What the call declares before it is dispatched. Calls may only waive the fee once
+			/// dispatched, see `support::PostDispatchInfo`.
+			pub fn dispatch_info(&self) -> $crate::support::DispatchInfo {
+				$crate::support::DispatchInfo {
+					weight: self.weight(),
+					pays_fee: $crate::support::Pays::Yes,
+				}
+			}
+		}
+
+		/// The events of the pallets that emit any, as stored by system.
+		#[derive(Debug, Clone)]
+		pub enum RuntimeEvent {
+			$($($($name($module::$event $(<$event_param>)?),)?)?)*
+		}
+
+		impl RuntimeEvent {
+			/// The name of the pallet that emitted the event, as in the metadata.
+			pub fn pallet(&self) -> &'static str {
+				// Dereferenced, so the match is exhaustive without pallets with events.
+				match *self {
+					$($($(RuntimeEvent::$name(ref event) => {
+						let _: &$module::$event $(<$event_param>)? = event;
+						stringify!($name)
+					},)?)?)*
+				}
+			}
+
+			/// The name of the variant of the event, as in the metadata.
+			pub fn name(&self) -> &'static str {
+				match *self {
+					$($($(RuntimeEvent::$name(ref event) => {
+						let event: &$module::$event $(<$event_param>)? = event;
+						event.name()
+					},)?)?)*
+				}
+			}
+
+			/// The accounts the event is about.
+			pub fn accounts(&self) -> Vec<&<$runtime as $crate::system::Config>::AccountId> {
+				match *self {
+					$($($(RuntimeEvent::$name(ref event) => {
+						let event: &$module::$event $(<$event_param>)? = event;
+						event.accounts()
+					},)?)?)*
+				}
+			}
+		}
+
+		/// The reasons the pallets hold funds for, see `balances::Pallet::hold`, encoded with the
+		/// index of the pallet in front.
+		#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+		pub enum RuntimeHoldReason {
+			$($($($name($module::$hold_reason),)?)?)*
+		}
+
+		impl $crate::codec::Encode for RuntimeHoldReason {
+			fn encode_to(&self, _dest: &mut Vec<u8>) {
+				match *self {
+					$($($(RuntimeHoldReason::$name(reason) => {
+						let reason: $module::$hold_reason = reason;
+						$crate::codec::Encode::encode_to(&($index as u8, reason), _dest)
+					},)?)?)*
+				}
+			}
+		}
+
+		impl $crate::codec::Decode for RuntimeHoldReason {
+			fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+				Ok(match <u8 as $crate::codec::Decode>::decode(input)? {
+					$($($($index => {
+						let reason: $module::$hold_reason = $crate::codec::Decode::decode(input)?;
+						RuntimeHoldReason::$name(reason)
+					},)?)?)*
+					_ => return Err("Invalid hold reason"),
+				})
+			}
+		}
+
+		$($($(
+			impl From<$module::$hold_reason> for RuntimeHoldReason {
+				fn from(reason: $module::$hold_reason) -> Self {
+					RuntimeHoldReason::$name(reason)
+				}
+			}
+		)?)?)*
+
+		impl $runtime {
+			pub fn new() -> Self {
+				Self { $($module: $module::Pallet::new(),)* }
+			}
+
+			/// Describes the pallets of the runtime, with the index each one's calls are encoded
+			/// with in `RuntimeCall`.
+			pub fn metadata() -> $crate::metadata::RuntimeMetadata {
+				$crate::metadata::RuntimeMetadata {
+					pallets: vec![$(
+						$crate::construct_runtime!(@metadata $runtime, $module $(, $index)?),
+					)*],
+				}
+			}
+
+			/// Move the events deposited by the pallets to system, which tags them with the
+			/// extrinsic being executed.
+			fn collect_events(&mut self) {
+				$($($(
+					let events: Vec<$module::$event $(<$event_param>)?> = self.$module.take_events();
+					for event in events {
+						self.system.deposit_event(RuntimeEvent::$name(event));
+					}
+				)?)?)*
+			}
+		}
+
+		impl $runtime {
+			/// Dispatch `runtime_call`, with what it reports about the weight it used and its
+			/// fee.
+			pub fn dispatch_with_post_info(
+				&mut self,
+				origin: $crate::support::RawOrigin<<$runtime as $crate::system::Config>::AccountId>,
+				runtime_call: RuntimeCall,
+			) -> $crate::support::DispatchResultWithPostInfo {
+				match runtime_call {
+					$($(RuntimeCall::$name(call) => {
+						let ($dispatch_runtime, $dispatch_origin, $dispatch_call) =
+							(&mut *self, origin, call);
+						let result = $dispatch;
+						result.map($crate::support::PostDispatchInfo::from)
+					},)?)*
+				}
+			}
+		}
+
+		impl Default for $runtime {
+			fn default() -> Self {
+				Self::new()
+			}
+		}
+
+		impl $crate::support::Dispatch for $runtime {
+			type Origin =
+				$crate::support::RawOrigin<<$runtime as $crate::system::Config>::AccountId>;
+			type Call = RuntimeCall;
+
+			fn dispatch(
+				&mut self,
+				origin: Self::Origin,
+				runtime_call: Self::Call,
+			) -> $crate::support::DispatchResult {
+				self.dispatch_with_post_info(origin, runtime_call).map(|_| ())
+			}
+		}
+	};
+	(@weight $call:ident) => {
+		$call.weight()
+	};
+	(@weight $call:ident |$weight_call:pat_param| $weight:expr) => {{
+		let $weight_call = $call;
+		$weight
+	}};
+	(@metadata $runtime:ident, $module:ident) => {
+		$module::Pallet::<$runtime>::metadata()
+	};
+	(@metadata $runtime:ident, $module:ident, $index:literal) => {
+		$crate::metadata::PalletMetadata {
+			index: Some($index),
+			..$module::Pallet::<$runtime>::metadata()
+		}
+	};
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{
+		balances,
+		codec::{decode_all, Encode},
+		proof_of_existence,
+		support::{Dispatch, IdentityLookup, PostDispatchInfo, RawOrigin},
+		system,
+	};
+
+	crate::construct_runtime! {
+		pub struct Runtime {
+			system: System,
+			balances: Balances {
+				call: Call<Runtime> = 3,
+				dispatch: |runtime, origin, call| {
+					runtime.balances.dispatch(origin, &IdentityLookup::default(), call)
+				},
+			},
+			proof_of_existence: ProofOfExistence {
+				call: Call<Runtime> = 4,
+				event: Event<Runtime>,
+				hold_reason: HoldReason,
+				dispatch: |runtime, origin, call| {
+					runtime.proof_of_existence.dispatch(&mut runtime.balances, origin, call)
+				},
+			},
+		}
+	}
+
+	impl system::Config for Runtime {
+		type AccountId = String;
+		type BlockNumber = u32;
+		type Nonce = u32;
+		type RuntimeEvent = RuntimeEvent;
+		const MAX_BLOCK_WEIGHT: u64 = 1_000;
+		const MAX_EXTRINSICS_PER_ACCOUNT: u32 = 10;
+	}
+
+	impl balances::Config for Runtime {
+		type Balance = u128;
+		type Address = String;
+		type RuntimeHoldReason = RuntimeHoldReason;
+		const EXISTENTIAL_DEPOSIT: u128 = 1;
+
+		fn dust_removal() -> balances::DustRemoval<String> {
+			balances::DustRemoval::Burn
+		}
+	}
+
+	impl proof_of_existence::Config for Runtime {
+		type Content = String;
+		const CLAIM_DEPOSIT: u128 = 10;
+		const CLAIM_TTL: u32 = 10;
+		const MAX_NOTE_LENGTH: usize = 8;
+	}
+
+	#[test]
+	fn the_runtime_is_generated_from_its_pallets() {
+		let mut runtime = Runtime::new();
+		runtime.balances.set_balance(&"alice".to_string(), 100);
+		let transfer = balances::Call::Transfer { to: "bob".to_string(), amount: 30 };
+		let call = RuntimeCall::Balances(transfer.clone());
+		let encoded = call.encode();
+		assert_eq!((encoded[0], encoded[1]), (3, 0));
+		assert_eq!(call.indices(), (3, 0));
+		assert_eq!(decode_all::<RuntimeCall>(&encoded).map(|call| call.encode()), Ok(encoded));
+		assert_eq!(decode_all::<RuntimeCall>(&[0]).map(|_| ()), Err("Invalid pallet index"));
+		assert_eq!(call.weight(), transfer.weight());
+		assert_eq!(call.dispatch_info().weight, transfer.weight());
+
+		// Calls that report nothing used their declared weight, and pay for it.
+		let post = runtime.dispatch_with_post_info(RawOrigin::Signed("alice".to_string()), call);
+		assert_eq!(post, Ok(PostDispatchInfo::default()));
+		assert_eq!(runtime.balances.balance(&"bob".to_string()), 30);
+		let claim = "document".to_string();
+		let create = proof_of_existence::Call::CreateClaim { claim: claim.clone(), note: None };
+		let handover = proof_of_existence::Call::TransferClaim { claim, to: "bob".to_string() };
+		for call in [create, handover] {
+			let call = RuntimeCall::ProofOfExistence(call);
+			runtime.dispatch(RawOrigin::Signed("alice".to_string()), call).unwrap();
+		}
+		runtime.collect_events();
+		assert_eq!(runtime.system.block_number(), 0);
+		let event = &runtime.system.events()[0].event;
+		assert_eq!((event.pallet(), event.name()), ("ProofOfExistence", "ClaimTransferred"));
+		assert_eq!(event.accounts(), ["alice", "bob"]);
+		// The claim deposit is held for the reason of the pallet, encoded with its index.
+		let reason = RuntimeHoldReason::from(proof_of_existence::HoldReason::ClaimDeposit);
+		assert_eq!(runtime.balances.balance_on_hold(reason, &"alice".to_string()), 10);
+		assert_eq!(decode_all::<RuntimeHoldReason>(&reason.encode()), Ok(reason));
+		assert_eq!(reason.encode(), [4, 0]);
+
+		// Only pallets with calls have an index.
+		let pallets = Runtime::metadata().pallets;
+		let indices: Vec<_> = pallets.iter().map(|pallet| (pallet.name, pallet.index)).collect();
+		let expected = [("System", None), ("Balances", Some(3)), ("ProofOfExistence", Some(4))];
+		assert_eq!(indices, expected);
+	}
+}
@@ -0,0 +1,646 @@
+use std::collections::BTreeMap;
+
+use crate::{
+	balances,
+	codec::{Decode, Encode},
+	hashing::{self, Hash},
+	metadata::{field, storage, variant, PalletMetadata},
+	migrations::{self, StorageVersion},
+	support::{ensure_signed, DispatchResult, Weight},
+	system::OriginFor,
+	trie::{self, Storage},
+};
+
+pub mod wasm;
+
+use wasm::{Gas, Trap, Value, ValueType};
+
+/// The gas charged for every call into the host, on top of one unit per byte it copies.
+const HOST_CALL_COST: u64 = 100;
+
+/// The functions contracts can import from the `env` module, with their signatures.
+const HOST_FUNCTIONS: &[(&str, &[ValueType], &[ValueType])] = {
+	use ValueType::{I32, I64};
+	&[
+		("seal_input", &[I32], &[I32]),
+		("seal_return", &[I32, I32], &[]),
+		("seal_get_storage", &[I32, I32, I32], &[I32]),
+		("seal_set_storage", &[I32, I32, I32, I32], &[]),
+		("seal_clear_storage", &[I32, I32], &[]),
+		("seal_caller", &[I32], &[I32]),
+		("seal_address", &[I32], &[I32]),
+		("seal_balance", &[], &[I64]),
+		("seal_value_transferred", &[], &[I64]),
+		("seal_transfer", &[I32, I32, I64], &[I32]),
+	]
+};
+
+pub trait Config: balances::Config {
+	/// The maximum size, in bytes, of an uploaded wasm blob.
+	const MAX_CODE_SIZE: usize;
+	/// The maximum gas a single instantiation or call can use.
+	const MAX_GAS: u64;
+
+	/// The address of a contract, derived from its deployer, code and a salt.
+	fn contract_address(
+		deployer: &Self::AccountId,
+		code_hash: &Hash,
+		salt: &[u8],
+	) -> Self::AccountId;
+	/// The raw bytes contracts see for an account, and back.
+	fn account_to_bytes(who: &Self::AccountId) -> Vec<u8>;
+	fn account_from_bytes(bytes: &[u8]) -> Option<Self::AccountId>;
+}
+
+#[derive(Debug, Clone)]
+pub enum Call<T: Config> {
+	UploadCode {
+		code: Vec<u8>,
+	},
+	Instantiate {
+		code_hash: Hash,
+		endowment: T::Balance,
+		gas_limit: u64,
+		data: Vec<u8>,
+		salt: Vec<u8>,
+	},
+	Call {
+		dest: T::AccountId,
+		value: T::Balance,
+		gas_limit: u64,
+		data: Vec<u8>,
+	},
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::UploadCode { code } => (0u8, code).encode_to(dest),
+			Call::Instantiate { code_hash, endowment, gas_limit, data, salt } => {
+				(1u8, code_hash, endowment, (gas_limit, data, salt)).encode_to(dest)
+			},
+			Call::Call { dest: contract, value, gas_limit, data } => {
+				(2u8, contract, value, (gas_limit, data)).encode_to(dest)
+			},
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::UploadCode { code: Decode::decode(input)? },
+			1 => Call::Instantiate {
+				code_hash: Decode::decode(input)?,
+				endowment: Decode::decode(input)?,
+				gas_limit: Decode::decode(input)?,
+				data: Decode::decode(input)?,
+				salt: Decode::decode(input)?,
+			},
+			2 => Call::Call {
+				dest: Decode::decode(input)?,
+				value: Decode::decode(input)?,
+				gas_limit: Decode::decode(input)?,
+				data: Decode::decode(input)?,
+			},
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::UploadCode { .. } => 0,
+			Call::Instantiate { .. } => 1,
+			Call::Call { .. } => 2,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::UploadCode { code } => 10_000 + code.len() as Weight,
+			// Executing a contract may use up to its whole gas limit.
+			Call::Instantiate { gas_limit, .. } | Call::Call { gas_limit, .. } => {
+				10_000u64.saturating_add(*gas_limit)
+			},
+		}
+	}
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContractInfo {
+	pub code_hash: Hash,
+	pub storage: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Encode for ContractInfo {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		let storage: Vec<_> = self.storage.iter().collect();
+		(&self.code_hash, storage).encode_to(dest);
+	}
+}
+
+impl Decode for ContractInfo {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (code_hash, storage): (_, Vec<_>) = Decode::decode(input)?;
+		Ok(ContractInfo { code_hash, storage: storage.into_iter().collect() })
+	}
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	/// The uploaded blobs, which are part of the state, unlike the modules parsed from them.
+	pristine_code: BTreeMap<Hash, Vec<u8>>,
+	codes: BTreeMap<Hash, wasm::Module>,
+	contracts: BTreeMap<T::AccountId, ContractInfo>,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self { pristine_code: BTreeMap::new(), codes: BTreeMap::new(), contracts: BTreeMap::new() }
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Contracts",
+			index: None,
+			calls: vec![
+				variant("UploadCode", 0, vec![field::<Vec<u8>>("code")]),
+				variant(
+					"Instantiate",
+					1,
+					vec![
+						field::<Hash>("code_hash"),
+						field::<T::Balance>("endowment"),
+						field::<u64>("gas_limit"),
+						field::<Vec<u8>>("data"),
+						field::<Vec<u8>>("salt"),
+					],
+				),
+				variant(
+					"Call",
+					2,
+					vec![
+						field::<T::AccountId>("dest"),
+						field::<T::Balance>("value"),
+						field::<u64>("gas_limit"),
+						field::<Vec<u8>>("data"),
+					],
+				),
+			],
+			storage: vec![
+				storage::<BTreeMap<Hash, Vec<u8>>>("pristine_code"),
+				storage::<BTreeMap<Hash, wasm::Module>>("codes"),
+				storage::<BTreeMap<T::AccountId, ContractInfo>>("contracts"),
+			],
+			events: Vec::new(),
+			errors: vec![
+				"Code not found",
+				"Code too large",
+				"Contract already exists",
+				"Contract must export deploy and call",
+				"Contract not found",
+				"Gas limit too high",
+				"Invalid account",
+				"Not a wasm module",
+				"Out of bounds memory access",
+				"Out of gas",
+				"Signature mismatch",
+				"Unknown host function",
+				"Unknown type",
+			],
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Contracts", STORAGE_VERSION);
+		trie::insert_entries(storage, "Contracts", "pristine_code", &self.pristine_code);
+		trie::insert_entries(storage, "Contracts", "contracts", &self.contracts);
+	}
+
+	/// Restore the code and contracts `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult {
+		migrations::ensure_version(storage, "Contracts", STORAGE_VERSION)?;
+		self.pristine_code = trie::decode_entries(storage, "Contracts", "pristine_code")?
+			.into_iter()
+			.collect();
+		// The modules were checked when the code was uploaded, so they only need parsing.
+		let codes = self
+			.pristine_code
+			.iter()
+			.map(|(hash, code)| Ok((*hash, wasm::Module::parse(code)?)));
+		self.codes = codes.collect::<Result<_, &'static str>>()?;
+		self.contracts =
+			trie::decode_entries(storage, "Contracts", "contracts")?.into_iter().collect();
+		Ok(())
+	}
+
+	/// Contracts hold and move funds, so calls are dispatched together with the balances
+	/// pallet.
+	pub fn dispatch(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		origin: OriginFor<T>,
+		call: Call<T>,
+	) -> DispatchResult {
+		let caller = ensure_signed(origin)?;
+		match call {
+			Call::UploadCode { code } => self.upload_code(code).map(|_| ()),
+			Call::Instantiate { code_hash, endowment, gas_limit, data, salt } => self
+				.instantiate(balances, caller, code_hash, endowment, gas_limit, data, salt)
+				.map(|_| ()),
+			Call::Call { dest, value, gas_limit, data } => {
+				self.call(balances, caller, dest, value, gas_limit, data).map(|_| ())
+			},
+		}
+	}
+
+	pub fn contract(&self, address: &T::AccountId) -> Option<&ContractInfo> {
+		self.contracts.get(address)
+	}
+
+	pub fn code_exists(&self, code_hash: &Hash) -> bool {
+		self.codes.contains_key(code_hash)
+	}
+
+	/// Store a wasm blob so contracts can be instantiated from it. The blob must only import
+	/// known host functions and export `deploy` and `call`.
+	pub fn upload_code(&mut self, code: Vec<u8>) -> Result<Hash, &'static str> {
+		if code.len() > T::MAX_CODE_SIZE {
+			return Err("Code too large");
+		}
+		let module = wasm::Module::parse(&code)?;
+
+		for import in module.imports() {
+			let ty = module.func_type(import.ty).ok_or("Unknown type")?;
+			let known = HOST_FUNCTIONS.iter().any(|(name, params, results)| {
+				import.module == "env"
+					&& import.name == *name
+					&& ty.params == *params
+					&& ty.results == *results
+			});
+			if !known {
+				return Err("Unknown host function");
+			}
+		}
+		for export in ["deploy", "call"] {
+			let ty = module.export(export).and_then(|index| module.function_type(index));
+			if ty.is_none_or(|ty| !ty.params.is_empty()) {
+				return Err("Contract must export deploy and call");
+			}
+		}
+
+		let code_hash = hashing::blake2_256(&code);
+		self.codes.insert(code_hash, module);
+		self.pristine_code.insert(code_hash, code);
+		Ok(code_hash)
+	}
+
+	/// Create a contract from uploaded code, endowed by `caller`, and run its `deploy` export.
+	/// Returns the address of the new contract.
+	#[allow(clippy::too_many_arguments)]
+	pub fn instantiate(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		caller: T::AccountId,
+		code_hash: Hash,
+		endowment: T::Balance,
+		gas_limit: u64,
+		data: Vec<u8>,
+		salt: Vec<u8>,
+	) -> Result<T::AccountId, &'static str> {
+		if !self.codes.contains_key(&code_hash) {
+			return Err("Code not found");
+		}
+		let address = T::contract_address(&caller, &code_hash, &salt);
+		if self.contracts.contains_key(&address) {
+			return Err("Contract already exists");
+		}
+
+		let contract = ContractInfo { code_hash, storage: BTreeMap::new() };
+		let (contract, _) = self
+			.execute(balances, "deploy", contract, caller, &address, endowment, gas_limit, &data)?;
+		self.contracts.insert(address.clone(), contract);
+		Ok(address)
+	}
+
+	/// Send `value` to the contract at `dest` and run its `call` export with `data` as input.
+	/// Returns the data the contract passed to `seal_return`.
+	pub fn call(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		caller: T::AccountId,
+		dest: T::AccountId,
+		value: T::Balance,
+		gas_limit: u64,
+		data: Vec<u8>,
+	) -> Result<Vec<u8>, &'static str> {
+		let contract = self.contracts.get(&dest).ok_or("Contract not found")?.clone();
+		let (contract, output) =
+			self.execute(balances, "call", contract, caller, &dest, value, gas_limit, &data)?;
+		self.contracts.insert(dest, contract);
+		Ok(output)
+	}
+
+	/// Run an export against copies of the balances and the contract storage, which replace
+	/// the originals only if the contract doesn't trap.
+	#[allow(clippy::too_many_arguments)]
+	fn execute(
+		&self,
+		balances: &mut balances::Pallet<T>,
+		export: &str,
+		mut contract: ContractInfo,
+		caller: T::AccountId,
+		address: &T::AccountId,
+		value: T::Balance,
+		gas_limit: u64,
+		input: &[u8],
+	) -> Result<(ContractInfo, Vec<u8>), &'static str> {
+		if gas_limit > T::MAX_GAS {
+			return Err("Gas limit too high");
+		}
+		let module = self.codes.get(&contract.code_hash).ok_or("Code not found")?;
+
+		let mut overlay = balances.clone();
+		overlay.transfer(caller.clone(), address.clone(), value)?;
+
+		let mut ext = Ext::<T> {
+			balances: &mut overlay,
+			storage: &mut contract.storage,
+			caller: &caller,
+			address,
+			value,
+			input,
+			output: Vec::new(),
+		};
+		let mut instance = wasm::Instance::new(module)?;
+		instance.invoke(export, &[], &mut ext, &mut Gas(gas_limit))?;
+
+		let output = ext.output;
+		*balances = overlay;
+		Ok((contract, output))
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// The host side of a contract execution.
+struct Ext<'a, T: Config> {
+	balances: &'a mut balances::Pallet<T>,
+	storage: &'a mut BTreeMap<Vec<u8>, Vec<u8>>,
+	caller: &'a T::AccountId,
+	address: &'a T::AccountId,
+	value: T::Balance,
+	input: &'a [u8],
+	output: Vec<u8>,
+}
+
+fn arg_i32(args: &[Value], index: usize) -> Result<i32, Trap> {
+	match args.get(index) {
+		Some(Value::I32(value)) => Ok(*value),
+		_ => Err("Signature mismatch"),
+	}
+}
+
+fn read<'m>(memory: &'m [u8], ptr: i32, len: i32, gas: &mut Gas) -> Result<&'m [u8], Trap> {
+	gas.charge(len as u32 as u64)?;
+	let start = ptr as u32 as usize;
+	memory
+		.get(start..start.checked_add(len as u32 as usize).ok_or("Out of bounds memory access")?)
+		.ok_or("Out of bounds memory access")
+}
+
+/// Copy `bytes` into the memory at `ptr`, returning their length.
+fn write(memory: &mut [u8], ptr: i32, bytes: &[u8], gas: &mut Gas) -> Result<Option<Value>, Trap> {
+	gas.charge(bytes.len() as u64)?;
+	let start = ptr as u32 as usize;
+	memory
+		.get_mut(start..start.checked_add(bytes.len()).ok_or("Out of bounds memory access")?)
+		.ok_or("Out of bounds memory access")?
+		.copy_from_slice(bytes);
+	Ok(Some(Value::I32(bytes.len() as i32)))
+}
+
+fn to_i64<B: TryInto<u64>>(balance: B) -> Value {
+	Value::I64(
+		balance
+			.try_into()
+			.map(|value| value.min(i64::MAX as u64) as i64)
+			.unwrap_or(i64::MAX),
+	)
+}
+
+impl<T: Config> wasm::Host for Ext<'_, T> {
+	fn call(
+		&mut self,
+		name: &str,
+		args: &[Value],
+		memory: &mut [u8],
+		gas: &mut Gas,
+	) -> Result<Option<Value>, Trap> {
+		gas.charge(HOST_CALL_COST)?;
+		match name {
+			"seal_input" => write(memory, arg_i32(args, 0)?, self.input, gas),
+			"seal_return" => {
+				self.output = read(memory, arg_i32(args, 0)?, arg_i32(args, 1)?, gas)?.to_vec();
+				Ok(None)
+			},
+			"seal_get_storage" => {
+				let key = read(memory, arg_i32(args, 0)?, arg_i32(args, 1)?, gas)?;
+				match self.storage.get(key).cloned() {
+					Some(value) => write(memory, arg_i32(args, 2)?, &value, gas),
+					None => Ok(Some(Value::I32(-1))),
+				}
+			},
+			"seal_set_storage" => {
+				let key = read(memory, arg_i32(args, 0)?, arg_i32(args, 1)?, gas)?.to_vec();
+				let value = read(memory, arg_i32(args, 2)?, arg_i32(args, 3)?, gas)?.to_vec();
+				self.storage.insert(key, value);
+				Ok(None)
+			},
+			"seal_clear_storage" => {
+				let key = read(memory, arg_i32(args, 0)?, arg_i32(args, 1)?, gas)?;
+				self.storage.remove(key);
+				Ok(None)
+			},
+			"seal_caller" => {
+				write(memory, arg_i32(args, 0)?, &T::account_to_bytes(self.caller), gas)
+			},
+			"seal_address" => {
+				write(memory, arg_i32(args, 0)?, &T::account_to_bytes(self.address), gas)
+			},
+			"seal_balance" => Ok(Some(to_i64(self.balances.balance(self.address)))),
+			"seal_value_transferred" => Ok(Some(to_i64(self.value))),
+			"seal_transfer" => {
+				let to = read(memory, arg_i32(args, 0)?, arg_i32(args, 1)?, gas)?;
+				let to = T::account_from_bytes(to).ok_or("Invalid account")?;
+				let value = match args.get(2) {
+					Some(Value::I64(value)) => T::Balance::from(*value as u64),
+					_ => return Err("Signature mismatch"),
+				};
+				let result = self.balances.transfer(self.address.clone(), to, value);
+				Ok(Some(Value::I32(result.is_err() as i32)))
+			},
+			_ => Err("Unknown host function"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::wasm::builder::module;
+	use crate::{balances, hashing::Hash, mock::Test};
+
+	const I32: u8 = 0x7f;
+	const I64: u8 = 0x7e;
+
+	impl super::Config for Test {
+		const MAX_CODE_SIZE: usize = 1024;
+		const MAX_GAS: u64 = 10_000;
+
+		fn contract_address(_: &String, code_hash: &Hash, salt: &[u8]) -> String {
+			format!("contract:{}:{}", code_hash[0], String::from_utf8_lossy(salt))
+		}
+
+		fn account_to_bytes(who: &String) -> Vec<u8> {
+			who.as_bytes().to_vec()
+		}
+
+		fn account_from_bytes(bytes: &[u8]) -> Option<String> {
+			String::from_utf8(bytes.to_vec()).ok()
+		}
+	}
+
+	/// Stores its input under the key `k` on deploy. Each call returns the stored value and
+	/// pays 5 to the caller.
+	fn store_and_pay() -> Vec<u8> {
+		#[rustfmt::skip]
+		let deploy = [
+			0x41, 0x10, 0x10, 0x00, 0x21, 0x00, // len = seal_input(16)
+			0x41, 0x00, 0x41, 0x01, 0x41, 0x10, 0x20, 0x00, 0x10, 0x02, // seal_set_storage(0, 1, 16, len)
+			0x0b,
+		];
+		#[rustfmt::skip]
+		let call = [
+			0x41, 0x00, 0x41, 0x01, 0x41, 0x10, 0x10, 0x01, 0x21, 0x00, // len = seal_get_storage(0, 1, 16)
+			0x41, 0x10, 0x20, 0x00, 0x10, 0x03, // seal_return(16, len)
+			0x41, 0x20, 0x41, 0x20, 0x10, 0x04, 0x21, 0x00, // len = seal_caller(32)
+			0x41, 0x20, 0x20, 0x00, 0x42, 0x05, 0x10, 0x05, 0x1a, // seal_transfer(32, len, 5)
+			0x0b,
+		];
+		module(
+			&[
+				(&[I32], &[I32]),
+				(&[I32, I32, I32], &[I32]),
+				(&[I32, I32, I32, I32], &[]),
+				(&[I32, I32], &[]),
+				(&[I32, I32, I64], &[I32]),
+				(&[], &[]),
+			],
+			&[
+				("seal_input", 0),
+				("seal_get_storage", 1),
+				("seal_set_storage", 2),
+				("seal_return", 3),
+				("seal_caller", 0),
+				("seal_transfer", 4),
+			],
+			&[(5, &[I32], &deploy), (5, &[I32], &call)],
+			&[("deploy", 6), ("call", 7)],
+			&[(0, b"k")],
+		)
+	}
+
+	#[test]
+	fn upload_validates_code() {
+		let mut contracts: super::Pallet<Test> = super::Pallet::new();
+
+		assert_eq!(contracts.upload_code(vec![0; 2048]), Err("Code too large"));
+		assert_eq!(contracts.upload_code(b"not wasm".to_vec()), Err("Not a wasm module"));
+
+		let no_exports = module(&[(&[], &[])], &[], &[(0, &[], &[0x0b])], &[("call", 0)], &[]);
+		assert_eq!(contracts.upload_code(no_exports), Err("Contract must export deploy and call"));
+
+		let unknown = module(&[(&[], &[])], &[("seal_terminate", 0)], &[], &[], &[]);
+		assert_eq!(contracts.upload_code(unknown), Err("Unknown host function"));
+
+		let code_hash = contracts.upload_code(store_and_pay()).unwrap();
+		assert!(contracts.code_exists(&code_hash));
+	}
+
+	#[test]
+	fn instantiate_and_call() {
+		let alice = "alice".to_string();
+		let mut balances: balances::Pallet<Test> = balances::Pallet::new();
+		let mut contracts: super::Pallet<Test> = super::Pallet::new();
+		balances.set_balance(&alice, 100);
+
+		let code_hash = contracts.upload_code(store_and_pay()).unwrap();
+		let address = contracts
+			.instantiate(
+				&mut balances,
+				alice.clone(),
+				code_hash,
+				50,
+				1_000,
+				b"hello".to_vec(),
+				vec![],
+			)
+			.unwrap();
+		assert_eq!(balances.balance(&address), 50);
+		assert_eq!(
+			contracts.contract(&address).unwrap().storage.get(b"k".as_slice()),
+			Some(&b"hello".to_vec())
+		);
+		assert_eq!(
+			contracts.instantiate(
+				&mut balances,
+				alice.clone(),
+				code_hash,
+				0,
+				1_000,
+				vec![],
+				vec![]
+			),
+			Err("Contract already exists")
+		);
+
+		let output =
+			contracts.call(&mut balances, alice.clone(), address.clone(), 0, 1_000, vec![]);
+		assert_eq!(output, Ok(b"hello".to_vec()));
+		assert_eq!(balances.balance(&alice), 55);
+		assert_eq!(balances.balance(&address), 45);
+	}
+
+	#[test]
+	fn trapped_execution_is_reverted() {
+		let alice = "alice".to_string();
+		let mut balances: balances::Pallet<Test> = balances::Pallet::new();
+		let mut contracts: super::Pallet<Test> = super::Pallet::new();
+		balances.set_balance(&alice, 100);
+
+		let code_hash = contracts.upload_code(store_and_pay()).unwrap();
+		let address = contracts
+			.instantiate(&mut balances, alice.clone(), code_hash, 50, 1_000, b"v".to_vec(), vec![])
+			.unwrap();
+
+		assert_eq!(
+			contracts.call(&mut balances, alice.clone(), address.clone(), 10, 200, vec![]),
+			Err("Out of gas")
+		);
+		assert_eq!(
+			contracts.call(&mut balances, alice.clone(), address.clone(), 0, 20_000, vec![]),
+			Err("Gas limit too high")
+		);
+		assert_eq!(balances.balance(&alice), 50);
+		assert_eq!(balances.balance(&address), 50);
+	}
+}
@@ -0,0 +1,1103 @@
+//! A small, metered interpreter for the subset of WebAssembly contracts are written in.
+//!
+//! Supported are integer (`i32`/`i64`) code with a single linear memory, structured control
+//! flow, direct calls and functions imported from the host. Floats, tables, globals and
+//! multi-value blocks are rejected when the module is parsed. Every executed instruction costs
+//! one unit of gas.
+
+use std::collections::BTreeMap;
+
+const PAGE_SIZE: usize = 65_536;
+/// The maximum number of memory pages a module can have, i.e. 1 MiB.
+pub const MAX_PAGES: u32 = 16;
+const MAX_CALL_DEPTH: usize = 64;
+
+pub type Trap = &'static str;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+	I32,
+	I64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+	I32(i32),
+	I64(i64),
+}
+
+impl Value {
+	fn default_of(ty: ValueType) -> Self {
+		match ty {
+			ValueType::I32 => Value::I32(0),
+			ValueType::I64 => Value::I64(0),
+		}
+	}
+
+	fn ty(&self) -> ValueType {
+		match self {
+			Value::I32(_) => ValueType::I32,
+			Value::I64(_) => ValueType::I64,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuncType {
+	pub params: Vec<ValueType>,
+	pub results: Vec<ValueType>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+	pub module: String,
+	pub name: String,
+	pub ty: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Instr {
+	Unreachable,
+	Nop,
+	Block { arity: usize, end: usize },
+	Loop,
+	If { arity: usize, else_: Option<usize>, end: usize },
+	Else { end: usize },
+	End,
+	Br(u32),
+	BrIf(u32),
+	Return,
+	Call(u32),
+	Drop,
+	Select,
+	LocalGet(u32),
+	LocalSet(u32),
+	LocalTee(u32),
+	Load { op: u8, offset: u32 },
+	Store { op: u8, offset: u32 },
+	MemorySize,
+	MemoryGrow,
+	I32Const(i32),
+	I64Const(i64),
+	Numeric(u8),
+}
+
+#[derive(Debug, Clone)]
+struct Function {
+	ty: u32,
+	locals: Vec<ValueType>,
+	code: Vec<Instr>,
+}
+
+/// A parsed module, ready to be instantiated.
+#[derive(Debug, Clone)]
+pub struct Module {
+	types: Vec<FuncType>,
+	imports: Vec<Import>,
+	functions: Vec<Function>,
+	memory_pages: u32,
+	exports: BTreeMap<String, u32>,
+	data: Vec<(u32, Vec<u8>)>,
+}
+
+/// The remaining gas of an execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gas(pub u64);
+
+impl Gas {
+	pub fn charge(&mut self, amount: u64) -> Result<(), Trap> {
+		self.0 = self.0.checked_sub(amount).ok_or("Out of gas")?;
+		Ok(())
+	}
+}
+
+/// The functions a module imports, provided by the embedder.
+pub trait Host {
+	fn call(
+		&mut self,
+		name: &str,
+		args: &[Value],
+		memory: &mut [u8],
+		gas: &mut Gas,
+	) -> Result<Option<Value>, Trap>;
+}
+
+struct Reader<'a> {
+	bytes: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> Reader<'a> {
+	fn new(bytes: &'a [u8]) -> Self {
+		Self { bytes, pos: 0 }
+	}
+
+	fn is_empty(&self) -> bool {
+		self.pos >= self.bytes.len()
+	}
+
+	fn byte(&mut self) -> Result<u8, Trap> {
+		let byte = *self.bytes.get(self.pos).ok_or("Unexpected end of module")?;
+		self.pos += 1;
+		Ok(byte)
+	}
+
+	fn bytes(&mut self, len: usize) -> Result<&'a [u8], Trap> {
+		let end = self.pos.checked_add(len).ok_or("Unexpected end of module")?;
+		let bytes = self.bytes.get(self.pos..end).ok_or("Unexpected end of module")?;
+		self.pos = end;
+		Ok(bytes)
+	}
+
+	fn leb(&mut self, bits: u32, signed: bool) -> Result<i128, Trap> {
+		let mut result: i128 = 0;
+		let mut shift = 0;
+		loop {
+			let byte = self.byte()?;
+			result |= ((byte & 0x7f) as i128) << shift;
+			shift += 7;
+			if byte & 0x80 == 0 {
+				if signed && shift < 128 && byte & 0x40 != 0 {
+					result |= -1i128 << shift;
+				}
+				return Ok(result);
+			}
+			if shift >= bits + 7 {
+				return Err("Invalid integer encoding");
+			}
+		}
+	}
+
+	fn u32(&mut self) -> Result<u32, Trap> {
+		u32::try_from(self.leb(32, false)?).map_err(|_| "Invalid integer encoding")
+	}
+
+	fn i32(&mut self) -> Result<i32, Trap> {
+		i32::try_from(self.leb(32, true)?).map_err(|_| "Invalid integer encoding")
+	}
+
+	fn i64(&mut self) -> Result<i64, Trap> {
+		i64::try_from(self.leb(64, true)?).map_err(|_| "Invalid integer encoding")
+	}
+
+	fn name(&mut self) -> Result<String, Trap> {
+		let len = self.u32()? as usize;
+		String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| "Invalid name")
+	}
+
+	fn value_type(&mut self) -> Result<ValueType, Trap> {
+		match self.byte()? {
+			0x7f => Ok(ValueType::I32),
+			0x7e => Ok(ValueType::I64),
+			_ => Err("Unsupported value type"),
+		}
+	}
+
+	fn vec<T>(
+		&mut self,
+		mut item: impl FnMut(&mut Self) -> Result<T, Trap>,
+	) -> Result<Vec<T>, Trap> {
+		let len = self.u32()?;
+		(0..len).map(|_| item(self)).collect()
+	}
+}
+
+impl Module {
+	pub fn parse(bytes: &[u8]) -> Result<Self, Trap> {
+		let mut reader = Reader::new(bytes);
+		if reader.bytes(8)? != b"\0asm\x01\0\0\0" {
+			return Err("Not a wasm module");
+		}
+
+		let mut module = Module {
+			types: Vec::new(),
+			imports: Vec::new(),
+			functions: Vec::new(),
+			memory_pages: 0,
+			exports: BTreeMap::new(),
+			data: Vec::new(),
+		};
+		let mut function_types = Vec::new();
+
+		while !reader.is_empty() {
+			let id = reader.byte()?;
+			let len = reader.u32()? as usize;
+			let mut section = Reader::new(reader.bytes(len)?);
+			match id {
+				0 => continue,
+				1 => module.types = section.vec(Self::parse_func_type)?,
+				2 => module.imports = section.vec(Self::parse_import)?,
+				3 => function_types = section.vec(|r| r.u32())?,
+				5 => module.memory_pages = Self::parse_memory(&mut section)?,
+				7 => {
+					for (name, index) in section.vec(Self::parse_export)?.into_iter().flatten() {
+						module.exports.insert(name, index);
+					}
+				},
+				10 => {
+					let bodies = section.vec(|r| {
+						let len = r.u32()? as usize;
+						r.bytes(len)
+					})?;
+					if bodies.len() != function_types.len() {
+						return Err("Function and code section mismatch");
+					}
+					for (ty, body) in function_types.iter().zip(bodies) {
+						module.functions.push(Self::parse_body(*ty, body)?);
+					}
+				},
+				11 => module.data = section.vec(Self::parse_data)?,
+				_ => return Err("Unsupported section"),
+			}
+			if !section.is_empty() {
+				return Err("Malformed section");
+			}
+		}
+
+		module.check()?;
+		Ok(module)
+	}
+
+	pub fn imports(&self) -> &[Import] {
+		&self.imports
+	}
+
+	pub fn func_type(&self, index: u32) -> Option<&FuncType> {
+		self.types.get(index as usize)
+	}
+
+	/// The type of the function at `index`, imported or defined in the module.
+	pub fn function_type(&self, index: u32) -> Option<&FuncType> {
+		let ty = match (index as usize).checked_sub(self.imports.len()) {
+			None => self.imports.get(index as usize)?.ty,
+			Some(local) => self.functions.get(local)?.ty,
+		};
+		self.types.get(ty as usize)
+	}
+
+	pub fn export(&self, name: &str) -> Option<u32> {
+		self.exports.get(name).copied()
+	}
+
+	fn parse_func_type(r: &mut Reader) -> Result<FuncType, Trap> {
+		if r.byte()? != 0x60 {
+			return Err("Invalid function type");
+		}
+		let params = r.vec(Reader::value_type)?;
+		let results = r.vec(Reader::value_type)?;
+		if results.len() > 1 {
+			return Err("Multiple results are not supported");
+		}
+		Ok(FuncType { params, results })
+	}
+
+	fn parse_import(r: &mut Reader) -> Result<Import, Trap> {
+		let module = r.name()?;
+		let name = r.name()?;
+		if r.byte()? != 0x00 {
+			return Err("Only functions can be imported");
+		}
+		Ok(Import { module, name, ty: r.u32()? })
+	}
+
+	fn parse_memory(r: &mut Reader) -> Result<u32, Trap> {
+		let limits = r.vec(|r| {
+			let min = match r.byte()? {
+				0x00 => r.u32()?,
+				0x01 => {
+					let min = r.u32()?;
+					r.u32()?;
+					min
+				},
+				_ => return Err("Invalid memory limits"),
+			};
+			Ok(min)
+		})?;
+		match limits.as_slice() {
+			[] => Ok(0),
+			[min] if *min <= MAX_PAGES => Ok(*min),
+			[_] => Err("Memory too large"),
+			_ => Err("Multiple memories are not supported"),
+		}
+	}
+
+	/// Only function exports are kept, memory exports are accepted and ignored.
+	fn parse_export(r: &mut Reader) -> Result<Option<(String, u32)>, Trap> {
+		let name = r.name()?;
+		let kind = r.byte()?;
+		let index = r.u32()?;
+		match kind {
+			0x00 => Ok(Some((name, index))),
+			0x02 => Ok(None),
+			_ => Err("Unsupported export"),
+		}
+	}
+
+	fn parse_data(r: &mut Reader) -> Result<(u32, Vec<u8>), Trap> {
+		if r.u32()? != 0 {
+			return Err("Unsupported data segment");
+		}
+		if r.byte()? != 0x41 {
+			return Err("Unsupported data offset");
+		}
+		let offset = r.i32()? as u32;
+		if r.byte()? != 0x0b {
+			return Err("Unsupported data offset");
+		}
+		let len = r.u32()? as usize;
+		Ok((offset, r.bytes(len)?.to_vec()))
+	}
+
+	fn parse_block_type(r: &mut Reader) -> Result<usize, Trap> {
+		match r.byte()? {
+			0x40 => Ok(0),
+			0x7f | 0x7e => Ok(1),
+			_ => Err("Unsupported block type"),
+		}
+	}
+
+	/// Decode a function body, resolving where every block, loop and `if` ends.
+	fn parse_body(ty: u32, body: &[u8]) -> Result<Function, Trap> {
+		let mut r = Reader::new(body);
+		let mut locals = Vec::new();
+		for (count, ty) in r.vec(|r| Ok((r.u32()?, r.value_type()?)))? {
+			if locals.len() + count as usize > 1024 {
+				return Err("Too many locals");
+			}
+			locals.extend(std::iter::repeat_n(ty, count as usize));
+		}
+
+		let mut code = Vec::new();
+		let mut blocks: Vec<usize> = Vec::new();
+		loop {
+			let index = code.len();
+			let opcode = r.byte()?;
+			let instr = match opcode {
+				0x00 => Instr::Unreachable,
+				0x01 => Instr::Nop,
+				0x02 => {
+					blocks.push(index);
+					Instr::Block { arity: Self::parse_block_type(&mut r)?, end: 0 }
+				},
+				0x03 => {
+					Self::parse_block_type(&mut r)?;
+					blocks.push(index);
+					Instr::Loop
+				},
+				0x04 => {
+					blocks.push(index);
+					Instr::If { arity: Self::parse_block_type(&mut r)?, else_: None, end: 0 }
+				},
+				0x05 => {
+					let start = *blocks.last().ok_or("Unexpected else")?;
+					match &mut code[start] {
+						Instr::If { else_: else_ @ None, .. } => *else_ = Some(index),
+						_ => return Err("Unexpected else"),
+					}
+					Instr::Else { end: 0 }
+				},
+				0x0b => {
+					let Some(start) = blocks.pop() else {
+						code.push(Instr::End);
+						break;
+					};
+					match &mut code[start] {
+						Instr::Block { end, .. } => *end = index,
+						Instr::If { end, else_, .. } => {
+							*end = index;
+							if let Some(else_) = *else_ {
+								code[else_] = Instr::Else { end: index };
+							}
+						},
+						_ => {},
+					}
+					Instr::End
+				},
+				0x0c => Instr::Br(r.u32()?),
+				0x0d => Instr::BrIf(r.u32()?),
+				0x0f => Instr::Return,
+				0x10 => Instr::Call(r.u32()?),
+				0x1a => Instr::Drop,
+				0x1b => Instr::Select,
+				0x20 => Instr::LocalGet(r.u32()?),
+				0x21 => Instr::LocalSet(r.u32()?),
+				0x22 => Instr::LocalTee(r.u32()?),
+				0x28..=0x35 => {
+					r.u32()?;
+					Instr::Load { op: opcode, offset: r.u32()? }
+				},
+				0x36..=0x3e => {
+					r.u32()?;
+					Instr::Store { op: opcode, offset: r.u32()? }
+				},
+				0x3f | 0x40 => {
+					if r.byte()? != 0x00 {
+						return Err("Invalid memory index");
+					}
+					if opcode == 0x3f {
+						Instr::MemorySize
+					} else {
+						Instr::MemoryGrow
+					}
+				},
+				0x41 => Instr::I32Const(r.i32()?),
+				0x42 => Instr::I64Const(r.i64()?),
+				0x45..=0x5a | 0x67..=0x78 | 0x79..=0x8a | 0xa7 | 0xac | 0xad => {
+					Instr::Numeric(opcode)
+				},
+				_ => return Err("Unsupported instruction"),
+			};
+			code.push(instr);
+		}
+
+		if !r.is_empty() {
+			return Err("Code after the end of the function");
+		}
+		Ok(Function { ty, locals, code })
+	}
+
+	/// Check that every referenced type and function exists.
+	fn check(&self) -> Result<(), Trap> {
+		let functions = (self.imports.len() + self.functions.len()) as u32;
+		let types = self.types.len() as u32;
+		if self.imports.iter().any(|import| import.ty >= types)
+			|| self.functions.iter().any(|function| function.ty >= types)
+		{
+			return Err("Unknown type");
+		}
+		if self.exports.values().any(|index| *index >= functions) {
+			return Err("Unknown function");
+		}
+		for function in &self.functions {
+			for instr in &function.code {
+				match instr {
+					Instr::Call(index) if *index >= functions => return Err("Unknown function"),
+					Instr::LocalGet(index) | Instr::LocalSet(index) | Instr::LocalTee(index)
+						if *index as usize
+							>= self.types[function.ty as usize].params.len()
+								+ function.locals.len() =>
+					{
+						return Err("Unknown local")
+					},
+					_ => {},
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+struct Label {
+	arity: usize,
+	height: usize,
+	/// Where execution continues when branching to this label.
+	target: usize,
+}
+
+/// An instantiated module with its own linear memory.
+pub struct Instance<'m> {
+	module: &'m Module,
+	memory: Vec<u8>,
+}
+
+impl<'m> Instance<'m> {
+	pub fn new(module: &'m Module) -> Result<Self, Trap> {
+		let mut memory = vec![0; module.memory_pages as usize * PAGE_SIZE];
+		for (offset, bytes) in &module.data {
+			let start = *offset as usize;
+			memory
+				.get_mut(start..start + bytes.len())
+				.ok_or("Data segment out of bounds")?
+				.copy_from_slice(bytes);
+		}
+		Ok(Self { module, memory })
+	}
+
+	pub fn memory(&self) -> &[u8] {
+		&self.memory
+	}
+
+	/// Call the exported function `name` with `args`.
+	pub fn invoke(
+		&mut self,
+		name: &str,
+		args: &[Value],
+		host: &mut impl Host,
+		gas: &mut Gas,
+	) -> Result<Option<Value>, Trap> {
+		let index = self.module.export(name).ok_or("Export not found")?;
+		self.call(index, args.to_vec(), host, gas, 0)
+	}
+
+	fn call(
+		&mut self,
+		index: u32,
+		args: Vec<Value>,
+		host: &mut impl Host,
+		gas: &mut Gas,
+		depth: usize,
+	) -> Result<Option<Value>, Trap> {
+		if depth >= MAX_CALL_DEPTH {
+			return Err("Call stack exhausted");
+		}
+		let module = self.module;
+		let imports = module.imports.len();
+		let ty = match (index as usize).checked_sub(imports) {
+			None => &module.types[module.imports[index as usize].ty as usize],
+			Some(local) => &module.types[module.functions[local].ty as usize],
+		};
+		if args.len() != ty.params.len() || args.iter().zip(&ty.params).any(|(a, p)| a.ty() != *p) {
+			return Err("Signature mismatch");
+		}
+
+		let result = match (index as usize).checked_sub(imports) {
+			None => {
+				let import = &module.imports[index as usize];
+				host.call(&import.name, &args, &mut self.memory, gas)?
+			},
+			Some(local) => self.execute(&module.functions[local], args, host, gas, depth)?,
+		};
+
+		match (result, ty.results.first()) {
+			(None, None) => Ok(None),
+			(Some(value), Some(ty)) if value.ty() == *ty => Ok(Some(value)),
+			_ => Err("Signature mismatch"),
+		}
+	}
+
+	fn execute(
+		&mut self,
+		function: &Function,
+		mut locals: Vec<Value>,
+		host: &mut impl Host,
+		gas: &mut Gas,
+		depth: usize,
+	) -> Result<Option<Value>, Trap> {
+		let module = self.module;
+		let result_arity = module.types[function.ty as usize].results.len();
+		locals.extend(function.locals.iter().map(|ty| Value::default_of(*ty)));
+
+		let mut stack: Vec<Value> = Vec::new();
+		let mut labels: Vec<Label> = Vec::new();
+		let mut pc = 0;
+
+		loop {
+			gas.charge(1)?;
+			let instr = function.code[pc];
+			pc += 1;
+			match instr {
+				Instr::Unreachable => return Err("Unreachable executed"),
+				Instr::Nop => {},
+				Instr::Block { arity, end } => {
+					labels.push(Label { arity, height: stack.len(), target: end + 1 })
+				},
+				Instr::Loop => labels.push(Label { arity: 0, height: stack.len(), target: pc - 1 }),
+				Instr::If { arity, else_, end } => {
+					let condition = pop_i32(&mut stack)?;
+					labels.push(Label { arity, height: stack.len(), target: end + 1 });
+					if condition == 0 {
+						pc = else_.map(|else_| else_ + 1).unwrap_or(end);
+					}
+				},
+				Instr::Else { end } => pc = end,
+				Instr::End => {
+					if labels.pop().is_none() {
+						return take_result(&mut stack, result_arity);
+					}
+				},
+				Instr::Br(depth) => {
+					if !branch(&mut stack, &mut labels, depth, &mut pc)? {
+						return take_result(&mut stack, result_arity);
+					}
+				},
+				Instr::BrIf(depth) => {
+					if pop_i32(&mut stack)? != 0
+						&& !branch(&mut stack, &mut labels, depth, &mut pc)?
+					{
+						return take_result(&mut stack, result_arity);
+					}
+				},
+				Instr::Return => return take_result(&mut stack, result_arity),
+				Instr::Call(index) => {
+					let ty = match (index as usize).checked_sub(module.imports.len()) {
+						None => module.imports[index as usize].ty,
+						Some(local) => module.functions[local].ty,
+					};
+					let params = module.types[ty as usize].params.len();
+					let args =
+						stack.split_off(stack.len().checked_sub(params).ok_or("Stack underflow")?);
+					if let Some(value) = self.call(index, args, host, gas, depth + 1)? {
+						stack.push(value);
+					}
+				},
+				Instr::Drop => {
+					pop(&mut stack)?;
+				},
+				Instr::Select => {
+					let condition = pop_i32(&mut stack)?;
+					let second = pop(&mut stack)?;
+					let first = pop(&mut stack)?;
+					stack.push(if condition != 0 { first } else { second });
+				},
+				Instr::LocalGet(index) => stack.push(locals[index as usize]),
+				Instr::LocalSet(index) => locals[index as usize] = pop(&mut stack)?,
+				Instr::LocalTee(index) => {
+					locals[index as usize] = *stack.last().ok_or("Stack underflow")?
+				},
+				Instr::Load { op, offset } => {
+					let address = effective_address(pop_i32(&mut stack)?, offset)?;
+					stack.push(load(&self.memory, op, address)?);
+				},
+				Instr::Store { op, offset } => {
+					let value = pop(&mut stack)?;
+					let address = effective_address(pop_i32(&mut stack)?, offset)?;
+					store(&mut self.memory, op, address, value)?;
+				},
+				Instr::MemorySize => stack.push(Value::I32((self.memory.len() / PAGE_SIZE) as i32)),
+				Instr::MemoryGrow => {
+					let delta = pop_i32(&mut stack)? as u32;
+					let pages = (self.memory.len() / PAGE_SIZE) as u32;
+					match pages.checked_add(delta).filter(|new| *new <= MAX_PAGES) {
+						Some(new) => {
+							self.memory.resize(new as usize * PAGE_SIZE, 0);
+							stack.push(Value::I32(pages as i32));
+						},
+						None => stack.push(Value::I32(-1)),
+					}
+				},
+				Instr::I32Const(value) => stack.push(Value::I32(value)),
+				Instr::I64Const(value) => stack.push(Value::I64(value)),
+				Instr::Numeric(op) => numeric(&mut stack, op)?,
+			}
+		}
+	}
+}
+
+/// Branch to the label `depth` levels up, returning `false` when that is the function itself.
+fn branch(
+	stack: &mut Vec<Value>,
+	labels: &mut Vec<Label>,
+	depth: u32,
+	pc: &mut usize,
+) -> Result<bool, Trap> {
+	let depth = depth as usize;
+	if depth == labels.len() {
+		return Ok(false);
+	}
+	let position = labels.len().checked_sub(depth + 1).ok_or("Unknown label")?;
+	let label = labels.drain(position..).next().ok_or("Unknown label")?;
+
+	let values = stack.split_off(stack.len().checked_sub(label.arity).ok_or("Stack underflow")?);
+	stack.truncate(label.height);
+	stack.extend(values);
+	*pc = label.target;
+	Ok(true)
+}
+
+fn take_result(stack: &mut Vec<Value>, arity: usize) -> Result<Option<Value>, Trap> {
+	if arity == 0 {
+		return Ok(None);
+	}
+	pop(stack).map(Some)
+}
+
+fn pop(stack: &mut Vec<Value>) -> Result<Value, Trap> {
+	stack.pop().ok_or("Stack underflow")
+}
+
+fn pop_i32(stack: &mut Vec<Value>) -> Result<i32, Trap> {
+	match pop(stack)? {
+		Value::I32(value) => Ok(value),
+		Value::I64(_) => Err("Type mismatch"),
+	}
+}
+
+fn pop_i64(stack: &mut Vec<Value>) -> Result<i64, Trap> {
+	match pop(stack)? {
+		Value::I64(value) => Ok(value),
+		Value::I32(_) => Err("Type mismatch"),
+	}
+}
+
+fn effective_address(base: i32, offset: u32) -> Result<usize, Trap> {
+	(base as u32)
+		.checked_add(offset)
+		.map(|address| address as usize)
+		.ok_or("Out of bounds memory access")
+}
+
+fn load(memory: &[u8], op: u8, address: usize) -> Result<Value, Trap> {
+	let width = match op {
+		0x28 | 0x34 | 0x35 => 4,
+		0x29 => 8,
+		0x2c | 0x2d | 0x30 | 0x31 => 1,
+		_ => 2,
+	};
+	let bytes = memory
+		.get(address..address.checked_add(width).ok_or("Out of bounds memory access")?)
+		.ok_or("Out of bounds memory access")?;
+	let mut raw = [0u8; 8];
+	raw[..width].copy_from_slice(bytes);
+	let unsigned = u64::from_le_bytes(raw);
+	let signed = ((unsigned << (64 - width * 8)) as i64) >> (64 - width * 8);
+
+	Ok(match op {
+		0x28 => Value::I32(unsigned as i32),
+		0x29 => Value::I64(unsigned as i64),
+		0x2c | 0x2e => Value::I32(signed as i32),
+		0x2d | 0x2f => Value::I32(unsigned as i32),
+		0x30 | 0x32 | 0x34 => Value::I64(signed),
+		_ => Value::I64(unsigned as i64),
+	})
+}
+
+fn store(memory: &mut [u8], op: u8, address: usize, value: Value) -> Result<(), Trap> {
+	let (width, raw) = match (op, value) {
+		(0x36, Value::I32(v)) => (4, v as u32 as u64),
+		(0x3a, Value::I32(v)) => (1, v as u32 as u64),
+		(0x3b, Value::I32(v)) => (2, v as u32 as u64),
+		(0x37, Value::I64(v)) => (8, v as u64),
+		(0x3c, Value::I64(v)) => (1, v as u64),
+		(0x3d, Value::I64(v)) => (2, v as u64),
+		(0x3e, Value::I64(v)) => (4, v as u64),
+		_ => return Err("Type mismatch"),
+	};
+	memory
+		.get_mut(address..address.checked_add(width).ok_or("Out of bounds memory access")?)
+		.ok_or("Out of bounds memory access")?
+		.copy_from_slice(&raw.to_le_bytes()[..width]);
+	Ok(())
+}
+
+fn numeric(stack: &mut Vec<Value>, op: u8) -> Result<(), Trap> {
+	let value = match op {
+		0x45 => Value::I32((pop_i32(stack)? == 0) as i32),
+		0x46..=0x4f => {
+			let b = pop_i32(stack)?;
+			let a = pop_i32(stack)?;
+			Value::I32(compare(op - 0x46, a as i64, b as i64, a as u32 as u64, b as u32 as u64))
+		},
+		0x50 => Value::I32((pop_i64(stack)? == 0) as i32),
+		0x51..=0x5a => {
+			let b = pop_i64(stack)?;
+			let a = pop_i64(stack)?;
+			Value::I32(compare(op - 0x51, a, b, a as u64, b as u64))
+		},
+		0x67 => Value::I32(pop_i32(stack)?.leading_zeros() as i32),
+		0x68 => Value::I32(pop_i32(stack)?.trailing_zeros() as i32),
+		0x69 => Value::I32(pop_i32(stack)?.count_ones() as i32),
+		0x6a..=0x78 => {
+			let b = pop_i32(stack)?;
+			let a = pop_i32(stack)?;
+			Value::I32(binary_i32(op, a, b)?)
+		},
+		0x79 => Value::I64(pop_i64(stack)?.leading_zeros() as i64),
+		0x7a => Value::I64(pop_i64(stack)?.trailing_zeros() as i64),
+		0x7b => Value::I64(pop_i64(stack)?.count_ones() as i64),
+		0x7c..=0x8a => {
+			let b = pop_i64(stack)?;
+			let a = pop_i64(stack)?;
+			Value::I64(binary_i64(op, a, b)?)
+		},
+		0xa7 => Value::I32(pop_i64(stack)? as i32),
+		0xac => Value::I64(pop_i32(stack)? as i64),
+		0xad => Value::I64(pop_i32(stack)? as u32 as i64),
+		_ => return Err("Unsupported instruction"),
+	};
+	stack.push(value);
+	Ok(())
+}
+
+/// Comparisons share their order between `i32` and `i64`: eq, ne, lt_s, lt_u, gt_s, gt_u,
+/// le_s, le_u, ge_s, ge_u.
+fn compare(op: u8, a: i64, b: i64, ua: u64, ub: u64) -> i32 {
+	let result = match op {
+		0 => a == b,
+		1 => a != b,
+		2 => a < b,
+		3 => ua < ub,
+		4 => a > b,
+		5 => ua > ub,
+		6 => a <= b,
+		7 => ua <= ub,
+		8 => a >= b,
+		_ => ua >= ub,
+	};
+	result as i32
+}
+
+fn binary_i32(op: u8, a: i32, b: i32) -> Result<i32, Trap> {
+	let (ua, ub) = (a as u32, b as u32);
+	Ok(match op {
+		0x6a => a.wrapping_add(b),
+		0x6b => a.wrapping_sub(b),
+		0x6c => a.wrapping_mul(b),
+		0x6d => {
+			if b == 0 {
+				return Err("Integer divide by zero");
+			}
+			a.checked_div(b).ok_or("Integer overflow")?
+		},
+		0x6e => ua.checked_div(ub).ok_or("Integer divide by zero")? as i32,
+		0x6f => {
+			if b == 0 {
+				return Err("Integer divide by zero");
+			}
+			a.wrapping_rem(b)
+		},
+		0x70 => ua.checked_rem(ub).ok_or("Integer divide by zero")? as i32,
+		0x71 => a & b,
+		0x72 => a | b,
+		0x73 => a ^ b,
+		0x74 => a.wrapping_shl(ub),
+		0x75 => a.wrapping_shr(ub),
+		0x76 => ua.wrapping_shr(ub) as i32,
+		0x77 => ua.rotate_left(ub % 32) as i32,
+		_ => ua.rotate_right(ub % 32) as i32,
+	})
+}
+
+fn binary_i64(op: u8, a: i64, b: i64) -> Result<i64, Trap> {
+	let (ua, ub) = (a as u64, b as u64);
+	Ok(match op {
+		0x7c => a.wrapping_add(b),
+		0x7d => a.wrapping_sub(b),
+		0x7e => a.wrapping_mul(b),
+		0x7f => {
+			if b == 0 {
+				return Err("Integer divide by zero");
+			}
+			a.checked_div(b).ok_or("Integer overflow")?
+		},
+		0x80 => ua.checked_div(ub).ok_or("Integer divide by zero")? as i64,
+		0x81 => {
+			if b == 0 {
+				return Err("Integer divide by zero");
+			}
+			a.wrapping_rem(b)
+		},
+		0x82 => ua.checked_rem(ub).ok_or("Integer divide by zero")? as i64,
+		0x83 => a & b,
+		0x84 => a | b,
+		0x85 => a ^ b,
+		0x86 => a.wrapping_shl(ub as u32),
+		0x87 => a.wrapping_shr(ub as u32),
+		0x88 => ua.wrapping_shr(ub as u32) as i64,
+		0x89 => ua.rotate_left((ub % 64) as u32) as i64,
+		_ => ua.rotate_right((ub % 64) as u32) as i64,
+	})
+}
+
+/// Helpers to assemble modules by hand in tests.
+#[cfg(test)]
+pub(crate) mod builder {
+	pub fn leb(mut value: u64) -> Vec<u8> {
+		let mut out = Vec::new();
+		loop {
+			let byte = (value & 0x7f) as u8;
+			value >>= 7;
+			if value == 0 {
+				out.push(byte);
+				return out;
+			}
+			out.push(byte | 0x80);
+		}
+	}
+
+	fn vec(items: &[Vec<u8>]) -> Vec<u8> {
+		let mut out = leb(items.len() as u64);
+		items.iter().for_each(|item| out.extend(item));
+		out
+	}
+
+	fn name(name: &str) -> Vec<u8> {
+		let mut out = leb(name.len() as u64);
+		out.extend(name.as_bytes());
+		out
+	}
+
+	fn section(id: u8, payload: Vec<u8>) -> Vec<u8> {
+		let mut out = vec![id];
+		out.extend(leb(payload.len() as u64));
+		out.extend(payload);
+		out
+	}
+
+	/// A module with one page of memory. `types` are `(params, results)` as raw value types,
+	/// `imports` are host functions `(name, type)` and `functions` are `(type, locals, code)`.
+	pub fn module(
+		types: &[(&[u8], &[u8])],
+		imports: &[(&str, u32)],
+		functions: &[(u32, &[u8], &[u8])],
+		exports: &[(&str, u32)],
+		data: &[(u32, &[u8])],
+	) -> Vec<u8> {
+		let mut out = b"\0asm\x01\0\0\0".to_vec();
+		let types: Vec<_> = types
+			.iter()
+			.map(|(params, results)| {
+				let mut ty = vec![0x60];
+				ty.extend(leb(params.len() as u64));
+				ty.extend(*params);
+				ty.extend(leb(results.len() as u64));
+				ty.extend(*results);
+				ty
+			})
+			.collect();
+		out.extend(section(1, vec(&types)));
+
+		let imports: Vec<_> = imports
+			.iter()
+			.map(|(field, ty)| [name("env"), name(field), vec![0x00], leb(*ty as u64)].concat())
+			.collect();
+		out.extend(section(2, vec(&imports)));
+
+		let declared: Vec<_> = functions.iter().map(|(ty, _, _)| leb(*ty as u64)).collect();
+		out.extend(section(3, vec(&declared)));
+		out.extend(section(5, vec(&[vec![0x00, 0x01]])));
+
+		let exports: Vec<_> = exports
+			.iter()
+			.map(|(field, index)| [name(field), vec![0x00], leb(*index as u64)].concat())
+			.collect();
+		out.extend(section(7, vec(&exports)));
+
+		let bodies: Vec<_> = functions
+			.iter()
+			.map(|(_, locals, code)| {
+				let locals: Vec<_> = locals.iter().map(|ty| vec![0x01, *ty]).collect();
+				let body = [vec(&locals), code.to_vec()].concat();
+				[leb(body.len() as u64), body].concat()
+			})
+			.collect();
+		out.extend(section(10, vec(&bodies)));
+
+		let data: Vec<_> = data
+			.iter()
+			.map(|(offset, bytes)| {
+				[vec![0x00, 0x41], leb(*offset as u64), vec![0x0b], name_bytes(bytes)].concat()
+			})
+			.collect();
+		out.extend(section(11, vec(&data)));
+		out
+	}
+
+	fn name_bytes(bytes: &[u8]) -> Vec<u8> {
+		let mut out = leb(bytes.len() as u64);
+		out.extend(bytes);
+		out
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{builder::module, Gas, Host, Instance, Module, Trap, Value};
+
+	const I32: u8 = 0x7f;
+
+	struct NoHost;
+
+	impl Host for NoHost {
+		fn call(
+			&mut self,
+			_: &str,
+			_: &[Value],
+			_: &mut [u8],
+			_: &mut Gas,
+		) -> Result<Option<Value>, Trap> {
+			Err("No host functions")
+		}
+	}
+
+	/// `factorial(n)`, computed in a loop.
+	fn factorial() -> Vec<u8> {
+		#[rustfmt::skip]
+		let code = [
+			0x41, 0x01, 0x21, 0x01, // acc = 1
+			0x02, 0x40, // block
+			0x03, 0x40, // loop
+			0x20, 0x00, 0x45, 0x0d, 0x01, // br_if 1 (n == 0)
+			0x20, 0x01, 0x20, 0x00, 0x6c, 0x21, 0x01, // acc *= n
+			0x20, 0x00, 0x41, 0x01, 0x6b, 0x21, 0x00, // n -= 1
+			0x0c, 0x00, // br 0
+			0x0b, 0x0b, // end loop, end block
+			0x20, 0x01, 0x0b, // acc
+		];
+		module(&[(&[I32], &[I32])], &[], &[(0, &[I32], &code)], &[("factorial", 0)], &[])
+	}
+
+	#[test]
+	fn executes_loops_and_arithmetic() {
+		let module = Module::parse(&factorial()).unwrap();
+		let mut instance = Instance::new(&module).unwrap();
+		let mut gas = Gas(10_000);
+
+		let result = instance.invoke("factorial", &[Value::I32(5)], &mut NoHost, &mut gas);
+		assert_eq!(result, Ok(Some(Value::I32(120))));
+		assert_eq!(
+			instance.invoke("factorial", &[Value::I64(5)], &mut NoHost, &mut gas),
+			Err("Signature mismatch")
+		);
+		assert_eq!(instance.invoke("missing", &[], &mut NoHost, &mut gas), Err("Export not found"));
+	}
+
+	#[test]
+	fn execution_is_metered() {
+		let module = Module::parse(&factorial()).unwrap();
+		let mut instance = Instance::new(&module).unwrap();
+
+		let mut gas = Gas(50);
+		let result = instance.invoke("factorial", &[Value::I32(1_000)], &mut NoHost, &mut gas);
+		assert_eq!(result, Err("Out of gas"));
+		assert_eq!(gas, Gas(0));
+	}
+
+	#[test]
+	fn memory_and_if_else() {
+		#[rustfmt::skip]
+		let code = [
+			0x20, 0x00, // n
+			0x04, I32, // if (result i32)
+			0x41, 0x00, 0x28, 0x02, 0x00, // i32.load offset=0
+			0x05, // else
+			0x41, 0x00, 0x41, 0x2a, 0x36, 0x02, 0x04, // i32.store offset=4 42
+			0x41, 0x04, 0x2d, 0x00, 0x00, // i32.load8_u
+			0x0b, 0x0b,
+		];
+		let bytes =
+			module(&[(&[I32], &[I32])], &[], &[(0, &[], &code)], &[("run", 0)], &[(0, &[7, 1])]);
+		let module = Module::parse(&bytes).unwrap();
+		let mut instance = Instance::new(&module).unwrap();
+		let mut gas = Gas(1_000);
+
+		assert_eq!(
+			instance.invoke("run", &[Value::I32(1)], &mut NoHost, &mut gas),
+			Ok(Some(Value::I32(263)))
+		);
+		assert_eq!(
+			instance.invoke("run", &[Value::I32(0)], &mut NoHost, &mut gas),
+			Ok(Some(Value::I32(42)))
+		);
+		assert_eq!(&instance.memory()[4..8], &[42, 0, 0, 0]);
+	}
+
+	#[test]
+	fn rejects_unsupported_modules() {
+		assert_eq!(Module::parse(b"not wasm").map(|_| ()), Err("Not a wasm module"));
+
+		// f32.const
+		let bytes = module(&[(&[], &[])], &[], &[(0, &[], &[0x43, 0, 0, 0, 0, 0x0b])], &[], &[]);
+		assert_eq!(Module::parse(&bytes).map(|_| ()), Err("Unsupported instruction"));
+
+		let bytes = module(&[(&[], &[])], &[], &[(0, &[], &[0x10, 0x05, 0x0b])], &[], &[]);
+		assert_eq!(Module::parse(&bytes).map(|_| ()), Err("Unknown function"));
+
+		let trap = module(&[(&[], &[])], &[], &[(0, &[], &[0x00, 0x0b])], &[("run", 0)], &[]);
+		let module = Module::parse(&trap).unwrap();
+		let mut instance = Instance::new(&module).unwrap();
+		assert_eq!(
+			instance.invoke("run", &[], &mut NoHost, &mut Gas(10)),
+			Err("Unreachable executed")
+		);
+	}
+}
@@ -0,0 +1,409 @@
+//! The primitives the keystore encrypts keys with: scrypt (RFC 7914) to derive a key from a
+//! password, and AES-256 in Galois/Counter Mode (NIST SP 800-38D) to encrypt and authenticate
+//! with it. PBKDF2-HMAC-SHA512 turns mnemonic phrases into seeds, see the `mnemonic` module.
+
+use std::{fs, io, io::Read};
+
+use crate::hashing::{self, Hash};
+
+/// `N` bytes from the randomness of the operating system.
+pub fn random_bytes<const N: usize>() -> io::Result<[u8; N]> {
+	let mut bytes = [0; N];
+	fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+	Ok(bytes)
+}
+
+const SHA256_BLOCK_LEN: usize = 64;
+
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Hash {
+	let mut padded = [0u8; SHA256_BLOCK_LEN];
+	match key.len() > SHA256_BLOCK_LEN {
+		true => padded[..32].copy_from_slice(&hashing::sha256(key)),
+		false => padded[..key.len()].copy_from_slice(key),
+	}
+	let mut inner: Vec<u8> = padded.iter().map(|byte| byte ^ 0x36).collect();
+	inner.extend_from_slice(data);
+	let mut outer: Vec<u8> = padded.iter().map(|byte| byte ^ 0x5c).collect();
+	outer.extend_from_slice(&hashing::sha256(&inner));
+	hashing::sha256(&outer)
+}
+
+/// Fill `out` with the PBKDF2-HMAC-SHA256 of `password` and `salt` (RFC 8018).
+pub fn pbkdf2_sha256(password: &[u8], salt: &[u8], rounds: u32, out: &mut [u8]) {
+	for (i, chunk) in out.chunks_mut(32).enumerate() {
+		let mut input = salt.to_vec();
+		input.extend_from_slice(&(i as u32 + 1).to_be_bytes());
+		let mut u = hmac_sha256(password, &input);
+		let mut t = u;
+		for _ in 1..rounds {
+			u = hmac_sha256(password, &u);
+			t.iter_mut().zip(u).for_each(|(t, u)| *t ^= u);
+		}
+		chunk.copy_from_slice(&t[..chunk.len()]);
+	}
+}
+
+const SHA512_BLOCK_LEN: usize = 128;
+
+pub fn hmac_sha512(key: &[u8], data: &[u8]) -> [u8; 64] {
+	let mut padded = [0u8; SHA512_BLOCK_LEN];
+	match key.len() > SHA512_BLOCK_LEN {
+		true => padded[..64].copy_from_slice(&hashing::sha512(key)),
+		false => padded[..key.len()].copy_from_slice(key),
+	}
+	let mut inner: Vec<u8> = padded.iter().map(|byte| byte ^ 0x36).collect();
+	inner.extend_from_slice(data);
+	let mut outer: Vec<u8> = padded.iter().map(|byte| byte ^ 0x5c).collect();
+	outer.extend_from_slice(&hashing::sha512(&inner));
+	hashing::sha512(&outer)
+}
+
+/// Fill `out` with the PBKDF2-HMAC-SHA512 of `password` and `salt` (RFC 8018).
+pub fn pbkdf2_sha512(password: &[u8], salt: &[u8], rounds: u32, out: &mut [u8]) {
+	for (i, chunk) in out.chunks_mut(64).enumerate() {
+		let mut input = salt.to_vec();
+		input.extend_from_slice(&(i as u32 + 1).to_be_bytes());
+		let mut u = hmac_sha512(password, &input);
+		let mut t = u;
+		for _ in 1..rounds {
+			u = hmac_sha512(password, &u);
+			t.iter_mut().zip(u).for_each(|(t, u)| *t ^= u);
+		}
+		chunk.copy_from_slice(&t[..chunk.len()]);
+	}
+}
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+	x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+	x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+	x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+	x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+}
+
+fn salsa20_8(block: &mut [u32; 16]) {
+	let mut x = *block;
+	for _ in 0..4 {
+		quarter_round(&mut x, 0, 4, 8, 12);
+		quarter_round(&mut x, 5, 9, 13, 1);
+		quarter_round(&mut x, 10, 14, 2, 6);
+		quarter_round(&mut x, 15, 3, 7, 11);
+		quarter_round(&mut x, 0, 1, 2, 3);
+		quarter_round(&mut x, 5, 6, 7, 4);
+		quarter_round(&mut x, 10, 11, 8, 9);
+		quarter_round(&mut x, 15, 12, 13, 14);
+	}
+	block.iter_mut().zip(x).for_each(|(word, x)| *word = word.wrapping_add(x));
+}
+
+/// Mix the `2 * r` blocks of 16 words in `b`.
+fn block_mix(b: &[u32]) -> Vec<u32> {
+	let blocks = b.len() / 16;
+	let mut x: [u32; 16] = b[b.len() - 16..].try_into().expect("16 words; qed");
+	let mut y = vec![0; b.len()];
+	for i in 0..blocks {
+		x.iter_mut().zip(&b[i * 16..i * 16 + 16]).for_each(|(x, b)| *x ^= b);
+		salsa20_8(&mut x);
+		// The even blocks go to the first half of the output, the odd ones to the second.
+		let position = (i / 2 + (i % 2) * blocks / 2) * 16;
+		y[position..position + 16].copy_from_slice(&x);
+	}
+	y
+}
+
+fn ro_mix(block: &mut [u8], n: usize) {
+	let mut x: Vec<u32> = block
+		.chunks(4)
+		.map(|word| u32::from_le_bytes(word.try_into().expect("4 bytes; qed")))
+		.collect();
+	let mut v = Vec::with_capacity(n);
+	for _ in 0..n {
+		let next = block_mix(&x);
+		v.push(std::mem::replace(&mut x, next));
+	}
+	for _ in 0..n {
+		let j = x[x.len() - 16] as usize % n;
+		x.iter_mut().zip(&v[j]).for_each(|(x, v)| *x ^= v);
+		x = block_mix(&x);
+	}
+	for (bytes, word) in block.chunks_mut(4).zip(x) {
+		bytes.copy_from_slice(&word.to_le_bytes());
+	}
+}
+
+/// Fill `out` with the scrypt key of `password` and `salt`, with a cost of `2^log_n`, a block
+/// size of `r` and a parallelism of `p`. Needs `128 * r * 2^log_n` bytes of memory.
+pub fn scrypt(password: &[u8], salt: &[u8], log_n: u8, r: u32, p: u32, out: &mut [u8]) {
+	let block_len = 128 * r as usize;
+	let mut b = vec![0; block_len * p as usize];
+	pbkdf2_sha256(password, salt, 1, &mut b);
+	for block in b.chunks_mut(block_len) {
+		ro_mix(block, 1 << log_n);
+	}
+	pbkdf2_sha256(password, &b, 1, out);
+}
+
+const SBOX: [u8; 256] = [
+	0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+	0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+	0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+	0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+	0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+	0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+	0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+	0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+	0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+	0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+	0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+	0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+	0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+	0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+	0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+	0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+fn xtime(byte: u8) -> u8 {
+	(byte << 1) ^ if byte & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+/// The AES block cipher with a 256-bit key (FIPS 197).
+pub struct Aes256 {
+	round_keys: [[u8; 16]; 15],
+}
+
+impl Aes256 {
+	pub fn new(key: &[u8; 32]) -> Self {
+		let mut words = [[0u8; 4]; 60];
+		for (i, word) in words.iter_mut().take(8).enumerate() {
+			word.copy_from_slice(&key[i * 4..i * 4 + 4]);
+		}
+		let mut rcon = 1;
+		for i in 8..60 {
+			let mut temp = words[i - 1];
+			if i % 8 == 0 {
+				temp.rotate_left(1);
+				temp = temp.map(|byte| SBOX[byte as usize]);
+				temp[0] ^= rcon;
+				rcon = xtime(rcon);
+			} else if i % 8 == 4 {
+				temp = temp.map(|byte| SBOX[byte as usize]);
+			}
+			for j in 0..4 {
+				words[i][j] = words[i - 8][j] ^ temp[j];
+			}
+		}
+
+		let mut round_keys = [[0u8; 16]; 15];
+		for (round, key) in round_keys.iter_mut().enumerate() {
+			for column in 0..4 {
+				key[column * 4..column * 4 + 4].copy_from_slice(&words[round * 4 + column]);
+			}
+		}
+		Self { round_keys }
+	}
+
+	pub fn encrypt_block(&self, block: &mut [u8; 16]) {
+		let add_round_key = |block: &mut [u8; 16], key: &[u8; 16]| {
+			block.iter_mut().zip(key).for_each(|(byte, key)| *byte ^= key);
+		};
+		add_round_key(block, &self.round_keys[0]);
+		for round in 1..15 {
+			// SubBytes and ShiftRows: the byte of row `r` moves `r` columns to the left.
+			let state = *block;
+			for column in 0..4 {
+				for row in 0..4 {
+					block[row + 4 * column] = SBOX[state[row + 4 * ((column + row) % 4)] as usize];
+				}
+			}
+			if round < 14 {
+				for column in block.chunks_mut(4) {
+					let [a0, a1, a2, a3] = [column[0], column[1], column[2], column[3]];
+					let all = a0 ^ a1 ^ a2 ^ a3;
+					column[0] ^= all ^ xtime(a0 ^ a1);
+					column[1] ^= all ^ xtime(a1 ^ a2);
+					column[2] ^= all ^ xtime(a2 ^ a3);
+					column[3] ^= all ^ xtime(a3 ^ a0);
+				}
+			}
+			add_round_key(block, &self.round_keys[round]);
+		}
+	}
+}
+
+/// The length of the authentication tag appended to the ciphertext.
+pub const TAG_LEN: usize = 16;
+
+/// Multiply in GF(2^128) with the bit order of GCM.
+fn gf_mul(x: u128, y: u128) -> u128 {
+	let (mut z, mut v) = (0, y);
+	for i in 0..128 {
+		if (x >> (127 - i)) & 1 == 1 {
+			z ^= v;
+		}
+		v = match v & 1 {
+			1 => (v >> 1) ^ (0xe1 << 120),
+			_ => v >> 1,
+		};
+	}
+	z
+}
+
+fn ghash(h: u128, aad: &[u8], ciphertext: &[u8]) -> u128 {
+	let mut x = 0;
+	for data in [aad, ciphertext] {
+		for chunk in data.chunks(16) {
+			let mut block = [0u8; 16];
+			block[..chunk.len()].copy_from_slice(chunk);
+			x = gf_mul(x ^ u128::from_be_bytes(block), h);
+		}
+	}
+	let lengths = ((aad.len() as u128 * 8) << 64) | (ciphertext.len() as u128 * 8);
+	gf_mul(x ^ lengths, h)
+}
+
+/// XOR `data` with the key stream of the counter blocks following `j0`.
+fn ctr(cipher: &Aes256, j0: [u8; 16], data: &mut [u8]) {
+	let mut counter = u32::from_be_bytes(j0[12..].try_into().expect("4 bytes; qed"));
+	for chunk in data.chunks_mut(16) {
+		counter = counter.wrapping_add(1);
+		let mut block = j0;
+		block[12..].copy_from_slice(&counter.to_be_bytes());
+		cipher.encrypt_block(&mut block);
+		chunk.iter_mut().zip(block).for_each(|(byte, key)| *byte ^= key);
+	}
+}
+
+fn tag(cipher: &Aes256, j0: [u8; 16], aad: &[u8], ciphertext: &[u8]) -> [u8; TAG_LEN] {
+	let mut h = [0u8; 16];
+	cipher.encrypt_block(&mut h);
+	let mut mask = j0;
+	cipher.encrypt_block(&mut mask);
+	(ghash(u128::from_be_bytes(h), aad, ciphertext) ^ u128::from_be_bytes(mask)).to_be_bytes()
+}
+
+fn j0(nonce: &[u8; 12]) -> [u8; 16] {
+	let mut j0 = [0u8; 16];
+	j0[..12].copy_from_slice(nonce);
+	j0[15] = 1;
+	j0
+}
+
+/// Encrypt `plaintext` and authenticate it together with `aad`. Returns the ciphertext followed
+/// by the tag. A nonce must never be used twice with the same key.
+pub fn aes256_gcm_encrypt(
+	key: &[u8; 32],
+	nonce: &[u8; 12],
+	plaintext: &[u8],
+	aad: &[u8],
+) -> Vec<u8> {
+	let cipher = Aes256::new(key);
+	let mut sealed = plaintext.to_vec();
+	ctr(&cipher, j0(nonce), &mut sealed);
+	let tag = tag(&cipher, j0(nonce), aad, &sealed);
+	sealed.extend_from_slice(&tag);
+	sealed
+}
+
+/// The plaintext of what `aes256_gcm_encrypt` returned, if it wasn't tampered with and `key` is
+/// the right one.
+pub fn aes256_gcm_decrypt(
+	key: &[u8; 32],
+	nonce: &[u8; 12],
+	sealed: &[u8],
+	aad: &[u8],
+) -> Result<Vec<u8>, &'static str> {
+	if sealed.len() < TAG_LEN {
+		return Err("Ciphertext too short");
+	}
+	let (ciphertext, expected) = sealed.split_at(sealed.len() - TAG_LEN);
+	let cipher = Aes256::new(key);
+	let tag = tag(&cipher, j0(nonce), aad, ciphertext);
+	// Compare every byte, so the time taken doesn't tell how much of the tag was right.
+	if tag.iter().zip(expected).fold(0, |diff, (a, b)| diff | (a ^ b)) != 0 {
+		return Err("Authentication failed");
+	}
+	let mut plaintext = ciphertext.to_vec();
+	ctr(&cipher, j0(nonce), &mut plaintext);
+	Ok(plaintext)
+}
+
+#[cfg(test)]
+mod test {
+	use crate::hashing::hex;
+
+	#[test]
+	fn hmac_and_pbkdf2_test_vectors() {
+		let mac = super::hmac_sha256(b"key", b"The quick brown fox jumps over the lazy dog");
+		assert_eq!(hex(&mac), "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8");
+		let long_key = super::hmac_sha256(&[b'k'; 100], b"data");
+		assert_eq!(
+			hex(&long_key),
+			"09380ee4b802da2363bc96e8e0d133ba275458ea8ddbc564f986fc12b31f8cb1"
+		);
+		let mut key = [0u8; 40];
+		super::pbkdf2_sha256(b"password", b"salt", 2, &mut key);
+		assert_eq!(
+			hex(&key),
+			"ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43830651afcb5c862f"
+		);
+	}
+
+	#[test]
+	fn sha512_hmac_and_pbkdf2_test_vectors() {
+		let mac = super::hmac_sha512(b"key", b"The quick brown fox jumps over the lazy dog");
+		assert_eq!(
+			hex(&mac),
+			"b42af09057bac1e2d41708e48a902e09b5ff7f12ab428a4fe86653c73dd248fb\
+			 82f948a549f7b791a5b41915ee4d1ec3935357e4e2317250d0372afa2ebeeb3a"
+		);
+		let mut key = [0u8; 80];
+		super::pbkdf2_sha512(b"password", b"salt", 2, &mut key);
+		assert_eq!(
+			hex(&key),
+			"e1d9c16aa681708a45f5c7c4e215ceb66e011a2e9f0040713f18aefdb866d53c\
+			 f76cab2868a39b9f7840edce4fef5a82be67335c77a6068e04112754f27ccf4e\
+			 473e311ad827b68945f4e2dddb204c78"
+		);
+	}
+
+	#[test]
+	fn scrypt_test_vectors() {
+		let mut key = [0u8; 64];
+		super::scrypt(b"", b"", 4, 1, 1, &mut key);
+		assert_eq!(
+			hex(&key),
+			"77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442\
+			 fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906"
+		);
+		super::scrypt(b"password", b"NaCl", 10, 8, 16, &mut key);
+		assert_eq!(
+			hex(&key),
+			"fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162\
+			 2eaf30d92e22a3886ff109279d9830dac727afb94a83ee6d8360cbdfa2cc0640"
+		);
+	}
+
+	#[test]
+	fn aes_gcm_test_vectors() {
+		let key: [u8; 32] = core::array::from_fn(|i| i as u8);
+		let mut block = 0x00112233445566778899aabbccddeeffu128.to_be_bytes();
+		super::Aes256::new(&key).encrypt_block(&mut block);
+		assert_eq!(hex(&block), "8ea2b7ca516745bfeafc49904b496089");
+
+		assert_eq!(
+			hex(&super::aes256_gcm_encrypt(&[0; 32], &[0; 12], b"", b"")),
+			"530f8afbc74536b9a963b4f1c4cb738b"
+		);
+		let nonce: [u8; 12] = core::array::from_fn(|i| i as u8);
+		let plaintext = b"The secret seed of an account, 40 bytes!";
+		let sealed = super::aes256_gcm_encrypt(&key, &nonce, plaintext, b"header");
+		assert_eq!(
+			hex(&sealed),
+			"136ab33bb680a169e835b7f8d48c1c4decb0a7559e5b3e1f5b0890eb69452086\
+			 3130cc85dba461b9b2c1a505d5316066cf0e45f9f67e9b44"
+		);
+		let opened = super::aes256_gcm_decrypt(&key, &nonce, &sealed, b"header");
+		assert_eq!(opened.as_deref(), Ok(&plaintext[..]));
+		let wrong_aad = super::aes256_gcm_decrypt(&key, &nonce, &sealed, b"other");
+		assert_eq!(wrong_aad, Err("Authentication failed"));
+	}
+}
@@ -0,0 +1,712 @@
+use core::fmt::Debug;
+use std::collections::BTreeMap;
+
+use num::{CheckedAdd, CheckedSub, Zero};
+
+use crate::{
+	balances::{self, LockIdentifier},
+	codec::{Decode, Encode},
+	hashing::{self, Hash},
+	metadata::{field, storage, variant, PalletMetadata, Registry, TypeDef, TypeInfo},
+	migrations::{self, StorageVersion},
+	support::{ensure_root, ensure_signed, DispatchResult, Weight},
+	system::OriginFor,
+	trie::{self, Storage},
+};
+
+const DEMOCRACY_ID: LockIdentifier = *b"democrac";
+
+pub type PropIndex = u32;
+pub type ReferendumIndex = u32;
+
+pub trait Config: balances::Config<RuntimeHoldReason: From<HoldReason>> {
+	/// The aggregated call type that proposals dispatch once enacted.
+	type RuntimeCall: Debug + Clone + Encode;
+	/// How often (in blocks) the most seconded proposal is turned into a referendum.
+	const LAUNCH_PERIOD: Self::BlockNumber;
+	/// How long (in blocks) a referendum is open for votes.
+	const VOTING_PERIOD: Self::BlockNumber;
+	/// The delay (in blocks) between a referendum passing and its call being dispatched.
+	const ENACTMENT_DELAY: Self::BlockNumber;
+	/// The minimum deposit needed to propose or second a proposal.
+	const MINIMUM_DEPOSIT: Self::Balance;
+	/// The maximum number of public proposals waiting to be launched.
+	const MAX_PROPOSALS: usize;
+
+	/// Check the origin of cancelling referenda, root by default.
+	fn ensure_cancel_origin(origin: OriginFor<Self>) -> DispatchResult {
+		ensure_root(origin)
+	}
+}
+
+/// Why democracy holds funds, see `balances::Pallet::hold`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HoldReason {
+	ProposalDeposit,
+}
+
+impl Encode for HoldReason {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			HoldReason::ProposalDeposit => 0u8.encode_to(dest),
+		}
+	}
+}
+
+impl Decode for HoldReason {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		match u8::decode(input)? {
+			0 => Ok(HoldReason::ProposalDeposit),
+			_ => Err("Invalid hold reason"),
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub enum Call<T: Config> {
+	Propose {
+		call: Box<T::RuntimeCall>,
+		deposit: T::Balance,
+	},
+	Second {
+		proposal: PropIndex,
+	},
+	Vote {
+		referendum: ReferendumIndex,
+		vote: Vote<T::Balance>,
+	},
+	/// Remove the voting lock of `target` for referenda that are over.
+	Unlock {
+		target: T::AccountId,
+	},
+	/// `ensure_cancel_origin` only: stop a referendum before it ends.
+	CancelReferendum {
+		referendum: ReferendumIndex,
+	},
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::Propose { call, deposit } => (0u8, call, deposit).encode_to(dest),
+			Call::Second { proposal } => (1u8, proposal).encode_to(dest),
+			Call::Vote { referendum, vote } => (2u8, referendum, vote).encode_to(dest),
+			Call::Unlock { target } => (3u8, target).encode_to(dest),
+			Call::CancelReferendum { referendum } => (4u8, referendum).encode_to(dest),
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T>
+where
+	T::RuntimeCall: Decode,
+{
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::Propose { call: Decode::decode(input)?, deposit: Decode::decode(input)? },
+			1 => Call::Second { proposal: Decode::decode(input)? },
+			2 => Call::Vote { referendum: Decode::decode(input)?, vote: Decode::decode(input)? },
+			3 => Call::Unlock { target: Decode::decode(input)? },
+			4 => Call::CancelReferendum { referendum: Decode::decode(input)? },
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::Propose { .. } => 0,
+			Call::Second { .. } => 1,
+			Call::Vote { .. } => 2,
+			Call::Unlock { .. } => 3,
+			Call::CancelReferendum { .. } => 4,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::Propose { .. } | Call::Vote { .. } => 10_000,
+			Call::Second { .. } | Call::Unlock { .. } | Call::CancelReferendum { .. } => 5_000,
+		}
+	}
+}
+
+/// A vote for or against a referendum, weighted by the balance locked behind it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vote<Balance> {
+	pub aye: bool,
+	pub balance: Balance,
+}
+
+impl<Balance: Encode> Encode for Vote<Balance> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(self.aye, &self.balance).encode_to(dest);
+	}
+}
+
+impl<Balance: Decode> Decode for Vote<Balance> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (aye, balance) = Decode::decode(input)?;
+		Ok(Vote { aye, balance })
+	}
+}
+
+impl<Balance: TypeInfo> TypeInfo for Vote<Balance> {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		TypeDef::Composite(vec![
+			registry.field::<bool>("aye"),
+			registry.field::<Balance>("balance"),
+		])
+	}
+}
+
+/// A proposal waiting to be launched, backed by the deposit of its proposer and seconders.
+#[derive(Debug, Clone)]
+pub struct Proposal<T: Config> {
+	pub index: PropIndex,
+	pub proposal_hash: Hash,
+	pub deposit: T::Balance,
+	/// The proposer followed by every seconder; an account can appear more than once.
+	pub backers: Vec<T::AccountId>,
+}
+
+impl<T: Config> Encode for Proposal<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(self.index, &self.proposal_hash, &self.deposit, &self.backers).encode_to(dest);
+	}
+}
+
+impl<T: Config> Decode for Proposal<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (index, proposal_hash, deposit, backers) = Decode::decode(input)?;
+		Ok(Proposal { index, proposal_hash, deposit, backers })
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tally<Balance> {
+	pub ayes: Balance,
+	pub nays: Balance,
+}
+
+impl<Balance: Encode> Encode for Tally<Balance> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.ayes, &self.nays).encode_to(dest);
+	}
+}
+
+impl<Balance: Decode> Decode for Tally<Balance> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (ayes, nays) = Decode::decode(input)?;
+		Ok(Tally { ayes, nays })
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Referendum<T: Config> {
+	pub proposal_hash: Hash,
+	pub end: T::BlockNumber,
+	pub tally: Tally<T::Balance>,
+	pub votes: BTreeMap<T::AccountId, Vote<T::Balance>>,
+}
+
+impl<T: Config> Encode for Referendum<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		let votes: Vec<_> = self.votes.iter().collect();
+		(&self.proposal_hash, &self.end, &self.tally, votes).encode_to(dest);
+	}
+}
+
+impl<T: Config> Decode for Referendum<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (proposal_hash, end, tally, votes): (_, _, _, Vec<_>) = Decode::decode(input)?;
+		Ok(Referendum { proposal_hash, end, tally, votes: votes.into_iter().collect() })
+	}
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	preimages: BTreeMap<Hash, T::RuntimeCall>,
+	public_props: Vec<Proposal<T>>,
+	prop_count: PropIndex,
+	referenda: BTreeMap<ReferendumIndex, Referendum<T>>,
+	referendum_count: ReferendumIndex,
+	/// Hashes of the passed proposals, by the block they are enacted at.
+	enactments: BTreeMap<T::BlockNumber, Vec<Hash>>,
+	next_launch: T::BlockNumber,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self {
+			preimages: BTreeMap::new(),
+			public_props: Vec::new(),
+			prop_count: 0,
+			referenda: BTreeMap::new(),
+			referendum_count: 0,
+			enactments: BTreeMap::new(),
+			next_launch: T::LAUNCH_PERIOD,
+		}
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Democracy",
+			index: None,
+			calls: vec![
+				variant(
+					"Propose",
+					0,
+					vec![field::<Box<T::RuntimeCall>>("call"), field::<T::Balance>("deposit")],
+				),
+				variant("Second", 1, vec![field::<PropIndex>("proposal")]),
+				variant(
+					"Vote",
+					2,
+					vec![field::<ReferendumIndex>("referendum"), field::<Vote<T::Balance>>("vote")],
+				),
+				variant("Unlock", 3, vec![field::<T::AccountId>("target")]),
+				variant("CancelReferendum", 4, vec![field::<ReferendumIndex>("referendum")]),
+			],
+			storage: vec![
+				storage::<BTreeMap<Hash, T::RuntimeCall>>("preimages"),
+				storage::<Vec<Proposal<T>>>("public_props"),
+				storage::<PropIndex>("prop_count"),
+				storage::<BTreeMap<ReferendumIndex, Referendum<T>>>("referenda"),
+				storage::<ReferendumIndex>("referendum_count"),
+				storage::<BTreeMap<T::BlockNumber, Vec<Hash>>>("enactments"),
+				storage::<T::BlockNumber>("next_launch"),
+			],
+			events: Vec::new(),
+			errors: vec![
+				"Deposit below minimum",
+				"Insufficient funds to vote",
+				"Overflow when tallying votes",
+				"Proposal does not exist",
+				"Referendum does not exist",
+				"Referendum is over",
+				"Too many proposals",
+			],
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Democracy", STORAGE_VERSION);
+		trie::insert_entries(storage, "Democracy", "preimages", &self.preimages);
+		trie::insert_value(storage, "Democracy", "public_props", &self.public_props);
+		trie::insert_value(storage, "Democracy", "prop_count", &self.prop_count);
+		trie::insert_entries(storage, "Democracy", "referenda", &self.referenda);
+		trie::insert_value(storage, "Democracy", "referendum_count", &self.referendum_count);
+		trie::insert_entries(storage, "Democracy", "enactments", &self.enactments);
+		trie::insert_value(storage, "Democracy", "next_launch", &self.next_launch);
+	}
+
+	/// Restore the proposals and referenda `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult
+	where
+		T::RuntimeCall: Decode,
+	{
+		migrations::ensure_version(storage, "Democracy", STORAGE_VERSION)?;
+		self.preimages =
+			trie::decode_entries(storage, "Democracy", "preimages")?.into_iter().collect();
+		trie::restore_value(storage, "Democracy", "public_props", &mut self.public_props)?;
+		trie::restore_value(storage, "Democracy", "prop_count", &mut self.prop_count)?;
+		self.referenda =
+			trie::decode_entries(storage, "Democracy", "referenda")?.into_iter().collect();
+		trie::restore_value(storage, "Democracy", "referendum_count", &mut self.referendum_count)?;
+		self.enactments =
+			trie::decode_entries(storage, "Democracy", "enactments")?.into_iter().collect();
+		trie::restore_value(storage, "Democracy", "next_launch", &mut self.next_launch)?;
+		Ok(())
+	}
+
+	/// Proposals hold deposits and votes lock funds, so calls are dispatched together with
+	/// the balances pallet and the current block number.
+	pub fn dispatch(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		now: T::BlockNumber,
+		origin: OriginFor<T>,
+		call: Call<T>,
+	) -> DispatchResult {
+		match call {
+			Call::Propose { call, deposit } => {
+				self.propose(balances, ensure_signed(origin)?, *call, deposit).map(|_| ())
+			},
+			Call::Second { proposal } => self.second(balances, ensure_signed(origin)?, proposal),
+			Call::Vote { referendum, vote } => {
+				self.vote(balances, now, ensure_signed(origin)?, referendum, vote)
+			},
+			Call::Unlock { target } => {
+				ensure_signed(origin)?;
+				self.unlock(balances, &target);
+				Ok(())
+			},
+			Call::CancelReferendum { referendum } => {
+				T::ensure_cancel_origin(origin)?;
+				self.cancel_referendum(referendum)
+			},
+		}
+	}
+
+	pub fn public_props(&self) -> &[Proposal<T>] {
+		&self.public_props
+	}
+
+	pub fn referendum(&self, index: ReferendumIndex) -> Option<&Referendum<T>> {
+		self.referenda.get(&index)
+	}
+
+	pub fn preimage(&self, proposal_hash: &Hash) -> Option<&T::RuntimeCall> {
+		self.preimages.get(proposal_hash)
+	}
+
+	/// Propose `call`, reserving `deposit` from the proposer until the proposal is launched.
+	pub fn propose(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		caller: T::AccountId,
+		call: T::RuntimeCall,
+		deposit: T::Balance,
+	) -> Result<PropIndex, &'static str> {
+		if deposit < T::MINIMUM_DEPOSIT {
+			return Err("Deposit below minimum");
+		}
+		if self.public_props.len() >= T::MAX_PROPOSALS {
+			return Err("Too many proposals");
+		}
+
+		balances.hold(HoldReason::ProposalDeposit.into(), &caller, deposit)?;
+
+		let index = self.prop_count;
+		self.prop_count += 1;
+		let proposal_hash = hashing::blake2_256(&call.encode());
+		self.preimages.insert(proposal_hash, call);
+		self.public_props
+			.push(Proposal { index, proposal_hash, deposit, backers: vec![caller] });
+		Ok(index)
+	}
+
+	/// Back a proposal by holding the same deposit as its proposer.
+	pub fn second(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		caller: T::AccountId,
+		proposal: PropIndex,
+	) -> DispatchResult {
+		let prop = self
+			.public_props
+			.iter_mut()
+			.find(|prop| prop.index == proposal)
+			.ok_or("Proposal does not exist")?;
+
+		balances.hold(HoldReason::ProposalDeposit.into(), &caller, prop.deposit)?;
+		prop.backers.push(caller);
+		Ok(())
+	}
+
+	/// Vote on an ongoing referendum, replacing any previous vote of the caller.
+	pub fn vote(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		now: T::BlockNumber,
+		caller: T::AccountId,
+		referendum: ReferendumIndex,
+		vote: Vote<T::Balance>,
+	) -> DispatchResult {
+		if vote.balance > balances.balance(&caller) {
+			return Err("Insufficient funds to vote");
+		}
+		let info = self.referenda.get_mut(&referendum).ok_or("Referendum does not exist")?;
+		if info.end <= now {
+			return Err("Referendum is over");
+		}
+
+		let mut tally = info.tally;
+		if let Some(previous) = info.votes.get(&caller) {
+			tally = Self::remove_from_tally(tally, previous);
+		}
+		tally = Self::add_to_tally(tally, &vote).ok_or("Overflow when tallying votes")?;
+
+		info.tally = tally;
+		info.votes.insert(caller.clone(), vote);
+		self.unlock(balances, &caller);
+		Ok(())
+	}
+
+	/// Lock the largest vote of `who` in the ongoing referenda, or remove the lock if there is
+	/// none.
+	pub fn unlock(&self, balances: &mut balances::Pallet<T>, who: &T::AccountId) {
+		let locked = self.referenda.values().filter_map(|info| info.votes.get(who)).fold(
+			T::Balance::zero(),
+			|max, vote| if vote.balance > max { vote.balance } else { max },
+		);
+
+		if locked.is_zero() {
+			balances.remove_lock(DEMOCRACY_ID, who);
+		} else {
+			balances.set_lock(DEMOCRACY_ID, who, locked);
+		}
+	}
+
+	pub fn cancel_referendum(&mut self, referendum: ReferendumIndex) -> DispatchResult {
+		let info = self.referenda.remove(&referendum).ok_or("Referendum does not exist")?;
+		self.preimages.remove(&info.proposal_hash);
+		Ok(())
+	}
+
+	/// Called at the start of every block: returns the calls of the proposals enacted at `now`,
+	/// to be dispatched with the Root origin. Referenda ending at `now` are tallied and, every
+	/// launch period, the most backed proposal becomes a new referendum.
+	pub fn on_initialize(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		now: T::BlockNumber,
+	) -> Vec<T::RuntimeCall> {
+		let enacted = self
+			.enactments
+			.remove(&now)
+			.unwrap_or_default()
+			.into_iter()
+			.filter_map(|proposal_hash| self.preimages.remove(&proposal_hash))
+			.collect();
+
+		let ended: Vec<ReferendumIndex> = self
+			.referenda
+			.iter()
+			.filter(|(_, info)| info.end <= now)
+			.map(|(index, _)| *index)
+			.collect();
+		for index in ended {
+			if let Some(info) = self.referenda.remove(&index) {
+				self.finish_referendum(balances, now, info);
+			}
+		}
+
+		if now >= self.next_launch {
+			self.next_launch = now.checked_add(&T::LAUNCH_PERIOD).unwrap_or(now);
+			self.launch_public(balances, now);
+		}
+
+		enacted
+	}
+
+	/// Turn the proposal with the most backers into a referendum, returning the deposits.
+	fn launch_public(&mut self, balances: &mut balances::Pallet<T>, now: T::BlockNumber) {
+		let Some(position) = self
+			.public_props
+			.iter()
+			.enumerate()
+			.max_by_key(|(position, prop)| (prop.backers.len(), core::cmp::Reverse(*position)))
+			.map(|(position, _)| position)
+		else {
+			return;
+		};
+
+		let prop = self.public_props.remove(position);
+		for backer in &prop.backers {
+			let _ = balances.release(HoldReason::ProposalDeposit.into(), backer, prop.deposit);
+		}
+
+		let index = self.referendum_count;
+		self.referendum_count += 1;
+		self.referenda.insert(
+			index,
+			Referendum {
+				proposal_hash: prop.proposal_hash,
+				end: now.checked_add(&T::VOTING_PERIOD).unwrap_or(now),
+				tally: Tally { ayes: T::Balance::zero(), nays: T::Balance::zero() },
+				votes: BTreeMap::new(),
+			},
+		);
+	}
+
+	/// Passed referenda (more ayes than nays) are queued for enactment, rejected ones are
+	/// dropped. Either way the votes no longer lock the voters' funds.
+	fn finish_referendum(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		now: T::BlockNumber,
+		info: Referendum<T>,
+	) {
+		if info.tally.ayes > info.tally.nays {
+			let when = now.checked_add(&T::ENACTMENT_DELAY).unwrap_or(now);
+			self.enactments.entry(when).or_default().push(info.proposal_hash);
+		} else {
+			self.preimages.remove(&info.proposal_hash);
+		}
+
+		for voter in info.votes.keys() {
+			self.unlock(balances, voter);
+		}
+	}
+
+	fn add_to_tally(
+		tally: Tally<T::Balance>,
+		vote: &Vote<T::Balance>,
+	) -> Option<Tally<T::Balance>> {
+		Some(if vote.aye {
+			Tally { ayes: tally.ayes.checked_add(&vote.balance)?, nays: tally.nays }
+		} else {
+			Tally { ayes: tally.ayes, nays: tally.nays.checked_add(&vote.balance)? }
+		})
+	}
+
+	/// A vote can only be removed from a tally it was added to, so this never underflows.
+	fn remove_from_tally(tally: Tally<T::Balance>, vote: &Vote<T::Balance>) -> Tally<T::Balance> {
+		let sub = |total: T::Balance| total.checked_sub(&vote.balance).unwrap_or(total);
+		if vote.aye {
+			Tally { ayes: sub(tally.ayes), nays: tally.nays }
+		} else {
+			Tally { ayes: tally.ayes, nays: sub(tally.nays) }
+		}
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::Vote;
+	use crate::{balances, mock::Test, support::RawOrigin};
+
+	impl super::Config for Test {
+		type RuntimeCall = &'static str;
+		const LAUNCH_PERIOD: u32 = 2;
+		const VOTING_PERIOD: u32 = 3;
+		const ENACTMENT_DELAY: u32 = 1;
+		const MINIMUM_DEPOSIT: u128 = 10;
+		const MAX_PROPOSALS: usize = 2;
+	}
+
+	fn setup() -> (balances::Pallet<Test>, super::Pallet<Test>) {
+		let mut balances = balances::Pallet::new();
+		for who in ["alice", "bob", "charlie"] {
+			balances.set_balance(&who.to_string(), 100);
+		}
+		(balances, super::Pallet::new())
+	}
+
+	#[test]
+	fn propose_and_second_reserve_deposits() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let (mut balances, mut democracy) = setup();
+
+		assert_eq!(
+			democracy.propose(&mut balances, alice.clone(), "call", 5),
+			Err("Deposit below minimum")
+		);
+		assert_eq!(democracy.propose(&mut balances, alice.clone(), "first", 20), Ok(0));
+		assert_eq!(democracy.propose(&mut balances, bob.clone(), "second", 10), Ok(1));
+		assert_eq!(
+			democracy.propose(&mut balances, bob.clone(), "third", 10),
+			Err("Too many proposals")
+		);
+		assert_eq!(democracy.second(&mut balances, bob.clone(), 0), Ok(()));
+		assert_eq!(democracy.second(&mut balances, bob.clone(), 7), Err("Proposal does not exist"));
+		assert_eq!(balances.reserved_balance(&alice), 20);
+		assert_eq!(balances.reserved_balance(&bob), 30);
+
+		// The most seconded proposal is launched and its deposits are returned.
+		assert!(democracy.on_initialize(&mut balances, 2).is_empty());
+		assert_eq!(democracy.public_props().len(), 1);
+		assert_eq!(democracy.public_props()[0].index, 1);
+		assert_eq!(democracy.referendum(0).map(|info| info.end), Some(5));
+		assert_eq!(balances.reserved_balance(&alice), 0);
+		assert_eq!(balances.reserved_balance(&bob), 10);
+	}
+
+	#[test]
+	fn passed_referendum_is_enacted_after_delay() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let charlie = "charlie".to_string();
+		let (mut balances, mut democracy) = setup();
+
+		let _ = democracy.propose(&mut balances, alice.clone(), "call", 10);
+		let _ = democracy.on_initialize(&mut balances, 2);
+
+		let aye = Vote { aye: true, balance: 60 };
+		let nay = Vote { aye: false, balance: 50 };
+		assert_eq!(
+			democracy.vote(&mut balances, 3, alice.clone(), 0, Vote { aye: true, balance: 200 }),
+			Err("Insufficient funds to vote")
+		);
+		assert_eq!(democracy.vote(&mut balances, 3, alice.clone(), 0, aye), Ok(()));
+		assert_eq!(democracy.vote(&mut balances, 3, bob.clone(), 0, nay), Ok(()));
+		assert_eq!(
+			democracy.vote(&mut balances, 3, charlie.clone(), 1, aye),
+			Err("Referendum does not exist")
+		);
+		assert_eq!(balances.locked_balance(&alice), 60);
+		assert_eq!(balances.transfer(alice.clone(), charlie.clone(), 50), Err("Balance is locked"));
+
+		assert!(democracy.on_initialize(&mut balances, 4).is_empty());
+		assert!(democracy.on_initialize(&mut balances, 5).is_empty());
+		assert!(democracy.referendum(0).is_none());
+		assert_eq!(balances.locked_balance(&alice), 0);
+		assert_eq!(democracy.on_initialize(&mut balances, 6), vec!["call"]);
+	}
+
+	#[test]
+	fn changing_a_vote_updates_the_tally() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let (mut balances, mut democracy) = setup();
+
+		let _ = democracy.propose(&mut balances, alice.clone(), "call", 10);
+		let _ = democracy.on_initialize(&mut balances, 2);
+
+		let _ = democracy.vote(&mut balances, 2, alice.clone(), 0, Vote { aye: true, balance: 60 });
+		let _ = democracy.vote(&mut balances, 2, bob.clone(), 0, Vote { aye: false, balance: 50 });
+		let _ =
+			democracy.vote(&mut balances, 2, alice.clone(), 0, Vote { aye: false, balance: 10 });
+		assert_eq!(
+			democracy.referendum(0).map(|info| info.tally),
+			Some(super::Tally { ayes: 0, nays: 60 })
+		);
+		assert_eq!(balances.locked_balance(&alice), 10);
+
+		// Rejected referenda are dropped without being enacted.
+		let _ = democracy.on_initialize(&mut balances, 5);
+		assert!(democracy.on_initialize(&mut balances, 6).is_empty());
+		assert_eq!(
+			democracy.vote(&mut balances, 6, alice.clone(), 0, Vote { aye: true, balance: 1 }),
+			Err("Referendum does not exist")
+		);
+	}
+
+	#[test]
+	fn cancel_referendum_requires_root() {
+		let alice = "alice".to_string();
+		let (mut balances, mut democracy) = setup();
+
+		let _ = democracy.propose(&mut balances, alice.clone(), "call", 10);
+		let _ = democracy.on_initialize(&mut balances, 2);
+
+		let result = democracy.dispatch(
+			&mut balances,
+			2,
+			RawOrigin::Signed(alice.clone()),
+			super::Call::CancelReferendum { referendum: 0 },
+		);
+		assert_eq!(result, Err("Bad origin"));
+		let result = democracy.dispatch(
+			&mut balances,
+			2,
+			RawOrigin::Root,
+			super::Call::CancelReferendum { referendum: 0 },
+		);
+		assert_eq!(result, Ok(()));
+		assert!(democracy.referendum(0).is_none());
+	}
+}
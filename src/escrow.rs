@@ -0,0 +1,426 @@
+use std::collections::BTreeMap;
+
+use num::{CheckedAdd, Zero};
+
+use crate::{
+	balances,
+	codec::{Decode, Encode},
+	metadata::{field, storage, unit, variant, PalletMetadata, Registry, TypeDef, TypeInfo},
+	migrations::{self, StorageVersion},
+	support::{ensure_signed, DispatchResult, Weight},
+	system::OriginFor,
+	trie::{self, Storage},
+};
+
+pub type EscrowIndex = u32;
+
+pub trait Config: balances::Config {
+	/// The longest (in blocks) funds can be held before they are refunded to the payer.
+	const MAX_TIMEOUT: Self::BlockNumber;
+}
+
+/// Where the funds of an escrow go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+	/// Pay the payee.
+	Release,
+	/// Give the funds back to the payer.
+	Refund,
+}
+
+impl Encode for Resolution {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Resolution::Release => 0u8.encode_to(dest),
+			Resolution::Refund => 1u8.encode_to(dest),
+		}
+	}
+}
+
+impl Decode for Resolution {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		match u8::decode(input)? {
+			0 => Ok(Resolution::Release),
+			1 => Ok(Resolution::Refund),
+			_ => Err("Invalid resolution"),
+		}
+	}
+}
+
+impl TypeInfo for Resolution {
+	fn type_def(_: &mut Registry) -> TypeDef {
+		TypeDef::Variant(vec![unit("Release", 0), unit("Refund", 1)])
+	}
+}
+
+#[derive(Debug, Clone)]
+pub enum Call<T: Config> {
+	Create {
+		payee: T::AccountId,
+		arbiter: Option<T::AccountId>,
+		amount: T::Balance,
+		timeout: T::BlockNumber,
+	},
+	/// Agree on a resolution as the payer or the payee; it is carried out once both agree.
+	Agree { escrow: EscrowIndex, resolution: Resolution },
+	/// Settle an escrow as its arbiter.
+	Resolve { escrow: EscrowIndex, resolution: Resolution },
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::Create { payee, arbiter, amount, timeout } => {
+				(0u8, payee, arbiter, amount, timeout).encode_to(dest)
+			},
+			Call::Agree { escrow, resolution } => (1u8, escrow, resolution).encode_to(dest),
+			Call::Resolve { escrow, resolution } => (2u8, escrow, resolution).encode_to(dest),
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::Create {
+				payee: Decode::decode(input)?,
+				arbiter: Decode::decode(input)?,
+				amount: Decode::decode(input)?,
+				timeout: Decode::decode(input)?,
+			},
+			1 => Call::Agree { escrow: Decode::decode(input)?, resolution: Decode::decode(input)? },
+			2 => {
+				Call::Resolve { escrow: Decode::decode(input)?, resolution: Decode::decode(input)? }
+			},
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::Create { .. } => 0,
+			Call::Agree { .. } => 1,
+			Call::Resolve { .. } => 2,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::Create { .. } | Call::Agree { .. } | Call::Resolve { .. } => 10_000,
+		}
+	}
+}
+
+/// Funds reserved from the payer until the escrow is settled.
+#[derive(Debug, Clone)]
+pub struct Escrow<T: Config> {
+	pub payer: T::AccountId,
+	pub payee: T::AccountId,
+	pub arbiter: Option<T::AccountId>,
+	pub amount: T::Balance,
+	/// The block at which the funds are refunded if the escrow wasn't settled before.
+	pub expiry: T::BlockNumber,
+	pub payer_agrees: Option<Resolution>,
+	pub payee_agrees: Option<Resolution>,
+}
+
+impl<T: Config> Encode for Escrow<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.payer, &self.payee, &self.arbiter, &self.amount).encode_to(dest);
+		(&self.expiry, &self.payer_agrees, &self.payee_agrees).encode_to(dest);
+	}
+}
+
+impl<T: Config> Decode for Escrow<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (payer, payee, arbiter, amount) = Decode::decode(input)?;
+		let (expiry, payer_agrees, payee_agrees) = Decode::decode(input)?;
+		Ok(Escrow { payer, payee, arbiter, amount, expiry, payer_agrees, payee_agrees })
+	}
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	escrows: BTreeMap<EscrowIndex, Escrow<T>>,
+	escrow_count: EscrowIndex,
+	/// May still list escrows that were settled before they expired.
+	expiries: BTreeMap<T::BlockNumber, Vec<EscrowIndex>>,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self { escrows: BTreeMap::new(), escrow_count: 0, expiries: BTreeMap::new() }
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Escrow",
+			index: None,
+			calls: vec![
+				variant(
+					"Create",
+					0,
+					vec![
+						field::<T::AccountId>("payee"),
+						field::<Option<T::AccountId>>("arbiter"),
+						field::<T::Balance>("amount"),
+						field::<T::BlockNumber>("timeout"),
+					],
+				),
+				variant(
+					"Agree",
+					1,
+					vec![field::<EscrowIndex>("escrow"), field::<Resolution>("resolution")],
+				),
+				variant(
+					"Resolve",
+					2,
+					vec![field::<EscrowIndex>("escrow"), field::<Resolution>("resolution")],
+				),
+			],
+			storage: vec![
+				storage::<BTreeMap<EscrowIndex, Escrow<T>>>("escrows"),
+				storage::<EscrowIndex>("escrow_count"),
+				storage::<BTreeMap<T::BlockNumber, Vec<EscrowIndex>>>("expiries"),
+			],
+			events: Vec::new(),
+			errors: vec![
+				"Caller is not a party",
+				"Caller is not the arbiter",
+				"Escrow does not exist",
+				"Invalid timeout",
+			],
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Escrow", STORAGE_VERSION);
+		trie::insert_entries(storage, "Escrow", "escrows", &self.escrows);
+		trie::insert_value(storage, "Escrow", "escrow_count", &self.escrow_count);
+		trie::insert_entries(storage, "Escrow", "expiries", &self.expiries);
+	}
+
+	/// Restore the escrows `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult {
+		migrations::ensure_version(storage, "Escrow", STORAGE_VERSION)?;
+		self.escrows = trie::decode_entries(storage, "Escrow", "escrows")?.into_iter().collect();
+		trie::restore_value(storage, "Escrow", "escrow_count", &mut self.escrow_count)?;
+		self.expiries = trie::decode_entries(storage, "Escrow", "expiries")?.into_iter().collect();
+		Ok(())
+	}
+
+	/// Escrowed funds are reserved and expire, so calls are dispatched together with the
+	/// balances pallet and the current block number.
+	pub fn dispatch(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		now: T::BlockNumber,
+		origin: OriginFor<T>,
+		call: Call<T>,
+	) -> DispatchResult {
+		let caller = ensure_signed(origin)?;
+		match call {
+			Call::Create { payee, arbiter, amount, timeout } => {
+				self.create(balances, now, caller, payee, arbiter, amount, timeout).map(|_| ())
+			},
+			Call::Agree { escrow, resolution } => self.agree(balances, caller, escrow, resolution),
+			Call::Resolve { escrow, resolution } => {
+				self.resolve(balances, caller, escrow, resolution)
+			},
+		}
+	}
+
+	pub fn escrow(&self, escrow: EscrowIndex) -> Option<&Escrow<T>> {
+		self.escrows.get(&escrow)
+	}
+
+	#[allow(clippy::too_many_arguments)]
+	pub fn create(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		now: T::BlockNumber,
+		payer: T::AccountId,
+		payee: T::AccountId,
+		arbiter: Option<T::AccountId>,
+		amount: T::Balance,
+		timeout: T::BlockNumber,
+	) -> Result<EscrowIndex, &'static str> {
+		if timeout.is_zero() || timeout > T::MAX_TIMEOUT {
+			return Err("Invalid timeout");
+		}
+		let expiry = now.checked_add(&timeout).ok_or("Invalid timeout")?;
+		balances.reserve(&payer, amount)?;
+
+		let escrow = self.escrow_count;
+		self.escrow_count += 1;
+		self.escrows.insert(
+			escrow,
+			Escrow {
+				payer,
+				payee,
+				arbiter,
+				amount,
+				expiry,
+				payer_agrees: None,
+				payee_agrees: None,
+			},
+		);
+		self.expiries.entry(expiry).or_default().push(escrow);
+		Ok(escrow)
+	}
+
+	pub fn agree(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		caller: T::AccountId,
+		escrow: EscrowIndex,
+		resolution: Resolution,
+	) -> DispatchResult {
+		let info = self.escrows.get_mut(&escrow).ok_or("Escrow does not exist")?;
+		if caller == info.payer {
+			info.payer_agrees = Some(resolution);
+		}
+		if caller == info.payee {
+			info.payee_agrees = Some(resolution);
+		}
+		if caller != info.payer && caller != info.payee {
+			return Err("Caller is not a party");
+		}
+
+		if info.payer_agrees == info.payee_agrees {
+			self.settle(balances, escrow, resolution)?;
+		}
+		Ok(())
+	}
+
+	pub fn resolve(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		caller: T::AccountId,
+		escrow: EscrowIndex,
+		resolution: Resolution,
+	) -> DispatchResult {
+		let info = self.escrows.get(&escrow).ok_or("Escrow does not exist")?;
+		if info.arbiter.as_ref() != Some(&caller) {
+			return Err("Caller is not the arbiter");
+		}
+		self.settle(balances, escrow, resolution)
+	}
+
+	/// Called at the start of every block: escrows that weren't settled in time are refunded.
+	pub fn on_initialize(&mut self, balances: &mut balances::Pallet<T>, now: T::BlockNumber) {
+		for escrow in self.expiries.remove(&now).unwrap_or_default() {
+			if self.escrows.contains_key(&escrow) {
+				let _ = self.settle(balances, escrow, Resolution::Refund);
+			}
+		}
+	}
+
+	fn settle(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		escrow: EscrowIndex,
+		resolution: Resolution,
+	) -> DispatchResult {
+		let info = self.escrows.get(&escrow).ok_or("Escrow does not exist")?;
+		match resolution {
+			Resolution::Release => {
+				balances.repatriate_reserved(&info.payer, &info.payee, info.amount)?
+			},
+			Resolution::Refund => balances.unreserve(&info.payer, info.amount)?,
+		}
+		self.escrows.remove(&escrow);
+		Ok(())
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::Resolution;
+	use crate::{balances, mock::Test};
+
+	impl super::Config for Test {
+		const MAX_TIMEOUT: u32 = 10;
+	}
+
+	fn setup() -> (balances::Pallet<Test>, super::Pallet<Test>) {
+		let mut balances = balances::Pallet::new();
+		balances.set_balance(&"alice".to_string(), 100);
+		(balances, super::Pallet::new())
+	}
+
+	#[test]
+	fn released_on_mutual_agreement() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let (mut balances, mut escrow) = setup();
+
+		assert_eq!(
+			escrow.create(&mut balances, 1, alice.clone(), bob.clone(), None, 60, 11),
+			Err("Invalid timeout")
+		);
+		assert_eq!(escrow.create(&mut balances, 1, alice.clone(), bob.clone(), None, 60, 5), Ok(0));
+		assert_eq!(balances.reserved_balance(&alice), 60);
+
+		assert_eq!(
+			escrow.agree(&mut balances, "charlie".to_string(), 0, Resolution::Release),
+			Err("Caller is not a party")
+		);
+		assert_eq!(escrow.agree(&mut balances, alice.clone(), 0, Resolution::Release), Ok(()));
+		assert_eq!(escrow.agree(&mut balances, bob.clone(), 0, Resolution::Refund), Ok(()));
+		assert!(escrow.escrow(0).is_some());
+
+		// The payer changing its mind to match the payee settles the escrow.
+		assert_eq!(escrow.agree(&mut balances, alice.clone(), 0, Resolution::Refund), Ok(()));
+		assert!(escrow.escrow(0).is_none());
+		assert_eq!(balances.balance(&alice), 100);
+		assert_eq!(balances.reserved_balance(&alice), 0);
+	}
+
+	#[test]
+	fn arbiter_decides_or_timeout_refunds() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let charlie = "charlie".to_string();
+		let (mut balances, mut escrow) = setup();
+
+		let _ = escrow.create(
+			&mut balances,
+			1,
+			alice.clone(),
+			bob.clone(),
+			Some(charlie.clone()),
+			30,
+			5,
+		);
+		let _ = escrow.create(&mut balances, 1, alice.clone(), bob.clone(), None, 30, 5);
+		assert_eq!(
+			escrow.resolve(&mut balances, bob.clone(), 0, Resolution::Release),
+			Err("Caller is not the arbiter")
+		);
+		assert_eq!(escrow.resolve(&mut balances, charlie.clone(), 0, Resolution::Release), Ok(()));
+		assert_eq!(balances.balance(&bob), 30);
+		assert_eq!(
+			escrow.resolve(&mut balances, charlie.clone(), 1, Resolution::Release),
+			Err("Caller is not the arbiter")
+		);
+
+		escrow.on_initialize(&mut balances, 5);
+		assert!(escrow.escrow(1).is_some());
+		escrow.on_initialize(&mut balances, 6);
+		assert!(escrow.escrow(1).is_none());
+		assert_eq!(balances.balance(&alice), 70);
+		assert_eq!(balances.reserved_balance(&alice), 0);
+	}
+}
@@ -0,0 +1,158 @@
+//! A block explorer for the terminal, for demos and debugging without a web frontend: the
+//! `explorer` command redraws the latest blocks of a node, with the calls and events of each,
+//! and the accounts and claims it watches. It queries the REST API of the node, like the wallet,
+//! and names the errors of failed extrinsics from the metadata of its own runtime.
+
+use std::{fmt::Write, io};
+
+use crate::{
+	hashing, json,
+	support::ModuleError,
+	types,
+	wallet::{self, Client},
+	Runtime,
+};
+
+/// How many of the latest blocks are shown, the best block first.
+pub const BLOCKS: types::BlockNumber = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Watched {
+	/// Its free and reserved balance is shown.
+	Account(types::AccountId),
+	/// Its owner is shown, if it is claimed.
+	Claim(types::Content),
+}
+
+impl Watched {
+	/// An account if `arg` is an address, and the content of a claim otherwise.
+	pub fn parse(arg: &str) -> Self {
+		arg.parse().map_or_else(|_| Watched::Claim(arg.to_string()), Watched::Account)
+	}
+}
+
+/// The screen of the explorer: the latest blocks of the node `client` talks to, then `watched`.
+pub fn render(client: &Client, watched: &[Watched]) -> io::Result<String> {
+	let mut screen = String::new();
+	let metadata = Runtime::metadata();
+	let (best, best_hash) = client.best_block()?;
+	let _ = writeln!(screen, "Best block #{} {}", best, hashing::hex(&best_hash));
+	for number in (best.saturating_sub(BLOCKS - 1).max(1)..=best).rev() {
+		let block = client.block(number)?;
+		let field = |name| wallet::field(&block, name).unwrap_or("?");
+		let _ = writeln!(screen, "\n#{} {} by {}", number, field("hash"), field("author"));
+		for call in json::strings(&block, "calls").unwrap_or_default() {
+			let _ = writeln!(screen, "  call  {}", call);
+		}
+		for event in json::strings(&block, "events").unwrap_or_default() {
+			let _ = writeln!(screen, "  event {}", event);
+		}
+		let block = json::parse(&block).unwrap_or(json::Value::Null);
+		for failed in block.get("failed").and_then(json::Value::as_array).unwrap_or_default() {
+			let error = failed.get("error").and_then(json::Value::as_array).and_then(|error| {
+				let (index, error) = (error.first()?.as_number()?, error.get(1)?.as_number()?);
+				metadata.error_name(ModuleError { index, error })
+			});
+			let (pallet, error) = error.unwrap_or(("?", "?"));
+			let extrinsic = failed.get("extrinsic").and_then(json::Value::as_number::<u32>);
+			let extrinsic = extrinsic.map_or("?".to_string(), |index| index.to_string());
+			let _ = writeln!(screen, "  failed #{} {}: {}", extrinsic, pallet, error);
+		}
+	}
+
+	if !watched.is_empty() {
+		let _ = writeln!(screen, "\nWatched");
+	}
+	for watched in watched {
+		let _ = match watched {
+			Watched::Account(account) => {
+				let balance = client.balance(account)?;
+				let field = |name| wallet::field(&balance, name).unwrap_or("?");
+				writeln!(
+					screen,
+					"  {} free {} reserved {}",
+					account,
+					field("free"),
+					field("reserved")
+				)
+			},
+			Watched::Claim(content) => match client.claim(content) {
+				Ok(claim) => {
+					let field = |name| wallet::field(&claim, name).unwrap_or("?");
+					let (owner, since) = (field("owner"), field("block_number"));
+					writeln!(screen, "  {:?} claimed by {} at block {}", content, owner, since)
+				},
+				// The node answered, but has no claim of `content`.
+				Err(e) if e.kind() == io::ErrorKind::Other => {
+					let error = e.to_string();
+					let error = wallet::field(&error, "error").unwrap_or("?");
+					writeln!(screen, "  {:?}: {}", content, error)
+				},
+				Err(e) => return Err(e),
+			},
+		};
+	}
+	Ok(screen)
+}
+
+#[cfg(test)]
+mod test {
+	use std::{net::TcpListener, thread};
+
+	use super::{render, Watched};
+	use crate::{
+		chain::Chain, genesis, genesis::GenesisBuilder, indexer::Indexer, metrics::Metrics,
+		proof_of_existence, rest, transaction_pool::TransactionPool, wallet, wallet::Client,
+		RuntimeCall,
+	};
+
+	#[test]
+	fn blocks_and_watched_items_are_shown() {
+		let alice = wallet::dev_account("alice");
+		let bob = wallet::dev_account("bob");
+		let state = GenesisBuilder::new().endow(&alice, 1_000).authority(&alice).build().unwrap();
+		let to = bob.clone();
+		let handover = proof_of_existence::Call::TransferClaim { claim: "document".into(), to };
+		let calls = [
+			wallet::transfer(bob.clone(), 10),
+			wallet::create_claim("document".into()),
+			RuntimeCall::ProofOfExistence(handover),
+			wallet::transfer(bob.clone(), 10_000),
+		];
+		let block = genesis::block(&state, "alice", genesis::signed(&state, "alice", calls));
+		let mut chain = Chain::new(state);
+		chain.import(block.unwrap(), 1_000).unwrap();
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let client = Client::new(listener.local_addr().unwrap().to_string());
+		let node = thread::spawn(move || {
+			let mut pool = TransactionPool::new();
+			for _ in 0..5 {
+				let (stream, _) = listener.accept().unwrap();
+				rest::handle_connection(
+					&chain,
+					&Metrics::new(),
+					&Indexer::new(),
+					&mut pool,
+					stream,
+				)
+				.unwrap();
+			}
+		});
+		let watched = ["document", "missing", &bob.to_string()].map(Watched::parse);
+		assert_eq!(watched[2], Watched::Account(bob.clone()));
+		let screen = render(&client, &watched).unwrap();
+		node.join().unwrap();
+
+		assert!(screen.starts_with("Best block #1 "));
+		assert!(screen.contains("  call  Timestamp::Set\n  call  Balances::Transfer\n"));
+		let calls = "  call  ProofOfExistence::TransferClaim\n  call  Balances::Transfer\n";
+		assert!(screen.contains(&format!("{}  event ", calls)));
+		assert!(screen.contains("  event ProofOfExistence(ClaimTransferred"));
+		assert!(screen.contains("  event Balances(Transferred"));
+		assert!(screen.contains("  failed #4 Balances: Insufficient balance\n"));
+		assert!(screen.contains(&format!("  \"document\" claimed by {} at block 1\n", bob)));
+		assert!(screen.contains("  \"missing\": Claim not found\n"));
+		assert!(screen.ends_with(&format!("  {} free 10 reserved 0\n", bob)));
+	}
+}
@@ -0,0 +1,569 @@
+//! A finality gadget in the spirit of GRANDPA. Validators vote in rounds, twice per round, and
+//! a vote for a block counts for all of its ancestors too:
+//!
+//! - In the prevote, a validator votes for its best block if it builds on the estimate of the
+//!   previous round, or else for the estimate.
+//! - The highest block that more than two thirds of the prevotes are for is the ghost of the
+//!   round, and validators precommit to it.
+//! - The highest block that more than two thirds of the precommits are for becomes final, and
+//!   from then on no fork can revert it.
+//!
+//! The estimate of a round is the highest ancestor of its ghost that the precommits still to
+//! come could finalize. The round is over once more than two thirds of the precommits arrived
+//! and no block after the estimate can be finalized by it anymore. Validators change their
+//! minds between rounds, e.g. to follow a longer fork, but building on the estimate keeps a
+//! round from finalizing a fork of a block an earlier round finalized.
+//!
+//! A node may follow another fork than the one the validators finalize, e.g. after missing some
+//! of their blocks. It then switches to the chain of the finalized block.
+//!
+//! Voters sign their votes with their session key, so a node can pass on the votes it hears
+//! from without being able to forge them. A node keeps the votes it received and cast in a
+//! `Voter`, which casts the vote of the node at the next stage whenever it is asked to.
+
+use std::{cmp::Reverse, collections::BTreeMap};
+
+use crate::{
+	account::AccountId32,
+	chain::Chain,
+	codec::{Decode, Encode},
+	hashing::Hash,
+	keystore,
+	signature::Verify,
+	types,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+	Prevote,
+	Precommit,
+}
+
+impl Encode for Stage {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		let index: u8 = match self {
+			Stage::Prevote => 0,
+			Stage::Precommit => 1,
+		};
+		index.encode_to(dest);
+	}
+}
+
+impl Decode for Stage {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		match u8::decode(input)? {
+			0 => Ok(Stage::Prevote),
+			1 => Ok(Stage::Precommit),
+			_ => Err("Invalid vote stage"),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vote {
+	pub round: u64,
+	pub stage: Stage,
+	pub hash: Hash,
+}
+
+impl Vote {
+	/// What voters sign, which can't be mistaken for the payload of an extrinsic or a seal.
+	fn signing_payload(&self) -> Vec<u8> {
+		("finality vote", self).encode()
+	}
+}
+
+impl Encode for Vote {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(self.round, self.stage, self.hash).encode_to(dest);
+	}
+}
+
+impl Decode for Vote {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (round, stage, hash) = Decode::decode(input)?;
+		Ok(Vote { round, stage, hash })
+	}
+}
+
+/// A vote with the signature of its voter, by the session key of the voter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedVote {
+	pub voter: types::AccountId,
+	pub vote: Vote,
+	pub signature: types::Signature,
+}
+
+impl SignedVote {
+	pub fn new(voter: types::AccountId, vote: Vote, key: &keystore::Signer) -> Self {
+		Self { voter, vote, signature: key.sign_payload(&vote.signing_payload()) }
+	}
+}
+
+impl Encode for SignedVote {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.voter, &self.vote, &self.signature).encode_to(dest);
+	}
+}
+
+impl Decode for SignedVote {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (voter, vote, signature) = Decode::decode(input)?;
+		Ok(SignedVote { voter, vote, signature })
+	}
+}
+
+type Votes = BTreeMap<types::AccountId, Hash>;
+
+#[derive(Default)]
+struct Round {
+	prevotes: Votes,
+	precommits: Votes,
+}
+
+pub struct Finality {
+	/// The validators and their session keys, as the session pallet lists them.
+	voters: Vec<(types::AccountId, types::SessionKeys)>,
+	round: u64,
+	/// The estimate of the previous round, which the votes of this round build on.
+	base: Option<Hash>,
+	/// The votes of the previous and current rounds, and of later ones received early.
+	rounds: BTreeMap<u64, Round>,
+}
+
+impl Finality {
+	pub fn new(voters: Vec<(types::AccountId, types::SessionKeys)>) -> Self {
+		Self { voters, round: 0, base: None, rounds: BTreeMap::new() }
+	}
+
+	pub fn voters(&self) -> &[(types::AccountId, types::SessionKeys)] {
+		&self.voters
+	}
+
+	fn is_voter(voters: &[(types::AccountId, types::SessionKeys)], who: &types::AccountId) -> bool {
+		voters.iter().any(|(voter, _)| voter == who)
+	}
+
+	/// Change the voters, e.g. to the validators of a new session. Votes of the old voters are
+	/// dropped.
+	pub fn set_voters(&mut self, voters: Vec<(types::AccountId, types::SessionKeys)>) {
+		for round in self.rounds.values_mut() {
+			round.prevotes.retain(|voter, _| Self::is_voter(&voters, voter));
+			round.precommits.retain(|voter, _| Self::is_voter(&voters, voter));
+		}
+		self.voters = voters;
+	}
+
+	/// The round validators vote in.
+	pub fn round(&self) -> u64 {
+		self.round
+	}
+
+	/// The block a validator votes for at `stage` of the current round, or `None` if it can't
+	/// precommit yet.
+	pub fn target(&self, chain: &Chain, stage: Stage) -> Option<Hash> {
+		match stage {
+			Stage::Prevote => match self.base {
+				Some(base) if !chain.is_descendant(chain.best_hash(), base) => Some(base),
+				_ => Some(chain.best_hash()),
+			},
+			Stage::Precommit => {
+				let ghost = self.supermajority(chain, &self.rounds.get(&self.round)?.prevotes)?;
+				self.base.is_none_or(|base| chain.is_descendant(ghost, base)).then_some(ghost)
+			},
+		}
+	}
+
+	/// Record a vote, unless its voter already voted at that stage of the round, or the round
+	/// is over. Votes for rounds after the next one are refused, as they would be kept until then
+	/// however many there are. Returns the newly finalized block, if the vote finalized one.
+	pub fn vote(
+		&mut self,
+		chain: &mut Chain,
+		signed: SignedVote,
+		local_now: types::Moment,
+	) -> Result<Option<Hash>, &'static str> {
+		let SignedVote { voter, vote, signature } = signed;
+		let (_, keys) = self.voters.iter().find(|(who, _)| *who == voter).ok_or("Not a voter")?;
+		if !signature.verify(&vote.signing_payload(), &AccountId32::new(*keys)) {
+			return Err("Bad vote signature");
+		}
+		if vote.round > self.round.saturating_add(1) {
+			return Err("Future round");
+		}
+		if chain.block(&vote.hash).is_none() && vote.hash != chain.finalized_head() {
+			return Err("Unknown block");
+		}
+		if vote.round.saturating_add(1) < self.round {
+			return Ok(None);
+		}
+		let round = self.rounds.entry(vote.round).or_default();
+		let votes = match vote.stage {
+			Stage::Prevote => &mut round.prevotes,
+			Stage::Precommit => &mut round.precommits,
+		};
+		votes.entry(voter).or_insert(vote.hash);
+
+		let finalized = self.finalize(chain, local_now)?;
+		self.advance(chain);
+		Ok(finalized)
+	}
+
+	/// Finalize the highest block more than two thirds of the precommits of a round are for.
+	fn finalize(
+		&self,
+		chain: &mut Chain,
+		local_now: types::Moment,
+	) -> Result<Option<Hash>, &'static str> {
+		let finalized = self
+			.rounds
+			.values()
+			.filter_map(|round| self.supermajority(chain, &round.precommits))
+			.filter(|block| chain.number(block) > chain.finalized_number())
+			.max_by_key(|block| chain.number(block));
+		let Some(block) = finalized else { return Ok(None) };
+		if !chain.is_descendant(chain.best_hash(), block) {
+			chain.set_best(block, local_now).map_err(|_| "Invalid finalized block")?;
+		}
+		chain.finalize(block)?;
+		Ok(Some(block))
+	}
+
+	/// Move on from the latest round that is over, dropping the votes of the rounds before it.
+	fn advance(&mut self, chain: &Chain) {
+		let over = self
+			.rounds
+			.range(self.round..)
+			.rev()
+			.find(|(_, round)| self.is_over(chain, round))
+			.map(|(number, round)| (*number, self.estimate(chain, round)));
+		if let Some((number, estimate)) = over {
+			self.round = number + 1;
+			self.base = estimate;
+			self.rounds = self.rounds.split_off(&number);
+		}
+	}
+
+	/// The highest block more than two thirds of `votes` are for.
+	fn supermajority(&self, chain: &Chain, votes: &Votes) -> Option<Hash> {
+		let mut candidates: Vec<_> = votes.values().flat_map(|vote| chain.route(*vote)).collect();
+		candidates.push(chain.finalized_head());
+		candidates.sort_by_key(|block| (Reverse(chain.number(block)), *block));
+		candidates.dedup();
+		let supermajority = |count: usize| count * 3 > self.voters.len() * 2;
+		candidates.into_iter().find(|block| supermajority(count(chain, votes, *block)))
+	}
+
+	/// Whether the precommits still to come could give `block` more than two thirds of them.
+	fn may_finalize(&self, chain: &Chain, round: &Round, block: Hash) -> bool {
+		let missing = self.voters.len() - round.precommits.len();
+		(count(chain, &round.precommits, block) + missing) * 3 > self.voters.len() * 2
+	}
+
+	/// The highest ancestor of the ghost of `round` that it may still finalize.
+	fn estimate(&self, chain: &Chain, round: &Round) -> Option<Hash> {
+		let ghost = self.supermajority(chain, &round.prevotes)?;
+		let mut route = chain.route(ghost);
+		route.insert(0, chain.finalized_head());
+		route.into_iter().rev().find(|block| self.may_finalize(chain, round, *block))
+	}
+
+	/// Whether more than two thirds of the precommits of `round` arrived, and no block after its
+	/// estimate can be finalized by the round anymore.
+	fn is_over(&self, chain: &Chain, round: &Round) -> bool {
+		if round.precommits.len() * 3 <= self.voters.len() * 2 {
+			return false;
+		}
+		let (Some(ghost), Some(estimate)) =
+			(self.supermajority(chain, &round.prevotes), self.estimate(chain, round))
+		else {
+			return false;
+		};
+		estimate != ghost
+			|| !round.precommits.values().flat_map(|vote| chain.route(*vote)).any(|block| {
+				let parent = chain.block(&block).map(|block| block.header.parent_hash);
+				parent == Some(ghost) && self.may_finalize(chain, round, block)
+			})
+	}
+}
+
+/// The finality gadget of a node: the votes it received, and the ones it cast if it is a voter.
+pub struct Voter {
+	finality: Finality,
+	/// Received votes for blocks that aren't imported yet, or for rounds after the next one.
+	pending: Vec<SignedVote>,
+	/// The votes of the node in the current and previous rounds, which are gossiped again until
+	/// their round is over, as some are lost.
+	cast: Vec<SignedVote>,
+}
+
+impl Voter {
+	pub fn new(voters: Vec<(types::AccountId, types::SessionKeys)>) -> Self {
+		Self { finality: Finality::new(voters), pending: Vec::new(), cast: Vec::new() }
+	}
+
+	pub fn finality(&self) -> &Finality {
+		&self.finality
+	}
+
+	/// Change the voters, e.g. to the validators of a new session, unless they are the same.
+	pub fn set_voters(&mut self, voters: &[(types::AccountId, types::SessionKeys)]) {
+		if self.finality.voters() != voters {
+			self.finality.set_voters(voters.to_vec());
+		}
+	}
+
+	/// Keep a vote received from another node, which `count` counts once its block is known.
+	pub fn receive(&mut self, vote: SignedVote) {
+		self.pending.push(vote);
+	}
+
+	/// Count the received votes for the blocks `chain` knows in the rounds the gadget accepts,
+	/// keeping the others for later. Returns the highest block they finalized, if any.
+	pub fn count(&mut self, chain: &mut Chain, local_now: types::Moment) -> Option<Hash> {
+		let Voter { finality, pending, .. } = self;
+		let mut finalized = None;
+		pending.retain(|signed| {
+			let (vote, round) = (signed.vote, finality.round());
+			if vote.round.saturating_add(1) < round {
+				return false;
+			}
+			let unknown = chain.block(&vote.hash).is_none() && vote.hash != chain.finalized_head();
+			if unknown || vote.round > round.saturating_add(1) {
+				return true;
+			}
+			if let Ok(Some(block)) = finality.vote(chain, signed.clone(), local_now) {
+				finalized = Some(block);
+			}
+			false
+		});
+		finalized
+	}
+
+	/// Count the received votes, then cast the vote of `voter` with its session key `key` at the
+	/// first stage of the current round it didn't vote at, if it is a voter and can vote yet.
+	/// Returns the votes of the node to gossip, and the highest block finalized, if any.
+	pub fn vote(
+		&mut self,
+		chain: &mut Chain,
+		voter: &types::AccountId,
+		key: &keystore::Signer,
+		local_now: types::Moment,
+	) -> (Vec<SignedVote>, Option<Hash>) {
+		let mut finalized = self.count(chain, local_now);
+		let round = self.finality.round();
+		let stage = [Stage::Prevote, Stage::Precommit].into_iter().find(|stage| {
+			!self.cast.iter().any(|v| v.vote.round == round && v.vote.stage == *stage)
+		});
+		let target = stage.and_then(|stage| Some((stage, self.finality.target(chain, stage)?)));
+		if let (Some((stage, hash)), true) =
+			(target, Finality::is_voter(self.finality.voters(), voter))
+		{
+			let vote = SignedVote::new(voter.clone(), Vote { round, stage, hash }, key);
+			self.cast.push(vote.clone());
+			self.pending.push(vote);
+			finalized = self.count(chain, local_now).or(finalized);
+		}
+		let round = self.finality.round();
+		self.cast.retain(|signed| signed.vote.round.saturating_add(1) >= round);
+		(self.cast.clone(), finalized)
+	}
+}
+
+/// How many of `votes` are for `block` or its descendants.
+fn count(chain: &Chain, votes: &Votes, block: Hash) -> usize {
+	votes.values().filter(|vote| chain.is_descendant(**vote, block)).count()
+}
+
+#[cfg(test)]
+mod test {
+	use super::{Finality, SignedVote, Stage, Vote, Voter};
+	use crate::{
+		chain::{self, Chain},
+		genesis,
+		hashing::Hash,
+		import_queue::{self, ImportError},
+		support, timestamp, types,
+		wallet::dev_account,
+		Runtime, RuntimeCall,
+	};
+
+	/// The block `author` produces on top of `state`, and the state after it.
+	fn block(state: &Runtime, author: &str) -> (types::Block, Runtime) {
+		let now = timestamp::Call::Set { now: (state.system.block_number() as u64 + 1) * 1_000 };
+		let extrinsics = vec![support::Extrinsic {
+			caller: None,
+			call: RuntimeCall::Timestamp(now),
+			signature: None,
+		}];
+		let block = import_queue::build_block(state, &dev_account(author), extrinsics).unwrap();
+		let mut next = state.clone();
+		next.execute_block(block.clone()).unwrap();
+		(block, next)
+	}
+
+	/// The voters with the session keys `GenesisBuilder` gives them.
+	fn authorities(voters: &[types::AccountId]) -> Vec<(types::AccountId, types::SessionKeys)> {
+		voters
+			.iter()
+			.map(|voter| (voter.clone(), genesis::session_keys(voter)))
+			.collect()
+	}
+
+	/// `vote` signed by the session key of `voter`.
+	fn signed(voter: &types::AccountId, vote: Vote) -> SignedVote {
+		SignedVote::new(voter.clone(), vote, &genesis::session_key(voter))
+	}
+
+	/// Import a block and return its hash.
+	fn import(chain: &mut Chain, block: types::Block) -> [u8; 32] {
+		let hash = chain::block_hash(&block);
+		chain.import(block, 10_000).unwrap();
+		hash
+	}
+
+	/// Let every voter prevote for `prevote`, then precommit to `precommit`, in `round`.
+	fn vote_round(
+		finality: &mut Finality,
+		chain: &mut Chain,
+		voters: &[types::AccountId],
+		round: u64,
+		(prevote, precommit): (Hash, Hash),
+	) -> Option<Hash> {
+		let mut finalized = None;
+		for (stage, hash) in [(Stage::Prevote, prevote), (Stage::Precommit, precommit)] {
+			for voter in voters {
+				let vote = Vote { round, stage, hash };
+				finalized =
+					finalized.or(finality.vote(chain, signed(voter, vote), 10_000).unwrap());
+			}
+		}
+		finalized
+	}
+
+	#[test]
+	fn supermajority_finalizes() {
+		let voters: Vec<_> = ["alice", "bob", "charlie"].iter().map(|v| dev_account(v)).collect();
+		let mut finality = Finality::new(authorities(&voters));
+		let mut chain = Chain::new(Runtime::new());
+		let (a1, a1_state) = block(&Runtime::new(), "alice");
+		let a1 = import(&mut chain, a1);
+		let a2 = import(&mut chain, block(&a1_state, "alice").0);
+
+		let prevote = |hash| Vote { round: 0, stage: Stage::Prevote, hash };
+		let precommit = |hash| Vote { round: 0, stage: Stage::Precommit, hash };
+		assert_eq!(finality.target(&chain, Stage::Prevote), Some(a2));
+		assert_eq!(
+			finality.vote(&mut chain, signed(&dev_account("dave"), prevote(a2)), 0),
+			Err("Not a voter")
+		);
+		assert_eq!(finality.vote(&mut chain, signed(&voters[0], prevote(a2)), 0), Ok(None));
+		assert_eq!(finality.vote(&mut chain, signed(&voters[1], prevote(a1)), 0), Ok(None));
+		assert_eq!(finality.target(&chain, Stage::Precommit), None);
+		assert_eq!(finality.vote(&mut chain, signed(&voters[2], prevote(a2)), 0), Ok(None));
+		// Every prevote is for a1 or its descendant a2, but two out of three aren't enough for a2.
+		assert_eq!(finality.target(&chain, Stage::Precommit), Some(a1));
+		assert_eq!(finality.vote(&mut chain, signed(&voters[0], precommit(a1)), 0), Ok(None));
+		assert_eq!(finality.vote(&mut chain, signed(&voters[1], precommit(a1)), 0), Ok(None));
+		assert_eq!(finality.round(), 0);
+		assert_eq!(finality.vote(&mut chain, signed(&voters[2], precommit(a1)), 0), Ok(Some(a1)));
+		assert_eq!(chain.finalized_head(), a1);
+		assert_eq!(finality.round(), 1);
+
+		assert_eq!(vote_round(&mut finality, &mut chain, &voters, 1, (a2, a2)), Some(a2));
+		assert_eq!(chain.finalized_number(), 2);
+	}
+
+	#[test]
+	fn votes_must_be_signed_and_for_near_rounds() {
+		let voters: Vec<_> = ["alice", "bob"].iter().map(|v| dev_account(v)).collect();
+		let mut finality = Finality::new(authorities(&voters));
+		let mut chain = Chain::new(Runtime::new());
+		let a1 = import(&mut chain, block(&Runtime::new(), "alice").0);
+		let prevote = |round| Vote { round, stage: Stage::Prevote, hash: a1 };
+
+		let mut forged = signed(&voters[1], prevote(0));
+		forged.voter = voters[0].clone();
+		assert_eq!(finality.vote(&mut chain, forged, 0), Err("Bad vote signature"));
+		let far = signed(&voters[0], prevote(2));
+		assert_eq!(finality.vote(&mut chain, far, 0), Err("Future round"));
+		let far = signed(&voters[0], prevote(u64::MAX));
+		assert_eq!(finality.vote(&mut chain, far, 0), Err("Future round"));
+		assert_eq!(finality.vote(&mut chain, signed(&voters[0], prevote(1)), 0), Ok(None));
+	}
+
+	#[test]
+	fn finalized_blocks_are_not_reverted() {
+		let voters: Vec<_> = ["alice", "bob"].iter().map(|v| dev_account(v)).collect();
+		let mut finality = Finality::new(authorities(&voters));
+		let mut chain = Chain::new(Runtime::new());
+		let genesis = Runtime::new();
+		let a1 = import(&mut chain, block(&genesis, "alice").0);
+		let b1 = import(&mut chain, block(&genesis, "bob").0);
+
+		assert_eq!(vote_round(&mut finality, &mut chain, &voters, 0, (a1, a1)), Some(a1));
+		assert!(chain.block(&b1).is_none());
+
+		// Forks from before the finalized block are refused.
+		let result = chain.import(block(&genesis, "charlie").0, 10_000);
+		assert_eq!(result, Err(ImportError::RevertsFinalized));
+		assert_eq!(chain.best_hash(), a1);
+	}
+
+	#[test]
+	fn later_rounds_build_on_the_estimate() {
+		let voters: Vec<_> =
+			["alice", "bob", "charlie", "dave"].iter().map(|v| dev_account(v)).collect();
+		let mut finality = Finality::new(authorities(&voters));
+		let mut chain = Chain::new(Runtime::new());
+		let genesis = Runtime::new();
+		let (a1, a1_state) = block(&genesis, "alice");
+		let a1 = import(&mut chain, a1);
+		let a2 = import(&mut chain, block(&a1_state, "alice").0);
+		let (b1, b1_state) = block(&genesis, "bob");
+		let (b2, b2_state) = block(&b1_state, "bob");
+		import(&mut chain, b1);
+
+		// The round may have finalized a1 elsewhere, as the precommit of dave is missing.
+		for voter in &voters {
+			let prevote = Vote { round: 0, stage: Stage::Prevote, hash: a1 };
+			let _ = finality.vote(&mut chain, signed(voter, prevote), 10_000);
+		}
+		for voter in &voters[..2] {
+			let precommit = Vote { round: 0, stage: Stage::Precommit, hash: a1 };
+			let _ = finality.vote(&mut chain, signed(voter, precommit), 10_000);
+		}
+		let precommit = Vote { round: 0, stage: Stage::Precommit, hash: chain.finalized_head() };
+		let _ = finality.vote(&mut chain, signed(&voters[2], precommit), 10_000);
+		assert_eq!((finality.round(), chain.finalized_number()), (1, 0));
+		// So prefer a1 to the fork b1, even once it is longer.
+		import(&mut chain, b2);
+		let b3 = import(&mut chain, block(&b2_state, "bob").0);
+		assert_eq!(chain.best_hash(), b3);
+		assert_eq!(finality.target(&chain, Stage::Prevote), Some(a1));
+
+		assert_eq!(vote_round(&mut finality, &mut chain, &voters, 1, (a2, a2)), Some(a2));
+		assert_eq!(chain.best_hash(), a2);
+		assert!(chain.block(&b3).is_none());
+	}
+
+	#[test]
+	fn a_lone_voter_finalizes_its_votes_and_gossips_them_until_their_round_is_over() {
+		let alice = dev_account("alice");
+		let mut voter = Voter::new(authorities(std::slice::from_ref(&alice)));
+		let mut chain = Chain::new(Runtime::new());
+		let a1 = import(&mut chain, block(&Runtime::new(), "alice").0);
+
+		let key = genesis::session_key(&alice);
+		let (cast, finalized) = voter.vote(&mut chain, &alice, &key, 10_000);
+		assert_eq!((cast.len(), finalized), (1, None));
+		assert_eq!(cast[0].vote, Vote { round: 0, stage: Stage::Prevote, hash: a1 });
+		let (cast, finalized) = voter.vote(&mut chain, &alice, &key, 10_000);
+		assert_eq!((cast.len(), finalized), (2, Some(a1)));
+		assert_eq!(chain.finalized_head(), a1);
+		// Nobody else votes, nor does a voter that isn't one.
+		let bob = dev_account("bob");
+		let (cast, _) = voter.vote(&mut chain, &bob, &genesis::session_key(&bob), 10_000);
+		assert_eq!(cast.len(), 2);
+	}
+}
@@ -0,0 +1,161 @@
+//! Entry points for fuzzing block execution, used by the targets in `fuzz/`: arbitrary bytes are
+//! decoded into blocks or calls and executed on a copy of a development state. Peers send the
+//! blocks a node imports, so whatever they decode to must be rejected or executed without a
+//! panic, and leave the state consistent, which is what every entry point asserts.
+//!
+//! Decoding rarely produces a block whose extrinsics are correctly signed, so `execute_calls`
+//! signs the calls it decodes with development keys instead, reaching the pallets.
+
+use crate::{
+	codec::{decode_all, Encode},
+	genesis::GenesisBuilder,
+	import_queue, support, types, wallet, Runtime, RuntimeCall,
+};
+
+/// The development accounts `genesis` funds, which sign the calls of `execute_calls`.
+pub const ACCOUNTS: [&str; 3] = ["alice", "bob", "charlie"];
+
+/// The time the blocks of `execute_calls` are built at.
+const NOW: types::Moment = 1_000;
+
+/// The state blocks are executed on: the development accounts are funded and alice is the sudo
+/// key.
+pub fn genesis() -> Runtime {
+	let genesis = ACCOUNTS.iter().fold(GenesisBuilder::new(), |genesis, name| {
+		genesis.endow(&wallet::dev_account(name), 1_000_000)
+	});
+	genesis
+		.sudo(&wallet::dev_account("alice"))
+		.build()
+		.expect("The genesis is valid")
+}
+
+/// Decode `data` as a block and execute it on a copy of `state`.
+pub fn execute_block(state: &Runtime, data: &[u8]) {
+	if let Ok(block) = decode_all::<types::Block>(data) {
+		execute(state, block);
+	}
+}
+
+/// Decode `data` as calls, each with the index of the account in `ACCOUNTS` dispatching it,
+/// and execute them on a copy of `state` as the extrinsics of the next block.
+pub fn execute_calls(state: &Runtime, data: &[u8]) {
+	let Ok(calls) = decode_all::<Vec<(u8, RuntimeCall)>>(data) else { return };
+	let Ok(now) = state.timestamp.create_inherent(NOW) else { return };
+	let genesis_hash = state.genesis_hash();
+	let mut nonces = ACCOUNTS.map(|name| state.system.get_nonce(&wallet::dev_account(name)));
+	let mut extrinsics = vec![support::Extrinsic {
+		caller: None,
+		call: RuntimeCall::Timestamp(now),
+		signature: None,
+	}];
+	for (account, call) in calls {
+		let account = usize::from(account) % ACCOUNTS.len();
+		let signer = wallet::dev_signer(ACCOUNTS[account]);
+		extrinsics.push(signer.sign(call, nonces[account], genesis_hash));
+		nonces[account] += 1;
+	}
+	let block = types::Block {
+		header: types::Header {
+			block_number: state.system.block_number() + 1,
+			parent_hash: import_queue::best_hash(state)
+				.unwrap_or(import_queue::GENESIS_PARENT_HASH),
+			extrinsics_root: import_queue::extrinsics_root(&extrinsics),
+			state_root: [0; 32],
+			author: wallet::dev_account(ACCOUNTS[0]),
+			spec_version: state.version().spec_version,
+		},
+		extrinsics,
+		seal: None,
+	};
+	execute(state, block);
+}
+
+/// Execute `block` on a copy of `state`. Whether it is valid or not, the balances must add up
+/// and the state must still encode and decode.
+fn execute(state: &Runtime, block: types::Block) {
+	let mut next = state.clone();
+	let _ = next.execute_block(block);
+	next.balances.assert_invariants();
+	let snapshot = next.export_state();
+	let restored = Runtime::import_state(&snapshot).expect("Exported states can be imported");
+	assert_eq!(restored.state_root(), next.state_root(), "Imported states have the same root");
+}
+
+/// Encoded inputs of `execute_calls`, from which the fuzzer starts mutating.
+pub fn seed_calls() -> Vec<Vec<u8>> {
+	let bob = wallet::dev_account("bob");
+	let calls = [
+		vec![(0u8, wallet::transfer(bob.clone(), 10))],
+		vec![(1, wallet::create_claim("document".to_string())), (1, wallet::transfer(bob, 5))],
+	];
+	calls.iter().map(Encode::encode).collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::{execute_block, execute_calls, genesis, seed_calls};
+	use crate::{
+		codec::{decode_all, Encode},
+		import_queue,
+		mock::Rng,
+		support, types, wallet, RuntimeCall,
+	};
+
+	/// `input` with a few bytes changed, inserted or cut off.
+	fn mutate(rng: &mut Rng, input: &[u8]) -> Vec<u8> {
+		let mut input = input.to_vec();
+		for _ in 0..=rng.below(4) {
+			match rng.below(3) {
+				0 if !input.is_empty() => {
+					let at = rng.below(input.len());
+					input[at] = rng.below(256) as u8;
+				},
+				1 if !input.is_empty() => {
+					input.truncate(rng.below(input.len()));
+				},
+				_ => input.insert(rng.below(input.len() + 1), rng.below(256) as u8),
+			}
+		}
+		input
+	}
+
+	#[test]
+	fn mutated_blocks_never_panic() {
+		let state = genesis();
+		let now = state.timestamp.create_inherent(1_000).unwrap();
+		let extrinsics = vec![
+			support::Extrinsic { caller: None, call: RuntimeCall::Timestamp(now), signature: None },
+			wallet::dev_signer("alice").sign(
+				wallet::transfer(wallet::dev_account("bob"), 10),
+				0,
+				state.genesis_hash(),
+			),
+		];
+		let author = wallet::dev_account("alice");
+		let block: types::Block = import_queue::build_block(&state, &author, extrinsics).unwrap();
+		let encoded = block.encode();
+		execute_block(&state, &encoded);
+
+		let mut rng = Rng(0x5eed);
+		for _ in 0..500 {
+			execute_block(&state, &mutate(&mut rng, &encoded));
+		}
+		for seed in seed_calls() {
+			for _ in 0..50 {
+				execute_calls(&state, &mutate(&mut rng, &seed));
+			}
+		}
+	}
+
+	#[test]
+	fn deeply_nested_calls_are_rejected() {
+		// Every sudo call wraps another one, so decoding recursed once per two bytes.
+		let mut data = vec![4, 0];
+		data.extend([2, 0].repeat(1_000_000));
+		data.extend(wallet::transfer(wallet::dev_account("bob"), 1).encode());
+		let calls = decode_all::<Vec<(u8, RuntimeCall)>>(&data).map(|_| ());
+		assert_eq!(calls, Err("Too deeply nested"));
+		execute_calls(&genesis(), &data);
+	}
+}
@@ -0,0 +1,336 @@
+//! The state a chain starts from, set up fluently with `GenesisBuilder`, and helpers to sign the
+//! extrinsics of development accounts and put them in blocks, so end-to-end tests of blocks read
+//! as what they do:
+//!
+//! ```ignore
+//! let alice = wallet::dev_account("alice");
+//! let state = GenesisBuilder::new().endow(&alice, 1_000).authority(&alice).build()?;
+//! let transfer = genesis::signed(&state, "alice", [wallet::transfer(bob, 10)]);
+//! let block = genesis::block(&state, "alice", transfer)?;
+//! ```
+
+use crate::{
+	codec::Encode, hashing, import_queue, inherents, keystore, signature::Scheme, types, wallet,
+	Runtime, RuntimeCall,
+};
+
+/// The time `block` puts in the timestamp inherent, the first block being at genesis.
+const BLOCK_TIME: types::Moment = 1_000;
+
+#[derive(Debug, Clone, Default)]
+pub struct GenesisBuilder {
+	endowments: Vec<(types::AccountId, types::Balance)>,
+	claims: Vec<(types::AccountId, types::Content)>,
+	authorities: Vec<(types::AccountId, types::SessionKeys)>,
+	sudo: Option<types::AccountId>,
+}
+
+impl GenesisBuilder {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Give `account` a free balance of `amount`.
+	pub fn endow(mut self, account: &types::AccountId, amount: types::Balance) -> Self {
+		self.endowments.push((account.clone(), amount));
+		self
+	}
+
+	/// A claim of `content` by `account`, which pays the deposit of the claim out of its
+	/// endowment.
+	pub fn claim(mut self, account: &types::AccountId, content: impl Into<types::Content>) -> Self {
+		self.claims.push((account.clone(), content.into()));
+		self
+	}
+
+	/// Make `account` a validator of the first session, with the keys of `session_keys`.
+	pub fn authority(self, account: &types::AccountId) -> Self {
+		self.authority_with_keys(account, session_keys(account))
+	}
+
+	/// Make `account` a validator of the first session, sealing its blocks with the key of the
+	/// public session keys `keys`, which its node has in its keystore.
+	pub fn authority_with_keys(
+		mut self,
+		account: &types::AccountId,
+		keys: types::SessionKeys,
+	) -> Self {
+		self.authorities.push((account.clone(), keys));
+		self
+	}
+
+	pub fn sudo(mut self, account: &types::AccountId) -> Self {
+		self.sudo = Some(account.clone());
+		self
+	}
+
+	/// The genesis state, or why it can't be set up, e.g. a claim that can't pay its deposit.
+	pub fn build(self) -> Result<Runtime, &'static str> {
+		let mut runtime = Runtime::new();
+		for (account, amount) in &self.endowments {
+			runtime.balances.set_balance(account, *amount);
+		}
+		for (account, content) in self.claims {
+			let Runtime { balances, proof_of_existence, .. } = &mut runtime;
+			proof_of_existence.create_claim(balances, account, content)?;
+		}
+		runtime.session.initialize_validators(self.authorities)?;
+		if let Some(key) = self.sudo {
+			runtime.sudo.initialize_key(key);
+		}
+		runtime.note_changes();
+		Ok(runtime)
+	}
+}
+
+/// The session key of an authority of `GenesisBuilder`, derived from its account. Anyone can
+/// derive it, so it only seals the blocks of development chains.
+pub fn session_key(account: &types::AccountId) -> keystore::Signer {
+	let seed = hashing::blake2_256(&("session keys", account).encode());
+	keystore::Signer::new(Scheme::Sr25519, seed)
+}
+
+/// The public session keys of an authority of `GenesisBuilder`, those of `session_key`.
+pub fn session_keys(account: &types::AccountId) -> types::SessionKeys {
+	let mut keys = [0; 32];
+	keys.copy_from_slice(session_key(account).account().as_ref());
+	keys
+}
+
+/// `calls` as extrinsics of the development account `signer`, at the nonces following its
+/// nonce in `state`.
+pub fn signed(
+	state: &Runtime,
+	signer: &str,
+	calls: impl IntoIterator<Item = RuntimeCall>,
+) -> Vec<types::Extrinsic> {
+	let signer = wallet::dev_signer(signer);
+	let nonce = state.system.get_nonce(signer.account());
+	let genesis_hash = state.genesis_hash();
+	calls
+		.into_iter()
+		.zip(nonce..)
+		.map(|(call, nonce)| signer.sign(call, nonce, genesis_hash))
+		.collect()
+}
+
+/// The next block on `state`, authored and sealed by the development account `author`: the
+/// inherents followed by `extrinsics`.
+pub fn block(
+	state: &Runtime,
+	author: &str,
+	extrinsics: Vec<types::Extrinsic>,
+) -> Result<types::Block, &'static str> {
+	let now = BLOCK_TIME * types::Moment::from(state.system.block_number() + 1);
+	let author = wallet::dev_account(author);
+	let mut block = inherents::create_inherents(state, now)?;
+	block.extend(extrinsics);
+	let block = import_queue::build_block(state, &author, block)?;
+	Ok(import_queue::seal(block, &session_key(&author)))
+}
+
+#[cfg(test)]
+mod test {
+	use super::{block, session_keys, signed, GenesisBuilder};
+	use crate::{
+		balances,
+		import_queue::{self, ImportError},
+		proof_of_existence::Config,
+		treasury, wallet, Runtime, RuntimeCall,
+	};
+
+	#[test]
+	fn blocks_execute_on_the_genesis_state() {
+		let alice = wallet::dev_account("alice");
+		let bob = wallet::dev_account("bob");
+		let mut state = GenesisBuilder::new()
+			.endow(&alice, 1_000)
+			.claim(&alice, "document")
+			.authority(&alice)
+			.sudo(&alice)
+			.build()
+			.unwrap();
+		assert_eq!(state.balances.balance(&alice), 1_000 - <Runtime as Config>::CLAIM_DEPOSIT);
+		assert_eq!(state.proof_of_existence.get_claim(&"document".to_string()), Some(&alice));
+		assert_eq!(state.session.validators(), &[(alice.clone(), session_keys(&alice))]);
+
+		let transfers = [wallet::transfer(bob.clone(), 10), wallet::transfer(bob.clone(), 20)];
+		let first = block(&state, "alice", signed(&state, "alice", transfers)).unwrap();
+		assert_eq!(import_queue::import(&mut state, first, 1_000).map(|b| b.block_number), Ok(1));
+		assert_eq!(state.balances.balance(&bob), 30);
+		// Only authorities author blocks.
+		let second = block(&state, "bob", Vec::new()).unwrap();
+		assert_eq!(import_queue::import(&mut state, second, 2_000), Err(ImportError::BadSeal));
+
+		let poor = GenesisBuilder::new().claim(&bob, "document").build();
+		assert_eq!(poor.map(|_| ()), Err("Insufficient balance"));
+	}
+
+	#[test]
+	fn validators_seal_with_the_key_of_their_session_keys() {
+		// A node authoring with its own key as its session key, as the default genesis has it.
+		let alice = wallet::dev_signer("alice");
+		let mut keys = [0; 32];
+		keys.copy_from_slice(alice.account().as_ref());
+		let genesis = GenesisBuilder::new().authority_with_keys(alice.account(), keys);
+		let mut state = genesis.build().unwrap();
+
+		let now = state.timestamp.create_inherent(super::BLOCK_TIME).unwrap();
+		let inherent = crate::support::Extrinsic {
+			caller: None,
+			call: RuntimeCall::Timestamp(now),
+			signature: None,
+		};
+		let unsealed = import_queue::build_block(&state, alice.account(), vec![inherent]).unwrap();
+		let result = import_queue::import(&mut state.clone(), unsealed.clone(), 1_000);
+		assert_eq!(result, Err(ImportError::BadSeal));
+		let sealed = import_queue::seal(unsealed, &alice);
+		assert_eq!(import_queue::import(&mut state, sealed, 1_000).map(|b| b.block_number), Ok(1));
+	}
+
+	#[test]
+	fn large_blocks_are_verified_in_parallel() {
+		let alice = wallet::dev_account("alice");
+		let bob = wallet::dev_account("bob");
+		let state = GenesisBuilder::new().endow(&alice, 10_000).authority(&alice).build().unwrap();
+		let transfers = (0..100).map(|_| wallet::transfer(bob.clone(), 1));
+		let mut extrinsics = signed(&state, "alice", transfers);
+		let mut next = state.clone();
+		next.execute_block(block(&state, "alice", extrinsics.clone()).unwrap()).unwrap();
+		assert_eq!(next.balances.balance(&bob), 100);
+
+		// A forged extrinsic in any chunk makes the whole block invalid.
+		extrinsics[77].call = wallet::transfer(bob.clone(), 1_000);
+		assert_eq!(block(&state, "alice", extrinsics).map(|_| ()), Err("Bad signature"));
+	}
+
+	#[test]
+	fn transfers_dispatched_in_parallel_leave_the_state_of_dispatching_them_in_order() {
+		let [alice, bob, charlie, dave, eve, ferdie] =
+			["alice", "bob", "charlie", "dave", "eve", "ferdie"].map(wallet::dev_account);
+		let treasury = <Runtime as treasury::Config>::account_id();
+		let mut genesis = GenesisBuilder::new().authority(&alice).endow(&treasury, 1_000);
+		for account in [&alice, &bob, &charlie, &dave, &eve] {
+			genesis = genesis.endow(account, 10_000);
+		}
+		let state = genesis.endow(&ferdie, 100).build().unwrap();
+
+		let new_accounts =
+			(0..30).map(|i| wallet::transfer(wallet::dev_account(&format!("new{i}")), 50));
+		let to_charlie = (0..30).map(|_| wallet::transfer(charlie.clone(), 7));
+		// The last transfer of dave fails, the one of ferdie reaps it.
+		let to_dave = (0..30).map(|_| wallet::transfer(dave.clone(), 3));
+		let to_dave = to_dave.chain([wallet::transfer(dave.clone(), 1_000_000)]);
+		let recipients = vec![(bob.clone().into(), 11), (ferdie.clone().into(), 13)];
+		let to_many = RuntimeCall::Balances(balances::Call::TransferMany { recipients });
+		let to_eve = [to_many].into_iter().chain((0..20).map(|_| wallet::transfer(eve.clone(), 1)));
+		let runs = [
+			signed(&state, "bob", new_accounts),
+			signed(&state, "charlie", to_dave),
+			signed(&state, "dave", to_charlie),
+			signed(&state, "eve", to_eve),
+			signed(&state, "ferdie", [wallet::transfer(eve.clone(), 99)]),
+		];
+		// The extrinsics of all callers, taking turns.
+		let mut runs = runs.map(Vec::into_iter);
+		let mut extrinsics = Vec::new();
+		loop {
+			let turn: Vec<_> = runs.iter_mut().filter_map(Iterator::next).collect();
+			if turn.is_empty() {
+				break;
+			}
+			extrinsics.extend(turn);
+		}
+		// A transfer paying the author is dispatched in order, between two batches.
+		extrinsics
+			.insert(70, signed(&state, "alice", [wallet::transfer(bob.clone(), 5)]).remove(0));
+		let block = block(&state, "alice", extrinsics).unwrap();
+
+		let mut in_order = state.clone();
+		in_order.execute_block_with(block.clone(), usize::MAX).unwrap();
+		let mut in_parallel = state.clone();
+		in_parallel.execute_block_with(block, 2).unwrap();
+		assert_eq!(in_parallel.balances.balance(&ferdie), 0);
+		assert_eq!(in_parallel.state_root(), in_order.state_root());
+		assert_eq!(in_parallel.balances.total_issuance(), in_order.balances.total_issuance());
+		let events = |state: &Runtime| format!("{:?}", state.system.events());
+		assert_eq!(events(&in_parallel), events(&in_order));
+	}
+
+	#[test]
+	fn failed_scheduled_calls_leave_no_trace() {
+		use crate::{multisig, support::Hooks};
+
+		let [alice, bob] = ["alice", "bob"].map(wallet::dev_account);
+		let mut state = GenesisBuilder::new().authority(&alice).build().unwrap();
+		// The multisig account has nothing to transfer, so the call of the operation fails.
+		let call = wallet::transfer(bob.clone(), 1_000);
+		let now = multisig::Timepoint { height: 0, index: 0 };
+		let first = state.multisig.as_multi(now, bob.clone(), 2, vec![alice.clone()], None, call);
+		assert!(matches!(first, Ok(None)));
+		let call = wallet::transfer(bob.clone(), 1_000);
+		let approval = RuntimeCall::Multisig(multisig::Call::AsMulti {
+			threshold: 2,
+			other_signatories: vec![bob.clone()],
+			maybe_timepoint: Some(now),
+			call: Box::new(call.clone()),
+		});
+		state.scheduler.schedule(alice.clone(), 1, approval).unwrap();
+
+		state.on_initialize(1);
+		// The approval completing the operation removed it, until the call failed.
+		let id = multisig::Pallet::<Runtime>::multi_account_id(&alice, vec![bob], 2).unwrap();
+		let hash = multisig::Pallet::<Runtime>::call_hash(&call);
+		assert!(state.multisig.multisig(&id, &hash).is_some());
+	}
+
+	#[test]
+	fn approved_motions_have_the_collective_origin() {
+		use crate::{
+			balances, collective,
+			support::{DispatchResult, RawOrigin},
+			tx_pause, types,
+		};
+
+		fn collective(
+			state: &mut Runtime,
+			who: &types::AccountId,
+			call: collective::Call<Runtime>,
+		) -> DispatchResult {
+			let call = RuntimeCall::Collective(call);
+			state.dispatch_with_post_info(RawOrigin::Signed(who.clone()), call).map(|_| ())
+		}
+		let propose =
+			|threshold, call| collective::Call::Propose { threshold, call: Box::new(call) };
+
+		let [alice, bob, charlie] = ["alice", "bob", "charlie"].map(wallet::dev_account);
+		let mut state = GenesisBuilder::new().authority(&alice).build().unwrap();
+		let members = vec![alice.clone(), bob.clone(), charlie.clone()];
+		state.collective.set_members(members).unwrap();
+		let pause = RuntimeCall::TxPause(tx_pause::Call::Pause {
+			pallet: "Balances".to_string(),
+			call: "Transfer".to_string(),
+		});
+
+		// A motion of one of the three members isn't a majority, one of two is.
+		assert_eq!(collective(&mut state, &alice, propose(1, pause.clone())), Err("Bad origin"));
+		assert_eq!(collective(&mut state, &alice, propose(2, pause)), Ok(()));
+		let proposal = state.collective.proposals()[0];
+		let vote = collective::Call::Vote { proposal, index: 0, approve: true };
+		assert_eq!(collective(&mut state, &bob, vote), Ok(()));
+		let close = collective::Call::Close { proposal, index: 0 };
+		assert_eq!(collective(&mut state, &bob, close), Ok(()));
+		assert!(state.tx_pause.is_paused("Balances", "Transfer"));
+
+		// Calls for root alone stay out of reach of the collective.
+		let set_balance = balances::Call::SetBalance { who: alice.clone(), amount: 1_000 };
+		let motion = propose(2, RuntimeCall::Balances(set_balance));
+		assert_eq!(collective(&mut state, &alice, motion), Ok(()));
+		let proposal = state.collective.proposals()[0];
+		let vote = collective::Call::Vote { proposal, index: 1, approve: true };
+		assert_eq!(collective(&mut state, &bob, vote), Ok(()));
+		let close = collective::Call::Close { proposal, index: 1 };
+		assert_eq!(collective(&mut state, &bob, close), Err("Bad origin"));
+		assert_eq!(state.balances.balance(&alice), 0);
+	}
+}
@@ -0,0 +1,451 @@
+//! A gRPC service for clients that prefer typed calls to the JSON of the `rest` module, defined
+//! in `proto/node.proto`: the `blockchain.Node` service, with
+//!
+//! - `SubmitExtrinsic`: submit an encoded extrinsic to the transaction pool, in either encoding
+//!   `Runtime::decode_extrinsic` reads, checked with `Runtime::validate_extrinsic` first.
+//! - `GetBalance`: the free and reserved balance of an account, by SS58 address.
+//! - `GetClaim`: the claim stored under a content hash.
+//! - `StreamBlocks`: the blocks of the best chain from a number on, with their events.
+//! - `GetFinalizedHead`: the number and hash of the last finalized block.
+//!
+//! It is served over the `http2` module on plain TCP, without TLS or message compression. The
+//! node serves one connection at a time between blocks, like the REST API, and closes it once
+//! the client was idle for `IDLE_TIMEOUT`, so a stream of blocks ends at the best block rather
+//! than following the chain.
+
+use std::{io, net::TcpStream, time::Duration};
+
+use crate::{
+	chain::{self, Chain},
+	codec,
+	http2::{self, Connection, Request},
+	import_queue,
+	transaction_pool::TransactionPool,
+	types, Runtime,
+};
+
+/// The definitions of the service, for clients to generate their code from.
+pub const PROTO: &str = include_str!("../proto/node.proto");
+/// What the paths of the methods of the service start with.
+pub const SERVICE_PATH: &str = "/blockchain.Node/";
+/// How long a connection can be idle before the node closes it.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The status a call ended with, if it failed, with its code as in the gRPC specification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+	pub code: u32,
+	pub message: String,
+}
+
+impl Status {
+	pub const INVALID_ARGUMENT: u32 = 3;
+	pub const NOT_FOUND: u32 = 5;
+	pub const UNIMPLEMENTED: u32 = 12;
+
+	fn new(code: u32, message: &str) -> Self {
+		Self { code, message: message.to_string() }
+	}
+
+	fn invalid(message: &str) -> Self {
+		Self::new(Self::INVALID_ARGUMENT, message)
+	}
+}
+
+/// A field of a protobuf message, by its wire type. Fixed-size fields are read but not used.
+enum Field<'a> {
+	Varint(u64),
+	Bytes(&'a [u8]),
+	Fixed,
+}
+
+fn read_varint(input: &mut &[u8]) -> Result<u64, Status> {
+	let mut value = 0;
+	for shift in (0..64).step_by(7) {
+		let (&byte, rest) = input.split_first().ok_or(Status::invalid("Invalid message"))?;
+		*input = rest;
+		value |= u64::from(byte & 0x7f) << shift;
+		if byte & 0x80 == 0 {
+			return Ok(value);
+		}
+	}
+	Err(Status::invalid("Invalid message"))
+}
+
+/// The fields of a protobuf message, by number, in order.
+fn fields(mut input: &[u8]) -> Result<Vec<(u64, Field<'_>)>, Status> {
+	let input = &mut input;
+	let mut fields = Vec::new();
+	while !input.is_empty() {
+		let key = read_varint(input)?;
+		let mut skip = |len: usize| {
+			let bytes = input.get(..len).ok_or(Status::invalid("Invalid message"))?;
+			*input = &input[len..];
+			Ok::<_, Status>(bytes)
+		};
+		let field = match key & 0x7 {
+			0 => Field::Varint(read_varint(input)?),
+			1 => skip(8).map(|_| Field::Fixed)?,
+			2 => {
+				let len = read_varint(input)? as usize;
+				let bytes = input.get(..len).ok_or(Status::invalid("Invalid message"))?;
+				*input = &input[len..];
+				Field::Bytes(bytes)
+			},
+			5 => skip(4).map(|_| Field::Fixed)?,
+			_ => return Err(Status::invalid("Invalid message")),
+		};
+		fields.push((key >> 3, field));
+	}
+	Ok(fields)
+}
+
+/// The last value of the length-delimited field `number`, or the empty default.
+fn bytes_field<'a>(fields: &[(u64, Field<'a>)], number: u64) -> &'a [u8] {
+	let values = fields.iter().filter(|(n, _)| *n == number);
+	let mut bytes = values.filter_map(|(_, field)| match field {
+		Field::Bytes(bytes) => Some(*bytes),
+		_ => None,
+	});
+	bytes.next_back().unwrap_or_default()
+}
+
+fn varint_field(fields: &[(u64, Field<'_>)], number: u64) -> u64 {
+	let values = fields.iter().filter(|(n, _)| *n == number);
+	let mut varints = values.filter_map(|(_, field)| match field {
+		Field::Varint(value) => Some(*value),
+		_ => None,
+	});
+	varints.next_back().unwrap_or_default()
+}
+
+fn varint(dest: &mut Vec<u8>, mut value: u64) {
+	while value >= 0x80 {
+		dest.push(value as u8 | 0x80);
+		value >>= 7;
+	}
+	dest.push(value as u8);
+}
+
+fn put_varint(dest: &mut Vec<u8>, number: u64, value: u64) {
+	varint(dest, number << 3);
+	varint(dest, value);
+}
+
+fn put_bytes(dest: &mut Vec<u8>, number: u64, bytes: &[u8]) {
+	varint(dest, number << 3 | 2);
+	varint(dest, bytes.len() as u64);
+	dest.extend(bytes);
+}
+
+/// A `Block` message of `block`, on `chain`.
+fn block_message(chain: &Chain, block: &types::Block) -> Vec<u8> {
+	let (header, hash) = (&block.header, chain::block_hash(block));
+	let mut message = Vec::new();
+	put_varint(&mut message, 1, header.block_number.into());
+	put_bytes(&mut message, 2, &hash);
+	put_bytes(&mut message, 3, &header.parent_hash);
+	put_bytes(&mut message, 4, &header.state_root);
+	put_bytes(&mut message, 5, header.author.to_string().as_bytes());
+	for extrinsic in &block.extrinsics {
+		put_bytes(&mut message, 6, &codec::Encode::encode(extrinsic));
+	}
+	for extrinsic in &block.extrinsics {
+		put_bytes(&mut message, 7, Runtime::call_name(&extrinsic.call).as_bytes());
+	}
+	let records = chain.state_at(&hash).map_or(&[][..], |state| state.system.events());
+	for record in records {
+		let mut event = Vec::new();
+		if let Some(extrinsic) = record.extrinsic_index {
+			put_varint(&mut event, 1, extrinsic.into());
+		}
+		put_bytes(&mut event, 2, record.event.pallet().as_bytes());
+		put_bytes(&mut event, 3, record.event.name().as_bytes());
+		for account in record.event.accounts() {
+			put_bytes(&mut event, 4, account.to_string().as_bytes());
+		}
+		put_bytes(&mut event, 5, format!("{:?}", record.event).as_bytes());
+		put_bytes(&mut message, 8, &event);
+	}
+	message
+}
+
+/// Answer the call of `method` with the protobuf message `request` on the best state of
+/// `chain`, with the messages of the response. Submitted extrinsics are added to `pool`.
+pub fn handle(
+	chain: &Chain,
+	pool: &mut TransactionPool,
+	method: &str,
+	request: &[u8],
+) -> Result<Vec<Vec<u8>>, Status> {
+	let fields = fields(request)?;
+	let mut response = Vec::new();
+	match method {
+		"SubmitExtrinsic" => {
+			let bytes = bytes_field(&fields, 1);
+			let extrinsic = Runtime::decode_extrinsic(bytes)
+				.map_err(|_| Status::invalid("Invalid extrinsic"))?;
+			let hash = pool
+				.submit(chain.state(), extrinsic)
+				.map_err(|e| Status::invalid(&format!("Invalid transaction: {:?}", e)))?;
+			put_bytes(&mut response, 1, &hash);
+		},
+		"GetBalance" => {
+			let account = std::str::from_utf8(bytes_field(&fields, 1)).ok();
+			let account = account.and_then(|account| account.parse::<types::AccountId>().ok());
+			let account = account.ok_or(Status::invalid("Invalid account"))?;
+			let balances = &chain.state().balances;
+			put_bytes(&mut response, 1, account.to_string().as_bytes());
+			put_bytes(&mut response, 2, balances.balance(&account).to_string().as_bytes());
+			let reserved = balances.reserved_balance(&account).to_string();
+			put_bytes(&mut response, 3, reserved.as_bytes());
+		},
+		"GetClaim" => {
+			let hash = bytes_field(&fields, 1).try_into();
+			let hash = hash.map_err(|_| Status::invalid("Invalid claim hash"))?;
+			let claim = chain.state().proof_of_existence.get_claim_details_by_hash(&hash);
+			let claim = claim.ok_or(Status::new(Status::NOT_FOUND, "Claim not found"))?;
+			put_bytes(&mut response, 1, claim.owner.to_string().as_bytes());
+			put_varint(&mut response, 2, claim.block_number.into());
+			put_bytes(&mut response, 3, claim.deposit.to_string().as_bytes());
+		},
+		"StreamBlocks" => {
+			let from = types::BlockNumber::try_from(varint_field(&fields, 1));
+			let from = from.map_err(|_| Status::invalid("Invalid block number"))?;
+			let blocks = chain.best_chain_blocks(from, chain.best_number());
+			return Ok(blocks.iter().map(|block| block_message(chain, block)).collect());
+		},
+		"GetFinalizedHead" => {
+			let mut hash = chain.finalized_head();
+			if hash == import_queue::GENESIS_PARENT_HASH {
+				hash = chain.state().genesis_hash();
+			}
+			put_varint(&mut response, 1, chain.finalized_number().into());
+			put_bytes(&mut response, 2, &hash);
+		},
+		_ => return Err(Status::new(Status::UNIMPLEMENTED, "Unknown method")),
+	}
+	Ok(vec![response])
+}
+
+/// The message of a gRPC request body, which is one length-prefixed, uncompressed message.
+fn request_message(body: &[u8]) -> Result<&[u8], Status> {
+	let Some((&[compressed, a, b, c, d], message)) = body.split_first_chunk::<5>() else {
+		return Err(Status::invalid("Invalid request"));
+	};
+	if compressed != 0 {
+		return Err(Status::new(Status::UNIMPLEMENTED, "Compressed messages"));
+	}
+	if message.len() != u32::from_be_bytes([a, b, c, d]) as usize {
+		return Err(Status::invalid("Invalid request"));
+	}
+	Ok(message)
+}
+
+fn respond(
+	chain: &Chain,
+	pool: &mut TransactionPool,
+	connection: &mut Connection<&TcpStream>,
+	request: Request,
+) -> io::Result<()> {
+	let (id, headers) = (request.stream, &request.headers);
+	let grpc = http2::header(headers, "content-type")
+		.is_some_and(|content_type| content_type.starts_with("application/grpc"));
+	if http2::header(headers, ":method") != Some("POST") || !grpc {
+		return connection.send_headers(id, &[(":status", "415")], true);
+	}
+	let path = http2::header(headers, ":path").unwrap_or_default();
+	let result = match path.strip_prefix(SERVICE_PATH) {
+		Some(method) => {
+			request_message(&request.body).and_then(|message| handle(chain, pool, method, message))
+		},
+		None => Err(Status::new(Status::UNIMPLEMENTED, "Unknown service")),
+	};
+	let response = [(":status", "200"), ("content-type", "application/grpc")];
+	match result {
+		Ok(messages) => {
+			connection.send_headers(id, &response, false)?;
+			for message in messages {
+				let len = (message.len() as u32).to_be_bytes();
+				connection.send_data(id, &[&[0][..], &len, &message].concat())?;
+			}
+			connection.send_headers(id, &[("grpc-status", "0")], true)
+		},
+		// A response without messages has its status in its headers.
+		Err(status) => {
+			let code = status.code.to_string();
+			let status = [("grpc-status", code.as_str()), ("grpc-message", &status.message)];
+			connection.send_headers(id, &[&response[..], &status].concat(), true)
+		},
+	}
+}
+
+/// Serve the calls of a client on `stream` until it closes the connection or is idle for
+/// `IDLE_TIMEOUT`.
+pub fn handle_connection(
+	chain: &Chain,
+	pool: &mut TransactionPool,
+	stream: TcpStream,
+) -> io::Result<()> {
+	stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+	let mut connection = Connection::accept(&stream)?;
+	loop {
+		let request = match connection.next_request() {
+			Ok(Some(request)) => request,
+			Ok(None) => return Ok(()),
+			Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+				return connection.go_away(0);
+			},
+			Err(e) => {
+				let _ = connection.go_away(http2::PROTOCOL_ERROR);
+				return Err(e);
+			},
+		};
+		respond(chain, pool, &mut connection, request)?;
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::{
+		io::Write,
+		net::{Shutdown, TcpListener, TcpStream},
+	};
+
+	use super::{Status, SERVICE_PATH};
+	use crate::{
+		balances,
+		chain::Chain,
+		codec::Encode,
+		http2::{self, Decoder, Frame},
+		proof_of_existence,
+		transaction_pool::TransactionPool,
+		wallet::{dev_account, dev_signer},
+		Runtime, RuntimeCall,
+	};
+
+	fn chain() -> Chain {
+		let mut genesis = Runtime::new();
+		genesis.balances.set_balance(&dev_account("alice"), 100);
+		let _ = genesis.proof_of_existence.create_claim(
+			&mut genesis.balances,
+			dev_account("alice"),
+			"document".to_string(),
+		);
+		Chain::new(genesis)
+	}
+
+	fn message(number: u64, bytes: &[u8]) -> Vec<u8> {
+		let mut message = Vec::new();
+		super::put_bytes(&mut message, number, bytes);
+		message
+	}
+
+	/// The length-delimited fields of `message`, as strings.
+	fn strings(message: &[u8]) -> Vec<(u64, String)> {
+		let fields = super::fields(message).unwrap().into_iter();
+		let strings = fields.filter_map(|(number, field)| match field {
+			super::Field::Bytes(bytes) => Some((number, String::from_utf8_lossy(bytes).into())),
+			_ => None,
+		});
+		strings.collect()
+	}
+
+	#[test]
+	fn calls_are_answered_with_protobuf_messages() {
+		let (chain, mut pool) = (chain(), TransactionPool::new());
+		let alice = dev_account("alice");
+		let mut call = |method, request: &[u8]| super::handle(&chain, &mut pool, method, request);
+
+		let balance = call("GetBalance", &message(1, alice.to_string().as_bytes())).unwrap();
+		let expected = [(1, alice.to_string()), (2, "99".to_string()), (3, "1".to_string())];
+		assert_eq!(strings(&balance[0]), expected);
+		let invalid = call("GetBalance", &message(1, b"alice"));
+		assert_eq!(invalid, Err(Status::invalid("Invalid account")));
+
+		let hash = proof_of_existence::Pallet::<Runtime>::hash_content(&"document".to_string());
+		let claim = call("GetClaim", &message(1, &hash)).unwrap();
+		assert_eq!(strings(&claim[0]), [(1, alice.to_string()), (3, "1".to_string())]);
+		let missing = call("GetClaim", &message(1, &[0; 32])).unwrap_err();
+		assert_eq!(missing.code, Status::NOT_FOUND);
+
+		let transfer = RuntimeCall::Balances(balances::Call::Transfer {
+			to: dev_account("bob").into(),
+			amount: 5,
+		});
+		let extrinsic = dev_signer("alice").sign(transfer, 0, chain.state().genesis_hash());
+		let submitted = call("SubmitExtrinsic", &message(1, &extrinsic.encode())).unwrap();
+		let hash = crate::hashing::blake2_256(&extrinsic.encode());
+		assert_eq!(submitted, [message(1, &hash)]);
+		let transfer =
+			RuntimeCall::Balances(balances::Call::Transfer { to: alice.into(), amount: 5 });
+		let unfunded = dev_signer("bob").sign(transfer, 0, chain.state().genesis_hash());
+		let unfunded = call("SubmitExtrinsic", &message(1, &unfunded.encode()));
+		assert_eq!(unfunded, Err(Status::invalid("Invalid transaction: Payment")));
+
+		assert_eq!(call("StreamBlocks", &[]), Ok(Vec::new()));
+		assert_eq!(call("Unknown", &[]).unwrap_err().code, Status::UNIMPLEMENTED);
+		assert_eq!(call("GetBalance", &[0xff]).unwrap_err().message, "Invalid message");
+		let mut genesis = Vec::new();
+		super::put_varint(&mut genesis, 1, 0);
+		super::put_bytes(&mut genesis, 2, &chain.state().genesis_hash());
+		assert_eq!(call("GetFinalizedHead", &[]), Ok(vec![genesis]));
+		let methods =
+			["SubmitExtrinsic", "GetBalance", "GetClaim", "StreamBlocks", "GetFinalizedHead"];
+		for method in methods {
+			assert!(super::PROTO.contains(&format!("rpc {}(", method)));
+		}
+	}
+
+	#[test]
+	fn calls_are_served_over_http2() {
+		let chain = chain();
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let bob = dev_account("bob").to_string();
+		let request = message(1, bob.as_bytes());
+		let path = format!("{}GetBalance", SERVICE_PATH);
+		let headers = [
+			(":method", "POST"),
+			(":scheme", "http"),
+			(":path", path.as_str()),
+			("content-type", "application/grpc"),
+			("te", "trailers"),
+		];
+		client.write_all(http2::PREFACE).unwrap();
+		Frame::new(http2::SETTINGS, 0, 0, Vec::new()).write(&mut client).unwrap();
+		let block = http2::encode_headers(&headers);
+		Frame::new(http2::HEADERS, http2::END_HEADERS, 1, block)
+			.write(&mut client)
+			.unwrap();
+		let body = [&[0][..], &(request.len() as u32).to_be_bytes(), &request].concat();
+		Frame::new(http2::DATA, http2::END_STREAM, 1, body).write(&mut client).unwrap();
+		client.shutdown(Shutdown::Write).unwrap();
+		let (server, _) = listener.accept().unwrap();
+		super::handle_connection(&chain, &mut TransactionPool::new(), server).unwrap();
+
+		let mut frames = Vec::new();
+		while let Ok(frame) = Frame::read(&mut client, 16_384) {
+			frames.push(frame);
+		}
+		let kinds: Vec<_> = frames.iter().map(|frame| (frame.kind, frame.flags)).collect();
+		let (end_headers, end_stream) = (http2::END_HEADERS, http2::END_STREAM);
+		let expected = [
+			(http2::SETTINGS, 0),
+			(http2::SETTINGS, http2::ACK),
+			// The data of the request moves the window of the connection.
+			(http2::WINDOW_UPDATE, 0),
+			(http2::HEADERS, end_headers),
+			(http2::DATA, 0),
+			(http2::HEADERS, end_headers | end_stream),
+		];
+		assert_eq!(kinds, expected);
+		let mut decoder = Decoder::new();
+		let headers = decoder.decode(&frames[3].payload).unwrap();
+		assert_eq!(http2::header(&headers, ":status"), Some("200"));
+		let response = &frames[4].payload[5..];
+		assert_eq!(strings(response), [(1, bob), (2, "0".to_string()), (3, "0".to_string())]);
+		let trailers = decoder.decode(&frames[5].payload).unwrap();
+		assert_eq!(http2::header(&trailers, "grpc-status"), Some("0"));
+	}
+}
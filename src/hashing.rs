@@ -0,0 +1,450 @@
+//! Cryptographic hashing primitives (BLAKE2b, RFC 7693, and SHA-1, SHA-256 and SHA-512,
+//! FIPS 180-4).
+
+/// A 256-bit hash.
+pub type Hash = [u8; 32];
+
+const IV: [u64; 8] = [
+	0x6a09e667f3bcc908,
+	0xbb67ae8584caa73b,
+	0x3c6ef372fe94f82b,
+	0xa54ff53a5f1d36f1,
+	0x510e527fade682d1,
+	0x9b05688c2b3e6c1f,
+	0x1f83d9abfb41bd6b,
+	0x5be0cd19137e2179,
+];
+
+const SIGMA: [[usize; 16]; 12] = [
+	[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+	[14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+	[11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+	[7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+	[9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+	[2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+	[12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+	[13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+	[6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+	[10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+	[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+	[14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+];
+
+const BLOCK_LEN: usize = 128;
+
+fn mix(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, x: u64, y: u64) {
+	v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+	v[d] = (v[d] ^ v[a]).rotate_right(32);
+	v[c] = v[c].wrapping_add(v[d]);
+	v[b] = (v[b] ^ v[c]).rotate_right(24);
+	v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+	v[d] = (v[d] ^ v[a]).rotate_right(16);
+	v[c] = v[c].wrapping_add(v[d]);
+	v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+fn compress(h: &mut [u64; 8], block: &[u8; BLOCK_LEN], counter: u128, last: bool) {
+	let mut m = [0u64; 16];
+	for (i, word) in m.iter_mut().enumerate() {
+		*word = u64::from_le_bytes(block[i * 8..i * 8 + 8].try_into().expect("8 bytes; qed"));
+	}
+
+	let mut v = [0u64; 16];
+	v[..8].copy_from_slice(h);
+	v[8..].copy_from_slice(&IV);
+	v[12] ^= counter as u64;
+	v[13] ^= (counter >> 64) as u64;
+	if last {
+		v[14] = !v[14];
+	}
+
+	for s in SIGMA.iter() {
+		mix(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+		mix(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+		mix(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+		mix(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+		mix(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+		mix(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+		mix(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+		mix(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+	}
+
+	for i in 0..8 {
+		h[i] ^= v[i] ^ v[i + 8];
+	}
+}
+
+/// Unkeyed BLAKE2b with an output of `N` bytes (`1 <= N <= 64`).
+pub fn blake2b<const N: usize>(data: &[u8]) -> [u8; N] {
+	assert!(N > 0 && N <= 64, "BLAKE2b output must be between 1 and 64 bytes");
+
+	let mut h = IV;
+	h[0] ^= 0x0101_0000 ^ N as u64;
+
+	let mut offset = 0;
+	while data.len() - offset > BLOCK_LEN {
+		let block = data[offset..offset + BLOCK_LEN].try_into().expect("128 bytes; qed");
+		offset += BLOCK_LEN;
+		compress(&mut h, block, offset as u128, false);
+	}
+
+	let mut last = [0u8; BLOCK_LEN];
+	last[..data.len() - offset].copy_from_slice(&data[offset..]);
+	compress(&mut h, &last, data.len() as u128, true);
+
+	let mut bytes = [0u8; 64];
+	for (i, word) in h.iter().enumerate() {
+		bytes[i * 8..i * 8 + 8].copy_from_slice(&word.to_le_bytes());
+	}
+	let mut out = [0u8; N];
+	out.copy_from_slice(&bytes[..N]);
+	out
+}
+
+pub fn blake2_128(data: &[u8]) -> [u8; 16] {
+	blake2b(data)
+}
+
+pub fn blake2_256(data: &[u8]) -> Hash {
+	blake2b(data)
+}
+
+const SHA256_IV: [u32; 8] = [
+	0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const SHA256_K: [u32; 64] = [
+	0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+	0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+	0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+	0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+	0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+	0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+	0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+	0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+fn sha256_compress(h: &mut [u32; 8], block: &[u8]) {
+	let mut w = [0u32; 64];
+	for (i, word) in w.iter_mut().take(16).enumerate() {
+		*word = u32::from_be_bytes(block[i * 4..i * 4 + 4].try_into().expect("4 bytes; qed"));
+	}
+	for i in 16..64 {
+		let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+		let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+		w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+	}
+
+	let mut v = *h;
+	for i in 0..64 {
+		let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+		let choice = (v[4] & v[5]) ^ (!v[4] & v[6]);
+		let t1 = v[7]
+			.wrapping_add(s1)
+			.wrapping_add(choice)
+			.wrapping_add(SHA256_K[i])
+			.wrapping_add(w[i]);
+		let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+		let majority = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+		let t2 = s0.wrapping_add(majority);
+		v.rotate_right(1);
+		v[4] = v[4].wrapping_add(t1);
+		v[0] = t1.wrapping_add(t2);
+	}
+
+	for i in 0..8 {
+		h[i] = h[i].wrapping_add(v[i]);
+	}
+}
+
+/// SHA-256, which the key derivation of the keystore is built on.
+pub fn sha256(data: &[u8]) -> Hash {
+	let mut h = SHA256_IV;
+	let mut padded = data.to_vec();
+	padded.push(0x80);
+	while padded.len() % 64 != 56 {
+		padded.push(0);
+	}
+	padded.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+	for block in padded.chunks(64) {
+		sha256_compress(&mut h, block);
+	}
+
+	let mut out = [0u8; 32];
+	for (i, word) in h.iter().enumerate() {
+		out[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+	}
+	out
+}
+
+const SHA512_IV: [u64; 8] = [
+	0x6a09e667f3bcc908,
+	0xbb67ae8584caa73b,
+	0x3c6ef372fe94f82b,
+	0xa54ff53a5f1d36f1,
+	0x510e527fade682d1,
+	0x9b05688c2b3e6c1f,
+	0x1f83d9abfb41bd6b,
+	0x5be0cd19137e2179,
+];
+
+const SHA512_K: [u64; 80] = [
+	0x428a2f98d728ae22,
+	0x7137449123ef65cd,
+	0xb5c0fbcfec4d3b2f,
+	0xe9b5dba58189dbbc,
+	0x3956c25bf348b538,
+	0x59f111f1b605d019,
+	0x923f82a4af194f9b,
+	0xab1c5ed5da6d8118,
+	0xd807aa98a3030242,
+	0x12835b0145706fbe,
+	0x243185be4ee4b28c,
+	0x550c7dc3d5ffb4e2,
+	0x72be5d74f27b896f,
+	0x80deb1fe3b1696b1,
+	0x9bdc06a725c71235,
+	0xc19bf174cf692694,
+	0xe49b69c19ef14ad2,
+	0xefbe4786384f25e3,
+	0x0fc19dc68b8cd5b5,
+	0x240ca1cc77ac9c65,
+	0x2de92c6f592b0275,
+	0x4a7484aa6ea6e483,
+	0x5cb0a9dcbd41fbd4,
+	0x76f988da831153b5,
+	0x983e5152ee66dfab,
+	0xa831c66d2db43210,
+	0xb00327c898fb213f,
+	0xbf597fc7beef0ee4,
+	0xc6e00bf33da88fc2,
+	0xd5a79147930aa725,
+	0x06ca6351e003826f,
+	0x142929670a0e6e70,
+	0x27b70a8546d22ffc,
+	0x2e1b21385c26c926,
+	0x4d2c6dfc5ac42aed,
+	0x53380d139d95b3df,
+	0x650a73548baf63de,
+	0x766a0abb3c77b2a8,
+	0x81c2c92e47edaee6,
+	0x92722c851482353b,
+	0xa2bfe8a14cf10364,
+	0xa81a664bbc423001,
+	0xc24b8b70d0f89791,
+	0xc76c51a30654be30,
+	0xd192e819d6ef5218,
+	0xd69906245565a910,
+	0xf40e35855771202a,
+	0x106aa07032bbd1b8,
+	0x19a4c116b8d2d0c8,
+	0x1e376c085141ab53,
+	0x2748774cdf8eeb99,
+	0x34b0bcb5e19b48a8,
+	0x391c0cb3c5c95a63,
+	0x4ed8aa4ae3418acb,
+	0x5b9cca4f7763e373,
+	0x682e6ff3d6b2b8a3,
+	0x748f82ee5defb2fc,
+	0x78a5636f43172f60,
+	0x84c87814a1f0ab72,
+	0x8cc702081a6439ec,
+	0x90befffa23631e28,
+	0xa4506cebde82bde9,
+	0xbef9a3f7b2c67915,
+	0xc67178f2e372532b,
+	0xca273eceea26619c,
+	0xd186b8c721c0c207,
+	0xeada7dd6cde0eb1e,
+	0xf57d4f7fee6ed178,
+	0x06f067aa72176fba,
+	0x0a637dc5a2c898a6,
+	0x113f9804bef90dae,
+	0x1b710b35131c471b,
+	0x28db77f523047d84,
+	0x32caab7b40c72493,
+	0x3c9ebe0a15c9bebc,
+	0x431d67c49c100d4c,
+	0x4cc5d4becb3e42b6,
+	0x597f299cfc657e2a,
+	0x5fcb6fab3ad6faec,
+	0x6c44198c4a475817,
+];
+
+fn sha512_compress(h: &mut [u64; 8], block: &[u8]) {
+	let mut w = [0u64; 80];
+	for (i, word) in w.iter_mut().take(16).enumerate() {
+		*word = u64::from_be_bytes(block[i * 8..i * 8 + 8].try_into().expect("8 bytes; qed"));
+	}
+	for i in 16..80 {
+		let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+		let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+		w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+	}
+
+	let mut v = *h;
+	for i in 0..80 {
+		let s1 = v[4].rotate_right(14) ^ v[4].rotate_right(18) ^ v[4].rotate_right(41);
+		let choice = (v[4] & v[5]) ^ (!v[4] & v[6]);
+		let t1 = v[7]
+			.wrapping_add(s1)
+			.wrapping_add(choice)
+			.wrapping_add(SHA512_K[i])
+			.wrapping_add(w[i]);
+		let s0 = v[0].rotate_right(28) ^ v[0].rotate_right(34) ^ v[0].rotate_right(39);
+		let majority = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+		let t2 = s0.wrapping_add(majority);
+		v.rotate_right(1);
+		v[4] = v[4].wrapping_add(t1);
+		v[0] = t1.wrapping_add(t2);
+	}
+
+	for i in 0..8 {
+		h[i] = h[i].wrapping_add(v[i]);
+	}
+}
+
+/// SHA-512, which mnemonic phrases are turned into seeds with.
+pub fn sha512(data: &[u8]) -> [u8; 64] {
+	let mut h = SHA512_IV;
+	let mut padded = data.to_vec();
+	padded.push(0x80);
+	while padded.len() % 128 != 112 {
+		padded.push(0);
+	}
+	padded.extend_from_slice(&(data.len() as u128 * 8).to_be_bytes());
+	for block in padded.chunks(128) {
+		sha512_compress(&mut h, block);
+	}
+
+	let mut out = [0u8; 64];
+	for (i, word) in h.iter().enumerate() {
+		out[i * 8..i * 8 + 8].copy_from_slice(&word.to_be_bytes());
+	}
+	out
+}
+
+/// SHA-1, which the WebSocket handshake answers with. It is broken as a cryptographic hash, so
+/// nothing else should use it.
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+	let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+	let mut padded = data.to_vec();
+	padded.push(0x80);
+	while padded.len() % 64 != 56 {
+		padded.push(0);
+	}
+	padded.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+	for block in padded.chunks(64) {
+		let mut w = [0u32; 80];
+		for (i, word) in w.iter_mut().take(16).enumerate() {
+			*word = u32::from_be_bytes(block[i * 4..i * 4 + 4].try_into().expect("4 bytes; qed"));
+		}
+		for i in 16..80 {
+			w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+		}
+		let mut v = h;
+		for (i, word) in w.iter().enumerate() {
+			let (f, k) = match i / 20 {
+				0 => ((v[1] & v[2]) | (!v[1] & v[3]), 0x5a827999),
+				1 => (v[1] ^ v[2] ^ v[3], 0x6ed9eba1),
+				2 => ((v[1] & v[2]) | (v[1] & v[3]) | (v[2] & v[3]), 0x8f1bbcdc),
+				_ => (v[1] ^ v[2] ^ v[3], 0xca62c1d6),
+			};
+			let t = v[0].rotate_left(5).wrapping_add(f).wrapping_add(v[4]).wrapping_add(k);
+			v = [t.wrapping_add(*word), v[0], v[1].rotate_left(30), v[2], v[3]];
+		}
+		for i in 0..5 {
+			h[i] = h[i].wrapping_add(v[i]);
+		}
+	}
+
+	let mut out = [0u8; 20];
+	for (i, word) in h.iter().enumerate() {
+		out[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+	}
+	out
+}
+
+/// Lowercase hexadecimal representation of some bytes.
+pub fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The bytes of a hexadecimal string, in either case.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+	if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+		return None;
+	}
+	(0..hex.len())
+		.step_by(2)
+		.map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::{blake2_256, blake2b, from_hex, hex, sha1, sha256, sha512};
+
+	#[test]
+	fn blake2b_test_vectors() {
+		assert_eq!(
+			hex(&blake2_256(b"")),
+			"0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8"
+		);
+		assert_eq!(
+			hex(&blake2b::<64>(b"abc")),
+			"ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+			 7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+		);
+	}
+
+	#[test]
+	fn sha1_test_vectors() {
+		assert_eq!(hex(&sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+		assert_eq!(
+			hex(&sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+			"84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+		);
+	}
+
+	#[test]
+	fn sha256_test_vectors() {
+		assert_eq!(
+			hex(&sha256(b"")),
+			"e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+		);
+		assert_eq!(
+			hex(&sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+			"248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+		);
+	}
+
+	#[test]
+	fn sha512_test_vectors() {
+		assert_eq!(
+			hex(&sha512(b"abc")),
+			"ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+			 2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+		);
+		assert_eq!(
+			hex(&sha512(&[b'a'; 200])),
+			"4b11459c33f52a22ee8236782714c150a3b2c60994e9acee17fe68947a3e6789\
+			 f31e7668394592da7bef827cddca88c4e6f86e4df7ed1ae6cba71f3e98faee9f"
+		);
+	}
+
+	#[test]
+	fn hex_round_trips() {
+		assert_eq!(from_hex(&hex(&[0, 0xab, 0xff])), Some(vec![0, 0xab, 0xff]));
+		assert_eq!(from_hex("ABcd"), Some(vec![0xab, 0xcd]));
+		assert_eq!(from_hex("abc"), None);
+		assert_eq!(from_hex("+f"), None);
+	}
+
+	#[test]
+	fn blake2b_multiple_blocks() {
+		let data = [0xabu8; 300];
+		assert_ne!(blake2_256(&data[..128]), blake2_256(&data[..129]));
+		assert_eq!(blake2_256(&data), blake2_256(&[0xabu8; 300]));
+	}
+}
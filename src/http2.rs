@@ -0,0 +1,705 @@
+//! The subset of HTTP/2 (RFC 9113) the `grpc` module serves over: one connection over plain
+//! TCP ("h2c" with prior knowledge, as gRPC clients connect without TLS), its streams, flow
+//! control and HPACK header compression (RFC 7541), Huffman coding included.
+//!
+//! The server never pushes, ignores priorities, and encodes its own headers as literals
+//! without indexing, so it keeps no dynamic table for them. Frames are read one at a time, on
+//! the thread serving the connection.
+
+use std::{
+	collections::{BTreeMap, VecDeque},
+	io::{self, Read, Write},
+	sync::OnceLock,
+};
+
+/// What a client sends before its first frame.
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+pub const DATA: u8 = 0x0;
+pub const HEADERS: u8 = 0x1;
+pub const PRIORITY: u8 = 0x2;
+pub const RST_STREAM: u8 = 0x3;
+pub const SETTINGS: u8 = 0x4;
+pub const PING: u8 = 0x6;
+pub const GOAWAY: u8 = 0x7;
+pub const WINDOW_UPDATE: u8 = 0x8;
+pub const CONTINUATION: u8 = 0x9;
+
+pub const END_STREAM: u8 = 0x1;
+/// The flag of a SETTINGS or PING frame answering one of the peer.
+pub const ACK: u8 = 0x1;
+pub const END_HEADERS: u8 = 0x4;
+pub const PADDED: u8 = 0x8;
+pub const PRIORITY_FLAG: u8 = 0x20;
+
+pub const PROTOCOL_ERROR: u32 = 0x1;
+pub const FRAME_SIZE_ERROR: u32 = 0x6;
+
+/// The largest frame either side sends until the peer allows more with its settings.
+const DEFAULT_MAX_FRAME_LEN: usize = 16_384;
+/// The flow-control window of a connection and its streams until window updates move it.
+const DEFAULT_WINDOW: i64 = 65_535;
+/// The size of the dynamic table of the HPACK decoder, which the server doesn't change.
+const HEADER_TABLE_SIZE: usize = 4_096;
+/// The largest request body a stream receives, which is plenty for an extrinsic.
+pub const MAX_BODY_LEN: usize = 1 << 20;
+
+fn invalid(error: &'static str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+	pub kind: u8,
+	pub flags: u8,
+	pub stream: u32,
+	pub payload: Vec<u8>,
+}
+
+impl Frame {
+	pub fn new(kind: u8, flags: u8, stream: u32, payload: Vec<u8>) -> Self {
+		Self { kind, flags, stream, payload }
+	}
+
+	/// Read a frame from `input`, failing if its payload is longer than `max_len`.
+	pub fn read(input: &mut impl Read, max_len: usize) -> io::Result<Self> {
+		let mut header = [0; 9];
+		input.read_exact(&mut header)?;
+		let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+		if len > max_len {
+			return Err(invalid("Frame too large"));
+		}
+		let stream = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & !(1 << 31);
+		let mut payload = vec![0; len];
+		input.read_exact(&mut payload)?;
+		Ok(Self { kind: header[3], flags: header[4], stream, payload })
+	}
+
+	pub fn write(&self, output: &mut impl Write) -> io::Result<()> {
+		let len = (self.payload.len() as u32).to_be_bytes();
+		let mut frame = vec![len[1], len[2], len[3], self.kind, self.flags];
+		frame.extend(self.stream.to_be_bytes());
+		frame.extend(&self.payload);
+		output.write_all(&frame)
+	}
+
+	/// The payload of a DATA or HEADERS frame without its padding and priority.
+	fn content(&self) -> io::Result<&[u8]> {
+		let mut payload = &self.payload[..];
+		let mut padding = 0;
+		if self.flags & PADDED != 0 {
+			let (&len, rest) = payload.split_first().ok_or(invalid("Missing padding length"))?;
+			(padding, payload) = (usize::from(len), rest);
+		}
+		if self.kind == HEADERS && self.flags & PRIORITY_FLAG != 0 {
+			payload = payload.get(5..).ok_or(invalid("Missing priority"))?;
+		}
+		payload
+			.get(..payload.len().wrapping_sub(padding))
+			.ok_or(invalid("Padding too long"))
+	}
+}
+
+/// The code lengths of the Huffman code of HPACK, by symbol, the last one being the end of the
+/// string. The code is canonical, so the codes follow from their lengths: shorter codes come
+/// first, and codes of the same length are in the order of their symbols.
+const HUFFMAN_LENGTHS: [u8; 257] = [
+	13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+	28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+	5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+	7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+	6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+	23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+	21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+	22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+	21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+	22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+];
+const EOS: u16 = 256;
+
+/// For every code length, the first code of that length and where its symbols start among the
+/// symbols ordered by code.
+struct HuffmanTable {
+	first: [u32; 31],
+	count: [u32; 31],
+	offset: [usize; 31],
+	symbols: Vec<u16>,
+}
+
+fn huffman_table() -> &'static HuffmanTable {
+	static TABLE: OnceLock<HuffmanTable> = OnceLock::new();
+	TABLE.get_or_init(|| {
+		let mut symbols: Vec<u16> = (0..=EOS).collect();
+		symbols.sort_by_key(|&symbol| HUFFMAN_LENGTHS[usize::from(symbol)]);
+		let mut table = HuffmanTable { first: [0; 31], count: [0; 31], offset: [0; 31], symbols };
+		for &len in &HUFFMAN_LENGTHS {
+			table.count[usize::from(len)] += 1;
+		}
+		for len in 1..31 {
+			table.first[len] = (table.first[len - 1] + table.count[len - 1]) << 1;
+			table.offset[len] = table.offset[len - 1] + table.count[len - 1] as usize;
+		}
+		table
+	})
+}
+
+/// Decode a Huffman-coded string. It is padded to a whole byte with the first bits of the end
+/// of string code, which is all ones; the code itself must not appear.
+pub fn huffman_decode(bytes: &[u8]) -> Result<Vec<u8>, &'static str> {
+	let table = huffman_table();
+	let mut decoded = Vec::with_capacity(bytes.len() * 8 / 5);
+	let (mut code, mut len) = (0u32, 0);
+	for bit in bytes
+		.iter()
+		.flat_map(|byte| (0..8).rev().map(move |i| u32::from(byte >> i & 1)))
+	{
+		(code, len) = (code << 1 | bit, len + 1);
+		if len > 30 {
+			return Err("Invalid Huffman code");
+		}
+		let index = code.wrapping_sub(table.first[len]);
+		if index < table.count[len] {
+			match table.symbols[table.offset[len] + index as usize] {
+				EOS => return Err("Huffman-coded end of string"),
+				symbol => decoded.push(symbol as u8),
+			}
+			(code, len) = (0, 0);
+		}
+	}
+	if len >= 8 || code != (1 << len) - 1 {
+		return Err("Invalid Huffman padding");
+	}
+	Ok(decoded)
+}
+
+/// The static table of HPACK, whose entries are numbered from 1.
+const STATIC_TABLE: [(&str, &str); 61] = [
+	(":authority", ""),
+	(":method", "GET"),
+	(":method", "POST"),
+	(":path", "/"),
+	(":path", "/index.html"),
+	(":scheme", "http"),
+	(":scheme", "https"),
+	(":status", "200"),
+	(":status", "204"),
+	(":status", "206"),
+	(":status", "304"),
+	(":status", "400"),
+	(":status", "404"),
+	(":status", "500"),
+	("accept-charset", ""),
+	("accept-encoding", "gzip, deflate"),
+	("accept-language", ""),
+	("accept-ranges", ""),
+	("accept", ""),
+	("access-control-allow-origin", ""),
+	("age", ""),
+	("allow", ""),
+	("authorization", ""),
+	("cache-control", ""),
+	("content-disposition", ""),
+	("content-encoding", ""),
+	("content-language", ""),
+	("content-length", ""),
+	("content-location", ""),
+	("content-range", ""),
+	("content-type", ""),
+	("cookie", ""),
+	("date", ""),
+	("etag", ""),
+	("expect", ""),
+	("expires", ""),
+	("from", ""),
+	("host", ""),
+	("if-match", ""),
+	("if-modified-since", ""),
+	("if-none-match", ""),
+	("if-range", ""),
+	("if-unmodified-since", ""),
+	("last-modified", ""),
+	("link", ""),
+	("location", ""),
+	("max-forwards", ""),
+	("proxy-authenticate", ""),
+	("proxy-authorization", ""),
+	("range", ""),
+	("referer", ""),
+	("refresh", ""),
+	("retry-after", ""),
+	("server", ""),
+	("set-cookie", ""),
+	("strict-transport-security", ""),
+	("transfer-encoding", ""),
+	("user-agent", ""),
+	("vary", ""),
+	("via", ""),
+	("www-authenticate", ""),
+];
+
+pub type Headers = Vec<(String, String)>;
+
+/// The value of the header `name` among `headers`, if it is there.
+pub fn header<'a>(headers: &'a Headers, name: &str) -> Option<&'a str> {
+	headers.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str())
+}
+
+/// An integer with an `prefix`-bit prefix, in the first byte of `input` after its flags.
+fn decode_int(input: &mut &[u8], prefix: u32) -> Result<usize, &'static str> {
+	let (&first, rest) = input.split_first().ok_or("Unexpected end of header block")?;
+	*input = rest;
+	let max = (1 << prefix) - 1;
+	let mut value = usize::from(first) & max;
+	if value < max {
+		return Ok(value);
+	}
+	for shift in (0..28).step_by(7) {
+		let (&byte, rest) = input.split_first().ok_or("Unexpected end of header block")?;
+		*input = rest;
+		value += usize::from(byte & 0x7f) << shift;
+		if byte & 0x80 == 0 {
+			return Ok(value);
+		}
+	}
+	Err("Header integer too large")
+}
+
+fn encode_int(dest: &mut Vec<u8>, flags: u8, prefix: u32, mut value: usize) {
+	let max = (1 << prefix) - 1;
+	if value < max {
+		dest.push(flags | value as u8);
+		return;
+	}
+	dest.push(flags | max as u8);
+	value -= max;
+	while value >= 0x80 {
+		dest.push(value as u8 | 0x80);
+		value >>= 7;
+	}
+	dest.push(value as u8);
+}
+
+fn decode_string(input: &mut &[u8]) -> Result<String, &'static str> {
+	let huffman = input.first().is_some_and(|byte| byte & 0x80 != 0);
+	let len = decode_int(input, 7)?;
+	let bytes = input.get(..len).ok_or("Unexpected end of header block")?;
+	*input = &input[len..];
+	let bytes = if huffman { huffman_decode(bytes)? } else { bytes.to_vec() };
+	String::from_utf8(bytes).map_err(|_| "Invalid header encoding")
+}
+
+fn encode_string(dest: &mut Vec<u8>, s: &str) {
+	encode_int(dest, 0, 7, s.len());
+	dest.extend(s.as_bytes());
+}
+
+/// The HPACK decoder of a connection, with the dynamic table the header blocks of the peer
+/// build up.
+#[derive(Debug, Clone, Default)]
+pub struct Decoder {
+	table: VecDeque<(String, String)>,
+	size: usize,
+	max_size: usize,
+}
+
+impl Decoder {
+	pub fn new() -> Self {
+		Self { max_size: HEADER_TABLE_SIZE, ..Default::default() }
+	}
+
+	fn entry(&self, index: usize) -> Result<(String, String), &'static str> {
+		match index {
+			0 => Err("Invalid header index"),
+			1..=61 => {
+				let (name, value) = STATIC_TABLE[index - 1];
+				Ok((name.to_string(), value.to_string()))
+			},
+			_ => self.table.get(index - 62).cloned().ok_or("Invalid header index"),
+		}
+	}
+
+	fn insert(&mut self, header: (String, String)) {
+		self.size += header.0.len() + header.1.len() + 32;
+		self.table.push_front(header);
+		self.evict();
+	}
+
+	fn evict(&mut self) {
+		while self.size > self.max_size {
+			let (name, value) = self.table.pop_back().expect("The table holds what it counts");
+			self.size -= name.len() + value.len() + 32;
+		}
+	}
+
+	/// The headers of a header block, in order.
+	pub fn decode(&mut self, mut block: &[u8]) -> Result<Headers, &'static str> {
+		let input = &mut block;
+		let mut headers = Vec::new();
+		while let Some(&first) = input.first() {
+			if first & 0x80 != 0 {
+				headers.push(self.entry(decode_int(input, 7)?)?);
+			} else if first & 0xe0 == 0x20 {
+				let size = decode_int(input, 5)?;
+				if size > HEADER_TABLE_SIZE {
+					return Err("Header table too large");
+				}
+				self.max_size = size;
+				self.evict();
+			} else {
+				// Literals with incremental indexing have a 6-bit index, the others 4 bits.
+				let indexed = first & 0xc0 == 0x40;
+				let name = match decode_int(input, if indexed { 6 } else { 4 })? {
+					0 => decode_string(input)?,
+					index => self.entry(index)?.0,
+				};
+				let header = (name, decode_string(input)?);
+				if indexed {
+					self.insert(header.clone());
+				}
+				headers.push(header);
+			}
+		}
+		Ok(headers)
+	}
+}
+
+/// A header block of `headers`: indexed if the static table has them, and literals without
+/// indexing otherwise, with the name from the static table if it is there.
+pub fn encode_headers(headers: &[(&str, &str)]) -> Vec<u8> {
+	let mut block = Vec::new();
+	for &(name, value) in headers {
+		if let Some(index) = STATIC_TABLE.iter().position(|&entry| entry == (name, value)) {
+			encode_int(&mut block, 0x80, 7, index + 1);
+			continue;
+		}
+		match STATIC_TABLE.iter().position(|&(n, _)| n == name) {
+			Some(index) => encode_int(&mut block, 0, 4, index + 1),
+			None => {
+				block.push(0);
+				encode_string(&mut block, name);
+			},
+		}
+		encode_string(&mut block, value);
+	}
+	block
+}
+
+/// A request whose stream the client ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+	pub stream: u32,
+	pub headers: Headers,
+	pub body: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct Stream {
+	headers: Option<Headers>,
+	body: Vec<u8>,
+	/// How much the server may still send on the stream.
+	window: i64,
+}
+
+/// The server side of a connection.
+pub struct Connection<S> {
+	io: S,
+	decoder: Decoder,
+	/// How much the server may still send on the connection, over all streams.
+	window: i64,
+	/// The window of new streams, from the settings of the client.
+	initial_window: i64,
+	max_frame_len: usize,
+	streams: BTreeMap<u32, Stream>,
+	/// The stream, flags and first fragment of a header block continued in CONTINUATION
+	/// frames, which must follow it directly.
+	continued: Option<(u32, u8, Vec<u8>)>,
+	last_stream: u32,
+	requests: VecDeque<Request>,
+}
+
+impl<S: Read + Write> Connection<S> {
+	/// Read the preface of the client and send the settings of the server, which are the
+	/// defaults.
+	pub fn accept(mut io: S) -> io::Result<Self> {
+		let mut preface = [0; PREFACE.len()];
+		io.read_exact(&mut preface)?;
+		if preface != PREFACE {
+			return Err(invalid("Not an HTTP/2 connection"));
+		}
+		Frame::new(SETTINGS, 0, 0, Vec::new()).write(&mut io)?;
+		Ok(Self {
+			io,
+			decoder: Decoder::new(),
+			window: DEFAULT_WINDOW,
+			initial_window: DEFAULT_WINDOW,
+			max_frame_len: DEFAULT_MAX_FRAME_LEN,
+			streams: BTreeMap::new(),
+			continued: None,
+			last_stream: 0,
+			requests: VecDeque::new(),
+		})
+	}
+
+	/// The next request the client ended its stream of, or `None` once the client closed the
+	/// connection or went away.
+	pub fn next_request(&mut self) -> io::Result<Option<Request>> {
+		loop {
+			if let Some(request) = self.requests.pop_front() {
+				return Ok(Some(request));
+			}
+			if !self.read_frame()? {
+				return Ok(None);
+			}
+		}
+	}
+
+	/// Read and handle a frame, returning whether the connection is still open.
+	fn read_frame(&mut self) -> io::Result<bool> {
+		let frame = match Frame::read(&mut self.io, DEFAULT_MAX_FRAME_LEN) {
+			Ok(frame) => frame,
+			Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+			Err(e) => return Err(e),
+		};
+		if let Some((stream, ..)) = self.continued {
+			if frame.kind != CONTINUATION || frame.stream != stream {
+				return Err(invalid("Expected a CONTINUATION frame"));
+			}
+		}
+		match frame.kind {
+			DATA => self.data(&frame)?,
+			HEADERS => {
+				if frame.stream % 2 == 0 {
+					return Err(invalid("Invalid stream id"));
+				}
+				if !self.streams.contains_key(&frame.stream) {
+					if frame.stream <= self.last_stream {
+						return Err(invalid("Stream closed"));
+					}
+					self.last_stream = frame.stream;
+					let stream = Stream { window: self.initial_window, ..Default::default() };
+					self.streams.insert(frame.stream, stream);
+				}
+				self.continued = Some((frame.stream, frame.flags, frame.content()?.to_vec()));
+				if frame.flags & END_HEADERS != 0 {
+					self.end_headers()?;
+				}
+			},
+			CONTINUATION => {
+				let Some((_, _, block)) = &mut self.continued else {
+					return Err(invalid("Unexpected CONTINUATION frame"));
+				};
+				block.extend(&frame.payload);
+				if block.len() > MAX_BODY_LEN {
+					return Err(invalid("Header block too large"));
+				}
+				if frame.flags & END_HEADERS != 0 {
+					self.end_headers()?;
+				}
+			},
+			RST_STREAM => {
+				self.streams.remove(&frame.stream);
+			},
+			SETTINGS if frame.flags & ACK == 0 => self.settings(&frame.payload)?,
+			PING if frame.flags & ACK == 0 => {
+				Frame::new(PING, ACK, 0, frame.payload).write(&mut self.io)?;
+			},
+			GOAWAY => return Ok(false),
+			WINDOW_UPDATE => {
+				let bytes = frame.payload.get(..4).ok_or(invalid("Invalid window update"))?;
+				let increment = u32::from_be_bytes(bytes.try_into().expect("4 bytes")) & !(1 << 31);
+				match frame.stream {
+					0 => self.window += i64::from(increment),
+					id => {
+						if let Some(stream) = self.streams.get_mut(&id) {
+							stream.window += i64::from(increment);
+						}
+					},
+				}
+			},
+			// Priorities, acknowledgements and frames of unknown types are ignored.
+			_ => {},
+		}
+		Ok(true)
+	}
+
+	fn data(&mut self, frame: &Frame) -> io::Result<()> {
+		let Some(stream) = self.streams.get_mut(&frame.stream) else {
+			return Err(invalid("Data on a closed stream"));
+		};
+		if stream.headers.is_none() {
+			return Err(invalid("Data before headers"));
+		}
+		stream.body.extend(frame.content()?);
+		if stream.body.len() > MAX_BODY_LEN {
+			return Err(invalid("Request body too large"));
+		}
+		// The data counts against the windows of the client however soon it is handled, so
+		// they are moved right away.
+		if !frame.payload.is_empty() {
+			let increment = (frame.payload.len() as u32).to_be_bytes().to_vec();
+			Frame::new(WINDOW_UPDATE, 0, 0, increment.clone()).write(&mut self.io)?;
+			if frame.flags & END_STREAM == 0 {
+				Frame::new(WINDOW_UPDATE, 0, frame.stream, increment).write(&mut self.io)?;
+			}
+		}
+		if frame.flags & END_STREAM != 0 {
+			self.end_stream(frame.stream);
+		}
+		Ok(())
+	}
+
+	fn end_headers(&mut self) -> io::Result<()> {
+		let (id, flags, block) = self.continued.take().expect("A header block is pending");
+		let headers = self.decoder.decode(&block).map_err(invalid)?;
+		if let Some(stream) = self.streams.get_mut(&id) {
+			// Trailers of the client end its stream, and are otherwise dropped.
+			stream.headers.get_or_insert(headers);
+		}
+		if flags & END_STREAM != 0 {
+			self.end_stream(id);
+		}
+		Ok(())
+	}
+
+	fn end_stream(&mut self, id: u32) {
+		if let Some(stream) = self.streams.get_mut(&id) {
+			let headers = stream.headers.clone().unwrap_or_default();
+			let body = std::mem::take(&mut stream.body);
+			self.requests.push_back(Request { stream: id, headers, body });
+		}
+	}
+
+	fn settings(&mut self, payload: &[u8]) -> io::Result<()> {
+		if !payload.len().is_multiple_of(6) {
+			return Err(invalid("Invalid settings"));
+		}
+		for setting in payload.chunks(6) {
+			let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+			match u16::from_be_bytes([setting[0], setting[1]]) {
+				// The initial window size, which moves the windows of open streams too.
+				0x4 => {
+					let delta = i64::from(value) - self.initial_window;
+					self.initial_window = i64::from(value);
+					self.streams.values_mut().for_each(|stream| stream.window += delta);
+				},
+				// The largest frame the client accepts.
+				0x5 => self.max_frame_len = (value as usize).max(DEFAULT_MAX_FRAME_LEN),
+				_ => {},
+			}
+		}
+		Frame::new(SETTINGS, ACK, 0, Vec::new()).write(&mut self.io)
+	}
+
+	/// Send `headers` on stream `id`, ending it with them if `end_stream`.
+	pub fn send_headers(
+		&mut self,
+		id: u32,
+		headers: &[(&str, &str)],
+		end_stream: bool,
+	) -> io::Result<()> {
+		let block = encode_headers(headers);
+		let mut fragments = block.chunks(self.max_frame_len).peekable();
+		let mut kind = HEADERS;
+		let end_stream = if end_stream { END_STREAM } else { 0 };
+		loop {
+			let fragment = fragments.next().unwrap_or_default();
+			let end_headers = if fragments.peek().is_none() { END_HEADERS } else { 0 };
+			let flags = if kind == HEADERS { end_stream | end_headers } else { end_headers };
+			Frame::new(kind, flags, id, fragment.to_vec()).write(&mut self.io)?;
+			if end_headers != 0 {
+				break;
+			}
+			kind = CONTINUATION;
+		}
+		if end_stream != 0 {
+			self.streams.remove(&id);
+		}
+		Ok(())
+	}
+
+	/// Send `data` on stream `id` as far as the windows of the client allow, reading its frames
+	/// while they are closed, e.g. for its window updates. Requests meanwhile wait for
+	/// `next_request`. Nothing is sent if the client reset the stream.
+	pub fn send_data(&mut self, id: u32, mut data: &[u8]) -> io::Result<()> {
+		while !data.is_empty() {
+			let Some(stream) = self.streams.get(&id) else { return Ok(()) };
+			let window = self.window.min(stream.window);
+			if window <= 0 {
+				if !self.read_frame()? {
+					return Err(io::ErrorKind::ConnectionAborted.into());
+				}
+				continue;
+			}
+			let len = data.len().min(self.max_frame_len).min(window as usize);
+			Frame::new(DATA, 0, id, data[..len].to_vec()).write(&mut self.io)?;
+			self.window -= len as i64;
+			self.streams.get_mut(&id).expect("The stream is open").window -= len as i64;
+			data = &data[len..];
+		}
+		Ok(())
+	}
+
+	/// Tell the client the server closes the connection, with `error`, e.g. `PROTOCOL_ERROR`,
+	/// or 0 if there is none.
+	pub fn go_away(&mut self, error: u32) -> io::Result<()> {
+		let payload = [self.last_stream.to_be_bytes(), error.to_be_bytes()].concat();
+		Frame::new(GOAWAY, 0, 0, payload).write(&mut self.io)?;
+		self.io.flush()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{Decoder, Frame, Headers};
+	use crate::hashing;
+
+	fn headers(headers: &[(&str, &str)]) -> Headers {
+		headers
+			.iter()
+			.map(|&(name, value)| (name.to_string(), value.to_string()))
+			.collect()
+	}
+
+	#[test]
+	fn header_blocks_are_decoded_with_the_dynamic_table() {
+		// The requests of RFC 7541, appendix C.4, Huffman-coded and indexed.
+		let mut decoder = Decoder::new();
+		let first = hashing::from_hex("828684418cf1e3c2e5f23a6ba0ab90f4ff").unwrap();
+		let authority = (":authority", "www.example.com");
+		let expected = [(":method", "GET"), (":scheme", "http"), (":path", "/"), authority];
+		assert_eq!(decoder.decode(&first), Ok(headers(&expected)));
+		let second = hashing::from_hex("828684be5886a8eb10649cbf").unwrap();
+		let mut expected = expected.to_vec();
+		expected.push(("cache-control", "no-cache"));
+		assert_eq!(decoder.decode(&second), Ok(headers(&expected)));
+		let third = hashing::from_hex("828785bf408825a849e95ba97d7f8925a849e95bb8e8b4bf").unwrap();
+		let expected = [
+			(":method", "GET"),
+			(":scheme", "https"),
+			(":path", "/index.html"),
+			authority,
+			("custom-key", "custom-value"),
+		];
+		assert_eq!(decoder.decode(&third), Ok(headers(&expected)));
+		assert_eq!(Decoder::new().decode(&third), Err("Invalid header index"));
+		// Padding must be the start of the end of string code.
+		assert_eq!(
+			super::huffman_decode(&[0xf1, 0xe3, 0xc2, 0x00]),
+			Err("Invalid Huffman padding")
+		);
+	}
+
+	#[test]
+	fn headers_and_frames_are_encoded_as_they_are_decoded() {
+		let sent = [(":status", "200"), ("content-type", "application/grpc"), ("grpc-status", "0")];
+		let block = super::encode_headers(&sent);
+		assert_eq!(block[0], 0x88);
+		assert_eq!(Decoder::new().decode(&block), Ok(headers(&sent)));
+
+		let frame = Frame::new(super::HEADERS, super::END_HEADERS, 3, block);
+		let mut bytes = Vec::new();
+		frame.write(&mut bytes).unwrap();
+		assert_eq!(Frame::read(&mut &bytes[..], 16_384).unwrap(), frame);
+		assert!(Frame::read(&mut &bytes[..], 8).is_err());
+	}
+}
@@ -0,0 +1,432 @@
+use std::collections::BTreeMap;
+
+use crate::{
+	balances,
+	codec::{Decode, Encode},
+	metadata::{field, storage, unit, variant, PalletMetadata, Registry, TypeDef, TypeInfo},
+	migrations::{self, StorageVersion},
+	support::{ensure_root, ensure_signed, DispatchResult, Weight},
+	system::OriginFor,
+	trie::{self, Storage},
+};
+
+pub trait Config: balances::Config {
+	/// The amount reserved from an account while it has an identity registered.
+	const BASIC_DEPOSIT: Self::Balance;
+	/// The maximum length, in bytes, of each identity field.
+	const MAX_FIELD_LENGTH: usize;
+	/// The maximum number of registrars.
+	const MAX_REGISTRARS: usize;
+}
+
+pub type RegistrarIndex = u32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentityInfo {
+	pub display: String,
+	pub email: Option<String>,
+	pub web: Option<String>,
+}
+
+impl Encode for IdentityInfo {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.display, &self.email, &self.web).encode_to(dest);
+	}
+}
+
+impl Decode for IdentityInfo {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (display, email, web) = Decode::decode(input)?;
+		Ok(IdentityInfo { display, email, web })
+	}
+}
+
+impl TypeInfo for IdentityInfo {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		TypeDef::Composite(vec![
+			registry.field::<String>("display"),
+			registry.field::<Option<String>>("email"),
+			registry.field::<Option<String>>("web"),
+		])
+	}
+}
+
+/// The opinion of a registrar on an identity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Judgement {
+	Unknown,
+	Reasonable,
+	KnownGood,
+	OutOfDate,
+	LowQuality,
+	Erroneous,
+}
+
+impl Encode for Judgement {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		dest.push(*self as u8);
+	}
+}
+
+impl Decode for Judgement {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Judgement::Unknown,
+			1 => Judgement::Reasonable,
+			2 => Judgement::KnownGood,
+			3 => Judgement::OutOfDate,
+			4 => Judgement::LowQuality,
+			5 => Judgement::Erroneous,
+			_ => return Err("Invalid judgement"),
+		})
+	}
+}
+
+impl TypeInfo for Judgement {
+	fn type_def(_: &mut Registry) -> TypeDef {
+		let names = ["Unknown", "Reasonable", "KnownGood", "OutOfDate", "LowQuality", "Erroneous"];
+		TypeDef::Variant(names.iter().enumerate().map(|(i, name)| unit(name, i as u8)).collect())
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Registration<T: Config> {
+	pub info: IdentityInfo,
+	pub deposit: T::Balance,
+	pub judgements: BTreeMap<RegistrarIndex, Judgement>,
+}
+
+impl<T: Config> Encode for Registration<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		let judgements: Vec<_> = self.judgements.iter().collect();
+		(&self.info, &self.deposit, judgements).encode_to(dest);
+	}
+}
+
+impl<T: Config> Decode for Registration<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (info, deposit, judgements): (_, _, Vec<_>) = Decode::decode(input)?;
+		Ok(Registration { info, deposit, judgements: judgements.into_iter().collect() })
+	}
+}
+
+#[derive(Debug, Clone)]
+pub enum Call<T: Config> {
+	SetIdentity {
+		info: IdentityInfo,
+	},
+	ClearIdentity,
+	ProvideJudgement {
+		reg_index: RegistrarIndex,
+		target: T::AccountId,
+		judgement: Judgement,
+	},
+	/// Root only: add a new registrar.
+	AddRegistrar {
+		account: T::AccountId,
+	},
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::SetIdentity { info } => (0u8, info).encode_to(dest),
+			Call::ClearIdentity => 1u8.encode_to(dest),
+			Call::ProvideJudgement { reg_index, target, judgement } => {
+				(2u8, reg_index, target, judgement).encode_to(dest)
+			},
+			Call::AddRegistrar { account } => (3u8, account).encode_to(dest),
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::SetIdentity { info: Decode::decode(input)? },
+			1 => Call::ClearIdentity,
+			2 => Call::ProvideJudgement {
+				reg_index: Decode::decode(input)?,
+				target: Decode::decode(input)?,
+				judgement: Decode::decode(input)?,
+			},
+			3 => Call::AddRegistrar { account: Decode::decode(input)? },
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::SetIdentity { .. } => 0,
+			Call::ClearIdentity => 1,
+			Call::ProvideJudgement { .. } => 2,
+			Call::AddRegistrar { .. } => 3,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::SetIdentity { .. } | Call::ClearIdentity => 10_000,
+			Call::ProvideJudgement { .. } | Call::AddRegistrar { .. } => 5_000,
+		}
+	}
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	identities: BTreeMap<T::AccountId, Registration<T>>,
+	registrars: Vec<T::AccountId>,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self { identities: BTreeMap::new(), registrars: Vec::new() }
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Identity",
+			index: None,
+			calls: vec![
+				variant("SetIdentity", 0, vec![field::<IdentityInfo>("info")]),
+				variant("ClearIdentity", 1, Vec::new()),
+				variant(
+					"ProvideJudgement",
+					2,
+					vec![
+						field::<RegistrarIndex>("reg_index"),
+						field::<T::AccountId>("target"),
+						field::<Judgement>("judgement"),
+					],
+				),
+				variant("AddRegistrar", 3, vec![field::<T::AccountId>("account")]),
+			],
+			storage: vec![
+				storage::<BTreeMap<T::AccountId, Registration<T>>>("identities"),
+				storage::<Vec<T::AccountId>>("registrars"),
+			],
+			events: Vec::new(),
+			errors: vec![
+				"Caller is not the registrar",
+				"Identity field too long",
+				"No identity found",
+				"Registrar does not exist",
+				"Too many registrars",
+			],
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Identity", STORAGE_VERSION);
+		trie::insert_entries(storage, "Identity", "identities", &self.identities);
+		trie::insert_value(storage, "Identity", "registrars", &self.registrars);
+	}
+
+	/// Restore the identities and registrars `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult {
+		migrations::ensure_version(storage, "Identity", STORAGE_VERSION)?;
+		self.identities =
+			trie::decode_entries(storage, "Identity", "identities")?.into_iter().collect();
+		trie::restore_value(storage, "Identity", "registrars", &mut self.registrars)?;
+		Ok(())
+	}
+
+	/// Identity calls reserve funds, so they are dispatched together with the balances pallet.
+	pub fn dispatch(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		origin: OriginFor<T>,
+		call: Call<T>,
+	) -> DispatchResult {
+		match call {
+			Call::SetIdentity { info } => self.set_identity(balances, ensure_signed(origin)?, info),
+			Call::ClearIdentity => self.clear_identity(balances, ensure_signed(origin)?),
+			Call::ProvideJudgement { reg_index, target, judgement } => {
+				self.provide_judgement(ensure_signed(origin)?, reg_index, target, judgement)
+			},
+			Call::AddRegistrar { account } => {
+				ensure_root(origin)?;
+				self.add_registrar(account).map(|_| ())
+			},
+		}
+	}
+
+	pub fn identity(&self, who: &T::AccountId) -> Option<&Registration<T>> {
+		self.identities.get(who)
+	}
+
+	pub fn registrars(&self) -> &[T::AccountId] {
+		&self.registrars
+	}
+
+	pub fn add_registrar(&mut self, account: T::AccountId) -> Result<RegistrarIndex, &'static str> {
+		if self.registrars.len() >= T::MAX_REGISTRARS {
+			return Err("Too many registrars");
+		}
+		self.registrars.push(account);
+		Ok((self.registrars.len() - 1) as RegistrarIndex)
+	}
+
+	/// Register or replace the identity of `caller`. The deposit is only reserved the first
+	/// time; replacing the information clears all previous judgements.
+	pub fn set_identity(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		caller: T::AccountId,
+		info: IdentityInfo,
+	) -> DispatchResult {
+		let fields = [Some(&info.display), info.email.as_ref(), info.web.as_ref()];
+		if fields.into_iter().flatten().any(|field| field.len() > T::MAX_FIELD_LENGTH) {
+			return Err("Identity field too long");
+		}
+
+		let deposit = match self.identities.get(&caller) {
+			Some(registration) => registration.deposit,
+			None => {
+				balances.reserve(&caller, T::BASIC_DEPOSIT)?;
+				T::BASIC_DEPOSIT
+			},
+		};
+
+		self.identities
+			.insert(caller, Registration { info, deposit, judgements: BTreeMap::new() });
+		Ok(())
+	}
+
+	/// Remove the identity of `caller` and return its deposit.
+	pub fn clear_identity(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		caller: T::AccountId,
+	) -> DispatchResult {
+		let registration = self.identities.get(&caller).ok_or("No identity found")?;
+		balances.unreserve(&caller, registration.deposit)?;
+		self.identities.remove(&caller);
+		Ok(())
+	}
+
+	pub fn provide_judgement(
+		&mut self,
+		caller: T::AccountId,
+		reg_index: RegistrarIndex,
+		target: T::AccountId,
+		judgement: Judgement,
+	) -> DispatchResult {
+		let registrar =
+			self.registrars.get(reg_index as usize).ok_or("Registrar does not exist")?;
+		if registrar != &caller {
+			return Err("Caller is not the registrar");
+		}
+
+		let registration = self.identities.get_mut(&target).ok_or("No identity found")?;
+		registration.judgements.insert(reg_index, judgement);
+		Ok(())
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{IdentityInfo, Judgement};
+	use crate::{balances, mock::Test};
+
+	impl super::Config for Test {
+		const BASIC_DEPOSIT: u128 = 10;
+		const MAX_FIELD_LENGTH: usize = 20;
+		const MAX_REGISTRARS: usize = 1;
+	}
+
+	fn info(display: &str) -> IdentityInfo {
+		IdentityInfo {
+			display: display.to_string(),
+			email: Some("alice@example.com".to_string()),
+			web: None,
+		}
+	}
+
+	#[test]
+	fn set_and_clear_identity() {
+		let alice = "alice".to_string();
+		let mut balances: balances::Pallet<Test> = balances::Pallet::new();
+		let mut identity: super::Pallet<Test> = super::Pallet::new();
+		balances.set_balance(&alice, 100);
+
+		assert_eq!(identity.set_identity(&mut balances, alice.clone(), info("Alice")), Ok(()));
+		assert_eq!(identity.identity(&alice).unwrap().info, info("Alice"));
+		assert_eq!(balances.balance(&alice), 90);
+		assert_eq!(balances.reserved_balance(&alice), 10);
+
+		// Updating the identity does not reserve a second deposit.
+		assert_eq!(identity.set_identity(&mut balances, alice.clone(), info("Alice B.")), Ok(()));
+		assert_eq!(balances.reserved_balance(&alice), 10);
+
+		assert_eq!(identity.clear_identity(&mut balances, alice.clone()), Ok(()));
+		assert!(identity.identity(&alice).is_none());
+		assert_eq!(balances.balance(&alice), 100);
+		assert_eq!(identity.clear_identity(&mut balances, alice.clone()), Err("No identity found"));
+	}
+
+	#[test]
+	fn set_identity_checks_deposit_and_fields() {
+		let alice = "alice".to_string();
+		let mut balances: balances::Pallet<Test> = balances::Pallet::new();
+		let mut identity: super::Pallet<Test> = super::Pallet::new();
+
+		assert_eq!(
+			identity.set_identity(&mut balances, alice.clone(), info("Alice")),
+			Err("Insufficient balance")
+		);
+
+		balances.set_balance(&alice, 100);
+		assert_eq!(
+			identity.set_identity(&mut balances, alice.clone(), info("A very long display name")),
+			Err("Identity field too long")
+		);
+		assert_eq!(balances.reserved_balance(&alice), 0);
+	}
+
+	#[test]
+	fn registrar_judgements() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut balances: balances::Pallet<Test> = balances::Pallet::new();
+		let mut identity: super::Pallet<Test> = super::Pallet::new();
+		balances.set_balance(&alice, 100);
+
+		assert_eq!(identity.add_registrar(bob.clone()), Ok(0));
+		assert_eq!(identity.add_registrar(alice.clone()), Err("Too many registrars"));
+
+		assert_eq!(
+			identity.provide_judgement(bob.clone(), 0, alice.clone(), Judgement::KnownGood),
+			Err("No identity found")
+		);
+
+		let _ = identity.set_identity(&mut balances, alice.clone(), info("Alice"));
+		assert_eq!(
+			identity.provide_judgement(alice.clone(), 0, alice.clone(), Judgement::KnownGood),
+			Err("Caller is not the registrar")
+		);
+		assert_eq!(
+			identity.provide_judgement(bob.clone(), 0, alice.clone(), Judgement::KnownGood),
+			Ok(())
+		);
+		assert_eq!(
+			identity.identity(&alice).unwrap().judgements.get(&0),
+			Some(&Judgement::KnownGood)
+		);
+
+		// New information invalidates the judgements given so far.
+		let _ = identity.set_identity(&mut balances, alice.clone(), info("Alice B."));
+		assert!(identity.identity(&alice).unwrap().judgements.is_empty());
+	}
+}
@@ -0,0 +1,385 @@
+//! Blocks received from other nodes are verified before they are executed: they must build on
+//! the best block, commit to their extrinsics, be sealed by a validator and carry valid
+//! inherents. Every block gets an import result, so a bad block is reported instead of
+//! bringing the node down.
+
+use std::collections::VecDeque;
+
+use crate::{
+	account::AccountId32,
+	codec::Encode,
+	hashing::{self, Hash},
+	keystore,
+	signature::Verify,
+	support::{self, Weight},
+	trie, types, Runtime,
+};
+
+/// The parent hash of the first block.
+pub const GENESIS_PARENT_HASH: Hash = [0; 32];
+
+/// The root committing a header to the extrinsics of its block.
+pub fn extrinsics_root(extrinsics: &[types::Extrinsic]) -> Hash {
+	hashing::blake2_256(&extrinsics.encode())
+}
+
+/// The hash of the best block of `runtime`, which the next block builds on.
+pub fn best_hash(runtime: &Runtime) -> Option<Hash> {
+	match runtime.system.block_number() {
+		0 => Some(GENESIS_PARENT_HASH),
+		best => runtime.system.block_hash(best),
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedBlock {
+	pub block_number: types::BlockNumber,
+	pub hash: Hash,
+	pub weight: Weight,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportError {
+	/// The block doesn't build on the best block.
+	UnknownParent,
+	BadExtrinsicsRoot,
+	/// The author isn't one of the validators of the current session, or the block isn't sealed
+	/// with its session key.
+	BadSeal,
+	InvalidInherents(&'static str),
+	/// The state after executing the block doesn't match the state root of its header.
+	BadStateRoot,
+	/// Executing the block failed, which leaves the state as it was.
+	Execution(&'static str),
+	/// The block is on a fork that doesn't contain the finalized block.
+	RevertsFinalized,
+	/// The node keeps as many blocks off the best chain as it may, see `chain::MAX_FORK_BLOCKS`.
+	TooManyForks,
+}
+
+pub type ImportResult = Result<ImportedBlock, ImportError>;
+
+#[derive(Default)]
+pub struct ImportQueue {
+	pending: VecDeque<types::Block>,
+}
+
+impl ImportQueue {
+	pub fn new() -> Self {
+		Self { pending: VecDeque::new() }
+	}
+
+	/// Queue a block received from the network.
+	pub fn import_block(&mut self, block: types::Block) {
+		self.pending.push_back(block);
+	}
+
+	pub fn pending(&self) -> usize {
+		self.pending.len()
+	}
+
+	/// Verify and execute the queued blocks in the order they were received.
+	pub fn process(
+		&mut self,
+		runtime: &mut Runtime,
+		local_now: types::Moment,
+	) -> Vec<ImportResult> {
+		self.pending.drain(..).map(|block| import(runtime, block, local_now)).collect()
+	}
+}
+
+/// Build the block `author` produces with `extrinsics` on top of the best block of `state`, by
+/// executing it to learn its state root.
+pub fn build_block(
+	state: &Runtime,
+	author: &types::AccountId,
+	extrinsics: Vec<types::Extrinsic>,
+) -> Result<types::Block, &'static str> {
+	let mut block = types::Block {
+		header: types::Header {
+			block_number: state.system.block_number() + 1,
+			parent_hash: best_hash(state).ok_or("Best block hash is unknown")?,
+			extrinsics_root: extrinsics_root(&extrinsics),
+			state_root: trie::EMPTY_ROOT,
+			author: author.clone(),
+			spec_version: state.version().spec_version,
+		},
+		extrinsics,
+		seal: None,
+	};
+	let mut next = state.clone();
+	next.execute_block(block.clone())?;
+	block.header.state_root = next.state_root();
+	Ok(block)
+}
+
+/// Seal `block` with `key`, the session key of its author.
+pub fn seal(mut block: types::Block, key: &keystore::Signer) -> types::Block {
+	block.seal = Some(key.sign_payload(&hashing::blake2_256(&block.header.encode())));
+	block
+}
+
+/// The checks that don't need the block to be executed.
+pub fn verify(
+	runtime: &Runtime,
+	block: &types::Block,
+	local_now: types::Moment,
+) -> Result<(), ImportError> {
+	let header = &block.header;
+	let best = runtime.system.block_number();
+	if header.block_number != best + 1 || best_hash(runtime) != Some(header.parent_hash) {
+		return Err(ImportError::UnknownParent);
+	}
+	if header.extrinsics_root != extrinsics_root(&block.extrinsics) {
+		return Err(ImportError::BadExtrinsicsRoot);
+	}
+	// Before the first session there are no validators, and anyone may author unsealed blocks.
+	let validators = runtime.session.validators();
+	if !validators.is_empty() {
+		let (_, keys) = validators
+			.iter()
+			.find(|(who, _)| *who == header.author)
+			.ok_or(ImportError::BadSeal)?;
+		let hash = hashing::blake2_256(&header.encode());
+		let key = AccountId32::new(*keys);
+		if !block.seal.as_ref().is_some_and(|seal| seal.verify(&hash, &key)) {
+			return Err(ImportError::BadSeal);
+		}
+	}
+	runtime.check_inherents(block, local_now).map_err(ImportError::InvalidInherents)
+}
+
+/// Verify `block` and execute it on top of `runtime`.
+pub fn import(
+	runtime: &mut Runtime,
+	block: types::Block,
+	local_now: types::Moment,
+) -> ImportResult {
+	verify(runtime, &block, local_now)?;
+	let hash = hashing::blake2_256(&block.header.encode());
+	let state_root = block.header.state_root;
+	support::with_transaction(runtime, |runtime| {
+		runtime.execute_block(block).map_err(ImportError::Execution)?;
+		match runtime.state_root() == state_root {
+			true => Ok(()),
+			false => Err(ImportError::BadStateRoot),
+		}
+	})?;
+
+	Ok(ImportedBlock {
+		block_number: runtime.system.block_number(),
+		hash,
+		weight: runtime.system.block_weight(),
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use super::{ImportError, ImportQueue};
+	use crate::{
+		genesis::{self, GenesisBuilder},
+		support, types,
+		wallet::{self, dev_account, dev_signer},
+		Runtime, RuntimeCall,
+	};
+
+	fn block(runtime: &Runtime, parent_hash: [u8; 32]) -> types::Block {
+		let now = runtime.timestamp.create_inherent(1_000).unwrap();
+		let extrinsics = vec![support::Extrinsic {
+			caller: None,
+			call: RuntimeCall::Timestamp(now),
+			signature: None,
+		}];
+		let mut block = super::build_block(runtime, &dev_account("alice"), extrinsics).unwrap();
+		block.header.parent_hash = parent_hash;
+		super::seal(block, &genesis::session_key(&dev_account("alice")))
+	}
+
+	#[test]
+	fn blocks_must_build_on_the_best_block() {
+		let mut runtime = Runtime::new();
+		let mut queue = ImportQueue::new();
+
+		queue.import_block(block(&runtime, [1; 32]));
+		queue.import_block(block(&runtime, super::GENESIS_PARENT_HASH));
+		let results = queue.process(&mut runtime, 1_000);
+		assert_eq!(results[0], Err(ImportError::UnknownParent));
+		let imported = results[1].clone().unwrap();
+		assert_eq!(imported.block_number, 1);
+
+		queue.import_block(block(&runtime, imported.hash));
+		assert_eq!(queue.process(&mut runtime, 1_000)[0].as_ref().map(|b| b.block_number), Ok(2));
+		assert_eq!(queue.pending(), 0);
+	}
+
+	#[test]
+	fn bad_blocks_leave_the_state_untouched() {
+		let mut runtime = Runtime::new();
+
+		let mut tampered = block(&runtime, super::GENESIS_PARENT_HASH);
+		tampered.extrinsics.clear();
+		let result = super::import(&mut runtime, tampered, 1_000);
+		assert_eq!(result, Err(ImportError::BadExtrinsicsRoot));
+
+		let mut wrong_state = block(&runtime, super::GENESIS_PARENT_HASH);
+		wrong_state.header.state_root = [1; 32];
+		let result = super::import(&mut runtime, wrong_state, 1_000);
+		assert_eq!(result, Err(ImportError::BadStateRoot));
+
+		let mut wrong_version = block(&runtime, super::GENESIS_PARENT_HASH);
+		wrong_version.header.spec_version += 1;
+		let result = super::import(&mut runtime, wrong_version, 1_000);
+		assert_eq!(result, Err(ImportError::Execution("Incompatible spec version")));
+		assert_eq!(runtime.system.block_number(), 0);
+	}
+
+	#[test]
+	fn blocks_must_be_sealed_by_their_author() {
+		let alice = dev_account("alice");
+		let mut runtime = GenesisBuilder::new().authority(&alice).build().unwrap();
+
+		let mut unsealed = block(&runtime, super::GENESIS_PARENT_HASH);
+		unsealed.seal = None;
+		let result = super::import(&mut runtime, unsealed, 1_000);
+		assert_eq!(result, Err(ImportError::BadSeal));
+
+		let forged = block(&runtime, super::GENESIS_PARENT_HASH);
+		let forged = super::seal(forged, &genesis::session_key(&dev_account("bob")));
+		let result = super::import(&mut runtime, forged, 1_000);
+		assert_eq!(result, Err(ImportError::BadSeal));
+
+		let sealed = block(&runtime, super::GENESIS_PARENT_HASH);
+		assert_eq!(super::import(&mut runtime, sealed, 1_000).map(|b| b.block_number), Ok(1));
+	}
+
+	#[test]
+	fn nodes_bootstrap_from_a_snapshot() {
+		let mut runtime = Runtime::new();
+		runtime.balances.set_balance(&dev_account("bob"), 100);
+		let first = block(&runtime, super::GENESIS_PARENT_HASH);
+		super::import(&mut runtime, first, 1_000).unwrap();
+
+		let mut restored = Runtime::import_state(&runtime.export_state()).unwrap();
+		assert_eq!(restored.state_root(), runtime.state_root());
+		assert_eq!(restored.balances.balance(&dev_account("bob")), 100);
+		let next = block(&runtime, super::best_hash(&runtime).unwrap());
+		assert_eq!(super::import(&mut restored, next, 2_000).map(|b| b.block_number), Ok(2));
+
+		let mut snapshot = runtime.export_state();
+		snapshot[0] = 2;
+		assert!(matches!(Runtime::import_state(&snapshot), Err("Unsupported snapshot version")));
+	}
+
+	#[test]
+	fn snapshots_restore_every_pallet() {
+		use crate::{genesis::GenesisBuilder, identity::IdentityInfo, vesting::VestingInfo};
+
+		let alice = dev_account("alice");
+		let bob = dev_account("bob");
+		let mut runtime = GenesisBuilder::new()
+			.endow(&alice, 10_000)
+			.endow(&bob, 10_000)
+			.claim(&alice, "document")
+			.authority(&alice)
+			.sudo(&alice)
+			.build()
+			.unwrap();
+		let Runtime { balances, identity, vesting, treasury, names, escrow, bounties, .. } =
+			&mut runtime;
+		balances.set_lock(*b"staking ", &bob, 500);
+		identity.add_registrar(bob.clone()).unwrap();
+		let info = IdentityInfo { display: "Alice".to_string(), email: None, web: None };
+		identity.set_identity(balances, alice.clone(), info).unwrap();
+		let schedule = VestingInfo { locked: 100, per_block: 10, starting_block: 5 };
+		vesting.add_vesting_schedule(balances, 0, &bob, schedule).unwrap();
+		treasury.propose_spend(balances, alice.clone(), 100, bob.clone()).unwrap();
+		names.register(balances, 0, alice.clone(), b"alice".to_vec()).unwrap();
+		escrow.create(balances, 0, alice.clone(), bob.clone(), None, 50, 10).unwrap();
+		bounties.post_bounty(balances, alice.clone(), 100, b"bug".to_vec()).unwrap();
+		runtime.proof_of_existence.attest(bob.clone(), "document".to_string()).unwrap();
+		runtime
+			.scheduler
+			.schedule(alice.clone(), 20, wallet::transfer(bob.clone(), 1))
+			.unwrap();
+		runtime.session.set_keys(bob.clone(), [7; 32]).unwrap();
+		runtime.collective.set_members(vec![alice.clone(), bob.clone()]).unwrap();
+		runtime.bridge.set_relayers(vec![bob.clone()], 1).unwrap();
+		let first = block(&runtime, super::GENESIS_PARENT_HASH);
+		super::import(&mut runtime, first, 1_000).unwrap();
+
+		let restored = Runtime::import_state(&runtime.export_state()).unwrap();
+		assert_eq!(restored.state_root(), runtime.state_root());
+		// The events and weight of the last block aren't state.
+		let (system, restored_system) = (&runtime.system, &restored.system);
+		assert_eq!(restored_system.block_hashes(), system.block_hashes());
+		for who in [&alice, &bob] {
+			let account = format!("{:?}", system.account(who));
+			assert_eq!(format!("{:?}", restored_system.account(who)), account);
+		}
+		macro_rules! assert_restored {
+			($($pallet:ident),*) => {
+				$(assert_eq!(
+					format!("{:?}", restored.$pallet),
+					format!("{:?}", runtime.$pallet),
+					stringify!($pallet),
+				);)*
+			};
+		}
+		assert_restored!(
+			balances,
+			proof_of_existence,
+			sudo,
+			timestamp,
+			scheduler,
+			identity,
+			multisig,
+			proxy,
+			vesting,
+			democracy,
+			treasury,
+			collective,
+			contracts,
+			oracle,
+			randomness,
+			bridge,
+			escrow,
+			names,
+			lottery,
+			bounties,
+			session,
+			tx_pause,
+			authorship,
+			transaction_payment
+		);
+	}
+
+	#[test]
+	fn upgrades_take_effect_in_the_next_block() {
+		use crate::{codec::Encode, sudo, system};
+
+		let mut runtime = Runtime::new();
+		runtime.sudo.initialize_key(dev_account("alice"));
+		runtime.balances.set_balance(&dev_account("alice"), 100);
+		let genesis_hash = runtime.genesis_hash();
+		let set_code = |spec_version: u32| {
+			let call = RuntimeCall::Sudo(sudo::Call::Sudo {
+				call: Box::new(RuntimeCall::System(system::Call::SetCode {
+					code: ("rust-blockchain", spec_version, 1u32).encode(),
+				})),
+			});
+			dev_signer("alice").sign(call, spec_version - 1, genesis_hash)
+		};
+		let mut first = block(&runtime, super::GENESIS_PARENT_HASH);
+		first.extrinsics.extend([set_code(1), set_code(2)]);
+		let first = super::build_block(&runtime, &dev_account("alice"), first.extrinsics).unwrap();
+		assert_eq!(first.header.spec_version, 1);
+		super::import(&mut runtime, first, 1_000).unwrap();
+		assert_eq!(runtime.version().spec_version, 2);
+
+		let mut stale = block(&runtime, super::best_hash(&runtime).unwrap());
+		assert_eq!(stale.header.spec_version, 2);
+		stale.header.spec_version = 1;
+		let result = super::import(&mut runtime, stale, 2_000);
+		assert_eq!(result, Err(ImportError::Execution("Incompatible spec version")));
+	}
+}
@@ -0,0 +1,304 @@
+//! Records the history of the chain for explorer-style queries, which the runtime state alone
+//! can't answer: blocks by range, the extrinsics and events of each block, the transfers of an
+//! account and the claims of an owner.
+//!
+//! Transfers are indexed from the `Transferred` events of the extrinsics, so transfers that
+//! failed are left out, and the recipients are the accounts actually paid, e.g. for names.
+//! Claims are looked up in the state after each block that touched them, so the index has their
+//! actual owner.
+//!
+//! The node indexes its best chain as it changes with `follow`, and the REST API answers the
+//! queries, see the `rest` module.
+
+use std::collections::BTreeMap;
+
+use crate::{
+	balances,
+	chain::{self, Chain},
+	hashing::Hash,
+	proof_of_existence,
+	rpc::EventRecord,
+	types, Runtime, RuntimeCall, RuntimeEvent,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedBlock {
+	pub number: types::BlockNumber,
+	pub hash: Hash,
+	pub author: types::AccountId,
+	pub extrinsics: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transfer {
+	pub block_number: types::BlockNumber,
+	pub extrinsic_index: u32,
+	pub from: types::AccountId,
+	pub to: types::AccountId,
+	pub amount: types::Balance,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedClaim {
+	pub hash: Hash,
+	pub owner: types::AccountId,
+	/// The block the claim was created in.
+	pub block_number: types::BlockNumber,
+}
+
+#[derive(Default)]
+pub struct Indexer {
+	blocks: BTreeMap<types::BlockNumber, IndexedBlock>,
+	extrinsics: BTreeMap<types::BlockNumber, Vec<types::Extrinsic>>,
+	events: BTreeMap<types::BlockNumber, Vec<EventRecord>>,
+	transfers: Vec<Transfer>,
+	claims: BTreeMap<Hash, IndexedClaim>,
+	/// The best block of the chain `follow` indexed last.
+	best: Hash,
+}
+
+impl Indexer {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Index `block`, given the state after it. A block with the number of an indexed one,
+	/// i.e. after a reorg, replaces it and the blocks after it; the claims it touched are
+	/// looked up again instead.
+	pub fn index_block(&mut self, block: &types::Block, state: &Runtime) {
+		let number = block.header.block_number;
+		self.blocks.split_off(&number);
+		self.extrinsics.split_off(&number);
+		self.events.split_off(&number);
+		self.transfers.retain(|transfer| transfer.block_number < number);
+
+		for record in state.system.events() {
+			let Some(extrinsic_index) = record.extrinsic_index else { continue };
+			if let RuntimeEvent::Balances(balances::Event::Transferred { from, to, amount }) =
+				&record.event
+			{
+				self.transfers.push(Transfer {
+					block_number: number,
+					extrinsic_index,
+					from: from.clone(),
+					to: to.clone(),
+					amount: *amount,
+				});
+			}
+		}
+		let mut touched = Vec::new();
+		for extrinsic in &block.extrinsics {
+			// Only signed extrinsics touch claims.
+			if extrinsic.caller.is_none() {
+				continue;
+			}
+			if let RuntimeCall::ProofOfExistence(call) = &extrinsic.call {
+				touched.extend(claims_of_call(call));
+			}
+		}
+		for content in touched {
+			let hash = proof_of_existence::Pallet::<Runtime>::hash_content(&content);
+			match state.proof_of_existence.get_claim_details_by_hash(&hash) {
+				Some(details) => {
+					let owner = details.owner.clone();
+					let claim = IndexedClaim { hash, owner, block_number: details.block_number };
+					self.claims.insert(hash, claim);
+				},
+				None => {
+					self.claims.remove(&hash);
+				},
+			}
+		}
+
+		self.blocks.insert(
+			number,
+			IndexedBlock {
+				number,
+				hash: chain::block_hash(block),
+				author: block.header.author.clone(),
+				extrinsics: block.extrinsics.len(),
+			},
+		);
+		self.extrinsics.insert(number, block.extrinsics.clone());
+		self.events.insert(number, state.system.events().to_vec());
+	}
+
+	/// Index the blocks of the best chain of `chain` that aren't yet, oldest first, e.g. after
+	/// blocks were imported or a fork became the best chain. Blocks whose state `chain` no
+	/// longer keeps, e.g. after a restart without archive pruning, are left out.
+	pub fn follow(&mut self, chain: &Chain) {
+		if chain.best_hash() == self.best {
+			return;
+		}
+		self.best = chain.best_hash();
+		let mut new = Vec::new();
+		let mut hash = self.best;
+		while let Some(block) = chain.block(&hash) {
+			let number = block.header.block_number;
+			if self.blocks.get(&number).is_some_and(|indexed| indexed.hash == hash) {
+				break;
+			}
+			new.push((hash, block));
+			hash = block.header.parent_hash;
+		}
+		for (hash, block) in new.into_iter().rev() {
+			if let Some(state) = chain.state_at(&hash) {
+				self.index_block(block, state);
+			}
+		}
+	}
+
+	/// The indexed blocks numbered `from` to `to`, both included.
+	pub fn blocks(
+		&self,
+		from: types::BlockNumber,
+		to: types::BlockNumber,
+	) -> impl Iterator<Item = &IndexedBlock> {
+		self.blocks.range(from..=to.max(from)).map(|(_, block)| block)
+	}
+
+	pub fn extrinsics(&self, block_number: types::BlockNumber) -> &[types::Extrinsic] {
+		self.extrinsics.get(&block_number).map_or(&[], Vec::as_slice)
+	}
+
+	pub fn events(&self, block_number: types::BlockNumber) -> &[EventRecord] {
+		self.events.get(&block_number).map_or(&[], Vec::as_slice)
+	}
+
+	/// The transfers from or to `account`, oldest first.
+	pub fn transfers_of<'a>(
+		&'a self,
+		account: &'a types::AccountId,
+	) -> impl Iterator<Item = &'a Transfer> + 'a {
+		self.transfers
+			.iter()
+			.filter(move |transfer| transfer.from == *account || transfer.to == *account)
+	}
+
+	pub fn claims_of<'a>(
+		&'a self,
+		owner: &'a types::AccountId,
+	) -> impl Iterator<Item = &'a IndexedClaim> + 'a {
+		self.claims.values().filter(move |claim| claim.owner == *owner)
+	}
+}
+
+/// The contents of the claims a call may create, remove or hand over.
+fn claims_of_call(call: &proof_of_existence::Call<Runtime>) -> Vec<types::Content> {
+	use proof_of_existence::Call;
+	match call {
+		Call::CreateClaim { claim, .. }
+		| Call::RevokeClaim { claim }
+		| Call::TransferClaim { claim, .. } => vec![claim.clone()],
+		Call::CreateClaims { claims } => claims.clone(),
+		Call::UpdateClaim { new, .. } => vec![new.clone()],
+		Call::Attest { .. } => Vec::new(),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::Indexer;
+	use crate::{
+		balances,
+		chain::{self, Chain},
+		genesis::{self, GenesisBuilder},
+		import_queue, proof_of_existence, support, types,
+		wallet::{dev_account, dev_signer},
+		Runtime, RuntimeCall,
+	};
+
+	/// Execute a block with `calls` of alice on `state` and index it.
+	fn produce(indexer: &mut Indexer, state: &mut Runtime, calls: Vec<RuntimeCall>) {
+		let now = state.timestamp.create_inherent(1_000).unwrap();
+		let (alice, genesis_hash) = (dev_account("alice"), state.genesis_hash());
+		let mut extrinsics = vec![support::Extrinsic {
+			caller: None,
+			call: RuntimeCall::Timestamp(now),
+			signature: None,
+		}];
+		let nonce = state.system.get_nonce(&alice);
+		extrinsics.extend(
+			calls
+				.into_iter()
+				.zip(nonce..)
+				.map(|(call, nonce)| dev_signer("alice").sign(call, nonce, genesis_hash)),
+		);
+		let block = import_queue::build_block(state, &dev_account("alice"), extrinsics).unwrap();
+		state.execute_block(block.clone()).unwrap();
+		indexer.index_block(&block, state);
+	}
+
+	#[test]
+	fn history_is_queryable() {
+		let (bob, charlie) = (dev_account("bob"), dev_account("charlie"));
+		let mut state = Runtime::new();
+		state.balances.set_balance(&dev_account("alice"), 1_000);
+		let mut indexer = Indexer::new();
+		let transfer = |to: &types::AccountId| {
+			RuntimeCall::Balances(balances::Call::Transfer { to: to.clone().into(), amount: 50 })
+		};
+		let claim = |claim: &str| {
+			RuntimeCall::ProofOfExistence(proof_of_existence::Call::CreateClaim {
+				claim: claim.to_string(),
+				note: None,
+			})
+		};
+		produce(&mut indexer, &mut state, vec![transfer(&bob), claim("a")]);
+		produce(
+			&mut indexer,
+			&mut state,
+			vec![
+				transfer(&charlie),
+				// Fails, so it isn't indexed.
+				RuntimeCall::Balances(balances::Call::Transfer {
+					to: bob.clone().into(),
+					amount: 10_000,
+				}),
+				claim("b"),
+				RuntimeCall::ProofOfExistence(proof_of_existence::Call::TransferClaim {
+					claim: "a".to_string(),
+					to: bob.clone(),
+				}),
+			],
+		);
+
+		assert_eq!(indexer.blocks(1, 5).map(|block| block.number).collect::<Vec<_>>(), [1, 2]);
+		assert_eq!(indexer.extrinsics(2).len(), 5);
+		assert_eq!(indexer.transfers_of(&bob).count(), 1);
+		assert_eq!(indexer.transfers_of(&dev_account("alice")).count(), 2);
+		assert_eq!(indexer.claims_of(&bob).count(), 1);
+		assert_eq!(indexer.claims_of(&dev_account("alice")).count(), 1);
+		assert_eq!(indexer.claims_of(&charlie).count(), 0);
+	}
+
+	#[test]
+	fn the_best_chain_is_followed() {
+		let (alice, bob) = (dev_account("alice"), dev_account("bob"));
+		let state = GenesisBuilder::new().endow(&alice, 1_000).authority(&alice).build().unwrap();
+		let mut chain = Chain::new(state.clone());
+		let mut indexer = Indexer::new();
+		let transfer =
+			RuntimeCall::Balances(balances::Call::Transfer { to: bob.clone().into(), amount: 50 });
+		let extrinsics = genesis::signed(&state, "alice", [transfer]);
+		chain
+			.import(genesis::block(&state, "alice", extrinsics).unwrap(), 100_000)
+			.unwrap();
+		indexer.follow(&chain);
+		indexer.follow(&chain);
+		assert_eq!(indexer.transfers_of(&bob).count(), 1);
+
+		// A fork without the transfer becomes the best chain.
+		let fork = genesis::block(&state, "alice", Vec::new()).unwrap();
+		let fork_hash = chain::block_hash(&fork);
+		chain.import(fork, 100_000).unwrap();
+		let fork_state = chain.state_at(&fork_hash).unwrap().clone();
+		chain
+			.import(genesis::block(&fork_state, "alice", Vec::new()).unwrap(), 100_000)
+			.unwrap();
+		indexer.follow(&chain);
+		assert_eq!(indexer.transfers_of(&bob).count(), 0);
+		let hashes: Vec<_> = indexer.blocks(1, 2).map(|block| block.hash).collect();
+		assert_eq!(hashes, [fork_hash, chain.best_hash()]);
+	}
+}
@@ -0,0 +1,144 @@
+//! Inherents are the unsigned extrinsics a block author puts at the front of its block, with
+//! what only the author can tell, e.g. the time. Each comes from an `InherentDataProvider`: the
+//! author asks every one of `PROVIDERS` for its inherent when building a block, and importing
+//! nodes check that the block starts with all of them, in order, and that each is valid given
+//! their own view.
+//!
+//! The timestamp is the only inherent so far. The author of a block is in its header, and the
+//! randomness is derived from the block hashes, so neither needs one.
+
+use crate::{support, types, Runtime, RuntimeCall};
+
+pub trait InherentDataProvider {
+	/// Whether `call` is the inherent of this provider.
+	fn is_inherent(&self, call: &RuntimeCall) -> bool;
+
+	/// The inherent of the block built on `state`, given the local clock of its author.
+	fn create_inherent(
+		&self,
+		state: &Runtime,
+		local_now: types::Moment,
+	) -> Result<RuntimeCall, &'static str>;
+
+	/// Check the inherent of a block built on `state` against the local clock of the importing
+	/// node.
+	fn check_inherent(
+		&self,
+		state: &Runtime,
+		call: &RuntimeCall,
+		local_now: types::Moment,
+	) -> support::DispatchResult;
+}
+
+/// Sets the time of the block, which may not be too far ahead of the clocks of other nodes.
+pub struct TimestampProvider;
+
+impl InherentDataProvider for TimestampProvider {
+	fn is_inherent(&self, call: &RuntimeCall) -> bool {
+		matches!(call, RuntimeCall::Timestamp(_))
+	}
+
+	fn create_inherent(
+		&self,
+		state: &Runtime,
+		local_now: types::Moment,
+	) -> Result<RuntimeCall, &'static str> {
+		state.timestamp.create_inherent(local_now).map(RuntimeCall::Timestamp)
+	}
+
+	fn check_inherent(
+		&self,
+		state: &Runtime,
+		call: &RuntimeCall,
+		local_now: types::Moment,
+	) -> support::DispatchResult {
+		match call {
+			RuntimeCall::Timestamp(call) => state.timestamp.check_inherent(call, local_now),
+			_ => Err("Not a timestamp inherent"),
+		}
+	}
+}
+
+/// The providers of the inherents every block starts with, in the order of their inherents.
+pub const PROVIDERS: &[&(dyn InherentDataProvider + Sync)] = &[&TimestampProvider];
+
+pub fn is_inherent(call: &RuntimeCall) -> bool {
+	PROVIDERS.iter().any(|provider| provider.is_inherent(call))
+}
+
+/// Whether `call` may be the extrinsic at `index` of a block, as far as inherents go: the
+/// inherent of the provider at `index`, or no inherent past them.
+pub fn is_in_place(call: &RuntimeCall, index: usize) -> bool {
+	match PROVIDERS.get(index) {
+		Some(provider) => provider.is_inherent(call),
+		None => !is_inherent(call),
+	}
+}
+
+/// The inherents of the block built on `state`, which its other extrinsics follow.
+pub fn create_inherents(
+	state: &Runtime,
+	local_now: types::Moment,
+) -> Result<Vec<types::Extrinsic>, &'static str> {
+	PROVIDERS
+		.iter()
+		.map(|provider| {
+			Ok(support::Extrinsic {
+				caller: None,
+				call: provider.create_inherent(state, local_now)?,
+				signature: None,
+			})
+		})
+		.collect()
+}
+
+/// Check that `block`, built on `state`, starts with the inherents of all providers and has no
+/// others, and that each is valid given the local clock of the importing node.
+pub fn check_inherents(
+	state: &Runtime,
+	block: &types::Block,
+	local_now: types::Moment,
+) -> support::DispatchResult {
+	for (index, provider) in PROVIDERS.iter().enumerate() {
+		match block.extrinsics.get(index) {
+			Some(extrinsic) if provider.is_inherent(&extrinsic.call) => {
+				provider.check_inherent(state, &extrinsic.call, local_now)?
+			},
+			_ => return Err("Missing inherent"),
+		}
+	}
+	let mut rest = block.extrinsics.iter().skip(PROVIDERS.len());
+	if rest.any(|extrinsic| is_inherent(&extrinsic.call)) {
+		return Err("Inherents must be at the front of the block");
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use super::{check_inherents, create_inherents};
+	use crate::{genesis, genesis::GenesisBuilder, wallet};
+
+	#[test]
+	fn blocks_start_with_valid_inherents() {
+		let alice = wallet::dev_account("alice");
+		let state = GenesisBuilder::new().endow(&alice, 1_000).build().unwrap();
+		let transfer = genesis::signed(&state, "alice", [wallet::transfer(alice.clone(), 1)]);
+		let block = genesis::block(&state, "alice", transfer).unwrap();
+		assert_eq!(check_inherents(&state, &block, 1_000), Ok(()));
+		// The clock of the author is ahead of the importing node's by more than the allowed drift.
+		let mut ahead = block.clone();
+		ahead.extrinsics[0] = create_inherents(&state, 100_000).unwrap().remove(0);
+		assert_eq!(check_inherents(&state, &ahead, 1_000), Err("Timestamp too far in the future"));
+
+		let mut missing = block.clone();
+		missing.extrinsics.remove(0);
+		assert_eq!(check_inherents(&state, &missing, 1_000), Err("Missing inherent"));
+		let mut twice = block.clone();
+		twice.extrinsics.extend(create_inherents(&state, 1_000).unwrap());
+		let expected = Err("Inherents must be at the front of the block");
+		assert_eq!(check_inherents(&state, &twice, 1_000), expected);
+		let mut next = state.clone();
+		assert_eq!(next.execute_block(twice), expected);
+	}
+}
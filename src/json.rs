@@ -0,0 +1,503 @@
+//! JSON documents as `Value`s, and the representation of the runtime types the node shows to
+//! clients: headers, blocks, extrinsics, events and what the `indexer` module indexes implement
+//! `ToJson`, and `FromJson` when they
+//! are read back, so the REST API, the RPC notifications and the block fixtures of the
+//! `database` module all write them the same way.
+//!
+//! The crate doesn't depend on serde, so the implementations are written by hand, for the types
+//! clients see. The state is exported in the SCALE-like encoding of the `codec` module instead,
+//! which every type has.
+
+use std::fmt;
+
+use crate::{
+	account::AccountId32,
+	codec::{decode_all, Encode},
+	hashing::{self, Hash},
+	indexer, support, system, types, Runtime,
+};
+
+/// How deeply arrays and objects may be nested in the documents that are read.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+	Null,
+	Bool(bool),
+	/// Kept as written, so integers of any size are read exactly.
+	Number(String),
+	String(String),
+	Array(Vec<Value>),
+	/// The fields in the order they were written.
+	Object(Vec<(String, Value)>),
+}
+
+impl Value {
+	/// The field `name` of an object.
+	pub fn get(&self, name: &str) -> Option<&Value> {
+		match self {
+			Value::Object(fields) => fields.iter().find(|(key, _)| key == name).map(|(_, v)| v),
+			_ => None,
+		}
+	}
+
+	pub fn as_str(&self) -> Option<&str> {
+		match self {
+			Value::String(s) => Some(s),
+			_ => None,
+		}
+	}
+
+	pub fn as_array(&self) -> Option<&[Value]> {
+		match self {
+			Value::Array(items) => Some(items),
+			_ => None,
+		}
+	}
+
+	/// A number, read as an `N`.
+	pub fn as_number<N: std::str::FromStr>(&self) -> Option<N> {
+		match self {
+			Value::Number(n) => n.parse().ok(),
+			_ => None,
+		}
+	}
+}
+
+/// The document, without whitespace.
+impl fmt::Display for Value {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Value::Null => f.write_str("null"),
+			Value::Bool(b) => write!(f, "{}", b),
+			Value::Number(n) => f.write_str(n),
+			Value::String(s) => f.write_str(&string(s)),
+			Value::Array(items) => {
+				f.write_str("[")?;
+				for (i, item) in items.iter().enumerate() {
+					let separator = if i == 0 { "" } else { "," };
+					write!(f, "{}{}", separator, item)?;
+				}
+				f.write_str("]")
+			},
+			Value::Object(fields) => {
+				f.write_str("{")?;
+				for (i, (name, value)) in fields.iter().enumerate() {
+					let separator = if i == 0 { "" } else { "," };
+					write!(f, "{}{}:{}", separator, string(name), value)?;
+				}
+				f.write_str("}")
+			},
+		}
+	}
+}
+
+pub trait ToJson {
+	fn to_json(&self) -> Value;
+}
+
+pub trait FromJson: Sized {
+	fn from_json(value: &Value) -> Result<Self, &'static str>;
+}
+
+/// An object with `fields`, in that order.
+pub fn object<const N: usize>(fields: [(&str, Value); N]) -> Value {
+	Value::Object(fields.into_iter().map(|(name, value)| (name.to_string(), value)).collect())
+}
+
+/// The field `name` of `object`, read as a `T`.
+pub fn field<T: FromJson>(object: &Value, name: &str) -> Result<T, &'static str> {
+	T::from_json(object.get(name).ok_or("Missing field")?)
+}
+
+impl<T: ToJson + ?Sized> ToJson for &T {
+	fn to_json(&self) -> Value {
+		(**self).to_json()
+	}
+}
+
+macro_rules! impl_json_for_numbers {
+	($($number:ty),*) => {$(
+		impl ToJson for $number {
+			fn to_json(&self) -> Value {
+				Value::Number(self.to_string())
+			}
+		}
+
+		impl FromJson for $number {
+			fn from_json(value: &Value) -> Result<Self, &'static str> {
+				value.as_number().ok_or("Invalid number")
+			}
+		}
+	)*};
+}
+
+impl_json_for_numbers!(u8, u16, u32, u64, u128, usize);
+
+impl ToJson for bool {
+	fn to_json(&self) -> Value {
+		Value::Bool(*self)
+	}
+}
+
+impl ToJson for str {
+	fn to_json(&self) -> Value {
+		Value::String(self.to_string())
+	}
+}
+
+impl ToJson for String {
+	fn to_json(&self) -> Value {
+		Value::String(self.clone())
+	}
+}
+
+impl FromJson for String {
+	fn from_json(value: &Value) -> Result<Self, &'static str> {
+		value.as_str().map(str::to_string).ok_or("Invalid string")
+	}
+}
+
+/// `None` is `null`.
+impl<T: ToJson> ToJson for Option<T> {
+	fn to_json(&self) -> Value {
+		self.as_ref().map_or(Value::Null, T::to_json)
+	}
+}
+
+impl<T: FromJson> FromJson for Option<T> {
+	fn from_json(value: &Value) -> Result<Self, &'static str> {
+		match value {
+			Value::Null => Ok(None),
+			value => T::from_json(value).map(Some),
+		}
+	}
+}
+
+impl<T: ToJson> ToJson for [T] {
+	fn to_json(&self) -> Value {
+		Value::Array(self.iter().map(T::to_json).collect())
+	}
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+	fn to_json(&self) -> Value {
+		self[..].to_json()
+	}
+}
+
+impl<T: FromJson> FromJson for Vec<T> {
+	fn from_json(value: &Value) -> Result<Self, &'static str> {
+		value.as_array().ok_or("Invalid array")?.iter().map(T::from_json).collect()
+	}
+}
+
+/// Hex-encoded, without a `0x` prefix.
+impl ToJson for Hash {
+	fn to_json(&self) -> Value {
+		Value::String(hashing::hex(self))
+	}
+}
+
+impl FromJson for Hash {
+	fn from_json(value: &Value) -> Result<Self, &'static str> {
+		let bytes = value.as_str().and_then(hashing::from_hex).ok_or("Invalid hash")?;
+		bytes.try_into().map_err(|_| "Invalid hash")
+	}
+}
+
+/// The SS58 address.
+impl ToJson for AccountId32 {
+	fn to_json(&self) -> Value {
+		Value::String(self.to_string())
+	}
+}
+
+impl FromJson for AccountId32 {
+	fn from_json(value: &Value) -> Result<Self, &'static str> {
+		value.as_str().ok_or("Invalid account")?.parse().map_err(|_| "Invalid account")
+	}
+}
+
+/// Hex-encoded, as the seal of blocks.
+impl ToJson for types::Signature {
+	fn to_json(&self) -> Value {
+		Value::String(hashing::hex(&self.encode()))
+	}
+}
+
+impl FromJson for types::Signature {
+	fn from_json(value: &Value) -> Result<Self, &'static str> {
+		let bytes = value.as_str().and_then(hashing::from_hex).ok_or("Invalid signature")?;
+		decode_all(&bytes)
+	}
+}
+
+impl<BlockNumber: ToJson, AccountId: ToJson> ToJson for support::Header<BlockNumber, AccountId> {
+	fn to_json(&self) -> Value {
+		object([
+			("number", self.block_number.to_json()),
+			("parent_hash", self.parent_hash.to_json()),
+			("extrinsics_root", self.extrinsics_root.to_json()),
+			("state_root", self.state_root.to_json()),
+			("author", self.author.to_json()),
+			("spec_version", self.spec_version.to_json()),
+		])
+	}
+}
+
+impl<BlockNumber: FromJson, AccountId: FromJson> FromJson
+	for support::Header<BlockNumber, AccountId>
+{
+	fn from_json(value: &Value) -> Result<Self, &'static str> {
+		Ok(support::Header {
+			block_number: field(value, "number")?,
+			parent_hash: field(value, "parent_hash")?,
+			extrinsics_root: field(value, "extrinsics_root")?,
+			state_root: field(value, "state_root")?,
+			author: field(value, "author")?,
+			spec_version: field(value, "spec_version")?,
+		})
+	}
+}
+
+/// Hex-encoded, next to the name of its call, which is only there to be read.
+impl ToJson for types::Extrinsic {
+	fn to_json(&self) -> Value {
+		object([
+			("call", Runtime::call_name(&self.call).to_json()),
+			("encoded", hashing::hex(&self.encode()).to_json()),
+		])
+	}
+}
+
+impl FromJson for types::Extrinsic {
+	fn from_json(value: &Value) -> Result<Self, &'static str> {
+		let hex = value.get("encoded").and_then(Value::as_str);
+		decode_all(&hex.and_then(hashing::from_hex).ok_or("Invalid extrinsic")?)
+	}
+}
+
+/// The fields of the header, followed by the extrinsics and the seal.
+impl ToJson for types::Block {
+	fn to_json(&self) -> Value {
+		let mut block = self.header.to_json();
+		if let Value::Object(fields) = &mut block {
+			fields.push(("extrinsics".to_string(), self.extrinsics.to_json()));
+			fields.push(("seal".to_string(), self.seal.to_json()));
+		}
+		block
+	}
+}
+
+impl FromJson for types::Block {
+	fn from_json(value: &Value) -> Result<Self, &'static str> {
+		Ok(types::Block {
+			header: FromJson::from_json(value)?,
+			extrinsics: field(value, "extrinsics")?,
+			seal: field(value, "seal")?,
+		})
+	}
+}
+
+/// The pallet and name of the event, the accounts it is about, and the event itself as Rust
+/// writes it.
+impl ToJson for system::EventRecord<Runtime> {
+	fn to_json(&self) -> Value {
+		let event = &self.event;
+		object([
+			("extrinsic", self.extrinsic_index.to_json()),
+			("pallet", event.pallet().to_json()),
+			("name", event.name().to_json()),
+			("accounts", event.accounts().to_json()),
+			("event", format!("{:?}", event).to_json()),
+		])
+	}
+}
+
+impl ToJson for indexer::Transfer {
+	fn to_json(&self) -> Value {
+		object([
+			("block_number", self.block_number.to_json()),
+			("extrinsic", self.extrinsic_index.to_json()),
+			("from", self.from.to_json()),
+			("to", self.to.to_json()),
+			("amount", self.amount.to_json()),
+		])
+	}
+}
+
+impl ToJson for indexer::IndexedClaim {
+	fn to_json(&self) -> Value {
+		object([
+			("hash", self.hash.to_json()),
+			("owner", self.owner.to_json()),
+			("block_number", self.block_number.to_json()),
+		])
+	}
+}
+
+/// `s` as a JSON string literal, quoted and escaped.
+pub fn string(s: &str) -> String {
+	let mut json = String::from('"');
+	for c in s.chars() {
+		match c {
+			'"' => json.push_str("\\\""),
+			'\\' => json.push_str("\\\\"),
+			c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+			c => json.push(c),
+		}
+	}
+	json.push('"');
+	json
+}
+
+/// The string literal `json` starts with, unescaped, and what follows it.
+fn parse_string(json: &str) -> Option<(String, &str)> {
+	let mut chars = json.strip_prefix('"')?.char_indices();
+	let mut s = String::new();
+	while let Some((i, c)) = chars.next() {
+		match c {
+			'"' => return Some((s, &json[i + 2..])),
+			'\\' => match chars.next()?.1 {
+				'u' => {
+					let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+					s.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+				},
+				'n' => s.push('\n'),
+				't' => s.push('\t'),
+				c => s.push(c),
+			},
+			c => s.push(c),
+		}
+	}
+	None
+}
+
+/// The items of an array or the fields of an object, each read by `item`, up to `close`.
+fn parse_list<'a, T>(
+	json: &'a str,
+	close: char,
+	mut item: impl FnMut(&'a str) -> Option<(T, &'a str)>,
+) -> Option<(Vec<T>, &'a str)> {
+	let mut items = Vec::new();
+	if let Some(rest) = json.trim_start().strip_prefix(close) {
+		return Some((items, rest));
+	}
+	let mut json = json;
+	loop {
+		let (next, rest) = item(json)?;
+		items.push(next);
+		let rest = rest.trim_start();
+		match rest.strip_prefix(',') {
+			Some(rest) => json = rest,
+			None => return Some((items, rest.strip_prefix(close)?)),
+		}
+	}
+}
+
+/// The value `json` starts with, nested at most `depth` levels deep, and what follows it.
+fn parse_value(json: &str, depth: usize) -> Option<(Value, &str)> {
+	let json = json.trim_start();
+	if let Some(rest) = json.strip_prefix('[') {
+		let items = parse_list(rest, ']', |json| parse_value(json, depth.checked_sub(1)?))?;
+		return Some((Value::Array(items.0), items.1));
+	}
+	if let Some(rest) = json.strip_prefix('{') {
+		let fields = parse_list(rest, '}', |json| {
+			let (key, rest) = parse_string(json.trim_start())?;
+			let rest = rest.trim_start().strip_prefix(':')?;
+			let (value, rest) = parse_value(rest, depth.checked_sub(1)?)?;
+			Some(((key, value), rest))
+		})?;
+		return Some((Value::Object(fields.0), fields.1));
+	}
+	if json.starts_with('"') {
+		let (s, rest) = parse_string(json)?;
+		return Some((Value::String(s), rest));
+	}
+	let end = json
+		.find(|c: char| !(c.is_ascii_alphanumeric() || "+-.".contains(c)))
+		.unwrap_or(json.len());
+	let (token, rest) = json.split_at(end);
+	let value = match token {
+		"null" => Value::Null,
+		"true" => Value::Bool(true),
+		"false" => Value::Bool(false),
+		// Unlike Rust, JSON has no `inf` or `NaN`.
+		n if n.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c))
+			&& n.parse::<f64>().is_ok() =>
+		{
+			Value::Number(n.to_string())
+		},
+		_ => return None,
+	};
+	Some((value, rest))
+}
+
+/// Read the JSON document `json`.
+pub fn parse(json: &str) -> Result<Value, &'static str> {
+	match parse_value(json, MAX_DEPTH) {
+		Some((value, rest)) if rest.trim().is_empty() => Ok(value),
+		_ => Err("Invalid JSON"),
+	}
+}
+
+/// The strings of the list in the field `name` of the object `json`.
+pub fn strings(json: &str, name: &str) -> Option<Vec<String>> {
+	let list = parse(json).ok()?;
+	let list = list.get(name)?.as_array()?;
+	list.iter().map(|s| s.as_str().map(str::to_string)).collect()
+}
+
+#[cfg(test)]
+mod test {
+	#[test]
+	fn strings_are_escaped_and_read_back() {
+		assert_eq!(super::string("a\"b\\c\n"), r#""a\"b\\c\u000a""#);
+		let list = format!(r#"{{"n":1,"list":[{},"é"]}}"#, super::string("a\"b\\c\n"));
+		assert_eq!(super::strings(&list, "list"), Some(vec!["a\"b\\c\n".into(), "é".into()]));
+		assert_eq!(super::strings(r#"{"list":[]}"#, "list"), Some(Vec::new()));
+		assert_eq!(super::strings(r#"{"list":["a"#, "list"), None);
+	}
+
+	#[test]
+	fn documents_are_read() {
+		use super::{parse, Value};
+		let document = parse(r#" {"a": [1, -2.5e3, true, null], "b": {"c": "d"}, "e": []} "#);
+		let document = document.unwrap();
+		let a = document.get("a").and_then(Value::as_array).unwrap();
+		assert_eq!(a[0].as_number::<u128>(), Some(1));
+		assert_eq!(a[1], Value::Number("-2.5e3".to_string()));
+		assert_eq!(&a[2..], [Value::Bool(true), Value::Null]);
+		assert_eq!(document.get("b").and_then(|b| b.get("c")?.as_str()), Some("d"));
+		assert_eq!(document.get("e").and_then(Value::as_array), Some(&[][..]));
+		for invalid in ["", "[1,]", "{\"a\" 1}", "[1] 2", "nul", "{1:2}", "NaN"] {
+			assert_eq!(parse(invalid), Err("Invalid JSON"), "{}", invalid);
+		}
+		let nested = "[".repeat(1_000) + &"]".repeat(1_000);
+		assert_eq!(parse(&nested), Err("Invalid JSON"));
+	}
+
+	#[test]
+	fn values_are_written_and_read_back() {
+		use super::{field, object, parse, FromJson, ToJson};
+		let alice = crate::wallet::dev_account("alice");
+		let value = object([
+			("account", alice.to_json()),
+			("amounts", vec![1u128, u128::MAX].to_json()),
+			("seal", None::<u32>.to_json()),
+			("name", "a\"b".to_json()),
+		]);
+		let written = value.to_string();
+		assert!(written.starts_with(&format!(r#"{{"account":"{}","amounts":[1,"#, alice)));
+		assert!(written.ends_with(r#""seal":null,"name":"a\"b"}"#));
+		let read = parse(&written).unwrap();
+		assert_eq!(read, value);
+		assert_eq!(field::<crate::types::AccountId>(&read, "account"), Ok(alice));
+		assert_eq!(field(&read, "amounts"), Ok(vec![1, u128::MAX]));
+		assert_eq!(field::<Option<u32>>(&read, "seal"), Ok(None));
+		assert_eq!(field::<u32>(&read, "name"), Err("Invalid number"));
+		assert_eq!(<[u8; 32]>::from_json(&read), Err("Invalid hash"));
+	}
+}
@@ -0,0 +1,329 @@
+//! Keys are kept in a directory, one file per account, encrypted under a password: scrypt
+//! derives a key from the password and a random salt, and AES-256-GCM encrypts the secret seed
+//! with it, so a wrong password or a tampered file is noticed instead of yielding a wrong key.
+//! Every key file records the signature scheme of its key, and only its owner may read it.
+//!
+//! New keys come from mnemonic phrases, and keys can be inserted by secret URI, so the accounts
+//! derived from one phrase are restored from its backup, see the `mnemonic` module.
+
+use std::{
+	fs,
+	io::{self, Write},
+	path::{Path, PathBuf},
+};
+
+use crate::{
+	codec::{self, Encode},
+	crypto,
+	hashing::Hash,
+	mnemonic,
+	signature::Scheme,
+	types, wallet, RuntimeCall,
+};
+
+/// The version of the format of key files.
+pub const VERSION: u32 = 2;
+
+/// The cost of deriving a key from a password, see `crypto::scrypt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScryptParams {
+	pub log_n: u8,
+	pub r: u32,
+	pub p: u32,
+}
+
+impl ScryptParams {
+	/// The largest `log_n` of a key file, 1 GiB with `r` = 8.
+	pub const MAX_LOG_N: u8 = 20;
+	/// The largest `r * p` of a key file.
+	pub const MAX_R_P: u64 = 1 << 16;
+	/// The most memory deriving the key of a key file may take, `128 * r * 2^log_n` bytes.
+	pub const MAX_MEMORY: u64 = 1 << 30;
+
+	/// Whether deriving a key with the parameters takes bounded time and memory. The parameters
+	/// of a key file are read before the password is checked, so a crafted file could otherwise
+	/// make unlocking it take forever, or all the memory.
+	pub fn is_bounded(&self) -> bool {
+		let (r, p) = (u64::from(self.r), u64::from(self.p));
+		(1..=Self::MAX_LOG_N).contains(&self.log_n)
+			&& r >= 1 && p >= 1
+			&& r * p <= Self::MAX_R_P
+			&& (128 * r) << self.log_n <= Self::MAX_MEMORY
+	}
+}
+
+impl Default for ScryptParams {
+	/// 32 MiB and a fraction of a second per key, as commonly recommended for interactive use.
+	fn default() -> Self {
+		Self { log_n: 15, r: 8, p: 1 }
+	}
+}
+
+type KeyFile = (u32, Scheme, (u8, u32, u32), [u8; 16], [u8; 12], Vec<u8>);
+
+fn derive_key(password: &str, salt: &[u8], params: ScryptParams) -> [u8; 32] {
+	let mut key = [0; 32];
+	crypto::scrypt(password.as_bytes(), salt, params.log_n, params.r, params.p, &mut key);
+	key
+}
+
+/// Write the key file at `path`, readable and writable by its owner only.
+fn write_key_file(path: &Path, bytes: &[u8]) -> io::Result<()> {
+	let mut options = fs::OpenOptions::new();
+	options.write(true).create(true).truncate(true);
+	#[cfg(unix)]
+	std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+	let mut file = options.open(path)?;
+	// The mode only applies to new files, not to the one of a key inserted again.
+	#[cfg(unix)]
+	file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+	file.write_all(bytes)
+}
+
+/// The account and the scheme are authenticated with the seed, so a key file can't be passed
+/// off as another one.
+fn associated_data(account: &types::AccountId, scheme: Scheme) -> Vec<u8> {
+	(account, scheme).encode()
+}
+
+/// A key unlocked with its password, which signs extrinsics.
+pub struct Signer {
+	scheme: Scheme,
+	seed: [u8; 32],
+	account: types::AccountId,
+}
+
+impl Signer {
+	pub fn new(scheme: Scheme, seed: [u8; 32]) -> Self {
+		Self { scheme, account: scheme.public(&seed), seed }
+	}
+
+	pub fn account(&self) -> &types::AccountId {
+		&self.account
+	}
+
+	pub fn scheme(&self) -> Scheme {
+		self.scheme
+	}
+
+	pub fn sign_payload(&self, payload: &[u8]) -> types::Signature {
+		self.scheme.sign(&self.seed, payload)
+	}
+
+	/// The extrinsic dispatching `call` from the account of the key at `nonce`, on the chain with
+	/// `genesis_hash`.
+	pub fn sign(
+		&self,
+		call: RuntimeCall,
+		nonce: types::Nonce,
+		genesis_hash: Hash,
+	) -> types::Extrinsic {
+		let unsigned =
+			wallet::UnsignedExtrinsic::new(self.account.clone(), call, nonce, genesis_hash);
+		let signature = self.sign_payload(&unsigned.signing_payload());
+		unsigned.attach(signature).expect("Signed by the key of the caller")
+	}
+}
+
+pub struct Keystore {
+	path: PathBuf,
+	params: ScryptParams,
+}
+
+impl Keystore {
+	pub fn new(path: impl AsRef<Path>) -> Self {
+		Self::with_params(path, ScryptParams::default())
+	}
+
+	/// A keystore encrypting new keys with `params`, e.g. cheaper ones in tests.
+	pub fn with_params(path: impl AsRef<Path>, params: ScryptParams) -> Self {
+		Self { path: path.as_ref().to_path_buf(), params }
+	}
+
+	fn key_path(&self, account: &types::AccountId) -> PathBuf {
+		self.path.join(account.to_string())
+	}
+
+	/// Store the `scheme` key with `seed` encrypted under `password`. Returns its account.
+	pub fn insert(
+		&self,
+		scheme: Scheme,
+		seed: &[u8; 32],
+		password: &str,
+	) -> io::Result<types::AccountId> {
+		let account = scheme.public(seed);
+		let (salt, nonce) = (crypto::random_bytes::<16>()?, crypto::random_bytes::<12>()?);
+		let key = derive_key(password, &salt, self.params);
+		let sealed =
+			crypto::aes256_gcm_encrypt(&key, &nonce, seed, &associated_data(&account, scheme));
+		let params = (self.params.log_n, self.params.r, self.params.p);
+		let file: KeyFile = (VERSION, scheme, params, salt, nonce, sealed);
+
+		fs::create_dir_all(&self.path)?;
+		write_key_file(&self.key_path(&account), &file.encode())?;
+		Ok(account)
+	}
+
+	/// Store the `scheme` key of the secret URI `uri`, e.g. a phrase with a derivation path.
+	pub fn insert_uri(
+		&self,
+		scheme: Scheme,
+		uri: &str,
+		password: &str,
+	) -> io::Result<types::AccountId> {
+		let seed = mnemonic::secret_uri(uri, scheme)
+			.map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+		self.insert(scheme, &seed, password)
+	}
+
+	/// Generate a new `scheme` key and store it encrypted under `password`. Returns the phrase to
+	/// back it up with, and its account.
+	pub fn generate(
+		&self,
+		scheme: Scheme,
+		password: &str,
+	) -> io::Result<(String, types::AccountId)> {
+		let phrase = mnemonic::generate()?;
+		let account = self.insert_uri(scheme, &phrase, password)?;
+		Ok((phrase, account))
+	}
+
+	/// The accounts of the stored keys, in order.
+	pub fn list(&self) -> io::Result<Vec<types::AccountId>> {
+		let entries = match fs::read_dir(&self.path) {
+			Ok(entries) => entries,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(e) => return Err(e),
+		};
+		let mut accounts = Vec::new();
+		for entry in entries {
+			let name = entry?.file_name();
+			accounts.extend(name.to_str().and_then(|name| name.parse::<types::AccountId>().ok()));
+		}
+		accounts.sort();
+		Ok(accounts)
+	}
+
+	/// Decrypt the key of `account` with `password`.
+	pub fn unlock(&self, account: &types::AccountId, password: &str) -> io::Result<Signer> {
+		let bytes = fs::read(self.key_path(account)).map_err(|e| match e.kind() {
+			io::ErrorKind::NotFound => io::Error::new(io::ErrorKind::NotFound, "Unknown key"),
+			_ => e,
+		})?;
+		let invalid = |error: &'static str| io::Error::new(io::ErrorKind::InvalidData, error);
+		// Files of other versions may not even decode, so the version is checked first.
+		if codec::Decode::decode(&mut &bytes[..]) != Ok(VERSION) {
+			return Err(invalid("Unsupported key file version"));
+		}
+		let (_, scheme, (log_n, r, p), salt, nonce, sealed): KeyFile =
+			codec::decode_all(&bytes).map_err(invalid)?;
+
+		let params = ScryptParams { log_n, r, p };
+		if !params.is_bounded() {
+			return Err(invalid("Unbounded scrypt parameters"));
+		}
+		let key = derive_key(password, &salt, params);
+		let associated_data = associated_data(account, scheme);
+		let seed = crypto::aes256_gcm_decrypt(&key, &nonce, &sealed, &associated_data)
+			.map_err(|_| io::Error::new(io::ErrorKind::PermissionDenied, "Wrong password"))?;
+		let seed = seed.try_into().map_err(|_| invalid("Invalid seed length"))?;
+		Ok(Signer::new(scheme, seed))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{KeyFile, Keystore, ScryptParams};
+	use crate::{
+		codec::{self, Encode},
+		signature::{Scheme, Verify},
+		support, wallet, RuntimeCall,
+	};
+
+	const CHEAP: ScryptParams = ScryptParams { log_n: 4, r: 1, p: 1 };
+
+	#[test]
+	fn keys_are_unlocked_with_their_password() {
+		let path = std::env::temp_dir().join(format!("blockchain-keystore-{}", std::process::id()));
+		let keystore = Keystore::with_params(&path, CHEAP);
+		assert_eq!(keystore.list().unwrap(), Vec::<crate::types::AccountId>::new());
+
+		let seed = [7; 32];
+		let account = keystore.insert(Scheme::Ed25519, &seed, "secret").unwrap();
+		assert_eq!(account, Scheme::Ed25519.public(&seed));
+		let (phrase, generated) = keystore.generate(Scheme::Sr25519, "other").unwrap();
+		let mut accounts = vec![account.clone(), generated.clone()];
+		accounts.sort();
+		assert_eq!(keystore.list().unwrap(), accounts);
+
+		for (account, password) in [(&account, "secret"), (&generated, "other")] {
+			let signer = keystore.unlock(account, password).unwrap();
+			let call = RuntimeCall::Balances(crate::balances::Call::Transfer {
+				to: wallet::dev_account("bob").into(),
+				amount: 1,
+			});
+			let extrinsic = signer.sign(call, 3, [9; 32]);
+			assert_eq!(extrinsic.caller.as_ref(), Some(account));
+			let (signature, extra) = extrinsic.signature.as_ref().unwrap();
+			assert_eq!(extra.nonce, 3);
+			let payload = support::signing_payload(&extrinsic.call, extra, &[9; 32], &[9; 32]);
+			assert!(signature.verify(&payload, account));
+			let other_chain = support::signing_payload(&extrinsic.call, extra, &[0; 32], &[0; 32]);
+			assert!(!signature.verify(&other_chain, account));
+		}
+		assert_eq!(keystore.unlock(&account, "secret").unwrap().scheme(), Scheme::Ed25519);
+		let wrong = keystore.unlock(&account, "guess").err().unwrap();
+		assert_eq!(wrong.to_string(), "Wrong password");
+		let unknown = keystore.unlock(&wallet::dev_account("nobody"), "secret").err().unwrap();
+		assert_eq!(unknown.to_string(), "Unknown key");
+
+		// Restoring from the phrase, e.g. in another keystore, yields the same account.
+		std::fs::remove_dir_all(&path).unwrap();
+		assert_eq!(keystore.insert_uri(Scheme::Sr25519, &phrase, "new").unwrap(), generated);
+		let derived = format!("{}//0", phrase);
+		assert_ne!(keystore.insert_uri(Scheme::Sr25519, &derived, "new").unwrap(), generated);
+		assert_ne!(keystore.insert_uri(Scheme::Ed25519, &phrase, "new").unwrap(), generated);
+		assert_eq!(keystore.list().unwrap().len(), 3);
+		let invalid = keystore.insert_uri(Scheme::Sr25519, "not a phrase", "new").err().unwrap();
+		assert_eq!(invalid.to_string(), "Unknown word");
+		std::fs::remove_dir_all(&path).unwrap();
+	}
+
+	#[test]
+	fn key_files_are_bound_to_their_account() {
+		let path = std::env::temp_dir().join(format!("blockchain-keys-{}", std::process::id()));
+		let keystore = Keystore::with_params(&path, CHEAP);
+		let first = keystore.insert(Scheme::Sr25519, &[1; 32], "secret").unwrap();
+		let second = keystore.insert(Scheme::Sr25519, &[2; 32], "secret").unwrap();
+		std::fs::copy(path.join(first.to_string()), path.join(second.to_string())).unwrap();
+
+		let swapped = keystore.unlock(&second, "secret").err().unwrap();
+		assert_eq!(swapped.to_string(), "Wrong password");
+		std::fs::remove_dir_all(&path).unwrap();
+	}
+
+	#[test]
+	fn key_files_with_unbounded_parameters_are_rejected() {
+		let path = std::env::temp_dir().join(format!("blockchain-params-{}", std::process::id()));
+		let keystore = Keystore::with_params(&path, CHEAP);
+		let account = keystore.insert(Scheme::Sr25519, &[1; 32], "secret").unwrap();
+		let file = path.join(account.to_string());
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			let mode = std::fs::metadata(&file).unwrap().permissions().mode();
+			assert_eq!(mode & 0o777, 0o600);
+		}
+
+		let (version, scheme, _, salt, nonce, sealed): KeyFile =
+			codec::decode_all(&std::fs::read(&file).unwrap()).unwrap();
+		for params in [(64, 1, 1), (0, 1, 1), (21, 1, 1), (4, 1 << 16, 2), (20, 64, 1), (4, 0, 1)] {
+			let crafted: KeyFile = (version, scheme, params, salt, nonce, sealed.clone());
+			std::fs::write(&file, crafted.encode()).unwrap();
+			let rejected = keystore.unlock(&account, "secret").err().unwrap();
+			assert_eq!(rejected.kind(), std::io::ErrorKind::InvalidData);
+		}
+		assert!(ScryptParams::default().is_bounded());
+		std::fs::remove_dir_all(&path).unwrap();
+	}
+}
@@ -0,0 +1,1401 @@
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	num::NonZeroUsize,
+	panic,
+	sync::OnceLock,
+	thread,
+};
+
+use codec::{Decode, Encode};
+use signature::{IdentifyAccount, Verify};
+use signed_extensions::SignedExtension;
+use support::{Dispatch, ValidateUnsigned};
+
+pub mod account;
+pub mod authorship;
+pub mod balances;
+pub mod benchmarking;
+pub mod bounties;
+pub mod bridge;
+pub mod chain;
+pub mod codec;
+pub mod collective;
+pub mod construct_runtime;
+pub mod contracts;
+pub mod crypto;
+pub mod database;
+pub mod democracy;
+pub mod escrow;
+pub mod explorer;
+pub mod finality;
+pub mod fuzzing;
+pub mod genesis;
+pub mod grpc;
+pub mod hashing;
+pub mod http2;
+pub mod identity;
+pub mod import_queue;
+pub mod indexer;
+pub mod inherents;
+pub mod json;
+pub mod keystore;
+pub mod light_client;
+pub mod logging;
+pub mod lottery;
+pub mod metadata;
+pub mod metrics;
+pub mod migrations;
+pub mod mnemonic;
+#[cfg(test)]
+mod mock;
+pub mod multisig;
+pub mod names;
+pub mod network;
+pub mod offchain;
+pub mod oracle;
+pub mod overlay;
+pub mod proof_of_existence;
+pub mod proxy;
+pub mod randomness;
+pub mod rest;
+pub mod rpc;
+pub mod scheduler;
+pub mod session;
+pub mod signature;
+pub mod signed_extensions;
+#[cfg(test)]
+mod simulation;
+pub mod sudo;
+pub mod support;
+pub mod sync;
+pub mod system;
+pub mod timestamp;
+pub mod transaction_payment;
+pub mod transaction_pool;
+pub mod treasury;
+pub mod trie;
+pub mod tx_pause;
+pub mod vesting;
+pub mod wallet;
+pub mod websocket;
+pub mod weights;
+
+pub mod types {
+	use crate::{support, RuntimeCall};
+
+	pub type AccountId = crate::account::AccountId32;
+	pub type Address = crate::account::MultiAddress;
+	pub type Balance = u128;
+	pub type BlockNumber = u32;
+	pub type Nonce = u32;
+	pub type RuntimeOrigin = support::RawOrigin<AccountId>;
+	pub type Signature = crate::signature::MultiSignature;
+	pub type SignedExtra = support::SignedExtra<Nonce, Balance>;
+	pub type Extrinsic = support::Extrinsic<AccountId, RuntimeCall, Signature, SignedExtra>;
+	pub type Header = support::Header<BlockNumber, AccountId>;
+	pub type Block = support::Block<Header, Extrinsic, Signature>;
+	pub type Content = String;
+	pub type Moment = u64;
+	pub type TaskName = String;
+	pub type OracleKey = String;
+	pub type OracleValue = u128;
+	pub type SessionKeys = [u8; 32];
+}
+
+/// The version of the state snapshot format, see `Runtime::export_state`.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// The fee paid by the caller of every extrinsic while blocks are no fuller than targeted, see
+/// `transaction_payment`.
+pub const TRANSACTION_FEE: types::Balance = 10;
+/// The share of the fees that goes to the treasury, the rest is burned.
+pub const TREASURY_FEE_SHARE: support::Percent = support::Percent(80);
+/// The share of the members of the collective whose approval lets a motion manage the
+/// treasury, tip from it, cancel referenda and pause calls.
+pub const COUNCIL_MAJORITY: support::Percent = support::Percent(50);
+
+/// The checks of signed extrinsics, see `Runtime::SIGNED_EXTENSIONS`.
+pub type SignedExtensions = (
+	signed_extensions::CheckEra,
+	signed_extensions::CheckRateLimit,
+	signed_extensions::CheckNonce,
+	signed_extensions::CheckWeight,
+	signed_extensions::ChargeTransactionPayment,
+);
+
+/// The fewest signatures a thread verifies, below which starting it costs more than it saves.
+const MIN_VERIFICATION_CHUNK: usize = 16;
+/// The fewest consecutive transfers dispatched on several threads, below which copying the
+/// runtime for every thread costs more than it saves.
+const MIN_PARALLEL_BATCH: usize = 64;
+
+construct_runtime! {
+	pub struct Runtime {
+		system: System {
+			call: Call = 20,
+			event: Event,
+			dispatch: |runtime, origin, call| {
+				support::ensure_root(origin)?;
+				match call {
+					system::Call::SetCode { code } => {
+						let version = Runtime::VERSION.of_code(&code)?;
+						if version.spec_version <= runtime.version().spec_version {
+							return Err("Spec version must increase");
+						}
+						runtime.system.set_code(code);
+					},
+					system::Call::SetCallFilter { filter } => runtime.system.set_call_filter(filter),
+				}
+				Ok(())
+			},
+		},
+		balances: Balances {
+			call: Call<Runtime> = 0,
+			event: Event<Runtime>,
+			dispatch: |runtime, origin, call| {
+				let lookup = AccountLookup::new(&runtime.names, runtime.system.block_number());
+				runtime.balances.dispatch(origin, &lookup, call)
+			},
+		},
+		proof_of_existence: ProofOfExistence {
+			call: Call<Runtime> = 1,
+			event: Event<Runtime>,
+			hold_reason: HoldReason,
+			dispatch: |runtime, origin, call| {
+				runtime.proof_of_existence.dispatch(&mut runtime.balances, origin, call)
+			},
+		},
+		sudo: Sudo {
+			call: Call<Runtime> = 2,
+			weight: |call| match call {
+				sudo::Call::Sudo { call: inner } => call.weight().saturating_add(inner.weight()),
+				sudo::Call::SetKey { .. } => call.weight(),
+			},
+			dispatch: |runtime, origin, call| {
+				let weight = call.weight();
+				match call {
+					// The sudo key pays no fee for the calls it dispatches.
+					sudo::Call::Sudo { call } => {
+						runtime.sudo.ensure_sudo(&support::ensure_signed(origin)?)?;
+						let post = runtime.dispatch_nested(types::RuntimeOrigin::Root, weight, *call)?;
+						Ok(support::PostDispatchInfo { pays_fee: support::Pays::No, ..post })
+					},
+					sudo::Call::SetKey { new } => {
+						let result = runtime.sudo.set_key(support::ensure_signed(origin)?, new);
+						result.map(support::PostDispatchInfo::from)
+					},
+				}
+			},
+		},
+		timestamp: Timestamp {
+			call: Call<Runtime> = 3,
+			dispatch: |runtime, origin, call| runtime.timestamp.dispatch(origin, call),
+		},
+		scheduler: Scheduler {
+			call: Call<Runtime> = 4,
+			dispatch: |runtime, origin, call| runtime.scheduler.dispatch(origin, call),
+		},
+		identity: Identity {
+			call: Call<Runtime> = 5,
+			dispatch: |runtime, origin, call| {
+				runtime.identity.dispatch(&mut runtime.balances, origin, call)
+			},
+		},
+		multisig: Multisig {
+			call: Call<Runtime> = 6,
+			weight: |call| match call {
+				multisig::Call::AsMulti { call: inner, .. } => {
+					call.weight().saturating_add(inner.weight())
+				},
+				_ => call.weight(),
+			},
+			dispatch: |runtime, origin, call| {
+				let now = multisig::Timepoint {
+					height: runtime.system.block_number(),
+					index: runtime.system.extrinsic_index().unwrap_or_default(),
+				};
+				let weight = call.weight();
+				match runtime.multisig.dispatch(now, origin, call)? {
+					Some((id, call)) => {
+						runtime.dispatch_nested(types::RuntimeOrigin::Signed(id), weight, call)
+					},
+					// Until the threshold is reached, the call isn't dispatched.
+					None => Ok(support::PostDispatchInfo::used(weight)),
+				}
+			},
+		},
+		proxy: Proxy {
+			call: Call<Runtime> = 7,
+			weight: |call| match call {
+				proxy::Call::Proxy { call: inner, .. }
+				| proxy::Call::ProxyAnnounced { call: inner, .. } => {
+					call.weight().saturating_add(inner.weight())
+				},
+				_ => call.weight(),
+			},
+			dispatch: |runtime, origin, call| {
+				let now = runtime.system.block_number();
+				let weight = call.weight();
+				match runtime.proxy.dispatch(now, origin, call)? {
+					Some((real, call)) => {
+						runtime.dispatch_nested(types::RuntimeOrigin::Signed(real), weight, call)
+					},
+					None => Ok(support::PostDispatchInfo::used(weight)),
+				}
+			},
+		},
+		vesting: Vesting {
+			call: Call<Runtime> = 8,
+			dispatch: |runtime, origin, call| {
+				let now = runtime.system.block_number();
+				runtime.vesting.dispatch(&mut runtime.balances, now, origin, call)
+			},
+		},
+		democracy: Democracy {
+			call: Call<Runtime> = 9,
+			hold_reason: HoldReason,
+			dispatch: |runtime, origin, call| {
+				let now = runtime.system.block_number();
+				runtime.democracy.dispatch(&mut runtime.balances, now, origin, call)
+			},
+		},
+		treasury: Treasury {
+			call: Call<Runtime> = 10,
+			dispatch: |runtime, origin, call| {
+				runtime.treasury.dispatch(&mut runtime.balances, origin, call)
+			},
+		},
+		collective: Collective {
+			call: Call<Runtime> = 11,
+			dispatch: |runtime, origin, call| {
+				let now = runtime.system.block_number();
+				// Approved motions go through the call filter, as their origin isn't root.
+				if let Some((origin, call)) = runtime.collective.dispatch(now, origin, call)? {
+					runtime.dispatch_filtered(origin, call)?;
+				}
+				Ok(())
+			},
+		},
+		contracts: Contracts {
+			call: Call<Runtime> = 12,
+			dispatch: |runtime, origin, call| {
+				runtime.contracts.dispatch(&mut runtime.balances, origin, call)
+			},
+		},
+		oracle: Oracle {
+			call: Call<Runtime> = 13,
+			dispatch: |runtime, origin, call| {
+				let now = runtime.system.block_number();
+				runtime.oracle.dispatch(now, origin, call)
+			},
+		},
+		randomness: Randomness,
+		bridge: Bridge {
+			call: Call<Runtime> = 14,
+			event: Event<Runtime>,
+			dispatch: |runtime, origin, call| {
+				runtime.bridge.dispatch(&mut runtime.balances, origin, call)
+			},
+		},
+		escrow: Escrow {
+			call: Call<Runtime> = 15,
+			dispatch: |runtime, origin, call| {
+				let now = runtime.system.block_number();
+				runtime.escrow.dispatch(&mut runtime.balances, now, origin, call)
+			},
+		},
+		names: Names {
+			call: Call<Runtime> = 16,
+			dispatch: |runtime, origin, call| {
+				let now = runtime.system.block_number();
+				runtime.names.dispatch(&mut runtime.balances, now, origin, call)
+			},
+		},
+		lottery: Lottery {
+			call: Call = 17,
+			dispatch: |runtime, origin, call| {
+				runtime.lottery.dispatch(&mut runtime.balances, origin, call)
+			},
+		},
+		bounties: Bounties {
+			call: Call<Runtime> = 18,
+			dispatch: |runtime, origin, call| {
+				let now = runtime.system.block_number();
+				runtime.bounties.dispatch(&mut runtime.balances, now, origin, call)
+			},
+		},
+		session: Session {
+			call: Call<Runtime> = 19,
+			dispatch: |runtime, origin, call| runtime.session.dispatch(origin, call),
+		},
+		tx_pause: TxPause {
+			call: Call = 21,
+			dispatch: |runtime, origin, call| runtime.tx_pause.dispatch(origin, call),
+		},
+		authorship: Authorship,
+		transaction_payment: TransactionPayment,
+	}
+}
+
+/// Resolves the addresses of calls, the one place they become accounts: an account is itself,
+/// a public key is the account derived from it, and a name is its owner while its registration
+/// lasts.
+pub struct AccountLookup<'a> {
+	names: &'a names::Pallet<Runtime>,
+	now: types::BlockNumber,
+}
+
+impl<'a> AccountLookup<'a> {
+	/// The lookup in block `now`, with the names of `names`.
+	pub fn new(names: &'a names::Pallet<Runtime>, now: types::BlockNumber) -> Self {
+		Self { names, now }
+	}
+}
+
+impl support::Lookup for AccountLookup<'_> {
+	type Source = types::Address;
+	type Target = types::AccountId;
+
+	fn lookup(&self, address: types::Address) -> Result<types::AccountId, &'static str> {
+		match address {
+			account::MultiAddress::Id(account) => Ok(account),
+			account::MultiAddress::PublicKey(signer) => Ok(signer.into_account()),
+			account::MultiAddress::Name(name) => {
+				self.names.lookup(&name, self.now).cloned().ok_or("Name not registered")
+			},
+		}
+	}
+}
+
+/// The kinds of proxy an account can register, each allowing a subset of the runtime calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyType {
+	Any,
+	BalancesOnly,
+	PoEOnly,
+}
+
+impl Encode for ProxyType {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		dest.push(*self as u8);
+	}
+}
+
+impl Decode for ProxyType {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => ProxyType::Any,
+			1 => ProxyType::BalancesOnly,
+			2 => ProxyType::PoEOnly,
+			_ => return Err("Invalid proxy type"),
+		})
+	}
+}
+
+impl metadata::TypeInfo for ProxyType {
+	fn type_def(_: &mut metadata::Registry) -> metadata::TypeDef {
+		let variants = ["Any", "BalancesOnly", "PoEOnly"].iter().enumerate();
+		metadata::TypeDef::Variant(
+			variants.map(|(i, name)| metadata::unit(name, i as u8)).collect(),
+		)
+	}
+}
+
+impl support::InstanceFilter<RuntimeCall> for ProxyType {
+	fn filter(&self, call: &RuntimeCall) -> bool {
+		match self {
+			ProxyType::Any => true,
+			ProxyType::BalancesOnly => matches!(call, RuntimeCall::Balances(_)),
+			ProxyType::PoEOnly => matches!(call, RuntimeCall::ProofOfExistence(_)),
+		}
+	}
+}
+
+impl system::Config for Runtime {
+	type AccountId = types::AccountId;
+	type BlockNumber = types::BlockNumber;
+	type Nonce = types::Nonce;
+	type RuntimeEvent = RuntimeEvent;
+	const MAX_BLOCK_WEIGHT: support::Weight = 20_000_000;
+	const MAX_EXTRINSICS_PER_ACCOUNT: u32 = 100;
+}
+
+impl balances::Config for Runtime {
+	type Balance = types::Balance;
+	type Address = types::Address;
+	type RuntimeHoldReason = RuntimeHoldReason;
+	const EXISTENTIAL_DEPOSIT: types::Balance = 5;
+
+	/// Dust goes to the treasury pot.
+	fn dust_removal() -> balances::DustRemoval<types::AccountId> {
+		balances::DustRemoval::Credit(<Runtime as treasury::Config>::account_id())
+	}
+}
+
+impl proof_of_existence::Config for Runtime {
+	type Content = types::Content;
+	const CLAIM_DEPOSIT: types::Balance = 1;
+	const CLAIM_TTL: types::BlockNumber = 1_000;
+	const MAX_NOTE_LENGTH: usize = 64;
+}
+
+impl sudo::Config for Runtime {
+	type RuntimeCall = RuntimeCall;
+}
+
+impl timestamp::Config for Runtime {
+	type Moment = types::Moment;
+	const MINIMUM_PERIOD: types::Moment = 1_000;
+	const MAX_DRIFT: types::Moment = 30_000;
+}
+
+impl scheduler::Config for Runtime {
+	type RuntimeCall = RuntimeCall;
+	type TaskName = types::TaskName;
+	const MAX_SCHEDULED_PER_BLOCK: usize = 50;
+}
+
+impl identity::Config for Runtime {
+	const BASIC_DEPOSIT: types::Balance = 10;
+	const MAX_FIELD_LENGTH: usize = 64;
+	const MAX_REGISTRARS: usize = 20;
+}
+
+impl multisig::Config for Runtime {
+	type RuntimeCall = RuntimeCall;
+	const MAX_SIGNATORIES: usize = 100;
+
+	fn multi_account_id(signatories: &[types::AccountId], threshold: u16) -> types::AccountId {
+		let entropy = ("multisig", signatories, threshold).encode();
+		types::AccountId::new(hashing::blake2_256(&entropy))
+	}
+}
+
+impl proxy::Config for Runtime {
+	type RuntimeCall = RuntimeCall;
+	type ProxyType = ProxyType;
+	const MAX_PROXIES: usize = 32;
+	const MAX_PENDING: usize = 32;
+}
+
+impl vesting::Config for Runtime {
+	const MIN_VESTED_TRANSFER: types::Balance = 10;
+	const MAX_SCHEDULES: usize = 28;
+
+	fn block_number_to_balance(block_number: types::BlockNumber) -> types::Balance {
+		block_number.into()
+	}
+}
+
+impl democracy::Config for Runtime {
+	type RuntimeCall = RuntimeCall;
+	const LAUNCH_PERIOD: types::BlockNumber = 10;
+	const VOTING_PERIOD: types::BlockNumber = 10;
+	const ENACTMENT_DELAY: types::BlockNumber = 5;
+	const MINIMUM_DEPOSIT: types::Balance = 10;
+	const MAX_PROPOSALS: usize = 100;
+
+	fn ensure_cancel_origin(origin: types::RuntimeOrigin) -> support::DispatchResult {
+		support::ensure_root_or_collective(origin, COUNCIL_MAJORITY)
+	}
+}
+
+impl treasury::Config for Runtime {
+	const PROPOSAL_BOND: support::Percent = support::Percent(5);
+	const PROPOSAL_BOND_MINIMUM: types::Balance = 10;
+	const SPEND_PERIOD: types::BlockNumber = 20;
+	const BURN: support::Percent = support::Percent(1);
+	const MAX_APPROVALS: usize = 100;
+
+	fn account_id() -> types::AccountId {
+		types::AccountId::from_pallet_id(b"py/trsry")
+	}
+
+	fn ensure_spend_origin(origin: types::RuntimeOrigin) -> support::DispatchResult {
+		support::ensure_root_or_collective(origin, COUNCIL_MAJORITY)
+	}
+}
+
+impl collective::Config for Runtime {
+	type RuntimeCall = RuntimeCall;
+	const MOTION_DURATION: types::BlockNumber = 20;
+	const MAX_MEMBERS: usize = 100;
+	const MAX_PROPOSALS: usize = 100;
+}
+
+impl contracts::Config for Runtime {
+	const MAX_CODE_SIZE: usize = 128 * 1024;
+	const MAX_GAS: u64 = 10_000_000;
+
+	fn contract_address(
+		deployer: &types::AccountId,
+		code_hash: &hashing::Hash,
+		salt: &[u8],
+	) -> types::AccountId {
+		let entropy = ("contract", deployer, code_hash, salt).encode();
+		types::AccountId::new(hashing::blake2_256(&entropy))
+	}
+
+	fn account_to_bytes(who: &types::AccountId) -> Vec<u8> {
+		who.as_ref().to_vec()
+	}
+
+	fn account_from_bytes(bytes: &[u8]) -> Option<types::AccountId> {
+		bytes.try_into().ok().map(types::AccountId::new)
+	}
+}
+
+impl oracle::Config for Runtime {
+	type OracleKey = types::OracleKey;
+	type OracleValue = types::OracleValue;
+	const MAX_FEEDERS: usize = 32;
+	const MAX_FEED_VALUES: usize = 16;
+	const MAX_AGE: types::BlockNumber = 10;
+	type Signature = types::Signature;
+
+	fn verify(signature: &types::Signature, payload: &[u8], feeder: &types::AccountId) -> bool {
+		signature.verify(payload, feeder)
+	}
+}
+
+/// The unsigned calls the pallets accept, besides inherents.
+impl ValidateUnsigned<types::BlockNumber> for Runtime {
+	type Call = RuntimeCall;
+
+	fn validate_unsigned(
+		&self,
+		now: types::BlockNumber,
+		call: &RuntimeCall,
+	) -> Result<support::ValidTransaction, support::InvalidTransaction> {
+		match call {
+			RuntimeCall::Oracle(call) => self.oracle.validate_unsigned(now, call),
+			_ => Err(support::InvalidTransaction::Call),
+		}
+	}
+}
+
+impl randomness::Config for Runtime {
+	const RANDOM_MATERIAL_LEN: usize = 81;
+}
+
+impl bridge::Config for Runtime {
+	const MAX_RELAYERS: usize = 16;
+
+	fn account_id() -> types::AccountId {
+		types::AccountId::from_pallet_id(b"py/bridg")
+	}
+}
+
+impl escrow::Config for Runtime {
+	const MAX_TIMEOUT: types::BlockNumber = 100;
+}
+
+impl names::Config for Runtime {
+	const MIN_NAME_LENGTH: usize = 3;
+	const MAX_NAME_LENGTH: usize = 32;
+	const REGISTRATION_DEPOSIT: types::Balance = 10;
+	const REGISTRATION_PERIOD: types::BlockNumber = 100;
+}
+
+impl lottery::Config for Runtime {
+	const TICKET_PRICE: types::Balance = 10;
+	const LOTTERY_PERIOD: types::BlockNumber = 50;
+	const TREASURY_CUT: support::Percent = support::Percent(10);
+	const MAX_TICKETS: usize = 1_000;
+
+	fn account_id() -> types::AccountId {
+		types::AccountId::from_pallet_id(b"py/lotto")
+	}
+}
+
+impl bounties::Config for Runtime {
+	const CHALLENGE_PERIOD: types::BlockNumber = 10;
+	const MAX_DESCRIPTION_LENGTH: usize = 256;
+	const MAX_TIP: types::Balance = 100;
+
+	fn ensure_tip_origin(origin: types::RuntimeOrigin) -> support::DispatchResult {
+		support::ensure_root_or_collective(origin, COUNCIL_MAJORITY)
+	}
+}
+
+impl session::Config for Runtime {
+	type Keys = types::SessionKeys;
+	const SESSION_LENGTH: types::BlockNumber = 10;
+	const MAX_VALIDATORS: usize = 100;
+}
+
+impl tx_pause::Config for Runtime {
+	const MAX_PAUSED: usize = 32;
+
+	fn call_exists(pallet: &str, call: &str) -> bool {
+		let mut pallets = Runtime::metadata().pallets.into_iter();
+		pallets.any(|p| p.name == pallet && p.calls.iter().any(|c| c.name == call))
+	}
+
+	/// The collective can pause calls in an emergency.
+	fn ensure_pause_origin(origin: types::RuntimeOrigin) -> support::DispatchResult {
+		support::ensure_root_or_collective(origin, COUNCIL_MAJORITY)
+	}
+}
+
+impl authorship::Config for Runtime {
+	const BLOCK_REWARD: types::Balance = 5;
+}
+
+impl transaction_payment::Config for Runtime {
+	const BASE_FEE: types::Balance = TRANSACTION_FEE;
+	const TARGET_FULLNESS: support::Percent = support::Percent(25);
+	const MIN_MULTIPLIER: transaction_payment::Multiplier = transaction_payment::Multiplier::ONE;
+	const MAX_MULTIPLIER: transaction_payment::Multiplier =
+		transaction_payment::Multiplier(100_000_000);
+}
+
+impl Runtime {
+	pub const VERSION: support::RuntimeVersion =
+		support::RuntimeVersion { spec_name: "rust-blockchain", spec_version: 1, impl_version: 1 };
+
+	/// The storage migrations of all pallets, applied to a snapshot before it is imported.
+	pub const MIGRATIONS: &'static [migrations::Migration] = &[migrations::Migration {
+		pallet: "ProofOfExistence",
+		to: 1,
+		migrate: proof_of_existence::Pallet::<Runtime>::migrate_to_v1,
+	}];
+
+	/// The checks of signed extrinsics, in the order they run, see `signed_extensions`.
+	pub const SIGNED_EXTENSIONS: SignedExtensions = (
+		signed_extensions::CheckEra,
+		signed_extensions::CheckRateLimit,
+		signed_extensions::CheckNonce,
+		signed_extensions::CheckWeight,
+		signed_extensions::ChargeTransactionPayment,
+	);
+
+	/// The version in effect: the one of the code last set by `System::SetCode`, if any. The
+	/// node runs the same native logic for all versions, which may branch on it.
+	pub fn version(&self) -> support::RuntimeVersion {
+		let code = self.system.code();
+		code.and_then(|code| Self::VERSION.of_code(code).ok()).unwrap_or(Self::VERSION)
+	}
+
+	pub fn execute_block(&mut self, block: types::Block) -> support::DispatchResult {
+		self.execute_block_with(block, MIN_PARALLEL_BATCH)
+	}
+
+	/// `execute_block`, dispatching runs of at least `min_batch` transfers in parallel, see
+	/// `dispatch_batch`. Tests compare it to executing the same block in order.
+	pub(crate) fn execute_block_with(
+		&mut self,
+		block: types::Block,
+		min_batch: usize,
+	) -> support::DispatchResult {
+		if block.header.spec_version != self.version().spec_version {
+			return Err("Incompatible spec version");
+		}
+		if self.system.genesis_hash().is_none() {
+			let genesis_hash = self.state_root();
+			self.system.set_genesis_hash(genesis_hash);
+		}
+		self.system.inc_block_number();
+		if self.system.block_number() != block.header.block_number {
+			return Err("Block number mismatch");
+		}
+		// Weights and signatures are known up front, so a block that is too heavy or carries a
+		// forged extrinsic is rejected before any of it is executed.
+		let weight = block.extrinsics.iter().fold(0, |weight: support::Weight, extrinsic| {
+			weight.saturating_add(extrinsic.call.weight())
+		});
+		self.system.set_block_weight(weight)?;
+		// Extrinsics whose era is over are skipped below, and their birth block may be
+		// forgotten already.
+		let block_number = self.system.block_number();
+		let alive: Vec<_> = block
+			.extrinsics
+			.iter()
+			.filter(|extrinsic| {
+				let extra = extrinsic.signature.as_ref().map(|(_, extra)| extra);
+				extra.is_none_or(|extra| extra.era.is_alive(block_number.into()))
+			})
+			.collect();
+		if !self.are_signed_by_callers(&alive) {
+			return Err("Bad signature");
+		}
+
+		let block_hash = hashing::blake2_256(&block.header.encode());
+		let _block = logging::span(&[("block_number", &block_number)]);
+		self.system.on_initialize();
+		self.authorship.on_initialize(&mut self.balances, block.header.author.clone());
+		support::Hooks::on_initialize(self, block_number);
+		self.note_changes();
+
+		let mut extrinsics = block.extrinsics.into_iter().enumerate().peekable();
+		while let Some((idx, extrinsic)) = extrinsics.next() {
+			if let Some(accounts) = self.footprint(idx, &extrinsic) {
+				let mut batch = vec![(idx, extrinsic, accounts)];
+				while let Some(accounts) =
+					extrinsics.peek().and_then(|(idx, e)| self.footprint(*idx, e))
+				{
+					let (idx, extrinsic) =
+						extrinsics.next().expect("The extrinsic was just peeked");
+					batch.push((idx, extrinsic, accounts));
+				}
+				self.dispatch_batch(batch, min_batch);
+				continue;
+			}
+			let types::Extrinsic { caller, call, signature } = extrinsic;
+			// Inherents are provided by the block author: they consume no nonce and a failing
+			// inherent makes the whole block invalid.
+			if !inherents::is_in_place(&call, idx) {
+				return Err("Inherents must be at the front of the block");
+			}
+			if inherents::is_inherent(&call) {
+				self.dispatch(types::RuntimeOrigin::None, call)?;
+				continue;
+			}
+
+			// Signatures were checked above, so an extrinsic with a caller is signed by it.
+			let (Some(caller), Some((_, extra))) = (caller, signature) else {
+				self.system.set_extrinsic_index(Some(idx as u32));
+				// Unsigned extrinsics pay no fee, so only the calls a pallet vouches for are
+				// included.
+				if self.validate_unsigned(block_number, &call).is_err() {
+					return Err("Invalid unsigned extrinsic");
+				}
+				let _extrinsic = logging::span(&[("extrinsic_index", &idx)]);
+				let _ = self.apply_extrinsic(types::RuntimeOrigin::None, call);
+				continue;
+			};
+			self.apply_signed(idx, caller, call, extra);
+		}
+
+		self.system.set_extrinsic_index(None);
+		self.timestamp.on_finalize()?;
+		support::Hooks::on_finalize(self, block_number);
+		self.note_changes();
+		self.randomness.on_finalize(block_number - 1, block.header.parent_hash);
+		// What the extrinsics used, rather than declared, sets the fees of the next block.
+		let weight = self.system.block_weight();
+		let max_weight = <Self as system::Config>::MAX_BLOCK_WEIGHT;
+		self.transaction_payment.on_finalize(weight, max_weight);
+		self.system.on_finalize(block_number, block_hash);
+		self.system.commit();
+		self.balances.commit();
+		self.proof_of_existence.commit();
+		logging::debug("runtime", "Block executed", &[("weight", &weight)]);
+
+		Ok(())
+	}
+
+	/// Check and dispatch the signed extrinsic at `idx` of the block. The pool can't know in
+	/// which order its extrinsics end up in blocks, so an extrinsic failing the checks, e.g. at
+	/// another nonce, is skipped rather than invalidating the block.
+	fn apply_signed(
+		&mut self,
+		idx: usize,
+		caller: types::AccountId,
+		call: RuntimeCall,
+		extra: types::SignedExtra,
+	) {
+		self.system.set_extrinsic_index(Some(idx as u32));
+		let _extrinsic = logging::span(&[("extrinsic_index", &idx), ("caller", &caller)]);
+		let info = call.dispatch_info();
+		match Self::SIGNED_EXTENSIONS.pre_dispatch(self, &caller, &call, &extra) {
+			Ok(pre) => {
+				// The nonce and fee are kept, but a failing call leaves no other trace.
+				let result = self.apply_extrinsic(types::RuntimeOrigin::Signed(caller), call);
+				Self::SIGNED_EXTENSIONS.post_dispatch(self, pre, &info, &result);
+			},
+			Err(e) => {
+				let error = format!("{:?}", e);
+				logging::warn("runtime", "Extrinsic skipped", &[("error", &error)]);
+				self.system.refund_block_weight(info.weight);
+			},
+		}
+		// What paying the fee noted belongs to the extrinsic too, e.g. the refund.
+		self.note_changes();
+	}
+
+	/// The accounts a signed transfer at `idx` of the block touches: its caller and recipients,
+	/// besides the author and the treasury it pays, see `dispatch_batch`. Other extrinsics, and
+	/// transfers to the author or the treasury, have none, as they may touch anything.
+	fn footprint(
+		&self,
+		idx: usize,
+		extrinsic: &types::Extrinsic,
+	) -> Option<BTreeSet<types::AccountId>> {
+		let (Some(caller), Some(_)) = (&extrinsic.caller, &extrinsic.signature) else {
+			return None;
+		};
+		if !inherents::is_in_place(&extrinsic.call, idx) {
+			return None;
+		}
+		let (to, recipients) = match &extrinsic.call {
+			RuntimeCall::Balances(balances::Call::Transfer { to, .. }) => (Some(to), &[][..]),
+			RuntimeCall::Balances(balances::Call::TransferMany { recipients }) => {
+				(None, &recipients[..])
+			},
+			_ => return None,
+		};
+		let lookup = self.account_lookup();
+		let mut accounts = BTreeSet::from([caller.clone()]);
+		for to in to.into_iter().chain(recipients.iter().map(|(to, _)| to)) {
+			accounts.insert(support::Lookup::lookup(&lookup, to.clone()).ok()?);
+		}
+		let treasury = <Self as treasury::Config>::account_id();
+		let author = self.authorship.author();
+		let pays = accounts.contains(&treasury) || author.is_some_and(|a| accounts.contains(a));
+		(!pays).then_some(accounts)
+	}
+
+	/// Dispatch consecutive transfers of a block, with the accounts they touch. Transfers touching
+	/// disjoint accounts commute, so groups of transfers sharing accounts are dispatched on
+	/// copies of the runtime on several threads, each group in order, and their changes merged.
+	/// The fees and tips they pay commute too: the credits of the author and the treasury, and
+	/// the total issuance, add up, while the events are put back in the order of the block.
+	///
+	/// Small batches are dispatched in order, as are batches crediting an account that could
+	/// overflow or that doesn't exist yet, which the first credit would create.
+	fn dispatch_batch(
+		&mut self,
+		batch: Vec<(usize, types::Extrinsic, BTreeSet<types::AccountId>)>,
+		min_batch: usize,
+	) {
+		let mut credited: Vec<_> = self.authorship.author().cloned().into_iter().collect();
+		credited.push(<Self as treasury::Config>::account_id());
+		credited.sort();
+		credited.dedup();
+		let issuance = self.balances.total_issuance();
+		let mergeable = credited.iter().all(|account| {
+			let free = self.balances.balance(account);
+			free != 0 && free.checked_add(issuance).is_some()
+		});
+		if batch.len() < min_batch || !mergeable {
+			for (idx, extrinsic, _) in batch {
+				self.apply_signed_extrinsic(idx, extrinsic);
+			}
+			return;
+		}
+
+		// Groups of transfers, in order, that share accounts with no other group.
+		let mut groups: Vec<(BTreeSet<types::AccountId>, Vec<_>)> = Vec::new();
+		for (idx, extrinsic, accounts) in batch {
+			let mut group = (accounts, vec![(idx, extrinsic)]);
+			let (sharing, disjoint) = groups
+				.into_iter()
+				.partition::<Vec<_>, _>(|(accounts, _)| !accounts.is_disjoint(&group.0));
+			groups = disjoint;
+			for (accounts, extrinsics) in sharing {
+				group.0.extend(accounts);
+				group.1.extend(extrinsics);
+			}
+			group.1.sort_by_key(|(idx, _)| *idx);
+			groups.push(group);
+		}
+		// The largest groups first, each to the thread with the fewest transfers so far.
+		groups.sort_by_key(|(_, extrinsics)| core::cmp::Reverse(extrinsics.len()));
+		let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+		let mut buckets: Vec<(BTreeSet<_>, Vec<_>)> = Vec::new();
+		for (accounts, extrinsics) in groups {
+			if buckets.len() < threads {
+				buckets.push((accounts, extrinsics));
+				continue;
+			}
+			let bucket = buckets.iter_mut().min_by_key(|(_, extrinsics)| extrinsics.len());
+			let bucket = bucket.expect("There is at least one thread");
+			bucket.0.extend(accounts);
+			bucket.1.extend(extrinsics);
+			bucket.1.sort_by_key(|(idx, _)| *idx);
+		}
+
+		let base = self.clone();
+		let events = base.system.events().len();
+		let executed = thread::scope(|scope| {
+			let dispatching: Vec<_> = buckets
+				.into_iter()
+				.map(|(accounts, extrinsics)| {
+					let base = &base;
+					scope.spawn(move || {
+						let mut runtime = base.clone();
+						for (idx, extrinsic) in extrinsics {
+							runtime.apply_signed_extrinsic(idx, extrinsic);
+						}
+						(accounts, runtime)
+					})
+				})
+				.collect();
+			// Every thread is joined, so a panic while dispatching is passed on.
+			let executed: Vec<_> = dispatching
+				.into_iter()
+				.map(|thread| thread.join().unwrap_or_else(|panic| panic::resume_unwind(panic)))
+				.collect();
+			executed
+		});
+		for (accounts, runtime) in &executed {
+			self.system.merge(&base.system, &runtime.system, accounts);
+			self.balances.merge(&base.balances, &runtime.balances, accounts, &credited);
+		}
+		self.system.sort_events(events);
+	}
+
+	/// `apply_signed` for an extrinsic `footprint` found to be signed.
+	fn apply_signed_extrinsic(&mut self, idx: usize, extrinsic: types::Extrinsic) {
+		if let (Some(caller), Some((_, extra))) = (extrinsic.caller, extrinsic.signature) {
+			self.apply_signed(idx, caller, extrinsic.call, extra);
+		}
+	}
+
+	/// Move what the pallets noted since the last dispatch or hook to system: their events, and
+	/// the references balances took to accounts or dropped, which create and reap them.
+	pub fn note_changes(&mut self) {
+		self.collect_events();
+		let changes = self.balances.take_ref_changes();
+		self.system.apply_ref_changes(changes);
+	}
+
+	/// The lookup of addresses in the current block.
+	pub fn account_lookup(&self) -> AccountLookup<'_> {
+		AccountLookup::new(&self.names, self.system.block_number())
+	}
+
+	/// Whether the call filter of system blocks `call`, or it is paused, which holds for any
+	/// origin but root.
+	pub fn is_filtered(&self, call: &RuntimeCall) -> bool {
+		let (pallet_index, call_index) = call.indices();
+		let (pallet, name) = Self::call_name(call).split_once("::").unwrap_or_default();
+		!self.system.call_filter().allows(pallet_index, call_index)
+			|| self.tx_pause.is_paused(pallet, name)
+	}
+
+	/// Dispatch `call`, unless the call filter blocks it for `origin`. Nested calls are filtered
+	/// too, e.g. the call of a proxy, but inherents are not.
+	fn dispatch_filtered(
+		&mut self,
+		origin: types::RuntimeOrigin,
+		call: RuntimeCall,
+	) -> support::DispatchResultWithPostInfo {
+		if !matches!(origin, types::RuntimeOrigin::Root) && self.is_filtered(&call) {
+			return Err("Call is filtered");
+		}
+		self.dispatch_with_post_info(origin, call)
+	}
+
+	/// Dispatch `call` for a call of `weight` itself, e.g. through sudo, which used that and
+	/// what `call` used.
+	fn dispatch_nested(
+		&mut self,
+		origin: types::RuntimeOrigin,
+		weight: support::Weight,
+		call: RuntimeCall,
+	) -> support::DispatchResultWithPostInfo {
+		let info = call.dispatch_info();
+		let post = self.dispatch_filtered(origin, call)?;
+		let actual_weight = weight.saturating_add(post.calc_actual_weight(&info));
+		Ok(support::PostDispatchInfo {
+			actual_weight: Some(actual_weight),
+			pays_fee: post.pays_fee,
+		})
+	}
+
+	/// Dispatch the call of an extrinsic in a transaction, so a failing call leaves no trace.
+	/// The weight the call didn't use is taken off the weight of the block.
+	fn apply_extrinsic(
+		&mut self,
+		origin: types::RuntimeOrigin,
+		call: RuntimeCall,
+	) -> support::DispatchResultWithPostInfo {
+		let name = Self::call_name(&call);
+		let (index, _) = call.indices();
+		let info = call.dispatch_info();
+		let result =
+			support::with_transaction(self, |runtime| runtime.dispatch_filtered(origin, call));
+		match result {
+			Ok(post) => {
+				self.system.refund_block_weight(info.weight - post.calc_actual_weight(&info))
+			},
+			Err(e) => {
+				logging::warn("runtime", "Extrinsic failed", &[("call", &name), ("error", &e)]);
+				self.system.note_extrinsic_failed(Self::dispatch_error(index, e));
+			},
+		}
+		self.note_changes();
+		result
+	}
+
+	/// The pallet and call names of `call`, e.g. `Balances::Transfer`, from the metadata. They
+	/// are looked up once, rather than for every extrinsic executed.
+	pub fn call_name(call: &RuntimeCall) -> &'static str {
+		static NAMES: OnceLock<BTreeMap<(u8, u8), String>> = OnceLock::new();
+		let names = NAMES.get_or_init(|| {
+			let pallets = Self::metadata().pallets.into_iter();
+			let pallets = pallets.filter_map(|pallet| Some((pallet.index?, pallet)));
+			pallets
+				.flat_map(|(index, pallet)| {
+					pallet.calls.into_iter().map(move |call| {
+						((index, call.index), format!("{}::{}", pallet.name, call.name))
+					})
+				})
+				.collect()
+		});
+		names.get(&call.indices()).map_or("Unknown", String::as_str)
+	}
+
+	/// `error`, of a call of the pallet at `index`, in compact form, from the metadata, which is
+	/// built once rather than for every failed extrinsic.
+	pub fn dispatch_error(index: u8, error: &str) -> support::DispatchError {
+		static METADATA: OnceLock<metadata::RuntimeMetadata> = OnceLock::new();
+		METADATA.get_or_init(Self::metadata).dispatch_error(index, error)
+	}
+
+	/// The layout of calls and extrinsics, with the types of every call field, from the
+	/// metadata, so a tool can decode extrinsics without the Rust types of this crate.
+	pub fn types() -> metadata::RuntimeTypes {
+		use account::{AccountId32, MultiAddress};
+		use core::any::type_name;
+		let mut registry = metadata::Registry::new();
+		registry.register::<bool>();
+		registry.register::<u16>();
+		registry.register::<u32>();
+		registry.register::<u64>();
+		registry.register::<u128>();
+		registry.register::<String>();
+		registry.register::<Vec<u8>>();
+		registry.register::<Vec<String>>();
+		registry.register::<Vec<(String, u128)>>();
+		registry.register::<Vec<(MultiAddress, u128)>>();
+		registry.register::<Vec<AccountId32>>();
+		registry.register::<Option<Vec<u8>>>();
+		registry.register::<Option<AccountId32>>();
+		registry.register::<Option<ProxyType>>();
+		registry.register::<Option<multisig::Timepoint<u32>>>();
+		registry.register::<signature::MultiSignature>();
+		registry.register::<support::DispatchError>();
+		registry.register::<bridge::InboundMessage<AccountId32, u128>>();
+		registry.register::<democracy::Vote<u128>>();
+		registry.register::<escrow::Resolution>();
+		registry.register::<identity::IdentityInfo>();
+		registry.register::<identity::Judgement>();
+		registry.register::<system::CallFilter>();
+		registry.register::<vesting::VestingInfo<u128, u32>>();
+		// Calls wrapping calls, e.g. of sudo, refer to `RuntimeCall` before it is defined.
+		let call = registry.reserve(type_name::<RuntimeCall>());
+		let boxed = registry.reserve(type_name::<Box<RuntimeCall>>());
+		let field = metadata::TypeField { name: None, ty: call };
+		registry.define(boxed, metadata::TypeDef::Composite(vec![field]));
+		let pallets = Self::metadata().pallets;
+		let calls = registry.calls(&pallets).expect("Call field types registered");
+		registry.define(call, calls);
+		registry.events(&pallets).expect("Event field types registered");
+		registry.errors(&pallets);
+		let extrinsic = registry.reserve(type_name::<types::Extrinsic>());
+		let fields = vec![
+			registry.field::<Option<AccountId32>>("caller"),
+			metadata::TypeField { name: Some("call"), ty: call },
+			registry.field::<Option<(signature::MultiSignature, types::SignedExtra)>>("signature"),
+		];
+		registry.define(extrinsic, metadata::TypeDef::Composite(fields));
+
+		let unchecked_extrinsic =
+			registry.reserve("sp_runtime::generic::unchecked_extrinsic::UncheckedExtrinsic");
+		let params = vec![
+			("Address", Some(registry.register::<MultiAddress>())),
+			("Call", Some(call)),
+			("Signature", Some(registry.register::<signature::MultiSignature>())),
+			("Extra", Some(registry.register::<types::SignedExtra>())),
+		];
+		registry.set_params(unchecked_extrinsic, params);
+		let bytes = vec![registry.unnamed::<Vec<u8>>()];
+		registry.define(unchecked_extrinsic, metadata::TypeDef::Composite(bytes));
+		// Named as in Substrate, and ordered so their types are the fields of `SignedExtra` and
+		// their additional types what `signing_payload` appends to it: the genesis hash, then
+		// the hash of the birth block.
+		let (nothing, hash) = (registry.reserve("()"), registry.register::<hashing::Hash>());
+		let extension =
+			|identifier, ty, additional| metadata::SignedExtension { identifier, ty, additional };
+		let signed_extensions = vec![
+			extension("CheckGenesis", nothing, hash),
+			extension("CheckNonce", registry.register::<types::Nonce>(), nothing),
+			extension("CheckMortality", registry.register::<support::Era>(), hash),
+			extension("ChargeTransactionPayment", registry.register::<types::Balance>(), nothing),
+		];
+		let runtime = registry.reserve(type_name::<Runtime>());
+		registry.define(runtime, metadata::TypeDef::Composite(Vec::new()));
+		metadata::RuntimeTypes {
+			registry,
+			call,
+			extrinsic,
+			unchecked_extrinsic,
+			signed_extensions,
+			runtime,
+		}
+	}
+
+	/// The metadata V14 of the runtime, which tools written for Substrate chains read.
+	pub fn metadata_v14() -> Vec<u8> {
+		Self::metadata().encode_v14(&Self::types())
+	}
+
+	/// An extrinsic submitted to the node, in the envelope of Substrate, see
+	/// `Extrinsic::encode_standard`, or else as this crate encodes it. Extrinsics in the envelope
+	/// are signed by an account or public key, not by a name, which is only resolved in
+	/// dispatch.
+	pub fn decode_extrinsic(bytes: &[u8]) -> Result<types::Extrinsic, &'static str> {
+		let caller = |address| match address {
+			account::MultiAddress::Id(account) => Ok(account),
+			account::MultiAddress::PublicKey(signer) => Ok(signer.into_account()),
+			account::MultiAddress::Name(_) => Err("Extrinsics aren't signed by name"),
+		};
+		types::Extrinsic::decode_standard(bytes, caller)
+			.or_else(|_| codec::decode_all::<types::Extrinsic>(bytes))
+	}
+
+	/// The state committed to by the state root of block headers: the storage of every pallet.
+	pub fn storage(&self) -> trie::Storage {
+		let mut storage = trie::Storage::new();
+		self.system.storage_entries(&mut storage);
+		self.balances.storage_entries(&mut storage);
+		self.proof_of_existence.storage_entries(&mut storage);
+		self.sudo.storage_entries(&mut storage);
+		self.timestamp.storage_entries(&mut storage);
+		self.scheduler.storage_entries(&mut storage);
+		self.identity.storage_entries(&mut storage);
+		self.multisig.storage_entries(&mut storage);
+		self.proxy.storage_entries(&mut storage);
+		self.vesting.storage_entries(&mut storage);
+		self.democracy.storage_entries(&mut storage);
+		self.treasury.storage_entries(&mut storage);
+		self.collective.storage_entries(&mut storage);
+		self.contracts.storage_entries(&mut storage);
+		self.oracle.storage_entries(&mut storage);
+		self.randomness.storage_entries(&mut storage);
+		self.bridge.storage_entries(&mut storage);
+		self.escrow.storage_entries(&mut storage);
+		self.names.storage_entries(&mut storage);
+		self.lottery.storage_entries(&mut storage);
+		self.bounties.storage_entries(&mut storage);
+		self.session.storage_entries(&mut storage);
+		self.tx_pause.storage_entries(&mut storage);
+		self.authorship.storage_entries(&mut storage);
+		self.transaction_payment.storage_entries(&mut storage);
+		storage
+	}
+
+	pub fn state_root(&self) -> hashing::Hash {
+		trie::root(&self.storage())
+	}
+
+	/// Prove the values of `keys` in the current state, e.g. to a light client.
+	pub fn prove_storage(&self, keys: &[Vec<u8>]) -> support::StorageProof {
+		support::prove_storage(&self.storage(), keys)
+	}
+
+	/// A snapshot of the state, to back it up or bootstrap another node from it: the versions
+	/// of the format and runtime, the hashes of the recent blocks and the entries of the state
+	/// root.
+	pub fn export_state(&self) -> Vec<u8> {
+		let storage: Vec<_> = self.storage().into_iter().collect();
+		let version = (SNAPSHOT_VERSION, self.version().spec_version);
+		(version, self.system.block_hashes(), storage).encode()
+	}
+
+	/// The runtime with the state of a snapshot made by `export_state`.
+	pub fn import_state(snapshot: &[u8]) -> Result<Self, &'static str> {
+		type Snapshot =
+			((u32, u32), Vec<(types::BlockNumber, hashing::Hash)>, Vec<(Vec<u8>, Vec<u8>)>);
+		let ((version, spec_version), block_hashes, storage): Snapshot =
+			codec::decode_all(snapshot)?;
+		if version != SNAPSHOT_VERSION {
+			return Err("Unsupported snapshot version");
+		}
+
+		let mut storage = storage.into_iter().collect();
+		migrations::apply(&mut storage, Self::MIGRATIONS)?;
+		let mut runtime = Self::new();
+		runtime.system.restore_entries(&storage, block_hashes)?;
+		runtime.balances.restore_entries(&storage)?;
+		runtime.proof_of_existence.restore_entries(&storage)?;
+		runtime.sudo.restore_entries(&storage)?;
+		runtime.timestamp.restore_entries(&storage)?;
+		runtime.scheduler.restore_entries(&storage)?;
+		runtime.identity.restore_entries(&storage)?;
+		runtime.multisig.restore_entries(&storage)?;
+		runtime.proxy.restore_entries(&storage)?;
+		runtime.vesting.restore_entries(&storage)?;
+		runtime.democracy.restore_entries(&storage)?;
+		runtime.treasury.restore_entries(&storage)?;
+		runtime.collective.restore_entries(&storage)?;
+		runtime.contracts.restore_entries(&storage)?;
+		runtime.oracle.restore_entries(&storage)?;
+		runtime.randomness.restore_entries(&storage)?;
+		runtime.bridge.restore_entries(&storage)?;
+		runtime.escrow.restore_entries(&storage)?;
+		runtime.names.restore_entries(&storage)?;
+		runtime.lottery.restore_entries(&storage)?;
+		runtime.bounties.restore_entries(&storage)?;
+		runtime.session.restore_entries(&storage)?;
+		runtime.tx_pause.restore_entries(&storage)?;
+		runtime.authorship.restore_entries(&storage)?;
+		runtime.transaction_payment.restore_entries(&storage)?;
+		// The spec version follows from the restored code.
+		if spec_version != runtime.version().spec_version {
+			return Err("Incompatible spec version");
+		}
+		Ok(runtime)
+	}
+
+	/// Check that an extrinsic can be included in a block, without executing it, e.g. before
+	/// admitting it to the transaction pool: signed extrinsics as far as `SIGNED_EXTENSIONS`
+	/// go, and unsigned ones as far as a pallet accepts them, see `ValidateUnsigned`.
+	pub fn validate_extrinsic(
+		&self,
+		extrinsic: &types::Extrinsic,
+	) -> Result<support::ValidTransaction, support::InvalidTransaction> {
+		use support::InvalidTransaction;
+
+		let types::Extrinsic { caller, call, signature } = extrinsic;
+		if inherents::is_inherent(call) || self.is_filtered(call) {
+			return Err(InvalidTransaction::Call);
+		}
+		let (Some(caller), Some((_, extra))) = (caller, signature) else {
+			if !self.is_signed_by_caller(extrinsic) {
+				return Err(InvalidTransaction::BadProof);
+			}
+			signed_extensions::CheckWeight::check(call)?;
+			return self.validate_unsigned(self.system.block_number() + 1, call);
+		};
+		// The checks are cheaper than the signature, which commits to the birth block of the
+		// era, so they go first.
+		let valid = Self::SIGNED_EXTENSIONS.validate(self, caller, call, extra)?;
+		if !self.is_signed_by_caller(extrinsic) {
+			return Err(InvalidTransaction::BadProof);
+		}
+		if matches!(call, RuntimeCall::Sudo(_)) && self.sudo.ensure_sudo(caller).is_err() {
+			return Err(InvalidTransaction::Call);
+		}
+		Ok(valid)
+	}
+
+	/// The state root of the genesis state, which identifies the chain: signatures commit to it,
+	/// so they can't be replayed on another chain.
+	pub fn genesis_hash(&self) -> hashing::Hash {
+		self.system.genesis_hash().unwrap_or_else(|| self.state_root())
+	}
+
+	/// The hash a signature in `era` commits to besides the genesis hash: the hash of the birth
+	/// block of a mortal era, if it is still among the recent blocks, or the genesis hash again
+	/// for immortal eras.
+	pub fn birth_hash(&self, era: &support::Era) -> Option<hashing::Hash> {
+		match *era {
+			support::Era::Immortal => Some(self.genesis_hash()),
+			support::Era::Mortal { birth_block, .. } => {
+				self.recent_block_hash(types::BlockNumber::try_from(birth_block).ok()?)
+			},
+		}
+	}
+
+	/// The hash of block `n` if a mortal era can start at it: one of the recent blocks, or
+	/// genesis, whose hash is the genesis hash.
+	pub fn recent_block_hash(&self, n: types::BlockNumber) -> Option<hashing::Hash> {
+		match n {
+			0 => Some(self.genesis_hash()),
+			n => self.system.block_hash(n),
+		}
+	}
+
+	/// Whether `extrinsic` is signed by its caller, or unsigned, with neither. Unsigned
+	/// extrinsics are checked against the state when they are dispatched.
+	fn is_signed_by_caller(&self, extrinsic: &types::Extrinsic) -> bool {
+		match (&extrinsic.caller, &extrinsic.call, &extrinsic.signature) {
+			(Some(caller), call, Some((signature, extra))) => {
+				let Some(birth_hash) = self.birth_hash(&extra.era) else {
+					return false;
+				};
+				let genesis_hash = self.genesis_hash();
+				let payload = support::signing_payload(call, extra, &genesis_hash, &birth_hash);
+				signature.verify(&payload, caller)
+			},
+			(None, _, None) => true,
+			_ => false,
+		}
+	}
+
+	/// Whether every one of `extrinsics` is signed by its caller. Verifying signatures is most of
+	/// the work of executing a block, and doesn't depend on the state the other extrinsics leave,
+	/// so large blocks are verified on several threads.
+	///
+	/// Dispatching mostly stays in order, as extrinsics may touch any state the ones before them
+	/// left, except for runs of transfers, see `dispatch_batch`.
+	fn are_signed_by_callers(&self, extrinsics: &[&types::Extrinsic]) -> bool {
+		let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+		let chunk = extrinsics.len().div_ceil(threads).max(MIN_VERIFICATION_CHUNK);
+		thread::scope(|scope| {
+			let verifying: Vec<_> = extrinsics
+				.chunks(chunk)
+				.map(|chunk| {
+					scope.spawn(move || {
+						chunk.iter().all(|extrinsic| self.is_signed_by_caller(extrinsic))
+					})
+				})
+				.collect();
+			// Every thread is joined, so a panic while verifying is passed on.
+			let results: Vec<_> = verifying
+				.into_iter()
+				.map(|thread| thread.join().unwrap_or_else(|panic| panic::resume_unwind(panic)))
+				.collect();
+			results.into_iter().all(|signed| signed)
+		})
+	}
+
+	/// Check the inherents of a block against the local view of the importing node, before
+	/// executing it, see the `inherents` module.
+	pub fn check_inherents(
+		&self,
+		block: &types::Block,
+		local_now: types::Moment,
+	) -> support::DispatchResult {
+		inherents::check_inherents(self, block, local_now)
+	}
+}
+
+impl support::Hooks<types::BlockNumber> for Runtime {
+	fn on_initialize(&mut self, block_number: types::BlockNumber) {
+		self.proof_of_existence.on_initialize(block_number);
+		// Scheduled calls and enactments run in a transaction each, like extrinsics, so one that
+		// fails leaves no trace.
+		for (idx, scheduler::Scheduled { owner, call, .. }) in
+			self.scheduler.on_initialize(block_number).into_iter().enumerate()
+		{
+			let origin = types::RuntimeOrigin::Signed(owner);
+			let result =
+				support::with_transaction(self, |runtime| runtime.dispatch_filtered(origin, call));
+			if let Err(e) = result {
+				let fields = &[("task_index", &idx as _), ("error", &e as _)];
+				logging::warn("runtime::scheduler", "Scheduled call failed", fields);
+			}
+		}
+
+		for (idx, call) in self
+			.democracy
+			.on_initialize(&mut self.balances, block_number)
+			.into_iter()
+			.enumerate()
+		{
+			let origin = types::RuntimeOrigin::Root;
+			if let Err(e) =
+				support::with_transaction(self, |runtime| runtime.dispatch(origin, call))
+			{
+				let fields = &[("proposal_index", &idx as _), ("error", &e as _)];
+				logging::warn("runtime::democracy", "Enactment failed", fields);
+			}
+		}
+		self.escrow.on_initialize(&mut self.balances, block_number);
+		// There is no staking or consensus yet: validators change through `ForceNewValidators`.
+		self.session.on_initialize(block_number, &mut (), &mut ());
+	}
+
+	fn on_finalize(&mut self, block_number: types::BlockNumber) {
+		let cut = self.lottery.on_finalize(&mut self.balances, &self.randomness, block_number);
+		// The cut is burned if the pot is about to overflow.
+		let _ = self.treasury.on_unbalanced(&mut self.balances, cut);
+		self.treasury.on_finalize(&mut self.balances, block_number);
+		self.proof_of_existence.on_finalize(&mut self.balances, block_number);
+	}
+}
@@ -0,0 +1,144 @@
+//! A light client follows the chain by its headers only, without executing any block. It
+//! checks that each header builds on a known one and is sealed by a validator, and trusts the
+//! state root of the headers: a value read from a full node is checked against it with a
+//! Merkle proof, see `support::verify_proof`.
+
+use std::collections::BTreeMap;
+
+use crate::{
+	codec::Encode,
+	hashing::{self, Hash},
+	import_queue::{ImportError, GENESIS_PARENT_HASH},
+	support, types,
+};
+
+pub struct LightClient {
+	headers: BTreeMap<Hash, types::Header>,
+	best: Hash,
+	/// The accounts that may author blocks. Anyone may while there are none.
+	validators: Vec<types::AccountId>,
+}
+
+impl LightClient {
+	pub fn new(validators: Vec<types::AccountId>) -> Self {
+		Self { headers: BTreeMap::new(), best: GENESIS_PARENT_HASH, validators }
+	}
+
+	pub fn set_validators(&mut self, validators: Vec<types::AccountId>) {
+		self.validators = validators;
+	}
+
+	pub fn best_hash(&self) -> Hash {
+		self.best
+	}
+
+	pub fn best_number(&self) -> types::BlockNumber {
+		self.number(&self.best)
+	}
+
+	pub fn header(&self, hash: &Hash) -> Option<&types::Header> {
+		self.headers.get(hash)
+	}
+
+	fn number(&self, hash: &Hash) -> types::BlockNumber {
+		self.headers.get(hash).map_or(0, |header| header.block_number)
+	}
+
+	/// Import a header received from a full node. The longest chain of headers is the best one.
+	pub fn import_header(&mut self, header: types::Header) -> Result<Hash, ImportError> {
+		let parent = header.parent_hash;
+		let known_parent = parent == GENESIS_PARENT_HASH || self.headers.contains_key(&parent);
+		if !known_parent || header.block_number != self.number(&parent) + 1 {
+			return Err(ImportError::UnknownParent);
+		}
+		if !self.validators.is_empty() && !self.validators.contains(&header.author) {
+			return Err(ImportError::BadSeal);
+		}
+		let hash = hashing::blake2_256(&header.encode());
+		if header.block_number > self.best_number() {
+			self.best = hash;
+		}
+		self.headers.insert(hash, header);
+		Ok(hash)
+	}
+
+	/// Import headers in order, e.g. the ones a full node sent in answer to a sync request.
+	/// Stops at the first bad header.
+	pub fn sync(
+		&mut self,
+		headers: impl IntoIterator<Item = types::Header>,
+	) -> Result<Hash, ImportError> {
+		for header in headers {
+			self.import_header(header)?;
+		}
+		Ok(self.best)
+	}
+
+	/// The value of `key` in the state after block `at`, read from the proof a full node sent.
+	pub fn read(
+		&self,
+		at: &Hash,
+		key: &[u8],
+		proof: &support::StorageProof,
+	) -> Result<Option<Vec<u8>>, &'static str> {
+		let header = self.headers.get(at).ok_or("Unknown block")?;
+		support::verify_proof(&header.state_root, proof, key)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::LightClient;
+	use crate::{
+		codec::Encode, import_queue, import_queue::ImportError, support, trie, types,
+		wallet::dev_account, Runtime, RuntimeCall,
+	};
+
+	/// Produce `n` blocks on a full node, returning their headers.
+	fn produce(full: &mut Runtime, n: u32) -> Vec<types::Header> {
+		let mut headers = Vec::new();
+		for _ in 0..n {
+			let now = full.timestamp.create_inherent(1_000).unwrap();
+			let extrinsics = vec![support::Extrinsic {
+				caller: None,
+				call: RuntimeCall::Timestamp(now),
+				signature: None,
+			}];
+			let block = import_queue::build_block(full, &dev_account("alice"), extrinsics).unwrap();
+			headers.push(block.header.clone());
+			full.execute_block(block).unwrap();
+		}
+		headers
+	}
+
+	#[test]
+	fn headers_are_synced() {
+		let mut full = Runtime::new();
+		let headers = produce(&mut full, 3);
+		let mut light = LightClient::new(vec![dev_account("bob")]);
+
+		assert_eq!(light.sync(headers.clone()), Err(ImportError::BadSeal));
+		light.set_validators(vec![dev_account("alice")]);
+		assert_eq!(light.sync(headers[1..].to_vec()), Err(ImportError::UnknownParent));
+		assert!(light.sync(headers).is_ok());
+		assert_eq!(light.best_number(), 3);
+	}
+
+	#[test]
+	fn reads_are_checked_against_the_state_root() {
+		let mut full = Runtime::new();
+		full.balances.set_balance(&dev_account("bob"), 100);
+		let mut light = LightClient::new(Vec::new());
+		let best = light.sync(produce(&mut full, 1)).unwrap();
+
+		let bob = trie::storage_key("Balances", "free", &dev_account("bob"));
+		let dave = trie::storage_key("Balances", "free", &dev_account("dave"));
+		let mut proof = full.prove_storage(&[bob.clone(), dave.clone()]);
+		assert_eq!(light.read(&best, &bob, &proof), Ok(Some(100u128.encode())));
+		assert_eq!(light.read(&best, &dave, &proof), Ok(None));
+
+		let forged = proof.entries.iter_mut().find(|entry| entry.key == bob).unwrap();
+		forged.value = 1_000u128.encode();
+		assert_eq!(light.read(&best, &bob, &proof), Err("Invalid proof"));
+	}
+}
@@ -0,0 +1,286 @@
+//! Structured logging. A record has a level, a target naming the part of the node it comes
+//! from, a message and fields. Spans add fields to all records logged while they are open,
+//! e.g. the block and extrinsic being executed.
+//!
+//! Which records are written is set by a filter like `info,runtime=debug`: a default level
+//! followed by levels for targets and their sub-targets (`runtime::fees`). Records go to
+//! stderr as text or, for log collectors, as one JSON object per line.
+
+use std::{
+	cell::RefCell,
+	fmt::{Display, Write},
+	ops::Range,
+	str::FromStr,
+	sync::Mutex,
+};
+
+use crate::json;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+	Error,
+	Warn,
+	Info,
+	Debug,
+	Trace,
+}
+
+impl Level {
+	fn as_str(&self) -> &'static str {
+		match self {
+			Level::Error => "ERROR",
+			Level::Warn => "WARN",
+			Level::Info => "INFO",
+			Level::Debug => "DEBUG",
+			Level::Trace => "TRACE",
+		}
+	}
+}
+
+/// A level, or `None` for `off`.
+fn parse_level(s: &str) -> Result<Option<Level>, &'static str> {
+	match s.to_ascii_lowercase().as_str() {
+		"off" => Ok(None),
+		"error" => Ok(Some(Level::Error)),
+		"warn" => Ok(Some(Level::Warn)),
+		"info" => Ok(Some(Level::Info)),
+		"debug" => Ok(Some(Level::Debug)),
+		"trace" => Ok(Some(Level::Trace)),
+		_ => Err("Invalid log level"),
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+	default: Option<Level>,
+	targets: Vec<(String, Option<Level>)>,
+}
+
+impl Filter {
+	/// Whether a record of `level` for `target` is written. The most specific directive
+	/// for the target applies.
+	pub fn enabled(&self, target: &str, level: Level) -> bool {
+		let matching = self.targets.iter().filter(|(prefix, _)| {
+			target == prefix
+				|| target.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.starts_with("::"))
+		});
+		let max = matching
+			.max_by_key(|(prefix, _)| prefix.len())
+			.map_or(self.default, |(_, max)| *max);
+		max.is_some_and(|max| level <= max)
+	}
+}
+
+impl Default for Filter {
+	fn default() -> Self {
+		Self { default: Some(Level::Info), targets: Vec::new() }
+	}
+}
+
+impl FromStr for Filter {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut filter = Filter { default: Some(Level::Info), targets: Vec::new() };
+		for directive in s.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+			match directive.split_once('=') {
+				Some((target, level)) => {
+					filter.targets.push((target.to_string(), parse_level(level)?))
+				},
+				None => filter.default = parse_level(directive)?,
+			}
+		}
+		Ok(filter)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+	#[default]
+	Text,
+	Json,
+}
+
+impl FromStr for Format {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"text" => Ok(Format::Text),
+			"json" => Ok(Format::Json),
+			_ => Err("Invalid log format"),
+		}
+	}
+}
+
+struct Config {
+	filter: Filter,
+	format: Format,
+}
+
+static CONFIG: Mutex<Option<Config>> = Mutex::new(None);
+
+/// The fields of the open spans of a thread, outermost first. Their values are written one
+/// after the other to a buffer kept across spans, so opening one, e.g. for every extrinsic
+/// executed, doesn't allocate once the buffer has grown.
+struct Spans {
+	values: String,
+	fields: Vec<(&'static str, Range<usize>)>,
+	/// The number of fields before each open span.
+	open: Vec<usize>,
+}
+
+thread_local! {
+	static SPANS: RefCell<Spans> = const {
+		RefCell::new(Spans { values: String::new(), fields: Vec::new(), open: Vec::new() })
+	};
+}
+
+/// Set the filter and format of the records. Until then, records of level info and more
+/// severe are written as text.
+pub fn init(filter: Filter, format: Format) {
+	*CONFIG.lock().expect("Logging lock poisoned") = Some(Config { filter, format });
+}
+
+/// Closes the span it was returned for when dropped.
+#[must_use]
+pub struct Span(());
+
+/// Open a span adding `fields` to the records logged by this thread until it is dropped.
+pub fn span(fields: &[(&'static str, &dyn Display)]) -> Span {
+	SPANS.with(|spans| {
+		let spans = &mut *spans.borrow_mut();
+		spans.open.push(spans.fields.len());
+		for (name, value) in fields {
+			let start = spans.values.len();
+			let _ = write!(spans.values, "{}", value);
+			spans.fields.push((name, start..spans.values.len()));
+		}
+	});
+	Span(())
+}
+
+impl Drop for Span {
+	fn drop(&mut self) {
+		SPANS.with(|spans| {
+			let spans = &mut *spans.borrow_mut();
+			let fields = spans.open.pop().unwrap_or_default();
+			let values = spans.fields.get(fields).map_or(spans.values.len(), |(_, v)| v.start);
+			spans.fields.truncate(fields);
+			spans.values.truncate(values);
+		});
+	}
+}
+
+/// The line a record is written as.
+pub fn format_record(
+	format: Format,
+	level: Level,
+	target: &str,
+	message: &str,
+	fields: &[(&str, String)],
+) -> String {
+	match format {
+		Format::Text => {
+			let mut line = format!("{} {}: {}", level.as_str(), target, message);
+			for (name, value) in fields {
+				match value.contains(char::is_whitespace) {
+					true => line.push_str(&format!(" {}={:?}", name, value)),
+					false => line.push_str(&format!(" {}={}", name, value)),
+				}
+			}
+			line
+		},
+		Format::Json => {
+			let fields: Vec<String> = fields
+				.iter()
+				.map(|(name, value)| format!("{}:{}", json::string(name), json::string(value)))
+				.collect();
+			format!(
+				"{{\"level\":\"{}\",\"target\":{},\"message\":{},\"fields\":{{{}}}}}",
+				level.as_str(),
+				json::string(target),
+				json::string(message),
+				fields.join(",")
+			)
+		},
+	}
+}
+
+pub fn log(level: Level, target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+	let config = CONFIG.lock().expect("Logging lock poisoned");
+	let (enabled, format) = match config.as_ref() {
+		Some(config) => (config.filter.enabled(target, level), config.format),
+		None => (Filter::default().enabled(target, level), Format::Text),
+	};
+	if !enabled {
+		return;
+	}
+	let mut all: Vec<(&str, String)> = SPANS.with(|spans| {
+		let spans = spans.borrow();
+		let fields = spans.fields.iter();
+		fields
+			.map(|(name, value)| (*name, spans.values[value.clone()].to_string()))
+			.collect()
+	});
+	all.extend(fields.iter().map(|(name, value)| (*name, value.to_string())));
+	eprintln!("{}", format_record(format, level, target, message, &all));
+}
+
+pub fn error(target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+	log(Level::Error, target, message, fields);
+}
+
+pub fn warn(target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+	log(Level::Warn, target, message, fields);
+}
+
+pub fn info(target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+	log(Level::Info, target, message, fields);
+}
+
+pub fn debug(target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+	log(Level::Debug, target, message, fields);
+}
+
+#[cfg(test)]
+mod test {
+	use super::{Filter, Format, Level};
+
+	#[test]
+	fn filters_apply_per_target() {
+		let filter: Filter = "warn,runtime=debug,runtime::fees=off".parse().unwrap();
+		assert!(filter.enabled("runtime", Level::Debug));
+		assert!(filter.enabled("runtime::hooks", Level::Debug));
+		assert!(!filter.enabled("runtime::fees", Level::Error));
+		assert!(!filter.enabled("runtimes", Level::Info));
+		assert!(filter.enabled("rest", Level::Warn));
+		assert_eq!("loud".parse::<Filter>(), Err("Invalid log level"));
+	}
+
+	#[test]
+	fn records_are_formatted_with_their_fields() {
+		let fields =
+			[("block_number", "1".to_string()), ("error", "Insufficient balance".to_string())];
+		let text =
+			super::format_record(Format::Text, Level::Warn, "runtime", "Call failed", &fields);
+		assert_eq!(text, "WARN runtime: Call failed block_number=1 error=\"Insufficient balance\"");
+		let json =
+			super::format_record(Format::Json, Level::Warn, "runtime", "Call failed", &fields);
+		assert_eq!(
+			json,
+			r#"{"level":"WARN","target":"runtime","message":"Call failed","fields":{"block_number":"1","error":"Insufficient balance"}}"#
+		);
+	}
+
+	#[test]
+	fn spans_are_closed_when_dropped() {
+		let block = super::span(&[("block_number", &1)]);
+		let extrinsic = super::span(&[("extrinsic_index", &0)]);
+		assert_eq!(super::SPANS.with(|spans| spans.borrow().open.len()), 2);
+		drop(extrinsic);
+		assert_eq!(super::SPANS.with(|spans| spans.borrow().values.clone()), "1");
+		drop(block);
+		assert!(super::SPANS.with(|spans| spans.borrow().fields.is_empty()));
+	}
+}
@@ -0,0 +1,286 @@
+use num::{CheckedAdd, CheckedSub};
+
+use crate::{
+	balances::{self, NegativeImbalance},
+	codec::{Decode, Encode},
+	hashing::Hash,
+	metadata::{storage, variant, PalletMetadata},
+	migrations::{self, StorageVersion},
+	support::{ensure_signed, DispatchResult, Percent, Randomness, Weight},
+	system::OriginFor,
+	trie::{self, Storage},
+};
+
+pub type LotteryIndex = u32;
+
+pub trait Config: balances::Config {
+	const TICKET_PRICE: Self::Balance;
+	/// How often (in blocks) a winner is drawn.
+	const LOTTERY_PERIOD: Self::BlockNumber;
+	/// The share of the pot that goes to the treasury when a winner is drawn.
+	const TREASURY_CUT: Percent;
+	/// The maximum number of tickets sold per lottery.
+	const MAX_TICKETS: usize;
+
+	/// The account holding the ticket sales until a winner is drawn.
+	fn account_id() -> Self::AccountId;
+}
+
+#[derive(Debug, Clone)]
+pub enum Call {
+	/// Buy a ticket for the current lottery; buying more tickets raises the odds of winning.
+	BuyTicket,
+}
+
+impl Encode for Call {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::BuyTicket => 0u8.encode_to(dest),
+		}
+	}
+}
+
+impl Decode for Call {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::BuyTicket,
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl Call {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::BuyTicket => 0,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::BuyTicket => 10_000,
+		}
+	}
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	/// One entry per ticket sold in the current lottery.
+	tickets: Vec<T::AccountId>,
+	lottery_index: LotteryIndex,
+	next_draw: T::BlockNumber,
+	/// The winner of each past lottery and the prize it got.
+	winners: Vec<(T::AccountId, T::Balance)>,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self {
+			tickets: Vec::new(),
+			lottery_index: 0,
+			next_draw: T::LOTTERY_PERIOD,
+			winners: Vec::new(),
+		}
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Lottery",
+			index: None,
+			calls: vec![variant("BuyTicket", 0, Vec::new())],
+			storage: vec![
+				storage::<Vec<T::AccountId>>("tickets"),
+				storage::<LotteryIndex>("lottery_index"),
+				storage::<T::BlockNumber>("next_draw"),
+				storage::<Vec<(T::AccountId, T::Balance)>>("winners"),
+			],
+			events: Vec::new(),
+			errors: vec!["Too many tickets"],
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Lottery", STORAGE_VERSION);
+		trie::insert_value(storage, "Lottery", "tickets", &self.tickets);
+		trie::insert_value(storage, "Lottery", "lottery_index", &self.lottery_index);
+		trie::insert_value(storage, "Lottery", "next_draw", &self.next_draw);
+		trie::insert_value(storage, "Lottery", "winners", &self.winners);
+	}
+
+	/// Restore the tickets and past winners `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult {
+		migrations::ensure_version(storage, "Lottery", STORAGE_VERSION)?;
+		trie::restore_value(storage, "Lottery", "tickets", &mut self.tickets)?;
+		trie::restore_value(storage, "Lottery", "lottery_index", &mut self.lottery_index)?;
+		trie::restore_value(storage, "Lottery", "next_draw", &mut self.next_draw)?;
+		trie::restore_value(storage, "Lottery", "winners", &mut self.winners)?;
+		Ok(())
+	}
+
+	/// Tickets are paid into the pot, so calls are dispatched together with the balances
+	/// pallet.
+	pub fn dispatch(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		origin: OriginFor<T>,
+		call: Call,
+	) -> DispatchResult {
+		let caller = ensure_signed(origin)?;
+		match call {
+			Call::BuyTicket => self.buy_ticket(balances, caller),
+		}
+	}
+
+	pub fn pot(&self, balances: &balances::Pallet<T>) -> T::Balance {
+		balances.balance(&T::account_id())
+	}
+
+	pub fn tickets(&self) -> &[T::AccountId] {
+		&self.tickets
+	}
+
+	pub fn lottery_index(&self) -> LotteryIndex {
+		self.lottery_index
+	}
+
+	pub fn winners(&self) -> &[(T::AccountId, T::Balance)] {
+		&self.winners
+	}
+
+	pub fn buy_ticket(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		buyer: T::AccountId,
+	) -> DispatchResult {
+		if self.tickets.len() >= T::MAX_TICKETS {
+			return Err("Too many tickets");
+		}
+		balances.transfer(buyer.clone(), T::account_id(), T::TICKET_PRICE)?;
+		self.tickets.push(buyer);
+		Ok(())
+	}
+
+	/// Called at the end of every block: at the end of a lottery period a ticket is drawn and
+	/// its buyer is paid the pot. Returns the treasury's cut, which has been taken out of the
+	/// pot and is up to the caller to resolve.
+	pub fn on_finalize(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		randomness: &impl Randomness<Hash, T::BlockNumber>,
+		now: T::BlockNumber,
+	) -> NegativeImbalance<T> {
+		if now < self.next_draw {
+			return NegativeImbalance::zero();
+		}
+		self.next_draw = now.checked_add(&T::LOTTERY_PERIOD).unwrap_or(now);
+
+		let tickets = core::mem::take(&mut self.tickets);
+		let subject = (b"lottery", self.lottery_index).encode();
+		self.lottery_index += 1;
+		if tickets.is_empty() {
+			return NegativeImbalance::zero();
+		}
+
+		let (random, _) = randomness.random(&subject);
+		let mut index = [0u8; 8];
+		index.copy_from_slice(&random[..8]);
+		let winner = tickets[(u64::from_le_bytes(index) % tickets.len() as u64) as usize].clone();
+
+		let pot = T::account_id();
+		let amount = balances.balance(&pot);
+		let cut = T::TREASURY_CUT.mul_floor(amount);
+		let prize = amount.checked_sub(&cut).unwrap_or(amount);
+		// The cut is taken first, so the pot isn't left with it as dust by the transfer.
+		let cut = balances.withdraw(&pot, cut).unwrap_or_else(|_| NegativeImbalance::zero());
+		if balances.transfer(pot.clone(), winner.clone(), prize).is_ok() {
+			self.winners.push((winner, prize));
+		}
+		cut
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{
+		balances,
+		hashing::Hash,
+		mock::Test,
+		support::{Percent, Randomness},
+	};
+
+	impl super::Config for Test {
+		const TICKET_PRICE: u128 = 10;
+		const LOTTERY_PERIOD: u32 = 5;
+		const TREASURY_CUT: Percent = Percent(10);
+		const MAX_TICKETS: usize = 3;
+
+		fn account_id() -> String {
+			"lottery".to_string()
+		}
+	}
+
+	/// Always draws the ticket at the index given by the first byte.
+	struct Fixed(u8);
+
+	impl Randomness<Hash, u32> for Fixed {
+		fn random(&self, _subject: &[u8]) -> (Hash, u32) {
+			let mut hash = [0u8; 32];
+			hash[0] = self.0;
+			(hash, 0)
+		}
+	}
+
+	#[test]
+	fn winner_gets_pot_minus_treasury_cut() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut balances: balances::Pallet<Test> = balances::Pallet::new();
+		let mut lottery: super::Pallet<Test> = super::Pallet::new();
+		balances.set_balance(&alice, 100);
+		balances.set_balance(&bob, 5);
+
+		assert_eq!(lottery.buy_ticket(&mut balances, alice.clone()), Ok(()));
+		assert_eq!(lottery.buy_ticket(&mut balances, bob.clone()), Err("Insufficient balance"));
+		assert_eq!(lottery.buy_ticket(&mut balances, alice.clone()), Ok(()));
+		assert_eq!(lottery.buy_ticket(&mut balances, alice.clone()), Ok(()));
+		assert_eq!(lottery.buy_ticket(&mut balances, alice.clone()), Err("Too many tickets"));
+		assert_eq!(lottery.pot(&balances), 30);
+
+		assert_eq!(lottery.on_finalize(&mut balances, &Fixed(1), 4).peek(), 0);
+		let cut = lottery.on_finalize(&mut balances, &Fixed(1), 5);
+		assert_eq!(cut.peek(), 3);
+		balances.burn(cut);
+		assert_eq!(balances.balance(&alice), 97);
+		assert_eq!(lottery.pot(&balances), 0);
+		assert_eq!(lottery.winners(), &[(alice.clone(), 27)]);
+		assert!(lottery.tickets().is_empty());
+		assert_eq!(lottery.lottery_index(), 1);
+	}
+
+	#[test]
+	fn draw_picks_ticket_by_random_value() {
+		let mut balances: balances::Pallet<Test> = balances::Pallet::new();
+		let mut lottery: super::Pallet<Test> = super::Pallet::new();
+		for buyer in ["alice", "bob", "charlie"] {
+			balances.set_balance(&buyer.to_string(), 10);
+			let _ = lottery.buy_ticket(&mut balances, buyer.to_string());
+		}
+
+		let cut = lottery.on_finalize(&mut balances, &Fixed(4), 5);
+		balances.burn(cut);
+		assert_eq!(lottery.winners(), &[("bob".to_string(), 27)]);
+
+		// Nobody bought a ticket for the second lottery, so nothing is drawn.
+		assert_eq!(lottery.on_finalize(&mut balances, &Fixed(4), 10).peek(), 0);
+		assert_eq!(lottery.lottery_index(), 2);
+	}
+}
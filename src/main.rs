@@ -1,152 +1,812 @@
-use support::Dispatch;
+use std::{
+	fs, io,
+	net::{TcpListener, TcpStream},
+	thread,
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-mod balances;
-mod proof_of_existence;
-mod support;
-mod system;
+use blockchain::{
+	balances, benchmarking,
+	chain::{self, Chain},
+	database::{self, Database},
+	explorer,
+	finality::Voter,
+	genesis::GenesisBuilder,
+	grpc, hashing, import_queue,
+	indexer::Indexer,
+	inherents,
+	keystore::{Keystore, Signer},
+	logging,
+	metrics::Metrics,
+	mnemonic,
+	network::Peer,
+	offchain, proof_of_existence, rest, rpc,
+	signature::Scheme,
+	sudo, support,
+	sync::BlockSync,
+	system,
+	transaction_pool::TransactionPool,
+	types,
+	wallet::{self, Client},
+	Runtime, RuntimeCall,
+};
 
-mod types {
-	use crate::{support, RuntimeCall};
+#[global_allocator]
+static ALLOCATOR: benchmarking::CountingAllocator = benchmarking::CountingAllocator;
 
-	pub type AccountId = String;
-	pub type Balance = u128;
-	pub type BlockNumber = u32;
-	pub type Nonce = u32;
-	pub type Extrinsic = support::Extrinsic<AccountId, RuntimeCall>;
-	pub type Header = support::Header<BlockNumber>;
-	pub type Block = support::Block<Header, Extrinsic>;
-	pub type Content = String;
+const USAGE: &str = "\
+Usage: blockchain <command> [options]
+
+Commands:
+  run                             Author blocks of the submitted extrinsics, serving the REST API
+                                  if --rest is passed, the gRPC service if --grpc is and the
+                                  subscriptions if --ws is, and sync with other nodes if
+                                  --listen or --bootnodes is
+  import-blocks <file>            Import the blocks in <file>
+  export-state <file>             Write a snapshot of the best state to <file>
+  chain export                    Print the blocks --from to --to of the best chain as JSON
+  chain import <file>             Import the blocks of a JSON file, as `chain export` prints them
+  purge-db                        Remove the database
+  key generate                    Generate a secret phrase
+  benchmark                       Time the calls of the `weights` module and print it with the
+                                  results
+  benchmark block                 Time the execution of a block of --size transfers
+  wallet generate                 Generate a key in the keystore
+  wallet insert <secret>          Store the key of a secret phrase or seed in the keystore
+  wallet list                     List the accounts of the keystore
+  wallet account <secret>         Print the account of a secret phrase or seed
+  wallet balance <account>        Query the balance of an account
+  wallet transfer <to> <amount>   Transfer from the account of --from to an address or @name
+  wallet claim <content>          Claim content for the account of --from
+  wallet sign <secret> <payload>  Sign a payload shown by --external, e.g. on an offline machine
+  explorer [<account|content>..]  Show the latest blocks of --node, and the balances of the
+                                  accounts and the claims of the contents, until interrupted
+
+Options:
+  --base-path <dir>               Where the database is kept, ./data by default
+  --pruning <mode>                archive, finalized or a number of blocks to keep the states of
+  --state-cache <entries>         The state entries the database caches, 4096 by default
+  --import-state <file>           The genesis state of a new database
+  --rest <address>                Where `run` serves the REST API
+  --grpc <address>                Where `run` serves the gRPC service of proto/node.proto
+  --ws <address>                  Where `run` serves JSON-RPC subscriptions over WebSocket
+  --listen <address>              Where `run` accepts the connections of other nodes
+  --bootnodes <address,..>        The nodes `run` connects to, and syncs from if they are ahead
+  --dev                           Make `run` author demo blocks of the dev account alice first
+  --oracle-source <key>=<url>     Where the off-chain worker of `run` fetches the value it feeds
+                                  the oracle for <key>, if the author is a feeder
+  --node <address>                The REST API the wallet talks to, 127.0.0.1:8080 by default
+  --from <account|number>         The account of the keystore the wallet sends from, or the
+                                  first block `chain export` prints, 1 by default
+  --to <number>                   The last block `chain export` prints, the best one by default
+  --tip <amount>                  Paid to the block author on top of the fee, for a higher priority
+  --external                      Sign wallet extrinsics elsewhere: show the payload, then read
+                                  the signature from the standard input
+  --author <account>              The account of the keystore `run` authors with, and the
+                                  validator of the default genesis
+  --keystore <dir>                Where keys are kept, ./keystore by default
+  --password <password>           The keystore password, read from the standard input otherwise
+  --scheme <scheme>               ed25519 or sr25519, the signature scheme of keys, sr25519 by default
+  --size <items>                  The size of the states `benchmark` runs on, 1000000 by default,
+                                  or of the blocks of `benchmark block`, 1000 by default
+  --repeats <runs>                How often `benchmark` runs every call, 20 by default
+  --log <filter>                  Log levels, e.g. warn,runtime=debug
+  --log-format <format>           text or json
+
+A secret is a phrase or a hex seed, optionally followed by //hard derivation junctions and a
+///password, e.g. \"<phrase>//polkadot//0\".
+";
+
+/// How often `run` puts the submitted extrinsics in a block.
+const BLOCK_TIME: Duration = Duration::from_secs(3);
+/// How often `run` casts the next finality vote of a validator author.
+const VOTE_TIME: Duration = Duration::from_millis(1500);
+/// Where a node that connects to `--bootnodes` accepts connections without `--listen`.
+const DEFAULT_LISTEN: &str = "127.0.0.1:0";
+/// How long a client may keep a read or write of its connection waiting. Connections are
+/// served between authoring blocks, so a slow client delays the next block by that much.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long the servers wait for connections before checking whether a block is due.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const DEFAULT_NODE: &str = "127.0.0.1:8080";
+/// How often `explorer` redraws.
+const EXPLORER_REFRESH: Duration = Duration::from_secs(1);
+
+/// The local wall-clock time, in milliseconds since the unix epoch.
+fn local_now() -> types::Moment {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.expect("Clock before unix epoch")
+		.as_millis() as types::Moment
+}
+
+/// The inherents the block author puts in front of every block it produces.
+fn inherents(runtime: &Runtime) -> Vec<types::Extrinsic> {
+	inherents::create_inherents(runtime, local_now()).expect("Inherents can be created")
+}
+
+/// The value following the command line flag `name`, if it was passed.
+fn arg(name: &str) -> Option<String> {
+	let args: Vec<String> = std::env::args().collect();
+	let i = args.iter().position(|arg| arg == name)?;
+	Some(args.get(i + 1).unwrap_or_else(|| panic!("Missing value for {}", name)).clone())
+}
+
+/// Whether the command line flag `name` was passed.
+fn flag(name: &str) -> bool {
+	std::env::args().any(|arg| arg == name)
+}
+
+/// The states the node keeps, set with `--pruning <archive|finalized|number of blocks>`.
+fn pruning() -> chain::Pruning {
+	match arg("--pruning") {
+		Some(mode) => mode.parse().expect("Invalid --pruning argument"),
+		None => chain::Pruning::Archive,
+	}
 }
 
-pub enum RuntimeCall {
-	Balances(balances::Call<Runtime>),
-	ProofOfExistence(proof_of_existence::Call<Runtime>),
+/// Set up logging from `--log <filter>`, e.g. `warn,runtime=debug`, and
+/// `--log-format <text|json>`.
+fn init_logging() {
+	let filter = arg("--log")
+		.map_or_else(Default::default, |filter| filter.parse().expect("Invalid --log argument"));
+	let format = arg("--log-format").map_or_else(Default::default, |format| {
+		format.parse().expect("Invalid --log-format argument")
+	});
+	logging::init(filter, format);
 }
 
-#[derive(Debug)]
-pub struct Runtime {
-	system: system::Pallet<Runtime>,
-	balances: balances::Pallet<Runtime>,
-	proof_of_existence: proof_of_existence::Pallet<Runtime>,
+/// The genesis state of a new database: the snapshot passed with `--import-state <file>`, or
+/// a fresh one with alice as the funded sudo key and `validator`, if there is one, as the
+/// validator sealing the blocks, with its own key as its session key.
+fn genesis(validator: Option<&types::AccountId>) -> Runtime {
+	match arg("--import-state") {
+		Some(path) => {
+			let snapshot = fs::read(path).expect("Snapshot can't be read");
+			Runtime::import_state(&snapshot).expect("Invalid snapshot")
+		},
+		None => {
+			let alice = wallet::dev_account("alice");
+			let mut genesis = GenesisBuilder::new().endow(&alice, 1_000).sudo(&alice);
+			if let Some(validator) = validator {
+				let mut keys = types::SessionKeys::default();
+				keys.copy_from_slice(validator.as_ref());
+				genesis = genesis.authority_with_keys(validator, keys);
+			}
+			genesis.build().expect("Invalid genesis")
+		},
+	}
+}
+
+/// The validator of the default genesis: the account of `--author`, which `run` authors with.
+fn validator() -> Option<types::AccountId> {
+	arg("--author").map(|author| author.parse().expect("Invalid --author account"))
+}
+
+/// The database in `--base-path <dir>`, caching `--state-cache <entries>` of the state.
+fn database() -> Database {
+	let database = Database::new(arg("--base-path").unwrap_or_else(|| "data".to_string()));
+	database.with_cache_capacity(
+		arg("--state-cache").map_or(database::DEFAULT_CACHE_CAPACITY, |entries| {
+			entries.parse().expect("Invalid --state-cache argument")
+		}),
+	)
+}
+
+/// The chain of `database`, starting from `genesis` with `validator` if it is new.
+fn open(database: &Database, validator: Option<&types::AccountId>) -> Chain {
+	database
+		.open(|| genesis(validator), pruning(), local_now())
+		.expect("Database can't be opened")
 }
 
-impl system::Config for Runtime {
-	type AccountId = types::AccountId;
-	type BlockNumber = types::BlockNumber;
-	type Nonce = types::Nonce;
+/// Import `block`, record it in `metrics` and print the outcome.
+fn import_block(chain: &mut Chain, metrics: &mut Metrics, block: types::Block) {
+	let started = Instant::now();
+	let result = chain.import(block.clone(), local_now());
+	if result.is_ok() {
+		metrics.block_imported(&block, started.elapsed());
+	}
+	match result {
+		Ok(chain::ImportOutcome::NewBest(imported)) => println!(
+			"Block {} imported, consumed weight {}",
+			imported.block_number, imported.weight
+		),
+		Ok(outcome) => println!("Block imported: {:?}", outcome),
+		Err(e) => eprintln!("Block import failed: {:?}", e),
+	}
+}
+
+/// `run`: serve the REST API, the gRPC service and the subscriptions on the addresses of
+/// `--rest`, `--grpc` and `--ws`, and sync with the nodes of `--listen` and `--bootnodes`, if
+/// they were passed, while authoring blocks, see `serve`. With `--dev`, the demo blocks are authored first.
+fn run() {
+	println!("Blockchain Running!");
+	let mut author = Author { key: author(), session_key: None };
+	let mut database = database();
+	let mut chain = open(&database, Some(author.key.account()));
+	let mut metrics = Metrics::new();
+	if flag("--dev") {
+		demo(&mut chain, &mut metrics, &mut author);
+		database.store(&chain).expect("Database can't be written");
+	}
+	let servers = Servers {
+		rest: listen(arg("--rest"), "REST API"),
+		grpc: listen(arg("--grpc"), "gRPC service"),
+		ws: listen(arg("--ws"), "WebSocket RPC"),
+	};
+	serve(&mut database, &mut chain, &mut metrics, servers, network(), &mut author);
 }
 
-impl balances::Config for Runtime {
-	type Balance = types::Balance;
+/// The listeners of the APIs `run` serves, each if its address was passed.
+struct Servers {
+	rest: Option<TcpListener>,
+	grpc: Option<TcpListener>,
+	ws: Option<TcpListener>,
 }
 
-impl proof_of_existence::Config for Runtime {
-	type Content = types::Content;
+/// The key `run` authors blocks with, and the session key it seals them with once unlocked.
+struct Author {
+	key: Signer,
+	session_key: Option<Signer>,
 }
 
-impl Runtime {
-	fn new() -> Self {
-		Self {
-			system: system::Pallet::new(),
-			balances: balances::Pallet::new(),
-			proof_of_existence: proof_of_existence::Pallet::new(),
+impl Author {
+	/// The block of `extrinsics` on the best block of `chain`, sealed with the session key.
+	fn block(
+		&mut self,
+		chain: &Chain,
+		extrinsics: Vec<types::Extrinsic>,
+	) -> Result<types::Block, &'static str> {
+		let block = import_queue::build_block(chain.state(), self.key.account(), extrinsics)?;
+		match self.session_key(chain.state())? {
+			Some(key) => Ok(import_queue::seal(block, key)),
+			None => Ok(block),
 		}
 	}
 
-	fn execute_block(&mut self, block: types::Block) -> support::DispatchResult {
-		self.system.inc_block_number();
-		if self.system.block_number() != block.header.block_number {
-			return Err("Block number mismatch");
+	/// The key of the session keys of the author on `state`: its own key, as in the default
+	/// genesis, or another key of the keystore, unlocked the first time. Blocks go unsealed
+	/// before there are validators, and an author that isn't one can't author any.
+	fn session_key(&mut self, state: &Runtime) -> Result<Option<&Signer>, &'static str> {
+		let validators = state.session.validators();
+		if validators.is_empty() {
+			return Ok(None);
+		}
+		let (_, keys) = validators
+			.iter()
+			.find(|(validator, _)| validator == self.key.account())
+			.ok_or("The author isn't a validator")?;
+		let public = types::AccountId::new(*keys);
+		if &public == self.key.account() {
+			return Ok(Some(&self.key));
 		}
+		if self.session_key.as_ref().is_none_or(|key| key.account() != &public) {
+			self.session_key = Some(unlock(&keystore(), &public));
+		}
+		Ok(self.session_key.as_ref())
+	}
+}
+
+/// The demo of `run --dev`: blocks by `author` of transfers, a claim and a sudo call of the dev
+/// account alice, which the default genesis funds and makes the sudo key.
+fn demo(chain: &mut Chain, metrics: &mut Metrics, author: &mut Author) {
+	let alice = wallet::dev_signer("alice");
+	let bob = wallet::dev_account("bob");
+	let charlie = wallet::dev_account("charlie");
+
+	let genesis_hash = chain.state().genesis_hash();
+	let nonce = |chain: &Chain| chain.state().system.get_nonce(alice.account());
 
-		for (idx, types::Extrinsic { caller, call }) in block.extrinsics.into_iter().enumerate() {
-			self.system.inc_nonce(&caller);
-			let _ = self.dispatch(caller, call).map_err(|e| {
-				eprintln!(
-					"Extrinsic Error \n\tBlock Number: {}\n\tExtrinsic Number: {}\n\tError: {}",
-					block.header.block_number, idx, e
-				)
-			});
+	let mut extrinsics = inherents(chain.state());
+	let first = nonce(chain);
+	extrinsics.extend([
+		alice.sign(
+			RuntimeCall::Balances(balances::Call::Transfer { to: bob.clone().into(), amount: 40 }),
+			first,
+			genesis_hash,
+		),
+		alice.sign(
+			RuntimeCall::Balances(balances::Call::Transfer {
+				to: charlie.clone().into(),
+				amount: 20,
+			}),
+			first + 1,
+			genesis_hash,
+		),
+		alice.sign(
+			RuntimeCall::Balances(balances::Call::Transfer {
+				to: charlie.clone().into(),
+				amount: 20,
+			}),
+			first + 2,
+			genesis_hash,
+		),
+	]);
+	let block_1 = author.block(chain, extrinsics).expect("Block can't be built");
+	import_block(chain, metrics, block_1);
+
+	let generic_claim = "Generic Claim".to_string();
+	let mut extrinsics = inherents(chain.state());
+	extrinsics.push(alice.sign(
+		RuntimeCall::ProofOfExistence(proof_of_existence::Call::CreateClaim {
+			claim: generic_claim.clone(),
+			note: None,
+		}),
+		nonce(chain),
+		genesis_hash,
+	));
+	let poe_block = author.block(chain, extrinsics).expect("Block can't be built");
+	import_block(chain, metrics, poe_block);
+
+	let mut extrinsics = inherents(chain.state());
+	extrinsics.push(alice.sign(
+		RuntimeCall::Sudo(sudo::Call::Sudo {
+			call: Box::new(RuntimeCall::Balances(balances::Call::ForceTransfer {
+				from: bob.clone(),
+				to: charlie.clone(),
+				amount: 10,
+			})),
+		}),
+		nonce(chain),
+		genesis_hash,
+	));
+	let sudo_block = author.block(chain, extrinsics).expect("Block can't be built");
+	import_block(chain, metrics, sudo_block);
+
+	println!("Alice nonce: {}", chain.state().system.get_nonce(alice.account()));
+	println!("{:?}", chain.state());
+}
+
+/// The peer of the node on the network of `--listen` and `--bootnodes`, if either was passed,
+/// connected to the bootnodes and asking them for their best blocks.
+fn network() -> Option<(Peer, BlockSync)> {
+	let (address, bootnodes) = (arg("--listen"), arg("--bootnodes"));
+	if address.is_none() && bootnodes.is_none() {
+		return None;
+	}
+	let address = address.unwrap_or_else(|| DEFAULT_LISTEN.to_string());
+	let listener = TcpListener::bind(&address)
+		.unwrap_or_else(|e| panic!("The node address can't be bound: {}", e));
+	println!("Accepting nodes on {}", listener.local_addr().expect("Listener is bound"));
+	let peer = Peer::listen(listener);
+	for bootnode in bootnodes.iter().flat_map(|bootnodes| bootnodes.split(',')) {
+		// A bootnode that is down is left out, the others may be up.
+		if let Err(e) = peer.connect(bootnode) {
+			logging::warn(
+				"network",
+				"Bootnode unreachable",
+				&[("bootnode", &bootnode), ("error", &e)],
+			);
 		}
+	}
+	let mut sync = BlockSync::new();
+	sync.start(&peer);
+	Some((peer, sync))
+}
 
-		Ok(())
+/// A listener on `address`, if there is one, for `api`.
+fn listen(address: Option<String>, api: &str) -> Option<TcpListener> {
+	let address = address?;
+	let listener = TcpListener::bind(&address)
+		.unwrap_or_else(|e| panic!("The address of the {} can't be bound: {}", api, e));
+	listener.set_nonblocking(true).expect("Listener can't be set up");
+	println!("Serving the {} on {}", api, address);
+	Some(listener)
+}
+
+/// A connection `listener` accepted, if it is there and a client was waiting.
+fn accept(listener: &Option<TcpListener>) -> Option<TcpStream> {
+	match listener.as_ref()?.accept() {
+		Ok((stream, _)) => {
+			stream.set_nonblocking(false).expect("Connection can't be set up");
+			stream
+				.set_read_timeout(Some(CONNECTION_TIMEOUT))
+				.expect("Connection can't be set up");
+			stream
+				.set_write_timeout(Some(CONNECTION_TIMEOUT))
+				.expect("Connection can't be set up");
+			Some(stream)
+		},
+		Err(e) if e.kind() == io::ErrorKind::WouldBlock => None,
+		Err(e) => panic!("Server failed: {}", e),
 	}
 }
 
-impl support::Dispatch for Runtime {
-	type Caller = <Runtime as system::Config>::AccountId;
-	type Call = RuntimeCall;
+/// Serve the REST API, the gRPC service and the subscriptions of `servers`, with `author`
+/// putting the submitted extrinsics in a block every `BLOCK_TIME`, sealed with its session key.
+/// The off-chain workers run after each block, submitting what they sign with the key of
+/// `author`, and the REST API answers history queries from the blocks indexed as the best chain
+/// changes.
+/// A validator author votes for the blocks to finalize every `VOTE_TIME`. On `network`, the
+/// node syncs the blocks of its peers and gossips what it authors, pools and votes.
+fn serve(
+	database: &mut Database,
+	chain: &mut Chain,
+	metrics: &mut Metrics,
+	servers: Servers,
+	mut network: Option<(Peer, BlockSync)>,
+	author: &mut Author,
+) {
+	let mut pool = TransactionPool::new();
+	let mut storage = offchain::LocalStorage::new();
+	if let Some(source) = arg("--oracle-source") {
+		let (key, url) = source.split_once('=').expect("Invalid --oracle-source argument");
+		storage.set(&[offchain::ORACLE_SOURCE_PREFIX, key.as_bytes()].concat(), url.as_bytes());
+	}
+	run_offchain_workers(chain, &mut pool, &mut storage, &author.key);
+	let mut voter = Voter::new(chain.state().session.validators().to_vec());
+	let mut rpc = servers.ws.as_ref().map(|_| rpc::Server::new(chain));
+	let mut indexer = Indexer::new();
+	let (mut last_block, mut last_vote) = (Instant::now(), Instant::now());
+	loop {
+		let (rest_stream, grpc_stream) = (accept(&servers.rest), accept(&servers.grpc));
+		let ws_stream = accept(&servers.ws);
+		if rest_stream.is_none() && grpc_stream.is_none() && ws_stream.is_none() {
+			thread::sleep(POLL_INTERVAL);
+		}
+		let pooled = pool.len();
+		indexer.follow(chain);
+		if let Some(stream) = rest_stream {
+			if let Err(e) = rest::handle_connection(chain, metrics, &indexer, &mut pool, stream) {
+				logging::warn("rest", "Request failed", &[("error", &e)]);
+			}
+		}
+		if let Some(stream) = grpc_stream {
+			if let Err(e) = grpc::handle_connection(chain, &mut pool, stream) {
+				logging::warn("grpc", "Connection failed", &[("error", &e)]);
+			}
+		}
+		if let (Some(stream), Some(rpc)) = (ws_stream, &mut rpc) {
+			if let Err(e) = rpc.connect(stream) {
+				logging::warn("rpc", "Handshake failed", &[("error", &e)]);
+			}
+		}
+		if let Some((peer, sync)) = &mut network {
+			// Submitted extrinsics are added at the end of the pool.
+			for extrinsic in pool.extrinsics()[pooled.min(pool.len())..].iter().cloned() {
+				peer.broadcast_transaction(extrinsic);
+			}
+			let best = chain.best_hash();
+			let received = sync.poll(peer, chain, local_now());
+			for extrinsic in received.transactions {
+				// Peers gossip what they pooled on other states, e.g. extrinsics in a block.
+				let _ = pool.submit(chain.state(), extrinsic);
+			}
+			received.votes.into_iter().for_each(|vote| voter.receive(vote));
+			if chain.best_hash() != best {
+				database.store(chain).expect("Database can't be written");
+			}
+		}
+		metrics.set_pool_size(pool.len());
+		if let Some(rpc) = &mut rpc {
+			rpc.poll(chain);
+		}
+		if last_vote.elapsed() >= VOTE_TIME {
+			last_vote = Instant::now();
+			let finalized = chain.finalized_head();
+			voter.set_voters(chain.state().session.validators());
+			let account = author.key.account().clone();
+			let votes = match author.session_key(chain.state()) {
+				Ok(Some(key)) => voter.vote(chain, &account, key, local_now()).0,
+				_ => {
+					voter.count(chain, local_now());
+					Vec::new()
+				},
+			};
+			if let Some((peer, _)) = &mut network {
+				votes.into_iter().for_each(|vote| peer.broadcast_vote(vote));
+			}
+			if chain.finalized_head() != finalized {
+				database.store(chain).expect("Database can't be written");
+			}
+		}
 
-	fn dispatch(
-		&mut self,
-		caller: Self::Caller,
-		runtime_call: Self::Call,
-	) -> support::DispatchResult {
-		match runtime_call {
-			RuntimeCall::Balances(call) => {
-				self.balances.dispatch(caller, call)?;
-			},
-			RuntimeCall::ProofOfExistence(call) => {
-				self.proof_of_existence.dispatch(caller, call)?;
+		if pool.is_empty() || last_block.elapsed() < BLOCK_TIME {
+			continue;
+		}
+		let mut extrinsics = inherents(chain.state());
+		let inherents_weight: support::Weight = extrinsics.iter().map(|e| e.call.weight()).sum();
+		let weight_limit = <Runtime as system::Config>::MAX_BLOCK_WEIGHT - inherents_weight;
+		extrinsics.extend(pool.take_ready(chain.state(), weight_limit));
+		metrics.set_pool_size(pool.len());
+		last_block = Instant::now();
+		match author.block(chain, extrinsics) {
+			Ok(block) => {
+				import_block(chain, metrics, block.clone());
+				if let Some((peer, _)) = &mut network {
+					peer.broadcast_block(block);
+				}
 			},
+			Err(e) => logging::warn("node", "Block can't be built", &[("error", &e)]),
 		}
-		Ok(())
+		database.store(chain).expect("Database can't be written");
+		metrics.set_state_cache(database.cache_stats());
+		run_offchain_workers(chain, &mut pool, &mut storage, &author.key);
 	}
 }
 
-fn main() {
-	println!("Blockchain Running!");
-	let mut runtime = Runtime::new();
-
-	let alice = "alice".to_string();
-	let bob = "bob".to_string();
-	let charlie = "charlie".to_string();
-
-	runtime.balances.set_balance(&alice, 100);
-	runtime.balances.set_balance(&bob, 0);
-
-	let block_1 = types::Block {
-		header: types::Header { block_number: 1 },
-		extrinsics: vec![
-			support::Extrinsic {
-				caller: alice.clone(),
-				call: RuntimeCall::Balances(balances::Call::Transfer {
-					to: bob.clone(),
-					amount: 40,
-				}),
-			},
-			support::Extrinsic {
-				caller: alice.clone(),
-				call: RuntimeCall::Balances(balances::Call::Transfer {
-					to: charlie.clone(),
-					amount: 20,
-				}),
-			},
-			support::Extrinsic {
-				caller: alice.clone(),
-				call: RuntimeCall::Balances(balances::Call::Transfer {
-					to: charlie.clone(),
-					amount: 20,
-				}),
+/// Run the off-chain workers on the best state, submitting what they sign with `signer` to
+/// `pool`.
+fn run_offchain_workers(
+	chain: &Chain,
+	pool: &mut TransactionPool,
+	storage: &mut offchain::LocalStorage,
+	signer: &Signer,
+) {
+	let nonce = pool.next_nonce(chain.state(), signer.account());
+	let http = offchain::Http;
+	for extrinsic in offchain::offchain_worker(chain.state(), &http, storage, signer, nonce) {
+		if let Err(e) = pool.submit(chain.state(), extrinsic) {
+			logging::warn("offchain", "Transaction rejected", &[("error", &format!("{:?}", e))]);
+		}
+	}
+}
+
+/// `import-blocks <file>`: import the blocks in `path`, encoded one after the other as in the
+/// `blocks` file of a database.
+fn import_blocks(path: &str) {
+	let mut database = database();
+	let mut chain = open(&database, validator().as_ref());
+	let mut metrics = Metrics::new();
+	let bytes = fs::read(path).expect("Block file can't be read");
+	for block in database::decode_blocks(&bytes).expect("Invalid block file") {
+		import_block(&mut chain, &mut metrics, block);
+	}
+	database.store(&chain).expect("Database can't be written");
+}
+
+/// `chain import <file>`: import the blocks of a JSON fixture, as `chain export` writes it.
+fn import_json(path: &str) {
+	let mut database = database();
+	let mut chain = open(&database, validator().as_ref());
+	let mut metrics = Metrics::new();
+	let json = fs::read_to_string(path).expect("Block file can't be read");
+	for block in database::blocks_from_json(&json).expect("Invalid block file") {
+		import_block(&mut chain, &mut metrics, block);
+	}
+	database.store(&chain).expect("Database can't be written");
+}
+
+/// `chain export`: print the blocks `--from` to `--to` of the best chain as JSON, all of them
+/// by default.
+fn export_json() {
+	let chain = open(&database(), validator().as_ref());
+	let from = arg("--from").map_or(1, |from| from.parse().expect("Invalid --from"));
+	let to = arg("--to").map_or(chain.best_number(), |to| to.parse().expect("Invalid --to"));
+	print!("{}", database::blocks_to_json(&chain.best_chain_blocks(from, to)));
+}
+
+/// `export-state <file>`: write a snapshot of the best state, which `--import-state` reads.
+fn export_state(path: &str) {
+	let chain = open(&database(), validator().as_ref());
+	fs::write(path, chain.state().export_state()).expect("Snapshot can't be written");
+	println!("State at block {} exported to {}", chain.best_number(), path);
+}
+
+/// `purge-db`: remove the database, so the next command starts from genesis.
+fn purge_db() {
+	let mut database = database();
+	database.purge().expect("Database can't be removed");
+	println!("Removed {}", database.path().display());
+}
+
+/// `key generate`: print a new secret phrase, its seed and its account.
+fn generate_key() {
+	let phrase = mnemonic::generate().expect("Randomness can't be read");
+	let seed = mnemonic::to_seed(&phrase, "").expect("Generated phrases are valid");
+	println!("Secret phrase: {}", phrase);
+	println!("Secret seed:   0x{}", hashing::hex(&seed));
+	println!("Account ID:    {}", scheme().public(&seed));
+}
+
+/// `benchmark`: time the benchmarked calls on states of `--size` items and print the weights
+/// module with the results.
+fn benchmark() {
+	let size = arg("--size").map_or(1_000_000, |size| size.parse().expect("Invalid --size"));
+	let repeats =
+		arg("--repeats").map_or(20, |repeats| repeats.parse().expect("Invalid --repeats"));
+	let measurements: Vec<_> = benchmarking::BENCHMARKS
+		.iter()
+		.map(|benchmark| {
+			eprintln!("Benchmarking {}::{}", benchmark.pallet, benchmark.call);
+			benchmarking::run(benchmark, size, repeats).expect("Benchmarked calls succeed")
+		})
+		.collect();
+	print!("{}", benchmarking::weights_source(&measurements));
+}
+
+/// `benchmark block`: time blocks of `--size` transfers and print the time per extrinsic.
+fn benchmark_block() {
+	let size = arg("--size").map_or(1_000, |size| size.parse().expect("Invalid --size"));
+	let repeats =
+		arg("--repeats").map_or(20, |repeats| repeats.parse().expect("Invalid --repeats"));
+	let measurement = benchmarking::block(size, repeats).expect("Benchmarked blocks execute");
+	println!("{:?} per extrinsic in blocks of {} transfers", measurement.execution, size);
+	println!("{} allocations per extrinsic", measurement.allocations);
+}
+
+/// The signature scheme of `--scheme <scheme>`, sr25519 by default.
+fn scheme() -> Scheme {
+	arg("--scheme").map_or(Scheme::default(), |scheme| scheme.parse().expect("Invalid --scheme"))
+}
+
+/// The seed of a secret phrase or seed passed on the command line.
+fn secret(uri: &str) -> [u8; 32] {
+	mnemonic::secret_uri(uri, scheme()).unwrap_or_else(|e| panic!("Invalid secret: {}", e))
+}
+
+/// The keystore in `--keystore <dir>`.
+fn keystore() -> Keystore {
+	Keystore::new(arg("--keystore").unwrap_or_else(|| "keystore".to_string()))
+}
+
+/// The keystore password, `--password <password>` or read from the standard input.
+fn password() -> String {
+	arg("--password").unwrap_or_else(|| {
+		eprint!("Keystore password: ");
+		let mut password = String::new();
+		io::stdin().read_line(&mut password).expect("Password can't be read");
+		password.trim_end_matches(['\r', '\n']).to_string()
+	})
+}
+
+/// The key of `account`, unlocked with the keystore password.
+fn unlock(keystore: &Keystore, account: &types::AccountId) -> Signer {
+	keystore
+		.unlock(account, &password())
+		.unwrap_or_else(|e| panic!("Key of {}: {}", account, e))
+}
+
+/// The key `run` authors blocks with: `--author <account>`, the first key of the keystore, or
+/// a new one.
+fn author() -> Signer {
+	let keystore = keystore();
+	let author = match arg("--author") {
+		Some(author) => author.parse().expect("Invalid --author account"),
+		None => match keystore.list().expect("Keystore can't be read").into_iter().next() {
+			Some(author) => author,
+			None => {
+				let (phrase, author) =
+					keystore.generate(scheme(), &password()).expect("Key can't be stored");
+				println!("Generated the author key {}, backed up by: {}", author, phrase);
+				return unlock(&keystore, &author);
 			},
-		],
+		},
 	};
+	unlock(&keystore, &author)
+}
 
-	let generic_claim = "Generic Claim".to_string();
-	let poe_block = types::Block {
-		header: types::Header { block_number: 2 },
-		extrinsics: vec![support::Extrinsic {
-			caller: alice.clone(),
-			call: RuntimeCall::ProofOfExistence(proof_of_existence::Call::CreateClaim {
-				claim: generic_claim.clone(),
-			}),
-		}],
+/// `call` from `--from <account>`, signed with its key in the keystore, or by an external
+/// signer if `--external` is passed: the node only ever sees the signature.
+fn signed(client: &Client, call: RuntimeCall) -> io::Result<types::Extrinsic> {
+	let from: types::AccountId = arg("--from")
+		.expect("Missing --from argument")
+		.parse()
+		.expect("Invalid --from account");
+	let tip = arg("--tip").map_or(0, |tip| tip.parse().expect("Invalid --tip"));
+	let unsigned = client.prepare(from.clone(), call)?.with_tip(tip);
+	let signature = if flag("--external") {
+		external_signature(&unsigned.signing_payload())
+	} else {
+		unlock(&keystore(), &from).sign_payload(&unsigned.signing_payload())
 	};
+	unsigned.attach(signature).map_err(io::Error::other)
+}
 
-	runtime.execute_block(block_1).expect("Wront Block");
-	runtime
-		.execute_block(poe_block)
-		.expect("Something went wrong wen creating claim");
+/// The signature of `payload` by an external signer, which is shown the payload and answers
+/// the hex of the signature on the standard input, e.g. from `wallet sign`.
+fn external_signature(payload: &[u8]) -> types::Signature {
+	println!("Signing payload: 0x{}", hashing::hex(payload));
+	eprint!("Signature: ");
+	let mut line = String::new();
+	io::stdin().read_line(&mut line).expect("Signature can't be read");
+	let bytes = hashing::from_hex(line.trim().trim_start_matches("0x"))
+		.and_then(|bytes| bytes.try_into().ok())
+		.expect("Invalid signature");
+	scheme().signature(bytes)
+}
 
-	println!("{:?}", runtime)
+/// `wallet <command>`: query the node at `--node <address>` and submit extrinsics to it.
+fn wallet(args: &[&str]) {
+	let client = Client::new(arg("--node").unwrap_or_else(|| DEFAULT_NODE.to_string()));
+	let result = match args {
+		["generate", ..] => {
+			let (phrase, account) =
+				keystore().generate(scheme(), &password()).expect("Key can't be stored");
+			println!("Secret phrase: {}", phrase);
+			println!("Account ID:    {}", account);
+			return;
+		},
+		["insert", uri, ..] => {
+			let seed = secret(uri);
+			println!(
+				"{}",
+				keystore().insert(scheme(), &seed, &password()).expect("Key can't be stored")
+			);
+			return;
+		},
+		["list", ..] => {
+			keystore()
+				.list()
+				.expect("Keystore can't be read")
+				.iter()
+				.for_each(|a| println!("{}", a));
+			return;
+		},
+		["account", uri, ..] => {
+			println!("{}", scheme().public(&secret(uri)));
+			return;
+		},
+		["balance", account, ..] => client.balance(&account.parse().expect("Invalid account")),
+		["sign", uri, payload, ..] => {
+			let payload =
+				hashing::from_hex(payload.trim_start_matches("0x")).expect("Invalid payload");
+			let signature = Signer::new(scheme(), secret(uri)).sign_payload(&payload);
+			println!("0x{}", hashing::hex(signature.as_bytes()));
+			return;
+		},
+		["transfer", to, amount, ..] => {
+			let amount = amount.parse().expect("Invalid amount");
+			let to: types::Address = to.parse().expect("Invalid address");
+			signed(&client, wallet::transfer(to, amount))
+				.and_then(|extrinsic| client.submit(&extrinsic))
+		},
+		["claim", content, ..] => signed(&client, wallet::create_claim(content.to_string()))
+			.and_then(|extrinsic| client.submit(&extrinsic)),
+		_ => {
+			eprint!("{}", USAGE);
+			std::process::exit(2);
+		},
+	};
+	match result {
+		Ok(response) => println!("{}", response),
+		Err(e) => {
+			eprintln!("Request failed: {}", e);
+			std::process::exit(1);
+		},
+	}
+}
+
+/// `explorer [<account|content>..]`: redraw the latest blocks of the node at `--node <address>`
+/// and what is watched, until interrupted.
+fn explorer(args: &[&str]) {
+	let client = Client::new(arg("--node").unwrap_or_else(|| DEFAULT_NODE.to_string()));
+	// The values of options aren't watched.
+	let watched: Vec<_> = args
+		.iter()
+		.enumerate()
+		.filter(|(i, arg)| !(arg.starts_with("--") || *i > 0 && args[i - 1].starts_with("--")))
+		.map(|(_, arg)| explorer::Watched::parse(arg))
+		.collect();
+	loop {
+		let screen = explorer::render(&client, &watched)
+			.unwrap_or_else(|e| format!("Node unreachable: {}\n", e));
+		// Clear the terminal, and draw from its top left corner.
+		print!("\x1b[2J\x1b[H{}", screen);
+		let _ = io::Write::flush(&mut io::stdout());
+		thread::sleep(EXPLORER_REFRESH);
+	}
+}
+
+fn main() {
+	init_logging();
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+		["run", ..] => run(),
+		["import-blocks", path, ..] => import_blocks(path),
+		["chain", "import", path, ..] => import_json(path),
+		["chain", "export", ..] => export_json(),
+		["export-state", path, ..] => export_state(path),
+		["purge-db", ..] => purge_db(),
+		["key", "generate", ..] => generate_key(),
+		["benchmark", "block", ..] => benchmark_block(),
+		["benchmark", ..] => benchmark(),
+		["wallet", args @ ..] => wallet(args),
+		["explorer", args @ ..] => explorer(args),
+		_ => {
+			eprint!("{}", USAGE);
+			std::process::exit(2);
+		},
+	}
 }
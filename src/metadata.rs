@@ -0,0 +1,724 @@
+//! A description of the runtime: its pallets with their calls, storage items, events and
+//! errors. It is encoded like any other runtime type, so external tools can read it to build
+//! and decode extrinsics instead of hard-coding the layout of every call.
+//!
+//! Types are described by their Rust name, e.g. `alloc::string::String`, as their encoding
+//! follows from it (see the `codec` module).
+//!
+//! `Registry` describes types by their layout instead, like the metadata V14 of Substrate: every
+//! type extrinsics are made of has an id, and refers to the types of its fields by theirs, so a
+//! tool can decode an extrinsic without knowing any Rust type, see `Runtime::types`.
+//! `RuntimeMetadata::encode_v14` writes both as the metadata V14 of Substrate, which tools
+//! written for Substrate chains, e.g. polkadot.js Apps or subxt, read to build extrinsics in its
+//! envelope, see `Extrinsic::encode_standard`. Storage items are left out of it, as their keys
+//! aren't hashed the way Substrate's are.
+
+use std::collections::BTreeMap;
+
+use crate::{
+	codec::{Compact, Decode, Encode},
+	support::{DispatchError, ModuleError, EXTRINSIC_FORMAT},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeMetadata {
+	pub pallets: Vec<PalletMetadata>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PalletMetadata {
+	pub name: &'static str,
+	/// The variant index of the pallet in `RuntimeCall`, if it has calls.
+	pub index: Option<u8>,
+	pub calls: Vec<VariantMetadata>,
+	pub storage: Vec<StorageMetadata>,
+	pub events: Vec<VariantMetadata>,
+	pub errors: Vec<&'static str>,
+}
+
+/// A call or event, with the index it is encoded with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariantMetadata {
+	pub name: &'static str,
+	pub index: u8,
+	pub fields: Vec<FieldMetadata>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldMetadata {
+	pub name: &'static str,
+	pub ty: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageMetadata {
+	pub name: &'static str,
+	pub ty: &'static str,
+}
+
+impl RuntimeMetadata {
+	/// `error` in compact form: in the pallet at `index` if it has it, or else in the first
+	/// pallet with an index that does, e.g. balances for a claim whose deposit can't be paid.
+	pub fn dispatch_error(&self, index: u8, error: &str) -> DispatchError {
+		let pallets = self.pallets.iter().filter(|pallet| pallet.index.is_some());
+		let mut pallets: Vec<_> = pallets.collect();
+		pallets.sort_by_key(|pallet| pallet.index != Some(index));
+		pallets
+			.into_iter()
+			.find_map(|pallet| {
+				let position = pallet.errors.iter().position(|name| *name == error)?;
+				Some(ModuleError { index: pallet.index?, error: position as u8 })
+			})
+			.map_or(DispatchError::Other, DispatchError::Module)
+	}
+
+	/// The names of the pallet and error `error` stands for, if the runtime has them.
+	pub fn error_name(&self, error: ModuleError) -> Option<(&'static str, &'static str)> {
+		let pallet = self.pallets.iter().find(|pallet| pallet.index == Some(error.index))?;
+		Some((pallet.name, pallet.errors.get(usize::from(error.error))?))
+	}
+
+	/// `self` as the metadata V14 of Substrate, behind `MAGIC`: the types of `types`, the
+	/// pallets with the ids of their call, event and error types, which `Registry::events` and
+	/// `Registry::errors` registered, and the envelope of extrinsics. Pallets without calls are
+	/// given the indices after those of the pallets with calls.
+	pub fn encode_v14(&self, types: &RuntimeTypes) -> Vec<u8> {
+		let mut out = MAGIC.to_vec();
+		out.push(V14);
+		let registry = &types.registry;
+		Compact(registry.types.len() as u64).encode_to(&mut out);
+		for (id, ty) in registry.types.iter().enumerate() {
+			(Compact(id as u64), ty.v14_path()).encode_to(&mut out);
+			let params = ty.params.iter().map(|(name, ty)| (*name, ty.map(compact)));
+			params.collect::<Vec<_>>().encode_to(&mut out);
+			ty.def.encode_v14(&mut out);
+			NO_DOCS.encode_to(&mut out);
+		}
+
+		let mut next_index = self.pallets.iter().filter_map(|pallet| pallet.index).max();
+		let id = |pallet: &PalletMetadata, item| {
+			registry.id(&format!("{}::{}", pallet.name, item)).map(compact)
+		};
+		Compact(self.pallets.len() as u64).encode_to(&mut out);
+		for pallet in &self.pallets {
+			let index = pallet.index.unwrap_or_else(|| {
+				let index = next_index.map_or(0, |index| index + 1);
+				next_index = Some(index);
+				index
+			});
+			let (calls, event, error) =
+				(id(pallet, "Call"), id(pallet, "Event"), id(pallet, "Error"));
+			let calls = calls.filter(|_| pallet.index.is_some());
+			// Without storage or constants.
+			(pallet.name, None::<()>, calls, event).encode_to(&mut out);
+			(Vec::<()>::new(), error, index).encode_to(&mut out);
+		}
+
+		let extensions = types.signed_extensions.iter().map(|extension| {
+			(extension.identifier, compact(extension.ty), compact(extension.additional))
+		});
+		let extensions: Vec<_> = extensions.collect();
+		(compact(types.unchecked_extrinsic), EXTRINSIC_FORMAT, extensions).encode_to(&mut out);
+		compact(types.runtime).encode_to(&mut out);
+		out
+	}
+}
+
+/// What the metadata of Substrate starts with, before its version.
+pub const MAGIC: [u8; 4] = *b"meta";
+pub const V14: u8 = 14;
+
+/// The empty list written for the docs of every type, field and variant, which the registry
+/// doesn't keep.
+const NO_DOCS: Compact = Compact(0);
+
+fn compact(id: TypeId) -> Compact {
+	Compact(u64::from(id))
+}
+
+/// A part of what the signature of an extrinsic commits to besides the call, in the order the
+/// runtime encodes them: `ty` is put in the extrinsic, and `additional` only in the payload
+/// signed, see `support::signing_payload`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedExtension {
+	pub identifier: &'static str,
+	pub ty: TypeId,
+	pub additional: TypeId,
+}
+
+pub fn variant(name: &'static str, index: u8, fields: Vec<FieldMetadata>) -> VariantMetadata {
+	VariantMetadata { name, index, fields }
+}
+
+pub fn field<T: ?Sized>(name: &'static str) -> FieldMetadata {
+	FieldMetadata { name, ty: core::any::type_name::<T>() }
+}
+
+pub fn storage<T: ?Sized>(name: &'static str) -> StorageMetadata {
+	StorageMetadata { name, ty: core::any::type_name::<T>() }
+}
+
+/// The id of a type in a `Registry`.
+pub type TypeId = u32;
+
+/// The layout of a type, which is enough to encode and decode its values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeDef {
+	/// Fields encoded one after the other.
+	Composite(Vec<TypeField>),
+	/// The index of the variant, as a byte, followed by its fields.
+	Variant(Vec<TypeVariant>),
+	/// Items prefixed with their compact-encoded number.
+	Sequence(TypeId),
+	/// A fixed number of items, without a prefix.
+	Array(u32, TypeId),
+	Tuple(Vec<TypeId>),
+	/// An integer, `bool` or `str`, named as in Rust.
+	Primitive(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeField {
+	pub name: Option<&'static str>,
+	pub ty: TypeId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeVariant {
+	pub name: &'static str,
+	pub index: u8,
+	pub fields: Vec<TypeField>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisteredType {
+	/// The Rust name of the type, as in `FieldMetadata`, or the pallet for its call type, e.g.
+	/// `Balances::Call`.
+	pub path: String,
+	/// The names of the generic parameters of the type, with the types they stand for, which
+	/// tools read e.g. the address and call types of extrinsics from.
+	pub params: Vec<(&'static str, Option<TypeId>)>,
+	pub def: TypeDef,
+}
+
+impl RegisteredType {
+	/// The segments of the path without generic arguments, as metadata V14 writes them, with
+	/// only the name of the types of the standard library, e.g. `Option`. Primitives, sequences,
+	/// arrays and tuples are described by their layout alone, without a path.
+	fn v14_path(&self) -> Vec<&str> {
+		match self.def {
+			TypeDef::Composite(_) | TypeDef::Variant(_) => {
+				let path = self.path.split('<').next().unwrap_or_default();
+				let segments: Vec<_> = path.split("::").collect();
+				match segments[0] {
+					"core" | "alloc" | "std" => segments[segments.len() - 1..].to_vec(),
+					_ => segments,
+				}
+			},
+			_ => Vec::new(),
+		}
+	}
+}
+
+/// Types whose layout `Registry` can describe.
+pub trait TypeInfo {
+	fn type_def(registry: &mut Registry) -> TypeDef;
+}
+
+/// Types by id, each registered once.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Registry {
+	types: Vec<RegisteredType>,
+	ids: BTreeMap<String, TypeId>,
+}
+
+impl Registry {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// The id of `T`, registering it and the types of its fields first if needed.
+	pub fn register<T: TypeInfo + ?Sized>(&mut self) -> TypeId {
+		let path = core::any::type_name::<T>();
+		if let Some(id) = self.id(path) {
+			return id;
+		}
+		let id = self.reserve(path);
+		let def = T::type_def(self);
+		self.define(id, def);
+		id
+	}
+
+	/// The id of a type whose layout is only defined later, so the types of its fields can
+	/// already refer to it, e.g. the calls of sudo to `RuntimeCall`.
+	pub fn reserve(&mut self, path: &str) -> TypeId {
+		if let Some(id) = self.id(path) {
+			return id;
+		}
+		let id = self.types.len() as TypeId;
+		let def = TypeDef::Tuple(Vec::new());
+		self.types
+			.push(RegisteredType { path: path.to_string(), params: Vec::new(), def });
+		self.ids.insert(path.to_string(), id);
+		id
+	}
+
+	pub fn define(&mut self, id: TypeId, def: TypeDef) {
+		self.types[id as usize].def = def;
+	}
+
+	pub fn set_params(&mut self, id: TypeId, params: Vec<(&'static str, Option<TypeId>)>) {
+		self.types[id as usize].params = params;
+	}
+
+	/// The id of the type registered as `path`.
+	pub fn id(&self, path: &str) -> Option<TypeId> {
+		self.ids.get(path).copied()
+	}
+
+	pub fn get(&self, id: TypeId) -> Option<&RegisteredType> {
+		self.types.get(id as usize)
+	}
+
+	pub fn types(&self) -> &[RegisteredType] {
+		&self.types
+	}
+
+	pub fn field<T: TypeInfo + ?Sized>(&mut self, name: &'static str) -> TypeField {
+		TypeField { name: Some(name), ty: self.register::<T>() }
+	}
+
+	pub fn unnamed<T: TypeInfo + ?Sized>(&mut self) -> TypeField {
+		TypeField { name: None, ty: self.register::<T>() }
+	}
+
+	/// `variants`, e.g. the calls of a pallet, with the types of their fields as registered
+	/// under their names.
+	pub fn variants(&self, variants: &[VariantMetadata]) -> Result<TypeDef, &'static str> {
+		let variants = variants.iter().map(|variant| {
+			let fields = variant.fields.iter().map(|field| {
+				let ty = self.id(field.ty).ok_or("Unregistered field type")?;
+				Ok(TypeField { name: Some(field.name), ty })
+			});
+			let fields = fields.collect::<Result<_, &'static str>>()?;
+			Ok(TypeVariant { name: variant.name, index: variant.index, fields })
+		});
+		Ok(TypeDef::Variant(variants.collect::<Result<_, &'static str>>()?))
+	}
+
+	/// The layout of `RuntimeCall`: a variant per pallet with calls, at its index, holding the
+	/// call type of the pallet, registered as e.g. `Balances::Call`.
+	pub fn calls(&mut self, pallets: &[PalletMetadata]) -> Result<TypeDef, &'static str> {
+		let mut variants = Vec::new();
+		for pallet in pallets {
+			let Some(index) = pallet.index else { continue };
+			let def = self.variants(&pallet.calls)?;
+			let id = self.reserve(&format!("{}::Call", pallet.name));
+			self.define(id, def);
+			let fields = vec![TypeField { name: None, ty: id }];
+			variants.push(TypeVariant { name: pallet.name, index, fields });
+		}
+		Ok(TypeDef::Variant(variants))
+	}
+
+	/// Register the events of every pallet with events, e.g. as `Balances::Event`, like the
+	/// types of its calls.
+	pub fn events(&mut self, pallets: &[PalletMetadata]) -> Result<(), &'static str> {
+		for pallet in pallets.iter().filter(|pallet| !pallet.events.is_empty()) {
+			let def = self.variants(&pallet.events)?;
+			let id = self.reserve(&format!("{}::Event", pallet.name));
+			self.define(id, def);
+		}
+		Ok(())
+	}
+
+	/// Register the errors of every pallet with errors, e.g. as `Balances::Error`: a variant
+	/// without fields per error, at its position, as `ModuleError` refers to it.
+	pub fn errors(&mut self, pallets: &[PalletMetadata]) {
+		for pallet in pallets.iter().filter(|pallet| !pallet.errors.is_empty()) {
+			let errors = pallet.errors.iter().zip(0..).map(|(name, index)| unit(name, index));
+			let id = self.reserve(&format!("{}::Error", pallet.name));
+			self.define(id, TypeDef::Variant(errors.collect()));
+		}
+	}
+
+	/// Skip the value of type `id` that `input` starts with, or fail if it doesn't start with
+	/// one, which is how a tool reads a value by its layout.
+	pub fn skip(&self, id: TypeId, input: &mut &[u8]) -> Result<(), &'static str> {
+		match &self.get(id).ok_or("Unknown type")?.def {
+			TypeDef::Composite(fields) => {
+				fields.iter().try_for_each(|field| self.skip(field.ty, input))
+			},
+			TypeDef::Variant(variants) => {
+				let index = u8::decode(input)?;
+				let variant = variants.iter().find(|variant| variant.index == index);
+				let variant = variant.ok_or("Invalid variant index")?;
+				variant.fields.iter().try_for_each(|field| self.skip(field.ty, input))
+			},
+			TypeDef::Sequence(item) => {
+				(0..Compact::decode(input)?.0).try_for_each(|_| self.skip(*item, input))
+			},
+			TypeDef::Array(len, item) => (0..*len).try_for_each(|_| self.skip(*item, input)),
+			TypeDef::Tuple(items) => items.iter().try_for_each(|item| self.skip(*item, input)),
+			TypeDef::Primitive(name) => {
+				let len = match *name {
+					"bool" | "u8" => 1,
+					"u16" => 2,
+					"u32" => 4,
+					"u64" => 8,
+					"u128" => 16,
+					"str" => Compact::decode(input)?.0 as usize,
+					_ => return Err("Unknown primitive"),
+				};
+				let rest = input.get(len..).ok_or("Unexpected end of input")?;
+				*input = rest;
+				Ok(())
+			},
+		}
+	}
+}
+
+/// The types of a runtime, with the ids of its calls and of its extrinsics among them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeTypes {
+	pub registry: Registry,
+	pub call: TypeId,
+	pub extrinsic: TypeId,
+	/// Extrinsics in the envelope of Substrate, which metadata V14 describes as bytes, with the
+	/// address, call, signature and extra types as its parameters.
+	pub unchecked_extrinsic: TypeId,
+	pub signed_extensions: Vec<SignedExtension>,
+	pub runtime: TypeId,
+}
+
+macro_rules! impl_type_info_for_primitives {
+	($($primitive:ty => $name:literal),*) => {$(
+		impl TypeInfo for $primitive {
+			fn type_def(_: &mut Registry) -> TypeDef {
+				TypeDef::Primitive($name)
+			}
+		}
+	)*};
+}
+
+impl_type_info_for_primitives!(
+	bool => "bool", u8 => "u8", u16 => "u16", u32 => "u32", u64 => "u64", u128 => "u128",
+	str => "str", String => "str"
+);
+
+impl<T: TypeInfo> TypeInfo for Vec<T> {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		TypeDef::Sequence(registry.register::<T>())
+	}
+}
+
+impl<T: TypeInfo, const N: usize> TypeInfo for [T; N] {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		TypeDef::Array(N as u32, registry.register::<T>())
+	}
+}
+
+impl<T: TypeInfo> TypeInfo for Option<T> {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		let some = vec![registry.unnamed::<T>()];
+		TypeDef::Variant(vec![
+			TypeVariant { name: "None", index: 0, fields: Vec::new() },
+			TypeVariant { name: "Some", index: 1, fields: some },
+		])
+	}
+}
+
+/// Encoded as the value it points to.
+impl<T: TypeInfo + ?Sized> TypeInfo for Box<T> {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		TypeDef::Composite(vec![registry.unnamed::<T>()])
+	}
+}
+
+impl<A: TypeInfo, B: TypeInfo> TypeInfo for (A, B) {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		TypeDef::Tuple(vec![registry.register::<A>(), registry.register::<B>()])
+	}
+}
+
+/// A variant without fields.
+pub fn unit(name: &'static str, index: u8) -> TypeVariant {
+	TypeVariant { name, index, fields: Vec::new() }
+}
+
+impl Encode for TypeDef {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			TypeDef::Composite(fields) => (0u8, fields).encode_to(dest),
+			TypeDef::Variant(variants) => (1u8, variants).encode_to(dest),
+			TypeDef::Sequence(item) => (2u8, item).encode_to(dest),
+			TypeDef::Array(len, item) => (3u8, len, item).encode_to(dest),
+			TypeDef::Tuple(items) => (4u8, items).encode_to(dest),
+			TypeDef::Primitive(name) => (5u8, *name).encode_to(dest),
+		}
+	}
+}
+
+impl TypeDef {
+	/// The encoding of metadata V14, which names primitives by index and whose fields have
+	/// compact-encoded type ids, type names and docs.
+	fn encode_v14(&self, dest: &mut Vec<u8>) {
+		let fields = |fields: &[TypeField], dest: &mut Vec<u8>| {
+			Compact(fields.len() as u64).encode_to(dest);
+			for field in fields {
+				(field.name, compact(field.ty), None::<&str>, NO_DOCS).encode_to(dest);
+			}
+		};
+		match self {
+			TypeDef::Composite(items) => {
+				0u8.encode_to(dest);
+				fields(items, dest);
+			},
+			TypeDef::Variant(variants) => {
+				(1u8, Compact(variants.len() as u64)).encode_to(dest);
+				for variant in variants {
+					variant.name.encode_to(dest);
+					fields(&variant.fields, dest);
+					(variant.index, NO_DOCS).encode_to(dest);
+				}
+			},
+			TypeDef::Sequence(item) => (2u8, compact(*item)).encode_to(dest),
+			TypeDef::Array(len, item) => (3u8, len, compact(*item)).encode_to(dest),
+			TypeDef::Tuple(items) => {
+				(4u8, items.iter().copied().map(compact).collect::<Vec<_>>()).encode_to(dest)
+			},
+			TypeDef::Primitive(name) => {
+				let primitives = ["bool", "char", "str", "u8", "u16", "u32", "u64", "u128"];
+				let index = primitives.iter().position(|primitive| primitive == name);
+				(5u8, index.unwrap_or_default() as u8).encode_to(dest);
+			},
+		}
+	}
+}
+
+impl Encode for TypeField {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(self.name, self.ty).encode_to(dest);
+	}
+}
+
+impl Encode for TypeVariant {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(self.name, self.index, &self.fields).encode_to(dest);
+	}
+}
+
+/// The types in the order of their ids, so the ids aren't written.
+impl Encode for Registry {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		let types: Vec<_> = self.types.iter().map(|ty| (&ty.path, &ty.def)).collect();
+		types.encode_to(dest);
+	}
+}
+
+impl Encode for RuntimeTypes {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.registry, self.call, self.extrinsic).encode_to(dest);
+	}
+}
+
+impl Encode for RuntimeMetadata {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		self.pallets.encode_to(dest);
+	}
+}
+
+impl Encode for PalletMetadata {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(self.name, self.index, &self.calls, &self.storage).encode_to(dest);
+		(&self.events, &self.errors).encode_to(dest);
+	}
+}
+
+impl Encode for VariantMetadata {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(self.name, self.index, &self.fields).encode_to(dest);
+	}
+}
+
+impl Encode for FieldMetadata {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(self.name, self.ty).encode_to(dest);
+	}
+}
+
+impl Encode for StorageMetadata {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(self.name, self.ty).encode_to(dest);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{
+		account::MultiAddress,
+		balances,
+		codec::{Compact, Decode, Encode},
+		sudo,
+		support::{DispatchError, ModuleError},
+		wallet::{dev_account, dev_signer},
+		Runtime, RuntimeCall,
+	};
+
+	type Fields = Vec<(Option<String>, Compact, Option<String>, Vec<String>)>;
+	type Params = Vec<(String, Option<Compact>)>;
+
+	/// Read a type definition of metadata V14, returning the type ids it refers to.
+	fn read_def(input: &mut &[u8]) -> Result<Vec<u64>, &'static str> {
+		let ids = |fields: Fields| fields.into_iter().map(|field| field.1 .0).collect();
+		Ok(match u8::decode(input)? {
+			0 => ids(Fields::decode(input)?),
+			1 => {
+				let mut referred = Vec::new();
+				for _ in 0..Compact::decode(input)?.0 {
+					let (_name, fields, _index, _docs): (String, Fields, u8, Vec<String>) =
+						Decode::decode(input)?;
+					referred.extend(ids(fields));
+				}
+				referred
+			},
+			2 => vec![Compact::decode(input)?.0],
+			3 => vec![<(u32, Compact)>::decode(input)?.1 .0],
+			4 => Vec::<Compact>::decode(input)?.into_iter().map(|id| id.0).collect(),
+			5 => match u8::decode(input)? {
+				0..=7 => Vec::new(),
+				_ => return Err("Unknown primitive"),
+			},
+			_ => return Err("Unknown type definition"),
+		})
+	}
+
+	#[test]
+	fn errors_are_encoded_by_index_and_decoded_by_name() {
+		let metadata = Runtime::metadata();
+		// A claim whose deposit can't be paid fails with the error of balances.
+		let error = metadata.dispatch_error(1, "Insufficient balance");
+		assert_eq!(error, DispatchError::Module(ModuleError { index: 0, error: 1 }));
+		assert_eq!(DispatchError::decode(&mut &error.encode()[..]), Ok(error));
+		let DispatchError::Module(error) = error else { unreachable!() };
+		assert_eq!(metadata.error_name(error), Some(("Balances", "Insufficient balance")));
+
+		let not_a_feeder = metadata.dispatch_error(13, "Not a feeder");
+		assert_eq!(not_a_feeder, DispatchError::Module(ModuleError { index: 13, error: 4 }));
+		assert_eq!(metadata.dispatch_error(13, "Unknown"), DispatchError::Other);
+		assert_eq!(metadata.error_name(ModuleError { index: 0, error: 200 }), None);
+	}
+
+	#[test]
+	fn extrinsics_are_read_by_their_layout() {
+		let types = Runtime::types();
+		let transfer = balances::Call::Transfer { to: dev_account("bob").into(), amount: 5 };
+		let signed = dev_signer("alice").sign(RuntimeCall::Balances(transfer), 0, [0; 32]);
+		let force = balances::Call::ForceTransfer {
+			from: dev_account("alice"),
+			to: dev_account("bob"),
+			amount: 5,
+		};
+		let sudo =
+			RuntimeCall::Sudo(sudo::Call::Sudo { call: Box::new(RuntimeCall::Balances(force)) });
+		for (ty, encoded) in [(types.extrinsic, signed.encode()), (types.call, sudo.encode())] {
+			let mut input = &encoded[..];
+			assert_eq!(types.registry.skip(ty, &mut input), Ok(()));
+			assert!(input.is_empty());
+			assert!(types.registry.skip(ty, &mut &encoded[..encoded.len() - 1]).is_err());
+		}
+		assert_eq!(
+			types.registry.skip(types.call, &mut &[0xff, 0][..]),
+			Err("Invalid variant index")
+		);
+		let balances = types.registry.id("Balances::Call").unwrap();
+		assert_eq!(types.registry.get(balances).unwrap().path, "Balances::Call");
+	}
+
+	#[test]
+	fn metadata_is_encoded_as_v14() {
+		let types = Runtime::types();
+		let encoded = Runtime::metadata_v14();
+		let mut input = &encoded[..];
+		assert_eq!(<([u8; 4], u8)>::decode(&mut input), Ok((*b"meta", 14)));
+
+		let count = Compact::decode(&mut input).unwrap().0;
+		assert_eq!(count, types.registry.types().len() as u64);
+		let mut paths = Vec::new();
+		for id in 0..count {
+			let (ty, path, params): (Compact, Vec<String>, Params) =
+				Decode::decode(&mut input).unwrap();
+			assert_eq!(ty.0, id);
+			let mut referred = read_def(&mut input).unwrap();
+			referred.extend(params.iter().filter_map(|(_, ty)| Some(ty.as_ref()?.0)));
+			assert!(referred.iter().all(|referred| *referred < count));
+			assert_eq!(Vec::<String>::decode(&mut input), Ok(Vec::new()));
+			paths.push(path.join("::"));
+		}
+		assert_eq!(paths[types.registry.id("Balances::Call").unwrap() as usize], "Balances::Call");
+		let option = types.registry.id(core::any::type_name::<Option<Vec<u8>>>()).unwrap();
+		assert_eq!(paths[option as usize], "Option");
+
+		let metadata = Runtime::metadata();
+		let pallets = Compact::decode(&mut input).unwrap().0;
+		assert_eq!(pallets, metadata.pallets.len() as u64);
+		let mut indices = Vec::new();
+		for pallet in &metadata.pallets {
+			let (name, storage, calls, event): (
+				String,
+				Option<()>,
+				Option<Compact>,
+				Option<Compact>,
+			) = Decode::decode(&mut input).unwrap();
+			let (constants, error, index): (Vec<()>, Option<Compact>, u8) =
+				Decode::decode(&mut input).unwrap();
+			assert_eq!((name.as_str(), storage, constants), (pallet.name, None, Vec::new()));
+			assert_eq!(calls.is_some(), pallet.index.is_some());
+			assert_eq!(event.is_some(), !pallet.events.is_empty());
+			assert_eq!(error.is_some(), !pallet.errors.is_empty());
+			assert_eq!(index, pallet.index.unwrap_or(index));
+			indices.push(index);
+		}
+		indices.sort();
+		indices.dedup();
+		assert_eq!(indices.len(), metadata.pallets.len());
+
+		let (extrinsic, version, extensions): (Compact, u8, Vec<(String, Compact, Compact)>) =
+			Decode::decode(&mut input).unwrap();
+		assert_eq!((extrinsic.0, version), (u64::from(types.unchecked_extrinsic), 4));
+		let identifiers: Vec<_> = extensions.iter().map(|extension| extension.0.as_str()).collect();
+		assert_eq!(
+			identifiers,
+			["CheckGenesis", "CheckNonce", "CheckMortality", "ChargeTransactionPayment"]
+		);
+		assert_eq!(Compact::decode(&mut input).unwrap().0, u64::from(types.runtime));
+		assert!(input.is_empty());
+	}
+
+	#[test]
+	fn extrinsics_in_the_standard_envelope_are_accepted() {
+		let types = Runtime::types();
+		let transfer = balances::Call::Transfer { to: dev_account("bob").into(), amount: 5 };
+		let signed = dev_signer("alice").sign(RuntimeCall::Balances(transfer), 3, [0; 32]);
+		let standard = signed.encode_standard(|caller| MultiAddress::Id(caller.clone()));
+		// A two-byte length, then the version of signed extrinsics.
+		assert_eq!(standard[2], 0x84);
+		// The layout tools read from the metadata covers the whole extrinsic.
+		let mut input = &standard[..];
+		assert_eq!(types.registry.skip(types.unchecked_extrinsic, &mut input), Ok(()));
+		assert!(input.is_empty());
+
+		let decoded = Runtime::decode_extrinsic(&standard).unwrap();
+		assert_eq!(decoded.encode(), signed.encode());
+		assert_eq!(Runtime::decode_extrinsic(&signed.encode()).unwrap().encode(), signed.encode());
+		assert!(Runtime::decode_extrinsic(&standard[..standard.len() - 1]).is_err());
+
+		let named = signed.encode_standard(|_| MultiAddress::Name(b"alice".to_vec()));
+		assert_eq!(
+			crate::types::Extrinsic::decode_standard(&named, |_: MultiAddress| Err("By name"))
+				.err(),
+			Some("By name")
+		);
+		assert!(Runtime::decode_extrinsic(&named).is_err());
+	}
+}
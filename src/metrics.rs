@@ -0,0 +1,147 @@
+//! Node metrics for monitoring, rendered in the Prometheus text format and served at
+//! `GET /metrics` by the REST server.
+
+use std::{fmt::Write, time::Duration};
+
+use crate::{chain::Chain, database::CacheStats, types};
+
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+	/// The extrinsics of the last imported block.
+	block_extrinsics: usize,
+	extrinsics_total: u64,
+	blocks_total: u64,
+	/// The time spent importing blocks, in seconds.
+	import_seconds_total: f64,
+	pool_size: usize,
+	peers: usize,
+	state_cache: CacheStats,
+}
+
+impl Metrics {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Record a block that was imported in `elapsed`.
+	pub fn block_imported(&mut self, block: &types::Block, elapsed: Duration) {
+		self.block_extrinsics = block.extrinsics.len();
+		self.extrinsics_total += block.extrinsics.len() as u64;
+		self.blocks_total += 1;
+		self.import_seconds_total += elapsed.as_secs_f64();
+	}
+
+	pub fn set_pool_size(&mut self, pool_size: usize) {
+		self.pool_size = pool_size;
+	}
+
+	pub fn set_peers(&mut self, peers: usize) {
+		self.peers = peers;
+	}
+
+	/// Record how the reads of the state in the database were served.
+	pub fn set_state_cache(&mut self, stats: CacheStats) {
+		self.state_cache = stats;
+	}
+
+	/// The metrics, with the heights of `chain`.
+	pub fn render(&self, chain: &Chain) -> String {
+		let mut out = String::new();
+		let gauges = [
+			("node_best_block", "Number of the best block.", chain.best_number() as f64),
+			(
+				"node_finalized_block",
+				"Number of the last finalized block.",
+				chain.finalized_number() as f64,
+			),
+			(
+				"node_block_extrinsics",
+				"Extrinsics in the last imported block.",
+				self.block_extrinsics as f64,
+			),
+			(
+				"node_transaction_pool_size",
+				"Transactions waiting in the pool.",
+				self.pool_size as f64,
+			),
+			("node_peers", "Connected peers.", self.peers as f64),
+			(
+				"node_state_cache_hit_rate",
+				"Share of the state reads of the database served by its cache.",
+				self.state_cache.hit_rate().unwrap_or(0.0),
+			),
+		];
+		for (name, help, value) in gauges {
+			describe(&mut out, name, "gauge", help);
+			let _ = writeln!(out, "{} {}", name, value);
+		}
+		describe(
+			&mut out,
+			"node_extrinsics_total",
+			"counter",
+			"Extrinsics in all imported blocks.",
+		);
+		let _ = writeln!(out, "node_extrinsics_total {}", self.extrinsics_total);
+		let counters = [
+			(
+				"node_state_cache_hits_total",
+				"State reads served by the cache.",
+				self.state_cache.hits,
+			),
+			(
+				"node_state_cache_misses_total",
+				"State reads from the disk.",
+				self.state_cache.misses,
+			),
+		];
+		for (name, help, value) in counters {
+			describe(&mut out, name, "counter", help);
+			let _ = writeln!(out, "{} {}", name, value);
+		}
+		// A summary without quantiles: the total time and the number of blocks.
+		describe(&mut out, "node_block_import_seconds", "summary", "Time spent importing blocks.");
+		let _ = writeln!(out, "node_block_import_seconds_sum {}", self.import_seconds_total);
+		let _ = writeln!(out, "node_block_import_seconds_count {}", self.blocks_total);
+		out
+	}
+}
+
+fn describe(out: &mut String, name: &str, kind: &str, help: &str) {
+	let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+}
+
+#[cfg(test)]
+mod test {
+	use std::time::Duration;
+
+	use super::Metrics;
+	use crate::{
+		chain::Chain, database::CacheStats, import_queue, support, wallet::dev_account, Runtime,
+		RuntimeCall,
+	};
+
+	#[test]
+	fn metrics_are_rendered_for_prometheus() {
+		let mut chain = Chain::new(Runtime::new());
+		let now = chain.state().timestamp.create_inherent(1_000).unwrap();
+		let extrinsics = vec![support::Extrinsic {
+			caller: None,
+			call: RuntimeCall::Timestamp(now),
+			signature: None,
+		}];
+		let block = import_queue::build_block(chain.state(), &dev_account("alice"), extrinsics);
+		let block = block.unwrap();
+		chain.import(block.clone(), 1_000).unwrap();
+
+		let mut metrics = Metrics::new();
+		metrics.block_imported(&block, Duration::from_millis(500));
+		metrics.set_peers(3);
+		metrics.set_state_cache(CacheStats { hits: 3, misses: 1 });
+		let rendered = metrics.render(&chain);
+		assert!(rendered.contains("# TYPE node_best_block gauge\nnode_best_block 1\n"));
+		assert!(rendered.contains("\nnode_block_import_seconds_sum 0.5\n"));
+		assert!(rendered.contains("\nnode_peers 3\n"));
+		assert!(rendered.contains("\nnode_state_cache_hit_rate 0.75\n"));
+		assert!(rendered.contains("\nnode_state_cache_misses_total 1\n"));
+	}
+}
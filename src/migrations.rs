@@ -0,0 +1,94 @@
+//! Storage migrations. Each pallet with state in the storage stores the version of its storage
+//! layout next to it. A pallet that changes its layout bumps its version and provides a
+//! migration from the previous one. Before a runtime restores a state, it applies the pending
+//! migrations in the order they were registered.
+
+use crate::{
+	codec::{self, Encode},
+	support::DispatchResult,
+	trie::{storage_key, Storage},
+};
+
+/// The version of the storage layout of a pallet. Storage without one is at version 0.
+pub type StorageVersion = u16;
+
+pub struct Migration {
+	pub pallet: &'static str,
+	/// The version the migration upgrades to, from the one before.
+	pub to: StorageVersion,
+	pub migrate: fn(&mut Storage) -> DispatchResult,
+}
+
+fn version_key(pallet: &str) -> Vec<u8> {
+	storage_key(pallet, ":version", &())
+}
+
+pub fn stored_version(storage: &Storage, pallet: &str) -> Result<StorageVersion, &'static str> {
+	storage
+		.get(&version_key(pallet))
+		.map_or(Ok(0), |version| codec::decode_all(version))
+}
+
+pub fn set_version(storage: &mut Storage, pallet: &str, version: StorageVersion) {
+	storage.insert(version_key(pallet), version.encode());
+}
+
+/// Fails unless the storage of `pallet` is at `version`, e.g. because it is newer than the
+/// runtime.
+pub fn ensure_version(storage: &Storage, pallet: &str, version: StorageVersion) -> DispatchResult {
+	match stored_version(storage, pallet)? == version {
+		true => Ok(()),
+		false => Err("Unsupported storage version"),
+	}
+}
+
+/// Apply the migrations past the stored version of their pallet, in order. Returns how many
+/// were applied.
+pub fn apply(storage: &mut Storage, migrations: &[Migration]) -> Result<usize, &'static str> {
+	let mut applied = 0;
+	for migration in migrations {
+		let version = stored_version(storage, migration.pallet)?;
+		if migration.to <= version {
+			continue;
+		}
+		if migration.to != version + 1 {
+			return Err("Missing migration");
+		}
+		(migration.migrate)(storage)?;
+		set_version(storage, migration.pallet, migration.to);
+		applied += 1;
+	}
+	Ok(applied)
+}
+
+#[cfg(test)]
+mod test {
+	use super::Migration;
+	use crate::trie::Storage;
+
+	fn double(storage: &mut Storage) -> crate::support::DispatchResult {
+		for value in storage.values_mut() {
+			value.iter_mut().for_each(|byte| *byte *= 2);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn pending_migrations_are_applied_in_order() {
+		let migrations = [
+			Migration { pallet: "Test", to: 1, migrate: double },
+			Migration { pallet: "Test", to: 2, migrate: double },
+		];
+		let mut storage = Storage::from([(vec![0], vec![1])]);
+		super::set_version(&mut storage, "Test", 1);
+
+		assert_eq!(super::apply(&mut storage, &migrations), Ok(1));
+		assert_eq!(storage[&vec![0]], vec![2]);
+		assert_eq!(super::stored_version(&storage, "Test"), Ok(2));
+		assert_eq!(super::apply(&mut storage, &migrations), Ok(0));
+		assert_eq!(super::ensure_version(&storage, "Test", 1), Err("Unsupported storage version"));
+
+		let mut storage = Storage::new();
+		assert_eq!(super::apply(&mut storage, &migrations[1..]), Err("Missing migration"));
+	}
+}
@@ -0,0 +1,202 @@
+//! Mnemonic phrases (BIP-39) write a seed as 12 to 24 words of a fixed list, the last of which
+//! carries a checksum, so it can be backed up on paper and a mistyped word is noticed.
+//!
+//! Wallets derive many accounts from one phrase with secret URIs like `<phrase>//polkadot//0`:
+//! every `//` junction derives a new seed from the previous one, the way Substrate derives keys
+//! of the scheme, so backing up the phrase backs up all of them. Soft junctions (`/name`) aren't
+//! supported.
+
+use std::io;
+
+use crate::{
+	codec::Encode,
+	crypto, hashing,
+	signature::{sr25519, Scheme},
+	wallet,
+};
+
+const WORDS: &str = include_str!("mnemonic/english.txt");
+const BITS_PER_WORD: usize = 11;
+const ROUNDS: u32 = 2048;
+
+fn word_list() -> Vec<&'static str> {
+	WORDS.lines().collect()
+}
+
+/// The phrase of `entropy`, which is 16 to 32 bytes, a multiple of 4.
+pub fn from_entropy(entropy: &[u8]) -> Result<String, &'static str> {
+	if !(16..=32).contains(&entropy.len()) || !entropy.len().is_multiple_of(4) {
+		return Err("Invalid entropy length");
+	}
+	// One checksum bit for every 32 bits of entropy, taken from its hash.
+	let mut bits: Vec<bool> = entropy.iter().flat_map(|byte| byte_bits(*byte)).collect();
+	let checksum = byte_bits(hashing::sha256(entropy)[0]);
+	bits.extend(checksum.into_iter().take(entropy.len() / 4));
+
+	let words = word_list();
+	let phrase: Vec<&str> = bits
+		.chunks(BITS_PER_WORD)
+		.map(|chunk| words[chunk.iter().fold(0, |index, &bit| index << 1 | bit as usize)])
+		.collect();
+	Ok(phrase.join(" "))
+}
+
+fn byte_bits(byte: u8) -> [bool; 8] {
+	core::array::from_fn(|i| byte >> (7 - i) & 1 == 1)
+}
+
+/// A new phrase of 12 words.
+pub fn generate() -> io::Result<String> {
+	let entropy: [u8; 16] = crypto::random_bytes()?;
+	Ok(from_entropy(&entropy).expect("Valid entropy length"))
+}
+
+/// The entropy a phrase was made of, checking its words and checksum.
+pub fn to_entropy(phrase: &str) -> Result<Vec<u8>, &'static str> {
+	let words = word_list();
+	let indices = phrase
+		.split_whitespace()
+		.map(|word| words.binary_search(&word).map_err(|_| "Unknown word"))
+		.collect::<Result<Vec<_>, _>>()?;
+	if !matches!(indices.len(), 12 | 15 | 18 | 21 | 24) {
+		return Err("Invalid phrase length");
+	}
+
+	let bits: Vec<bool> = indices
+		.iter()
+		.flat_map(|index| (0..BITS_PER_WORD).rev().map(move |i| index >> i & 1 == 1))
+		.collect();
+	let (entropy_bits, checksum) = bits.split_at(indices.len() * BITS_PER_WORD * 32 / 33);
+	let entropy: Vec<u8> = entropy_bits
+		.chunks(8)
+		.map(|chunk| chunk.iter().fold(0, |byte, &bit| byte << 1 | bit as u8))
+		.collect();
+	match checksum == &byte_bits(hashing::sha256(&entropy)[0])[..checksum.len()] {
+		true => Ok(entropy),
+		false => Err("Invalid phrase checksum"),
+	}
+}
+
+/// The seed of `phrase` under `password`, as Substrate wallets compute it: the first half of the
+/// PBKDF2-HMAC-SHA512 of the entropy, salted with `mnemonic` and the password.
+pub fn to_seed(phrase: &str, password: &str) -> Result<[u8; 32], &'static str> {
+	let entropy = to_entropy(phrase)?;
+	let mut seed = [0u8; 64];
+	crypto::pbkdf2_sha512(&entropy, format!("mnemonic{}", password).as_bytes(), ROUNDS, &mut seed);
+	Ok(seed[..32].try_into().expect("32 of 64 bytes"))
+}
+
+/// The seed of the hard junction `junction` of the `scheme` key with `seed`. Numbers are encoded
+/// as such, so `//0` and `//1` are what wallets derive too.
+pub fn derive_hard(scheme: Scheme, seed: &[u8; 32], junction: &str) -> [u8; 32] {
+	let encoded = match junction.parse::<u64>() {
+		Ok(index) => index.encode(),
+		Err(_) => junction.encode(),
+	};
+	let mut chain_code = [0u8; 32];
+	match encoded.len() > 32 {
+		true => chain_code = hashing::blake2_256(&encoded),
+		false => chain_code[..encoded.len()].copy_from_slice(&encoded),
+	}
+	match scheme {
+		Scheme::Ed25519 => hashing::blake2_256(&("Ed25519HDKD", seed, chain_code).encode()),
+		Scheme::Sr25519 => sr25519::Pair::from_seed(seed).derive_hard(&chain_code),
+	}
+}
+
+/// The seed of a secret URI for a key of `scheme`: a phrase or a hex seed, then any
+/// `//junction`s, then optionally `///password`, which only applies to phrases.
+pub fn secret_uri(uri: &str, scheme: Scheme) -> Result<[u8; 32], &'static str> {
+	let (uri, password) = match uri.split_once("///") {
+		Some((uri, password)) => (uri, Some(password)),
+		None => (uri, None),
+	};
+	let (secret, mut path) = uri.split_at(uri.find('/').unwrap_or(uri.len()));
+	let mut seed = match (secret.trim().starts_with("0x"), password) {
+		(true, None) => wallet::parse_seed(secret.trim())?,
+		(true, Some(_)) => return Err("Passwords only apply to phrases"),
+		(false, password) => to_seed(secret, password.unwrap_or(""))?,
+	};
+
+	while !path.is_empty() {
+		let Some(rest) = path.strip_prefix("//") else {
+			return Err("Soft derivation isn't supported");
+		};
+		let (junction, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+		if junction.is_empty() {
+			return Err("Invalid derivation path");
+		}
+		seed = derive_hard(scheme, &seed, junction);
+		path = rest;
+	}
+	Ok(seed)
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{
+		hashing::{from_hex, hex},
+		signature::Scheme,
+	};
+
+	const ZOO: &str = "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong";
+	/// The phrase of the development accounts of Substrate.
+	const DEV: &str = "bottom drive obey lake curtain smoke basket hold race lonely fit walk";
+
+	#[test]
+	fn phrases_match_the_bip39_test_vectors() {
+		let vectors = [
+			(
+				"7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+				"legal winner thank year wave sausage worth useful legal winner thank yellow",
+			),
+			(
+				"9e885d952ad362caeb4efe34a8e91bd2",
+				"ozone drill grab fiber curtain grace pudding thank cruise elder eight picnic",
+			),
+			("ffffffffffffffffffffffffffffffff", ZOO),
+		];
+		for (entropy, phrase) in vectors {
+			assert_eq!(super::from_entropy(&from_hex(entropy).unwrap()).as_deref(), Ok(phrase));
+			assert_eq!(super::to_entropy(phrase).map(|e| hex(&e)).as_deref(), Ok(entropy));
+		}
+		let generated = super::generate().unwrap();
+		assert_eq!(super::to_entropy(&generated).map(|e| e.len()), Ok(16));
+
+		let bad_checksum = ZOO.replace("wrong", "zoo");
+		assert_eq!(super::to_entropy(&bad_checksum), Err("Invalid phrase checksum"));
+		assert_eq!(super::to_entropy(&ZOO.replace("wrong", "wrongly")), Err("Unknown word"));
+		assert_eq!(super::to_entropy("zoo zoo zoo"), Err("Invalid phrase length"));
+	}
+
+	#[test]
+	fn secret_uris_derive_hard_junctions() {
+		let seed = super::to_seed(DEV, "").unwrap();
+		assert_eq!(hex(&seed), "fac7959dbfe72f052e5a0c3c8d6530f202b02fd8f9f5ca3580ec8deb7797479e");
+		assert_eq!(super::secret_uri(DEV, Scheme::Ed25519), Ok(seed));
+		let with_password = super::secret_uri(&format!("{}///password", DEV), Scheme::Ed25519);
+		assert_eq!(with_password, super::to_seed(DEV, "password"));
+		assert_ne!(with_password, Ok(seed));
+
+		let alice = super::secret_uri(&format!("{}//Alice", DEV), Scheme::Ed25519).unwrap();
+		assert_eq!(hex(&alice), "abf8e5bdbe30c65656c0a3cbd181ff8a56294a69dfedd27982aace4a76909115");
+		let alice = super::secret_uri(&format!("{}//Alice", DEV), Scheme::Sr25519).unwrap();
+		// The account of `//Alice` on Substrate development chains.
+		let public = "d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d";
+		assert_eq!(hex(Scheme::Sr25519.public(&alice).as_ref()), public);
+
+		let first = super::secret_uri(&format!("{}//polkadot//0", DEV), Scheme::Sr25519).unwrap();
+		let polkadot = super::derive_hard(Scheme::Sr25519, &seed, "polkadot");
+		assert_eq!(first, super::derive_hard(Scheme::Sr25519, &polkadot, "0"));
+		let hex_seed = format!("0x{}//polkadot//0", hex(&seed));
+		assert_eq!(super::secret_uri(&hex_seed, Scheme::Sr25519), Ok(first));
+
+		let soft = format!("{}/soft", DEV);
+		assert_eq!(
+			super::secret_uri(&soft, Scheme::Sr25519),
+			Err("Soft derivation isn't supported")
+		);
+		let empty = format!("{}//", DEV);
+		assert_eq!(super::secret_uri(&empty, Scheme::Sr25519), Err("Invalid derivation path"));
+	}
+}
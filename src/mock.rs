@@ -0,0 +1,169 @@
+//! The configuration the tests of the pallets share: `Test` configures system and balances with
+//! plain types, e.g. accounts are strings, and aggregates the hold reasons of the pallets in
+//! `HoldReason`, so the tests of a pallet only configure the pallet itself. It configures proof
+//! of existence as well, and `MockRuntimeBuilder` sets up a state of the three pallets to test
+//! on:
+//!
+//! ```ignore
+//! let mut runtime = MockRuntimeBuilder::new()
+//!     .with_balance("alice", 100)
+//!     .with_block_number(3)
+//!     .with_claim("alice", "document")
+//!     .build();
+//! ```
+
+use crate::{
+	balances,
+	codec::{Decode, Encode},
+	democracy, proof_of_existence, system,
+};
+
+pub struct Test;
+
+impl system::Config for Test {
+	type AccountId = String;
+	type BlockNumber = u32;
+	type Nonce = u32;
+	type RuntimeEvent = ();
+	const MAX_BLOCK_WEIGHT: u64 = 1_000_000;
+	const MAX_EXTRINSICS_PER_ACCOUNT: u32 = 2;
+}
+
+impl balances::Config for Test {
+	type Balance = u128;
+	type Address = String;
+	type RuntimeHoldReason = HoldReason;
+	const EXISTENTIAL_DEPOSIT: u128 = 5;
+
+	fn dust_removal() -> balances::DustRemoval<String> {
+		balances::DustRemoval::Burn
+	}
+}
+
+impl proof_of_existence::Config for Test {
+	type Content = String;
+	const CLAIM_DEPOSIT: u128 = 10;
+	const CLAIM_TTL: u32 = 10;
+	const MAX_NOTE_LENGTH: usize = 8;
+}
+
+/// The hold reasons of the pallets tested on `Test`, aggregated as `construct_runtime` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HoldReason {
+	ProofOfExistence(proof_of_existence::HoldReason),
+	Democracy(democracy::HoldReason),
+}
+
+impl Encode for HoldReason {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			HoldReason::ProofOfExistence(reason) => (0u8, reason).encode_to(dest),
+			HoldReason::Democracy(reason) => (1u8, reason).encode_to(dest),
+		}
+	}
+}
+
+impl Decode for HoldReason {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => HoldReason::ProofOfExistence(Decode::decode(input)?),
+			1 => HoldReason::Democracy(Decode::decode(input)?),
+			_ => return Err("Invalid hold reason"),
+		})
+	}
+}
+
+impl From<proof_of_existence::HoldReason> for HoldReason {
+	fn from(reason: proof_of_existence::HoldReason) -> Self {
+		HoldReason::ProofOfExistence(reason)
+	}
+}
+
+impl From<democracy::HoldReason> for HoldReason {
+	fn from(reason: democracy::HoldReason) -> Self {
+		HoldReason::Democracy(reason)
+	}
+}
+
+/// The pallets of `Test`, whose functions tests call directly.
+pub struct MockRuntime {
+	pub system: system::Pallet<Test>,
+	pub balances: balances::Pallet<Test>,
+	pub proof_of_existence: proof_of_existence::Pallet<Test>,
+}
+
+impl MockRuntime {
+	/// Finalize the current block and initialize the next ones up to `n`, as executing empty
+	/// blocks does.
+	pub fn run_to_block(&mut self, n: u32) {
+		while self.system.block_number() < n {
+			let now = self.system.block_number();
+			self.proof_of_existence.on_finalize(&mut self.balances, now);
+			self.system.inc_block_number();
+			self.proof_of_existence.on_initialize(self.system.block_number());
+		}
+	}
+}
+
+/// A xorshift generator, enough for reproducible random tests.
+pub struct Rng(pub u64);
+
+impl Rng {
+	/// A number below `bound`.
+	pub fn below(&mut self, bound: usize) -> usize {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		(self.0 % bound as u64) as usize
+	}
+}
+
+#[derive(Default)]
+pub struct MockRuntimeBuilder {
+	balances: Vec<(String, u128)>,
+	block_number: u32,
+	claims: Vec<(String, String)>,
+}
+
+impl MockRuntimeBuilder {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn with_balance(mut self, who: &str, amount: u128) -> Self {
+		self.balances.push((who.to_string(), amount));
+		self
+	}
+
+	/// Start at block `n` rather than at genesis.
+	pub fn with_block_number(mut self, n: u32) -> Self {
+		self.block_number = n;
+		self
+	}
+
+	/// A claim of `content` by `who`, made at the block the runtime starts at. `who` pays the
+	/// deposit, so needs a balance.
+	pub fn with_claim(mut self, who: &str, content: &str) -> Self {
+		self.claims.push((who.to_string(), content.to_string()));
+		self
+	}
+
+	pub fn build(self) -> MockRuntime {
+		let mut runtime = MockRuntime {
+			system: system::Pallet::new(),
+			balances: balances::Pallet::new(),
+			proof_of_existence: proof_of_existence::Pallet::new(),
+		};
+		for (who, amount) in self.balances {
+			runtime.balances.set_balance(&who, amount);
+		}
+		runtime.run_to_block(self.block_number);
+		for (who, content) in self.claims {
+			let MockRuntime { balances, proof_of_existence, .. } = &mut runtime;
+			proof_of_existence
+				.create_claim(balances, who, content)
+				.expect("Claims of the mock runtime can be made");
+		}
+		runtime
+	}
+}
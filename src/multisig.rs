@@ -0,0 +1,596 @@
+use core::fmt::Debug;
+use std::collections::BTreeMap;
+
+use crate::{
+	codec::{Decode, Encode},
+	hashing::{blake2_256, Hash},
+	metadata::{field, storage, variant, PalletMetadata, Registry, TypeDef, TypeInfo},
+	migrations::{self, StorageVersion},
+	support::{ensure_signed, DispatchResult, Weight},
+	system::{self, OriginFor},
+	trie::{self, Storage},
+};
+
+pub trait Config: system::Config {
+	/// The aggregated call type a multisig account can execute.
+	type RuntimeCall: Debug + Clone + Encode;
+	/// The maximum number of signatories of a multisig account, including the caller.
+	const MAX_SIGNATORIES: usize;
+
+	/// Derive the deterministic account controlled by `signatories` (sorted, without
+	/// duplicates) at the given `threshold`.
+	fn multi_account_id(signatories: &[Self::AccountId], threshold: u16) -> Self::AccountId;
+}
+
+/// The block number and extrinsic index at which a multisig operation was first approved.
+/// Later approvals must refer to it, so that operations for the same call are never mixed up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timepoint<BlockNumber> {
+	pub height: BlockNumber,
+	pub index: u32,
+}
+
+impl<BlockNumber: Encode> Encode for Timepoint<BlockNumber> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.height, &self.index).encode_to(dest);
+	}
+}
+
+impl<BlockNumber: Decode> Decode for Timepoint<BlockNumber> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (height, index) = Decode::decode(input)?;
+		Ok(Timepoint { height, index })
+	}
+}
+
+impl<BlockNumber: TypeInfo> TypeInfo for Timepoint<BlockNumber> {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		TypeDef::Composite(vec![
+			registry.field::<BlockNumber>("height"),
+			registry.field::<u32>("index"),
+		])
+	}
+}
+
+#[derive(Debug, Clone)]
+pub enum Call<T: Config> {
+	AsMulti {
+		threshold: u16,
+		other_signatories: Vec<T::AccountId>,
+		maybe_timepoint: Option<Timepoint<T::BlockNumber>>,
+		call: Box<T::RuntimeCall>,
+	},
+	ApproveAsMulti {
+		threshold: u16,
+		other_signatories: Vec<T::AccountId>,
+		maybe_timepoint: Option<Timepoint<T::BlockNumber>>,
+		call_hash: Hash,
+	},
+	CancelAsMulti {
+		threshold: u16,
+		other_signatories: Vec<T::AccountId>,
+		timepoint: Timepoint<T::BlockNumber>,
+		call_hash: Hash,
+	},
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::AsMulti { threshold, other_signatories, maybe_timepoint, call } => {
+				(0u8, threshold, other_signatories, (maybe_timepoint, call)).encode_to(dest)
+			},
+			Call::ApproveAsMulti { threshold, other_signatories, maybe_timepoint, call_hash } => {
+				(1u8, threshold, other_signatories, (maybe_timepoint, call_hash)).encode_to(dest)
+			},
+			Call::CancelAsMulti { threshold, other_signatories, timepoint, call_hash } => {
+				(2u8, threshold, other_signatories, (timepoint, call_hash)).encode_to(dest)
+			},
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T>
+where
+	T::RuntimeCall: Decode,
+{
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::AsMulti {
+				threshold: Decode::decode(input)?,
+				other_signatories: Decode::decode(input)?,
+				maybe_timepoint: Decode::decode(input)?,
+				call: Decode::decode(input)?,
+			},
+			1 => Call::ApproveAsMulti {
+				threshold: Decode::decode(input)?,
+				other_signatories: Decode::decode(input)?,
+				maybe_timepoint: Decode::decode(input)?,
+				call_hash: Decode::decode(input)?,
+			},
+			2 => Call::CancelAsMulti {
+				threshold: Decode::decode(input)?,
+				other_signatories: Decode::decode(input)?,
+				timepoint: Decode::decode(input)?,
+				call_hash: Decode::decode(input)?,
+			},
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::AsMulti { .. } => 0,
+			Call::ApproveAsMulti { .. } => 1,
+			Call::CancelAsMulti { .. } => 2,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			// Approvals are checked against every signatory.
+			Call::AsMulti { other_signatories, .. }
+			| Call::ApproveAsMulti { other_signatories, .. }
+			| Call::CancelAsMulti { other_signatories, .. } => {
+				10_000 + 1_000 * other_signatories.len() as Weight
+			},
+		}
+	}
+}
+
+/// A call that reached its approval threshold, with the multisig account that executes it.
+pub type Executable<T> = (<T as system::Config>::AccountId, <T as Config>::RuntimeCall);
+
+/// An open multisig operation.
+#[derive(Debug, Clone)]
+pub struct Multisig<T: Config> {
+	pub when: Timepoint<T::BlockNumber>,
+	pub depositor: T::AccountId,
+	/// The signatories that approved so far, sorted.
+	pub approvals: Vec<T::AccountId>,
+}
+
+impl<T: Config> Encode for Multisig<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.when, &self.depositor, &self.approvals).encode_to(dest);
+	}
+}
+
+impl<T: Config> Decode for Multisig<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (when, depositor, approvals) = Decode::decode(input)?;
+		Ok(Multisig { when, depositor, approvals })
+	}
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	multisigs: BTreeMap<(T::AccountId, Hash), Multisig<T>>,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self { multisigs: BTreeMap::new() }
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Multisig",
+			index: None,
+			calls: vec![
+				variant(
+					"AsMulti",
+					0,
+					vec![
+						field::<u16>("threshold"),
+						field::<Vec<T::AccountId>>("other_signatories"),
+						field::<Option<Timepoint<T::BlockNumber>>>("maybe_timepoint"),
+						field::<Box<T::RuntimeCall>>("call"),
+					],
+				),
+				variant(
+					"ApproveAsMulti",
+					1,
+					vec![
+						field::<u16>("threshold"),
+						field::<Vec<T::AccountId>>("other_signatories"),
+						field::<Option<Timepoint<T::BlockNumber>>>("maybe_timepoint"),
+						field::<Hash>("call_hash"),
+					],
+				),
+				variant(
+					"CancelAsMulti",
+					2,
+					vec![
+						field::<u16>("threshold"),
+						field::<Vec<T::AccountId>>("other_signatories"),
+						field::<Timepoint<T::BlockNumber>>("timepoint"),
+						field::<Hash>("call_hash"),
+					],
+				),
+			],
+			storage: vec![storage::<BTreeMap<(T::AccountId, Hash), Multisig<T>>>("multisigs")],
+			events: Vec::new(),
+			errors: vec![
+				"Already approved",
+				"Multisig operation not found",
+				"Only the depositor can cancel",
+				"Sender in signatories",
+				"Signatories must be unique",
+				"Threshold higher than the number of signatories",
+				"Threshold must be at least 2",
+				"Too many signatories",
+				"Unexpected timepoint",
+				"Wrong timepoint",
+			],
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Multisig", STORAGE_VERSION);
+		trie::insert_entries(storage, "Multisig", "multisigs", &self.multisigs);
+	}
+
+	/// Restore the open operations `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult {
+		migrations::ensure_version(storage, "Multisig", STORAGE_VERSION)?;
+		self.multisigs =
+			trie::decode_entries(storage, "Multisig", "multisigs")?.into_iter().collect();
+		Ok(())
+	}
+
+	/// Dispatch a multisig call made by `caller` at `now`. Once the approval threshold is
+	/// reached, the multisig account and the call it should execute are returned, so the
+	/// runtime can dispatch it with the multisig account as caller.
+	pub fn dispatch(
+		&mut self,
+		now: Timepoint<T::BlockNumber>,
+		origin: OriginFor<T>,
+		call: Call<T>,
+	) -> Result<Option<Executable<T>>, &'static str> {
+		let caller = ensure_signed(origin)?;
+		match call {
+			Call::AsMulti { threshold, other_signatories, maybe_timepoint, call } => {
+				self.as_multi(now, caller, threshold, other_signatories, maybe_timepoint, *call)
+			},
+			Call::ApproveAsMulti { threshold, other_signatories, maybe_timepoint, call_hash } => {
+				self.approve_as_multi(
+					now,
+					caller,
+					threshold,
+					other_signatories,
+					maybe_timepoint,
+					call_hash,
+				)
+				.map(|_| None)
+			},
+			Call::CancelAsMulti { threshold, other_signatories, timepoint, call_hash } => self
+				.cancel_as_multi(caller, threshold, other_signatories, timepoint, call_hash)
+				.map(|_| None),
+		}
+	}
+
+	pub fn multisig(&self, id: &T::AccountId, call_hash: &Hash) -> Option<&Multisig<T>> {
+		self.multisigs.get(&(id.clone(), *call_hash))
+	}
+
+	pub fn call_hash(call: &T::RuntimeCall) -> Hash {
+		blake2_256(&call.encode())
+	}
+
+	/// The multisig account of `caller` together with `other_signatories`.
+	pub fn multi_account_id(
+		caller: &T::AccountId,
+		other_signatories: Vec<T::AccountId>,
+		threshold: u16,
+	) -> Result<T::AccountId, &'static str> {
+		let signatories = Self::signatories(caller, other_signatories, threshold)?;
+		Ok(T::multi_account_id(&signatories, threshold))
+	}
+
+	pub fn as_multi(
+		&mut self,
+		now: Timepoint<T::BlockNumber>,
+		caller: T::AccountId,
+		threshold: u16,
+		other_signatories: Vec<T::AccountId>,
+		maybe_timepoint: Option<Timepoint<T::BlockNumber>>,
+		call: T::RuntimeCall,
+	) -> Result<Option<Executable<T>>, &'static str> {
+		let call_hash = Self::call_hash(&call);
+		let id = Self::multi_account_id(&caller, other_signatories, threshold)?;
+
+		let approvals = self.approve(now, &id, caller, call_hash, maybe_timepoint, threshold)?;
+		if approvals < threshold as usize {
+			return Ok(None);
+		}
+
+		self.multisigs.remove(&(id.clone(), call_hash));
+		Ok(Some((id, call)))
+	}
+
+	pub fn approve_as_multi(
+		&mut self,
+		now: Timepoint<T::BlockNumber>,
+		caller: T::AccountId,
+		threshold: u16,
+		other_signatories: Vec<T::AccountId>,
+		maybe_timepoint: Option<Timepoint<T::BlockNumber>>,
+		call_hash: Hash,
+	) -> DispatchResult {
+		let id = Self::multi_account_id(&caller, other_signatories, threshold)?;
+		self.approve(now, &id, caller, call_hash, maybe_timepoint, threshold)?;
+		Ok(())
+	}
+
+	pub fn cancel_as_multi(
+		&mut self,
+		caller: T::AccountId,
+		threshold: u16,
+		other_signatories: Vec<T::AccountId>,
+		timepoint: Timepoint<T::BlockNumber>,
+		call_hash: Hash,
+	) -> DispatchResult {
+		let id = Self::multi_account_id(&caller, other_signatories, threshold)?;
+		let multisig = self
+			.multisigs
+			.get(&(id.clone(), call_hash))
+			.ok_or("Multisig operation not found")?;
+
+		if multisig.when != timepoint {
+			return Err("Wrong timepoint");
+		}
+		if multisig.depositor != caller {
+			return Err("Only the depositor can cancel");
+		}
+
+		self.multisigs.remove(&(id, call_hash));
+		Ok(())
+	}
+
+	/// Record the approval of `caller` and return the number of approvals so far. Approving
+	/// twice is only allowed once the threshold is reached, i.e. when the caller is completing
+	/// the operation with the call data.
+	#[allow(clippy::too_many_arguments)]
+	fn approve(
+		&mut self,
+		now: Timepoint<T::BlockNumber>,
+		id: &T::AccountId,
+		caller: T::AccountId,
+		call_hash: Hash,
+		maybe_timepoint: Option<Timepoint<T::BlockNumber>>,
+		threshold: u16,
+	) -> Result<usize, &'static str> {
+		let key = (id.clone(), call_hash);
+		let Some(multisig) = self.multisigs.get_mut(&key) else {
+			if maybe_timepoint.is_some() {
+				return Err("Unexpected timepoint");
+			}
+			let multisig =
+				Multisig { when: now, depositor: caller.clone(), approvals: vec![caller] };
+			self.multisigs.insert(key, multisig);
+			return Ok(1);
+		};
+
+		if maybe_timepoint != Some(multisig.when) {
+			return Err("Wrong timepoint");
+		}
+		match multisig.approvals.binary_search(&caller) {
+			Ok(_) if multisig.approvals.len() < threshold as usize => Err("Already approved"),
+			Ok(_) => Ok(multisig.approvals.len()),
+			Err(pos) => {
+				multisig.approvals.insert(pos, caller);
+				Ok(multisig.approvals.len())
+			},
+		}
+	}
+
+	/// Sort the full signatory set and check it is well formed.
+	fn signatories(
+		caller: &T::AccountId,
+		other_signatories: Vec<T::AccountId>,
+		threshold: u16,
+	) -> Result<Vec<T::AccountId>, &'static str> {
+		if threshold < 2 {
+			return Err("Threshold must be at least 2");
+		}
+
+		let mut signatories = other_signatories;
+		if signatories.contains(caller) {
+			return Err("Sender in signatories");
+		}
+		signatories.push(caller.clone());
+		signatories.sort();
+
+		if signatories.windows(2).any(|pair| pair[0] == pair[1]) {
+			return Err("Signatories must be unique");
+		}
+		if signatories.len() > T::MAX_SIGNATORIES {
+			return Err("Too many signatories");
+		}
+		if threshold as usize > signatories.len() {
+			return Err("Threshold higher than the number of signatories");
+		}
+		Ok(signatories)
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::Timepoint;
+	use crate::{
+		hashing::{blake2_256, hex},
+		mock::Test,
+	};
+
+	impl super::Config for Test {
+		type RuntimeCall = String;
+		const MAX_SIGNATORIES: usize = 3;
+
+		fn multi_account_id(signatories: &[String], threshold: u16) -> String {
+			let encoded = crate::codec::Encode::encode(&(signatories, threshold));
+			format!("multisig:{}", hex(&blake2_256(&encoded)))
+		}
+	}
+
+	fn at(height: u32, index: u32) -> Timepoint<u32> {
+		Timepoint { height, index }
+	}
+
+	#[test]
+	fn multisig_account_is_deterministic() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let charlie = "charlie".to_string();
+		type Multisig = super::Pallet<Test>;
+
+		let from_alice = Multisig::multi_account_id(&alice, vec![charlie.clone(), bob.clone()], 2);
+		let from_bob = Multisig::multi_account_id(&bob, vec![alice.clone(), charlie.clone()], 2);
+		assert_eq!(from_alice, from_bob);
+		assert_ne!(
+			from_alice,
+			Multisig::multi_account_id(&alice, vec![bob.clone(), charlie.clone()], 3)
+		);
+
+		assert_eq!(
+			Multisig::multi_account_id(&alice, vec![bob.clone()], 1),
+			Err("Threshold must be at least 2")
+		);
+		assert_eq!(
+			Multisig::multi_account_id(&alice, vec![alice.clone()], 2),
+			Err("Sender in signatories")
+		);
+		assert_eq!(
+			Multisig::multi_account_id(&alice, vec![bob.clone(), bob.clone()], 2),
+			Err("Signatories must be unique")
+		);
+		assert_eq!(
+			Multisig::multi_account_id(
+				&alice,
+				vec![bob.clone(), charlie.clone(), "dave".to_string()],
+				2
+			),
+			Err("Too many signatories")
+		);
+		assert_eq!(
+			Multisig::multi_account_id(&alice, vec![bob.clone()], 3),
+			Err("Threshold higher than the number of signatories")
+		);
+	}
+
+	#[test]
+	fn call_executes_once_threshold_is_reached() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let charlie = "charlie".to_string();
+		let call = "transfer".to_string();
+		let call_hash = super::Pallet::<Test>::call_hash(&call);
+		let mut multisig: super::Pallet<Test> = super::Pallet::new();
+		let id =
+			super::Pallet::<Test>::multi_account_id(&alice, vec![bob.clone(), charlie.clone()], 2)
+				.unwrap();
+
+		assert_eq!(
+			multisig.approve_as_multi(
+				at(1, 0),
+				alice.clone(),
+				2,
+				vec![bob.clone(), charlie.clone()],
+				Some(at(1, 0)),
+				call_hash
+			),
+			Err("Unexpected timepoint")
+		);
+		assert_eq!(
+			multisig.approve_as_multi(
+				at(1, 0),
+				alice.clone(),
+				2,
+				vec![bob.clone(), charlie.clone()],
+				None,
+				call_hash
+			),
+			Ok(())
+		);
+		assert_eq!(multisig.multisig(&id, &call_hash).unwrap().approvals, vec![alice.clone()]);
+		assert_eq!(
+			multisig.approve_as_multi(
+				at(2, 0),
+				alice.clone(),
+				2,
+				vec![bob.clone(), charlie.clone()],
+				Some(at(1, 0)),
+				call_hash
+			),
+			Err("Already approved")
+		);
+
+		assert_eq!(
+			multisig.as_multi(
+				at(2, 0),
+				bob.clone(),
+				2,
+				vec![alice.clone(), charlie.clone()],
+				Some(at(2, 0)),
+				call.clone()
+			),
+			Err("Wrong timepoint")
+		);
+		assert_eq!(
+			multisig.as_multi(
+				at(2, 0),
+				bob.clone(),
+				2,
+				vec![alice.clone(), charlie.clone()],
+				Some(at(1, 0)),
+				call.clone()
+			),
+			Ok(Some((id.clone(), call.clone())))
+		);
+		assert!(multisig.multisig(&id, &call_hash).is_none());
+	}
+
+	#[test]
+	fn cancel_multisig_operation() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let call = "transfer".to_string();
+		let call_hash = super::Pallet::<Test>::call_hash(&call);
+		let mut multisig: super::Pallet<Test> = super::Pallet::new();
+		let id = super::Pallet::<Test>::multi_account_id(&alice, vec![bob.clone()], 2).unwrap();
+
+		assert_eq!(
+			multisig.as_multi(at(1, 2), alice.clone(), 2, vec![bob.clone()], None, call.clone()),
+			Ok(None)
+		);
+
+		assert_eq!(
+			multisig.cancel_as_multi(bob.clone(), 2, vec![alice.clone()], at(1, 2), call_hash),
+			Err("Only the depositor can cancel")
+		);
+		assert_eq!(
+			multisig.cancel_as_multi(alice.clone(), 2, vec![bob.clone()], at(1, 3), call_hash),
+			Err("Wrong timepoint")
+		);
+		assert_eq!(
+			multisig.cancel_as_multi(alice.clone(), 2, vec![bob.clone()], at(1, 2), call_hash),
+			Ok(())
+		);
+		assert!(multisig.multisig(&id, &call_hash).is_none());
+		assert_eq!(
+			multisig.cancel_as_multi(alice.clone(), 2, vec![bob.clone()], at(1, 2), call_hash),
+			Err("Multisig operation not found")
+		);
+	}
+}
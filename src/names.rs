@@ -0,0 +1,388 @@
+use std::collections::BTreeMap;
+
+use num::CheckedAdd;
+
+use crate::{
+	balances,
+	codec::{Decode, Encode},
+	metadata::{field, storage, variant, PalletMetadata},
+	migrations::{self, StorageVersion},
+	support::{ensure_signed, DispatchResult, Weight},
+	system::OriginFor,
+	trie::{self, Storage},
+};
+
+pub trait Config: balances::Config {
+	const MIN_NAME_LENGTH: usize;
+	const MAX_NAME_LENGTH: usize;
+	/// The amount reserved from the owner of a name for as long as it owns it.
+	const REGISTRATION_DEPOSIT: Self::Balance;
+	/// How long (in blocks) a registration or renewal lasts.
+	const REGISTRATION_PERIOD: Self::BlockNumber;
+}
+
+#[derive(Debug, Clone)]
+pub enum Call<T: Config> {
+	/// Register a name that is free or whose registration expired.
+	Register {
+		name: Vec<u8>,
+	},
+	Renew {
+		name: Vec<u8>,
+	},
+	TransferName {
+		name: Vec<u8>,
+		new_owner: T::AccountId,
+	},
+	/// Give up a name, getting the deposit back.
+	Release {
+		name: Vec<u8>,
+	},
+	/// Transfer funds to the owner of a registered name.
+	TransferToName {
+		name: Vec<u8>,
+		amount: T::Balance,
+	},
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::Register { name } => (0u8, name).encode_to(dest),
+			Call::Renew { name } => (1u8, name).encode_to(dest),
+			Call::TransferName { name, new_owner } => (2u8, name, new_owner).encode_to(dest),
+			Call::Release { name } => (3u8, name).encode_to(dest),
+			Call::TransferToName { name, amount } => (4u8, name, amount).encode_to(dest),
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::Register { name: Decode::decode(input)? },
+			1 => Call::Renew { name: Decode::decode(input)? },
+			2 => Call::TransferName {
+				name: Decode::decode(input)?,
+				new_owner: Decode::decode(input)?,
+			},
+			3 => Call::Release { name: Decode::decode(input)? },
+			4 => Call::TransferToName {
+				name: Decode::decode(input)?,
+				amount: Decode::decode(input)?,
+			},
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::Register { .. } => 0,
+			Call::Renew { .. } => 1,
+			Call::TransferName { .. } => 2,
+			Call::Release { .. } => 3,
+			Call::TransferToName { .. } => 4,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::Register { .. } | Call::Renew { .. } | Call::TransferName { .. } => 10_000,
+			Call::Release { .. } | Call::TransferToName { .. } => 10_000,
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Registration<T: Config> {
+	pub owner: T::AccountId,
+	pub deposit: T::Balance,
+	/// The first block at which the name no longer resolves.
+	pub expiry: T::BlockNumber,
+}
+
+impl<T: Config> Encode for Registration<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.owner, &self.deposit, &self.expiry).encode_to(dest);
+	}
+}
+
+impl<T: Config> Decode for Registration<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (owner, deposit, expiry) = Decode::decode(input)?;
+		Ok(Registration { owner, deposit, expiry })
+	}
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	/// Expired registrations are kept, with their deposit, until the name is registered again.
+	registrations: BTreeMap<Vec<u8>, Registration<T>>,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self { registrations: BTreeMap::new() }
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Names",
+			index: None,
+			calls: vec![
+				variant("Register", 0, vec![field::<Vec<u8>>("name")]),
+				variant("Renew", 1, vec![field::<Vec<u8>>("name")]),
+				variant(
+					"TransferName",
+					2,
+					vec![field::<Vec<u8>>("name"), field::<T::AccountId>("new_owner")],
+				),
+				variant("Release", 3, vec![field::<Vec<u8>>("name")]),
+				variant(
+					"TransferToName",
+					4,
+					vec![field::<Vec<u8>>("name"), field::<T::Balance>("amount")],
+				),
+			],
+			storage: vec![storage::<BTreeMap<Vec<u8>, Registration<T>>>("registrations")],
+			events: Vec::new(),
+			errors: vec![
+				"Block number overflow",
+				"Caller is not the owner",
+				"Invalid name length",
+				"Name already registered",
+				"Name not registered",
+			],
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Names", STORAGE_VERSION);
+		trie::insert_entries(storage, "Names", "registrations", &self.registrations);
+	}
+
+	/// Restore the registrations `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult {
+		migrations::ensure_version(storage, "Names", STORAGE_VERSION)?;
+		self.registrations =
+			trie::decode_entries(storage, "Names", "registrations")?.into_iter().collect();
+		Ok(())
+	}
+
+	/// Names are paid for with deposits and expire, so calls are dispatched together with the
+	/// balances pallet and the current block number.
+	pub fn dispatch(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		now: T::BlockNumber,
+		origin: OriginFor<T>,
+		call: Call<T>,
+	) -> DispatchResult {
+		let caller = ensure_signed(origin)?;
+		match call {
+			Call::Register { name } => self.register(balances, now, caller, name),
+			Call::Renew { name } => self.renew(now, caller, name),
+			Call::TransferName { name, new_owner } => {
+				self.transfer_name(balances, now, caller, name, new_owner)
+			},
+			Call::Release { name } => self.release(balances, caller, name),
+			Call::TransferToName { name, amount } => {
+				let to = self.lookup(&name, now).ok_or("Name not registered")?.clone();
+				balances.transfer(caller, to, amount)
+			},
+		}
+	}
+
+	pub fn registration(&self, name: &[u8]) -> Option<&Registration<T>> {
+		self.registrations.get(name)
+	}
+
+	/// The owner of `name`, unless its registration expired.
+	pub fn lookup(&self, name: &[u8], now: T::BlockNumber) -> Option<&T::AccountId> {
+		self.registrations
+			.get(name)
+			.filter(|registration| now < registration.expiry)
+			.map(|registration| &registration.owner)
+	}
+
+	pub fn register(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		now: T::BlockNumber,
+		caller: T::AccountId,
+		name: Vec<u8>,
+	) -> DispatchResult {
+		if name.len() < T::MIN_NAME_LENGTH || name.len() > T::MAX_NAME_LENGTH {
+			return Err("Invalid name length");
+		}
+		if self.lookup(&name, now).is_some() {
+			return Err("Name already registered");
+		}
+		let expiry = now.checked_add(&T::REGISTRATION_PERIOD).ok_or("Block number overflow")?;
+		balances.reserve(&caller, T::REGISTRATION_DEPOSIT)?;
+
+		let registration = Registration { owner: caller, deposit: T::REGISTRATION_DEPOSIT, expiry };
+		if let Some(expired) = self.registrations.insert(name, registration) {
+			let _ = balances.unreserve(&expired.owner, expired.deposit);
+		}
+		Ok(())
+	}
+
+	/// Extend the registration of `name` by a period, starting from now if it already expired.
+	pub fn renew(
+		&mut self,
+		now: T::BlockNumber,
+		caller: T::AccountId,
+		name: Vec<u8>,
+	) -> DispatchResult {
+		let registration = self.owned_registration(&caller, &name)?;
+		let from = if registration.expiry < now { now } else { registration.expiry };
+		registration.expiry =
+			from.checked_add(&T::REGISTRATION_PERIOD).ok_or("Block number overflow")?;
+		Ok(())
+	}
+
+	/// Hand `name` over to `new_owner`, which takes over the deposit.
+	pub fn transfer_name(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		now: T::BlockNumber,
+		caller: T::AccountId,
+		name: Vec<u8>,
+		new_owner: T::AccountId,
+	) -> DispatchResult {
+		if self.lookup(&name, now).is_none() {
+			return Err("Name not registered");
+		}
+		let registration = self.owned_registration(&caller, &name)?;
+		balances.reserve(&new_owner, registration.deposit)?;
+		let _ = balances.unreserve(&registration.owner, registration.deposit);
+		registration.owner = new_owner;
+		Ok(())
+	}
+
+	pub fn release(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		caller: T::AccountId,
+		name: Vec<u8>,
+	) -> DispatchResult {
+		self.owned_registration(&caller, &name)?;
+		if let Some(registration) = self.registrations.remove(&name) {
+			balances.unreserve(&registration.owner, registration.deposit)?;
+		}
+		Ok(())
+	}
+
+	fn owned_registration(
+		&mut self,
+		caller: &T::AccountId,
+		name: &[u8],
+	) -> Result<&mut Registration<T>, &'static str> {
+		let registration = self.registrations.get_mut(name).ok_or("Name not registered")?;
+		if registration.owner != *caller {
+			return Err("Caller is not the owner");
+		}
+		Ok(registration)
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::Call;
+	use crate::{balances, mock::Test, support::RawOrigin};
+
+	impl super::Config for Test {
+		const MIN_NAME_LENGTH: usize = 3;
+		const MAX_NAME_LENGTH: usize = 8;
+		const REGISTRATION_DEPOSIT: u128 = 10;
+		const REGISTRATION_PERIOD: u32 = 5;
+	}
+
+	fn setup() -> (balances::Pallet<Test>, super::Pallet<Test>) {
+		let mut balances = balances::Pallet::new();
+		balances.set_balance(&"alice".to_string(), 100);
+		balances.set_balance(&"bob".to_string(), 100);
+		(balances, super::Pallet::new())
+	}
+
+	#[test]
+	fn transfers_target_registered_names() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let (mut balances, mut names) = setup();
+
+		assert_eq!(
+			names.register(&mut balances, 1, alice.clone(), b"al".to_vec()),
+			Err("Invalid name length")
+		);
+		assert_eq!(names.register(&mut balances, 1, alice.clone(), b"alice".to_vec()), Ok(()));
+		assert_eq!(balances.reserved_balance(&alice), 10);
+		assert_eq!(
+			names.register(&mut balances, 2, bob.clone(), b"alice".to_vec()),
+			Err("Name already registered")
+		);
+
+		let pay = Call::TransferToName { name: b"alice".to_vec(), amount: 20 };
+		assert_eq!(names.dispatch(&mut balances, 2, RawOrigin::Signed(bob.clone()), pay), Ok(()));
+		assert_eq!(balances.balance(&alice), 110);
+
+		let pay = Call::TransferToName { name: b"nobody".to_vec(), amount: 20 };
+		assert_eq!(
+			names.dispatch(&mut balances, 2, RawOrigin::Signed(bob), pay),
+			Err("Name not registered")
+		);
+	}
+
+	#[test]
+	fn names_expire_unless_renewed() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let (mut balances, mut names) = setup();
+
+		let _ = names.register(&mut balances, 1, alice.clone(), b"alice".to_vec());
+		assert_eq!(names.renew(2, bob.clone(), b"alice".to_vec()), Err("Caller is not the owner"));
+		assert_eq!(names.renew(2, alice.clone(), b"alice".to_vec()), Ok(()));
+		assert_eq!(names.lookup(b"alice", 10), Some(&alice));
+		assert_eq!(names.lookup(b"alice", 11), None);
+
+		// Once expired, anyone can take the name and the previous deposit is returned.
+		assert_eq!(names.register(&mut balances, 11, bob.clone(), b"alice".to_vec()), Ok(()));
+		assert_eq!(balances.reserved_balance(&alice), 0);
+		assert_eq!(names.lookup(b"alice", 11), Some(&bob));
+	}
+
+	#[test]
+	fn transfer_moves_the_deposit() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let (mut balances, mut names) = setup();
+
+		let _ = names.register(&mut balances, 1, alice.clone(), b"alice".to_vec());
+		assert_eq!(
+			names.transfer_name(&mut balances, 1, alice.clone(), b"alice".to_vec(), bob.clone()),
+			Ok(())
+		);
+		assert_eq!(balances.reserved_balance(&alice), 0);
+		assert_eq!(balances.reserved_balance(&bob), 10);
+
+		assert_eq!(
+			names.release(&mut balances, alice.clone(), b"alice".to_vec()),
+			Err("Caller is not the owner")
+		);
+		assert_eq!(names.release(&mut balances, bob.clone(), b"alice".to_vec()), Ok(()));
+		assert_eq!(balances.balance(&bob), 100);
+		assert!(names.registration(b"alice").is_none());
+	}
+}
@@ -0,0 +1,485 @@
+//! Gossip between nodes: a node broadcasts the blocks it authors, the extrinsics it pools and
+//! its finality votes, and the blocks it receives go to its import queue. Every gossiped message is handled once,
+//! however many peers send it. The other messages are requests and responses between two
+//! peers, see the `sync` module.
+//!
+//! There are two transports behind the same `Peer` interface. In-process, the peers of a
+//! `Network` are the nodes joined to it, e.g. on separate threads or in the simulation. Over
+//! TCP, the peers of a node are the nodes it connected to or that connected to it, see
+//! `Peer::listen`, and each message is a frame: its length as 4 little-endian bytes, then its
+//! encoding. Both ends start a connection with `HANDSHAKE`, so nodes only talk to nodes of the
+//! same protocol version. Nodes connected over TCP aren't all connected to each other, so they
+//! pass on the gossip they didn't see before to their other peers.
+
+use std::{
+	collections::BTreeSet,
+	io::{self, BufReader, Read, Write},
+	net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+	sync::{
+		mpsc::{self, Receiver, Sender},
+		Arc, Mutex,
+	},
+	thread,
+	time::Duration,
+};
+
+use crate::{
+	codec::{self, Decode, Encode},
+	finality::SignedVote,
+	hashing::{self, Hash},
+	import_queue::ImportQueue,
+	types,
+};
+
+/// The id of a peer in an in-process `Network`, or of the connection to it over TCP.
+pub type PeerId = usize;
+
+/// What both ends of a connection send first: the name of the protocol and its version.
+pub const HANDSHAKE: &[u8; 8] = b"rbchain\x01";
+/// The longest frame read from a peer, which a block of the heaviest extrinsics fits in.
+pub const MAX_FRAME_LEN: usize = 16 << 20;
+/// How long a peer may take to send its handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long sending a message to a peer may block before the connection is dropped, so a
+/// stalled peer doesn't stall the node.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where a peer receives messages, with the id of their sender.
+type Inbox = Sender<(PeerId, Message)>;
+
+#[derive(Clone)]
+pub enum Message {
+	Block(types::Block),
+	Transaction(types::Extrinsic),
+	/// Ask a peer for its best block number.
+	StatusRequest,
+	Status {
+		best_number: types::BlockNumber,
+	},
+	/// Ask a peer for the blocks of its best chain numbered `from` to `to`, both included.
+	BlockRequest {
+		from: types::BlockNumber,
+		to: types::BlockNumber,
+	},
+	Blocks(Vec<types::Block>),
+	Vote(SignedVote),
+}
+
+impl Message {
+	fn is_gossip(&self) -> bool {
+		matches!(self, Message::Block(_) | Message::Transaction(_) | Message::Vote(_))
+	}
+}
+
+impl Decode for Message {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		match u8::decode(input)? {
+			0 => Ok(Message::Block(Decode::decode(input)?)),
+			1 => Ok(Message::Transaction(Decode::decode(input)?)),
+			2 => Ok(Message::StatusRequest),
+			3 => Ok(Message::Status { best_number: Decode::decode(input)? }),
+			4 => Ok(Message::BlockRequest {
+				from: Decode::decode(input)?,
+				to: Decode::decode(input)?,
+			}),
+			5 => Ok(Message::Blocks(Decode::decode(input)?)),
+			6 => Ok(Message::Vote(Decode::decode(input)?)),
+			_ => Err("Invalid message"),
+		}
+	}
+}
+
+fn invalid(error: &'static str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Write `message` to `output` as a frame.
+pub fn write_frame(output: &mut impl Write, message: &Message) -> io::Result<()> {
+	let encoded = message.encode();
+	let mut frame = (encoded.len() as u32).to_le_bytes().to_vec();
+	frame.extend(encoded);
+	output.write_all(&frame)
+}
+
+/// Read a frame from `input`, which must hold a whole message.
+pub fn read_frame(input: &mut impl Read) -> io::Result<Message> {
+	let mut len = [0; 4];
+	input.read_exact(&mut len)?;
+	let len = u32::from_le_bytes(len) as usize;
+	if len > MAX_FRAME_LEN {
+		return Err(invalid("Frame too large"));
+	}
+	let mut encoded = vec![0; len];
+	input.read_exact(&mut encoded)?;
+	codec::decode_all(&encoded).map_err(invalid)
+}
+
+impl Encode for Message {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Message::Block(block) => (0u8, block).encode_to(dest),
+			Message::Transaction(extrinsic) => (1u8, extrinsic).encode_to(dest),
+			Message::StatusRequest => 2u8.encode_to(dest),
+			Message::Status { best_number } => (3u8, best_number).encode_to(dest),
+			Message::BlockRequest { from, to } => (4u8, from, to).encode_to(dest),
+			Message::Blocks(blocks) => (5u8, blocks).encode_to(dest),
+			Message::Vote(vote) => (6u8, vote).encode_to(dest),
+		}
+	}
+}
+
+#[derive(Clone, Default)]
+pub struct Network {
+	/// The inboxes of the peers, indexed by their id.
+	peers: Arc<Mutex<Vec<Inbox>>>,
+}
+
+impl Network {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn join(&self) -> Peer {
+		let (sender, inbox) = mpsc::channel();
+		let mut peers = self.peers.lock().expect("Network lock poisoned");
+		peers.push(sender);
+		let links = Links::Local { id: peers.len() - 1, network: self.clone() };
+		Peer { links, inbox, seen: BTreeSet::new() }
+	}
+
+	pub fn peer_count(&self) -> usize {
+		self.peers.lock().expect("Network lock poisoned").len()
+	}
+}
+
+/// The connections of a node to its peers over TCP, by id. The id of a connection that
+/// failed isn't reused, so a late response of the peer isn't taken for one of another.
+#[derive(Clone, Default)]
+struct Connections(Arc<Mutex<Vec<Option<TcpStream>>>>);
+
+impl Connections {
+	/// Send the handshake over `stream`, then read the messages of the peer into `inbox` on a
+	/// thread of its own, until the connection fails or the peer sends an invalid frame.
+	fn add(&self, mut stream: TcpStream, inbox: Inbox) -> io::Result<PeerId> {
+		stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+		stream.write_all(HANDSHAKE)?;
+		let reader = stream.try_clone()?;
+		let id = {
+			let mut connections = self.0.lock().expect("Network lock poisoned");
+			connections.push(Some(stream));
+			connections.len() - 1
+		};
+		let connections = self.clone();
+		thread::spawn(move || {
+			let _ = Self::read(id, reader, &inbox);
+			connections.remove(id);
+		});
+		Ok(id)
+	}
+
+	/// Read the messages of the peer connected through `stream` into `inbox`, until the node
+	/// stops receiving them.
+	fn read(id: PeerId, stream: TcpStream, inbox: &Inbox) -> io::Result<()> {
+		stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+		let mut reader = BufReader::new(stream);
+		let mut handshake = [0; HANDSHAKE.len()];
+		reader.read_exact(&mut handshake)?;
+		if &handshake != HANDSHAKE {
+			return Err(invalid("Unknown protocol"));
+		}
+		reader.get_ref().set_read_timeout(None)?;
+		loop {
+			let message = read_frame(&mut reader)?;
+			if inbox.send((id, message)).is_err() {
+				return Ok(());
+			}
+		}
+	}
+
+	fn ids(&self) -> Vec<PeerId> {
+		let connections = self.0.lock().expect("Network lock poisoned");
+		let open = connections.iter().enumerate().filter(|(_, stream)| stream.is_some());
+		open.map(|(id, _)| id).collect()
+	}
+
+	/// Send `message` to peer `to`, dropping the connection if it fails.
+	fn send(&self, to: PeerId, message: &Message) {
+		let mut connections = self.0.lock().expect("Network lock poisoned");
+		let Some(Some(stream)) = connections.get_mut(to) else {
+			return;
+		};
+		if write_frame(stream, message).is_err() {
+			let _ = stream.shutdown(Shutdown::Both);
+			connections[to] = None;
+		}
+	}
+
+	fn remove(&self, id: PeerId) {
+		let mut connections = self.0.lock().expect("Network lock poisoned");
+		if let Some(stream) = connections.get_mut(id).and_then(Option::take) {
+			let _ = stream.shutdown(Shutdown::Both);
+		}
+	}
+}
+
+/// How a peer reaches the others.
+enum Links {
+	/// As peer `id` of an in-process network.
+	Local { id: PeerId, network: Network },
+	/// Over its TCP connections, whose messages are read into `inbox`.
+	Tcp { connections: Connections, inbox: Inbox },
+}
+
+pub struct Peer {
+	links: Links,
+	inbox: Receiver<(PeerId, Message)>,
+	/// The hashes of the messages sent or received so far.
+	seen: BTreeSet<Hash>,
+}
+
+impl Peer {
+	/// A peer connected to other nodes over TCP: the ones connecting to `listener`, which are
+	/// accepted on a thread of its own, and the ones it connects to with `connect`.
+	pub fn listen(listener: TcpListener) -> Peer {
+		let (sender, inbox) = mpsc::channel();
+		let connections = Connections::default();
+		let accepted = (connections.clone(), sender.clone());
+		thread::spawn(move || {
+			// A node that fails to connect can try again.
+			for stream in listener.incoming().flatten() {
+				let _ = accepted.0.add(stream, accepted.1.clone());
+			}
+		});
+		Peer { links: Links::Tcp { connections, inbox: sender }, inbox, seen: BTreeSet::new() }
+	}
+
+	/// Connect to the node listening at `address`, e.g. a bootnode, as a new peer. Only peers
+	/// on TCP connect to others, the ones of an in-process network are connected already.
+	pub fn connect(&self, address: impl ToSocketAddrs) -> io::Result<PeerId> {
+		let Links::Tcp { connections, inbox } = &self.links else {
+			return Err(io::Error::new(io::ErrorKind::Unsupported, "In-process peer"));
+		};
+		connections.add(TcpStream::connect(address)?, inbox.clone())
+	}
+
+	/// The other peers of the network, which every peer can discover, or the open connections
+	/// of a peer on TCP.
+	pub fn discover(&self) -> Vec<PeerId> {
+		match &self.links {
+			Links::Local { id, network } => {
+				(0..network.peer_count()).filter(|other| other != id).collect()
+			},
+			Links::Tcp { connections, .. } => connections.ids(),
+		}
+	}
+
+	/// Send a request or response to a single peer.
+	pub fn send_to(&self, to: PeerId, message: Message) {
+		match &self.links {
+			Links::Local { id, network } => {
+				let peers = network.peers.lock().expect("Network lock poisoned");
+				if let Some(peer) = peers.get(to) {
+					let _ = peer.send((*id, message));
+				}
+			},
+			Links::Tcp { connections, .. } => connections.send(to, &message),
+		}
+	}
+
+	/// Announce `block`, e.g. one this node authored.
+	pub fn broadcast_block(&mut self, block: types::Block) {
+		self.broadcast(Message::Block(block));
+	}
+
+	/// Pass an extrinsic on to the pools of the other nodes.
+	pub fn broadcast_transaction(&mut self, extrinsic: types::Extrinsic) {
+		self.broadcast(Message::Transaction(extrinsic));
+	}
+
+	/// Send a finality vote to every peer, again if it was sent before, as votes are sent every
+	/// round until theirs is over in case some peers missed them.
+	pub fn broadcast_vote(&mut self, vote: SignedVote) {
+		let message = Message::Vote(vote);
+		self.seen.insert(hashing::blake2_256(&message.encode()));
+		self.send_to_all(&message, None);
+	}
+
+	/// Send `message` to every peer, unless it was sent or received before.
+	fn broadcast(&mut self, message: Message) {
+		if self.seen.insert(hashing::blake2_256(&message.encode())) {
+			self.send_to_all(&message, None);
+		}
+	}
+
+	/// Send `message` to every peer, but `except`.
+	fn send_to_all(&self, message: &Message, except: Option<PeerId>) {
+		match &self.links {
+			Links::Local { id, network } => {
+				let peers = network.peers.lock().expect("Network lock poisoned");
+				let others = peers.iter().enumerate().filter(|(other, _)| other != id);
+				for (_, peer) in others.filter(|(other, _)| Some(*other) != except) {
+					// A peer that left just misses the message.
+					let _ = peer.send((*id, message.clone()));
+				}
+			},
+			Links::Tcp { connections, .. } => {
+				for to in connections.ids().into_iter().filter(|to| Some(*to) != except) {
+					connections.send(to, message);
+				}
+			},
+		}
+	}
+
+	/// The messages received since the last call, with their sender, leaving out gossip that
+	/// was seen before. Over TCP, the gossip not seen before is passed on to the other peers.
+	pub fn receive(&mut self) -> Vec<(PeerId, Message)> {
+		let mut received = Vec::new();
+		while let Ok((from, message)) = self.inbox.try_recv() {
+			if !message.is_gossip() || self.seen.insert(hashing::blake2_256(&message.encode())) {
+				if message.is_gossip() && matches!(self.links, Links::Tcp { .. }) {
+					self.send_to_all(&message, Some(from));
+				}
+				received.push((from, message));
+			}
+		}
+		received
+	}
+
+	/// Queue the received blocks in `queue` for import, and return the received transactions.
+	pub fn enqueue(&mut self, queue: &mut ImportQueue) -> Vec<types::Extrinsic> {
+		let mut transactions = Vec::new();
+		for (_, message) in self.receive() {
+			match message {
+				Message::Block(block) => queue.import_block(block),
+				Message::Transaction(extrinsic) => transactions.push(extrinsic),
+				_ => {},
+			}
+		}
+		transactions
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::{
+		net::TcpListener,
+		thread,
+		time::{Duration, Instant},
+	};
+
+	use super::{read_frame, write_frame, Message, Network, Peer, PeerId, MAX_FRAME_LEN};
+	use crate::{
+		finality::{SignedVote, Stage, Vote},
+		genesis,
+		import_queue::ImportQueue,
+		support,
+		wallet::dev_account,
+		Runtime, RuntimeCall,
+	};
+
+	fn tcp_peer() -> (Peer, String) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap().to_string();
+		(Peer::listen(listener), address)
+	}
+
+	/// Wait for `peer` to receive something, as messages over TCP take a while.
+	fn wait(peer: &mut Peer) -> Vec<(PeerId, Message)> {
+		let deadline = Instant::now() + Duration::from_secs(5);
+		loop {
+			let received = peer.receive();
+			if !received.is_empty() || Instant::now() > deadline {
+				return received;
+			}
+			thread::sleep(Duration::from_millis(10));
+		}
+	}
+
+	#[test]
+	fn gossiped_blocks_are_imported_once() {
+		let network = Network::new();
+		let (mut author, mut node) = (network.join(), network.join());
+		let mut runtime = Runtime::new();
+		let now = runtime.timestamp.create_inherent(1_000).unwrap();
+		let extrinsic =
+			support::Extrinsic { caller: None, call: RuntimeCall::Timestamp(now), signature: None };
+		let block = crate::import_queue::build_block(
+			&runtime,
+			&dev_account("alice"),
+			vec![extrinsic.clone()],
+		)
+		.unwrap();
+
+		author.broadcast_transaction(extrinsic);
+		author.broadcast_block(block.clone());
+		author.broadcast_block(block);
+		let mut queue = ImportQueue::new();
+		assert_eq!(node.enqueue(&mut queue).len(), 1);
+		assert_eq!(queue.pending(), 1);
+		assert!(queue.process(&mut runtime, 1_000)[0].is_ok());
+		assert!(author.receive().is_empty());
+		assert_eq!(network.peer_count(), 2);
+	}
+
+	#[test]
+	fn votes_are_sent_again() {
+		let network = Network::new();
+		let (mut voter, mut node) = (network.join(), network.join());
+		let alice = dev_account("alice");
+		let vote = Vote { round: 0, stage: Stage::Prevote, hash: [1; 32] };
+		let vote = SignedVote::new(alice.clone(), vote, &genesis::session_key(&alice));
+
+		voter.broadcast_vote(vote.clone());
+		let mut late = network.join();
+		voter.broadcast_vote(vote);
+		// The node received the vote once, and the peer that joined late received it still.
+		assert!(matches!(&node.receive()[..], [(0, Message::Vote(_))]));
+		assert!(matches!(&late.receive()[..], [(0, Message::Vote(_))]));
+	}
+
+	#[test]
+	fn gossip_is_passed_on_over_tcp() {
+		let ((mut first, first_address), (mut second, second_address)) = (tcp_peer(), tcp_peer());
+		let (mut third, _) = tcp_peer();
+		// The second node is connected to both others, which aren't connected to each other.
+		second.connect(&first_address).unwrap();
+		third.connect(&second_address).unwrap();
+		let deadline = Instant::now() + Duration::from_secs(5);
+		while (first.discover().len(), second.discover().len()) != (1, 2) {
+			assert!(Instant::now() < deadline, "The nodes don't connect");
+			thread::sleep(Duration::from_millis(10));
+		}
+
+		let extrinsic = support::Extrinsic {
+			caller: None,
+			call: RuntimeCall::Timestamp(Runtime::new().timestamp.create_inherent(1_000).unwrap()),
+			signature: None,
+		};
+		first.broadcast_transaction(extrinsic.clone());
+		assert!(matches!(&wait(&mut second)[..], [(_, Message::Transaction(_))]));
+		assert!(matches!(&wait(&mut third)[..], [(_, Message::Transaction(_))]));
+		// Gossip seen before isn't received again, e.g. when a peer sends it back.
+		third.broadcast_transaction(extrinsic);
+		thread::sleep(Duration::from_millis(200));
+		assert!(second.receive().is_empty());
+
+		let to = third.discover()[0];
+		third.send_to(to, Message::Status { best_number: 7 });
+		assert!(matches!(&wait(&mut second)[..], [(_, Message::Status { best_number: 7 })]));
+	}
+
+	#[test]
+	fn frames_hold_one_message() {
+		let mut frame = Vec::new();
+		write_frame(&mut frame, &Message::BlockRequest { from: 1, to: 3 }).unwrap();
+		let message = read_frame(&mut &frame[..]).unwrap();
+		assert!(matches!(message, Message::BlockRequest { from: 1, to: 3 }));
+		assert!(read_frame(&mut &frame[..frame.len() - 1]).is_err());
+
+		let too_large = (MAX_FRAME_LEN as u32 + 1).to_le_bytes();
+		assert!(read_frame(&mut &too_large[..]).is_err());
+		frame.push(0);
+		frame[0] += 1;
+		assert!(read_frame(&mut &frame[..]).is_err());
+	}
+}
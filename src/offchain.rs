@@ -0,0 +1,213 @@
+//! Off-chain workers run after the node imports a block, outside consensus: what they do isn't
+//! part of any block, and differs between nodes. They read the state after the block, may fetch
+//! data over HTTP and keep values in the local storage of the node, and hand back the calls they
+//! want on chain, which the node signs and submits to its transaction pool like any other
+//! transaction, or submits unsigned if a pallet accepts them without a fee, see
+//! `support::ValidateUnsigned`.
+//!
+//! The oracle has the only worker so far: a node whose key is a feeder fetches the values of
+//! the keys it has sources for, and feeds them unsigned with the signature of its key.
+
+use std::{
+	collections::BTreeMap,
+	io::{self, Read, Write},
+	net::TcpStream,
+};
+
+use crate::{keystore::Signer, oracle, support, types, Runtime, RuntimeCall};
+
+/// The prefix of the local storage keys of the oracle sources, followed by the oracle key. Their
+/// value is the URL answering with the value of the key, in decimal.
+pub const ORACLE_SOURCE_PREFIX: &[u8] = b"oracle:source:";
+
+pub trait HttpClient {
+	/// The body of the response to a GET request for `url`, if it succeeded.
+	fn get(&self, url: &str) -> io::Result<String>;
+}
+
+/// Plain HTTP/1.1 over TCP, for `http://host[:port]/path` URLs. There is no TLS.
+pub struct Http;
+
+impl HttpClient for Http {
+	fn get(&self, url: &str) -> io::Result<String> {
+		let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "Invalid URL");
+		let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+		let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+		let address = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+		let mut stream = TcpStream::connect(address)?;
+		let path = if path.is_empty() { "/" } else { path };
+		write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host)?;
+		let mut response = String::new();
+		stream.read_to_string(&mut response)?;
+
+		let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+		match head.split_whitespace().nth(1).and_then(|status| status.parse::<u16>().ok()) {
+			Some(200..=299) => Ok(body.to_string()),
+			Some(status) => Err(io::Error::other(format!("HTTP status {}", status))),
+			None => Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid response")),
+		}
+	}
+}
+
+/// Values the node keeps for its workers, which aren't part of the state.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LocalStorage {
+	values: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl LocalStorage {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+		self.values.get(key).map(Vec::as_slice)
+	}
+
+	pub fn set(&mut self, key: &[u8], value: &[u8]) {
+		self.values.insert(key.to_vec(), value.to_vec());
+	}
+
+	pub fn remove(&mut self, key: &[u8]) {
+		self.values.remove(key);
+	}
+
+	/// The entries whose key starts with `prefix`, without it.
+	pub fn with_prefix<'a>(
+		&'a self,
+		prefix: &'a [u8],
+	) -> impl Iterator<Item = (&'a [u8], &'a [u8])> + 'a {
+		let entries = self.values.range(prefix.to_vec()..);
+		let entries = entries.take_while(move |(key, _)| key.starts_with(prefix));
+		entries.map(move |(key, value)| (&key[prefix.len()..], value.as_slice()))
+	}
+}
+
+/// What a worker has access to, besides the state.
+pub struct Context<'a> {
+	pub http: &'a dyn HttpClient,
+	pub storage: &'a mut LocalStorage,
+	/// The key of the node, which signs the submitted calls.
+	pub signer: &'a Signer,
+	calls: Vec<RuntimeCall>,
+	unsigned: Vec<RuntimeCall>,
+}
+
+impl Context<'_> {
+	/// Submit `call`, signed by `signer`.
+	pub fn submit(&mut self, call: RuntimeCall) {
+		self.calls.push(call);
+	}
+
+	/// Submit `call` unsigned, which only the calls a pallet validates unsigned may be.
+	pub fn submit_unsigned(&mut self, call: RuntimeCall) {
+		self.unsigned.push(call);
+	}
+}
+
+/// Fetch the values of the oracle keys with a source in local storage, and feed them if the
+/// account of the node is a feeder, in the next block. Sources that fail are left out.
+fn oracle_worker(state: &Runtime, context: &mut Context) {
+	let feeder = context.signer.account();
+	if !state.oracle.feeders().contains(feeder) {
+		return;
+	}
+	let sources: Vec<_> = context
+		.storage
+		.with_prefix(ORACLE_SOURCE_PREFIX)
+		.filter_map(|(key, url)| Some((String::from_utf8(key.to_vec()).ok()?, url.to_vec())))
+		.collect();
+	let values: Vec<_> = sources
+		.into_iter()
+		.filter_map(|(key, url)| {
+			let body = context.http.get(std::str::from_utf8(&url).ok()?).ok()?;
+			Some((key, body.trim().parse().ok()?))
+		})
+		.collect();
+	if !values.is_empty() {
+		let block = state.system.block_number() + 1;
+		let payload = oracle::feed_payload::<Runtime>(block, &values);
+		let signature = context.signer.sign_payload(&payload);
+		let feeder = feeder.clone();
+		let call = oracle::Call::FeedValuesUnsigned { block, values, feeder, signature };
+		context.submit_unsigned(RuntimeCall::Oracle(call));
+	}
+}
+
+/// Run the workers on `state`, the state after an imported block, and sign the calls they
+/// submit with `signer`, at the nonces from `nonce` on. The unsigned calls follow.
+pub fn offchain_worker(
+	state: &Runtime,
+	http: &dyn HttpClient,
+	storage: &mut LocalStorage,
+	signer: &Signer,
+	nonce: types::Nonce,
+) -> Vec<types::Extrinsic> {
+	let (calls, unsigned) = (Vec::new(), Vec::new());
+	let mut context = Context { http, storage, signer, calls, unsigned };
+	oracle_worker(state, &mut context);
+	let genesis_hash = state.genesis_hash();
+	let calls = context.calls.into_iter().zip(nonce..);
+	let signed = calls.map(|(call, nonce)| signer.sign(call, nonce, genesis_hash));
+	let unsigned = context.unsigned.into_iter().map(|call| support::Extrinsic {
+		caller: None,
+		call,
+		signature: None,
+	});
+	signed.chain(unsigned).collect()
+}
+
+#[cfg(test)]
+mod test {
+	use std::{
+		io::{BufRead, BufReader, Write},
+		net::TcpListener,
+		thread,
+	};
+
+	use super::{offchain_worker, Http, HttpClient, LocalStorage, ORACLE_SOURCE_PREFIX};
+	use crate::{genesis, genesis::GenesisBuilder, support::InvalidTransaction, wallet};
+
+	#[test]
+	fn feeders_feed_the_values_of_their_sources() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/price", listener.local_addr().unwrap());
+		let source = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let request: Vec<String> = BufReader::new(&stream)
+				.lines()
+				.map(Result::unwrap)
+				.take_while(|line| !line.is_empty())
+				.collect();
+			assert_eq!(request[0], "GET /price HTTP/1.1");
+			stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\n420\n").unwrap();
+		});
+
+		let alice = wallet::dev_account("alice");
+		let mut state = GenesisBuilder::new().endow(&alice, 1_000).build().unwrap();
+		let mut storage = LocalStorage::new();
+		storage.set(&[ORACLE_SOURCE_PREFIX, b"DOT/USD"].concat(), url.as_bytes());
+		let signer = wallet::dev_signer("alice");
+		// Only feeders feed.
+		assert!(offchain_worker(&state, &Http, &mut storage, &signer, 0).is_empty());
+
+		state.oracle.add_feeder(alice.clone()).unwrap();
+		let submitted = offchain_worker(&state, &Http, &mut storage, &signer, 0);
+		source.join().unwrap();
+		assert_eq!(submitted.len(), 1);
+		assert!(submitted[0].caller.is_none());
+		assert!(state.validate_extrinsic(&submitted[0]).is_ok());
+		// No other call is accepted unsigned.
+		let mut transfer = submitted[0].clone();
+		transfer.call = wallet::transfer(alice.clone(), 1);
+		assert_eq!(state.validate_extrinsic(&transfer), Err(InvalidTransaction::Call));
+		let block = genesis::block(&state, "alice", vec![transfer]);
+		assert_eq!(block.map(|_| ()), Err("Invalid unsigned extrinsic"));
+
+		state.execute_block(genesis::block(&state, "bob", submitted).unwrap()).unwrap();
+		assert_eq!(state.oracle.get(&"DOT/USD".to_string(), 1), Some(420));
+		// Unsigned feeds pay no fee, and Bob has the block reward.
+		assert_eq!(state.balances.balance(&alice), 1_000);
+		assert!(Http.get("https://example.com").is_err());
+	}
+}
@@ -0,0 +1,474 @@
+use core::fmt::Debug;
+use std::collections::BTreeMap;
+
+use num::CheckedSub;
+
+use crate::{
+	codec::{Decode, Encode},
+	metadata::{field, storage, variant, PalletMetadata},
+	migrations::{self, StorageVersion},
+	support::{
+		ensure_none, ensure_root, ensure_signed, DispatchResult, InvalidTransaction,
+		TransactionPriority, ValidTransaction, ValidateUnsigned, Weight,
+	},
+	system::{self, OriginFor},
+	trie::{self, Storage},
+};
+
+pub trait Config: system::Config {
+	/// What the values are fed for, e.g. a currency pair.
+	type OracleKey: Debug + Ord + Clone + Encode;
+	/// The data itself, e.g. a price.
+	type OracleValue: Debug + Ord + Copy + Encode;
+	/// The maximum number of feeders.
+	const MAX_FEEDERS: usize;
+	/// The maximum number of values a feeder can submit at once.
+	const MAX_FEED_VALUES: usize;
+	/// How many blocks a fed value counts towards the aggregate.
+	const MAX_AGE: Self::BlockNumber;
+	/// What feeders sign the values they feed unsigned with.
+	type Signature: Debug + Clone + Encode;
+
+	/// Whether `signature` is `feeder`'s over `payload`.
+	fn verify(signature: &Self::Signature, payload: &[u8], feeder: &Self::AccountId) -> bool;
+}
+
+#[derive(Debug, Clone)]
+pub enum Call<T: Config> {
+	FeedValues {
+		values: Vec<(T::OracleKey, T::OracleValue)>,
+	},
+	/// Root only: allow an account to feed values.
+	AddFeeder {
+		feeder: T::AccountId,
+	},
+	/// Root only: remove a feeder and the values it fed.
+	RemoveFeeder {
+		feeder: T::AccountId,
+	},
+	/// Unsigned, e.g. from an off-chain worker, so the feeder pays no fee: `signature` is the
+	/// feeder's over `feed_payload(block, values)`, and only valid in `block`.
+	FeedValuesUnsigned {
+		block: T::BlockNumber,
+		values: Vec<(T::OracleKey, T::OracleValue)>,
+		feeder: T::AccountId,
+		signature: T::Signature,
+	},
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::FeedValues { values } => (0u8, values).encode_to(dest),
+			Call::AddFeeder { feeder } => (1u8, feeder).encode_to(dest),
+			Call::RemoveFeeder { feeder } => (2u8, feeder).encode_to(dest),
+			Call::FeedValuesUnsigned { block, values, feeder, signature } => {
+				(3u8, block, values, feeder, signature).encode_to(dest)
+			},
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T>
+where
+	T::OracleKey: Decode,
+	T::OracleValue: Decode,
+	T::Signature: Decode,
+{
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::FeedValues { values: Decode::decode(input)? },
+			1 => Call::AddFeeder { feeder: Decode::decode(input)? },
+			2 => Call::RemoveFeeder { feeder: Decode::decode(input)? },
+			3 => {
+				let (block, values, feeder, signature) = Decode::decode(input)?;
+				Call::FeedValuesUnsigned { block, values, feeder, signature }
+			},
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::FeedValues { .. } => 0,
+			Call::AddFeeder { .. } => 1,
+			Call::RemoveFeeder { .. } => 2,
+			Call::FeedValuesUnsigned { .. } => 3,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::FeedValues { values } | Call::FeedValuesUnsigned { values, .. } => {
+				5_000 + 1_000 * values.len() as Weight
+			},
+			Call::AddFeeder { .. } | Call::RemoveFeeder { .. } => 5_000,
+		}
+	}
+}
+
+/// What a feeder signs to feed `values` unsigned in `block`.
+pub fn feed_payload<T: Config>(
+	block: T::BlockNumber,
+	values: &[(T::OracleKey, T::OracleValue)],
+) -> Vec<u8> {
+	("oracle feed", block, values).encode()
+}
+
+/// A value submitted by a single feeder, with the block it was fed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampedValue<Value, BlockNumber> {
+	pub value: Value,
+	pub block: BlockNumber,
+}
+
+impl<Value: Encode, BlockNumber: Encode> Encode for TimestampedValue<Value, BlockNumber> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.value, &self.block).encode_to(dest);
+	}
+}
+
+impl<Value: Decode, BlockNumber: Decode> Decode for TimestampedValue<Value, BlockNumber> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (value, block) = Decode::decode(input)?;
+		Ok(TimestampedValue { value, block })
+	}
+}
+
+pub type TimestampedValueOf<T> =
+	TimestampedValue<<T as Config>::OracleValue, <T as system::Config>::BlockNumber>;
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	/// Kept sorted.
+	feeders: Vec<T::AccountId>,
+	raw_values: BTreeMap<T::OracleKey, BTreeMap<T::AccountId, TimestampedValueOf<T>>>,
+	last_fed: BTreeMap<T::AccountId, T::BlockNumber>,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self { feeders: Vec::new(), raw_values: BTreeMap::new(), last_fed: BTreeMap::new() }
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Oracle",
+			index: None,
+			calls: vec![
+				variant(
+					"FeedValues",
+					0,
+					vec![field::<Vec<(T::OracleKey, T::OracleValue)>>("values")],
+				),
+				variant("AddFeeder", 1, vec![field::<T::AccountId>("feeder")]),
+				variant("RemoveFeeder", 2, vec![field::<T::AccountId>("feeder")]),
+				variant(
+					"FeedValuesUnsigned",
+					3,
+					vec![
+						field::<T::BlockNumber>("block"),
+						field::<Vec<(T::OracleKey, T::OracleValue)>>("values"),
+						field::<T::AccountId>("feeder"),
+						field::<T::Signature>("signature"),
+					],
+				),
+			],
+			storage: vec![
+				storage::<Vec<T::AccountId>>("feeders"),
+				storage::<BTreeMap<T::OracleKey, BTreeMap<T::AccountId, TimestampedValueOf<T>>>>(
+					"raw_values",
+				),
+				storage::<BTreeMap<T::AccountId, T::BlockNumber>>("last_fed"),
+			],
+			events: Vec::new(),
+			errors: vec![
+				"Already a feeder",
+				"Already fed this block",
+				"Caller is not a feeder",
+				"Feed for another block",
+				"Not a feeder",
+				"Too many feeders",
+				"Too many values",
+			],
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Oracle", STORAGE_VERSION);
+		trie::insert_value(storage, "Oracle", "feeders", &self.feeders);
+		let raw_values = self.raw_values.iter().flat_map(|(key, values)| {
+			values.iter().map(move |(feeder, value)| ((key, feeder), value))
+		});
+		trie::insert_entries(storage, "Oracle", "raw_values", raw_values);
+		trie::insert_entries(storage, "Oracle", "last_fed", &self.last_fed);
+	}
+
+	/// Restore the feeders and values `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult
+	where
+		T::OracleKey: Decode,
+		T::OracleValue: Decode,
+	{
+		migrations::ensure_version(storage, "Oracle", STORAGE_VERSION)?;
+		trie::restore_value(storage, "Oracle", "feeders", &mut self.feeders)?;
+		let mut raw_values = BTreeMap::<_, BTreeMap<_, _>>::new();
+		for ((key, feeder), value) in trie::decode_entries(storage, "Oracle", "raw_values")? {
+			raw_values.entry(key).or_default().insert(feeder, value);
+		}
+		self.raw_values = raw_values;
+		self.last_fed = trie::decode_entries(storage, "Oracle", "last_fed")?.into_iter().collect();
+		Ok(())
+	}
+
+	/// Feeds are only accepted once per block, so calls are dispatched with the current block
+	/// number.
+	pub fn dispatch(
+		&mut self,
+		now: T::BlockNumber,
+		origin: OriginFor<T>,
+		call: Call<T>,
+	) -> DispatchResult {
+		match call {
+			Call::FeedValues { values } => self.feed_values(now, ensure_signed(origin)?, values),
+			Call::AddFeeder { feeder } => {
+				ensure_root(origin)?;
+				self.add_feeder(feeder)
+			},
+			Call::RemoveFeeder { feeder } => {
+				ensure_root(origin)?;
+				self.remove_feeder(feeder)
+			},
+			// The signature was checked by `validate_unsigned` before the call was dispatched.
+			Call::FeedValuesUnsigned { block, values, feeder, .. } => {
+				ensure_none(origin)?;
+				if block != now {
+					return Err("Feed for another block");
+				}
+				self.feed_values(now, feeder, values)
+			},
+		}
+	}
+
+	pub fn feeders(&self) -> &[T::AccountId] {
+		&self.feeders
+	}
+
+	pub fn add_feeder(&mut self, feeder: T::AccountId) -> DispatchResult {
+		let Err(index) = self.feeders.binary_search(&feeder) else {
+			return Err("Already a feeder");
+		};
+		if self.feeders.len() >= T::MAX_FEEDERS {
+			return Err("Too many feeders");
+		}
+		self.feeders.insert(index, feeder);
+		Ok(())
+	}
+
+	pub fn remove_feeder(&mut self, feeder: T::AccountId) -> DispatchResult {
+		let index = self.feeders.binary_search(&feeder).map_err(|_| "Not a feeder")?;
+		self.feeders.remove(index);
+		self.last_fed.remove(&feeder);
+		for values in self.raw_values.values_mut() {
+			values.remove(&feeder);
+		}
+		self.raw_values.retain(|_, values| !values.is_empty());
+		Ok(())
+	}
+
+	/// Submit values for several keys, replacing what `caller` fed before.
+	pub fn feed_values(
+		&mut self,
+		now: T::BlockNumber,
+		caller: T::AccountId,
+		values: Vec<(T::OracleKey, T::OracleValue)>,
+	) -> DispatchResult {
+		if self.feeders.binary_search(&caller).is_err() {
+			return Err("Caller is not a feeder");
+		}
+		if values.len() > T::MAX_FEED_VALUES {
+			return Err("Too many values");
+		}
+		if self.last_fed.get(&caller) == Some(&now) {
+			return Err("Already fed this block");
+		}
+
+		self.last_fed.insert(caller.clone(), now);
+		for (key, value) in values {
+			self.raw_values
+				.entry(key)
+				.or_default()
+				.insert(caller.clone(), TimestampedValue { value, block: now });
+		}
+		Ok(())
+	}
+
+	/// The value each feeder submitted for `key`, fresh or not.
+	pub fn raw_values(&self, key: &T::OracleKey) -> Vec<(&T::AccountId, &TimestampedValueOf<T>)> {
+		self.raw_values
+			.get(key)
+			.map(|values| values.iter().collect())
+			.unwrap_or_default()
+	}
+
+	/// The median of the values fed for `key` no more than `MAX_AGE` blocks before `now`, or
+	/// `None` if every feeder's value is stale. With an even number of values the upper one
+	/// of the middle two is used.
+	pub fn get(&self, key: &T::OracleKey, now: T::BlockNumber) -> Option<T::OracleValue> {
+		let mut fresh: Vec<T::OracleValue> = self
+			.raw_values
+			.get(key)?
+			.values()
+			.filter(|fed| now.checked_sub(&fed.block).is_some_and(|age| age <= T::MAX_AGE))
+			.map(|fed| fed.value)
+			.collect();
+		if fresh.is_empty() {
+			return None;
+		}
+		fresh.sort();
+		Some(fresh[fresh.len() / 2])
+	}
+}
+
+impl<T: Config> ValidateUnsigned<T::BlockNumber> for Pallet<T> {
+	type Call = Call<T>;
+
+	/// Feeds signed by a feeder for `now`, ahead of any signed transaction.
+	fn validate_unsigned(
+		&self,
+		now: T::BlockNumber,
+		call: &Call<T>,
+	) -> Result<ValidTransaction, InvalidTransaction> {
+		let Call::FeedValuesUnsigned { block, values, feeder, signature } = call else {
+			return Err(InvalidTransaction::Call);
+		};
+		if *block != now {
+			return Err(InvalidTransaction::Stale);
+		}
+		if self.feeders.binary_search(feeder).is_err() || values.len() > T::MAX_FEED_VALUES {
+			return Err(InvalidTransaction::Call);
+		}
+		if !T::verify(signature, &feed_payload::<T>(*block, values), feeder) {
+			return Err(InvalidTransaction::BadProof);
+		}
+		Ok(ValidTransaction { priority: TransactionPriority::MAX, weight: call.weight() })
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{feed_payload, Call};
+	use crate::{
+		mock::Test,
+		support::{InvalidTransaction, RawOrigin, ValidateUnsigned},
+	};
+
+	impl super::Config for Test {
+		type OracleKey = &'static str;
+		type OracleValue = u128;
+		const MAX_FEEDERS: usize = 3;
+		const MAX_FEED_VALUES: usize = 2;
+		const MAX_AGE: u32 = 2;
+		/// The signer and what it signed.
+		type Signature = (String, Vec<u8>);
+
+		fn verify(signature: &Self::Signature, payload: &[u8], feeder: &String) -> bool {
+			signature.0 == *feeder && signature.1 == payload
+		}
+	}
+
+	fn oracle() -> super::Pallet<Test> {
+		let mut oracle = super::Pallet::new();
+		for feeder in ["alice", "bob", "charlie"] {
+			assert_eq!(oracle.add_feeder(feeder.to_string()), Ok(()));
+		}
+		oracle
+	}
+
+	#[test]
+	fn only_feeders_can_feed() {
+		let alice = "alice".to_string();
+		let mut oracle = oracle();
+
+		assert_eq!(oracle.add_feeder(alice.clone()), Err("Already a feeder"));
+		assert_eq!(oracle.add_feeder("dave".to_string()), Err("Too many feeders"));
+		assert_eq!(
+			oracle.dispatch(
+				1,
+				RawOrigin::Signed(alice.clone()),
+				super::Call::AddFeeder { feeder: alice.clone() }
+			),
+			Err("Bad origin")
+		);
+		assert_eq!(
+			oracle.feed_values(1, "dave".to_string(), vec![("BTC", 1)]),
+			Err("Caller is not a feeder")
+		);
+		assert_eq!(
+			oracle.feed_values(1, alice.clone(), vec![("BTC", 1), ("ETH", 1), ("DOT", 1)]),
+			Err("Too many values")
+		);
+		assert_eq!(oracle.feed_values(1, alice.clone(), vec![("BTC", 1)]), Ok(()));
+		assert_eq!(
+			oracle.feed_values(1, alice.clone(), vec![("BTC", 2)]),
+			Err("Already fed this block")
+		);
+
+		assert_eq!(oracle.remove_feeder(alice.clone()), Ok(()));
+		assert!(oracle.raw_values(&"BTC").is_empty());
+		assert_eq!(oracle.remove_feeder(alice.clone()), Err("Not a feeder"));
+	}
+
+	#[test]
+	fn median_of_fresh_values() {
+		let mut oracle = oracle();
+
+		let _ = oracle.feed_values(1, "alice".to_string(), vec![("BTC", 100)]);
+		let _ = oracle.feed_values(3, "bob".to_string(), vec![("BTC", 300)]);
+		let _ = oracle.feed_values(3, "charlie".to_string(), vec![("BTC", 200)]);
+
+		assert_eq!(oracle.get(&"BTC", 3), Some(200));
+		assert_eq!(oracle.get(&"ETH", 3), None);
+
+		// Alice's value is stale: the upper of the two remaining values is used.
+		assert_eq!(oracle.get(&"BTC", 4), Some(300));
+		assert_eq!(oracle.get(&"BTC", 6), None);
+		assert_eq!(oracle.raw_values(&"BTC").len(), 3);
+	}
+
+	#[test]
+	fn feeders_feed_unsigned_with_their_signature() {
+		let mut oracle = oracle();
+		let feed = |block, feeder: &str, signer: &str| {
+			let values = vec![("BTC", 100)];
+			let signature = (signer.to_string(), feed_payload::<Test>(block, &values));
+			Call::<Test>::FeedValuesUnsigned { block, values, feeder: feeder.into(), signature }
+		};
+
+		assert!(oracle.validate_unsigned(1, &feed(1, "alice", "alice")).is_ok());
+		let forged = feed(1, "alice", "bob");
+		assert_eq!(oracle.validate_unsigned(1, &forged).err(), Some(InvalidTransaction::BadProof));
+		let old = feed(1, "alice", "alice");
+		assert_eq!(oracle.validate_unsigned(2, &old).err(), Some(InvalidTransaction::Stale));
+		let outsider = feed(1, "dave", "dave");
+		assert_eq!(oracle.validate_unsigned(1, &outsider).err(), Some(InvalidTransaction::Call));
+		let signed = Call::FeedValues { values: vec![("BTC", 100)] };
+		assert_eq!(oracle.validate_unsigned(1, &signed).err(), Some(InvalidTransaction::Call));
+
+		let signed_origin = RawOrigin::Signed("alice".to_string());
+		assert_eq!(oracle.dispatch(1, signed_origin, feed(1, "alice", "alice")), Err("Bad origin"));
+		assert_eq!(oracle.dispatch(1, RawOrigin::None, feed(1, "alice", "alice")), Ok(()));
+		assert_eq!(oracle.get(&"BTC", 1), Some(100));
+	}
+}
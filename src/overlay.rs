@@ -0,0 +1,249 @@
+//! Copy-on-write maps for the storage of the pallets holding the most entries: accounts,
+//! balances and claims. A map keeps its committed entries behind an `Arc`, and the changes made
+//! since in an overlay behind another one. Cloning a map, and so the runtime, only counts
+//! references, so dry runs, block building trials and forks execute on copies of the state
+//! without copying all of it.
+//!
+//! The first change to a copy copies the overlay, which `commit` keeps small by merging it into
+//! the committed entries at the end of every block. That copies the committed entries, once,
+//! if an older state still shares them.
+
+use std::{
+	cmp::Ordering,
+	collections::{btree_map, BTreeMap},
+	fmt,
+	iter::Peekable,
+	ops::RangeBounds,
+	sync::Arc,
+};
+
+pub struct OverlayMap<K, V> {
+	committed: Arc<BTreeMap<K, V>>,
+	/// The changes since the last commit, `None` for a removed entry.
+	changes: Arc<BTreeMap<K, Option<V>>>,
+}
+
+impl<K: Ord + Clone, V: Clone> OverlayMap<K, V> {
+	pub fn new() -> Self {
+		Self { committed: Arc::new(BTreeMap::new()), changes: Arc::new(BTreeMap::new()) }
+	}
+
+	pub fn get(&self, key: &K) -> Option<&V> {
+		match self.changes.get(key) {
+			Some(change) => change.as_ref(),
+			None => self.committed.get(key),
+		}
+	}
+
+	pub fn contains_key(&self, key: &K) -> bool {
+		self.get(key).is_some()
+	}
+
+	pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+		let previous = self.get(&key).cloned();
+		Arc::make_mut(&mut self.changes).insert(key, Some(value));
+		previous
+	}
+
+	pub fn remove(&mut self, key: &K) -> Option<V> {
+		let previous = self.get(key).cloned()?;
+		Arc::make_mut(&mut self.changes).insert(key.clone(), None);
+		Some(previous)
+	}
+
+	/// Make the value of `key` the one `other` has, or remove it if `other` has none.
+	pub fn copy_entry(&mut self, other: &Self, key: &K) {
+		match other.get(key) {
+			Some(value) => self.insert(key.clone(), value.clone()),
+			None => self.remove(key),
+		};
+	}
+
+	/// A mutable reference to the value of `key`, which is copied into the overlay first.
+	pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+		if !self.changes.contains_key(key) {
+			let value = self.committed.get(key)?.clone();
+			Arc::make_mut(&mut self.changes).insert(key.clone(), Some(value));
+		}
+		Arc::make_mut(&mut self.changes).get_mut(key)?.as_mut()
+	}
+
+	/// The value of `key`, inserting `default()` if there is none, like `entry(key).or_insert_with`.
+	pub fn get_or_insert_with(&mut self, key: K, default: impl FnOnce() -> V) -> &mut V {
+		if !self.contains_key(&key) {
+			Arc::make_mut(&mut self.changes).insert(key.clone(), Some(default()));
+		}
+		self.get_mut(&key).expect("The value was just inserted")
+	}
+
+	/// The entries in the order of their keys.
+	pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+		self.into_iter()
+	}
+
+	pub fn range<R: RangeBounds<K> + Clone>(&self, range: R) -> impl Iterator<Item = (&K, &V)> {
+		Merge {
+			committed: self.committed.range(range.clone()).peekable(),
+			changes: self.changes.range(range).peekable(),
+		}
+	}
+
+	pub fn keys(&self) -> impl Iterator<Item = &K> {
+		self.iter().map(|(key, _)| key)
+	}
+
+	pub fn values(&self) -> impl Iterator<Item = &V> {
+		self.iter().map(|(_, value)| value)
+	}
+
+	pub fn len(&self) -> usize {
+		let added = self
+			.changes
+			.iter()
+			.filter(|(key, change)| change.is_some() && !self.committed.contains_key(key));
+		let removed = self
+			.changes
+			.iter()
+			.filter(|(key, change)| change.is_none() && self.committed.contains_key(key));
+		self.committed.len() + added.count() - removed.count()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Keep only the entries `f` returns `true` for.
+	pub fn retain(&mut self, mut f: impl FnMut(&K, &V) -> bool) {
+		let removed: Vec<K> = self
+			.iter()
+			.filter(|(key, value)| !f(key, value))
+			.map(|(key, _)| key.clone())
+			.collect();
+		for key in removed {
+			self.remove(&key);
+		}
+	}
+
+	/// Merge the changes into the committed entries, e.g. at the end of a block.
+	pub fn commit(&mut self) {
+		if self.changes.is_empty() {
+			return;
+		}
+		let changes = std::mem::take(Arc::make_mut(&mut self.changes));
+		let committed = Arc::make_mut(&mut self.committed);
+		for (key, change) in changes {
+			match change {
+				Some(value) => committed.insert(key, value),
+				None => committed.remove(&key),
+			};
+		}
+	}
+}
+
+/// The entries of the committed map and of the overlay together, in the order of their keys.
+pub struct Merge<C: Iterator, O: Iterator> {
+	committed: Peekable<C>,
+	changes: Peekable<O>,
+}
+
+impl<'a, K: Ord + 'a, V: 'a, C, O> Iterator for Merge<C, O>
+where
+	C: Iterator<Item = (&'a K, &'a V)>,
+	O: Iterator<Item = (&'a K, &'a Option<V>)>,
+{
+	type Item = (&'a K, &'a V);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			let order = match (self.committed.peek(), self.changes.peek()) {
+				(None, None) => return None,
+				(Some(_), None) => Ordering::Less,
+				(None, Some(_)) => Ordering::Greater,
+				(Some((committed, _)), Some((changed, _))) => committed.cmp(changed),
+			};
+			match order {
+				Ordering::Less => return self.committed.next(),
+				// A change replaces the committed entry of its key.
+				Ordering::Equal => drop(self.committed.next()),
+				Ordering::Greater => {},
+			}
+			if let (key, Some(value)) = self.changes.next()? {
+				return Some((key, value));
+			}
+		}
+	}
+}
+
+impl<K, V> Clone for OverlayMap<K, V> {
+	fn clone(&self) -> Self {
+		Self { committed: Arc::clone(&self.committed), changes: Arc::clone(&self.changes) }
+	}
+}
+
+impl<K: Ord + Clone, V: Clone> Default for OverlayMap<K, V> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<K: Ord + Clone, V: Clone + PartialEq> PartialEq for OverlayMap<K, V> {
+	fn eq(&self, other: &Self) -> bool {
+		self.iter().eq(other.iter())
+	}
+}
+
+impl<K: Ord + Clone + fmt::Debug, V: Clone + fmt::Debug> fmt::Debug for OverlayMap<K, V> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_map().entries(self.iter()).finish()
+	}
+}
+
+impl<K: Ord + Clone, V: Clone> FromIterator<(K, V)> for OverlayMap<K, V> {
+	fn from_iter<I: IntoIterator<Item = (K, V)>>(entries: I) -> Self {
+		Self { committed: Arc::new(entries.into_iter().collect()), changes: Arc::default() }
+	}
+}
+
+impl<'a, K: Ord + Clone, V: Clone> IntoIterator for &'a OverlayMap<K, V> {
+	type Item = (&'a K, &'a V);
+	type IntoIter = Merge<btree_map::Iter<'a, K, V>, btree_map::Iter<'a, K, Option<V>>>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		Merge {
+			committed: self.committed.iter().peekable(),
+			changes: self.changes.iter().peekable(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::OverlayMap;
+
+	#[test]
+	fn changes_are_layered_over_the_committed_entries() {
+		let mut map: OverlayMap<u32, &str> =
+			[(1, "one"), (2, "two"), (4, "four")].into_iter().collect();
+		let committed = map.clone();
+		assert_eq!(map.insert(3, "three"), None);
+		assert_eq!(map.insert(1, "uno"), Some("one"));
+		assert_eq!(map.remove(&2), Some("two"));
+		assert_eq!(map.remove(&5), None);
+		*map.get_mut(&4).unwrap() = "vier";
+		*map.get_or_insert_with(6, || "six") = "sechs";
+
+		let entries: Vec<_> = map.iter().map(|(key, value)| (*key, *value)).collect();
+		assert_eq!(entries, [(1, "uno"), (3, "three"), (4, "vier"), (6, "sechs")]);
+		assert_eq!(map.range(2..5).count(), 2);
+		assert_eq!(map.len(), 4);
+		// The copy taken before is untouched.
+		assert_eq!(committed.len(), 3);
+		assert_eq!(committed.get(&1), Some(&"one"));
+
+		let before = map.clone();
+		map.commit();
+		assert_eq!(map, before);
+		map.retain(|key, _| key % 2 == 0);
+		assert_eq!(map.keys().copied().collect::<Vec<_>>(), [4, 6]);
+	}
+}
@@ -1,103 +1,670 @@
 use core::fmt::Debug;
-use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+
+use num::{CheckedAdd, CheckedMul, Zero};
 
 use crate::{
-	support::{self, DispatchResult},
-	system,
+	balances,
+	codec::{Decode, Encode},
+	hashing::{self, Hash},
+	metadata::{field, storage, variant, PalletMetadata},
+	migrations::{self, StorageVersion},
+	overlay::OverlayMap,
+	support::{self, ensure_signed, DispatchResult, Weight},
+	system::OriginFor,
+	trie::{self, storage_key, Storage},
+	weights,
 };
 
-pub trait Config: system::Config {
-	type Content: Debug + Ord;
+/// Version 1 stores `ClaimDetails` instead of only the owner of a claim.
+pub const STORAGE_VERSION: StorageVersion = 1;
+
+pub trait Config: balances::Config<RuntimeHoldReason: From<HoldReason>> {
+	type Content: Debug + Ord + Encode + Decode;
+	/// The amount held from the creator of a claim until it is revoked or expires.
+	const CLAIM_DEPOSIT: Self::Balance;
+	/// How long (in blocks) a claim lasts before it expires.
+	const CLAIM_TTL: Self::BlockNumber;
+	const MAX_NOTE_LENGTH: usize;
+}
+
+/// Why proof of existence holds funds, see `balances::Pallet::hold`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HoldReason {
+	ClaimDeposit,
+}
+
+impl Encode for HoldReason {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			HoldReason::ClaimDeposit => 0u8.encode_to(dest),
+		}
+	}
+}
+
+impl Decode for HoldReason {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		match u8::decode(input)? {
+			0 => Ok(HoldReason::ClaimDeposit),
+			_ => Err("Invalid hold reason"),
+		}
+	}
+}
+
+/// Claims hold a deposit, so calls are dispatched together with the balances pallet.
+#[support::pallet(
+	dispatch(balances: &mut balances::Pallet<T>, origin: OriginFor<T>),
+	errors(
+		AlreadyAttested = "Already attested",
+		CannotAttestOwnClaim = "Cannot attest own claim",
+		ClaimAlreadyExists = "Claim already exists",
+		ClaimAlreadyUpdated = "Claim already updated",
+		ClaimDoesNotExist = "Claim does not exist",
+		ClaimIsPartOfAHistory = "Claim is part of a history",
+		DepositOverflow = "Deposit overflow",
+		NoteTooLong = "Note too long",
+		NotTheOwner = "The claim does not belong to Caller",
+	),
+)]
+impl<T: Config> Pallet<T> {
+	#[call(index = 0, weight = weights::proof_of_existence::CREATE_CLAIM)]
+	fn create_claim(claim: T::Content, note: Option<Vec<u8>>) -> DispatchResult {
+		let caller = ensure_signed(origin)?;
+		self.create_claim_with_note(balances, caller, claim, note)
+	}
+
+	#[call(index = 1, weight = weights::proof_of_existence::REVOKE_CLAIM)]
+	fn revoke_claim(claim: T::Content) -> DispatchResult {
+		self.revoke_claim(balances, ensure_signed(origin)?, claim)
+	}
+
+	/// Hand a claim of the caller over to another account.
+	#[call(index = 2, weight = weights::proof_of_existence::TRANSFER_CLAIM)]
+	fn transfer_claim(claim: T::Content, to: T::AccountId) -> DispatchResult {
+		self.transfer_claim(ensure_signed(origin)?, claim, to)
+	}
+
+	/// Create several claims at once: either all of them are created or none is.
+	#[call(index = 3, weight = weights::proof_of_existence::CREATE_CLAIM * claims.len() as Weight)]
+	fn create_claims(claims: Vec<T::Content>) -> DispatchResult {
+		self.create_claims(balances, ensure_signed(origin)?, claims)
+	}
+
+	/// Vouch for a claim held by another account.
+	#[call(index = 4, weight = weights::proof_of_existence::ATTEST)]
+	fn attest(claim: T::Content) -> DispatchResult {
+		self.attest(ensure_signed(origin)?, claim)
+	}
+
+	/// Claim a new revision of a document the caller has a claim on.
+	#[call(index = 5, weight = weights::proof_of_existence::UPDATE_CLAIM)]
+	fn update_claim(old: T::Content, new: T::Content) -> DispatchResult {
+		self.update_claim(balances, ensure_signed(origin)?, old, new)
+	}
+}
+
+#[derive(Debug, Clone)]
+pub enum Event<T: Config> {
+	ClaimTransferred { claim: Hash, from: T::AccountId, to: T::AccountId },
+	ClaimAttested { claim: Hash, attester: T::AccountId },
 }
 
-pub enum Call<T: Config> {
-	CreateClaim { claim: T::Content },
-	RevokeClaim { claim: T::Content },
+impl<T: Config> Event<T> {
+	/// The name of the variant, as in the metadata.
+	pub fn name(&self) -> &'static str {
+		match self {
+			Event::ClaimTransferred { .. } => "ClaimTransferred",
+			Event::ClaimAttested { .. } => "ClaimAttested",
+		}
+	}
+
+	/// The accounts the event is about.
+	pub fn accounts(&self) -> Vec<&T::AccountId> {
+		match self {
+			Event::ClaimTransferred { from, to, .. } => vec![from, to],
+			Event::ClaimAttested { attester, .. } => vec![attester],
+		}
+	}
 }
 
 #[derive(Debug)]
+pub struct ClaimDetails<T: Config> {
+	pub owner: T::AccountId,
+	/// The block the claim was created in.
+	pub block_number: T::BlockNumber,
+	/// A short annotation left by the owner when making the claim.
+	pub note: Option<Vec<u8>>,
+	/// The account that created the claim, which gets its deposit back. Transferring the claim
+	/// leaves the deposit where it is.
+	pub depositor: T::AccountId,
+	pub deposit: T::Balance,
+}
+
+// Derived, it would need `T: Clone`, which storage maps can't require of the configuration.
+impl<T: Config> Clone for ClaimDetails<T> {
+	fn clone(&self) -> Self {
+		Self {
+			owner: self.owner.clone(),
+			block_number: self.block_number,
+			note: self.note.clone(),
+			depositor: self.depositor.clone(),
+			deposit: self.deposit,
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
 pub struct Pallet<T: Config> {
-	claims: BTreeMap<T::Content, T::AccountId>,
+	/// Claims are keyed by the hash of their content, so the content itself never ends up in
+	/// the state.
+	claims: OverlayMap<Hash, ClaimDetails<T>>,
+	/// The claims of each account, so they can be listed without going through all claims.
+	claims_of: OverlayMap<T::AccountId, BTreeSet<Hash>>,
+	/// May still list claims that were revoked, or created again after they expired.
+	expiries: OverlayMap<T::BlockNumber, Vec<Hash>>,
+	/// The accounts that attested each claim, next to its owner.
+	attestations: OverlayMap<Hash, BTreeSet<T::AccountId>>,
+	/// The revisions of each document, linked both ways. They are kept when a claim goes away,
+	/// so the history of a document stays complete.
+	previous_revision: OverlayMap<Hash, Hash>,
+	next_revision: OverlayMap<Hash, Hash>,
+	now: T::BlockNumber,
+	events: Vec<Event<T>>,
 }
 
-impl<T: Config> support::Dispatch for Pallet<T> {
-	type Call = Call<T>;
-	type Caller = T::AccountId;
+impl<T: Config> Encode for ClaimDetails<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.owner, &self.block_number, &self.note).encode_to(dest);
+		(&self.depositor, &self.deposit).encode_to(dest);
+	}
+}
 
-	fn dispatch(&mut self, caller: Self::Caller, call: Self::Call) -> DispatchResult {
-		match call {
-			Call::CreateClaim { claim } => self.create_claim(caller, claim),
-			Call::RevokeClaim { claim } => self.revoke_claim(caller, claim),
-		}
+impl<T: Config> Decode for ClaimDetails<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (owner, block_number, note) = Decode::decode(input)?;
+		let (depositor, deposit) = Decode::decode(input)?;
+		Ok(Self { owner, block_number, note, depositor, deposit })
 	}
 }
 
 impl<T: Config> Pallet<T> {
 	pub fn new() -> Self {
-		Self { claims: BTreeMap::new() }
+		Self {
+			claims: OverlayMap::new(),
+			claims_of: OverlayMap::new(),
+			expiries: OverlayMap::new(),
+			attestations: OverlayMap::new(),
+			previous_revision: OverlayMap::new(),
+			next_revision: OverlayMap::new(),
+			now: T::BlockNumber::zero(),
+			events: Vec::new(),
+		}
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "ProofOfExistence",
+			index: None,
+			calls: Call::<T>::metadata(),
+			storage: vec![
+				storage::<OverlayMap<Hash, ClaimDetails<T>>>("claims"),
+				storage::<OverlayMap<T::AccountId, BTreeSet<Hash>>>("claims_of"),
+				storage::<OverlayMap<T::BlockNumber, Vec<Hash>>>("expiries"),
+				storage::<OverlayMap<Hash, BTreeSet<T::AccountId>>>("attestations"),
+				storage::<OverlayMap<Hash, Hash>>("previous_revision"),
+				storage::<OverlayMap<Hash, Hash>>("next_revision"),
+				storage::<T::BlockNumber>("now"),
+			],
+			events: vec![
+				variant(
+					"ClaimTransferred",
+					0,
+					vec![
+						field::<Hash>("claim"),
+						field::<T::AccountId>("from"),
+						field::<T::AccountId>("to"),
+					],
+				),
+				variant(
+					"ClaimAttested",
+					1,
+					vec![field::<Hash>("claim"), field::<T::AccountId>("attester")],
+				),
+			],
+			errors: Error::metadata(),
+		}
+	}
+
+	/// The hash a claim on `content` is stored under. Can be computed off-chain, to look a
+	/// claim up with `get_claim_by_hash` without revealing the content.
+	pub fn hash_content(content: &T::Content) -> Hash {
+		hashing::blake2_256(&content.encode())
 	}
 
 	pub fn get_claim(&self, claim: &T::Content) -> Option<&T::AccountId> {
-		return self.claims.get(claim);
+		self.get_claim_by_hash(&Self::hash_content(claim))
+	}
+
+	pub fn get_claim_by_hash(&self, hash: &Hash) -> Option<&T::AccountId> {
+		self.get_claim_details_by_hash(hash).map(|details| &details.owner)
+	}
+
+	pub fn get_claim_details(&self, claim: &T::Content) -> Option<&ClaimDetails<T>> {
+		self.get_claim_details_by_hash(&Self::hash_content(claim))
+	}
+
+	/// Merge the changes of the block into the committed storage, see `overlay`.
+	pub fn commit(&mut self) {
+		self.claims.commit();
+		self.claims_of.commit();
+		self.expiries.commit();
+		self.attestations.commit();
+		self.previous_revision.commit();
+		self.next_revision.commit();
+	}
+
+	/// The entries of the state root: the claims, including expired ones that weren't removed,
+	/// their attestations and the revision history of documents.
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "ProofOfExistence", STORAGE_VERSION);
+		for (hash, details) in &self.claims {
+			storage.insert(storage_key("ProofOfExistence", "claims", hash), details.encode());
+		}
+		let attestations = self.attestations.iter().flat_map(|(hash, attesters)| {
+			attesters.iter().map(move |attester| ((hash, attester), ()))
+		});
+		trie::insert_entries(storage, "ProofOfExistence", "attestations", attestations);
+		trie::insert_entries(
+			storage,
+			"ProofOfExistence",
+			"previous_revision",
+			&self.previous_revision,
+		);
+		trie::insert_entries(storage, "ProofOfExistence", "next_revision", &self.next_revision);
+		trie::insert_value(storage, "ProofOfExistence", "now", &self.now);
+	}
+
+	/// Restore the claims `storage_entries` put in `storage`, rebuilding the indexes.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult {
+		migrations::ensure_version(storage, "ProofOfExistence", STORAGE_VERSION)?;
+		for ((hash, attester), ()) in trie::decode_entries::<(Hash, T::AccountId), ()>(
+			storage,
+			"ProofOfExistence",
+			"attestations",
+		)? {
+			self.attestations.get_or_insert_with(hash, Default::default).insert(attester);
+		}
+		self.previous_revision =
+			trie::decode_entries(storage, "ProofOfExistence", "previous_revision")?
+				.into_iter()
+				.collect();
+		self.next_revision = trie::decode_entries(storage, "ProofOfExistence", "next_revision")?
+			.into_iter()
+			.collect();
+		if let Some(now) = trie::decode_value(storage, "ProofOfExistence", "now")? {
+			self.now = now;
+		}
+		for (hash, details) in
+			trie::decode_entries::<Hash, ClaimDetails<T>>(storage, "ProofOfExistence", "claims")?
+		{
+			self.claims_of
+				.get_or_insert_with(details.owner.clone(), Default::default)
+				.insert(hash);
+			if let Some(expiry) = Self::expiry(details.block_number) {
+				self.expiries.get_or_insert_with(expiry, Default::default).push(hash);
+			}
+			self.claims.insert(hash, details);
+		}
+		Ok(())
+	}
+
+	/// Turn the owners stored by version 0 into claim details. Those claims were free and
+	/// didn't record when they were made, so they get no deposit and block 0.
+	pub fn migrate_to_v1(storage: &mut Storage) -> DispatchResult {
+		let owners =
+			trie::decode_entries::<Hash, T::AccountId>(storage, "ProofOfExistence", "claims")?;
+		for (hash, owner) in owners {
+			let details = ClaimDetails::<T> {
+				owner: owner.clone(),
+				block_number: T::BlockNumber::zero(),
+				note: None,
+				depositor: owner,
+				deposit: T::Balance::zero(),
+			};
+			storage.insert(storage_key("ProofOfExistence", "claims", &hash), details.encode());
+		}
+		Ok(())
+	}
+
+	/// Expired claims are treated as nonexistent, even before they are removed.
+	pub fn get_claim_details_by_hash(&self, hash: &Hash) -> Option<&ClaimDetails<T>> {
+		self.claims.get(hash).filter(|details| !self.is_expired(details.block_number))
+	}
+
+	/// The hashes of the claims `owner` holds.
+	pub fn claims_of<'a>(&'a self, owner: &T::AccountId) -> impl Iterator<Item = &'a Hash> + 'a {
+		self.claims_of
+			.get(owner)
+			.into_iter()
+			.flatten()
+			.filter(|hash| self.get_claim_by_hash(hash).is_some())
+	}
+
+	pub fn claim_count(&self, owner: &T::AccountId) -> usize {
+		self.claims_of(owner).count()
+	}
+
+	/// The accounts that attested a claim on `content`.
+	pub fn attesters<'a>(
+		&'a self,
+		claim: &T::Content,
+	) -> impl Iterator<Item = &'a T::AccountId> + 'a {
+		self.attesters_by_hash(&Self::hash_content(claim))
+	}
+
+	/// The hashes of all revisions of the document `claim` is a revision of, oldest first.
+	pub fn claim_history(&self, claim: &T::Content) -> Vec<Hash> {
+		let mut first = Self::hash_content(claim);
+		while let Some(previous) = self.previous_revision.get(&first) {
+			first = *previous;
+		}
+		let mut history = vec![first];
+		while let Some(next) = self.next_revision.get(&history[history.len() - 1]) {
+			history.push(*next);
+		}
+		history
+	}
+
+	pub fn attesters_by_hash<'a>(
+		&'a self,
+		hash: &Hash,
+	) -> impl Iterator<Item = &'a T::AccountId> + 'a {
+		let live = self.get_claim_by_hash(hash).is_some();
+		self.attestations.get(hash).filter(|_| live).into_iter().flatten()
+	}
+
+	/// The block at which a claim created at `created` expires, if it ever does.
+	fn expiry(created: T::BlockNumber) -> Option<T::BlockNumber> {
+		created.checked_add(&T::CLAIM_TTL)
+	}
+
+	fn is_expired(&self, created: T::BlockNumber) -> bool {
+		Self::expiry(created).is_some_and(|expiry| expiry <= self.now)
+	}
+
+	pub fn create_claim(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		caller: T::AccountId,
+		claim: T::Content,
+	) -> DispatchResult {
+		self.create_claim_with_note(balances, caller, claim, None)
+	}
+
+	pub fn create_claim_with_note(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		caller: T::AccountId,
+		claim: T::Content,
+		note: Option<Vec<u8>>,
+	) -> DispatchResult {
+		if note.as_ref().is_some_and(|note| note.len() > T::MAX_NOTE_LENGTH) {
+			return Err(Error::NoteTooLong.into());
+		}
+		let hash = Self::hash_content(&claim);
+		if self.get_claim_by_hash(&hash).is_some() {
+			return Err(Error::ClaimAlreadyExists.into());
+		}
+		balances.hold(HoldReason::ClaimDeposit.into(), &caller, T::CLAIM_DEPOSIT)?;
+		self.insert_claim(balances, caller, hash, note);
+		Ok(())
+	}
+
+	/// Fails without creating any claim if one of them already exists, or appears twice.
+	pub fn create_claims(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		caller: T::AccountId,
+		claims: Vec<T::Content>,
+	) -> DispatchResult {
+		let mut hashes = BTreeSet::new();
+		for claim in &claims {
+			let hash = Self::hash_content(claim);
+			if self.get_claim_by_hash(&hash).is_some() || !hashes.insert(hash) {
+				return Err(Error::ClaimAlreadyExists.into());
+			}
+		}
+		let count = T::Balance::from(hashes.len() as u64);
+		let deposit = T::CLAIM_DEPOSIT.checked_mul(&count).ok_or(Error::DepositOverflow)?;
+		balances.hold(HoldReason::ClaimDeposit.into(), &caller, deposit)?;
+		for hash in hashes {
+			self.insert_claim(balances, caller.clone(), hash, None);
+		}
+		Ok(())
+	}
+
+	/// Store a new claim whose deposit is held already.
+	fn insert_claim(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		caller: T::AccountId,
+		hash: Hash,
+		note: Option<Vec<u8>>,
+	) {
+		let details = ClaimDetails {
+			owner: caller.clone(),
+			block_number: self.now,
+			note,
+			depositor: caller.clone(),
+			deposit: T::CLAIM_DEPOSIT,
+		};
+		if let Some(old) = self.claims.insert(hash, details) {
+			// The claim had expired, but wasn't removed yet.
+			self.remove_from_index(&old.owner, &hash);
+			self.attestations.remove(&hash);
+			let _ = balances.release(HoldReason::ClaimDeposit.into(), &old.depositor, old.deposit);
+		}
+		self.claims_of.get_or_insert_with(caller, Default::default).insert(hash);
+		if let Some(expiry) = Self::expiry(self.now) {
+			self.expiries.get_or_insert_with(expiry, Default::default).push(hash);
+		}
+	}
+
+	pub fn revoke_claim(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		caller: T::AccountId,
+		claim: T::Content,
+	) -> DispatchResult {
+		let hash = Self::hash_content(&claim);
+		let claim_owner = self.get_claim_by_hash(&hash).ok_or(Error::ClaimDoesNotExist)?;
+
+		if claim_owner != &caller {
+			return Err(Error::NotTheOwner.into());
+		}
+
+		self.remove_claim(balances, &hash);
+		Ok(())
+	}
+
+	/// Remove a claim and give its deposit back.
+	fn remove_claim(&mut self, balances: &mut balances::Pallet<T>, hash: &Hash) {
+		if let Some(details) = self.claims.remove(hash) {
+			self.remove_from_index(&details.owner, hash);
+			self.attestations.remove(hash);
+			// The deposit is still held, as only this pallet releases it.
+			let reason = HoldReason::ClaimDeposit.into();
+			let _ = balances.release(reason, &details.depositor, details.deposit);
+		}
 	}
 
-	pub fn create_claim(&mut self, caller: T::AccountId, claim: T::Content) -> DispatchResult {
-		match self.get_claim(&claim) {
-			Some(_) => Err("Claim already exists"),
-			None => {
-				self.claims.insert(claim, caller);
-				Ok(())
-			},
+	pub fn transfer_claim(
+		&mut self,
+		caller: T::AccountId,
+		claim: T::Content,
+		to: T::AccountId,
+	) -> DispatchResult {
+		let hash = Self::hash_content(&claim);
+		let claim_owner = self.get_claim_by_hash(&hash).ok_or(Error::ClaimDoesNotExist)?;
+
+		if claim_owner != &caller {
+			return Err(Error::NotTheOwner.into());
+		}
+
+		if let Some(details) = self.claims.get_mut(&hash) {
+			details.owner = to.clone();
 		}
+		self.remove_from_index(&caller, &hash);
+		self.claims_of.get_or_insert_with(to.clone(), Default::default).insert(hash);
+		// The new owner can't vouch for their own claim.
+		if let Some(attesters) = self.attestations.get_mut(&hash) {
+			attesters.remove(&to);
+		}
+		self.events.push(Event::ClaimTransferred { claim: hash, from: caller, to });
+		Ok(())
 	}
 
-	pub fn revoke_claim(&mut self, caller: T::AccountId, claim: T::Content) -> DispatchResult {
-		let claim_owner = self.get_claim(&claim).ok_or("Claim does not exist")?;
+	/// Claim `new` as the next revision of `old`, which must be held by the caller. The claim
+	/// on `old` is kept, and each revision can only be updated once.
+	pub fn update_claim(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		caller: T::AccountId,
+		old: T::Content,
+		new: T::Content,
+	) -> DispatchResult {
+		let old_hash = Self::hash_content(&old);
+		let new_hash = Self::hash_content(&new);
+		let claim_owner = self.get_claim_by_hash(&old_hash).ok_or(Error::ClaimDoesNotExist)?;
 
 		if claim_owner != &caller {
-			return Err("The claim does not belong to Caller");
+			return Err(Error::NotTheOwner.into());
+		}
+		if self.next_revision.contains_key(&old_hash) {
+			return Err(Error::ClaimAlreadyUpdated.into());
 		}
+		// Linking a revision that was in a history before could turn it into a cycle.
+		if self.previous_revision.contains_key(&new_hash)
+			|| self.next_revision.contains_key(&new_hash)
+		{
+			return Err(Error::ClaimIsPartOfAHistory.into());
+		}
+
+		self.create_claim(balances, caller, new)?;
+		self.previous_revision.insert(new_hash, old_hash);
+		self.next_revision.insert(old_hash, new_hash);
+		Ok(())
+	}
 
-		self.claims.remove(&claim);
+	pub fn attest(&mut self, caller: T::AccountId, claim: T::Content) -> DispatchResult {
+		let hash = Self::hash_content(&claim);
+		let claim_owner = self.get_claim_by_hash(&hash).ok_or(Error::ClaimDoesNotExist)?;
+
+		if claim_owner == &caller {
+			return Err(Error::CannotAttestOwnClaim.into());
+		}
+		if !self
+			.attestations
+			.get_or_insert_with(hash, Default::default)
+			.insert(caller.clone())
+		{
+			return Err(Error::AlreadyAttested.into());
+		}
+		self.events.push(Event::ClaimAttested { claim: hash, attester: caller });
 		Ok(())
 	}
+
+	fn remove_from_index(&mut self, owner: &T::AccountId, hash: &Hash) {
+		if let Some(hashes) = self.claims_of.get_mut(owner) {
+			hashes.remove(hash);
+			if hashes.is_empty() {
+				self.claims_of.remove(owner);
+			}
+		}
+	}
+
+	pub fn events(&self) -> &[Event<T>] {
+		&self.events
+	}
+
+	/// Hand the events deposited so far to the caller.
+	pub fn take_events(&mut self) -> Vec<Event<T>> {
+		core::mem::take(&mut self.events)
+	}
 }
 
-#[cfg(test)]
-mod test {
+impl<T: Config> Pallet<T> {
+	/// Called at the start of every block.
+	pub fn on_initialize(&mut self, now: T::BlockNumber) {
+		self.now = now;
+	}
 
-	struct TestConfig;
+	/// Called at the end of every block: the claims that expire at `now` are removed and their
+	/// deposits given back.
+	pub fn on_finalize(&mut self, balances: &mut balances::Pallet<T>, now: T::BlockNumber) {
+		for hash in self.expiries.remove(&now).unwrap_or_default() {
+			let expired = self
+				.claims
+				.get(&hash)
+				.is_some_and(|details| Self::expiry(details.block_number) == Some(now));
+			if expired {
+				self.remove_claim(balances, &hash);
+			}
+		}
+	}
+}
 
-	impl crate::system::Config for TestConfig {
-		type Nonce = u32;
-		type BlockNumber = u32;
-		type AccountId = String;
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
 	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{
+		balances,
+		mock::{MockRuntime, MockRuntimeBuilder, Test},
+	};
 
-	impl super::Config for TestConfig {
-		type Content = String;
+	fn setup() -> (balances::Pallet<Test>, super::Pallet<Test>) {
+		let runtime = MockRuntimeBuilder::new().with_balance("alice", 100).build();
+		(runtime.balances, runtime.proof_of_existence)
 	}
 
 	#[test]
 	fn basic_proof_of_existence() {
 		let alice = "alice".to_string();
 		let my_document: String = "my_document".to_string();
-		let mut poe: super::Pallet<TestConfig> = super::Pallet::new();
+		let (mut balances, mut poe) = setup();
 
-		let _ = poe.create_claim(alice.clone(), my_document.clone());
+		let _ = poe.create_claim(&mut balances, alice.clone(), my_document.clone());
 		assert_eq!(poe.get_claim(&my_document), Some(&alice));
 	}
 
+	#[test]
+	fn claims_are_stored_by_hash() {
+		let alice = "alice".to_string();
+		let my_document: String = "my_document".to_string();
+		let (mut balances, mut poe) = setup();
+
+		let _ = poe.create_claim(&mut balances, alice.clone(), my_document.clone());
+
+		let hash = super::Pallet::<Test>::hash_content(&my_document);
+		assert_eq!(poe.get_claim_by_hash(&hash), Some(&alice));
+		assert_eq!(poe.get_claim_by_hash(&[0; 32]), None);
+	}
+
 	#[test]
 	fn revoke_in_proof_of_existence() {
 		let alice = "alice".to_string();
 		let my_document: String = "my_document".to_string();
-		let mut poe: super::Pallet<TestConfig> = super::Pallet::new();
+		let (mut balances, mut poe) = setup();
 
-		let _ = poe.create_claim(alice.clone(), my_document.clone());
+		let _ = poe.create_claim(&mut balances, alice.clone(), my_document.clone());
 		assert_eq!(poe.get_claim(&my_document), Some(&alice));
 
-		let _ = poe.revoke_claim(alice.clone(), my_document.clone());
+		let _ = poe.revoke_claim(&mut balances, alice.clone(), my_document.clone());
 		assert_eq!(poe.get_claim(&my_document), None);
 	}
 
@@ -105,9 +672,9 @@ mod test {
 	fn revoke_not_existing_claim() {
 		let alice = "alice".to_string();
 		let my_document: String = "my_document".to_string();
-		let mut poe: super::Pallet<TestConfig> = super::Pallet::new();
+		let (mut balances, mut poe) = setup();
 
-		let result = poe.revoke_claim(alice.clone(), my_document.clone());
+		let result = poe.revoke_claim(&mut balances, alice.clone(), my_document.clone());
 		assert_eq!(result, Err("Claim does not exist"));
 	}
 
@@ -116,11 +683,248 @@ mod test {
 		let alice = "alice".to_string();
 		let bob: String = "bob".to_string();
 		let my_document: String = "my_document".to_string();
-		let mut poe: super::Pallet<TestConfig> = super::Pallet::new();
+		let (mut balances, mut poe) = setup();
 
-		let _ = poe.create_claim(alice.clone(), my_document.clone());
+		let _ = poe.create_claim(&mut balances, alice.clone(), my_document.clone());
 
-		let result: Result<(), &str> = poe.revoke_claim(bob.clone(), my_document.clone());
+		let result: Result<(), &str> =
+			poe.revoke_claim(&mut balances, bob.clone(), my_document.clone());
 		assert_eq!(result, Err("The claim does not belong to Caller"));
 	}
+
+	#[test]
+	fn create_claims_is_atomic() {
+		let alice = "alice".to_string();
+		let (mut balances, mut poe) = setup();
+		let _ = poe.create_claim(&mut balances, alice.clone(), "b".to_string());
+
+		let claims = vec!["a".to_string(), "b".to_string()];
+		assert_eq!(
+			poe.create_claims(&mut balances, alice.clone(), claims),
+			Err("Claim already exists")
+		);
+		assert_eq!(poe.get_claim(&"a".to_string()), None);
+
+		let claims = vec!["a".to_string(), "a".to_string()];
+		assert_eq!(
+			poe.create_claims(&mut balances, alice.clone(), claims),
+			Err("Claim already exists")
+		);
+		assert_eq!(poe.claim_count(&alice), 1);
+
+		let claims = vec!["a".to_string(), "c".to_string()];
+		assert_eq!(poe.create_claims(&mut balances, alice.clone(), claims), Ok(()));
+		assert_eq!(poe.claim_count(&alice), 3);
+	}
+
+	#[test]
+	fn claims_carry_details() {
+		let alice = "alice".to_string();
+		let my_document: String = "my_document".to_string();
+		let MockRuntime { mut balances, proof_of_existence: mut poe, .. } =
+			MockRuntimeBuilder::new()
+				.with_balance("alice", 100)
+				.with_block_number(3)
+				.build();
+
+		let result = poe.create_claim_with_note(
+			&mut balances,
+			alice.clone(),
+			my_document.clone(),
+			Some(vec![0; 9]),
+		);
+		assert_eq!(result, Err("Note too long"));
+		let result = poe.create_claim_with_note(
+			&mut balances,
+			alice.clone(),
+			my_document.clone(),
+			Some(b"v1".to_vec()),
+		);
+		assert_eq!(result, Ok(()));
+
+		let details = poe.get_claim_details(&my_document).unwrap();
+		assert_eq!(details.owner, alice);
+		assert_eq!(details.block_number, 3);
+		assert_eq!(details.note.as_deref(), Some(&b"v1"[..]));
+		assert_eq!(poe.get_claim(&my_document), Some(&alice));
+	}
+
+	#[test]
+	fn claims_are_indexed_by_owner() {
+		let alice = "alice".to_string();
+		let (mut balances, mut poe) = setup();
+
+		for document in ["a", "b", "c"] {
+			let _ = poe.create_claim(&mut balances, alice.clone(), document.to_string());
+		}
+		let _ = poe.revoke_claim(&mut balances, alice.clone(), "b".to_string());
+
+		let mut expected: Vec<_> = ["a", "c"]
+			.iter()
+			.map(|document| super::Pallet::<Test>::hash_content(&document.to_string()))
+			.collect();
+		expected.sort();
+		assert_eq!(poe.claims_of(&alice).copied().collect::<Vec<_>>(), expected);
+		assert_eq!(poe.claim_count(&alice), 2);
+		assert_eq!(poe.claim_count(&"bob".to_string()), 0);
+	}
+
+	#[test]
+	fn claims_expire() {
+		let alice = "alice".to_string();
+		let my_document: String = "my_document".to_string();
+		let mut runtime = MockRuntimeBuilder::new()
+			.with_balance("alice", 100)
+			.with_block_number(5)
+			.with_claim("alice", "my_document")
+			.build();
+
+		runtime.run_to_block(14);
+		assert_eq!(runtime.proof_of_existence.get_claim(&my_document), Some(&alice));
+		runtime.run_to_block(15);
+		let MockRuntime { balances, proof_of_existence: poe, .. } = &mut runtime;
+		assert_eq!(poe.get_claim(&my_document), None);
+		assert_eq!(
+			poe.revoke_claim(balances, alice.clone(), my_document.clone()),
+			Err("Claim does not exist")
+		);
+
+		runtime.run_to_block(16);
+		let poe = &runtime.proof_of_existence;
+		assert!(poe.claims.is_empty());
+		assert!(poe.claims_of.is_empty());
+		assert!(poe.expiries.is_empty());
+	}
+
+	#[test]
+	fn transfer_claim() {
+		let alice = "alice".to_string();
+		let bob: String = "bob".to_string();
+		let my_document: String = "my_document".to_string();
+		let (mut balances, mut poe) = setup();
+
+		let _ = poe.create_claim(&mut balances, alice.clone(), my_document.clone());
+		let result = poe.transfer_claim(bob.clone(), my_document.clone(), bob.clone());
+		assert_eq!(result, Err("The claim does not belong to Caller"));
+
+		assert_eq!(poe.transfer_claim(alice.clone(), my_document.clone(), bob.clone()), Ok(()));
+		assert_eq!(poe.get_claim(&my_document), Some(&bob));
+		assert_eq!(poe.claim_count(&alice), 0);
+		assert_eq!(poe.claim_count(&bob), 1);
+		let hash = super::Pallet::<Test>::hash_content(&my_document);
+		assert!(matches!(
+			poe.take_events().as_slice(),
+			[super::Event::ClaimTransferred { claim, from, to }]
+				if *claim == hash && *from == alice && *to == bob
+		));
+
+		let result = poe.revoke_claim(&mut balances, alice.clone(), my_document.clone());
+		assert_eq!(result, Err("The claim does not belong to Caller"));
+	}
+
+	#[test]
+	fn claims_reserve_a_deposit() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let (mut balances, mut poe) = setup();
+
+		let result = poe.create_claim(&mut balances, bob.clone(), "bob's".to_string());
+		assert_eq!(result, Err("Insufficient balance"));
+		assert_eq!(poe.get_claim(&"bob's".to_string()), None);
+
+		let claims = vec!["a".to_string(), "b".to_string()];
+		assert_eq!(poe.create_claims(&mut balances, alice.clone(), claims), Ok(()));
+		assert_eq!(balances.reserved_balance(&alice), 20);
+
+		// The deposit stays with alice when the claim changes hands.
+		let _ = poe.transfer_claim(alice.clone(), "a".to_string(), bob.clone());
+		assert_eq!(poe.revoke_claim(&mut balances, bob.clone(), "a".to_string()), Ok(()));
+		assert_eq!(balances.reserved_balance(&alice), 10);
+		assert_eq!(balances.reserved_balance(&bob), 0);
+
+		poe.on_finalize(&mut balances, 10);
+		assert_eq!(balances.reserved_balance(&alice), 0);
+		assert_eq!(balances.balance(&alice), 100);
+	}
+
+	#[test]
+	fn attest_claim() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let charlie = "charlie".to_string();
+		let my_document: String = "my_document".to_string();
+		let (mut balances, mut poe) = setup();
+
+		assert_eq!(poe.attest(bob.clone(), my_document.clone()), Err("Claim does not exist"));
+		let _ = poe.create_claim(&mut balances, alice.clone(), my_document.clone());
+		assert_eq!(poe.attest(alice.clone(), my_document.clone()), Err("Cannot attest own claim"));
+		assert_eq!(poe.attest(bob.clone(), my_document.clone()), Ok(()));
+		assert_eq!(poe.attest(bob.clone(), my_document.clone()), Err("Already attested"));
+		assert_eq!(poe.attest(charlie.clone(), my_document.clone()), Ok(()));
+		assert_eq!(poe.attesters(&my_document).collect::<Vec<_>>(), vec![&bob, &charlie]);
+		assert_eq!(poe.events().len(), 2);
+
+		let _ = poe.transfer_claim(alice.clone(), my_document.clone(), bob.clone());
+		assert_eq!(poe.attesters(&my_document).collect::<Vec<_>>(), vec![&charlie]);
+
+		let _ = poe.revoke_claim(&mut balances, bob.clone(), my_document.clone());
+		assert_eq!(poe.attesters(&my_document).count(), 0);
+		assert!(poe.attestations.is_empty());
+	}
+
+	#[test]
+	fn update_claim_keeps_history() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let (v1, v2, v3) = ("v1".to_string(), "v2".to_string(), "v3".to_string());
+		let hash = super::Pallet::<Test>::hash_content;
+		let (mut balances, mut poe) = setup();
+
+		let _ = poe.create_claim(&mut balances, alice.clone(), v1.clone());
+		let result = poe.update_claim(&mut balances, bob.clone(), v1.clone(), v2.clone());
+		assert_eq!(result, Err("The claim does not belong to Caller"));
+		assert_eq!(poe.update_claim(&mut balances, alice.clone(), v1.clone(), v2.clone()), Ok(()));
+		let result = poe.update_claim(&mut balances, alice.clone(), v1.clone(), v3.clone());
+		assert_eq!(result, Err("Claim already updated"));
+		assert_eq!(poe.update_claim(&mut balances, alice.clone(), v2.clone(), v3.clone()), Ok(()));
+		assert_eq!(poe.get_claim(&v1), Some(&alice));
+		assert_eq!(balances.reserved_balance(&alice), 30);
+
+		// The history survives the claims on older revisions.
+		let _ = poe.revoke_claim(&mut balances, alice.clone(), v1.clone());
+		assert_eq!(poe.claim_history(&v2), vec![hash(&v1), hash(&v2), hash(&v3)]);
+		let _ = poe.create_claim(&mut balances, alice.clone(), v1.clone());
+		let result = poe.update_claim(&mut balances, alice.clone(), v3.clone(), v1.clone());
+		assert_eq!(result, Err("Claim is part of a history"));
+
+		assert_eq!(poe.claim_history(&"other".to_string()), vec![hash(&"other".to_string())]);
+
+		let mut storage = crate::trie::Storage::new();
+		poe.storage_entries(&mut storage);
+		let mut restored = super::Pallet::<Test>::new();
+		assert_eq!(restored.restore_entries(&storage), Ok(()));
+		assert_eq!(restored.claim_history(&v2), vec![hash(&v1), hash(&v2), hash(&v3)]);
+	}
+
+	#[test]
+	fn owners_are_migrated_to_claim_details() {
+		use crate::{codec::Encode, migrations, trie};
+
+		let alice = "alice".to_string();
+		let hash = super::Pallet::<Test>::hash_content(&"my_document".to_string());
+		let mut storage = trie::Storage::new();
+		storage.insert(trie::storage_key("ProofOfExistence", "claims", &hash), alice.encode());
+
+		let mut poe = super::Pallet::<Test>::new();
+		assert_eq!(poe.restore_entries(&storage), Err("Unsupported storage version"));
+		let migration = migrations::Migration {
+			pallet: "ProofOfExistence",
+			to: 1,
+			migrate: super::Pallet::<Test>::migrate_to_v1,
+		};
+		assert_eq!(migrations::apply(&mut storage, &[migration]), Ok(1));
+		assert_eq!(poe.restore_entries(&storage), Ok(()));
+		let details = poe.get_claim_details_by_hash(&hash).unwrap();
+		assert_eq!((&details.owner, details.deposit), (&alice, 0));
+	}
 }
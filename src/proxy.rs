@@ -0,0 +1,671 @@
+use core::fmt::Debug;
+use std::collections::BTreeMap;
+
+use num::{CheckedAdd, Zero};
+
+use crate::{
+	codec::{Decode, Encode},
+	hashing::{blake2_256, Hash},
+	metadata::{field, storage, variant, PalletMetadata},
+	migrations::{self, StorageVersion},
+	support::{ensure_signed, DispatchResult, InstanceFilter, Weight},
+	system::{self, OriginFor},
+	trie::{self, Storage},
+};
+
+pub trait Config: system::Config {
+	/// The aggregated call type a proxy can dispatch.
+	type RuntimeCall: Debug + Clone + Encode;
+	/// The kinds of proxy, each allowing a subset of the calls.
+	type ProxyType: Debug + Copy + PartialEq + Encode + InstanceFilter<Self::RuntimeCall>;
+	/// The maximum number of proxies a single account can have.
+	const MAX_PROXIES: usize;
+	/// The maximum number of pending announcements a single delegate can have.
+	const MAX_PENDING: usize;
+}
+
+#[derive(Debug, Clone)]
+pub enum Call<T: Config> {
+	AddProxy {
+		delegate: T::AccountId,
+		proxy_type: T::ProxyType,
+		delay: T::BlockNumber,
+	},
+	RemoveProxy {
+		delegate: T::AccountId,
+		proxy_type: T::ProxyType,
+		delay: T::BlockNumber,
+	},
+	Proxy {
+		real: T::AccountId,
+		force_proxy_type: Option<T::ProxyType>,
+		call: Box<T::RuntimeCall>,
+	},
+	Announce {
+		real: T::AccountId,
+		call_hash: Hash,
+	},
+	RemoveAnnouncement {
+		real: T::AccountId,
+		call_hash: Hash,
+	},
+	RejectAnnouncement {
+		delegate: T::AccountId,
+		call_hash: Hash,
+	},
+	ProxyAnnounced {
+		delegate: T::AccountId,
+		real: T::AccountId,
+		force_proxy_type: Option<T::ProxyType>,
+		call: Box<T::RuntimeCall>,
+	},
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::AddProxy { delegate, proxy_type, delay } => {
+				(0u8, delegate, proxy_type, delay).encode_to(dest)
+			},
+			Call::RemoveProxy { delegate, proxy_type, delay } => {
+				(1u8, delegate, proxy_type, delay).encode_to(dest)
+			},
+			Call::Proxy { real, force_proxy_type, call } => {
+				(2u8, real, force_proxy_type, call).encode_to(dest)
+			},
+			Call::Announce { real, call_hash } => (3u8, real, call_hash).encode_to(dest),
+			Call::RemoveAnnouncement { real, call_hash } => (4u8, real, call_hash).encode_to(dest),
+			Call::RejectAnnouncement { delegate, call_hash } => {
+				(5u8, delegate, call_hash).encode_to(dest)
+			},
+			Call::ProxyAnnounced { delegate, real, force_proxy_type, call } => {
+				(6u8, delegate, real, (force_proxy_type, call)).encode_to(dest)
+			},
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T>
+where
+	T::RuntimeCall: Decode,
+	T::ProxyType: Decode,
+{
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::AddProxy {
+				delegate: Decode::decode(input)?,
+				proxy_type: Decode::decode(input)?,
+				delay: Decode::decode(input)?,
+			},
+			1 => Call::RemoveProxy {
+				delegate: Decode::decode(input)?,
+				proxy_type: Decode::decode(input)?,
+				delay: Decode::decode(input)?,
+			},
+			2 => Call::Proxy {
+				real: Decode::decode(input)?,
+				force_proxy_type: Decode::decode(input)?,
+				call: Decode::decode(input)?,
+			},
+			3 => Call::Announce { real: Decode::decode(input)?, call_hash: Decode::decode(input)? },
+			4 => Call::RemoveAnnouncement {
+				real: Decode::decode(input)?,
+				call_hash: Decode::decode(input)?,
+			},
+			5 => Call::RejectAnnouncement {
+				delegate: Decode::decode(input)?,
+				call_hash: Decode::decode(input)?,
+			},
+			6 => Call::ProxyAnnounced {
+				delegate: Decode::decode(input)?,
+				real: Decode::decode(input)?,
+				force_proxy_type: Decode::decode(input)?,
+				call: Decode::decode(input)?,
+			},
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::AddProxy { .. } => 0,
+			Call::RemoveProxy { .. } => 1,
+			Call::Proxy { .. } => 2,
+			Call::Announce { .. } => 3,
+			Call::RemoveAnnouncement { .. } => 4,
+			Call::RejectAnnouncement { .. } => 5,
+			Call::ProxyAnnounced { .. } => 6,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::AddProxy { .. } | Call::RemoveProxy { .. } => 5_000,
+			// The weight of the proxied call is added by the runtime.
+			Call::Proxy { .. } => 5_000,
+			Call::Announce { .. } | Call::RemoveAnnouncement { .. } => 5_000,
+			Call::RejectAnnouncement { .. } => 5_000,
+			Call::ProxyAnnounced { .. } => 10_000,
+		}
+	}
+}
+
+/// The permission of `delegate` to dispatch calls on behalf of an account.
+#[derive(Debug, Clone)]
+pub struct ProxyDefinition<T: Config> {
+	pub delegate: T::AccountId,
+	pub proxy_type: T::ProxyType,
+	/// The number of blocks a call must be announced in advance; zero means no announcement.
+	pub delay: T::BlockNumber,
+}
+
+impl<T: Config> Encode for ProxyDefinition<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.delegate, &self.proxy_type, &self.delay).encode_to(dest);
+	}
+}
+
+impl<T: Config> Decode for ProxyDefinition<T>
+where
+	T::ProxyType: Decode,
+{
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (delegate, proxy_type, delay) = Decode::decode(input)?;
+		Ok(ProxyDefinition { delegate, proxy_type, delay })
+	}
+}
+
+impl<T: Config> ProxyDefinition<T> {
+	fn is(&self, delegate: &T::AccountId, proxy_type: T::ProxyType, delay: T::BlockNumber) -> bool {
+		&self.delegate == delegate && self.proxy_type == proxy_type && self.delay == delay
+	}
+}
+
+/// A call a delegate intends to make on behalf of `real`, once the proxy delay has passed.
+#[derive(Debug, Clone)]
+pub struct Announcement<T: Config> {
+	pub real: T::AccountId,
+	pub call_hash: Hash,
+	pub height: T::BlockNumber,
+}
+
+impl<T: Config> Encode for Announcement<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.real, &self.call_hash, &self.height).encode_to(dest);
+	}
+}
+
+impl<T: Config> Decode for Announcement<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (real, call_hash, height) = Decode::decode(input)?;
+		Ok(Announcement { real, call_hash, height })
+	}
+}
+
+/// A proxied call cleared for dispatch, with the account it must be dispatched as.
+pub type Executable<T> = (<T as system::Config>::AccountId, <T as Config>::RuntimeCall);
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	proxies: BTreeMap<T::AccountId, Vec<ProxyDefinition<T>>>,
+	announcements: BTreeMap<T::AccountId, Vec<Announcement<T>>>,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self { proxies: BTreeMap::new(), announcements: BTreeMap::new() }
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Proxy",
+			index: None,
+			calls: vec![
+				variant(
+					"AddProxy",
+					0,
+					vec![
+						field::<T::AccountId>("delegate"),
+						field::<T::ProxyType>("proxy_type"),
+						field::<T::BlockNumber>("delay"),
+					],
+				),
+				variant(
+					"RemoveProxy",
+					1,
+					vec![
+						field::<T::AccountId>("delegate"),
+						field::<T::ProxyType>("proxy_type"),
+						field::<T::BlockNumber>("delay"),
+					],
+				),
+				variant(
+					"Proxy",
+					2,
+					vec![
+						field::<T::AccountId>("real"),
+						field::<Option<T::ProxyType>>("force_proxy_type"),
+						field::<Box<T::RuntimeCall>>("call"),
+					],
+				),
+				variant(
+					"Announce",
+					3,
+					vec![field::<T::AccountId>("real"), field::<Hash>("call_hash")],
+				),
+				variant(
+					"RemoveAnnouncement",
+					4,
+					vec![field::<T::AccountId>("real"), field::<Hash>("call_hash")],
+				),
+				variant(
+					"RejectAnnouncement",
+					5,
+					vec![field::<T::AccountId>("delegate"), field::<Hash>("call_hash")],
+				),
+				variant(
+					"ProxyAnnounced",
+					6,
+					vec![
+						field::<T::AccountId>("delegate"),
+						field::<T::AccountId>("real"),
+						field::<Option<T::ProxyType>>("force_proxy_type"),
+						field::<Box<T::RuntimeCall>>("call"),
+					],
+				),
+			],
+			storage: vec![
+				storage::<BTreeMap<T::AccountId, Vec<ProxyDefinition<T>>>>("proxies"),
+				storage::<BTreeMap<T::AccountId, Vec<Announcement<T>>>>("announcements"),
+			],
+			events: Vec::new(),
+			errors: vec![
+				"Announcement delay has not passed",
+				"Announcement not found",
+				"Block number overflow",
+				"Call filtered by proxy type",
+				"Not a proxy",
+				"Proxy already exists",
+				"Proxy does not exist",
+				"Proxy requires the call to be announced",
+				"Too many pending announcements",
+				"Too many proxies",
+			],
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Proxy", STORAGE_VERSION);
+		trie::insert_entries(storage, "Proxy", "proxies", &self.proxies);
+		trie::insert_entries(storage, "Proxy", "announcements", &self.announcements);
+	}
+
+	/// Restore the proxies and announcements `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult
+	where
+		T::ProxyType: Decode,
+	{
+		migrations::ensure_version(storage, "Proxy", STORAGE_VERSION)?;
+		self.proxies = trie::decode_entries(storage, "Proxy", "proxies")?.into_iter().collect();
+		self.announcements =
+			trie::decode_entries(storage, "Proxy", "announcements")?.into_iter().collect();
+		Ok(())
+	}
+
+	/// Dispatch a proxy call made by `caller` at block `now`. Proxied calls that pass all the
+	/// checks are returned, so the runtime can dispatch them with the real account as caller.
+	pub fn dispatch(
+		&mut self,
+		now: T::BlockNumber,
+		origin: OriginFor<T>,
+		call: Call<T>,
+	) -> Result<Option<Executable<T>>, &'static str> {
+		let caller = ensure_signed(origin)?;
+		match call {
+			Call::AddProxy { delegate, proxy_type, delay } => {
+				self.add_proxy(caller, delegate, proxy_type, delay).map(|_| None)
+			},
+			Call::RemoveProxy { delegate, proxy_type, delay } => {
+				self.remove_proxy(caller, delegate, proxy_type, delay).map(|_| None)
+			},
+			Call::Proxy { real, force_proxy_type, call } => {
+				self.proxy(caller, real, force_proxy_type, *call).map(Some)
+			},
+			Call::Announce { real, call_hash } => {
+				self.announce(now, caller, real, call_hash).map(|_| None)
+			},
+			Call::RemoveAnnouncement { real, call_hash } => {
+				self.remove_announcement(caller, real, call_hash).map(|_| None)
+			},
+			Call::RejectAnnouncement { delegate, call_hash } => {
+				self.reject_announcement(caller, delegate, call_hash).map(|_| None)
+			},
+			Call::ProxyAnnounced { delegate, real, force_proxy_type, call } => {
+				self.proxy_announced(now, delegate, real, force_proxy_type, *call).map(Some)
+			},
+		}
+	}
+
+	pub fn proxies(&self, real: &T::AccountId) -> &[ProxyDefinition<T>] {
+		self.proxies.get(real).map(|proxies| proxies.as_slice()).unwrap_or_default()
+	}
+
+	pub fn announcements(&self, delegate: &T::AccountId) -> &[Announcement<T>] {
+		self.announcements
+			.get(delegate)
+			.map(|pending| pending.as_slice())
+			.unwrap_or_default()
+	}
+
+	pub fn call_hash(call: &T::RuntimeCall) -> Hash {
+		blake2_256(&call.encode())
+	}
+
+	pub fn add_proxy(
+		&mut self,
+		caller: T::AccountId,
+		delegate: T::AccountId,
+		proxy_type: T::ProxyType,
+		delay: T::BlockNumber,
+	) -> DispatchResult {
+		let proxies = self.proxies.entry(caller).or_default();
+
+		if proxies.iter().any(|p| p.is(&delegate, proxy_type, delay)) {
+			return Err("Proxy already exists");
+		}
+		if proxies.len() >= T::MAX_PROXIES {
+			return Err("Too many proxies");
+		}
+
+		proxies.push(ProxyDefinition { delegate, proxy_type, delay });
+		Ok(())
+	}
+
+	pub fn remove_proxy(
+		&mut self,
+		caller: T::AccountId,
+		delegate: T::AccountId,
+		proxy_type: T::ProxyType,
+		delay: T::BlockNumber,
+	) -> DispatchResult {
+		let proxies = self.proxies.get_mut(&caller).ok_or("Proxy does not exist")?;
+		let pos = proxies
+			.iter()
+			.position(|p| p.is(&delegate, proxy_type, delay))
+			.ok_or("Proxy does not exist")?;
+
+		proxies.remove(pos);
+		if proxies.is_empty() {
+			self.proxies.remove(&caller);
+		}
+		Ok(())
+	}
+
+	/// Dispatch `call` as `real` through a proxy without announcement delay.
+	pub fn proxy(
+		&self,
+		caller: T::AccountId,
+		real: T::AccountId,
+		force_proxy_type: Option<T::ProxyType>,
+		call: T::RuntimeCall,
+	) -> Result<Executable<T>, &'static str> {
+		let proxy = self.find_proxy(&real, &caller, force_proxy_type)?;
+		if !proxy.delay.is_zero() {
+			return Err("Proxy requires the call to be announced");
+		}
+		if !proxy.proxy_type.filter(&call) {
+			return Err("Call filtered by proxy type");
+		}
+		Ok((real, call))
+	}
+
+	pub fn announce(
+		&mut self,
+		now: T::BlockNumber,
+		caller: T::AccountId,
+		real: T::AccountId,
+		call_hash: Hash,
+	) -> DispatchResult {
+		self.find_proxy(&real, &caller, None)?;
+
+		let pending = self.announcements.entry(caller).or_default();
+		if pending.len() >= T::MAX_PENDING {
+			return Err("Too many pending announcements");
+		}
+		pending.push(Announcement { real, call_hash, height: now });
+		Ok(())
+	}
+
+	pub fn remove_announcement(
+		&mut self,
+		caller: T::AccountId,
+		real: T::AccountId,
+		call_hash: Hash,
+	) -> DispatchResult {
+		self.take_announcement(&caller, |a| a.real == real && a.call_hash == call_hash)
+			.map(|_| ())
+	}
+
+	/// Called by the real account to prevent an announced call from being made.
+	pub fn reject_announcement(
+		&mut self,
+		caller: T::AccountId,
+		delegate: T::AccountId,
+		call_hash: Hash,
+	) -> DispatchResult {
+		self.take_announcement(&delegate, |a| a.real == caller && a.call_hash == call_hash)
+			.map(|_| ())
+	}
+
+	/// Dispatch an announced call once the delay of the proxy has passed. Anyone can trigger it.
+	pub fn proxy_announced(
+		&mut self,
+		now: T::BlockNumber,
+		delegate: T::AccountId,
+		real: T::AccountId,
+		force_proxy_type: Option<T::ProxyType>,
+		call: T::RuntimeCall,
+	) -> Result<Executable<T>, &'static str> {
+		let proxy = self.find_proxy(&real, &delegate, force_proxy_type)?;
+		let (proxy_type, delay) = (proxy.proxy_type, proxy.delay);
+		if !proxy_type.filter(&call) {
+			return Err("Call filtered by proxy type");
+		}
+
+		let call_hash = Self::call_hash(&call);
+		let announcement = self
+			.announcements(&delegate)
+			.iter()
+			.find(|a| a.real == real && a.call_hash == call_hash)
+			.ok_or("Announcement not found")?;
+		let ready_at = announcement.height.checked_add(&delay).ok_or("Block number overflow")?;
+		if now < ready_at {
+			return Err("Announcement delay has not passed");
+		}
+
+		self.take_announcement(&delegate, |a| a.real == real && a.call_hash == call_hash)?;
+		Ok((real, call))
+	}
+
+	fn find_proxy(
+		&self,
+		real: &T::AccountId,
+		delegate: &T::AccountId,
+		force_proxy_type: Option<T::ProxyType>,
+	) -> Result<&ProxyDefinition<T>, &'static str> {
+		self.proxies(real)
+			.iter()
+			.find(|p| &p.delegate == delegate && force_proxy_type.is_none_or(|t| t == p.proxy_type))
+			.ok_or("Not a proxy")
+	}
+
+	fn take_announcement(
+		&mut self,
+		delegate: &T::AccountId,
+		matches: impl Fn(&Announcement<T>) -> bool,
+	) -> Result<Announcement<T>, &'static str> {
+		let pending = self.announcements.get_mut(delegate).ok_or("Announcement not found")?;
+		let pos = pending.iter().position(matches).ok_or("Announcement not found")?;
+
+		let announcement = pending.remove(pos);
+		if pending.is_empty() {
+			self.announcements.remove(delegate);
+		}
+		Ok(announcement)
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{codec::Encode, mock::Test, support::InstanceFilter};
+
+	#[derive(Debug, Clone, Copy, PartialEq)]
+	pub enum ProxyType {
+		Any,
+		TransfersOnly,
+	}
+
+	impl Encode for ProxyType {
+		fn encode_to(&self, dest: &mut Vec<u8>) {
+			dest.push(*self as u8);
+		}
+	}
+
+	impl InstanceFilter<String> for ProxyType {
+		fn filter(&self, call: &String) -> bool {
+			match self {
+				ProxyType::Any => true,
+				ProxyType::TransfersOnly => call.starts_with("transfer"),
+			}
+		}
+	}
+
+	impl super::Config for Test {
+		type RuntimeCall = String;
+		type ProxyType = ProxyType;
+		const MAX_PROXIES: usize = 2;
+		const MAX_PENDING: usize = 2;
+	}
+
+	#[test]
+	fn add_and_remove_proxies() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut proxy: super::Pallet<Test> = super::Pallet::new();
+
+		assert_eq!(proxy.add_proxy(alice.clone(), bob.clone(), ProxyType::Any, 0), Ok(()));
+		assert_eq!(
+			proxy.add_proxy(alice.clone(), bob.clone(), ProxyType::Any, 0),
+			Err("Proxy already exists")
+		);
+		assert_eq!(
+			proxy.add_proxy(alice.clone(), bob.clone(), ProxyType::TransfersOnly, 0),
+			Ok(())
+		);
+		assert_eq!(
+			proxy.add_proxy(alice.clone(), "charlie".to_string(), ProxyType::Any, 0),
+			Err("Too many proxies")
+		);
+		assert_eq!(proxy.proxies(&alice).len(), 2);
+
+		assert_eq!(
+			proxy.remove_proxy(alice.clone(), bob.clone(), ProxyType::Any, 1),
+			Err("Proxy does not exist")
+		);
+		assert_eq!(proxy.remove_proxy(alice.clone(), bob.clone(), ProxyType::Any, 0), Ok(()));
+		assert_eq!(proxy.proxies(&alice).len(), 1);
+	}
+
+	#[test]
+	fn proxy_calls_are_filtered_by_type() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut proxy: super::Pallet<Test> = super::Pallet::new();
+
+		assert_eq!(
+			proxy.proxy(bob.clone(), alice.clone(), None, "transfer".to_string()),
+			Err("Not a proxy")
+		);
+
+		let _ = proxy.add_proxy(alice.clone(), bob.clone(), ProxyType::TransfersOnly, 0);
+		assert_eq!(
+			proxy.proxy(bob.clone(), alice.clone(), None, "transfer".to_string()),
+			Ok((alice.clone(), "transfer".to_string()))
+		);
+		assert_eq!(
+			proxy.proxy(bob.clone(), alice.clone(), None, "create_claim".to_string()),
+			Err("Call filtered by proxy type")
+		);
+		assert_eq!(
+			proxy.proxy(bob.clone(), alice.clone(), Some(ProxyType::Any), "transfer".to_string()),
+			Err("Not a proxy")
+		);
+	}
+
+	#[test]
+	fn delayed_proxy_requires_announcement() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let call = "transfer".to_string();
+		let call_hash = super::Pallet::<Test>::call_hash(&call);
+		let mut proxy: super::Pallet<Test> = super::Pallet::new();
+		let _ = proxy.add_proxy(alice.clone(), bob.clone(), ProxyType::Any, 5);
+
+		assert_eq!(
+			proxy.proxy(bob.clone(), alice.clone(), None, call.clone()),
+			Err("Proxy requires the call to be announced")
+		);
+		assert_eq!(
+			proxy.proxy_announced(10, bob.clone(), alice.clone(), None, call.clone()),
+			Err("Announcement not found")
+		);
+
+		assert_eq!(proxy.announce(10, bob.clone(), alice.clone(), call_hash), Ok(()));
+		assert_eq!(
+			proxy.proxy_announced(14, bob.clone(), alice.clone(), None, call.clone()),
+			Err("Announcement delay has not passed")
+		);
+		assert_eq!(
+			proxy.proxy_announced(15, bob.clone(), alice.clone(), None, call.clone()),
+			Ok((alice.clone(), call.clone()))
+		);
+		assert!(proxy.announcements(&bob).is_empty());
+	}
+
+	#[test]
+	fn announcements_can_be_removed_or_rejected() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let call_hash = super::Pallet::<Test>::call_hash(&"transfer".to_string());
+		let mut proxy: super::Pallet<Test> = super::Pallet::new();
+
+		assert_eq!(proxy.announce(1, bob.clone(), alice.clone(), call_hash), Err("Not a proxy"));
+
+		let _ = proxy.add_proxy(alice.clone(), bob.clone(), ProxyType::Any, 5);
+		let _ = proxy.announce(1, bob.clone(), alice.clone(), call_hash);
+		let _ = proxy.announce(1, bob.clone(), alice.clone(), call_hash);
+		assert_eq!(
+			proxy.announce(1, bob.clone(), alice.clone(), call_hash),
+			Err("Too many pending announcements")
+		);
+
+		assert_eq!(proxy.remove_announcement(bob.clone(), alice.clone(), call_hash), Ok(()));
+		assert_eq!(
+			proxy.reject_announcement(bob.clone(), bob.clone(), call_hash),
+			Err("Announcement not found")
+		);
+		assert_eq!(proxy.reject_announcement(alice.clone(), bob.clone(), call_hash), Ok(()));
+		assert!(proxy.announcements(&bob).is_empty());
+	}
+}
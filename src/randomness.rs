@@ -0,0 +1,136 @@
+use std::collections::VecDeque;
+
+use num::Zero;
+
+use crate::{
+	codec::Encode,
+	hashing::{self, Hash},
+	metadata::{storage, PalletMetadata},
+	migrations::{self, StorageVersion},
+	support, system,
+	trie::{self, Storage},
+};
+
+pub trait Config: system::Config {
+	/// The number of past block hashes mixed into the random values.
+	const RANDOM_MATERIAL_LEN: usize;
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+/// Pseudo-random values from a hash chain over the most recent block hashes. They are cheap
+/// but weak: the author of a block can influence them, so high-stakes users should wait for
+/// a VRF based source behind the same `Randomness` trait.
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	/// The oldest hash comes first.
+	random_material: VecDeque<Hash>,
+	/// The block the latest hash is from.
+	last_block: T::BlockNumber,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self { random_material: VecDeque::new(), last_block: T::BlockNumber::zero() }
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Randomness",
+			index: None,
+			calls: Vec::new(),
+			storage: vec![
+				storage::<VecDeque<Hash>>("random_material"),
+				storage::<T::BlockNumber>("last_block"),
+			],
+			events: Vec::new(),
+			errors: Vec::new(),
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Randomness", STORAGE_VERSION);
+		trie::insert_value(
+			storage,
+			"Randomness",
+			"random_material",
+			&self.random_material.iter().collect::<Vec<_>>(),
+		);
+		trie::insert_value(storage, "Randomness", "last_block", &self.last_block);
+	}
+
+	/// Restore the random material `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> support::DispatchResult {
+		migrations::ensure_version(storage, "Randomness", STORAGE_VERSION)?;
+		let material: Option<Vec<Hash>> =
+			trie::decode_value(storage, "Randomness", "random_material")?;
+		self.random_material = material.unwrap_or_default().into();
+		trie::restore_value(storage, "Randomness", "last_block", &mut self.last_block)?;
+		Ok(())
+	}
+
+	/// Called at the end of every block with the hash of its parent, so later blocks use it as
+	/// entropy. The hash of the block itself commits to its state root, which includes this.
+	pub fn on_finalize(&mut self, parent: T::BlockNumber, parent_hash: Hash) {
+		if self.random_material.len() >= T::RANDOM_MATERIAL_LEN {
+			self.random_material.pop_front();
+		}
+		self.random_material.push_back(parent_hash);
+		self.last_block = parent;
+	}
+
+	/// The seed of the current block, shared by everything that doesn't need its own subject.
+	pub fn random_seed(&self) -> (Hash, T::BlockNumber) {
+		support::Randomness::random(self, b"")
+	}
+}
+
+impl<T: Config> support::Randomness<Hash, T::BlockNumber> for Pallet<T> {
+	fn random(&self, subject: &[u8]) -> (Hash, T::BlockNumber) {
+		let material = (subject, &self.random_material.iter().collect::<Vec<_>>()).encode();
+		(hashing::blake2_256(&material), self.last_block)
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{mock::Test, support::Randomness};
+
+	impl super::Config for Test {
+		const RANDOM_MATERIAL_LEN: usize = 2;
+	}
+
+	#[test]
+	fn random_values_change_every_block() {
+		let mut randomness: super::Pallet<Test> = super::Pallet::new();
+
+		let (genesis, known_since) = randomness.random_seed();
+		assert_eq!(known_since, 0);
+
+		randomness.on_finalize(1, [1; 32]);
+		let (first, known_since) = randomness.random_seed();
+		assert_eq!(known_since, 1);
+		assert_ne!(first, genesis);
+		assert_eq!(randomness.random_seed().0, first);
+		assert_ne!(randomness.random(b"lottery").0, first);
+	}
+
+	#[test]
+	fn only_recent_hashes_are_used() {
+		let mut short: super::Pallet<Test> = super::Pallet::new();
+		let mut long: super::Pallet<Test> = super::Pallet::new();
+
+		short.on_finalize(1, [2; 32]);
+		short.on_finalize(2, [3; 32]);
+		for (n, hash) in [[1; 32], [2; 32], [3; 32]].into_iter().enumerate() {
+			long.on_finalize(n as u32, hash);
+		}
+		assert_eq!(short.random(b"subject").0, long.random(b"subject").0);
+	}
+}
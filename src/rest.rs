@@ -0,0 +1,475 @@
+//! A small HTTP API answering common queries with JSON, for clients that don't speak JSON-RPC:
+//!
+//! - `GET /balance/{account}`: the free and reserved balance of an account, by SS58 address.
+//! - `GET /nonce/{account}`: the nonce the next extrinsic of an account is signed at, after the
+//!   ones of the account in the transaction pool.
+//! - `GET /genesis`: the genesis hash, which signatures commit to besides the nonce.
+//! - `GET /best`: the number of the best block and its hash, which the signatures of mortal
+//!   extrinsics born at it commit to: the genesis hash before the first block.
+//! - `GET /finalized`: the number of the last finalized block and its hash, the genesis hash
+//!   before the first block is finalized.
+//! - `GET /claim/{hash}`: the claim stored under a hex-encoded content hash.
+//! - `GET /block/{number}`: the header of a block of the best chain, with the names of the calls
+//!   of its extrinsics, and its events if the node kept the state after it or indexed the
+//!   block. The extrinsics that
+//!   failed are listed with their error as `[pallet index, error index]`, or `null` if it isn't
+//!   a pallet error, which `RuntimeMetadata::error_name` decodes.
+//! - `POST /extrinsic`: submit an extrinsic, hex-encoded in the body, to the transaction pool
+//!   of the node, in the envelope of Substrate or as this crate encodes it, see
+//!   `Runtime::decode_extrinsic`. It is checked with `Runtime::validate_extrinsic` first.
+//! - `GET /metadata`: the hex-encoded metadata V14 of the runtime, see the `metadata` module.
+//! - `GET /transfers/{account}`: the transfers from or to an account, oldest first, and
+//! - `GET /claims/{account}`: the claims an account owns, as the `indexer` module indexed them.
+//! - `GET /metrics`: the node metrics for Prometheus, see the `metrics` module.
+//!
+//! Requests are served one at a time, and connections are closed after each response. Clients
+//! that prefer typed calls use the gRPC service of the `grpc` module instead.
+
+use std::{
+	io::{BufRead, BufReader, Read, Write},
+	net::{TcpListener, TcpStream},
+};
+
+use crate::{
+	chain::Chain,
+	hashing, import_queue,
+	indexer::Indexer,
+	json::{self, ToJson, Value},
+	logging,
+	metrics::Metrics,
+	support::DispatchError,
+	system,
+	transaction_pool::TransactionPool,
+	types, Runtime, RuntimeEvent,
+};
+
+/// The largest request body that is read, which is plenty for an extrinsic.
+const MAX_BODY_LEN: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+	pub status: u16,
+	pub content_type: &'static str,
+	pub body: String,
+}
+
+impl Response {
+	fn ok(body: Value) -> Self {
+		Self { status: 200, content_type: "application/json", body: body.to_string() }
+	}
+
+	fn error(status: u16, message: &str) -> Self {
+		let body = json::object([("error", message.to_json())]).to_string();
+		Self { status, content_type: "application/json", body }
+	}
+
+	fn reason(&self) -> &'static str {
+		match self.status {
+			200 => "OK",
+			202 => "Accepted",
+			400 => "Bad Request",
+			404 => "Not Found",
+			405 => "Method Not Allowed",
+			_ => "Error",
+		}
+	}
+}
+
+/// Answer the request for `path` on the best state of `chain`, and the history of `indexer`.
+/// Submitted extrinsics are added to `pool`.
+pub fn handle(
+	chain: &Chain,
+	metrics: &Metrics,
+	indexer: &Indexer,
+	pool: &mut TransactionPool,
+	method: &str,
+	path: &str,
+	body: &str,
+) -> Response {
+	let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+	match (method, segments.as_slice()) {
+		("GET", ["metrics"]) => Response {
+			status: 200,
+			content_type: "text/plain; version=0.0.4",
+			body: metrics.render(chain),
+		},
+		("GET", ["balance", account]) => {
+			let Ok(account) = account.parse::<types::AccountId>() else {
+				return Response::error(400, "Invalid account");
+			};
+			let balances = &chain.state().balances;
+			Response::ok(json::object([
+				("account", account.to_json()),
+				("free", balances.balance(&account).to_json()),
+				("reserved", balances.reserved_balance(&account).to_json()),
+			]))
+		},
+		("GET", ["nonce", account]) => {
+			let Ok(account) = account.parse::<types::AccountId>() else {
+				return Response::error(400, "Invalid account");
+			};
+			let nonce = pool.next_nonce(chain.state(), &account);
+			Response::ok(json::object([("account", account.to_json()), ("nonce", nonce.to_json())]))
+		},
+		("GET", ["genesis"]) => {
+			Response::ok(json::object([("hash", chain.state().genesis_hash().to_json())]))
+		},
+		("GET", ["best"]) => {
+			let number = chain.best_number();
+			let hash = chain.state().recent_block_hash(number).expect("The best block is recent");
+			Response::ok(json::object([("number", number.to_json()), ("hash", hash.to_json())]))
+		},
+		("GET", ["finalized"]) => {
+			let mut hash = chain.finalized_head();
+			if hash == import_queue::GENESIS_PARENT_HASH {
+				hash = chain.state().genesis_hash();
+			}
+			let number = chain.finalized_number();
+			Response::ok(json::object([("number", number.to_json()), ("hash", hash.to_json())]))
+		},
+		("GET", ["metadata"]) => {
+			let metadata = format!("0x{}", hashing::hex(&Runtime::metadata_v14()));
+			Response::ok(json::object([("metadata", metadata.to_json())]))
+		},
+		("GET", ["claim", hash]) => {
+			let Some(hash) = hashing::from_hex(hash).and_then(|hash| hash.try_into().ok()) else {
+				return Response::error(400, "Invalid claim hash");
+			};
+			match chain.state().proof_of_existence.get_claim_details_by_hash(&hash) {
+				Some(details) => Response::ok(json::object([
+					("owner", details.owner.to_json()),
+					("block_number", details.block_number.to_json()),
+					("deposit", details.deposit.to_json()),
+				])),
+				None => Response::error(404, "Claim not found"),
+			}
+		},
+		("GET", ["block", number]) => {
+			let Ok(number) = number.parse::<types::BlockNumber>() else {
+				return Response::error(400, "Invalid block number");
+			};
+			match chain.best_chain_blocks(number, number).first() {
+				Some(block) => {
+					let (header, hash) = (&block.header, crate::chain::block_hash(block));
+					let calls: Vec<_> =
+						block.extrinsics.iter().map(|e| Runtime::call_name(&e.call)).collect();
+					let indexed = indexer.blocks(number, number).any(|block| block.hash == hash);
+					let records = match chain.state_at(&hash) {
+						Some(state) => state.system.events(),
+						None if indexed => indexer.events(number),
+						None => &[],
+					};
+					let failed = records.iter().filter_map(|record| match record.event {
+						RuntimeEvent::System(system::Event::ExtrinsicFailed { error }) => {
+							let error = match error {
+								DispatchError::Module(e) => Some([e.index, e.error].to_vec()),
+								DispatchError::Other => None,
+							};
+							let extrinsic = record.extrinsic_index?;
+							Some(json::object([
+								("extrinsic", extrinsic.to_json()),
+								("error", error.to_json()),
+							]))
+						},
+						_ => None,
+					});
+					let events: Vec<_> =
+						records.iter().map(|record| format!("{:?}", record.event)).collect();
+					Response::ok(json::object([
+						("number", header.block_number.to_json()),
+						("hash", hash.to_json()),
+						("parent_hash", header.parent_hash.to_json()),
+						("state_root", header.state_root.to_json()),
+						("author", header.author.to_json()),
+						("extrinsics", block.extrinsics.len().to_json()),
+						("calls", calls.to_json()),
+						("events", events.to_json()),
+						("failed", Value::Array(failed.collect())),
+					]))
+				},
+				None => Response::error(404, "Block not found"),
+			}
+		},
+		("GET", ["transfers", account]) => {
+			let Ok(account) = account.parse::<types::AccountId>() else {
+				return Response::error(400, "Invalid account");
+			};
+			Response::ok(indexer.transfers_of(&account).collect::<Vec<_>>().to_json())
+		},
+		("GET", ["claims", account]) => {
+			let Ok(account) = account.parse::<types::AccountId>() else {
+				return Response::error(400, "Invalid account");
+			};
+			Response::ok(indexer.claims_of(&account).collect::<Vec<_>>().to_json())
+		},
+		("POST", ["extrinsic"]) => {
+			let bytes = hashing::from_hex(body.trim().trim_start_matches("0x"));
+			let Some(Ok(extrinsic)) = bytes.map(|bytes| Runtime::decode_extrinsic(&bytes)) else {
+				return Response::error(400, "Invalid extrinsic");
+			};
+			let hash = match pool.submit(chain.state(), extrinsic) {
+				Ok(hash) => hash,
+				Err(e) => return Response::error(400, &format!("Invalid transaction: {:?}", e)),
+			};
+			Response {
+				status: 202,
+				content_type: "application/json",
+				body: json::object([("hash", hash.to_json())]).to_string(),
+			}
+		},
+		(
+			_,
+			["balance" | "nonce" | "claim" | "block" | "transfers" | "claims", _]
+			| ["extrinsic" | "genesis" | "best" | "finalized" | "metadata" | "metrics"],
+		) => Response::error(405, "Method not allowed"),
+		_ => Response::error(404, "Not found"),
+	}
+}
+
+/// Read one request from `stream` and write the response.
+pub fn handle_connection(
+	chain: &Chain,
+	metrics: &Metrics,
+	indexer: &Indexer,
+	pool: &mut TransactionPool,
+	stream: TcpStream,
+) -> std::io::Result<()> {
+	let mut reader = BufReader::new(&stream);
+	let mut request_line = String::new();
+	reader.read_line(&mut request_line)?;
+	let mut content_length = 0;
+	loop {
+		let mut header = String::new();
+		if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+			break;
+		}
+		if let Some((name, value)) = header.split_once(':') {
+			if name.eq_ignore_ascii_case("content-length") {
+				content_length = value.trim().parse().unwrap_or(0);
+			}
+		}
+	}
+	let mut body = Vec::new();
+	reader.take(content_length.min(MAX_BODY_LEN) as u64).read_to_end(&mut body)?;
+
+	let mut parts = request_line.split_whitespace();
+	let response = match (parts.next(), parts.next(), String::from_utf8(body)) {
+		(Some(method), Some(path), Ok(body)) => {
+			handle(chain, metrics, indexer, pool, method, path, &body)
+		},
+		_ => Response::error(400, "Bad request"),
+	};
+	write!(
+		&stream,
+		"HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+		response.status,
+		response.reason(),
+		response.content_type,
+		response.body.len(),
+		response.body
+	)
+}
+
+/// Serve requests on `listener` until it fails.
+pub fn serve(
+	chain: &Chain,
+	metrics: &Metrics,
+	indexer: &Indexer,
+	pool: &mut TransactionPool,
+	listener: TcpListener,
+) -> std::io::Result<()> {
+	for stream in listener.incoming() {
+		if let Err(e) = handle_connection(chain, metrics, indexer, pool, stream?) {
+			logging::warn("rest", "Request failed", &[("error", &e)]);
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use std::{
+		io::{Read, Write},
+		net::{TcpListener, TcpStream},
+	};
+
+	use crate::{
+		balances,
+		chain::Chain,
+		codec::Encode,
+		genesis::{self, GenesisBuilder},
+		hashing,
+		indexer::Indexer,
+		json,
+		metrics::Metrics,
+		proof_of_existence,
+		transaction_pool::TransactionPool,
+		wallet::{self, dev_account, dev_signer},
+		Runtime, RuntimeCall,
+	};
+
+	fn chain() -> Chain {
+		let mut genesis = Runtime::new();
+		genesis.balances.set_balance(&dev_account("alice"), 100);
+		let _ = genesis.proof_of_existence.create_claim(
+			&mut genesis.balances,
+			dev_account("alice"),
+			"document".to_string(),
+		);
+		Chain::new(genesis)
+	}
+
+	fn handle(chain: &Chain, metrics: &Metrics, method: &str, path: &str) -> super::Response {
+		super::handle(
+			chain,
+			metrics,
+			&Indexer::new(),
+			&mut TransactionPool::new(),
+			method,
+			path,
+			"",
+		)
+	}
+
+	#[test]
+	fn queries_are_answered_with_json() {
+		let (chain, metrics) = (chain(), Metrics::new());
+		let alice = dev_account("alice");
+		let balance = handle(&chain, &metrics, "GET", &format!("/balance/{}", alice));
+		let expected = format!(r#"{{"account":"{}","free":99,"reserved":1}}"#, alice);
+		assert_eq!(balance.body, expected);
+		let hash = proof_of_existence::Pallet::<Runtime>::hash_content(&"document".to_string());
+		let claim = handle(&chain, &metrics, "GET", &format!("/claim/{}", hashing::hex(&hash)));
+		let expected = format!(r#"{{"owner":"{}","block_number":0,"deposit":1}}"#, alice);
+		assert_eq!(claim.body, expected);
+
+		assert_eq!(handle(&chain, &metrics, "GET", "/claim/zz").status, 400);
+		let mistyped = handle(&chain, &metrics, "GET", "/balance/alice");
+		assert_eq!(mistyped.body, r#"{"error":"Invalid account"}"#);
+		assert_eq!(handle(&chain, &metrics, "GET", "/block/1").status, 404);
+		let path = format!("/balance/{}", alice);
+		assert_eq!(handle(&chain, &metrics, "POST", &path).status, 405);
+		assert_eq!(handle(&chain, &metrics, "GET", "/extrinsic").status, 405);
+		let rendered = handle(&chain, &metrics, "GET", "/metrics");
+		assert!(rendered.body.contains("node_best_block 0"));
+	}
+
+	#[test]
+	fn valid_extrinsics_are_added_to_the_pool() {
+		let (chain, metrics, mut pool) = (chain(), Metrics::new(), TransactionPool::new());
+		let genesis_hash = chain.state().genesis_hash();
+		let transfer = |caller: &str| {
+			let call = RuntimeCall::Balances(balances::Call::Transfer {
+				to: dev_account("bob").into(),
+				amount: 5,
+			});
+			dev_signer(caller).sign(call, 0, genesis_hash)
+		};
+		let mut submit = |body: &str| {
+			super::handle(&chain, &metrics, &Indexer::new(), &mut pool, "POST", "/extrinsic", body)
+		};
+
+		assert_eq!(submit(&hashing::hex(&transfer("alice").encode())).status, 202);
+		let unfunded = submit(&hashing::hex(&transfer("bob").encode()));
+		assert_eq!(unfunded.body, r#"{"error":"Invalid transaction: Payment"}"#);
+		let mut forged = transfer("bob");
+		forged.caller = Some(dev_account("alice"));
+		let forged = submit(&hashing::hex(&forged.encode()));
+		assert_eq!(forged.body, r#"{"error":"Invalid transaction: BadProof"}"#);
+		assert_eq!(submit("zz").status, 400);
+		assert_eq!(pool.len(), 1);
+	}
+
+	#[test]
+	fn nonces_count_the_extrinsics_in_the_pool() {
+		let alice = dev_account("alice");
+		let mut genesis = Runtime::new();
+		genesis.balances.set_balance(&alice, 100);
+		genesis.system.inc_nonce(&alice).unwrap();
+		let (chain, metrics, mut pool) =
+			(Chain::new(genesis), Metrics::new(), TransactionPool::new());
+		let genesis_hash = chain.state().genesis_hash();
+		let transfer = |nonce| {
+			let call = RuntimeCall::Balances(balances::Call::Transfer {
+				to: dev_account("bob").into(),
+				amount: 5,
+			});
+			hashing::hex(&dev_signer("alice").sign(call, nonce, genesis_hash).encode())
+		};
+		let mut request = |method, path: &str, body: &str| {
+			super::handle(&chain, &metrics, &Indexer::new(), &mut pool, method, path, body)
+		};
+
+		let path = format!("/nonce/{}", alice);
+		let nonce = |nonce| format!(r#"{{"account":"{}","nonce":{}}}"#, alice, nonce);
+		assert_eq!(request("GET", &path, "").body, nonce(1));
+		let stale = request("POST", "/extrinsic", &transfer(0));
+		assert_eq!(stale.body, r#"{"error":"Invalid transaction: Stale"}"#);
+		assert_eq!(request("POST", "/extrinsic", &transfer(1)).status, 202);
+		assert_eq!(request("GET", &path, "").body, nonce(2));
+		// Later nonces wait in the pool, but the next one is still missing.
+		assert_eq!(request("POST", "/extrinsic", &transfer(3)).status, 202);
+		assert_eq!(request("GET", &path, "").body, nonce(2));
+
+		let expected = format!(r#"{{"hash":"{}"}}"#, hashing::hex(&genesis_hash));
+		assert_eq!(request("GET", "/genesis", "").body, expected);
+		// Before the first block, mortal extrinsics are born at genesis.
+		let best = format!(r#"{{"number":0,"hash":"{}"}}"#, hashing::hex(&genesis_hash));
+		assert_eq!(request("GET", "/best", "").body, best);
+		assert_eq!(request("GET", "/finalized", "").body, best);
+	}
+
+	#[test]
+	fn history_is_answered_from_the_indexer() {
+		let alice = dev_account("alice");
+		let state = GenesisBuilder::new().endow(&alice, 1_000).authority(&alice).build().unwrap();
+		let mut chain = Chain::new(state.clone());
+		let transfer = RuntimeCall::Balances(balances::Call::Transfer {
+			to: dev_account("bob").into(),
+			amount: 50,
+		});
+		let calls = [transfer, wallet::create_claim("document".into())];
+		let extrinsics = genesis::signed(&state, "alice", calls);
+		chain
+			.import(genesis::block(&state, "alice", extrinsics).unwrap(), 100_000)
+			.unwrap();
+		let mut indexer = Indexer::new();
+		indexer.follow(&chain);
+		let request = |path: &str| {
+			let pool = &mut TransactionPool::new();
+			super::handle(&chain, &Metrics::new(), &indexer, pool, "GET", path, "")
+		};
+
+		let transfers = json::parse(&request(&format!("/transfers/{}", alice)).body).unwrap();
+		let transfers = transfers.as_array().unwrap();
+		assert_eq!(transfers.len(), 1);
+		assert_eq!(transfers[0].get("amount").and_then(json::Value::as_number), Some(50u128));
+		let claims = json::parse(&request(&format!("/claims/{}", alice)).body).unwrap();
+		assert_eq!(claims.as_array().map(<[_]>::len), Some(1));
+		assert_eq!(request("/claims/alice").status, 400);
+	}
+
+	#[test]
+	fn requests_are_served_over_http() {
+		let chain = chain();
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let bob = dev_account("bob");
+		let request = format!("GET /balance/{} HTTP/1.1\r\nHost: localhost\r\n\r\n", bob);
+		client.write_all(request.as_bytes()).unwrap();
+		let (server, _) = listener.accept().unwrap();
+		super::handle_connection(
+			&chain,
+			&Metrics::new(),
+			&Indexer::new(),
+			&mut TransactionPool::new(),
+			server,
+		)
+		.unwrap();
+
+		let mut response = String::new();
+		client.read_to_string(&mut response).unwrap();
+		assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+		assert!(response.ends_with(&format!(r#"{{"account":"{}","free":0,"reserved":0}}"#, bob)));
+	}
+}
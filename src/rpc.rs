@@ -0,0 +1,557 @@
+//! Subscriptions for clients that follow the chain, e.g. explorers, instead of polling it:
+//! the new best heads, the finalized heads, and the events of each new best block. The node
+//! calls `notify` after it imported or finalized blocks.
+//!
+//! Event subscriptions are filtered by the node, so e.g. a wallet only receives the transfers of
+//! its own accounts rather than every event of every block. What subscribers receive is sent to
+//! clients as JSON-RPC notifications, see `notification`, by the `Server` of the WebSocket
+//! endpoint of `run --ws`. Its clients call
+//!
+//! - `chain_subscribeNewHeads`, notified with `chain_newHead`,
+//! - `chain_subscribeFinalizedHeads`, notified with `chain_finalizedHead`,
+//! - `chain_subscribeEvents`, notified with `chain_events`, with an optional filter object of
+//!   `pallet`, `name` and `account` as its parameter,
+//!
+//! which answer with the id of the subscription, and `chain_unsubscribe` with that id. They
+//! also call `state_getMetadata`, answered with the hex-encoded metadata V14 of the runtime as
+//! in Substrate, to build extrinsics.
+
+use std::{
+	collections::BTreeMap,
+	io::{self, Read, Write},
+	net::TcpStream,
+	sync::mpsc::{self, Receiver, Sender},
+};
+
+use crate::{
+	chain::Chain,
+	hashing::{self, Hash},
+	json::{self, ToJson, Value},
+	system, types,
+	websocket::{self, Frame},
+	Runtime,
+};
+
+pub type SubscriptionId = u64;
+pub type EventRecord = system::EventRecord<Runtime>;
+
+pub struct Subscriptions {
+	next_id: SubscriptionId,
+	new_heads: BTreeMap<SubscriptionId, Sender<types::Header>>,
+	finalized: BTreeMap<SubscriptionId, Sender<types::Header>>,
+	events: BTreeMap<SubscriptionId, (Sender<Vec<EventRecord>>, EventFilter)>,
+	/// The heads of the last notification.
+	best: Hash,
+	finalized_head: Hash,
+}
+
+/// The events a subscriber receives: those matching every criterion that is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventFilter {
+	/// The pallet that emitted the event, e.g. `ProofOfExistence`.
+	pub pallet: Option<String>,
+	/// The variant of the event, e.g. `ClaimTransferred`.
+	pub name: Option<String>,
+	/// An account the event is about.
+	pub account: Option<types::AccountId>,
+}
+
+impl EventFilter {
+	pub fn matches(&self, record: &EventRecord) -> bool {
+		let event = &record.event;
+		self.pallet.as_ref().is_none_or(|pallet| pallet == event.pallet())
+			&& self.name.as_ref().is_none_or(|name| name == event.name())
+			&& self.account.as_ref().is_none_or(|account| event.accounts().contains(&account))
+	}
+}
+
+/// The JSON-RPC notification of `method`, e.g. `chain_newHead`, with `result` for the
+/// subscription `id`.
+pub fn notification(method: &str, id: SubscriptionId, result: &impl ToJson) -> String {
+	let params = json::object([("subscription", id.to_json()), ("result", result.to_json())]);
+	json::object([("jsonrpc", "2.0".to_json()), ("method", method.to_json()), ("params", params)])
+		.to_string()
+}
+
+/// Send `value` to each subscriber, dropping the ones that went away.
+fn send_all<T: Clone>(subscribers: &mut BTreeMap<SubscriptionId, Sender<T>>, value: T) {
+	subscribers.retain(|_, subscriber| subscriber.send(value.clone()).is_ok());
+}
+
+impl Subscriptions {
+	/// Subscriptions to `chain`, notifying what changes after now.
+	pub fn new(chain: &Chain) -> Self {
+		Self {
+			next_id: 0,
+			new_heads: BTreeMap::new(),
+			finalized: BTreeMap::new(),
+			events: BTreeMap::new(),
+			best: chain.best_hash(),
+			finalized_head: chain.finalized_head(),
+		}
+	}
+
+	fn subscribe<T>(
+		next_id: &mut SubscriptionId,
+		subscribers: &mut BTreeMap<SubscriptionId, Sender<T>>,
+	) -> (SubscriptionId, Receiver<T>) {
+		let (sender, receiver) = mpsc::channel();
+		let id = *next_id;
+		*next_id += 1;
+		subscribers.insert(id, sender);
+		(id, receiver)
+	}
+
+	pub fn subscribe_new_heads(&mut self) -> (SubscriptionId, Receiver<types::Header>) {
+		Self::subscribe(&mut self.next_id, &mut self.new_heads)
+	}
+
+	pub fn subscribe_finalized(&mut self) -> (SubscriptionId, Receiver<types::Header>) {
+		Self::subscribe(&mut self.next_id, &mut self.finalized)
+	}
+
+	/// The events of each new best block that match `filter`, possibly none.
+	pub fn subscribe_events(
+		&mut self,
+		filter: EventFilter,
+	) -> (SubscriptionId, Receiver<Vec<EventRecord>>) {
+		let (sender, receiver) = mpsc::channel();
+		let id = self.next_id;
+		self.next_id += 1;
+		self.events.insert(id, (sender, filter));
+		(id, receiver)
+	}
+
+	/// Returns whether there was such a subscription.
+	pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+		self.new_heads.remove(&id).is_some()
+			|| self.finalized.remove(&id).is_some()
+			|| self.events.remove(&id).is_some()
+	}
+
+	/// Notify the subscribers of each block that became best or was finalized since the last
+	/// call, in order. After a reorganization, the blocks of the new best chain after the one it
+	/// shares with the last notified best chain are notified. Events are notified for the blocks
+	/// whose state the chain still keeps.
+	pub fn notify(&mut self, chain: &Chain) {
+		for (hash, block) in new_blocks(chain, self.best, self.finalized_head, chain.best_hash()) {
+			send_all(&mut self.new_heads, block.header.clone());
+			let Some(state) = chain.state_at(&hash) else {
+				continue;
+			};
+			let events = state.system.events();
+			self.events.retain(|_, (subscriber, filter)| {
+				let matching = events.iter().filter(|record| filter.matches(record));
+				subscriber.send(matching.cloned().collect()).is_ok()
+			});
+		}
+		let finalized = chain.finalized_head();
+		for (_, block) in new_blocks(chain, self.finalized_head, self.finalized_head, finalized) {
+			send_all(&mut self.finalized, block.header.clone());
+		}
+		(self.best, self.finalized_head) = (chain.best_hash(), finalized);
+	}
+}
+
+/// The blocks up to `hash` after the last one it shares with the chain of `notified`, or after
+/// `finalized`, which both contain, in order. `notified` may have been pruned since.
+fn new_blocks(
+	chain: &Chain,
+	mut notified: Hash,
+	finalized: Hash,
+	mut hash: Hash,
+) -> Vec<(Hash, &types::Block)> {
+	let mut blocks = Vec::new();
+	while let Some(block) = chain.block(&hash).filter(|_| hash != finalized) {
+		while let Some(old) = chain.block(&notified) {
+			if old.header.block_number <= block.header.block_number {
+				break;
+			}
+			notified = old.header.parent_hash;
+		}
+		if hash == notified {
+			break;
+		}
+		blocks.push((hash, block));
+		hash = block.header.parent_hash;
+	}
+	blocks.reverse();
+	blocks
+}
+
+/// A subscription of a client of the `Server`, with what it was notified of since the last
+/// poll.
+enum Subscription {
+	NewHeads(Receiver<types::Header>),
+	Finalized(Receiver<types::Header>),
+	Events(Receiver<Vec<EventRecord>>),
+}
+
+impl Subscription {
+	/// The notifications to send the client of the subscription `id`.
+	fn notifications(&self, id: SubscriptionId) -> Vec<String> {
+		match self {
+			Subscription::NewHeads(heads) => {
+				heads.try_iter().map(|head| notification("chain_newHead", id, &head)).collect()
+			},
+			Subscription::Finalized(heads) => heads
+				.try_iter()
+				.map(|head| notification("chain_finalizedHead", id, &head))
+				.collect(),
+			Subscription::Events(events) => events
+				.try_iter()
+				.map(|events| notification("chain_events", id, &events))
+				.collect(),
+		}
+	}
+}
+
+struct Client {
+	stream: TcpStream,
+	/// What the client sent that isn't a whole frame yet.
+	received: Vec<u8>,
+	subscriptions: BTreeMap<SubscriptionId, Subscription>,
+}
+
+impl Client {
+	/// Send `frame`, waiting for it to be written whole, as the connection is only nonblocking
+	/// for reads.
+	fn send(&mut self, frame: &Frame) -> io::Result<()> {
+		self.stream.set_nonblocking(false)?;
+		self.stream.write_all(&frame.encode(None))?;
+		self.stream.set_nonblocking(true)
+	}
+
+	/// Read what the client sent since the last call, and answer the frames received whole.
+	/// Returns whether the connection is still open.
+	fn poll(&mut self, subscriptions: &mut Subscriptions) -> io::Result<bool> {
+		let mut buffer = [0; 4_096];
+		loop {
+			match self.stream.read(&mut buffer) {
+				Ok(0) => return Ok(false),
+				Ok(len) => self.received.extend(&buffer[..len]),
+				Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+				Err(e) => return Err(e),
+			}
+		}
+		while let Some((frame, len)) = Frame::parse(&self.received)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+		{
+			self.received.drain(..len);
+			match frame.opcode {
+				websocket::TEXT => {
+					let response =
+						self.call(subscriptions, &String::from_utf8_lossy(&frame.payload));
+					self.send(&Frame::text(&response.to_string()))?;
+				},
+				websocket::PING => self.send(&Frame { opcode: websocket::PONG, ..frame })?,
+				websocket::CLOSE => {
+					self.send(&frame)?;
+					return Ok(false);
+				},
+				_ => {},
+			}
+		}
+		Ok(true)
+	}
+
+	/// The response to the JSON-RPC `request`.
+	fn call(&mut self, subscriptions: &mut Subscriptions, request: &str) -> Value {
+		let request = json::parse(request).unwrap_or(Value::Null);
+		let id = request.get("id").cloned().unwrap_or(Value::Null);
+		let params = request.get("params").and_then(Value::as_array).unwrap_or_default();
+		let result = match request.get("method").and_then(Value::as_str) {
+			Some("chain_subscribeNewHeads") => {
+				let (id, heads) = subscriptions.subscribe_new_heads();
+				self.subscriptions.insert(id, Subscription::NewHeads(heads));
+				Ok(id.to_json())
+			},
+			Some("chain_subscribeFinalizedHeads") => {
+				let (id, heads) = subscriptions.subscribe_finalized();
+				self.subscriptions.insert(id, Subscription::Finalized(heads));
+				Ok(id.to_json())
+			},
+			Some("chain_subscribeEvents") => match event_filter(params.first()) {
+				Ok(filter) => {
+					let (id, events) = subscriptions.subscribe_events(filter);
+					self.subscriptions.insert(id, Subscription::Events(events));
+					Ok(id.to_json())
+				},
+				Err(e) => Err((-32602, e)),
+			},
+			Some("chain_unsubscribe") => match params.first().and_then(Value::as_number) {
+				Some(id) => {
+					let own = self.subscriptions.remove(&id).is_some();
+					Ok((own && subscriptions.unsubscribe(id)).to_json())
+				},
+				None => Err((-32602, "Invalid subscription id")),
+			},
+			Some("state_getMetadata") => {
+				Ok(format!("0x{}", hashing::hex(&Runtime::metadata_v14())).to_json())
+			},
+			Some(_) => Err((-32601, "Method not found")),
+			None => Err((-32600, "Invalid request")),
+		};
+		let (name, value) = match result {
+			Ok(result) => ("result", result),
+			Err((code, message)) => (
+				"error",
+				json::object([
+					("code", Value::Number(code.to_string())),
+					("message", message.to_json()),
+				]),
+			),
+		};
+		json::object([("jsonrpc", "2.0".to_json()), ("id", id), (name, value)])
+	}
+}
+
+/// The event filter of a `chain_subscribeEvents` request, every event without one.
+fn event_filter(params: Option<&Value>) -> Result<EventFilter, &'static str> {
+	let Some(params) = params else {
+		return Ok(EventFilter::default());
+	};
+	let field = |name| params.get(name).and_then(Value::as_str).map(str::to_string);
+	let account = match field("account") {
+		Some(account) => Some(account.parse().map_err(|_| "Invalid account")?),
+		None => None,
+	};
+	Ok(EventFilter { pallet: field("pallet"), name: field("name"), account })
+}
+
+/// Serves `Subscriptions` as JSON-RPC over WebSocket connections, see the module doc. The node
+/// polls it between blocks, like its other servers.
+pub struct Server {
+	subscriptions: Subscriptions,
+	clients: Vec<Client>,
+}
+
+impl Server {
+	pub fn new(chain: &Chain) -> Self {
+		Self { subscriptions: Subscriptions::new(chain), clients: Vec::new() }
+	}
+
+	/// Accept the WebSocket handshake of a client on `stream`, then keep serving it.
+	pub fn connect(&mut self, mut stream: TcpStream) -> io::Result<()> {
+		websocket::handshake(&mut stream)?;
+		stream.set_nonblocking(true)?;
+		let client = Client { stream, received: Vec::new(), subscriptions: BTreeMap::new() };
+		self.clients.push(client);
+		Ok(())
+	}
+
+	pub fn client_count(&self) -> usize {
+		self.clients.len()
+	}
+
+	/// Answer the requests of the clients, then send them the notifications of what changed on
+	/// `chain` since the last poll. Clients whose connection closed or failed are dropped, and
+	/// their subscriptions with the next notification.
+	pub fn poll(&mut self, chain: &Chain) {
+		let Server { subscriptions, clients } = self;
+		clients.retain_mut(|client| client.poll(subscriptions).unwrap_or(false));
+		subscriptions.notify(chain);
+		clients.retain_mut(|client| {
+			let notifications: Vec<_> = client
+				.subscriptions
+				.iter()
+				.flat_map(|(id, subscription)| subscription.notifications(*id))
+				.collect();
+			notifications.iter().all(|text| client.send(&Frame::text(text)).is_ok())
+		});
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::{
+		io::{Read, Write},
+		net::{TcpListener, TcpStream},
+		time::Duration,
+	};
+
+	use super::{EventFilter, Server, Subscriptions};
+	use crate::{
+		chain::{self, Chain},
+		genesis::{self, GenesisBuilder},
+		import_queue, json, proof_of_existence, support, types, wallet,
+		wallet::dev_account,
+		websocket::Frame,
+		Runtime, RuntimeCall,
+	};
+
+	/// The block of a timestamp at `now` on `state`, and the state after it.
+	fn block(state: &Runtime, now: u64) -> (types::Block, Runtime) {
+		let now = state.timestamp.create_inherent(now).unwrap();
+		let extrinsics = vec![support::Extrinsic {
+			caller: None,
+			call: RuntimeCall::Timestamp(now),
+			signature: None,
+		}];
+		let block = import_queue::build_block(state, &dev_account("alice"), extrinsics).unwrap();
+		let mut next = state.clone();
+		next.execute_block(block.clone()).unwrap();
+		(block, next)
+	}
+
+	#[test]
+	fn subscribers_are_notified_of_new_heads() {
+		let mut chain = Chain::new(Runtime::new());
+		let mut subscriptions = Subscriptions::new(&chain);
+		let (_, new_heads) = subscriptions.subscribe_new_heads();
+		let (_, finalized) = subscriptions.subscribe_finalized();
+		let (events_id, events) = subscriptions.subscribe_events(EventFilter::default());
+
+		let now = chain.state().timestamp.create_inherent(1_000).unwrap();
+		let extrinsics = vec![support::Extrinsic {
+			caller: None,
+			call: RuntimeCall::Timestamp(now),
+			signature: None,
+		}];
+		let block = import_queue::build_block(chain.state(), &dev_account("alice"), extrinsics);
+		chain.import(block.unwrap(), 1_000).unwrap();
+		subscriptions.notify(&chain);
+		subscriptions.notify(&chain);
+
+		let heads: Vec<_> = new_heads.try_iter().collect();
+		assert_eq!(heads.iter().map(|header| header.block_number).collect::<Vec<_>>(), [1]);
+		let notification = super::notification("chain_newHead", 0, &heads[0]);
+		assert!(notification.starts_with(
+			r#"{"jsonrpc":"2.0","method":"chain_newHead","params":{"subscription":0,"result":{"number":1,"#
+		));
+		assert!(finalized.try_recv().is_err());
+		assert!(events.try_recv().is_ok());
+
+		chain.finalize(chain.best_hash()).unwrap();
+		subscriptions.notify(&chain);
+		assert_eq!(finalized.try_recv().map(|header| header.block_number), Ok(1));
+		assert!(subscriptions.unsubscribe(events_id));
+		assert!(!subscriptions.unsubscribe(events_id));
+	}
+
+	#[test]
+	fn every_new_best_block_is_notified_across_reorganizations() {
+		let mut chain = Chain::new(Runtime::new());
+		let mut subscriptions = Subscriptions::new(&chain);
+		let (_, new_heads) = subscriptions.subscribe_new_heads();
+		let (_, events) = subscriptions.subscribe_events(EventFilter::default());
+		let import = |chain: &mut Chain, state: &Runtime, now| {
+			let (block, next) = block(state, now);
+			let hash = chain::block_hash(&block);
+			chain.import(block, 100_000).unwrap();
+			(hash, next)
+		};
+		let (a1, a1_state) = import(&mut chain, &Runtime::new(), 1_000);
+		let (a2, _) = import(&mut chain, &a1_state, 2_000);
+		subscriptions.notify(&chain);
+		let hashes = |heads: Vec<types::Header>| {
+			heads.iter().map(|head| head.parent_hash).collect::<Vec<_>>()
+		};
+		let genesis = import_queue::GENESIS_PARENT_HASH;
+		assert_eq!(hashes(new_heads.try_iter().collect()), [genesis, a1]);
+		assert_eq!(events.try_iter().count(), 2);
+
+		// A longer fork of a1 becomes the best chain: its blocks after a1 are notified.
+		let (b2, b2_state) = import(&mut chain, &a1_state, 3_000);
+		import(&mut chain, &b2_state, 4_000);
+		subscriptions.notify(&chain);
+		assert_ne!(a2, b2);
+		assert_eq!(hashes(new_heads.try_iter().collect()), [a1, b2]);
+	}
+
+	#[test]
+	fn subscriptions_are_served_over_websocket() {
+		let mut chain = Chain::new(Runtime::new());
+		let mut server = Server::new(&chain);
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		client
+			.write_all(
+				b"GET / HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+				  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+			)
+			.unwrap();
+		server.connect(listener.accept().unwrap().0).unwrap();
+		let mut response = [0; 129];
+		client.read_exact(&mut response).unwrap();
+		assert!(response.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+
+		let subscribe = r#"{"jsonrpc":"2.0","id":1,"method":"chain_subscribeNewHeads"}"#;
+		client.write_all(&Frame::text(subscribe).encode(Some([7, 7, 7, 7]))).unwrap();
+		std::thread::sleep(Duration::from_millis(50));
+		server.poll(&chain);
+		assert_eq!(read_text(&mut client), r#"{"jsonrpc":"2.0","id":1,"result":0}"#);
+		assert_eq!(server.client_count(), 1);
+
+		chain.import(block(chain.state(), 1_000).0, 1_000).unwrap();
+		server.poll(&chain);
+		let notification = json::parse(&read_text(&mut client)).unwrap();
+		assert_eq!(notification.get("method").and_then(json::Value::as_str), Some("chain_newHead"));
+
+		drop(client);
+		std::thread::sleep(Duration::from_millis(50));
+		server.poll(&chain);
+		assert_eq!(server.client_count(), 0);
+	}
+
+	/// The text of the next frame the server sent to `client`.
+	fn read_text(client: &mut TcpStream) -> String {
+		let mut header = [0; 2];
+		client.read_exact(&mut header).unwrap();
+		let mut len = usize::from(header[1]);
+		if len == 126 {
+			let mut extended = [0; 2];
+			client.read_exact(&mut extended).unwrap();
+			len = usize::from(u16::from_be_bytes(extended));
+		}
+		let mut payload = vec![0; len];
+		client.read_exact(&mut payload).unwrap();
+		String::from_utf8(payload).unwrap()
+	}
+
+	#[test]
+	fn events_are_filtered_for_their_subscribers() {
+		let (alice, bob) = (dev_account("alice"), dev_account("bob"));
+		let state = GenesisBuilder::new().endow(&alice, 1_000).authority(&alice).build().unwrap();
+		let mut chain = Chain::new(state);
+		let mut subscriptions = Subscriptions::new(&chain);
+		let filter = |pallet: &str, name: &str, account: Option<_>| EventFilter {
+			pallet: Some(pallet.to_string()),
+			name: Some(name.to_string()),
+			account,
+		};
+		let (_, bobs) = subscriptions.subscribe_events(filter(
+			"ProofOfExistence",
+			"ClaimTransferred",
+			Some(bob.clone()),
+		));
+		let (_, charlies) = subscriptions.subscribe_events(EventFilter {
+			account: Some(dev_account("charlie")),
+			..EventFilter::default()
+		});
+		let (_, attested) =
+			subscriptions.subscribe_events(filter("ProofOfExistence", "ClaimAttested", None));
+
+		let handover =
+			proof_of_existence::Call::TransferClaim { claim: "document".into(), to: bob };
+		let calls =
+			[wallet::create_claim("document".into()), RuntimeCall::ProofOfExistence(handover)];
+		let extrinsics = genesis::signed(chain.state(), "alice", calls);
+		chain
+			.import(genesis::block(chain.state(), "alice", extrinsics).unwrap(), 1_000)
+			.unwrap();
+		subscriptions.notify(&chain);
+
+		let transfers = bobs.try_recv().unwrap();
+		assert_eq!(transfers.len(), 1);
+		assert_eq!(transfers[0].event.accounts(), [&alice, &dev_account("bob")]);
+		let notification =
+			json::parse(&super::notification("chain_events", 1, &transfers)).unwrap();
+		let result = notification.get("params").and_then(|params| params.get("result"));
+		let transfer = &result.and_then(json::Value::as_array).unwrap()[0];
+		assert_eq!(transfer.get("name").and_then(json::Value::as_str), Some("ClaimTransferred"));
+		assert!(charlies.try_recv().unwrap().is_empty());
+		assert!(attested.try_recv().unwrap().is_empty());
+	}
+}
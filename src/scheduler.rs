@@ -0,0 +1,374 @@
+use core::fmt::Debug;
+use std::collections::BTreeMap;
+
+use num::Zero;
+
+use crate::{
+	codec::{Decode, Encode},
+	metadata::{field, storage, variant, PalletMetadata},
+	migrations::{self, StorageVersion},
+	support::{self, DispatchResult, Weight},
+	system::{self, OriginFor},
+	trie::{self, Storage},
+};
+
+pub trait Config: system::Config {
+	/// The aggregated call type that can be scheduled.
+	type RuntimeCall: Debug + Clone + Encode;
+	/// The identifier of a named task.
+	type TaskName: Debug + Ord + Clone + Encode;
+	/// The maximum number of tasks that can be scheduled for a single block.
+	const MAX_SCHEDULED_PER_BLOCK: usize;
+}
+
+#[derive(Debug, Clone)]
+pub enum Call<T: Config> {
+	Schedule { when: T::BlockNumber, call: Box<T::RuntimeCall> },
+	Cancel { when: T::BlockNumber, index: u32 },
+	ScheduleNamed { id: T::TaskName, when: T::BlockNumber, call: Box<T::RuntimeCall> },
+	CancelNamed { id: T::TaskName },
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::Schedule { when, call } => (0u8, when, call).encode_to(dest),
+			Call::Cancel { when, index } => (1u8, when, index).encode_to(dest),
+			Call::ScheduleNamed { id, when, call } => (2u8, id, when, call).encode_to(dest),
+			Call::CancelNamed { id } => (3u8, id).encode_to(dest),
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T>
+where
+	T::RuntimeCall: Decode,
+	T::TaskName: Decode,
+{
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::Schedule { when: Decode::decode(input)?, call: Decode::decode(input)? },
+			1 => Call::Cancel { when: Decode::decode(input)?, index: Decode::decode(input)? },
+			2 => Call::ScheduleNamed {
+				id: Decode::decode(input)?,
+				when: Decode::decode(input)?,
+				call: Decode::decode(input)?,
+			},
+			3 => Call::CancelNamed { id: Decode::decode(input)? },
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::Schedule { .. } => 0,
+			Call::Cancel { .. } => 1,
+			Call::ScheduleNamed { .. } => 2,
+			Call::CancelNamed { .. } => 3,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::Schedule { .. } | Call::ScheduleNamed { .. } => 5_000,
+			Call::Cancel { .. } | Call::CancelNamed { .. } => 5_000,
+		}
+	}
+}
+
+/// A call waiting in the agenda, dispatched on behalf of the account that scheduled it.
+#[derive(Debug, Clone)]
+pub struct Scheduled<T: Config> {
+	pub owner: T::AccountId,
+	pub call: T::RuntimeCall,
+	pub name: Option<T::TaskName>,
+}
+
+impl<T: Config> Encode for Scheduled<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.owner, &self.call, &self.name).encode_to(dest);
+	}
+}
+
+impl<T: Config> Decode for Scheduled<T>
+where
+	T::RuntimeCall: Decode,
+	T::TaskName: Decode,
+{
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (owner, call, name) = Decode::decode(input)?;
+		Ok(Scheduled { owner, call, name })
+	}
+}
+
+/// The block number and agenda index a task lives at.
+pub type TaskAddress<BlockNumber> = (BlockNumber, u32);
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	now: T::BlockNumber,
+	agenda: BTreeMap<T::BlockNumber, Vec<Option<Scheduled<T>>>>,
+	lookup: BTreeMap<T::TaskName, TaskAddress<T::BlockNumber>>,
+}
+
+impl<T: Config> support::Dispatch for Pallet<T> {
+	type Call = Call<T>;
+	type Origin = OriginFor<T>;
+
+	fn dispatch(&mut self, origin: Self::Origin, call: Self::Call) -> DispatchResult {
+		let caller = support::ensure_signed(origin)?;
+		match call {
+			Call::Schedule { when, call } => self.schedule(caller, when, *call).map(|_| ()),
+			Call::Cancel { when, index } => self.cancel(caller, when, index),
+			Call::ScheduleNamed { id, when, call } => {
+				self.schedule_named(caller, id, when, *call).map(|_| ())
+			},
+			Call::CancelNamed { id } => self.cancel_named(caller, id),
+		}
+	}
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self { now: T::BlockNumber::zero(), agenda: BTreeMap::new(), lookup: BTreeMap::new() }
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Scheduler",
+			index: None,
+			calls: vec![
+				variant(
+					"Schedule",
+					0,
+					vec![field::<T::BlockNumber>("when"), field::<Box<T::RuntimeCall>>("call")],
+				),
+				variant("Cancel", 1, vec![field::<T::BlockNumber>("when"), field::<u32>("index")]),
+				variant(
+					"ScheduleNamed",
+					2,
+					vec![
+						field::<T::TaskName>("id"),
+						field::<T::BlockNumber>("when"),
+						field::<Box<T::RuntimeCall>>("call"),
+					],
+				),
+				variant("CancelNamed", 3, vec![field::<T::TaskName>("id")]),
+			],
+			storage: vec![
+				storage::<T::BlockNumber>("now"),
+				storage::<BTreeMap<T::BlockNumber, Vec<Option<Scheduled<T>>>>>("agenda"),
+				storage::<BTreeMap<T::TaskName, TaskAddress<T::BlockNumber>>>("lookup"),
+			],
+			events: Vec::new(),
+			errors: vec![
+				"Agenda is full for the target block",
+				"Target block number is in the past",
+				"Task does not exist",
+				"Task name already in use",
+				"The task does not belong to Caller",
+			],
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Scheduler", STORAGE_VERSION);
+		trie::insert_value(storage, "Scheduler", "now", &self.now);
+		trie::insert_entries(storage, "Scheduler", "agenda", &self.agenda);
+		trie::insert_entries(storage, "Scheduler", "lookup", &self.lookup);
+	}
+
+	/// Restore the agenda `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult
+	where
+		T::RuntimeCall: Decode,
+		T::TaskName: Decode,
+	{
+		migrations::ensure_version(storage, "Scheduler", STORAGE_VERSION)?;
+		trie::restore_value(storage, "Scheduler", "now", &mut self.now)?;
+		self.agenda = trie::decode_entries(storage, "Scheduler", "agenda")?.into_iter().collect();
+		self.lookup = trie::decode_entries(storage, "Scheduler", "lookup")?.into_iter().collect();
+		Ok(())
+	}
+
+	/// Called at the start of every block: returns the tasks due at `now`, in the order they
+	/// were scheduled, and forgets about them.
+	pub fn on_initialize(&mut self, now: T::BlockNumber) -> Vec<Scheduled<T>> {
+		self.now = now;
+		let due: Vec<Scheduled<T>> =
+			self.agenda.remove(&now).unwrap_or_default().into_iter().flatten().collect();
+		for task in &due {
+			if let Some(name) = &task.name {
+				self.lookup.remove(name);
+			}
+		}
+		due
+	}
+
+	pub fn agenda(&self, when: &T::BlockNumber) -> Vec<&Scheduled<T>> {
+		self.agenda
+			.get(when)
+			.map(|tasks| tasks.iter().flatten().collect())
+			.unwrap_or_default()
+	}
+
+	pub fn lookup(&self, id: &T::TaskName) -> Option<&TaskAddress<T::BlockNumber>> {
+		self.lookup.get(id)
+	}
+
+	pub fn schedule(
+		&mut self,
+		caller: T::AccountId,
+		when: T::BlockNumber,
+		call: T::RuntimeCall,
+	) -> Result<TaskAddress<T::BlockNumber>, &'static str> {
+		self.do_schedule(Scheduled { owner: caller, call, name: None }, when)
+	}
+
+	pub fn schedule_named(
+		&mut self,
+		caller: T::AccountId,
+		id: T::TaskName,
+		when: T::BlockNumber,
+		call: T::RuntimeCall,
+	) -> Result<TaskAddress<T::BlockNumber>, &'static str> {
+		if self.lookup.contains_key(&id) {
+			return Err("Task name already in use");
+		}
+
+		let address =
+			self.do_schedule(Scheduled { owner: caller, call, name: Some(id.clone()) }, when)?;
+		self.lookup.insert(id, address);
+		Ok(address)
+	}
+
+	pub fn cancel(
+		&mut self,
+		caller: T::AccountId,
+		when: T::BlockNumber,
+		index: u32,
+	) -> DispatchResult {
+		let slot = self
+			.agenda
+			.get_mut(&when)
+			.and_then(|tasks| tasks.get_mut(index as usize))
+			.ok_or("Task does not exist")?;
+		let task = slot.as_ref().ok_or("Task does not exist")?;
+
+		if task.owner != caller {
+			return Err("The task does not belong to Caller");
+		}
+
+		if let Some(name) = slot.take().and_then(|task| task.name) {
+			self.lookup.remove(&name);
+		}
+		Ok(())
+	}
+
+	pub fn cancel_named(&mut self, caller: T::AccountId, id: T::TaskName) -> DispatchResult {
+		let (when, index) = *self.lookup.get(&id).ok_or("Task does not exist")?;
+		self.cancel(caller, when, index)
+	}
+
+	fn do_schedule(
+		&mut self,
+		task: Scheduled<T>,
+		when: T::BlockNumber,
+	) -> Result<TaskAddress<T::BlockNumber>, &'static str> {
+		if when <= self.now {
+			return Err("Target block number is in the past");
+		}
+
+		let tasks = self.agenda.entry(when).or_default();
+		if tasks.len() >= T::MAX_SCHEDULED_PER_BLOCK {
+			return Err("Agenda is full for the target block");
+		}
+
+		tasks.push(Some(task));
+		Ok((when, (tasks.len() - 1) as u32))
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::mock::Test;
+
+	impl super::Config for Test {
+		type RuntimeCall = &'static str;
+		type TaskName = String;
+		const MAX_SCHEDULED_PER_BLOCK: usize = 2;
+	}
+
+	#[test]
+	fn scheduled_calls_run_at_target_block() {
+		let alice = "alice".to_string();
+		let mut scheduler: super::Pallet<Test> = super::Pallet::new();
+
+		assert_eq!(scheduler.schedule(alice.clone(), 2, "first"), Ok((2, 0)));
+		assert_eq!(scheduler.schedule(alice.clone(), 2, "second"), Ok((2, 1)));
+		assert_eq!(
+			scheduler.schedule(alice.clone(), 2, "third"),
+			Err("Agenda is full for the target block")
+		);
+
+		assert!(scheduler.on_initialize(1).is_empty());
+		let due: Vec<_> = scheduler.on_initialize(2).into_iter().map(|task| task.call).collect();
+		assert_eq!(due, vec!["first", "second"]);
+		assert!(scheduler.agenda(&2).is_empty());
+
+		assert_eq!(
+			scheduler.schedule(alice.clone(), 2, "late"),
+			Err("Target block number is in the past")
+		);
+	}
+
+	#[test]
+	fn cancel_scheduled_call() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut scheduler: super::Pallet<Test> = super::Pallet::new();
+
+		let (when, index) = scheduler.schedule(alice.clone(), 3, "call").unwrap();
+
+		assert_eq!(
+			scheduler.cancel(bob.clone(), when, index),
+			Err("The task does not belong to Caller")
+		);
+		assert_eq!(scheduler.cancel(alice.clone(), when, index), Ok(()));
+		assert_eq!(scheduler.cancel(alice.clone(), when, index), Err("Task does not exist"));
+		assert!(scheduler.on_initialize(3).is_empty());
+	}
+
+	#[test]
+	fn named_schedules() {
+		let alice = "alice".to_string();
+		let task = "payroll".to_string();
+		let mut scheduler: super::Pallet<Test> = super::Pallet::new();
+
+		assert_eq!(scheduler.schedule_named(alice.clone(), task.clone(), 5, "pay"), Ok((5, 0)));
+		assert_eq!(
+			scheduler.schedule_named(alice.clone(), task.clone(), 6, "pay"),
+			Err("Task name already in use")
+		);
+		assert_eq!(scheduler.lookup(&task), Some(&(5, 0)));
+
+		assert_eq!(scheduler.cancel_named(alice.clone(), task.clone()), Ok(()));
+		assert_eq!(scheduler.lookup(&task), None);
+		assert_eq!(scheduler.cancel_named(alice.clone(), task.clone()), Err("Task does not exist"));
+
+		let _ = scheduler.schedule_named(alice.clone(), task.clone(), 5, "pay");
+		assert_eq!(scheduler.on_initialize(5).len(), 1);
+		assert_eq!(scheduler.lookup(&task), None);
+	}
+}
@@ -0,0 +1,368 @@
+use core::fmt::Debug;
+use std::collections::BTreeMap;
+
+use num::CheckedAdd;
+
+use crate::{
+	codec::{Decode, Encode},
+	metadata::{field, storage, variant, PalletMetadata},
+	migrations::{self, StorageVersion},
+	support::{
+		ensure_root, ensure_signed, DispatchResult, SessionHandler, SessionIndex, SessionManager,
+		Weight,
+	},
+	system::{self, OriginFor},
+	trie::{self, Storage},
+};
+
+pub trait Config: system::Config {
+	/// The keys a validator signs with during a session, e.g. for block authoring.
+	type Keys: Debug + Clone + Ord + Encode;
+	/// How long (in blocks) a session lasts.
+	const SESSION_LENGTH: Self::BlockNumber;
+	const MAX_VALIDATORS: usize;
+}
+
+#[derive(Debug, Clone)]
+pub enum Call<T: Config> {
+	/// Register the keys the caller validates with from the next session on.
+	SetKeys {
+		keys: T::Keys,
+	},
+	PurgeKeys,
+	/// Root only: queue the validators for the next session, overriding the session manager.
+	ForceNewValidators {
+		validators: Vec<T::AccountId>,
+	},
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::SetKeys { keys } => (0u8, keys).encode_to(dest),
+			Call::PurgeKeys => 1u8.encode_to(dest),
+			Call::ForceNewValidators { validators } => (2u8, validators).encode_to(dest),
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T>
+where
+	T::Keys: Decode,
+{
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::SetKeys { keys: Decode::decode(input)? },
+			1 => Call::PurgeKeys,
+			2 => Call::ForceNewValidators { validators: Decode::decode(input)? },
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::SetKeys { .. } => 0,
+			Call::PurgeKeys => 1,
+			Call::ForceNewValidators { .. } => 2,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::SetKeys { .. } | Call::PurgeKeys => 5_000,
+			Call::ForceNewValidators { validators } => 5_000 + 1_000 * validators.len() as Weight,
+		}
+	}
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	current_index: SessionIndex,
+	/// The active validators with the keys they had when the session started.
+	validators: Vec<(T::AccountId, T::Keys)>,
+	/// The validators of the next session, as planned during the current one.
+	queued: Option<Vec<T::AccountId>>,
+	next_keys: BTreeMap<T::AccountId, T::Keys>,
+	key_owner: BTreeMap<T::Keys, T::AccountId>,
+	next_rotation: T::BlockNumber,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self {
+			current_index: 0,
+			validators: Vec::new(),
+			queued: None,
+			next_keys: BTreeMap::new(),
+			key_owner: BTreeMap::new(),
+			next_rotation: T::SESSION_LENGTH,
+		}
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Session",
+			index: None,
+			calls: vec![
+				variant("SetKeys", 0, vec![field::<T::Keys>("keys")]),
+				variant("PurgeKeys", 1, Vec::new()),
+				variant("ForceNewValidators", 2, vec![field::<Vec<T::AccountId>>("validators")]),
+			],
+			storage: vec![
+				storage::<SessionIndex>("current_index"),
+				storage::<Vec<(T::AccountId, T::Keys)>>("validators"),
+				storage::<Option<Vec<T::AccountId>>>("queued"),
+				storage::<BTreeMap<T::AccountId, T::Keys>>("next_keys"),
+				storage::<BTreeMap<T::Keys, T::AccountId>>("key_owner"),
+				storage::<T::BlockNumber>("next_rotation"),
+			],
+			events: Vec::new(),
+			errors: vec!["Duplicate key", "No keys registered", "Too many validators"],
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Session", STORAGE_VERSION);
+		trie::insert_value(storage, "Session", "current_index", &self.current_index);
+		trie::insert_value(storage, "Session", "validators", &self.validators);
+		if let Some(queued) = &self.queued {
+			trie::insert_value(storage, "Session", "queued", queued);
+		}
+		trie::insert_entries(storage, "Session", "next_keys", &self.next_keys);
+		trie::insert_entries(storage, "Session", "key_owner", &self.key_owner);
+		trie::insert_value(storage, "Session", "next_rotation", &self.next_rotation);
+	}
+
+	/// Restore the validators and keys `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult
+	where
+		T::Keys: Decode,
+	{
+		migrations::ensure_version(storage, "Session", STORAGE_VERSION)?;
+		trie::restore_value(storage, "Session", "current_index", &mut self.current_index)?;
+		trie::restore_value(storage, "Session", "validators", &mut self.validators)?;
+		self.queued = trie::decode_value(storage, "Session", "queued")?;
+		self.next_keys =
+			trie::decode_entries(storage, "Session", "next_keys")?.into_iter().collect();
+		self.key_owner =
+			trie::decode_entries(storage, "Session", "key_owner")?.into_iter().collect();
+		trie::restore_value(storage, "Session", "next_rotation", &mut self.next_rotation)?;
+		Ok(())
+	}
+
+	pub fn dispatch(&mut self, origin: OriginFor<T>, call: Call<T>) -> DispatchResult {
+		match call {
+			Call::SetKeys { keys } => self.set_keys(ensure_signed(origin)?, keys),
+			Call::PurgeKeys => self.purge_keys(ensure_signed(origin)?),
+			Call::ForceNewValidators { validators } => {
+				ensure_root(origin)?;
+				self.force_new_validators(validators)
+			},
+		}
+	}
+
+	pub fn current_index(&self) -> SessionIndex {
+		self.current_index
+	}
+
+	pub fn validators(&self) -> &[(T::AccountId, T::Keys)] {
+		&self.validators
+	}
+
+	pub fn next_keys(&self, who: &T::AccountId) -> Option<&T::Keys> {
+		self.next_keys.get(who)
+	}
+
+	pub fn key_owner(&self, keys: &T::Keys) -> Option<&T::AccountId> {
+		self.key_owner.get(keys)
+	}
+
+	pub fn set_keys(&mut self, caller: T::AccountId, keys: T::Keys) -> DispatchResult {
+		if self.key_owner.get(&keys).is_some_and(|owner| *owner != caller) {
+			return Err("Duplicate key");
+		}
+		if let Some(old) = self.next_keys.insert(caller.clone(), keys.clone()) {
+			self.key_owner.remove(&old);
+		}
+		self.key_owner.insert(keys, caller);
+		Ok(())
+	}
+
+	pub fn purge_keys(&mut self, caller: T::AccountId) -> DispatchResult {
+		let keys = self.next_keys.remove(&caller).ok_or("No keys registered")?;
+		self.key_owner.remove(&keys);
+		Ok(())
+	}
+
+	/// Set the validators of the first session at genesis, with the keys they register, without
+	/// any origin check.
+	pub fn initialize_validators(
+		&mut self,
+		validators: Vec<(T::AccountId, T::Keys)>,
+	) -> DispatchResult {
+		let mut accounts = Vec::new();
+		for (who, keys) in validators {
+			self.set_keys(who.clone(), keys)?;
+			accounts.push(who);
+		}
+		accounts.sort();
+		accounts.dedup();
+		if accounts.len() > T::MAX_VALIDATORS {
+			return Err("Too many validators");
+		}
+		self.validators = accounts
+			.into_iter()
+			.filter_map(|who| self.next_keys.get(&who).cloned().map(|keys| (who, keys)))
+			.collect();
+		Ok(())
+	}
+
+	pub fn force_new_validators(&mut self, mut validators: Vec<T::AccountId>) -> DispatchResult {
+		validators.sort();
+		validators.dedup();
+		if validators.len() > T::MAX_VALIDATORS {
+			return Err("Too many validators");
+		}
+		self.queued = Some(validators);
+		Ok(())
+	}
+
+	/// Called at the start of every block: at the end of a session the queued validators take
+	/// over, and `manager` is asked for the validators of the session after. Validators that
+	/// have no keys registered are left out.
+	pub fn on_initialize(
+		&mut self,
+		now: T::BlockNumber,
+		manager: &mut impl SessionManager<T::AccountId>,
+		handler: &mut impl SessionHandler<T::AccountId, T::Keys>,
+	) {
+		if now < self.next_rotation {
+			return;
+		}
+		self.next_rotation = now.checked_add(&T::SESSION_LENGTH).unwrap_or(now);
+		self.current_index += 1;
+
+		let validators = match self.queued.take() {
+			Some(queued) => queued,
+			None => self.validators.iter().map(|(who, _)| who.clone()).collect(),
+		};
+		let validators: Vec<_> = validators
+			.into_iter()
+			.filter_map(|who| self.next_keys.get(&who).cloned().map(|keys| (who, keys)))
+			.take(T::MAX_VALIDATORS)
+			.collect();
+		let changed = validators != self.validators;
+		self.validators = validators;
+
+		if let Some(next) = manager.new_session(self.current_index + 1) {
+			let _ = self.force_new_validators(next);
+		}
+		handler.on_new_session(changed, &self.validators);
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{
+		mock::Test,
+		support::{SessionHandler, SessionIndex, SessionManager},
+	};
+
+	impl super::Config for Test {
+		type Keys = u8;
+		const SESSION_LENGTH: u32 = 5;
+		const MAX_VALIDATORS: usize = 2;
+	}
+
+	/// Plans the given validators for every session.
+	struct Planned(Vec<String>);
+
+	impl SessionManager<String> for Planned {
+		fn new_session(&mut self, _new_index: SessionIndex) -> Option<Vec<String>> {
+			Some(self.0.clone())
+		}
+	}
+
+	#[derive(Default)]
+	struct Consensus(Vec<(bool, Vec<(String, u8)>)>);
+
+	impl SessionHandler<String, u8> for Consensus {
+		fn on_new_session(&mut self, changed: bool, validators: &[(String, u8)]) {
+			self.0.push((changed, validators.to_vec()));
+		}
+	}
+
+	#[test]
+	fn keys_are_unique() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut session: super::Pallet<Test> = super::Pallet::new();
+
+		assert_eq!(session.set_keys(alice.clone(), 1), Ok(()));
+		assert_eq!(session.set_keys(bob.clone(), 1), Err("Duplicate key"));
+		assert_eq!(session.set_keys(alice.clone(), 2), Ok(()));
+		assert_eq!(session.key_owner(&1), None);
+		assert_eq!(session.set_keys(bob.clone(), 1), Ok(()));
+
+		assert_eq!(session.purge_keys(alice.clone()), Ok(()));
+		assert_eq!(session.purge_keys(alice.clone()), Err("No keys registered"));
+		assert_eq!(session.next_keys(&bob), Some(&1));
+	}
+
+	#[test]
+	fn genesis_validators_validate_the_first_session() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut session: super::Pallet<Test> = super::Pallet::new();
+		let too_many = vec![(alice.clone(), 1), (bob.clone(), 2), ("charlie".to_string(), 3)];
+		assert_eq!(session.initialize_validators(too_many), Err("Too many validators"));
+
+		let mut session: super::Pallet<Test> = super::Pallet::new();
+		let validators = vec![(bob.clone(), 2), (alice.clone(), 1)];
+		assert_eq!(session.initialize_validators(validators), Ok(()));
+		assert_eq!(session.validators(), &[(alice.clone(), 1), (bob.clone(), 2)]);
+		assert_eq!(session.key_owner(&2), Some(&bob));
+	}
+
+	#[test]
+	fn planned_validators_take_over_a_session_later() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut session: super::Pallet<Test> = super::Pallet::new();
+		let mut manager = Planned(vec![bob.clone(), alice.clone()]);
+		let mut consensus = Consensus::default();
+		let _ = session.set_keys(alice.clone(), 1);
+		let _ = session.force_new_validators(vec![alice.clone()]);
+
+		session.on_initialize(4, &mut manager, &mut consensus);
+		assert_eq!(session.current_index(), 0);
+		session.on_initialize(5, &mut manager, &mut consensus);
+		assert_eq!(session.validators(), &[(alice.clone(), 1)]);
+
+		// Bob is planned but has no keys yet, then registers them.
+		session.on_initialize(10, &mut manager, &mut consensus);
+		let _ = session.set_keys(bob.clone(), 2);
+		session.on_initialize(15, &mut manager, &mut consensus);
+
+		assert_eq!(session.current_index(), 3);
+		assert_eq!(
+			consensus.0,
+			vec![
+				(true, vec![(alice.clone(), 1)]),
+				(false, vec![(alice.clone(), 1)]),
+				(true, vec![(alice.clone(), 1), (bob.clone(), 2)]),
+			]
+		);
+	}
+}
@@ -0,0 +1,240 @@
+//! Signatures of extrinsics. A signature proves that the account of an extrinsic made it, so
+//! accounts are public keys and nobody else can spend from them.
+//!
+//! Every scheme implements `Verify` for its signature, and extrinsics carry a `MultiSignature`,
+//! which says which scheme made it, so every account picks its own scheme and a new one only
+//! takes a new variant. Keys are kept as 32-byte seeds together with their `Scheme`.
+
+use std::{fmt, str::FromStr};
+
+use crate::{
+	account::AccountId32,
+	codec::{Decode, Encode},
+	metadata::{Registry, TypeDef, TypeInfo, TypeVariant},
+};
+
+pub mod curve25519;
+pub mod ed25519;
+pub mod merlin;
+pub mod sr25519;
+
+/// A signature that can be checked against the account that made it.
+pub trait Verify {
+	fn verify(&self, message: &[u8], signer: &AccountId32) -> bool;
+}
+
+/// What an account is derived from, e.g. a public key.
+pub trait IdentifyAccount {
+	type AccountId;
+
+	fn into_account(self) -> Self::AccountId;
+}
+
+/// A public key of any of the supported schemes. Both have 32-byte keys, and the account of a
+/// key is the key itself, so signatures are verified against the account directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultiSigner {
+	Ed25519([u8; 32]),
+	Sr25519([u8; 32]),
+}
+
+impl IdentifyAccount for MultiSigner {
+	type AccountId = AccountId32;
+
+	fn into_account(self) -> AccountId32 {
+		match self {
+			MultiSigner::Ed25519(public) | MultiSigner::Sr25519(public) => AccountId32::new(public),
+		}
+	}
+}
+
+impl Encode for MultiSigner {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			MultiSigner::Ed25519(public) => (0u8, public).encode_to(dest),
+			MultiSigner::Sr25519(public) => (1u8, public).encode_to(dest),
+		}
+	}
+}
+
+impl Decode for MultiSigner {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		match u8::decode(input)? {
+			0 => Ok(MultiSigner::Ed25519(Decode::decode(input)?)),
+			1 => Ok(MultiSigner::Sr25519(Decode::decode(input)?)),
+			_ => Err("Invalid signature scheme"),
+		}
+	}
+}
+
+impl TypeInfo for MultiSigner {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		let public = registry.unnamed::<[u8; 32]>();
+		TypeDef::Variant(vec![
+			TypeVariant { name: "Ed25519", index: 0, fields: vec![public.clone()] },
+			TypeVariant { name: "Sr25519", index: 1, fields: vec![public] },
+		])
+	}
+}
+
+/// A signature of any of the supported schemes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultiSignature {
+	Ed25519(ed25519::Signature),
+	Sr25519(sr25519::Signature),
+}
+
+impl MultiSignature {
+	/// The 64 bytes of the signature, without its scheme.
+	pub fn as_bytes(&self) -> &[u8; 64] {
+		match self {
+			MultiSignature::Ed25519(signature) => &signature.0,
+			MultiSignature::Sr25519(signature) => &signature.0,
+		}
+	}
+}
+
+impl Verify for MultiSignature {
+	fn verify(&self, message: &[u8], signer: &AccountId32) -> bool {
+		match self {
+			MultiSignature::Ed25519(signature) => signature.verify(message, signer),
+			MultiSignature::Sr25519(signature) => signature.verify(message, signer),
+		}
+	}
+}
+
+impl Encode for MultiSignature {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			MultiSignature::Ed25519(signature) => (0u8, signature.0).encode_to(dest),
+			MultiSignature::Sr25519(signature) => (1u8, signature.0).encode_to(dest),
+		}
+	}
+}
+
+impl Decode for MultiSignature {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		match u8::decode(input)? {
+			0 => Ok(MultiSignature::Ed25519(ed25519::Signature(Decode::decode(input)?))),
+			1 => Ok(MultiSignature::Sr25519(sr25519::Signature(Decode::decode(input)?))),
+			_ => Err("Invalid signature scheme"),
+		}
+	}
+}
+
+impl TypeInfo for MultiSignature {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		let signature = registry.unnamed::<[u8; 64]>();
+		TypeDef::Variant(vec![
+			TypeVariant { name: "Ed25519", index: 0, fields: vec![signature.clone()] },
+			TypeVariant { name: "Sr25519", index: 1, fields: vec![signature] },
+		])
+	}
+}
+
+/// The scheme of a key. Like in Substrate, accounts are sr25519 keys unless chosen otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scheme {
+	Ed25519,
+	#[default]
+	Sr25519,
+}
+
+impl Scheme {
+	/// The account of the key with `seed`.
+	pub fn public(self, seed: &[u8; 32]) -> AccountId32 {
+		self.signer(seed).into_account()
+	}
+
+	/// The public key with `seed`.
+	pub fn signer(self, seed: &[u8; 32]) -> MultiSigner {
+		match self {
+			Scheme::Ed25519 => MultiSigner::Ed25519(ed25519::Pair::from_seed(seed).public()),
+			Scheme::Sr25519 => MultiSigner::Sr25519(sr25519::Pair::from_seed(seed).public()),
+		}
+	}
+
+	/// The signature of the 64 bytes of a signature of the scheme, e.g. as a signer returns it.
+	pub fn signature(self, bytes: [u8; 64]) -> MultiSignature {
+		match self {
+			Scheme::Ed25519 => MultiSignature::Ed25519(ed25519::Signature(bytes)),
+			Scheme::Sr25519 => MultiSignature::Sr25519(sr25519::Signature(bytes)),
+		}
+	}
+
+	pub fn sign(self, seed: &[u8; 32], message: &[u8]) -> MultiSignature {
+		match self {
+			Scheme::Ed25519 => {
+				MultiSignature::Ed25519(ed25519::Pair::from_seed(seed).sign(message))
+			},
+			Scheme::Sr25519 => {
+				MultiSignature::Sr25519(sr25519::Pair::from_seed(seed).sign(message))
+			},
+		}
+	}
+}
+
+impl fmt::Display for Scheme {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Scheme::Ed25519 => "ed25519",
+			Scheme::Sr25519 => "sr25519",
+		})
+	}
+}
+
+impl FromStr for Scheme {
+	type Err = &'static str;
+
+	fn from_str(name: &str) -> Result<Self, Self::Err> {
+		match name {
+			"ed25519" => Ok(Scheme::Ed25519),
+			"sr25519" => Ok(Scheme::Sr25519),
+			_ => Err("Unknown signature scheme"),
+		}
+	}
+}
+
+impl Encode for Scheme {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(*self as u8).encode_to(dest);
+	}
+}
+
+impl Decode for Scheme {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		match u8::decode(input)? {
+			0 => Ok(Scheme::Ed25519),
+			1 => Ok(Scheme::Sr25519),
+			_ => Err("Invalid signature scheme"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{IdentifyAccount, MultiSignature, MultiSigner, Scheme, Verify};
+	use crate::codec::{decode_all, Encode};
+
+	#[test]
+	fn signatures_say_which_scheme_verifies_them() {
+		for scheme in [Scheme::Ed25519, Scheme::Sr25519] {
+			let (seed, account) = ([3; 32], scheme.public(&[3; 32]));
+			let signature = scheme.sign(&seed, b"message");
+			assert!(signature.verify(b"message", &account));
+			assert_eq!(decode_all::<MultiSignature>(&signature.encode()), Ok(signature));
+			assert_eq!(scheme.signature(*signature.as_bytes()), signature);
+			assert_eq!(scheme.to_string().parse(), Ok(scheme));
+			assert_eq!(decode_all::<Scheme>(&scheme.encode()), Ok(scheme));
+			let signer = scheme.signer(&seed);
+			assert_eq!(decode_all::<MultiSigner>(&signer.encode()), Ok(signer));
+			assert_eq!(signer.into_account(), account);
+		}
+		// The same seed is another account in another scheme.
+		let ed25519 = Scheme::Ed25519.public(&[3; 32]);
+		assert!(!Scheme::Sr25519.sign(&[3; 32], b"message").verify(b"message", &ed25519));
+		let mut unknown = Scheme::Ed25519.sign(&[3; 32], b"message").encode();
+		unknown[0] = 2;
+		assert_eq!(decode_all::<MultiSignature>(&unknown), Err("Invalid signature scheme"));
+	}
+}
@@ -0,0 +1,537 @@
+//! Arithmetic on Curve25519 in its twisted Edwards form, with the two encodings of points the
+//! signature schemes use: compressed Edwards points (ed25519) and Ristretto255 (sr25519,
+//! RFC 9496), which hides the cofactor of the curve.
+//!
+//! Secret keys and nonces only pass through code that doesn't branch or index on their value:
+//! scalar multiplication adds the point for every bit and keeps the sum through a mask, and
+//! scalars are reduced bit by bit with masked subtraction rather than by `BigUint` division.
+//! Branches remain on public values, such as decoding points and checking signatures. What the
+//! compiler makes of the masks hasn't been checked against the generated code.
+
+use std::ops::{Add, Mul, Neg, Sub};
+
+const MASK: u64 = (1 << 51) - 1;
+
+/// An element of the field of integers modulo 2^255 - 19, as five limbs of 51 bits.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldElement([u64; 5]);
+
+const fn digit(c: u8) -> u8 {
+	match c {
+		b'0'..=b'9' => c - b'0',
+		_ => c - b'a' + 10,
+	}
+}
+
+/// 32 little-endian bytes written in hex.
+const fn constant(hex: &[u8; 64]) -> [u8; 32] {
+	let mut bytes = [0; 32];
+	let mut i = 0;
+	while i < 32 {
+		bytes[i] = digit(hex[2 * i]) << 4 | digit(hex[2 * i + 1]);
+		i += 1;
+	}
+	bytes
+}
+
+/// The constant of the curve equation, -121665/121666.
+const D: [u8; 32] = constant(b"a3785913ca4deb75abd841414d0a700098e879777940c78c73fe6f2bee6c0352");
+const D2: [u8; 32] = constant(b"59f1b226949bd6eb56b183829a14e00030d1f3eef2808e19e7fcdf56dcd90624");
+const SQRT_M1: [u8; 32] =
+	constant(b"b0a00e4a271beec478e42fad0618432fa7d7fb3d99004d2b0bdfc14f8024832b");
+const INVSQRT_A_MINUS_D: [u8; 32] =
+	constant(b"ea405d80aafdc899be72415a17162f9d40d801fe917bc216a2fcafcf05896c78");
+const BASE_X: [u8; 32] =
+	constant(b"1ad5258f602d56c9b2a7259560c72c695cdcd6fd31e2a4c0fe536ecdd3366921");
+const BASE_Y: [u8; 32] =
+	constant(b"5866666666666666666666666666666666666666666666666666666666666666");
+/// The order of the prime-order subgroup, 2^252 + 27742317777372353535851937790883648493.
+const ORDER: [u8; 32] =
+	constant(b"edd3f55c1a631258d69cf7a2def9de1400000000000000000000000000000010");
+
+impl FieldElement {
+	pub const ZERO: Self = Self([0; 5]);
+	pub const ONE: Self = Self([1, 0, 0, 0, 0]);
+
+	/// The element of 32 little-endian bytes, ignoring the highest bit.
+	pub fn from_bytes(bytes: &[u8; 32]) -> Self {
+		let mut words = [0u64; 4];
+		for (word, chunk) in words.iter_mut().zip(bytes.chunks(8)) {
+			*word = u64::from_le_bytes(chunk.try_into().expect("8 bytes"));
+		}
+		let limb = |start: usize| {
+			let (word, offset) = (start / 64, start % 64);
+			let mut value = words[word] >> offset;
+			if offset > 13 && word + 1 < 4 {
+				value |= words[word + 1] << (64 - offset);
+			}
+			value & MASK
+		};
+		Self([limb(0), limb(51), limb(102), limb(153), limb(204)])
+	}
+
+	/// The canonical encoding, below 2^255 - 19.
+	pub fn to_bytes(self) -> [u8; 32] {
+		let mut limbs = carry(self.0);
+		// Whether the value is at least the modulus, in which case it is subtracted once.
+		let mut q = (limbs[0] + 19) >> 51;
+		for limb in &limbs[1..] {
+			q = (limb + q) >> 51;
+		}
+		limbs[0] += 19 * q;
+		for i in 0..4 {
+			limbs[i + 1] += limbs[i] >> 51;
+			limbs[i] &= MASK;
+		}
+		limbs[4] &= MASK;
+
+		let (mut bytes, mut acc, mut bits, mut i) = ([0u8; 32], 0u128, 0, 0);
+		for limb in limbs {
+			acc |= (limb as u128) << bits;
+			bits += 51;
+			while bits >= 8 {
+				bytes[i] = acc as u8;
+				acc >>= 8;
+				bits -= 8;
+				i += 1;
+			}
+		}
+		bytes[i] = acc as u8;
+		bytes
+	}
+
+	pub fn square(self) -> Self {
+		self * self
+	}
+
+	fn pow2k(self, k: u32) -> Self {
+		(0..k).fold(self, |x, _| x.square())
+	}
+
+	/// `self^(2^250 - 1)` and `self^11`, which both inversion and square roots build on.
+	fn pow22501(self) -> (Self, Self) {
+		let t2 = self * self.pow2k(3);
+		let t3 = self.square() * t2;
+		let t5 = t2 * t3.square();
+		let t7 = t5.pow2k(5) * t5;
+		let t9 = t7.pow2k(10) * t7;
+		let t11 = t9.pow2k(20) * t9;
+		let t13 = t11.pow2k(10) * t7;
+		let t15 = t13.pow2k(50) * t13;
+		let t17 = t15.pow2k(100) * t15;
+		(t17.pow2k(50) * t13, t3)
+	}
+
+	/// `self^(p - 2)`, the inverse of a non-zero element.
+	pub fn invert(self) -> Self {
+		let (t19, t3) = self.pow22501();
+		t19.pow2k(5) * t3
+	}
+
+	/// `self^((p - 5) / 8)`.
+	fn pow_p58(self) -> Self {
+		let (t19, _) = self.pow22501();
+		t19.pow2k(2) * self
+	}
+
+	pub fn is_negative(self) -> bool {
+		self.to_bytes()[0] & 1 == 1
+	}
+
+	pub fn is_zero(self) -> bool {
+		self.to_bytes() == [0; 32]
+	}
+
+	/// `b` if `choice`, otherwise `a`, without branching on `choice`.
+	pub fn select(a: Self, b: Self, choice: bool) -> Self {
+		let mask = 0u64.wrapping_sub(choice as u64);
+		Self(core::array::from_fn(|i| a.0[i] ^ (mask & (a.0[i] ^ b.0[i]))))
+	}
+
+	fn abs(self) -> Self {
+		Self::select(self, -self, self.is_negative())
+	}
+
+	/// Whether `u / v` is a square, and the non-negative square root of `u / v`, or of
+	/// `sqrt(-1) * u / v` if it isn't.
+	pub fn sqrt_ratio(u: Self, v: Self) -> (bool, Self) {
+		let v3 = v.square() * v;
+		let v7 = v3.square() * v;
+		let mut r = u * v3 * (u * v7).pow_p58();
+		let check = v * r.square();
+		let sqrt_m1 = Self::from_bytes(&SQRT_M1);
+		let correct_sign = check == u;
+		let flipped_sign = check == -u;
+		let flipped_sign_i = check == -u * sqrt_m1;
+		r = Self::select(r, r * sqrt_m1, flipped_sign | flipped_sign_i);
+		(correct_sign | flipped_sign, r.abs())
+	}
+}
+
+/// Carry the limbs into 51 bits each, except for a small excess of the lowest one.
+fn carry(mut limbs: [u64; 5]) -> [u64; 5] {
+	for i in 0..4 {
+		limbs[i + 1] += limbs[i] >> 51;
+		limbs[i] &= MASK;
+	}
+	limbs[0] += 19 * (limbs[4] >> 51);
+	limbs[4] &= MASK;
+	limbs[1] += limbs[0] >> 51;
+	limbs[0] &= MASK;
+	limbs
+}
+
+impl PartialEq for FieldElement {
+	fn eq(&self, other: &Self) -> bool {
+		let (a, b) = (self.to_bytes(), other.to_bytes());
+		a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+	}
+}
+
+impl Add for FieldElement {
+	type Output = Self;
+
+	fn add(self, rhs: Self) -> Self {
+		Self(carry(core::array::from_fn(|i| self.0[i] + rhs.0[i])))
+	}
+}
+
+impl Sub for FieldElement {
+	type Output = Self;
+
+	fn sub(self, rhs: Self) -> Self {
+		// Adding 16p keeps every limb positive, as the limbs of `rhs` are below 2^52.
+		const SIXTEEN_P: [u64; 5] = [(MASK - 18) * 16, MASK * 16, MASK * 16, MASK * 16, MASK * 16];
+		Self(carry(core::array::from_fn(|i| self.0[i] + SIXTEEN_P[i] - rhs.0[i])))
+	}
+}
+
+impl Neg for FieldElement {
+	type Output = Self;
+
+	fn neg(self) -> Self {
+		Self::ZERO - self
+	}
+}
+
+impl Mul for FieldElement {
+	type Output = Self;
+
+	fn mul(self, rhs: Self) -> Self {
+		let (a, b) = (self.0, rhs.0);
+		let m = |x: u64, y: u64| x as u128 * y as u128;
+		// Limbs that overflow 2^255 wrap around multiplied by 19.
+		let b19: [u64; 5] = core::array::from_fn(|i| b[i] * 19);
+		let c = [
+			m(a[0], b[0]) + m(a[1], b19[4]) + m(a[2], b19[3]) + m(a[3], b19[2]) + m(a[4], b19[1]),
+			m(a[0], b[1]) + m(a[1], b[0]) + m(a[2], b19[4]) + m(a[3], b19[3]) + m(a[4], b19[2]),
+			m(a[0], b[2]) + m(a[1], b[1]) + m(a[2], b[0]) + m(a[3], b19[4]) + m(a[4], b19[3]),
+			m(a[0], b[3]) + m(a[1], b[2]) + m(a[2], b[1]) + m(a[3], b[0]) + m(a[4], b19[4]),
+			m(a[0], b[4]) + m(a[1], b[3]) + m(a[2], b[2]) + m(a[3], b[1]) + m(a[4], b[0]),
+		];
+		let mut limbs = [0u64; 5];
+		let mut excess = 0u128;
+		for i in 0..5 {
+			let value = c[i] + excess;
+			limbs[i] = value as u64 & MASK;
+			excess = value >> 51;
+		}
+		limbs[0] += excess as u64 * 19;
+		limbs[1] += limbs[0] >> 51;
+		limbs[0] &= MASK;
+		Self(limbs)
+	}
+}
+
+/// A point of the curve in extended coordinates: `x = X/Z`, `y = Y/Z` and `xy = T/Z`.
+#[derive(Debug, Clone, Copy)]
+pub struct EdwardsPoint {
+	x: FieldElement,
+	y: FieldElement,
+	z: FieldElement,
+	t: FieldElement,
+}
+
+impl EdwardsPoint {
+	pub const IDENTITY: Self = Self {
+		x: FieldElement::ZERO,
+		y: FieldElement::ONE,
+		z: FieldElement::ONE,
+		t: FieldElement::ZERO,
+	};
+
+	/// The generator both signature schemes use.
+	pub fn basepoint() -> Self {
+		let (x, y) = (FieldElement::from_bytes(&BASE_X), FieldElement::from_bytes(&BASE_Y));
+		Self { x, y, z: FieldElement::ONE, t: x * y }
+	}
+
+	/// `b` if `choice`, otherwise `a`, without branching on `choice`.
+	fn select(a: Self, b: Self, choice: bool) -> Self {
+		Self {
+			x: FieldElement::select(a.x, b.x, choice),
+			y: FieldElement::select(a.y, b.y, choice),
+			z: FieldElement::select(a.z, b.z, choice),
+			t: FieldElement::select(a.t, b.t, choice),
+		}
+	}
+
+	/// `scalar * self`, with the scalar as 32 little-endian bytes. Every bit costs a doubling and
+	/// an addition, whichever its value.
+	pub fn scalar_mul(self, scalar: &[u8; 32]) -> Self {
+		let mut result = Self::IDENTITY;
+		for i in (0..256).rev() {
+			result = result + result;
+			let bit = (scalar[i / 8] >> (i % 8)) & 1 == 1;
+			result = Self::select(result, result + self, bit);
+		}
+		result
+	}
+
+	/// The compressed Edwards encoding: `y`, with the sign of `x` in the highest bit.
+	pub fn compress(self) -> [u8; 32] {
+		let z_inv = self.z.invert();
+		let mut bytes = (self.y * z_inv).to_bytes();
+		bytes[31] |= ((self.x * z_inv).is_negative() as u8) << 7;
+		bytes
+	}
+
+	pub fn decompress(bytes: &[u8; 32]) -> Option<Self> {
+		let y = FieldElement::from_bytes(bytes);
+		let sign = bytes[31] >> 7 == 1;
+		let mut canonical = y.to_bytes();
+		canonical[31] |= bytes[31] & 0x80;
+		if canonical != *bytes {
+			return None;
+		}
+		let d = FieldElement::from_bytes(&D);
+		let (is_square, mut x) = FieldElement::sqrt_ratio(
+			y.square() - FieldElement::ONE,
+			d * y.square() + FieldElement::ONE,
+		);
+		if !is_square || (x.is_zero() && sign) {
+			return None;
+		}
+		if x.is_negative() != sign {
+			x = -x;
+		}
+		Some(Self { x, y, z: FieldElement::ONE, t: x * y })
+	}
+
+	/// The Ristretto255 encoding of the class of points `self` is in.
+	pub fn ristretto_compress(self) -> [u8; 32] {
+		let Self { x, y, z, t } = self;
+		let u1 = (z + y) * (z - y);
+		let u2 = x * y;
+		let (_, invsqrt) = FieldElement::sqrt_ratio(FieldElement::ONE, u1 * u2.square());
+		let den1 = invsqrt * u1;
+		let den2 = invsqrt * u2;
+		let z_inv = den1 * den2 * t;
+		let sqrt_m1 = FieldElement::from_bytes(&SQRT_M1);
+
+		let rotate = (t * z_inv).is_negative();
+		let (x, y) = (
+			FieldElement::select(x, y * sqrt_m1, rotate),
+			FieldElement::select(y, x * sqrt_m1, rotate),
+		);
+		let den_inv =
+			FieldElement::select(den2, den1 * FieldElement::from_bytes(&INVSQRT_A_MINUS_D), rotate);
+		let y = FieldElement::select(y, -y, (x * z_inv).is_negative());
+		(den_inv * (z - y)).abs().to_bytes()
+	}
+
+	pub fn ristretto_decompress(bytes: &[u8; 32]) -> Option<Self> {
+		let s = FieldElement::from_bytes(bytes);
+		if s.to_bytes() != *bytes || s.is_negative() {
+			return None;
+		}
+		let ss = s.square();
+		let u1 = FieldElement::ONE - ss;
+		let u2 = FieldElement::ONE + ss;
+		let u2_sqr = u2.square();
+		let v = -(FieldElement::from_bytes(&D) * u1.square()) - u2_sqr;
+		let (was_square, invsqrt) = FieldElement::sqrt_ratio(FieldElement::ONE, v * u2_sqr);
+		let den_x = invsqrt * u2;
+		let den_y = invsqrt * den_x * v;
+		let x = (s + s) * den_x;
+		let x = x.abs();
+		let y = u1 * den_y;
+		let t = x * y;
+		if !was_square || t.is_negative() || y.is_zero() {
+			return None;
+		}
+		Some(Self { x, y, z: FieldElement::ONE, t })
+	}
+}
+
+impl Add for EdwardsPoint {
+	type Output = Self;
+
+	fn add(self, rhs: Self) -> Self {
+		let a = (self.y - self.x) * (rhs.y - rhs.x);
+		let b = (self.y + self.x) * (rhs.y + rhs.x);
+		let c = self.t * FieldElement::from_bytes(&D2) * rhs.t;
+		let d = self.z * rhs.z;
+		let d = d + d;
+		let (e, f, g, h) = (b - a, d - c, d + c, b + a);
+		Self { x: e * f, y: g * h, z: f * g, t: e * h }
+	}
+}
+
+impl Neg for EdwardsPoint {
+	type Output = Self;
+
+	fn neg(self) -> Self {
+		Self { x: -self.x, t: -self.t, ..self }
+	}
+}
+
+/// Scalars are integers modulo the order of the basepoint, as 32 little-endian bytes.
+pub mod scalar {
+	type Limbs = [u64; 4];
+
+	fn from_bytes(bytes: &[u8; 32]) -> Limbs {
+		core::array::from_fn(|i| {
+			u64::from_le_bytes(bytes[i * 8..][..8].try_into().expect("8 bytes"))
+		})
+	}
+
+	fn to_bytes(limbs: Limbs) -> [u8; 32] {
+		let mut bytes = [0; 32];
+		for (chunk, limb) in bytes.chunks_mut(8).zip(limbs) {
+			chunk.copy_from_slice(&limb.to_le_bytes());
+		}
+		bytes
+	}
+
+	/// `a - b`, and whether it borrowed.
+	fn sub(a: Limbs, b: Limbs) -> (Limbs, bool) {
+		let (mut difference, mut borrow) = ([0; 4], false);
+		for i in 0..4 {
+			let (d, b1) = a[i].overflowing_sub(b[i]);
+			let (d, b2) = d.overflowing_sub(borrow as u64);
+			difference[i] = d;
+			borrow = b1 | b2;
+		}
+		(difference, borrow)
+	}
+
+	/// Little-endian bytes of any length, e.g. a hash, reduced modulo the order. The bits are
+	/// shifted in from the top, subtracting the order through a mask whenever the value reaches
+	/// it, so the time taken depends on the length alone.
+	pub fn reduce(bytes: &[u8]) -> [u8; 32] {
+		let order = from_bytes(&super::ORDER);
+		let mut value: Limbs = [0; 4];
+		for bit in bytes.iter().rev().flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1)) {
+			// Below the order, which is below 2^253, so doubling it can't overflow.
+			value = core::array::from_fn(|i| {
+				(value[i] << 1) | if i == 0 { bit as u64 } else { value[i - 1] >> 63 }
+			});
+			let (reduced, borrow) = sub(value, order);
+			let mask = (borrow as u64).wrapping_sub(1);
+			value = core::array::from_fn(|i| value[i] ^ (mask & (value[i] ^ reduced[i])));
+		}
+		to_bytes(value)
+	}
+
+	/// `a * b + c`, modulo the order.
+	pub fn mul_add(a: &[u8; 32], b: &[u8; 32], c: &[u8; 32]) -> [u8; 32] {
+		let [a, b, c] = [a, b, c].map(from_bytes);
+		// Below 2^512, so eight limbs hold it.
+		let mut wide = [0u64; 8];
+		for i in 0..4 {
+			let mut carry = 0u128;
+			for j in 0..4 {
+				let value = wide[i + j] as u128 + a[i] as u128 * b[j] as u128 + carry;
+				wide[i + j] = value as u64;
+				carry = value >> 64;
+			}
+			wide[i + 4] = carry as u64;
+		}
+		let mut carry = 0u128;
+		for (i, limb) in wide.iter_mut().enumerate() {
+			let value = *limb as u128 + c.get(i).copied().unwrap_or(0) as u128 + carry;
+			*limb = value as u64;
+			carry = value >> 64;
+		}
+		reduce(&wide.iter().flat_map(|limb| limb.to_le_bytes()).collect::<Vec<_>>())
+	}
+
+	/// Whether `bytes` is below the order, so every scalar has one encoding.
+	pub fn is_canonical(bytes: &[u8; 32]) -> bool {
+		sub(from_bytes(bytes), from_bytes(&super::ORDER)).1
+	}
+
+	pub fn is_zero(bytes: &[u8; 32]) -> bool {
+		bytes.iter().fold(0, |acc, byte| acc | byte) == 0
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{scalar, EdwardsPoint, FieldElement, ORDER};
+	use crate::hashing::hex;
+	use num::BigUint;
+
+	#[test]
+	fn field_arithmetic_is_modular() {
+		let two = FieldElement::ONE + FieldElement::ONE;
+		let minus_one = -FieldElement::ONE;
+		assert_eq!(minus_one + FieldElement::ONE, FieldElement::ZERO);
+		assert_eq!(two * two.invert(), FieldElement::ONE);
+		// 2^255 - 20 is -1, and 2^255 - 19 isn't canonical.
+		let mut p_minus_one = [0xff; 32];
+		p_minus_one[0] = 0xec;
+		p_minus_one[31] = 0x7f;
+		assert_eq!(minus_one.to_bytes(), p_minus_one);
+		p_minus_one[0] = 0xed;
+		assert_eq!(FieldElement::from_bytes(&p_minus_one).to_bytes(), [0; 32]);
+		let (is_square, root) = FieldElement::sqrt_ratio(two * two, FieldElement::ONE);
+		assert!(is_square && root == two);
+	}
+
+	#[test]
+	fn points_round_trip_through_both_encodings() {
+		let base = EdwardsPoint::basepoint();
+		let ristretto = "e2f2ae0a6abc4e71a884a961c500515f58e30b6aa582dd8db6a65945e08d2d76";
+		assert_eq!(hex(&base.ristretto_compress()), ristretto);
+		let compressed = "5866666666666666666666666666666666666666666666666666666666666666";
+		assert_eq!(hex(&base.compress()), compressed);
+
+		let mut scalar = [0; 32];
+		scalar[0] = 7;
+		let point = base.scalar_mul(&scalar);
+		let seven = base + base + base + base + base + base + base;
+		assert_eq!(point.compress(), seven.compress());
+		let decompressed = EdwardsPoint::decompress(&point.compress()).unwrap();
+		assert_eq!(decompressed.compress(), point.compress());
+		let decoded = EdwardsPoint::ristretto_decompress(&point.ristretto_compress()).unwrap();
+		assert_eq!(decoded.ristretto_compress(), point.ristretto_compress());
+		assert_eq!((point + -point).compress(), EdwardsPoint::IDENTITY.compress());
+		assert!(EdwardsPoint::ristretto_decompress(&[0xff; 32]).is_none());
+	}
+
+	#[test]
+	fn scalars_reduce_modulo_the_order() {
+		let order = BigUint::from_bytes_le(&ORDER);
+		let mut wide = [0u8; 64];
+		for (i, byte) in wide.iter_mut().enumerate() {
+			*byte = (i as u8).wrapping_mul(151) ^ 0xa5;
+		}
+		let reduced = BigUint::from_bytes_le(&scalar::reduce(&wide));
+		assert_eq!(reduced, BigUint::from_bytes_le(&wide) % &order);
+		assert_eq!(scalar::reduce(&ORDER), [0; 32]);
+		assert!(!scalar::is_canonical(&ORDER));
+
+		let minus_one = (&order - 1u8).to_bytes_le().try_into().unwrap();
+		assert!(scalar::is_canonical(&minus_one));
+		let mut one = [0; 32];
+		one[0] = 1;
+		assert_eq!(scalar::mul_add(&minus_one, &minus_one, &[0; 32]), one);
+		assert!(scalar::is_zero(&scalar::mul_add(&minus_one, &one, &one)));
+		let max = [0xff; 32];
+		let expected =
+			(BigUint::from_bytes_le(&max).pow(2) + BigUint::from_bytes_le(&max)) % &order;
+		assert_eq!(BigUint::from_bytes_le(&scalar::mul_add(&max, &max, &max)), expected);
+	}
+}
@@ -0,0 +1,121 @@
+//! Ed25519 signatures (RFC 8032): the secret scalar and a nonce prefix are the halves of the
+//! SHA-512 of the seed, and every signature commits to its message through the same hash.
+
+use super::{
+	curve25519::{scalar, EdwardsPoint},
+	Verify,
+};
+use crate::{account::AccountId32, hashing};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature(pub [u8; 64]);
+
+/// A key pair, expanded from its seed.
+pub struct Pair {
+	secret: [u8; 32],
+	prefix: [u8; 32],
+	public: [u8; 32],
+}
+
+impl Pair {
+	pub fn from_seed(seed: &[u8; 32]) -> Self {
+		let hash = hashing::sha512(seed);
+		let mut secret: [u8; 32] = hash[..32].try_into().expect("32 of 64 bytes");
+		secret[0] &= 0b1111_1000;
+		secret[31] &= 0b0111_1111;
+		secret[31] |= 0b0100_0000;
+		let public = EdwardsPoint::basepoint().scalar_mul(&secret).compress();
+		Self { secret, prefix: hash[32..].try_into().expect("32 of 64 bytes"), public }
+	}
+
+	pub fn public(&self) -> [u8; 32] {
+		self.public
+	}
+
+	/// Signatures are deterministic: the nonce is the hash of the prefix and the message.
+	pub fn sign(&self, message: &[u8]) -> Signature {
+		let nonce = scalar::reduce(&hashing::sha512(&[&self.prefix[..], message].concat()));
+		let commitment = EdwardsPoint::basepoint().scalar_mul(&nonce).compress();
+		let k = challenge(&commitment, &self.public, message);
+		let mut signature = [0; 64];
+		signature[..32].copy_from_slice(&commitment);
+		signature[32..].copy_from_slice(&scalar::mul_add(&k, &self.secret, &nonce));
+		Signature(signature)
+	}
+}
+
+fn challenge(commitment: &[u8; 32], public: &[u8; 32], message: &[u8]) -> [u8; 32] {
+	scalar::reduce(&hashing::sha512(&[&commitment[..], public, message].concat()))
+}
+
+/// The account is the compressed public key.
+impl Verify for Signature {
+	fn verify(&self, message: &[u8], signer: &AccountId32) -> bool {
+		let public: [u8; 32] = signer.as_ref().try_into().expect("Accounts are 32 bytes");
+		let Some(point) = EdwardsPoint::decompress(&public) else {
+			return false;
+		};
+		let commitment: [u8; 32] = self.0[..32].try_into().expect("32 of 64 bytes");
+		let s: [u8; 32] = self.0[32..].try_into().expect("32 of 64 bytes");
+		if !scalar::is_canonical(&s) {
+			return false;
+		}
+		let k = challenge(&commitment, &public, message);
+		let expected = EdwardsPoint::basepoint().scalar_mul(&s) + (-point).scalar_mul(&k);
+		expected.compress() == commitment
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{Pair, Signature};
+	use crate::{
+		account::AccountId32,
+		hashing::{from_hex, hex},
+		signature::Verify,
+	};
+
+	fn seed(hex: &str) -> [u8; 32] {
+		from_hex(hex).unwrap().try_into().unwrap()
+	}
+
+	#[test]
+	fn signatures_match_the_rfc_8032_test_vectors() {
+		let vectors = [
+			(
+				"9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+				"",
+				"d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+				"e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bac\
+				c61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+			),
+			(
+				"4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+				"72",
+				"3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+				"92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e\
+				458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+			),
+		];
+		for (secret, message, public, signature) in vectors {
+			let (pair, message) = (Pair::from_seed(&seed(secret)), from_hex(message).unwrap());
+			assert_eq!(hex(&pair.public()), public);
+			let signed = pair.sign(&message);
+			assert_eq!(hex(&signed.0), signature);
+			assert!(signed.verify(&message, &AccountId32::new(pair.public())));
+		}
+	}
+
+	#[test]
+	fn forged_signatures_are_rejected() {
+		let pair = Pair::from_seed(&[7; 32]);
+		let signer = AccountId32::new(pair.public());
+		let signature = pair.sign(b"transfer 5");
+		assert!(!signature.verify(b"transfer 500", &signer));
+		let other = AccountId32::new(Pair::from_seed(&[8; 32]).public());
+		assert!(!signature.verify(b"transfer 5", &other));
+		let mut tampered = signature.0;
+		tampered[40] ^= 1;
+		assert!(!Signature(tampered).verify(b"transfer 5", &signer));
+	}
+}
@@ -0,0 +1,219 @@
+//! Merlin transcripts, on which sr25519 builds its challenges: every message a signature
+//! commits to is absorbed with its label into a STROBE-128 duplex over Keccak-f[1600], so a
+//! challenge depends on all of them and on their order.
+
+/// The bytes of the state absorbed or squeezed before the permutation runs again.
+const RATE: usize = 166;
+
+const FLAG_I: u8 = 1;
+const FLAG_A: u8 = 1 << 1;
+const FLAG_C: u8 = 1 << 2;
+const FLAG_M: u8 = 1 << 4;
+const FLAG_K: u8 = 1 << 5;
+
+const ROUND_CONSTANTS: [u64; 24] = [
+	0x0000000000000001,
+	0x0000000000008082,
+	0x800000000000808a,
+	0x8000000080008000,
+	0x000000000000808b,
+	0x0000000080000001,
+	0x8000000080008081,
+	0x8000000000008009,
+	0x000000000000008a,
+	0x0000000000000088,
+	0x0000000080008009,
+	0x000000008000000a,
+	0x000000008000808b,
+	0x800000000000008b,
+	0x8000000000008089,
+	0x8000000000008003,
+	0x8000000000008002,
+	0x8000000000000080,
+	0x000000000000800a,
+	0x800000008000000a,
+	0x8000000080008081,
+	0x8000000000008080,
+	0x0000000080000001,
+	0x8000000080008008,
+];
+
+/// The rotation of the lane at `x + 5 * y`, by `[x][y]`.
+const ROTATIONS: [[u32; 5]; 5] = [
+	[0, 36, 3, 41, 18],
+	[1, 44, 10, 45, 2],
+	[62, 6, 43, 15, 61],
+	[28, 55, 25, 21, 56],
+	[27, 20, 39, 8, 14],
+];
+
+/// The permutation of SHA-3, on 25 little-endian lanes of 64 bits.
+pub fn keccak_f1600(bytes: &mut [u8; 200]) {
+	let mut state = [0u64; 25];
+	for (lane, chunk) in state.iter_mut().zip(bytes.chunks(8)) {
+		*lane = u64::from_le_bytes(chunk.try_into().expect("8 bytes"));
+	}
+	for round_constant in ROUND_CONSTANTS {
+		let parity: [u64; 5] =
+			core::array::from_fn(|x| (0..5).fold(0, |c, y| c ^ state[x + 5 * y]));
+		for x in 0..5 {
+			let d = parity[(x + 4) % 5] ^ parity[(x + 1) % 5].rotate_left(1);
+			for y in 0..5 {
+				state[x + 5 * y] ^= d;
+			}
+		}
+		let mut moved = [0u64; 25];
+		for x in 0..5 {
+			for y in 0..5 {
+				moved[y + 5 * ((2 * x + 3 * y) % 5)] =
+					state[x + 5 * y].rotate_left(ROTATIONS[x][y]);
+			}
+		}
+		for x in 0..5 {
+			for y in 0..5 {
+				let (next, after) = (moved[(x + 1) % 5 + 5 * y], moved[(x + 2) % 5 + 5 * y]);
+				state[x + 5 * y] = moved[x + 5 * y] ^ (!next & after);
+			}
+		}
+		state[0] ^= round_constant;
+	}
+	for (lane, chunk) in state.iter().zip(bytes.chunks_mut(8)) {
+		chunk.copy_from_slice(&lane.to_le_bytes());
+	}
+}
+
+/// The operations of STROBE-128 the transcripts use.
+#[derive(Clone)]
+struct Strobe128 {
+	state: [u8; 200],
+	pos: usize,
+	pos_begin: usize,
+}
+
+impl Strobe128 {
+	fn new(protocol: &[u8]) -> Self {
+		let mut state = [0; 200];
+		state[..6].copy_from_slice(&[1, RATE as u8 + 2, 1, 0, 1, 96]);
+		state[6..18].copy_from_slice(b"STROBEv1.0.2");
+		keccak_f1600(&mut state);
+		let mut strobe = Self { state, pos: 0, pos_begin: 0 };
+		strobe.meta_ad(protocol, false);
+		strobe
+	}
+
+	fn run_f(&mut self) {
+		self.state[self.pos] ^= self.pos_begin as u8;
+		self.state[self.pos + 1] ^= 0x04;
+		self.state[RATE + 1] ^= 0x80;
+		keccak_f1600(&mut self.state);
+		self.pos = 0;
+		self.pos_begin = 0;
+	}
+
+	fn absorb(&mut self, data: &[u8]) {
+		for byte in data {
+			self.state[self.pos] ^= byte;
+			self.pos += 1;
+			if self.pos == RATE {
+				self.run_f();
+			}
+		}
+	}
+
+	fn squeeze(&mut self, dest: &mut [u8]) {
+		for byte in dest {
+			*byte = self.state[self.pos];
+			self.state[self.pos] = 0;
+			self.pos += 1;
+			if self.pos == RATE {
+				self.run_f();
+			}
+		}
+	}
+
+	/// Start an operation, unless `more` continues the previous one.
+	fn begin_op(&mut self, flags: u8, more: bool) {
+		if more {
+			return;
+		}
+		let old_begin = self.pos_begin;
+		self.pos_begin = self.pos + 1;
+		self.absorb(&[old_begin as u8, flags]);
+		if flags & (FLAG_C | FLAG_K) != 0 && self.pos != 0 {
+			self.run_f();
+		}
+	}
+
+	fn meta_ad(&mut self, data: &[u8], more: bool) {
+		self.begin_op(FLAG_M | FLAG_A, more);
+		self.absorb(data);
+	}
+
+	fn ad(&mut self, data: &[u8], more: bool) {
+		self.begin_op(FLAG_A, more);
+		self.absorb(data);
+	}
+
+	fn prf(&mut self, dest: &mut [u8], more: bool) {
+		self.begin_op(FLAG_I | FLAG_A | FLAG_C, more);
+		self.squeeze(dest);
+	}
+}
+
+#[derive(Clone)]
+pub struct Transcript {
+	strobe: Strobe128,
+}
+
+impl Transcript {
+	pub fn new(label: &[u8]) -> Self {
+		let mut transcript = Self { strobe: Strobe128::new(b"Merlin v1.0") };
+		transcript.append_message(b"dom-sep", label);
+		transcript
+	}
+
+	pub fn append_message(&mut self, label: &[u8], message: &[u8]) {
+		self.strobe.meta_ad(label, false);
+		self.strobe.meta_ad(&(message.len() as u32).to_le_bytes(), true);
+		self.strobe.ad(message, false);
+	}
+
+	/// Fill `dest` with a challenge depending on everything appended so far.
+	pub fn challenge_bytes(&mut self, label: &[u8], dest: &mut [u8]) {
+		self.strobe.meta_ad(label, false);
+		self.strobe.meta_ad(&(dest.len() as u32).to_le_bytes(), true);
+		self.strobe.prf(dest, false);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::Transcript;
+	use crate::hashing::hex;
+
+	#[test]
+	fn keccak_matches_sha3() {
+		// SHA3-256 of nothing is the permutation of its padding.
+		let mut state = [0; 200];
+		state[0] = 0x06;
+		state[135] = 0x80;
+		super::keccak_f1600(&mut state);
+		let empty = "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a";
+		assert_eq!(hex(&state[..32]), empty);
+	}
+
+	#[test]
+	fn challenges_match_the_merlin_test_vector() {
+		let mut transcript = Transcript::new(b"test protocol");
+		transcript.append_message(b"some label", b"some data");
+		let mut challenge = [0; 32];
+		transcript.clone().challenge_bytes(b"challenge", &mut challenge);
+		let expected = "d5a21972d0d5fe320c0d263fac7fffb8145aa640af6e9bca177c03c7efcf0615";
+		assert_eq!(hex(&challenge), expected);
+
+		transcript.append_message(b"some label", b"other data");
+		let mut other = [0; 32];
+		transcript.challenge_bytes(b"challenge", &mut other);
+		assert_ne!(other, challenge);
+	}
+}
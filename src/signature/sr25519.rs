@@ -0,0 +1,158 @@
+//! Schnorr signatures over Ristretto255 as schnorrkel makes them, the default scheme of
+//! Substrate accounts. Messages are absorbed into a Merlin transcript in the `substrate` signing
+//! context, so a signature made for this chain also verifies with Substrate tools and the other
+//! way around.
+//!
+//! Seeds are schnorrkel mini secret keys, expanded the way of ed25519 like Substrate does, so a
+//! phrase gives the same account here and in Substrate wallets.
+
+use super::{
+	curve25519::{scalar, EdwardsPoint},
+	merlin::Transcript,
+	Verify,
+};
+use crate::{account::AccountId32, hashing};
+
+const SIGNING_CONTEXT: &[u8] = b"substrate";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature(pub [u8; 64]);
+
+/// A key pair, expanded from its seed.
+pub struct Pair {
+	key: [u8; 32],
+	nonce: [u8; 32],
+	public: [u8; 32],
+}
+
+impl Pair {
+	pub fn from_seed(seed: &[u8; 32]) -> Self {
+		let hash = hashing::sha512(seed);
+		let mut clamped: [u8; 32] = hash[..32].try_into().expect("32 of 64 bytes");
+		clamped[0] &= 0b1111_1000;
+		clamped[31] &= 0b0011_1111;
+		clamped[31] |= 0b0100_0000;
+		// Ristretto points have no cofactor to clear, so the clamped scalar is divided by it.
+		let key = core::array::from_fn(|i| clamped[i] >> 3 | clamped.get(i + 1).unwrap_or(&0) << 5);
+		let public = EdwardsPoint::basepoint().scalar_mul(&key).ristretto_compress();
+		Self { key, nonce: hash[32..].try_into().expect("32 of 64 bytes"), public }
+	}
+
+	pub fn public(&self) -> [u8; 32] {
+		self.public
+	}
+
+	/// The seed of the hard junction with `chain_code`.
+	pub fn derive_hard(&self, chain_code: &[u8; 32]) -> [u8; 32] {
+		let mut transcript = Transcript::new(b"SchnorrRistrettoHDKD");
+		transcript.append_message(b"sign-bytes", b"");
+		transcript.append_message(b"chain-code", chain_code);
+		transcript.append_message(b"secret-key", &self.key);
+		let mut seed = [0; 32];
+		transcript.challenge_bytes(b"HDKD-hard", &mut seed);
+		seed
+	}
+
+	/// Signatures are deterministic: the nonce is a challenge of the transcript of the message
+	/// and the secret nonce of the key, where schnorrkel also mixes in randomness.
+	pub fn sign(&self, message: &[u8]) -> Signature {
+		let mut transcript = transcript(message, &self.public);
+		let mut witness = transcript.clone();
+		witness.append_message(b"signing", &self.nonce);
+		let mut nonce = [0; 64];
+		witness.challenge_bytes(b"witness", &mut nonce);
+		let nonce = scalar::reduce(&nonce);
+
+		let commitment = EdwardsPoint::basepoint().scalar_mul(&nonce).ristretto_compress();
+		let k = challenge(&mut transcript, &commitment);
+		let mut signature = [0; 64];
+		signature[..32].copy_from_slice(&commitment);
+		signature[32..].copy_from_slice(&scalar::mul_add(&k, &self.key, &nonce));
+		// Marks the signature as schnorrkel's, rather than of its earlier versions.
+		signature[63] |= 0x80;
+		Signature(signature)
+	}
+}
+
+fn transcript(message: &[u8], public: &[u8; 32]) -> Transcript {
+	let mut transcript = Transcript::new(b"SigningContext");
+	transcript.append_message(b"", SIGNING_CONTEXT);
+	transcript.append_message(b"sign-bytes", message);
+	transcript.append_message(b"proto-name", b"Schnorr-sig");
+	transcript.append_message(b"sign:pk", public);
+	transcript
+}
+
+fn challenge(transcript: &mut Transcript, commitment: &[u8; 32]) -> [u8; 32] {
+	transcript.append_message(b"sign:R", commitment);
+	let mut k = [0; 64];
+	transcript.challenge_bytes(b"sign:c", &mut k);
+	scalar::reduce(&k)
+}
+
+/// The account is the Ristretto encoding of the public key.
+impl Verify for Signature {
+	fn verify(&self, message: &[u8], signer: &AccountId32) -> bool {
+		let public: [u8; 32] = signer.as_ref().try_into().expect("Accounts are 32 bytes");
+		let Some(point) = EdwardsPoint::ristretto_decompress(&public) else {
+			return false;
+		};
+		let commitment: [u8; 32] = self.0[..32].try_into().expect("32 of 64 bytes");
+		let mut s: [u8; 32] = self.0[32..].try_into().expect("32 of 64 bytes");
+		if s[31] & 0x80 == 0 {
+			return false;
+		}
+		s[31] &= 0x7f;
+		if !scalar::is_canonical(&s) {
+			return false;
+		}
+		let k = challenge(&mut transcript(message, &public), &commitment);
+		let expected = EdwardsPoint::basepoint().scalar_mul(&s) + (-point).scalar_mul(&k);
+		expected.ristretto_compress() == commitment
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{Pair, Signature};
+	use crate::{
+		account::AccountId32,
+		hashing::{from_hex, hex},
+		signature::Verify,
+	};
+
+	/// The seed of the development phrase of Substrate.
+	const DEV_SEED: &str = "fac7959dbfe72f052e5a0c3c8d6530f202b02fd8f9f5ca3580ec8deb7797479e";
+
+	#[test]
+	fn keys_match_substrate() {
+		let root = Pair::from_seed(&from_hex(DEV_SEED).unwrap().try_into().unwrap());
+		let public = "46ebddef8cd9bb167dc30878d7113b7e168e6f0646beffd77d69d39bad76b47a";
+		assert_eq!(hex(&root.public()), public);
+		// The chain code of `//Alice` is its SCALE encoding, zero-padded.
+		let mut chain_code = [0; 32];
+		chain_code[..6].copy_from_slice(b"\x14Alice");
+		let alice = Pair::from_seed(&root.derive_hard(&chain_code));
+		let public = "d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d";
+		assert_eq!(hex(&alice.public()), public);
+	}
+
+	#[test]
+	fn signatures_verify_only_their_message_and_signer() {
+		let pair = Pair::from_seed(&[7; 32]);
+		let signer = AccountId32::new(pair.public());
+		let signature = pair.sign(b"transfer 5");
+		assert_eq!(pair.sign(b"transfer 5"), signature);
+		assert!(signature.verify(b"transfer 5", &signer));
+		assert!(!signature.verify(b"transfer 500", &signer));
+		let other = AccountId32::new(Pair::from_seed(&[8; 32]).public());
+		assert!(!signature.verify(b"transfer 5", &other));
+
+		let mut unmarked = signature.0;
+		unmarked[63] &= 0x7f;
+		assert!(!Signature(unmarked).verify(b"transfer 5", &signer));
+		let mut tampered = signature.0;
+		tampered[5] ^= 1;
+		assert!(!Signature(tampered).verify(b"transfer 5", &signer));
+	}
+}
@@ -0,0 +1,423 @@
+//! Signed extensions check what a signed extrinsic carries besides its call, its
+//! `SignedExtra`, and do what comes with it, e.g. using up its nonce or paying its fee. The
+//! runtime runs `Runtime::SIGNED_EXTENSIONS` in order: `validate` before the transaction pool
+//! admits the extrinsic, and `pre_dispatch` and `post_dispatch` around dispatching it in a
+//! block, the latter with what the call reported, e.g. to refund the fee of weight it didn't
+//! use. A tuple of extensions is an extension running each of them in turn.
+//!
+//! The weight of a whole block is checked before any of it is executed, and signatures by the
+//! runtime itself, as unsigned extrinsics need the latter too.
+
+use crate::{
+	balances::NegativeImbalance,
+	support::{
+		self, DispatchInfo, InvalidTransaction, Pays, TransactionPriority, ValidTransaction,
+	},
+	system, types, Runtime, RuntimeCall, TREASURY_FEE_SHARE,
+};
+
+pub trait SignedExtension {
+	/// What `pre_dispatch` leaves for `post_dispatch`.
+	type Pre;
+
+	/// Whether the extrinsic of `who` may be included in the block following `state`.
+	fn validate(
+		&self,
+		state: &Runtime,
+		who: &types::AccountId,
+		call: &RuntimeCall,
+		extra: &types::SignedExtra,
+	) -> Result<ValidTransaction, InvalidTransaction>;
+
+	/// Check the extrinsic again right before it is dispatched, and make the changes that come
+	/// with it. The extrinsic is skipped if any extension fails, and the changes of the ones
+	/// before are kept.
+	fn pre_dispatch(
+		&self,
+		state: &mut Runtime,
+		who: &types::AccountId,
+		call: &RuntimeCall,
+		extra: &types::SignedExtra,
+	) -> Result<Self::Pre, InvalidTransaction>;
+
+	/// Called once the call, which declared `info`, was dispatched, whatever the result.
+	fn post_dispatch(
+		&self,
+		_state: &mut Runtime,
+		_pre: Self::Pre,
+		_info: &DispatchInfo,
+		_result: &support::DispatchResultWithPostInfo,
+	) {
+	}
+}
+
+macro_rules! impl_for_tuples {
+	($($extension:ident $index:tt),+) => {
+		impl<$($extension: SignedExtension),+> SignedExtension for ($($extension,)+) {
+			type Pre = ($($extension::Pre,)+);
+
+			fn validate(
+				&self,
+				state: &Runtime,
+				who: &types::AccountId,
+				call: &RuntimeCall,
+				extra: &types::SignedExtra,
+			) -> Result<ValidTransaction, InvalidTransaction> {
+				let valid = ValidTransaction::default();
+				$(let valid = valid.combine_with(self.$index.validate(state, who, call, extra)?);)+
+				Ok(valid)
+			}
+
+			fn pre_dispatch(
+				&self,
+				state: &mut Runtime,
+				who: &types::AccountId,
+				call: &RuntimeCall,
+				extra: &types::SignedExtra,
+			) -> Result<Self::Pre, InvalidTransaction> {
+				Ok(($(self.$index.pre_dispatch(state, who, call, extra)?,)+))
+			}
+
+			fn post_dispatch(
+				&self,
+				state: &mut Runtime,
+				pre: Self::Pre,
+				info: &DispatchInfo,
+				result: &support::DispatchResultWithPostInfo,
+			) {
+				$(self.$index.post_dispatch(state, pre.$index, info, result);)+
+			}
+		}
+	};
+}
+
+impl_for_tuples!(A 0);
+impl_for_tuples!(A 0, B 1);
+impl_for_tuples!(A 0, B 1, C 2);
+impl_for_tuples!(A 0, B 1, C 2, D 3);
+impl_for_tuples!(A 0, B 1, C 2, D 3, E 4);
+impl_for_tuples!(A 0, B 1, C 2, D 3, E 4, F 5);
+
+/// The era of the extrinsic must be alive, and its birth block known.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CheckEra;
+
+impl SignedExtension for CheckEra {
+	type Pre = ();
+
+	fn validate(
+		&self,
+		state: &Runtime,
+		_who: &types::AccountId,
+		_call: &RuntimeCall,
+		extra: &types::SignedExtra,
+	) -> Result<ValidTransaction, InvalidTransaction> {
+		if state.birth_hash(&extra.era).is_none() {
+			return Err(InvalidTransaction::AncientBirthBlock);
+		}
+		let next_block = u64::from(state.system.block_number()) + 1;
+		if !extra.era.is_alive(next_block) {
+			return Err(InvalidTransaction::Stale);
+		}
+		Ok(ValidTransaction::default())
+	}
+
+	fn pre_dispatch(
+		&self,
+		state: &mut Runtime,
+		_who: &types::AccountId,
+		_call: &RuntimeCall,
+		extra: &types::SignedExtra,
+	) -> Result<(), InvalidTransaction> {
+		match extra.era.is_alive(state.system.block_number().into()) {
+			true => Ok(()),
+			false => Err(InvalidTransaction::Stale),
+		}
+	}
+}
+
+/// The caller may not have more than `MAX_EXTRINSICS_PER_ACCOUNT` signed extrinsics in a block.
+/// The transaction pool doesn't put more than that in a block, so validating checks nothing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CheckRateLimit;
+
+impl SignedExtension for CheckRateLimit {
+	type Pre = ();
+
+	fn validate(
+		&self,
+		_state: &Runtime,
+		_who: &types::AccountId,
+		_call: &RuntimeCall,
+		_extra: &types::SignedExtra,
+	) -> Result<ValidTransaction, InvalidTransaction> {
+		Ok(ValidTransaction::default())
+	}
+
+	fn pre_dispatch(
+		&self,
+		state: &mut Runtime,
+		who: &types::AccountId,
+		_call: &RuntimeCall,
+		_extra: &types::SignedExtra,
+	) -> Result<(), InvalidTransaction> {
+		let noted = state.system.note_account_extrinsic(who);
+		noted.map_err(|_| InvalidTransaction::ExhaustsResources)
+	}
+}
+
+/// The extrinsic must be at the nonce of its caller, which it uses up.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CheckNonce;
+
+impl SignedExtension for CheckNonce {
+	type Pre = ();
+
+	/// Extrinsics at later nonces are valid, as the pool may hold the ones before them. The
+	/// last nonce can't be used, as the nonce couldn't be increased after it.
+	fn validate(
+		&self,
+		state: &Runtime,
+		who: &types::AccountId,
+		_call: &RuntimeCall,
+		extra: &types::SignedExtra,
+	) -> Result<ValidTransaction, InvalidTransaction> {
+		if extra.nonce < state.system.get_nonce(who) || extra.nonce == types::Nonce::MAX {
+			return Err(InvalidTransaction::Stale);
+		}
+		Ok(ValidTransaction::default())
+	}
+
+	fn pre_dispatch(
+		&self,
+		state: &mut Runtime,
+		who: &types::AccountId,
+		_call: &RuntimeCall,
+		extra: &types::SignedExtra,
+	) -> Result<(), InvalidTransaction> {
+		let nonce = state.system.get_nonce(who);
+		if extra.nonce < nonce {
+			return Err(InvalidTransaction::Stale);
+		}
+		if extra.nonce > nonce {
+			return Err(InvalidTransaction::Future);
+		}
+		state.system.inc_nonce(who).map_err(|_| InvalidTransaction::Stale)
+	}
+}
+
+/// The call must fit in a block.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CheckWeight;
+
+impl CheckWeight {
+	/// The weight of `call`, if it fits in a block. Unsigned calls are checked with it too.
+	pub fn check(call: &RuntimeCall) -> Result<support::Weight, InvalidTransaction> {
+		let weight = call.weight();
+		if weight > <Runtime as system::Config>::MAX_BLOCK_WEIGHT {
+			return Err(InvalidTransaction::ExhaustsResources);
+		}
+		Ok(weight)
+	}
+}
+
+impl SignedExtension for CheckWeight {
+	type Pre = ();
+
+	fn validate(
+		&self,
+		_state: &Runtime,
+		_who: &types::AccountId,
+		call: &RuntimeCall,
+		_extra: &types::SignedExtra,
+	) -> Result<ValidTransaction, InvalidTransaction> {
+		Ok(ValidTransaction { priority: 0, weight: Self::check(call)? })
+	}
+
+	/// The weight of the whole block was checked before its first extrinsic.
+	fn pre_dispatch(
+		&self,
+		_state: &mut Runtime,
+		_who: &types::AccountId,
+		_call: &RuntimeCall,
+		_extra: &types::SignedExtra,
+	) -> Result<(), InvalidTransaction> {
+		Ok(())
+	}
+}
+
+/// The caller pays the transaction fee and its tip, and the block author gets the tip. The fee
+/// pays for the declared weight of the call: once dispatched, the share of the weight the call
+/// didn't use is refunded, and all of it if the call doesn't pay. The treasury gets its share of
+/// the rest and the rest is burned.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChargeTransactionPayment;
+
+impl ChargeTransactionPayment {
+	/// The part of `fee`, paid for a call declaring `info`, that is kept after dispatching it.
+	pub fn actual_fee(
+		fee: types::Balance,
+		info: &DispatchInfo,
+		result: &support::DispatchResultWithPostInfo,
+	) -> types::Balance {
+		match result {
+			Ok(post) if post.pays_fee(info) == Pays::No => 0,
+			Ok(post) => {
+				let actual_weight = post.calc_actual_weight(info);
+				fee.saturating_mul(actual_weight.into()) / types::Balance::from(info.weight.max(1))
+			},
+			Err(_) => fee,
+		}
+	}
+}
+
+impl SignedExtension for ChargeTransactionPayment {
+	/// The caller and the fee it paid, which is resolved once the call reported what it used.
+	type Pre = (types::AccountId, NegativeImbalance<Runtime>);
+
+	/// The priority is what the caller pays per weight, so lighter calls go first unless a
+	/// heavier one tips more.
+	fn validate(
+		&self,
+		state: &Runtime,
+		who: &types::AccountId,
+		call: &RuntimeCall,
+		extra: &types::SignedExtra,
+	) -> Result<ValidTransaction, InvalidTransaction> {
+		let paid = state.transaction_payment.fee().saturating_add(extra.tip);
+		state
+			.balances
+			.can_withdraw(who, paid)
+			.map_err(|_| InvalidTransaction::Payment)?;
+		let max_weight = <Runtime as system::Config>::MAX_BLOCK_WEIGHT;
+		let priority = paid.saturating_mul(max_weight.into()) / u128::from(call.weight().max(1));
+		let priority = TransactionPriority::try_from(priority).unwrap_or(TransactionPriority::MAX);
+		Ok(ValidTransaction { priority, weight: 0 })
+	}
+
+	fn pre_dispatch(
+		&self,
+		state: &mut Runtime,
+		who: &types::AccountId,
+		_call: &RuntimeCall,
+		extra: &types::SignedExtra,
+	) -> Result<Self::Pre, InvalidTransaction> {
+		let fee = state.transaction_payment.fee();
+		let Runtime { balances, authorship, .. } = state;
+		let withdrawn = balances.withdraw(who, fee.saturating_add(extra.tip));
+		let (tip, fee) = withdrawn.map_err(|_| InvalidTransaction::Payment)?.split(extra.tip);
+		// Like the block reward, a tip the author can't receive without an overflow is burned.
+		match authorship.author() {
+			Some(author) => {
+				let _ = balances.resolve(author, tip);
+			},
+			None => balances.burn(tip),
+		}
+		Ok((who.clone(), fee))
+	}
+
+	fn post_dispatch(
+		&self,
+		state: &mut Runtime,
+		(who, fee): Self::Pre,
+		info: &DispatchInfo,
+		result: &support::DispatchResultWithPostInfo,
+	) {
+		let actual_fee = Self::actual_fee(fee.peek(), info, result);
+		let Runtime { balances, treasury, .. } = state;
+		let refund = fee.peek() - actual_fee;
+		let (refund, fee) = fee.split(refund);
+		// The refund was withdrawn from the caller just before, so it fits.
+		let _ = balances.resolve(&who, refund);
+		// Like the tip, a share the treasury can't receive without an overflow is burned, as is
+		// the rest of the fee.
+		let (share, rest) = fee.split(TREASURY_FEE_SHARE.mul_floor(actual_fee));
+		let _ = treasury.on_unbalanced(balances, share);
+		balances.burn(rest);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{ChargeTransactionPayment, CheckNonce, SignedExtension};
+	use crate::{
+		genesis::{self, GenesisBuilder},
+		multisig,
+		support::{self, InvalidTransaction, Pays, PostDispatchInfo},
+		types, wallet, Runtime, RuntimeCall, TRANSACTION_FEE,
+	};
+
+	#[test]
+	fn extensions_run_in_order() {
+		let alice = wallet::dev_account("alice");
+		let bob = wallet::dev_account("bob");
+		let mut state = GenesisBuilder::new().endow(&alice, 1_000).build().unwrap();
+		let call = wallet::transfer(alice.clone(), 1);
+		let extra = |nonce, tip| types::SignedExtra { nonce, era: support::Era::Immortal, tip };
+
+		let extensions = Runtime::SIGNED_EXTENSIONS;
+
+		let valid = extensions.validate(&state, &alice, &call, &extra(0, 0)).unwrap();
+		assert_eq!(valid.weight, call.weight());
+		let tipped = extensions.validate(&state, &alice, &call, &extra(0, 10)).unwrap();
+		assert!(tipped.priority > valid.priority);
+		// Later nonces wait in the pool, but can't be dispatched yet.
+		assert!(extensions.validate(&state, &alice, &call, &extra(5, 0)).is_ok());
+		let result = extensions.pre_dispatch(&mut state, &alice, &call, &extra(5, 0));
+		assert_eq!(result.err(), Some(InvalidTransaction::Future));
+
+		let pre = extensions.pre_dispatch(&mut state, &alice, &call, &extra(0, 0)).unwrap();
+		assert_eq!(state.system.get_nonce(&alice), 1);
+		assert_eq!(state.balances.balance(&alice), 1_000 - TRANSACTION_FEE);
+		// The call used half its weight, so half the fee is refunded.
+		let info = call.dispatch_info();
+		let half = Ok(PostDispatchInfo::used(info.weight / 2));
+		extensions.post_dispatch(&mut state, pre, &info, &half);
+		assert_eq!(state.balances.balance(&alice), 1_000 - TRANSACTION_FEE / 2);
+		let free = Ok(PostDispatchInfo { actual_weight: None, pays_fee: Pays::No });
+		assert_eq!(ChargeTransactionPayment::actual_fee(TRANSACTION_FEE, &info, &free), 0);
+		let failed = Err("Failed");
+		let fee = ChargeTransactionPayment::actual_fee(TRANSACTION_FEE, &info, &failed);
+		assert_eq!(fee, TRANSACTION_FEE);
+		let stale = CheckNonce.validate(&state, &alice, &call, &extra(0, 0));
+		assert_eq!(stale.err(), Some(InvalidTransaction::Stale));
+		// The last nonce would leave the account without one to go on with.
+		let last = CheckNonce.validate(&state, &alice, &call, &extra(types::Nonce::MAX, 0));
+		assert_eq!(last.err(), Some(InvalidTransaction::Stale));
+
+		// Bob can't pay, but the nonce checked before is used up all the same.
+		let checks = (CheckNonce, ChargeTransactionPayment);
+		let result = checks.pre_dispatch(&mut state, &bob, &call, &extra(0, 0));
+		assert_eq!(result.err(), Some(InvalidTransaction::Payment));
+		assert_eq!(state.system.get_nonce(&bob), 1);
+	}
+
+	#[test]
+	fn unused_weight_is_refunded() {
+		let alice = wallet::dev_account("alice");
+		let bob = wallet::dev_account("bob");
+		let mut state = GenesisBuilder::new().endow(&alice, 1_000).build().unwrap();
+		// The transfer waits for Bob's approval, so only the multisig call itself is executed.
+		let transfer = Box::new(wallet::transfer(bob.clone(), 10));
+		let other_signatories = vec![bob.clone()];
+		let call = multisig::Call::AsMulti {
+			threshold: 2,
+			other_signatories,
+			maybe_timepoint: None,
+			call: transfer,
+		};
+		let used = call.weight();
+		let call = RuntimeCall::Multisig(call);
+		let declared = call.weight();
+		assert!(used < declared);
+
+		let block =
+			genesis::block(&state, "charlie", genesis::signed(&state, "alice", [call])).unwrap();
+		let inherents = block.extrinsics[0].call.weight();
+		state.execute_block(block).unwrap();
+		assert_eq!(state.system.block_weight(), inherents + used);
+		let kept = TRANSACTION_FEE * types::Balance::from(used) / types::Balance::from(declared);
+		let balance = state.balances.balance(&alice) + state.balances.reserved_balance(&alice);
+		assert_eq!(balance, 1_000 - kept);
+	}
+}
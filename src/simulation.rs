@@ -0,0 +1,327 @@
+//! A harness that runs several in-process nodes against faults: the validators take turns
+//! authoring blocks and vote for their best block, while gossip between them is dropped or
+//! delayed, and byzantine validators equivocate or send invalid blocks. Honest nodes must never
+//! finalize conflicting blocks, and must agree on the finalized chain once the faults stop.
+//!
+//! Nodes gossip through the harness, which decides when each message arrives, votes included,
+//! which are sent again every round until their finality round is over, as some are lost.
+//! Requests and responses between two peers go
+//! straight through the network, like over a connection that retries: a node that receives a
+//! block whose parent it doesn't know asks the sender for the blocks after its finalized block.
+
+use crate::{
+	chain::{self, Chain, ImportOutcome},
+	finality::{SignedVote, Voter},
+	genesis::{self, GenesisBuilder},
+	hashing::Hash,
+	import_queue::{self, ImportError},
+	inherents, keystore,
+	mock::Rng,
+	network::{Message, Network, Peer},
+	support, types, wallet,
+};
+
+/// The time between two rounds, in which one block is authored.
+const ROUND_TIME: types::Moment = 1_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Behavior {
+	Honest,
+	/// Authors two blocks at every turn, each sent to half of the nodes.
+	Equivocating,
+	/// Authors blocks whose state root is wrong.
+	InvalidBlocks,
+}
+
+/// What happens to gossiped messages.
+#[derive(Debug, Clone, Copy)]
+pub struct Faults {
+	/// The share of messages that are lost.
+	pub drop: support::Percent,
+	/// Messages arrive up to this many rounds late.
+	pub max_delay: u32,
+}
+
+impl Faults {
+	/// Every message arrives in the round it is sent.
+	pub const NONE: Faults = Faults { drop: support::Percent(0), max_delay: 0 };
+}
+
+enum Gossip {
+	Block(types::Block),
+	Vote(SignedVote),
+}
+
+/// A gossiped message on its way.
+struct InFlight {
+	arrival: u32,
+	from: usize,
+	to: usize,
+	gossip: Gossip,
+}
+
+pub struct Node {
+	pub behavior: Behavior,
+	pub account: types::AccountId,
+	pub chain: Chain,
+	/// The session key the node seals its blocks and signs its votes with.
+	key: keystore::Signer,
+	peer: Peer,
+	voter: Voter,
+}
+
+pub struct Simulation {
+	pub nodes: Vec<Node>,
+	/// The blocks byzantine nodes sent that no node may import.
+	pub invalid: Vec<Hash>,
+	faults: Faults,
+	in_flight: Vec<InFlight>,
+	round: u32,
+	rng: Rng,
+}
+
+impl Simulation {
+	/// A node for every behavior, all of them validators, gossiping reliably until `set_faults`
+	/// is called.
+	pub fn new(behaviors: &[Behavior], seed: u64) -> Self {
+		let accounts: Vec<_> = (0..behaviors.len())
+			.map(|i| wallet::dev_account(&format!("node {i}")))
+			.collect();
+		let genesis = accounts.iter().fold(GenesisBuilder::new(), GenesisBuilder::authority);
+		let genesis = genesis.build().expect("The genesis is valid");
+		let network = Network::new();
+		let nodes = behaviors
+			.iter()
+			.zip(&accounts)
+			.map(|(behavior, account)| Node {
+				behavior: *behavior,
+				account: account.clone(),
+				chain: Chain::new(genesis.clone()),
+				key: genesis::session_key(account),
+				peer: network.join(),
+				voter: Voter::new(genesis.session.validators().to_vec()),
+			})
+			.collect();
+		let (faults, rng) = (Faults::NONE, Rng(seed.max(1)));
+		Self { nodes, invalid: Vec::new(), faults, in_flight: Vec::new(), round: 0, rng }
+	}
+
+	pub fn set_faults(&mut self, faults: Faults) {
+		self.faults = faults;
+	}
+
+	pub fn run(&mut self, rounds: u32) {
+		for _ in 0..rounds {
+			self.round += 1;
+			self.author();
+			self.deliver();
+			self.vote();
+			self.assert_safe();
+		}
+	}
+
+	fn now(&self) -> types::Moment {
+		types::Moment::from(self.round) * ROUND_TIME
+	}
+
+	/// The block the author of the round builds on its best block, with a timestamp of at least
+	/// `now`.
+	fn build(&self, author: usize, now: types::Moment) -> types::Block {
+		let node = &self.nodes[author];
+		let state = node.chain.state();
+		let inherents = inherents::create_inherents(state, now).expect("No overflow");
+		let block =
+			import_queue::build_block(state, &node.account, inherents).expect("Blocks build");
+		import_queue::seal(block, &node.key)
+	}
+
+	/// The validators take turns authoring, in the order they joined.
+	fn author(&mut self) {
+		let (author, now) = (self.round as usize % self.nodes.len(), self.now());
+		let block = self.build(author, now);
+		match self.nodes[author].behavior {
+			Behavior::Honest => {
+				let _ = self.nodes[author].chain.import(block.clone(), now);
+				self.gossip_to_all(author, || Gossip::Block(block.clone()));
+			},
+			Behavior::Equivocating => {
+				// A second block at the same height, which only differs by its timestamp.
+				let other = self.build(author, now + 1);
+				let _ = self.nodes[author].chain.import(block.clone(), now);
+				let _ = self.nodes[author].chain.import(other.clone(), now);
+				for to in (0..self.nodes.len()).filter(|to| *to != author) {
+					let block = if to % 2 == 0 { block.clone() } else { other.clone() };
+					self.gossip(author, to, Gossip::Block(block));
+				}
+			},
+			Behavior::InvalidBlocks => {
+				let mut invalid = block;
+				invalid.header.state_root = [0xba; 32];
+				self.invalid.push(chain::block_hash(&invalid));
+				self.gossip_to_all(author, || Gossip::Block(invalid.clone()));
+			},
+		}
+	}
+
+	fn gossip_to_all(&mut self, from: usize, gossip: impl Fn() -> Gossip) {
+		for to in (0..self.nodes.len()).filter(|to| *to != from) {
+			self.gossip(from, to, gossip());
+		}
+	}
+
+	/// Send `gossip` through the faults: it is lost, or arrives up to `max_delay` rounds later.
+	fn gossip(&mut self, from: usize, to: usize, gossip: Gossip) {
+		if self.rng.below(100) < usize::from(self.faults.drop.0) {
+			return;
+		}
+		let arrival = self.round + self.rng.below(self.faults.max_delay as usize + 1) as u32;
+		self.in_flight.push(InFlight { arrival, from, to, gossip });
+	}
+
+	/// Hand the messages that arrive in this round to their nodes, and let them handle what
+	/// they received.
+	fn deliver(&mut self) {
+		let round = self.round;
+		let (arrived, in_flight) = std::mem::take(&mut self.in_flight)
+			.into_iter()
+			.partition(|m| m.arrival <= round);
+		self.in_flight = in_flight;
+		for InFlight { from, to, gossip, .. } in arrived {
+			match gossip {
+				Gossip::Block(block) => self.nodes[from].peer.send_to(to, Message::Block(block)),
+				Gossip::Vote(vote) => self.nodes[to].voter.receive(vote),
+			}
+		}
+		// Responses are received in the same round as their request, so nodes are handled until
+		// no messages are left.
+		let now = self.now();
+		let mut relayed = Vec::new();
+		loop {
+			let mut handled = false;
+			for (index, node) in self.nodes.iter_mut().enumerate() {
+				let mut blocks = Vec::new();
+				handled |= node.handle(now, &mut blocks);
+				relayed.extend(blocks.into_iter().map(|block| (index, block)));
+			}
+			if !handled {
+				break;
+			}
+		}
+		for (from, block) in relayed {
+			self.gossip_to_all(from, || Gossip::Block(block.clone()));
+		}
+	}
+
+	fn vote(&mut self) {
+		let now = self.now();
+		for voter in 0..self.nodes.len() {
+			let Node { account, chain, key, voter: gadget, .. } = &mut self.nodes[voter];
+			let (cast, _) = gadget.vote(chain, account, key, now);
+			for vote in cast {
+				self.gossip_to_all(voter, || Gossip::Vote(vote.clone()));
+			}
+		}
+	}
+
+	pub fn honest(&self) -> impl Iterator<Item = &Node> {
+		self.nodes.iter().filter(|node| node.behavior == Behavior::Honest)
+	}
+
+	/// Honest nodes never finalize conflicting blocks, nor build on invalid ones. Those may be
+	/// kept on a fork, which isn't executed until it becomes the best chain.
+	pub fn assert_safe(&self) {
+		for node in self.honest() {
+			let best_chain = node.chain.route(node.chain.best_hash());
+			let invalid = self.invalid.iter().any(|hash| best_chain.contains(hash));
+			assert!(!invalid, "Invalid block on the best chain");
+			for other in self.honest() {
+				let (ours, theirs) = (node.chain.finalized_head(), other.chain.finalized_head());
+				if node.chain.finalized_number() >= other.chain.finalized_number() {
+					assert!(node.chain.is_descendant(ours, theirs), "Conflicting finalized blocks");
+				}
+			}
+		}
+	}
+}
+
+impl Node {
+	/// Handle the received messages, returning whether there were any. Gossiped blocks that are
+	/// new to the node are added to `relay`, so the blocks of an equivocating author reach every
+	/// node, as do the votes for them.
+	fn handle(&mut self, now: types::Moment, relay: &mut Vec<types::Block>) -> bool {
+		let received = self.peer.receive();
+		let any = !received.is_empty();
+		for (from, message) in received {
+			match message {
+				Message::Block(block) => {
+					let number = block.header.block_number;
+					match self.chain.import(block.clone(), now) {
+						Ok(ImportOutcome::AlreadyKnown) => {},
+						Ok(_) => relay.push(block),
+						Err(ImportError::UnknownParent) => {
+							let first = self.chain.finalized_number() + 1;
+							let request = Message::BlockRequest { from: first, to: number };
+							self.peer.send_to(from, request);
+						},
+						Err(_) => {},
+					}
+				},
+				Message::BlockRequest { from: first, to } => {
+					let blocks = self.chain.best_chain_blocks(first, to);
+					self.peer.send_to(from, Message::Blocks(blocks));
+				},
+				Message::Blocks(blocks) => {
+					for block in blocks {
+						let _ = self.chain.import(block, now);
+					}
+				},
+				_ => {},
+			}
+		}
+		any
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{Behavior, Faults, Simulation};
+	use crate::{support::Percent, types};
+
+	/// Run the nodes through `faulty` rounds of faults and `quiet` rounds without, then check
+	/// that the honest ones finalized the same chain, close to its best block.
+	fn assert_honest_nodes_agree(seed: u64, faulty: u32, quiet: u32) {
+		use Behavior::*;
+		let behaviors = [Honest, Honest, Equivocating, Honest, Honest, InvalidBlocks, Honest];
+		let mut simulation = Simulation::new(&behaviors, seed);
+		simulation.set_faults(Faults { drop: Percent(20), max_delay: 3 });
+		simulation.run(faulty);
+		// The faults stop, and every message in flight arrives.
+		simulation.set_faults(Faults::NONE);
+		simulation.run(quiet);
+
+		let mut honest = simulation.honest();
+		let first = honest.next().unwrap();
+		// A block is final two rounds after the prevotes for it, so only the latest aren't.
+		assert!(first.chain.finalized_number() >= quiet as types::BlockNumber);
+		assert!(first.chain.finalized_number() + 3 >= first.chain.best_number());
+		for node in honest {
+			assert_eq!(node.chain.finalized_head(), first.chain.finalized_head());
+		}
+		assert!(!simulation.invalid.is_empty());
+	}
+
+	#[test]
+	fn honest_nodes_agree_despite_faults() {
+		assert_honest_nodes_agree(1, 12, 8);
+	}
+
+	/// Longer runs with more seeds, too slow for every test run: `cargo test --release --
+	/// --ignored`.
+	#[test]
+	#[ignore]
+	fn honest_nodes_agree_despite_faults_over_many_seeds() {
+		for seed in 1..=3 {
+			assert_honest_nodes_agree(seed, 40, 20);
+		}
+	}
+}
@@ -0,0 +1,183 @@
+use core::fmt::Debug;
+
+use crate::{
+	codec::{Decode, Encode},
+	metadata::{field, storage, variant, PalletMetadata},
+	migrations::{self, StorageVersion},
+	support::{DispatchResult, Weight},
+	system,
+	trie::{self, storage_key, Storage},
+};
+
+pub trait Config: system::Config {
+	/// The aggregated call type the sudo key is allowed to dispatch with Root origin.
+	type RuntimeCall: Debug + Clone + Encode;
+}
+
+#[derive(Debug, Clone)]
+pub enum Call<T: Config> {
+	Sudo { call: Box<T::RuntimeCall> },
+	SetKey { new: T::AccountId },
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::Sudo { call } => (0u8, call).encode_to(dest),
+			Call::SetKey { new } => (1u8, new).encode_to(dest),
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T>
+where
+	T::RuntimeCall: Decode,
+{
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::Sudo { call: Decode::decode(input)? },
+			1 => Call::SetKey { new: Decode::decode(input)? },
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::Sudo { .. } => 0,
+			Call::SetKey { .. } => 1,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			// The weight of the wrapped call is added by the runtime.
+			Call::Sudo { .. } | Call::SetKey { .. } => 1_000,
+		}
+	}
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	key: Option<T::AccountId>,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self { key: None }
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Sudo",
+			index: None,
+			calls: vec![
+				variant("Sudo", 0, vec![field::<Box<T::RuntimeCall>>("call")]),
+				variant("SetKey", 1, vec![field::<T::AccountId>("new")]),
+			],
+			storage: vec![storage::<Option<T::AccountId>>("key")],
+			events: Vec::new(),
+			errors: vec!["Caller is not the sudo key"],
+		}
+	}
+
+	pub fn key(&self) -> Option<&T::AccountId> {
+		self.key.as_ref()
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Sudo", STORAGE_VERSION);
+		if let Some(key) = &self.key {
+			storage.insert(storage_key("Sudo", "key", &()), key.encode());
+		}
+	}
+
+	/// Restore the key `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult {
+		migrations::ensure_version(storage, "Sudo", STORAGE_VERSION)?;
+		let keys = trie::decode_entries::<(), T::AccountId>(storage, "Sudo", "key")?;
+		self.key = keys.into_iter().next().map(|((), key)| key);
+		Ok(())
+	}
+
+	/// Set the sudo key at genesis, without any origin check.
+	pub fn initialize_key(&mut self, key: T::AccountId) {
+		self.key = Some(key);
+	}
+
+	pub fn ensure_sudo(&self, caller: &T::AccountId) -> DispatchResult {
+		match &self.key {
+			Some(key) if key == caller => Ok(()),
+			_ => Err("Caller is not the sudo key"),
+		}
+	}
+
+	pub fn set_key(&mut self, caller: T::AccountId, new: T::AccountId) -> DispatchResult {
+		self.ensure_sudo(&caller)?;
+		self.key = Some(new);
+		Ok(())
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::mock::Test;
+
+	impl super::Config for Test {
+		type RuntimeCall = ();
+	}
+
+	#[test]
+	fn no_key_rejects_everyone() {
+		let sudo: super::Pallet<Test> = super::Pallet::new();
+
+		assert_eq!(sudo.key(), None);
+		assert_eq!(sudo.ensure_sudo(&"alice".to_string()), Err("Caller is not the sudo key"));
+	}
+
+	#[test]
+	fn ensure_sudo_checks_key() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut sudo: super::Pallet<Test> = super::Pallet::new();
+		sudo.initialize_key(alice.clone());
+
+		assert_eq!(sudo.ensure_sudo(&alice), Ok(()));
+		assert_eq!(sudo.ensure_sudo(&bob), Err("Caller is not the sudo key"));
+	}
+
+	#[test]
+	fn set_key_rotates_sudo() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut sudo: super::Pallet<Test> = super::Pallet::new();
+		sudo.initialize_key(alice.clone());
+
+		assert_eq!(sudo.set_key(bob.clone(), bob.clone()), Err("Caller is not the sudo key"));
+		assert_eq!(sudo.set_key(alice.clone(), bob.clone()), Ok(()));
+		assert_eq!(sudo.key(), Some(&bob));
+		assert_eq!(sudo.ensure_sudo(&alice), Err("Caller is not the sudo key"));
+	}
+
+	#[test]
+	fn key_is_restored_from_storage() {
+		let mut sudo: super::Pallet<Test> = super::Pallet::new();
+		sudo.initialize_key("alice".to_string());
+		let mut storage = crate::trie::Storage::new();
+		sudo.storage_entries(&mut storage);
+
+		let mut restored: super::Pallet<Test> = super::Pallet::new();
+		assert_eq!(restored.restore_entries(&storage), Ok(()));
+		assert_eq!(restored.key(), Some(&"alice".to_string()));
+	}
+}
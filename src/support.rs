@@ -1,24 +1,874 @@
-pub struct Block<Header, Extrinsic> {
-    pub header: Header,
-    pub extrinsics: Vec<Extrinsic>
+pub use blockchain_macros::{pallet, Dispatchable};
+use num::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, Zero};
+
+use crate::{
+	codec::{self, Compact, Decode, Encode},
+	hashing::{self, Hash},
+	metadata::{unit, Registry, TypeDef, TypeInfo, TypeVariant},
+	trie::{self, Storage},
+};
+
+#[derive(Clone)]
+pub struct Block<Header, Extrinsic, Seal> {
+	pub header: Header,
+	pub extrinsics: Vec<Extrinsic>,
+	/// The signature of the author over the hash of the header, with its session key. The hash
+	/// of a block is the one of its header, so it doesn't depend on the seal.
+	pub seal: Option<Seal>,
+}
+
+#[derive(Clone)]
+pub struct Header<BlockNumber, AccountId> {
+	pub block_number: BlockNumber,
+	/// The hash of the header of the block this one builds on.
+	pub parent_hash: Hash,
+	/// Commits the header to the extrinsics of its block.
+	pub extrinsics_root: Hash,
+	/// Commits the header to the state after its block, see the `trie` module.
+	pub state_root: Hash,
+	/// The account that produced the block.
+	pub author: AccountId,
+	/// The `spec_version` of the runtime that produced the block.
+	pub spec_version: u32,
+}
+
+/// Identifies the logic of a runtime. Nodes only import blocks produced by the same
+/// `spec_version` as theirs, while `impl_version` changes don't affect the outcome of blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuntimeVersion {
+	pub spec_name: &'static str,
+	pub spec_version: u32,
+	pub impl_version: u32,
+}
+
+#[derive(Clone)]
+pub struct Extrinsic<Caller, Call, Signature, Extra> {
+	/// The signer of a signed extrinsic. Unsigned extrinsics have none: inherents, and the calls
+	/// a pallet accepts unsigned, see `ValidateUnsigned`.
+	pub caller: Option<Caller>,
+	pub call: Call,
+	/// The signature of the caller and what it signed besides the call, see `signing_payload`.
+	pub signature: Option<(Signature, Extra)>,
+}
+
+/// How long a signed extrinsic can be included in a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Era {
+	/// Forever, which only the nonce keeps from being included twice.
+	Immortal,
+	/// In the `period` blocks after `birth_block`. The signature commits to the hash of the
+	/// birth block, which must still be among the recent block hashes, so the extrinsic is
+	/// dropped on forks without it too.
+	Mortal { birth_block: u64, period: u64 },
 }
 
-pub struct Header<BlockNumber> {
-    pub block_number: BlockNumber
+impl Era {
+	/// Whether an extrinsic of the era can be included in block `n`.
+	pub fn is_alive(&self, n: u64) -> bool {
+		match *self {
+			Era::Immortal => true,
+			Era::Mortal { birth_block, period } => birth_block < n && n - birth_block <= period,
+		}
+	}
 }
 
-pub struct Extrinsic<Caller, Call> {
-    pub caller: Caller,
-    pub call: Call
+/// What a signature commits to besides the call, which the runtime checks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignedExtra<Nonce, Balance> {
+	/// The nonce of the caller the extrinsic must be executed at, so it's executed only once.
+	pub nonce: Nonce,
+	pub era: Era,
+	/// Paid to the block author on top of the fee, for a higher priority in the pool.
+	pub tip: Balance,
 }
 
+/// Payloads are signed as their hash once they are longer than this, so signers with little
+/// memory, e.g. hardware wallets, can sign them.
+pub const MAX_UNHASHED_PAYLOAD_LEN: usize = 256;
+
+/// The bytes the caller signs: `call`, `extra`, the genesis hash of the chain, which keeps the
+/// signature from being used on another chain, and the hash of the birth block of a mortal
+/// `extra.era`, the genesis hash again for immortal ones.
+pub fn signing_payload<Call: Encode, Extra: Encode>(
+	call: &Call,
+	extra: &Extra,
+	genesis_hash: &Hash,
+	birth_hash: &Hash,
+) -> Vec<u8> {
+	let payload = (call, extra, genesis_hash, birth_hash).encode();
+	match payload.len() > MAX_UNHASHED_PAYLOAD_LEN {
+		true => hashing::blake2_256(&payload).to_vec(),
+		false => payload,
+	}
+}
+
+impl<Header: Encode, Extrinsic: Encode, Seal: Encode> Encode for Block<Header, Extrinsic, Seal> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.header, &self.extrinsics, &self.seal).encode_to(dest);
+	}
+}
+
+impl<Header: Decode, Extrinsic: Decode, Seal: Decode> Decode for Block<Header, Extrinsic, Seal> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (header, extrinsics, seal) = Decode::decode(input)?;
+		Ok(Block { header, extrinsics, seal })
+	}
+}
+
+impl<BlockNumber: Encode, AccountId: Encode> Encode for Header<BlockNumber, AccountId> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.block_number, &self.parent_hash, &self.extrinsics_root, &self.state_root)
+			.encode_to(dest);
+		(&self.author, self.spec_version).encode_to(dest);
+	}
+}
+
+impl<BlockNumber: Decode, AccountId: Decode> Decode for Header<BlockNumber, AccountId> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (block_number, parent_hash, extrinsics_root, state_root) = Decode::decode(input)?;
+		let (author, spec_version) = Decode::decode(input)?;
+		Ok(Header { block_number, parent_hash, extrinsics_root, state_root, author, spec_version })
+	}
+}
+
+impl RuntimeVersion {
+	/// The version runtime code starts with, encoded like a version. The code must be for the
+	/// same spec as `self`.
+	pub fn of_code(&self, mut code: &[u8]) -> Result<RuntimeVersion, &'static str> {
+		let (spec_name, spec_version, impl_version): (String, u32, u32) =
+			Decode::decode(&mut code).map_err(|_| "Invalid code")?;
+		if spec_name != self.spec_name {
+			return Err("Invalid spec name");
+		}
+		Ok(RuntimeVersion { spec_name: self.spec_name, spec_version, impl_version })
+	}
+}
+
+impl Encode for RuntimeVersion {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(self.spec_name, self.spec_version, self.impl_version).encode_to(dest);
+	}
+}
+
+impl<Caller: Encode, Call: Encode, Signature: Encode, Extra: Encode> Encode
+	for Extrinsic<Caller, Call, Signature, Extra>
+{
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.caller, &self.call, &self.signature).encode_to(dest);
+	}
+}
+
+impl<Caller: Decode, Call: Decode, Signature: Decode, Extra: Decode> Decode
+	for Extrinsic<Caller, Call, Signature, Extra>
+{
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (caller, call, signature) = Decode::decode(input)?;
+		Ok(Extrinsic { caller, call, signature })
+	}
+}
+
+/// The format of the envelope of Substrate extrinsics, in the version byte that starts them.
+pub const EXTRINSIC_FORMAT: u8 = 4;
+/// Set in the version byte of signed extrinsics.
+const SIGNED: u8 = 0x80;
+
+impl<Caller, Call, Signature, Extra> Extrinsic<Caller, Call, Signature, Extra> {
+	/// The extrinsic in the envelope of Substrate: its compact-encoded length, the version byte,
+	/// and for signed extrinsics the `address` of the caller, the signature and the extra, then
+	/// the call. Unsigned extrinsics are written without their caller.
+	pub fn encode_standard<Address: Encode>(&self, address: impl Fn(&Caller) -> Address) -> Vec<u8>
+	where
+		Call: Encode,
+		Signature: Encode,
+		Extra: Encode,
+	{
+		let mut extrinsic = Vec::new();
+		match (&self.caller, &self.signature) {
+			(Some(caller), Some((signature, extra))) => {
+				(EXTRINSIC_FORMAT | SIGNED, address(caller), signature, extra)
+					.encode_to(&mut extrinsic)
+			},
+			_ => EXTRINSIC_FORMAT.encode_to(&mut extrinsic),
+		}
+		self.call.encode_to(&mut extrinsic);
+		let mut out = Compact(extrinsic.len() as u64).encode();
+		out.extend(extrinsic);
+		out
+	}
+
+	/// An extrinsic in the envelope of Substrate, see `encode_standard`, with the caller of a
+	/// signed one looked up by its address with `caller`.
+	pub fn decode_standard<Address: Decode>(
+		bytes: &[u8],
+		caller: impl Fn(Address) -> Result<Caller, &'static str>,
+	) -> Result<Self, &'static str>
+	where
+		Call: Decode,
+		Signature: Decode,
+		Extra: Decode,
+	{
+		let mut input = bytes;
+		let len = Compact::decode(&mut input)?.0;
+		if input.len() as u64 != len {
+			return Err("Invalid extrinsic length");
+		}
+		let version = u8::decode(&mut input)?;
+		if version & !SIGNED != EXTRINSIC_FORMAT {
+			return Err("Unsupported extrinsic format");
+		}
+		let (caller, signature) = match version & SIGNED != 0 {
+			true => {
+				let (address, signature, extra) = Decode::decode(&mut input)?;
+				(Some(caller(address)?), Some((signature, extra)))
+			},
+			false => (None, None),
+		};
+		let call = codec::decode_all(input)?;
+		Ok(Extrinsic { caller, call, signature })
+	}
+}
+
+impl Encode for Era {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Era::Immortal => 0u8.encode_to(dest),
+			Era::Mortal { birth_block, period } => (1u8, birth_block, period).encode_to(dest),
+		}
+	}
+}
+
+impl Decode for Era {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		match u8::decode(input)? {
+			0 => Ok(Era::Immortal),
+			1 => {
+				let (birth_block, period) = Decode::decode(input)?;
+				Ok(Era::Mortal { birth_block, period })
+			},
+			_ => Err("Invalid era"),
+		}
+	}
+}
+
+impl TypeInfo for Era {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		let mortal = vec![registry.field::<u64>("birth_block"), registry.field::<u64>("period")];
+		TypeDef::Variant(vec![
+			unit("Immortal", 0),
+			TypeVariant { name: "Mortal", index: 1, fields: mortal },
+		])
+	}
+}
+
+impl<Nonce: Encode, Balance: Encode> Encode for SignedExtra<Nonce, Balance> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.nonce, self.era, &self.tip).encode_to(dest);
+	}
+}
+
+impl<Nonce: Decode, Balance: Decode> Decode for SignedExtra<Nonce, Balance> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (nonce, era, tip) = Decode::decode(input)?;
+		Ok(SignedExtra { nonce, era, tip })
+	}
+}
+
+impl<Nonce: TypeInfo, Balance: TypeInfo> TypeInfo for SignedExtra<Nonce, Balance> {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		TypeDef::Composite(vec![
+			registry.field::<Nonce>("nonce"),
+			registry.field::<Era>("era"),
+			registry.field::<Balance>("tip"),
+		])
+	}
+}
 
 pub type DispatchResult = Result<(), &'static str>;
 
+/// An error of a pallet in compact form: the index of the pallet in `RuntimeCall` and of the
+/// error in the `errors` of its metadata, which `RuntimeMetadata::error_name` turns back into
+/// names. Errors are only ever appended to the metadata of a pallet, so the indices stay stable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModuleError {
+	pub index: u8,
+	pub error: u8,
+}
+
+/// Why a dispatched call failed, as it is put in events and RPC responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchError {
+	Module(ModuleError),
+	/// The error is in the metadata of no pallet with an index.
+	Other,
+}
+
+impl Encode for ModuleError {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(self.index, self.error).encode_to(dest);
+	}
+}
+
+impl Decode for ModuleError {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (index, error) = Decode::decode(input)?;
+		Ok(ModuleError { index, error })
+	}
+}
+
+impl Encode for DispatchError {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			DispatchError::Module(error) => (0u8, error).encode_to(dest),
+			DispatchError::Other => 1u8.encode_to(dest),
+		}
+	}
+}
+
+impl Decode for DispatchError {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		match u8::decode(input)? {
+			0 => Ok(DispatchError::Module(Decode::decode(input)?)),
+			1 => Ok(DispatchError::Other),
+			_ => Err("Invalid dispatch error"),
+		}
+	}
+}
+
+impl TypeInfo for ModuleError {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		TypeDef::Composite(vec![registry.field::<u8>("index"), registry.field::<u8>("error")])
+	}
+}
+
+impl TypeInfo for DispatchError {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		let module = vec![registry.unnamed::<ModuleError>()];
+		TypeDef::Variant(vec![
+			TypeVariant { name: "Module", index: 0, fields: module },
+			unit("Other", 1),
+		])
+	}
+}
+
+/// The cost of executing a call, in abstract units of computation and storage access. Blocks
+/// only fit a limited amount of it, see `system::Config::MAX_BLOCK_WEIGHT`.
+pub type Weight = u64;
+
+/// Whether the caller of a call pays the transaction fee for it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Pays {
+	#[default]
+	Yes,
+	No,
+}
+
+/// What a call declares before it is dispatched: its worst case, which is what its extrinsic is
+/// checked against the block limit and charged for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DispatchInfo {
+	pub weight: Weight,
+	pub pays_fee: Pays,
+}
+
+/// What a call reports once dispatched: the weight it used if it did less work than declared,
+/// and whether its caller still pays, e.g. not for an oracle feed. The extrinsic is refunded
+/// the difference.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PostDispatchInfo {
+	pub actual_weight: Option<Weight>,
+	pub pays_fee: Pays,
+}
+
+impl PostDispatchInfo {
+	/// A call that used `weight`, and is paid for.
+	pub fn used(weight: Weight) -> Self {
+		Self { actual_weight: Some(weight), pays_fee: Pays::Yes }
+	}
+
+	/// The weight the call used, no more than it declared in `info`.
+	pub fn calc_actual_weight(&self, info: &DispatchInfo) -> Weight {
+		self.actual_weight.map_or(info.weight, |weight| weight.min(info.weight))
+	}
+
+	/// Whether the caller pays: unless either `info` or `self` says otherwise.
+	pub fn pays_fee(&self, info: &DispatchInfo) -> Pays {
+		match (info.pays_fee, self.pays_fee) {
+			(Pays::Yes, Pays::Yes) => Pays::Yes,
+			_ => Pays::No,
+		}
+	}
+}
+
+/// Calls that report nothing used their declared weight, and are paid for.
+impl From<()> for PostDispatchInfo {
+	fn from(_: ()) -> Self {
+		Self::default()
+	}
+}
+
+/// The result of a dispatched call that may report what it used, see `PostDispatchInfo`. A
+/// failing call is charged what it declared.
+pub type DispatchResultWithPostInfo = Result<PostDispatchInfo, &'static str>;
+
+/// The priority of a transaction in the pool: higher goes first.
+pub type TransactionPriority = u64;
+
+/// What the transaction pool needs to know about a transaction that can be included in a block.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidTransaction {
+	pub priority: TransactionPriority,
+	pub weight: Weight,
+}
+
+impl ValidTransaction {
+	/// What two checks of the same transaction found together: their priorities add up, and
+	/// the larger weight counts.
+	pub fn combine_with(self, other: Self) -> Self {
+		Self {
+			priority: self.priority.saturating_add(other.priority),
+			weight: self.weight.max(other.weight),
+		}
+	}
+}
+
+/// Why a transaction can't be included in a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidTransaction {
+	/// The caller can't pay the fee.
+	Payment,
+	/// The call doesn't fit in a block.
+	ExhaustsResources,
+	/// The extrinsic isn't signed by its caller.
+	BadProof,
+	/// The nonce of the extrinsic was used already, or its era is over.
+	Stale,
+	/// The nonce of the extrinsic is ahead of its caller's, so the extrinsics before it are
+	/// missing.
+	Future,
+	/// The birth block of the mortal extrinsic isn't among the recent blocks, so its signature
+	/// can't be checked.
+	AncientBirthBlock,
+	/// The call can't be submitted as a transaction, e.g. an inherent, or can't succeed for
+	/// this caller.
+	Call,
+}
+
+/// Implemented by pallets accepting some of their calls without a signature or fee, e.g. values
+/// an off-chain worker submits, which must then prove themselves what they are allowed to do.
+/// Every other unsigned call, but inherents, is rejected.
+pub trait ValidateUnsigned<BlockNumber> {
+	type Call;
+
+	/// Whether `call` may be included unsigned in block `now`, and how it ranks in the pool.
+	fn validate_unsigned(
+		&self,
+		now: BlockNumber,
+		call: &Self::Call,
+	) -> Result<ValidTransaction, InvalidTransaction>;
+}
+
+/// Where a call comes from, which decides what it is allowed to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RawOrigin<AccountId> {
+	/// Signed by an account, e.g. a regular extrinsic.
+	Signed(AccountId),
+	/// The privileged origin, e.g. sudo or an enacted referendum.
+	Root,
+	/// Unsigned, e.g. an inherent put in the block by its author.
+	None,
+	/// A motion of the collective, approved by `ayes` of its `members`.
+	Collective { ayes: u32, members: u32 },
+}
+
+/// The account that signed the call, or `"Bad origin"` for any other origin.
+pub fn ensure_signed<AccountId>(origin: RawOrigin<AccountId>) -> Result<AccountId, &'static str> {
+	match origin {
+		RawOrigin::Signed(who) => Ok(who),
+		_ => Err("Bad origin"),
+	}
+}
+
+pub fn ensure_root<AccountId>(origin: RawOrigin<AccountId>) -> DispatchResult {
+	match origin {
+		RawOrigin::Root => Ok(()),
+		_ => Err("Bad origin"),
+	}
+}
+
+pub fn ensure_none<AccountId>(origin: RawOrigin<AccountId>) -> DispatchResult {
+	match origin {
+		RawOrigin::None => Ok(()),
+		_ => Err("Bad origin"),
+	}
+}
+
+/// Root, or a motion of the collective that at least `share` of its members approved. Pallets
+/// whose privileged calls the collective may make check their origin with it in their config.
+pub fn ensure_root_or_collective<AccountId>(
+	origin: RawOrigin<AccountId>,
+	share: Percent,
+) -> DispatchResult {
+	match origin {
+		RawOrigin::Root => Ok(()),
+		RawOrigin::Collective { ayes, members }
+			if u64::from(ayes) * 100 >= u64::from(share.0) * u64::from(members) =>
+		{
+			Ok(())
+		},
+		_ => Err("Bad origin"),
+	}
+}
+
+/// Usually derived on the `Call` enum of a pallet with `Dispatchable`.
 pub trait Dispatch {
-    type Caller;
-    type Call;
+	type Origin;
+	type Call;
+
+	fn dispatch(&mut self, origin: Self::Origin, call: Self::Call) -> DispatchResult;
+}
+
+/// Resolves how calls address accounts, e.g. by a registered name, into the accounts. The
+/// runtime has one lookup, which the pallets taking addresses are dispatched with.
+pub trait Lookup {
+	type Source;
+	type Target;
+
+	fn lookup(&self, source: Self::Source) -> Result<Self::Target, &'static str>;
+}
+
+/// The lookup of configurations whose addresses are the accounts themselves, e.g. in tests.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IdentityLookup<T>(core::marker::PhantomData<T>);
+
+impl<T> Lookup for IdentityLookup<T> {
+	type Source = T;
+	type Target = T;
+
+	fn lookup(&self, source: T) -> Result<T, &'static str> {
+		Ok(source)
+	}
+}
+
+/// Run `f` as a transaction on `state`: its changes are committed if it returns `Ok` and
+/// discarded if it returns `Err`, so a failing call never leaves half of its writes behind.
+/// Storage lives in the pallets themselves, so the changes are made on the state directly and
+/// a copy taken beforehand is put back on failure. The largest maps of the runtime are
+/// `OverlayMap`s, which makes that copy cheap. It is put back with `clone_from`, so what refers
+/// into the state from outside the transaction keeps doing so, e.g. a fee taken out of balances.
+pub fn with_transaction<S: Clone, R, E>(
+	state: &mut S,
+	f: impl FnOnce(&mut S) -> Result<R, E>,
+) -> Result<R, E> {
+	let snapshot = state.clone();
+	let result = f(state);
+	if result.is_err() {
+		state.clone_from(&snapshot);
+	}
+	result
+}
+
+/// An entry of the state, with the proof that it is part of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofEntry {
+	pub key: Vec<u8>,
+	pub value: Vec<u8>,
+	pub proof: trie::Proof,
+}
+
+/// Proves the values of some keys against a state root, or that they aren't in the state. It
+/// holds the entries of the keys that are present, and the neighbours of the ones that aren't:
+/// two adjacent entries around the key show that nothing is stored in between.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StorageProof {
+	pub entries: Vec<ProofEntry>,
+}
+
+impl Encode for ProofEntry {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.key, &self.value, &self.proof).encode_to(dest);
+	}
+}
+
+impl Encode for StorageProof {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		self.entries.encode_to(dest);
+	}
+}
+
+/// Prove the values of `keys` in `storage`, whether they are stored or not.
+pub fn prove_storage(storage: &Storage, keys: &[Vec<u8>]) -> StorageProof {
+	let mut indices = std::collections::BTreeSet::new();
+	for key in keys {
+		// The entry of the key if there is one, and otherwise the ones right below and above.
+		let below = storage.range::<Vec<u8>, _>(..=key).count();
+		if storage.contains_key(key) {
+			indices.insert(below - 1);
+			continue;
+		}
+		if below > 0 {
+			indices.insert(below - 1);
+		}
+		if below < storage.len() {
+			indices.insert(below);
+		}
+	}
+	let entries = indices
+		.into_iter()
+		.map(|index| {
+			let (key, value) = storage.iter().nth(index).expect("Index is in the storage");
+			let proof = trie::prove_index(storage, index);
+			ProofEntry { key: key.clone(), value: value.clone(), proof }
+		})
+		.collect();
+	StorageProof { entries }
+}
 
-    fn dispatch(&mut self, caller: Self::Caller, call: Self::Call) -> DispatchResult;
+/// The value of `key` in the state with root `root`, as shown by `proof`.
+pub fn verify_proof(
+	root: &Hash,
+	proof: &StorageProof,
+	key: &[u8],
+) -> Result<Option<Vec<u8>>, &'static str> {
+	let mut below: Option<&ProofEntry> = None;
+	let mut above: Option<&ProofEntry> = None;
+	for entry in &proof.entries {
+		if !trie::verify(root, &entry.key, &entry.value, &entry.proof) {
+			return Err("Invalid proof");
+		}
+		match entry.key.as_slice().cmp(key) {
+			core::cmp::Ordering::Equal => return Ok(Some(entry.value.clone())),
+			core::cmp::Ordering::Less => {
+				if below.is_none_or(|below| below.key < entry.key) {
+					below = Some(entry);
+				}
+			},
+			core::cmp::Ordering::Greater => {
+				if above.is_none_or(|above| above.key > entry.key) {
+					above = Some(entry);
+				}
+			},
+		}
+	}
+	let absent = match (below, above) {
+		(Some(below), Some(above)) => below.proof.index + 1 == above.proof.index,
+		(Some(below), None) => below.proof.index + 1 == below.proof.leaves,
+		(None, Some(above)) => above.proof.index == 0,
+		(None, None) => *root == trie::EMPTY_ROOT,
+	};
+	match absent {
+		true => Ok(None),
+		false => Err("Incomplete proof"),
+	}
 }
 
+/// Per-block logic, run by `execute_block` before and after the extrinsics of every block.
+/// Both default to doing nothing, so only what is needed has to be implemented. Pallets whose
+/// hooks act on other pallets, e.g. the balances one, take them as extra arguments instead and
+/// are driven from the runtime's implementation.
+pub trait Hooks<BlockNumber> {
+	fn on_initialize(&mut self, _n: BlockNumber) {}
+
+	fn on_finalize(&mut self, _n: BlockNumber) {}
+}
+
+/// A filter over calls, e.g. deciding which calls a proxy of a given type may dispatch.
+pub trait InstanceFilter<Call> {
+	fn filter(&self, call: &Call) -> bool;
+}
+
+/// A percentage, e.g. the share of the fees that goes to the treasury.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Percent(pub u8);
+
+impl Percent {
+	/// `self` of `value`, rounded down. Splitting `value` first keeps the product from
+	/// overflowing.
+	pub fn mul_floor<N>(&self, value: N) -> N
+	where
+		N: From<u8> + Zero + CheckedMul + CheckedDiv + CheckedSub + CheckedAdd + Copy,
+	{
+		let parts = N::from(self.0.min(100));
+		let hundred = N::from(100);
+		let whole = value.checked_div(&hundred).unwrap_or(N::zero());
+		let rest = value.checked_sub(&whole.checked_mul(&hundred).unwrap_or(N::zero()));
+		let rest = rest.unwrap_or(N::zero());
+
+		let whole = whole.checked_mul(&parts).unwrap_or(N::zero());
+		let rest = rest.checked_mul(&parts).and_then(|rest| rest.checked_div(&hundred));
+		whole.checked_add(&rest.unwrap_or(N::zero())).unwrap_or(value)
+	}
+}
+
+/// A source of randomness, e.g. for lotteries or picking block authors. Returns the random
+/// value for `subject` along with the block number since which it could have been known.
+pub trait Randomness<Output, BlockNumber> {
+	fn random(&self, subject: &[u8]) -> (Output, BlockNumber);
+}
+
+pub type SessionIndex = u32;
+
+/// Decides who validates from a session on, e.g. based on staking results.
+pub trait SessionManager<ValidatorId> {
+	/// The validators for session `new_index`, or `None` to keep the current ones.
+	fn new_session(&mut self, new_index: SessionIndex) -> Option<Vec<ValidatorId>>;
+}
+
+/// Keeps the validators that are in place.
+impl<ValidatorId> SessionManager<ValidatorId> for () {
+	fn new_session(&mut self, _new_index: SessionIndex) -> Option<Vec<ValidatorId>> {
+		None
+	}
+}
+
+/// Told about every new session, e.g. consensus taking over the new authorities.
+pub trait SessionHandler<ValidatorId, Keys> {
+	/// `changed` is whether the validators or their keys differ from the previous session.
+	fn on_new_session(&mut self, changed: bool, validators: &[(ValidatorId, Keys)]);
+}
+
+impl<ValidatorId, Keys> SessionHandler<ValidatorId, Keys> for () {
+	fn on_new_session(&mut self, _changed: bool, _validators: &[(ValidatorId, Keys)]) {}
+}
+
+#[cfg(test)]
+mod test {
+	/// A pallet declared with `#[pallet]`, counting the steps of accounts.
+	mod counter {
+		use crate::{
+			support::{ensure_root, ensure_signed, DispatchResult},
+			system::{self, OriginFor},
+		};
+
+		pub struct Pallet<T: system::Config> {
+			pub counts: Vec<(T::AccountId, u32)>,
+		}
+
+		#[crate::support::pallet(
+			dispatch(step: u32, origin: OriginFor<T>),
+			errors(TooManySteps = "Too many steps"),
+		)]
+		impl<T: system::Config> Pallet<T> {
+			/// Count `times` steps for the caller.
+			#[call(index = 0, weight = 10 * u64::from(*times))]
+			fn count(times: u32) -> DispatchResult {
+				let caller = ensure_signed(origin)?;
+				let steps = step.checked_mul(times).ok_or(Error::TooManySteps)?;
+				self.counts.push((caller, steps));
+				Ok(())
+			}
+
+			/// Root only: forget the steps of `who`.
+			#[call(index = 1, weight = 1)]
+			fn reset(who: T::AccountId) -> DispatchResult {
+				ensure_root(origin)?;
+				self.counts.retain(|(account, _)| *account != who);
+				Ok(())
+			}
+		}
+	}
+
+	#[test]
+	fn calls_are_generated_from_the_functions_of_the_pallet() {
+		use self::counter::{Call, Error, Pallet};
+		use crate::{
+			codec::{decode_all, Encode},
+			mock::Test,
+			support::RawOrigin,
+		};
+
+		let call = Call::<Test>::Count { times: 3 };
+		assert_eq!(call.weight(), 30);
+		assert_eq!(Call::<Test>::Reset { who: "alice".to_string() }.index(), 1);
+		let encoded = call.encode();
+		assert_eq!(encoded, (0u8, 3u32).encode());
+		let decoded = decode_all::<Call<Test>>(&encoded).map(|call| call.encode());
+		assert_eq!(decoded, Ok(encoded));
+		assert_eq!(decode_all::<Call<Test>>(&[2]).map(|_| ()), Err("Invalid call"));
+
+		let mut pallet = Pallet::<Test> { counts: Vec::new() };
+		pallet.dispatch(2, RawOrigin::Signed("alice".to_string()), call).unwrap();
+		assert_eq!(pallet.counts, vec![("alice".to_string(), 6)]);
+		assert_eq!(
+			pallet.dispatch(2, RawOrigin::None, Call::Count { times: 1 }),
+			Err("Bad origin")
+		);
+		let too_many = Call::Count { times: u32::MAX };
+		let result = pallet.dispatch(2, RawOrigin::Signed("bob".to_string()), too_many);
+		assert_eq!(result, Err(Error::TooManySteps.into()));
+		pallet
+			.dispatch(2, RawOrigin::Root, Call::Reset { who: "alice".to_string() })
+			.unwrap();
+		assert!(pallet.counts.is_empty());
+
+		let names: Vec<_> = Call::<Test>::metadata().iter().map(|call| call.name).collect();
+		assert_eq!(names, vec!["Count", "Reset"]);
+		assert_eq!(Error::metadata(), vec!["Too many steps"]);
+	}
+
+	#[test]
+	fn failed_transactions_are_rolled_back() {
+		let mut state = vec![1u8];
+
+		let result = super::with_transaction(&mut state, |state| {
+			state.push(2);
+			Ok::<_, &'static str>(state.len())
+		});
+		assert_eq!(result, Ok(2));
+
+		let result = super::with_transaction(&mut state, |state| {
+			state.push(3);
+			Err::<(), _>("Something went wrong")
+		});
+		assert_eq!(result, Err("Something went wrong"));
+		assert_eq!(state, vec![1, 2]);
+	}
+
+	#[test]
+	fn long_signing_payloads_are_hashed() {
+		use crate::codec::Encode;
+
+		let extra = super::SignedExtra { nonce: 1u32, era: super::Era::Immortal, tip: 3u128 };
+		let short = super::signing_payload(&vec![7u8; 8], &extra, &[2; 32], &[2; 32]);
+		assert_eq!(short, (vec![7u8; 8], (1u32, 0u8, 3u128), [2u8; 32], [2u8; 32]).encode());
+		let long = super::signing_payload(&vec![7u8; 300], &extra, &[2; 32], &[2; 32]);
+		let encoded = (vec![7u8; 300], (1u32, 0u8, 3u128), [2u8; 32], [2u8; 32]).encode();
+		assert_eq!(long, crate::hashing::blake2_256(&encoded).to_vec());
+	}
+
+	#[test]
+	fn mortal_eras_end_after_their_period() {
+		use crate::codec::{decode_all, Encode};
+
+		let era = super::Era::Mortal { birth_block: 10, period: 4 };
+		let alive: Vec<u64> = (0..20).filter(|&n| era.is_alive(n)).collect();
+		assert_eq!(alive, vec![11, 12, 13, 14]);
+		assert!(super::Era::Immortal.is_alive(u64::MAX));
+		for era in [era, super::Era::Immortal] {
+			assert_eq!(decode_all::<super::Era>(&era.encode()), Ok(era));
+		}
+	}
+
+	#[test]
+	fn storage_proofs() {
+		let storage: super::Storage =
+			[(vec![1], vec![10]), (vec![3], vec![30]), (vec![5], vec![50])]
+				.into_iter()
+				.collect();
+		let root = crate::trie::root(&storage);
+		let keys = [vec![0], vec![3], vec![4], vec![6]];
+		let proof = super::prove_storage(&storage, &keys);
+
+		assert_eq!(super::verify_proof(&root, &proof, &[3]), Ok(Some(vec![30])));
+		for absent in [[0], [4], [6]] {
+			assert_eq!(super::verify_proof(&root, &proof, &absent), Ok(None));
+		}
+		// Key 0 lies below 3, but the proof doesn't show that nothing is stored below 3.
+		let partial = super::prove_storage(&storage, &[vec![4]]);
+		assert_eq!(super::verify_proof(&root, &partial, &[0]), Err("Incomplete proof"));
+		assert_eq!(super::verify_proof(&[1; 32], &proof, &[3]), Err("Invalid proof"));
+
+		let empty = super::Storage::new();
+		let proof = super::prove_storage(&empty, &keys);
+		assert_eq!(super::verify_proof(&crate::trie::EMPTY_ROOT, &proof, &[3]), Ok(None));
+	}
+}
@@ -0,0 +1,235 @@
+//! Block sync. A node that starts behind asks the peers it discovers for their best block,
+//! requests the blocks it misses from the best one, a range of numbers at a time, and imports
+//! them in order. Once it caught up it follows the chain through gossip. A node on a fork, e.g.
+//! one that authored blocks while it was offline, downloads the blocks of the best peer from
+//! its finalized block on instead, as only they are sure to be on both chains.
+
+use std::collections::BTreeMap;
+
+use crate::{
+	chain::{self, Chain},
+	finality::SignedVote,
+	import_queue::ImportError,
+	network::{Message, Peer, PeerId},
+	types,
+};
+
+/// The most blocks asked for in a single request.
+pub const MAX_BLOCKS_PER_REQUEST: types::BlockNumber = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncState {
+	/// Waiting for the peers to tell their best block.
+	Discovering,
+	/// Downloading the blocks up to `target` from `peer`.
+	Downloading { peer: PeerId, target: types::BlockNumber },
+	/// Caught up: new blocks come through gossip.
+	Live,
+}
+
+/// The gossip `BlockSync::poll` received besides blocks.
+#[derive(Default)]
+pub struct Received {
+	pub transactions: Vec<types::Extrinsic>,
+	pub votes: Vec<SignedVote>,
+}
+
+pub struct BlockSync {
+	state: SyncState,
+	/// The best block number each peer announced.
+	peers: BTreeMap<PeerId, types::BlockNumber>,
+	/// The number of the next block to download of a fork that isn't the best chain yet.
+	fork: Option<types::BlockNumber>,
+}
+
+impl BlockSync {
+	pub fn new() -> Self {
+		Self { state: SyncState::Discovering, peers: BTreeMap::new(), fork: None }
+	}
+
+	pub fn state(&self) -> SyncState {
+		self.state
+	}
+
+	/// Ask the peers on the network for their best block. Without any, the node is the first
+	/// one and is live right away.
+	pub fn start(&mut self, peer: &Peer) {
+		let others = peer.discover();
+		if others.is_empty() {
+			self.state = SyncState::Live;
+		}
+		for other in others {
+			peer.send_to(other, Message::StatusRequest);
+		}
+	}
+
+	/// Handle the messages received since the last call: answer the requests of other peers,
+	/// import the blocks received, and request the next ones. Returns the transactions and
+	/// votes received.
+	pub fn poll(
+		&mut self,
+		peer: &mut Peer,
+		chain: &mut Chain,
+		local_now: types::Moment,
+	) -> Received {
+		let mut received = Received::default();
+		for (from, message) in peer.receive() {
+			match message {
+				Message::StatusRequest => {
+					peer.send_to(from, Message::Status { best_number: chain.best_number() });
+				},
+				Message::BlockRequest { from: first, to } => {
+					let to = to.min(first.saturating_add(MAX_BLOCKS_PER_REQUEST - 1));
+					peer.send_to(from, Message::Blocks(chain.best_chain_blocks(first, to)));
+				},
+				Message::Status { best_number } => {
+					self.peers.insert(from, best_number);
+					if self.state == SyncState::Discovering {
+						self.request_next(peer, chain);
+					}
+				},
+				Message::Blocks(blocks) => {
+					if !matches!(self.state, SyncState::Downloading { peer, .. } if peer == from) {
+						continue;
+					}
+					let first = blocks.first().map(|block| block.header.block_number);
+					let last = blocks
+						.last()
+						.map(|block| (block.header.block_number, chain::block_hash(block)));
+					let imported = blocks
+						.into_iter()
+						.try_for_each(|block| chain.import(block, local_now).map(drop));
+					let forked_above =
+						first.is_some_and(|first| first > chain.finalized_number() + 1);
+					self.fork = match (imported, last) {
+						(Ok(()), Some((number, hash))) => {
+							(chain.best_hash() != hash).then_some(number + 1)
+						},
+						// The blocks of the peer are on a fork of the best chain.
+						(Err(ImportError::UnknownParent), _) if forked_above => {
+							Some(chain.finalized_number() + 1)
+						},
+						// The peer sent bad blocks, or none: sync from another one.
+						_ => {
+							self.peers.remove(&from);
+							None
+						},
+					};
+					self.request_next(peer, chain);
+				},
+				Message::Block(block) => {
+					// Blocks after the one being synced to can't be imported yet.
+					if self.state == SyncState::Live {
+						let _ = chain.import(block, local_now);
+					}
+				},
+				Message::Transaction(extrinsic) => received.transactions.push(extrinsic),
+				Message::Vote(vote) => received.votes.push(vote),
+			}
+		}
+		received
+	}
+
+	/// Request the next blocks from the peer with the best chain, if it is ahead.
+	fn request_next(&mut self, peer: &Peer, chain: &Chain) {
+		let best = self.peers.iter().max_by_key(|(_, best_number)| **best_number);
+		let from = self.fork.unwrap_or(chain.best_number() + 1);
+		self.state = match best {
+			Some((&id, &target)) if target > chain.best_number() && target >= from => {
+				let to = target.min(from + MAX_BLOCKS_PER_REQUEST - 1);
+				peer.send_to(id, Message::BlockRequest { from, to });
+				SyncState::Downloading { peer: id, target }
+			},
+			_ => {
+				self.fork = None;
+				SyncState::Live
+			},
+		};
+	}
+}
+
+impl Default for BlockSync {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{BlockSync, SyncState};
+	use crate::{
+		chain::Chain, import_queue, network::Network, support, wallet::dev_account, Runtime,
+		RuntimeCall,
+	};
+
+	/// Author a block on top of the best block of `chain`.
+	fn author(chain: &mut Chain) -> crate::types::Block {
+		author_at(chain, 1_000)
+	}
+
+	/// Author a block on top of the best block of `chain`, with a timestamp of at least `now`.
+	fn author_at(chain: &mut Chain, now: crate::types::Moment) -> crate::types::Block {
+		let state = chain.state();
+		let now = state.timestamp.create_inherent(now).unwrap();
+		let extrinsics = vec![support::Extrinsic {
+			caller: None,
+			call: RuntimeCall::Timestamp(now),
+			signature: None,
+		}];
+		let block = import_queue::build_block(state, &dev_account("alice"), extrinsics).unwrap();
+		chain.import(block.clone(), 100_000).unwrap();
+		block
+	}
+
+	#[test]
+	fn new_nodes_catch_up_then_follow_gossip() {
+		let network = Network::new();
+		let mut first = network.join();
+		let (mut first_chain, mut first_sync) = (Chain::new(Runtime::new()), BlockSync::new());
+		first_sync.start(&first);
+		assert_eq!(first_sync.state(), SyncState::Live);
+		for _ in 0..3 {
+			author(&mut first_chain);
+		}
+
+		let mut second = network.join();
+		let (mut second_chain, mut second_sync) = (Chain::new(Runtime::new()), BlockSync::new());
+		second_sync.start(&second);
+		first_sync.poll(&mut first, &mut first_chain, 100_000);
+		second_sync.poll(&mut second, &mut second_chain, 100_000);
+		assert_eq!(second_sync.state(), SyncState::Downloading { peer: 0, target: 3 });
+
+		first_sync.poll(&mut first, &mut first_chain, 100_000);
+		second_sync.poll(&mut second, &mut second_chain, 100_000);
+		assert_eq!(second_sync.state(), SyncState::Live);
+		assert_eq!(second_chain.best_hash(), first_chain.best_hash());
+
+		let block = author(&mut first_chain);
+		first.broadcast_block(block);
+		second_sync.poll(&mut second, &mut second_chain, 100_000);
+		assert_eq!(second_chain.best_number(), 4);
+	}
+
+	#[test]
+	fn nodes_on_a_fork_download_the_best_chain_from_their_finalized_block() {
+		let network = Network::new();
+		let (mut first, mut second) = (network.join(), network.join());
+		let (mut first_chain, mut first_sync) = (Chain::new(Runtime::new()), BlockSync::new());
+		let (mut second_chain, mut second_sync) = (Chain::new(Runtime::new()), BlockSync::new());
+		for _ in 0..3 {
+			author(&mut first_chain);
+		}
+		for _ in 0..2 {
+			author_at(&mut second_chain, 5_000);
+		}
+
+		second_sync.start(&second);
+		// The third block of the first node doesn't build on the second block of the second.
+		for _ in 0..3 {
+			first_sync.poll(&mut first, &mut first_chain, 100_000);
+			second_sync.poll(&mut second, &mut second_chain, 100_000);
+		}
+		assert_eq!(second_sync.state(), SyncState::Live);
+		assert_eq!(second_chain.best_hash(), first_chain.best_hash());
+	}
+}
@@ -1,23 +1,295 @@
-use std::{collections::BTreeMap, ops::AddAssign};
+use core::fmt::Debug;
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	ops::AddAssign,
+};
 
 use num::{CheckedAdd, CheckedSub, One, Zero};
 
+use crate::{
+	codec::{Decode, Encode},
+	hashing::Hash,
+	metadata::{field, storage, variant, PalletMetadata, Registry, TypeDef, TypeInfo},
+	migrations::{self, StorageVersion},
+	overlay::OverlayMap,
+	support::{DispatchError, DispatchResult, RawOrigin, Weight},
+	trie::{self, storage_key, Storage},
+};
+
 pub trait Config {
-    type AccountId: Ord + Clone;
-    type BlockNumber: Zero + One + CheckedSub + CheckedAdd + Copy + AddAssign;
-    type Nonce: Ord + Clone + Zero + One + CheckedSub + CheckedAdd + Copy;
+	type AccountId: Debug + Ord + Clone + Encode + Decode;
+	type BlockNumber: Debug
+		+ Encode
+		+ Decode
+		+ Zero
+		+ One
+		+ CheckedSub
+		+ CheckedAdd
+		+ Copy
+		+ AddAssign
+		+ Ord;
+	type Nonce: Debug + Encode + Decode + Ord + Clone + Zero + One + CheckedSub + CheckedAdd + Copy;
+	/// The events of all pallets of the runtime.
+	type RuntimeEvent: Debug + Clone;
+	/// The total weight the extrinsics of a block may consume.
+	const MAX_BLOCK_WEIGHT: Weight;
+	/// The most signed extrinsics of a single account in a block, so no account can fill
+	/// blocks on its own however much it pays.
+	const MAX_EXTRINSICS_PER_ACCOUNT: u32;
+}
+
+/// How many of the most recent block hashes are kept.
+pub const BLOCK_HASH_COUNT: usize = 256;
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+/// How many references to an account there are.
+pub type RefCount = u32;
+
+/// The origin of the calls dispatched to the pallets of a runtime.
+pub type OriginFor<T> = RawOrigin<<T as Config>::AccountId>;
+
+#[derive(Debug)]
+pub struct AccountInfo<T: Config> {
+	pub nonce: T::Nonce,
+	/// The number of references that let the account exist, e.g. a balance.
+	pub providers: RefCount,
+	/// The number of references that depend on the account existing, e.g. a lock on its
+	/// balance. An account can't lose its last provider while it has consumers.
+	pub consumers: RefCount,
+}
+
+// Derived, it would need `T: Clone`, which storage maps can't require of the configuration.
+impl<T: Config> Clone for AccountInfo<T> {
+	fn clone(&self) -> Self {
+		Self { nonce: self.nonce, providers: self.providers, consumers: self.consumers }
+	}
+}
+
+impl<T: Config> AccountInfo<T> {
+	fn new() -> Self {
+		Self { nonce: T::Nonce::zero(), providers: 0, consumers: 0 }
+	}
+}
+
+impl<T: Config> Encode for AccountInfo<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(self.nonce, self.providers, self.consumers).encode_to(dest);
+	}
+}
+
+impl<T: Config> Decode for AccountInfo<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (nonce, providers, consumers) = Decode::decode(input)?;
+		Ok(AccountInfo { nonce, providers, consumers })
+	}
+}
+
+/// A reference a pallet took to an account or dropped, for pallets that don't borrow system,
+/// e.g. balances. The runtime applies them with `Pallet::apply_ref_changes`, in order, so an
+/// account gains its provider before its consumers and loses them before its provider.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefChange<AccountId> {
+	IncProviders(AccountId),
+	DecProviders(AccountId),
+	IncConsumers(AccountId),
+	DecConsumers(AccountId),
 }
 
 #[derive(Debug)]
+pub struct EventRecord<T: Config> {
+	/// The extrinsic that emitted the event, or `None` for the block hooks.
+	pub extrinsic_index: Option<u32>,
+	pub event: T::RuntimeEvent,
+}
+
+// Derived, it would need `T: Clone`, as for `AccountInfo`.
+impl<T: Config> Clone for EventRecord<T> {
+	fn clone(&self) -> Self {
+		Self { extrinsic_index: self.extrinsic_index, event: self.event.clone() }
+	}
+}
+
+/// The classes of calls the runtime doesn't dispatch, by their indices in `RuntimeCall`, e.g.
+/// transfers during a migration. Root isn't filtered, so it can always lift the filter again.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallFilter {
+	/// Pallets none of whose calls are dispatched.
+	pub pallets: Vec<u8>,
+	/// Single calls, by the index of their pallet and their own.
+	pub calls: Vec<(u8, u8)>,
+}
+
+impl CallFilter {
+	/// Whether the call at index `call` of the pallet at index `pallet` may be dispatched.
+	pub fn allows(&self, pallet: u8, call: u8) -> bool {
+		!self.pallets.contains(&pallet) && !self.calls.contains(&(pallet, call))
+	}
+}
+
+impl Encode for CallFilter {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.pallets, &self.calls).encode_to(dest);
+	}
+}
+
+impl Decode for CallFilter {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (pallets, calls) = Decode::decode(input)?;
+		Ok(CallFilter { pallets, calls })
+	}
+}
+
+impl TypeInfo for CallFilter {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		TypeDef::Composite(vec![
+			registry.field::<Vec<u8>>("pallets"),
+			registry.field::<Vec<(u8, u8)>>("calls"),
+		])
+	}
+}
+
+#[derive(Debug, Clone)]
+pub enum Call {
+	/// Replace the code of the runtime, from the next block on. Root only; the runtime checks
+	/// the version the code starts with.
+	SetCode { code: Vec<u8> },
+	/// Replace the call filter. Root only.
+	SetCallFilter { filter: CallFilter },
+}
+
+impl Encode for Call {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::SetCode { code } => (0u8, code).encode_to(dest),
+			Call::SetCallFilter { filter } => (1u8, filter).encode_to(dest),
+		}
+	}
+}
+
+impl Decode for Call {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::SetCode { code: Decode::decode(input)? },
+			1 => Call::SetCallFilter { filter: Decode::decode(input)? },
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+	/// An extrinsic failed, with its error in compact form.
+	ExtrinsicFailed { error: DispatchError },
+}
+
+impl Event {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Event::ExtrinsicFailed { .. } => "ExtrinsicFailed",
+		}
+	}
+
+	/// The events of system are about no account.
+	pub fn accounts<AccountId>(&self) -> Vec<&AccountId> {
+		Vec::new()
+	}
+}
+
+impl Call {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::SetCode { .. } => 0,
+			Call::SetCallFilter { .. } => 1,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::SetCode { code } => 10_000 + code.len() as Weight,
+			Call::SetCallFilter { filter } => {
+				10_000 + (filter.pallets.len() + filter.calls.len()) as Weight
+			},
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
 pub struct Pallet<T: Config> {
 	block_number: T::BlockNumber,
-	nonce: BTreeMap<T::AccountId, T::Nonce>,
+	accounts: OverlayMap<T::AccountId, AccountInfo<T>>,
+	extrinsic_index: Option<u32>,
+	/// The weight consumed by the extrinsics of the current block.
+	block_weight: Weight,
+	/// The number of signed extrinsics of each account in the current block.
+	account_extrinsics: BTreeMap<T::AccountId, u32>,
+	/// The hashes of the last `BLOCK_HASH_COUNT` blocks.
+	block_hash: BTreeMap<T::BlockNumber, Hash>,
+	/// The state root of the genesis state, recorded when the first block is executed.
+	genesis_hash: Option<Hash>,
+	/// The events emitted during the current block.
+	events: Vec<EventRecord<T>>,
+	/// The events of system itself, until the runtime collects them.
+	pending_events: Vec<Event>,
+	/// The runtime code set by `SetCode`, if any.
+	code: Option<Vec<u8>>,
+	/// Code set during the current block, which takes effect once it is finalized.
+	pending_code: Option<Vec<u8>>,
+	call_filter: CallFilter,
 }
 
-impl<T: Config> Pallet<T> 
-{
+impl<T: Config> Pallet<T> {
 	pub fn new() -> Self {
-		Self { block_number: T::BlockNumber::zero(), nonce: BTreeMap::new() }
+		Self {
+			block_number: T::BlockNumber::zero(),
+			accounts: OverlayMap::new(),
+			extrinsic_index: None,
+			block_weight: 0,
+			account_extrinsics: BTreeMap::new(),
+			block_hash: BTreeMap::new(),
+			genesis_hash: None,
+			events: Vec::new(),
+			pending_events: Vec::new(),
+			code: None,
+			pending_code: None,
+			call_filter: CallFilter::default(),
+		}
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "System",
+			index: None,
+			calls: vec![
+				variant("SetCode", 0, vec![field::<Vec<u8>>("code")]),
+				variant("SetCallFilter", 1, vec![field::<CallFilter>("filter")]),
+			],
+			storage: vec![
+				storage::<T::BlockNumber>("block_number"),
+				storage::<OverlayMap<T::AccountId, AccountInfo<T>>>("accounts"),
+				storage::<Option<u32>>("extrinsic_index"),
+				storage::<Weight>("block_weight"),
+				storage::<BTreeMap<T::AccountId, u32>>("account_extrinsics"),
+				storage::<BTreeMap<T::BlockNumber, Hash>>("block_hash"),
+				storage::<Option<Hash>>("genesis_hash"),
+				storage::<Vec<EventRecord<T>>>("events"),
+				storage::<Option<Vec<u8>>>("code"),
+				storage::<Option<Vec<u8>>>("pending_code"),
+				storage::<CallFilter>("call_filter"),
+			],
+			events: vec![variant("ExtrinsicFailed", 0, vec![field::<DispatchError>("error")])],
+			// Any pallet may fail with `Bad origin`, see `support::ensure_signed`.
+			errors: vec![
+				"Account has consumers",
+				"Bad origin",
+				"Block weight limit exceeded",
+				"No providers",
+				"Call is filtered",
+				"Too many extrinsics of the account",
+				"Nonce overflow",
+			],
+		}
 	}
 
 	pub fn block_number(&self) -> T::BlockNumber {
@@ -25,43 +297,312 @@ impl<T: Config> Pallet<T>
 	}
 
 	pub fn inc_block_number(&mut self) {
-		let new_block_number = self.block_number.checked_add(&T::BlockNumber::one()).unwrap_or(T::BlockNumber::zero());
+		let new_block_number = self
+			.block_number
+			.checked_add(&T::BlockNumber::one())
+			.unwrap_or(T::BlockNumber::zero());
 		self.block_number = new_block_number;
 	}
 
-	pub fn inc_nonce(&mut self, who: &T::AccountId) {
-        let zero = T::Nonce::zero();
-		let nonce = self.nonce.get(who).unwrap_or(&zero);
-		let new_nonce = nonce.checked_add(&T::Nonce::one()).unwrap();
+	/// The index of the extrinsic currently being executed, if any.
+	pub fn extrinsic_index(&self) -> Option<u32> {
+		self.extrinsic_index
+	}
+
+	pub fn set_extrinsic_index(&mut self, index: Option<u32>) {
+		self.extrinsic_index = index;
+	}
+
+	pub fn block_weight(&self) -> Weight {
+		self.block_weight
+	}
+
+	/// Record the weight of the extrinsics of the current block, which may not exceed
+	/// `MAX_BLOCK_WEIGHT`.
+	pub fn set_block_weight(&mut self, weight: Weight) -> DispatchResult {
+		if weight > T::MAX_BLOCK_WEIGHT {
+			return Err("Block weight limit exceeded");
+		}
+		self.block_weight = weight;
+		Ok(())
+	}
 
-		self.nonce.insert(who.clone(), new_nonce);
+	/// The number of signed extrinsics of `who` in the current block so far.
+	pub fn account_extrinsics(&self, who: &T::AccountId) -> u32 {
+		self.account_extrinsics.get(who).copied().unwrap_or(0)
 	}
 
-	pub fn get_nonce(&mut self, who: &T::AccountId) -> T::Nonce {
-		*self.nonce.get(who).unwrap()
+	/// Count a signed extrinsic of `who` in the current block, unless it has
+	/// `MAX_EXTRINSICS_PER_ACCOUNT` already.
+	pub fn note_account_extrinsic(&mut self, who: &T::AccountId) -> DispatchResult {
+		let count = self.account_extrinsics.entry(who.clone()).or_insert(0);
+		if *count >= T::MAX_EXTRINSICS_PER_ACCOUNT {
+			return Err("Too many extrinsics of the account");
+		}
+		*count += 1;
+		Ok(())
+	}
+
+	/// Take `weight` off the weight of the current block, e.g. what an extrinsic declared but
+	/// didn't use.
+	pub fn refund_block_weight(&mut self, weight: Weight) {
+		self.block_weight = self.block_weight.saturating_sub(weight);
+	}
+
+	/// Take what `executed`, a copy of `base` that executed extrinsics touching only
+	/// `accounts`, changed since: the entries of those accounts, the weight refunded and the
+	/// events. `self` is `base` or another copy merged into it, so the events are put back in
+	/// order with `sort_events` once all copies are merged.
+	pub fn merge(&mut self, base: &Self, executed: &Self, accounts: &BTreeSet<T::AccountId>) {
+		for account in accounts {
+			self.accounts.copy_entry(&executed.accounts, account);
+			match executed.account_extrinsics.get(account) {
+				Some(count) => self.account_extrinsics.insert(account.clone(), *count),
+				None => self.account_extrinsics.remove(account),
+			};
+		}
+		let refunded = base.block_weight.saturating_sub(executed.block_weight);
+		self.refund_block_weight(refunded);
+		self.events.extend(executed.events[base.events.len()..].iter().cloned());
+	}
+
+	/// Order the events from the `start`th one on by the extrinsics that emitted them, as if
+	/// the extrinsics were executed in order. Events of the same extrinsic keep their order.
+	pub fn sort_events(&mut self, start: usize) {
+		self.events[start..].sort_by_key(|record| record.extrinsic_index);
+	}
+
+	/// The hash of block `n`, if it is one of the last `BLOCK_HASH_COUNT` blocks.
+	pub fn block_hash(&self, n: T::BlockNumber) -> Option<Hash> {
+		self.block_hash.get(&n).copied()
+	}
+
+	/// Record `event`, tagged with the extrinsic being executed.
+	pub fn deposit_event(&mut self, event: T::RuntimeEvent) {
+		self.events.push(EventRecord { extrinsic_index: self.extrinsic_index, event });
+	}
+
+	/// Note that the extrinsic being executed failed with `error`.
+	pub fn note_extrinsic_failed(&mut self, error: DispatchError) {
+		self.pending_events.push(Event::ExtrinsicFailed { error });
+	}
+
+	/// Hand the events deposited so far to the caller.
+	pub fn take_events(&mut self) -> Vec<Event> {
+		core::mem::take(&mut self.pending_events)
+	}
+
+	pub fn events(&self) -> &[EventRecord<T>] {
+		&self.events
+	}
+
+	pub fn events_for_extrinsic(&self, idx: u32) -> impl Iterator<Item = &T::RuntimeEvent> {
+		self.events
+			.iter()
+			.filter(move |record| record.extrinsic_index == Some(idx))
+			.map(|record| &record.event)
+	}
+
+	/// Called at the start of every block: the events of the previous block are cleared.
+	pub fn on_initialize(&mut self) {
+		self.events.clear();
+		self.account_extrinsics.clear();
+	}
+
+	/// Called at the end of every block: `hash` is recorded as the hash of block `n`, and the
+	/// oldest hash dropped once there are too many.
+	/// Record the hash of block `n`, and switch to the code set during it, if any.
+	pub fn on_finalize(&mut self, n: T::BlockNumber, hash: Hash) {
+		self.block_hash.insert(n, hash);
+		while self.block_hash.len() > BLOCK_HASH_COUNT {
+			self.block_hash.pop_first();
+		}
+		if let Some(code) = self.pending_code.take() {
+			self.code = Some(code);
+		}
+	}
+
+	pub fn genesis_hash(&self) -> Option<Hash> {
+		self.genesis_hash
+	}
+
+	pub fn set_genesis_hash(&mut self, hash: Hash) {
+		self.genesis_hash = Some(hash);
+	}
+
+	pub fn code(&self) -> Option<&[u8]> {
+		self.code.as_deref()
+	}
+
+	pub fn call_filter(&self) -> &CallFilter {
+		&self.call_filter
+	}
+
+	/// Replace the call filter, without any origin check.
+	pub fn set_call_filter(&mut self, filter: CallFilter) {
+		self.call_filter = filter;
+	}
+
+	/// Set `code` to replace the runtime at the end of the block, without any origin check.
+	pub fn set_code(&mut self, code: Vec<u8>) {
+		self.pending_code = Some(code);
+	}
+
+	pub fn account(&self, who: &T::AccountId) -> Option<&AccountInfo<T>> {
+		self.accounts.get(who)
+	}
+
+	pub fn account_exists(&self, who: &T::AccountId) -> bool {
+		self.accounts.get(who).is_some_and(|info| info.providers > 0)
+	}
+
+	/// Fails once the nonce of `who` can't grow anymore, which leaves it unchanged.
+	pub fn inc_nonce(&mut self, who: &T::AccountId) -> DispatchResult {
+		let info = self.accounts.get_or_insert_with(who.clone(), AccountInfo::new);
+		info.nonce = info.nonce.checked_add(&T::Nonce::one()).ok_or("Nonce overflow")?;
+		Ok(())
+	}
+
+	pub fn get_nonce(&self, who: &T::AccountId) -> T::Nonce {
+		self.accounts.get(who).map(|info| info.nonce).unwrap_or(T::Nonce::zero())
+	}
+
+	/// Merge the changes of the block into the committed storage, see `overlay`.
+	pub fn commit(&mut self) {
+		self.accounts.commit();
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "System", STORAGE_VERSION);
+		storage.insert(storage_key("System", "block_number", &()), self.block_number.encode());
+		if let Some(code) = &self.code {
+			storage.insert(storage_key("System", "code", &()), code.encode());
+		}
+		if let Some(hash) = &self.genesis_hash {
+			storage.insert(storage_key("System", "genesis_hash", &()), hash.encode());
+		}
+		if self.call_filter != CallFilter::default() {
+			let filter = self.call_filter.encode();
+			storage.insert(storage_key("System", "call_filter", &()), filter);
+		}
+		for (who, info) in &self.accounts {
+			storage.insert(storage_key("System", "account", who), info.encode());
+		}
+	}
+
+	/// Restore the state `storage_entries` put in `storage`, with the hashes of the recent
+	/// blocks, which aren't part of it.
+	pub fn restore_entries(
+		&mut self,
+		storage: &Storage,
+		block_hashes: Vec<(T::BlockNumber, Hash)>,
+	) -> DispatchResult {
+		migrations::ensure_version(storage, "System", STORAGE_VERSION)?;
+		let [((), block_number)] = trie::decode_entries(storage, "System", "block_number")?[..]
+		else {
+			return Err("Missing block number");
+		};
+		self.block_number = block_number;
+		self.accounts = trie::decode_entries(storage, "System", "account")?.into_iter().collect();
+		self.block_hash = block_hashes.into_iter().collect();
+		self.code = trie::decode_entries(storage, "System", "code")?.pop().map(|((), code)| code);
+		let genesis_hash = trie::decode_entries(storage, "System", "genesis_hash")?.pop();
+		self.genesis_hash = genesis_hash.map(|((), hash)| hash);
+		let call_filter = trie::decode_entries(storage, "System", "call_filter")?.pop();
+		self.call_filter = call_filter.map_or_else(CallFilter::default, |((), filter)| filter);
+		Ok(())
+	}
+
+	/// The hashes of the most recent blocks, oldest first.
+	pub fn block_hashes(&self) -> Vec<(T::BlockNumber, Hash)> {
+		self.block_hash.iter().map(|(n, hash)| (*n, *hash)).collect()
+	}
+
+	pub fn inc_providers(&mut self, who: &T::AccountId) {
+		let info = self.accounts.get_or_insert_with(who.clone(), AccountInfo::new);
+		info.providers = info.providers.saturating_add(1);
+	}
+
+	/// Drop a provider of `who`. The account, nonce included, is reaped once nothing references
+	/// it anymore.
+	pub fn dec_providers(&mut self, who: &T::AccountId) -> DispatchResult {
+		let info = self.accounts.get_mut(who).ok_or("No providers")?;
+		match info.providers {
+			0 => return Err("No providers"),
+			1 if info.consumers > 0 => return Err("Account has consumers"),
+			_ => info.providers -= 1,
+		}
+		if info.providers == 0 {
+			self.accounts.remove(who);
+		}
+		Ok(())
+	}
+
+	/// Only accounts that exist can be depended on.
+	pub fn inc_consumers(&mut self, who: &T::AccountId) -> DispatchResult {
+		match self.accounts.get_mut(who) {
+			Some(info) if info.providers > 0 => {
+				info.consumers = info.consumers.saturating_add(1);
+				Ok(())
+			},
+			_ => Err("No providers"),
+		}
+	}
+
+	pub fn dec_consumers(&mut self, who: &T::AccountId) {
+		if let Some(info) = self.accounts.get_mut(who) {
+			info.consumers = info.consumers.saturating_sub(1);
+		}
+	}
+
+	/// Apply the references the pallets noted, see `RefChange`. They are noted in an order that
+	/// can't fail, so a failure is a bug of the pallet noting them.
+	pub fn apply_ref_changes(&mut self, changes: Vec<RefChange<T::AccountId>>) {
+		for change in changes {
+			let result = match &change {
+				RefChange::IncProviders(who) => {
+					self.inc_providers(who);
+					Ok(())
+				},
+				RefChange::DecProviders(who) => self.dec_providers(who),
+				RefChange::IncConsumers(who) => self.inc_consumers(who),
+				RefChange::DecConsumers(who) => {
+					self.dec_consumers(who);
+					Ok(())
+				},
+			};
+			debug_assert!(result.is_ok(), "Invalid reference change {:?}: {:?}", change, result);
+		}
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
 	}
 }
 
 #[cfg(test)]
 mod test {
-
-    struct TestConfig;
-
-    impl super::Config for TestConfig {
-        type AccountId = String;
-        type BlockNumber = u32;
-        type Nonce = u32; 
-    }
+	use super::{CallFilter, Event};
+	use crate::{
+		codec::Encode,
+		genesis::{self, GenesisBuilder},
+		mock::Test,
+		sudo,
+		support::{DispatchError, InvalidTransaction, ModuleError},
+		wallet, RuntimeCall, RuntimeEvent,
+	};
 
 	#[test]
 	fn init_system() {
-		let system: super::Pallet<TestConfig> = super::Pallet::new();
+		let system: super::Pallet<Test> = super::Pallet::new();
 		assert_eq!(system.block_number(), 0);
 	}
 
 	#[test]
 	fn inc_block_number() {
-		let mut system: super::Pallet<TestConfig> = super::Pallet::new();
+		let mut system: super::Pallet<Test> = super::Pallet::new();
 		system.inc_block_number();
 
 		assert_eq!(system.block_number(), 1);
@@ -71,9 +612,136 @@ mod test {
 	fn inc_nonce() {
 		let alice = &"alice".to_string();
 
-		let mut system: super::Pallet<TestConfig> = super::Pallet::new();
-		system.inc_nonce(alice);
-
+		let mut system: super::Pallet<Test> = super::Pallet::new();
+		assert_eq!(system.inc_nonce(alice), Ok(()));
 		assert_eq!(system.get_nonce(alice), 1);
+
+		system.accounts.get_mut(alice).unwrap().nonce = u32::MAX;
+		assert_eq!(system.inc_nonce(alice), Err("Nonce overflow"));
+		assert_eq!(system.get_nonce(alice), u32::MAX);
+	}
+
+	#[test]
+	fn filtered_calls_are_not_dispatched() {
+		let alice = wallet::dev_account("alice");
+		let bob = wallet::dev_account("bob");
+		let mut state = GenesisBuilder::new()
+			.endow(&alice, 1_000)
+			.authority(&alice)
+			.sudo(&alice)
+			.build()
+			.unwrap();
+		let transfer = wallet::transfer(bob.clone(), 10);
+		let index = transfer.encode();
+		// No transfers, and no proof of existence at all.
+		let filter = CallFilter { pallets: vec![1], calls: vec![(index[0], index[1])] };
+		let call = RuntimeCall::System(super::Call::SetCallFilter { filter: filter.clone() });
+		let sudo = RuntimeCall::Sudo(sudo::Call::Sudo { call: Box::new(call) });
+		let first = genesis::signed(&state, "alice", [sudo]);
+		state.execute_block(genesis::block(&state, "charlie", first).unwrap()).unwrap();
+		assert_eq!(state.system.call_filter(), &filter);
+
+		let claim = wallet::create_claim("document".into());
+		let filtered = genesis::signed(&state, "alice", [transfer, claim]);
+		assert_eq!(state.validate_extrinsic(&filtered[0]), Err(InvalidTransaction::Call));
+		assert_eq!(state.validate_extrinsic(&filtered[1]), Err(InvalidTransaction::Call));
+		state
+			.execute_block(genesis::block(&state, "charlie", filtered).unwrap())
+			.unwrap();
+		assert_eq!(state.balances.balance(&bob), 0);
+		let error = DispatchError::Module(ModuleError { index: 20, error: 4 });
+		let events = state.system.events().iter().filter_map(|record| match &record.event {
+			RuntimeEvent::System(event) => Some(event.clone()),
+			_ => None,
+		});
+		let failed = Event::ExtrinsicFailed { error };
+		assert_eq!(events.collect::<Vec<_>>(), [failed.clone(), failed]);
+	}
+
+	#[test]
+	fn extrinsics_per_account_are_limited() {
+		let (alice, bob) = (&"alice".to_string(), &"bob".to_string());
+		let mut system: super::Pallet<Test> = super::Pallet::new();
+		assert_eq!(system.note_account_extrinsic(alice), Ok(()));
+		assert_eq!(system.note_account_extrinsic(alice), Ok(()));
+		let third = system.note_account_extrinsic(alice);
+		assert_eq!(third, Err("Too many extrinsics of the account"));
+		assert_eq!(system.note_account_extrinsic(bob), Ok(()));
+		assert_eq!(system.account_extrinsics(alice), 2);
+
+		// The next block starts over.
+		system.on_initialize();
+		assert_eq!(system.account_extrinsics(alice), 0);
+		assert_eq!(system.note_account_extrinsic(alice), Ok(()));
+	}
+
+	#[test]
+	fn block_weight_is_limited() {
+		let mut system: super::Pallet<Test> = super::Pallet::new();
+
+		assert_eq!(system.set_block_weight(1_000_000), Ok(()));
+		assert_eq!(system.set_block_weight(1_000_001), Err("Block weight limit exceeded"));
+		assert_eq!(system.block_weight(), 1_000_000);
+	}
+
+	#[test]
+	fn recent_block_hashes_are_kept() {
+		let mut system: super::Pallet<Test> = super::Pallet::new();
+		for n in 1..=300 {
+			system.on_finalize(n, [n as u8; 32]);
+		}
+
+		assert_eq!(system.block_hash(44), None);
+		assert_eq!(system.block_hash(45), Some([45; 32]));
+		assert_eq!(system.block_hash(300), Some([44; 32]));
+		assert_eq!(system.block_hash.len(), super::BLOCK_HASH_COUNT);
+	}
+
+	#[test]
+	fn accounts_are_reference_counted() {
+		let alice = &"alice".to_string();
+		let mut system: super::Pallet<Test> = super::Pallet::new();
+
+		assert_eq!(system.inc_consumers(alice), Err("No providers"));
+		system.inc_nonce(alice).unwrap();
+		assert!(!system.account_exists(alice));
+
+		system.inc_providers(alice);
+		assert_eq!(system.inc_consumers(alice), Ok(()));
+		assert_eq!(system.dec_providers(alice), Err("Account has consumers"));
+		system.dec_consumers(alice);
+
+		assert_eq!(system.dec_providers(alice), Ok(()));
+		assert!(system.account(alice).is_none());
+		assert_eq!(system.get_nonce(alice), 0);
+		assert_eq!(system.dec_providers(alice), Err("No providers"));
+	}
+
+	#[test]
+	fn events_are_tagged_with_their_extrinsic() {
+		let mut system: super::Pallet<Test> = super::Pallet::new();
+		system.deposit_event(());
+		system.set_extrinsic_index(Some(0));
+		system.deposit_event(());
+		system.set_extrinsic_index(Some(1));
+		system.deposit_event(());
+		system.deposit_event(());
+
+		assert_eq!(system.events().len(), 4);
+		assert_eq!(system.events()[0].extrinsic_index, None);
+		assert_eq!(system.events_for_extrinsic(1).count(), 2);
+
+		system.on_initialize();
+		assert!(system.events().is_empty());
+	}
+
+	#[test]
+	fn code_switches_at_the_end_of_the_block() {
+		let mut system: super::Pallet<Test> = super::Pallet::new();
+		system.set_code(vec![1]);
+		assert_eq!(system.code(), None);
+
+		system.on_finalize(1, [1; 32]);
+		assert_eq!(system.code(), Some(&[1][..]));
 	}
 }
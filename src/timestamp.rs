@@ -0,0 +1,228 @@
+use num::{CheckedAdd, Zero};
+
+use crate::{
+	codec::{Decode, Encode},
+	metadata::{field, storage, variant, PalletMetadata},
+	migrations::{self, StorageVersion},
+	support::{ensure_none, DispatchResult, Dispatchable, Weight},
+	system::{self, OriginFor},
+	trie::{self, Storage},
+};
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+pub trait Config: system::Config {
+	/// The unit of time, e.g. milliseconds since the unix epoch.
+	type Moment: Encode + Zero + CheckedAdd + Copy + PartialOrd;
+	/// The minimum amount of time that must pass between two consecutive blocks.
+	const MINIMUM_PERIOD: Self::Moment;
+	/// How far into the future, relative to the local clock, a block timestamp may be.
+	const MAX_DRIFT: Self::Moment;
+}
+
+/// Calls of the timestamp pallet. These are inherents: they are inserted by the block author
+/// and are never dispatched on behalf of a signed account.
+#[derive(Debug, Clone, Dispatchable)]
+pub enum Call<T: Config> {
+	Set { now: T::Moment },
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::Set { now } => (0u8, now).encode_to(dest),
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T>
+where
+	T::Moment: Decode,
+{
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::Set { now: Decode::decode(input)? },
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::Set { .. } => 0,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::Set { .. } => 1_000,
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	now: T::Moment,
+	did_update: bool,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self { now: T::Moment::zero(), did_update: false }
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Timestamp",
+			index: None,
+			calls: vec![variant("Set", 0, vec![field::<T::Moment>("now")])],
+			storage: vec![storage::<T::Moment>("now"), storage::<bool>("did_update")],
+			events: Vec::new(),
+			errors: vec![
+				"Timestamp must be updated once in the block",
+				"Timestamp must be updated only once in the block",
+				"Timestamp must increment by at least MINIMUM_PERIOD",
+				"Timestamp overflow",
+				"Timestamp too far in the future",
+			],
+		}
+	}
+
+	pub fn now(&self) -> T::Moment {
+		self.now
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Timestamp", STORAGE_VERSION);
+		trie::insert_value(storage, "Timestamp", "now", &self.now);
+		trie::insert_value(storage, "Timestamp", "did_update", &self.did_update);
+	}
+
+	/// Restore the timestamp `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult
+	where
+		T::Moment: Decode,
+	{
+		migrations::ensure_version(storage, "Timestamp", STORAGE_VERSION)?;
+		trie::restore_value(storage, "Timestamp", "now", &mut self.now)?;
+		trie::restore_value(storage, "Timestamp", "did_update", &mut self.did_update)?;
+		Ok(())
+	}
+
+	/// The earliest timestamp the next block is allowed to carry.
+	pub fn next_minimum(&self) -> Result<T::Moment, &'static str> {
+		self.now.checked_add(&T::MINIMUM_PERIOD).ok_or("Timestamp overflow")
+	}
+
+	/// Build the inherent a block author puts at the front of its block, given its local clock.
+	pub fn create_inherent(&self, local_now: T::Moment) -> Result<Call<T>, &'static str> {
+		let minimum = self.next_minimum()?;
+		let now = if local_now < minimum { minimum } else { local_now };
+		Ok(Call::Set { now })
+	}
+
+	/// Check a proposed timestamp against the local clock of the importing node.
+	pub fn check_inherent(&self, call: &Call<T>, local_now: T::Moment) -> DispatchResult {
+		let Call::Set { now } = call;
+		let max = local_now.checked_add(&T::MAX_DRIFT).ok_or("Timestamp overflow")?;
+		if *now > max {
+			return Err("Timestamp too far in the future");
+		}
+		Ok(())
+	}
+
+	/// Timestamp calls are inherents, so they are only accepted with the None origin.
+	pub fn set(&mut self, origin: OriginFor<T>, now: T::Moment) -> DispatchResult {
+		ensure_none(origin)?;
+		if self.did_update {
+			return Err("Timestamp must be updated only once in the block");
+		}
+		if now < self.next_minimum()? {
+			return Err("Timestamp must increment by at least MINIMUM_PERIOD");
+		}
+
+		self.now = now;
+		self.did_update = true;
+		Ok(())
+	}
+
+	/// Called once all extrinsics of a block have been executed.
+	pub fn on_finalize(&mut self) -> DispatchResult {
+		if !self.did_update {
+			return Err("Timestamp must be updated once in the block");
+		}
+		self.did_update = false;
+		Ok(())
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{
+		mock::Test,
+		support::{Dispatch, RawOrigin},
+	};
+
+	impl super::Config for Test {
+		type Moment = u64;
+		const MINIMUM_PERIOD: u64 = 5;
+		const MAX_DRIFT: u64 = 100;
+	}
+
+	#[test]
+	fn set_timestamp_once_per_block() {
+		let mut timestamp: super::Pallet<Test> = super::Pallet::new();
+
+		assert_eq!(timestamp.set(RawOrigin::None, 10), Ok(()));
+		assert_eq!(timestamp.now(), 10);
+		assert_eq!(
+			timestamp.set(RawOrigin::None, 20),
+			Err("Timestamp must be updated only once in the block")
+		);
+		let signed = RawOrigin::Signed("alice".to_string());
+		assert_eq!(timestamp.dispatch(signed, super::Call::Set { now: 20 }), Err("Bad origin"));
+
+		assert_eq!(timestamp.on_finalize(), Ok(()));
+		assert_eq!(timestamp.on_finalize(), Err("Timestamp must be updated once in the block"));
+	}
+
+	#[test]
+	fn timestamp_must_increase_by_minimum_period() {
+		let mut timestamp: super::Pallet<Test> = super::Pallet::new();
+
+		let _ = timestamp.set(RawOrigin::None, 10);
+		let _ = timestamp.on_finalize();
+
+		assert_eq!(
+			timestamp.set(RawOrigin::None, 14),
+			Err("Timestamp must increment by at least MINIMUM_PERIOD")
+		);
+		assert_eq!(timestamp.set(RawOrigin::None, 15), Ok(()));
+	}
+
+	#[test]
+	fn create_and_check_inherent() {
+		let mut timestamp: super::Pallet<Test> = super::Pallet::new();
+		let _ = timestamp.set(RawOrigin::None, 50);
+		let _ = timestamp.on_finalize();
+
+		let super::Call::Set { now } = timestamp.create_inherent(52).unwrap();
+		assert_eq!(now, 55);
+		let super::Call::Set { now } = timestamp.create_inherent(80).unwrap();
+		assert_eq!(now, 80);
+
+		assert_eq!(timestamp.check_inherent(&super::Call::Set { now: 150 }, 50), Ok(()));
+		assert_eq!(
+			timestamp.check_inherent(&super::Call::Set { now: 151 }, 50),
+			Err("Timestamp too far in the future")
+		);
+	}
+}
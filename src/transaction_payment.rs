@@ -0,0 +1,193 @@
+//! Congestion pricing: the fee of an extrinsic is the base fee times a multiplier, which goes
+//! up after blocks fuller than targeted and down after emptier ones, so fees follow demand
+//! without anyone setting them. The fee pays for the declared weight of a call, and the share
+//! of it the call didn't use is refunded, see `signed_extensions::ChargeTransactionPayment`.
+
+use std::marker::PhantomData;
+
+use num::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, Zero};
+
+use crate::{
+	balances,
+	codec::{Decode, Encode},
+	metadata::{storage, PalletMetadata},
+	migrations::{self, StorageVersion},
+	support::{DispatchResult, Percent, Weight},
+	trie::{self, storage_key, Storage},
+};
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+/// How fast the multiplier follows the fullness of blocks: it moves by a quarter of how much
+/// fuller or emptier than targeted a block was, e.g. up by 18.75% after a full block when a
+/// quarter is targeted.
+const ADJUSTMENT_DIVISOR: i128 = 4;
+
+pub trait Config: balances::Config {
+	/// The fee while the multiplier is one.
+	const BASE_FEE: Self::Balance;
+	/// How much of the block weight limit blocks are meant to use, with fees steady.
+	const TARGET_FULLNESS: Percent;
+	const MIN_MULTIPLIER: Multiplier;
+	const MAX_MULTIPLIER: Multiplier;
+}
+
+/// A factor in millionths, so `Multiplier::ONE` leaves a fee as it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Multiplier(pub u64);
+
+impl Multiplier {
+	pub const ONE: Multiplier = Multiplier(1_000_000);
+
+	/// `value` times `self`, rounded down. Like `Percent::mul_floor`, `value` is split first,
+	/// so the product only overflows if the result does.
+	pub fn mul_floor<N>(&self, value: N) -> Option<N>
+	where
+		N: From<u64> + Zero + CheckedMul + CheckedDiv + CheckedSub + CheckedAdd + Copy,
+	{
+		let (factor, one) = (N::from(self.0), N::from(Self::ONE.0));
+		let whole = value.checked_div(&one)?;
+		let rest = value.checked_sub(&whole.checked_mul(&one)?)?;
+		let rest = rest.checked_mul(&factor)?.checked_div(&one)?;
+		whole.checked_mul(&factor)?.checked_add(&rest)
+	}
+}
+
+impl Encode for Multiplier {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		self.0.encode_to(dest);
+	}
+}
+
+impl Decode for Multiplier {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(Multiplier(Decode::decode(input)?))
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	/// The multiplier of the fees of the next block.
+	next_fee_multiplier: Multiplier,
+	_config: PhantomData<T>,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self { next_fee_multiplier: T::MIN_MULTIPLIER, _config: PhantomData }
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "TransactionPayment",
+			index: None,
+			calls: Vec::new(),
+			storage: vec![storage::<Multiplier>("next_fee_multiplier")],
+			events: Vec::new(),
+			errors: Vec::new(),
+		}
+	}
+
+	pub fn next_fee_multiplier(&self) -> Multiplier {
+		self.next_fee_multiplier
+	}
+
+	/// The fee of an extrinsic in the current block: the base fee times the multiplier. The
+	/// multiplier is at most `MAX_MULTIPLIER`, so only a base fee close to the largest balance
+	/// overflows, which is charged as it is then.
+	pub fn fee(&self) -> T::Balance {
+		self.next_fee_multiplier.mul_floor(T::BASE_FEE).unwrap_or(T::BASE_FEE)
+	}
+
+	/// Called at the end of every block with the weight it used: the multiplier moves towards
+	/// the fullness of the block, within `MIN_MULTIPLIER` and `MAX_MULTIPLIER`.
+	pub fn on_finalize(&mut self, block_weight: Weight, max_weight: Weight) {
+		let one = i128::from(Multiplier::ONE.0);
+		let fullness =
+			i128::from(block_weight.min(max_weight)) * one / i128::from(max_weight.max(1));
+		let target = i128::from(T::TARGET_FULLNESS.0.min(100)) * one / 100;
+		let multiplier = i128::from(self.next_fee_multiplier.0);
+		let next = multiplier + multiplier * (fullness - target) / one / ADJUSTMENT_DIVISOR;
+		let next = Multiplier(u64::try_from(next.max(0)).unwrap_or(u64::MAX));
+		self.next_fee_multiplier = next.clamp(T::MIN_MULTIPLIER, T::MAX_MULTIPLIER);
+	}
+
+	/// The multiplier unless it is the minimum, at which a new chain starts.
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "TransactionPayment", STORAGE_VERSION);
+		if self.next_fee_multiplier != T::MIN_MULTIPLIER {
+			let key = storage_key("TransactionPayment", "next_fee_multiplier", &());
+			storage.insert(key, self.next_fee_multiplier.encode());
+		}
+	}
+
+	/// Restore the multiplier `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult {
+		migrations::ensure_version(storage, "TransactionPayment", STORAGE_VERSION)?;
+		let mut multiplier = trie::decode_entries::<(), Multiplier>(
+			storage,
+			"TransactionPayment",
+			"next_fee_multiplier",
+		)?;
+		self.next_fee_multiplier = multiplier.pop().map_or(T::MIN_MULTIPLIER, |((), m)| m);
+		Ok(())
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::Multiplier;
+	use crate::{mock::Test, support::Percent, trie::Storage};
+
+	impl super::Config for Test {
+		const BASE_FEE: u128 = 100;
+		const TARGET_FULLNESS: Percent = Percent(25);
+		const MIN_MULTIPLIER: Multiplier = Multiplier::ONE;
+		const MAX_MULTIPLIER: Multiplier = Multiplier(2_000_000);
+	}
+
+	#[test]
+	fn fees_follow_the_fullness_of_blocks() {
+		let mut payment: super::Pallet<Test> = super::Pallet::new();
+		assert_eq!(payment.fee(), 100);
+
+		// A full block is three quarters over the target, which raises fees by 3/16.
+		payment.on_finalize(1_000, 1_000);
+		assert_eq!(payment.next_fee_multiplier(), Multiplier(1_187_500));
+		assert_eq!(payment.fee(), 118);
+		payment.on_finalize(250, 1_000);
+		assert_eq!(payment.fee(), 118);
+		// An empty block lowers them by 1/16, but not below the minimum.
+		payment.on_finalize(0, 1_000);
+		assert_eq!(payment.next_fee_multiplier(), Multiplier(1_113_282));
+		for _ in 0..10 {
+			payment.on_finalize(0, 1_000);
+		}
+		assert_eq!(payment.next_fee_multiplier(), Multiplier::ONE);
+		for _ in 0..10 {
+			payment.on_finalize(1_000, 1_000);
+		}
+		assert_eq!(payment.fee(), 200);
+	}
+
+	#[test]
+	fn the_multiplier_is_kept_in_storage() {
+		let mut payment: super::Pallet<Test> = super::Pallet::new();
+		let mut storage = Storage::new();
+		payment.storage_entries(&mut storage);
+		assert_eq!(storage.len(), 1);
+
+		payment.on_finalize(1_000, 1_000);
+		payment.storage_entries(&mut storage);
+		let mut restored: super::Pallet<Test> = super::Pallet::new();
+		restored.restore_entries(&storage).unwrap();
+		assert_eq!(restored.next_fee_multiplier(), payment.next_fee_multiplier());
+		assert_eq!(Multiplier(2_000_000).mul_floor(u128::MAX), None);
+	}
+}
@@ -0,0 +1,197 @@
+//! The extrinsics submitted to the node, waiting to be put in a block. They are validated when
+//! they are submitted, and again against the state the next block builds on, and blocks take
+//! the ready ones by priority: an extrinsic is ready once the extrinsics of its caller before
+//! its nonce are ahead of it, so a caller's extrinsics keep their order whatever they tip. A
+//! block takes no more than `MAX_EXTRINSICS_PER_ACCOUNT` of the extrinsics of a caller, and the
+//! rest wait for the next blocks.
+
+use std::{cmp::Reverse, collections::BTreeMap};
+
+use crate::{
+	codec::Encode,
+	hashing::{self, Hash},
+	support::{InvalidTransaction, ValidTransaction, Weight},
+	system, types, Runtime,
+};
+
+#[derive(Default)]
+pub struct TransactionPool {
+	extrinsics: Vec<types::Extrinsic>,
+}
+
+impl TransactionPool {
+	pub fn new() -> Self {
+		Self { extrinsics: Vec::new() }
+	}
+
+	pub fn len(&self) -> usize {
+		self.extrinsics.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.extrinsics.is_empty()
+	}
+
+	/// The extrinsics in the pool, in the order they were submitted.
+	pub fn extrinsics(&self) -> &[types::Extrinsic] {
+		&self.extrinsics
+	}
+
+	/// The nonce the next extrinsic of `account` is signed at: its nonce on `state`, after the
+	/// extrinsics of the account in the pool at the nonces that follow.
+	pub fn next_nonce(&self, state: &Runtime, account: &types::AccountId) -> types::Nonce {
+		let pooled = |nonce| {
+			self.extrinsics.iter().any(|extrinsic| {
+				let extra = extrinsic.signature.as_ref().map(|(_, extra)| extra);
+				extrinsic.caller.as_ref() == Some(account)
+					&& extra.is_some_and(|extra| extra.nonce == nonce)
+			})
+		};
+		let mut nonce = state.system.get_nonce(account);
+		while pooled(nonce) {
+			nonce += 1;
+		}
+		nonce
+	}
+
+	/// Add `extrinsic` to the pool if it is valid on `state`, and return its hash.
+	pub fn submit(
+		&mut self,
+		state: &Runtime,
+		extrinsic: types::Extrinsic,
+	) -> Result<Hash, InvalidTransaction> {
+		state.validate_extrinsic(&extrinsic)?;
+		let hash = hashing::blake2_256(&extrinsic.encode());
+		self.extrinsics.push(extrinsic);
+		Ok(hash)
+	}
+
+	/// Take the extrinsics for the next block on `state`, the ready one of the highest priority
+	/// first, until there are no more or the next would exceed `weight_limit` in total.
+	/// Extrinsics that are no longer valid are dropped, and the others stay in the pool.
+	pub fn take_ready(&mut self, state: &Runtime, weight_limit: Weight) -> Vec<types::Extrinsic> {
+		let mut pending: Vec<(ValidTransaction, types::Extrinsic)> = self
+			.extrinsics
+			.drain(..)
+			.filter_map(|extrinsic| Some((state.validate_extrinsic(&extrinsic).ok()?, extrinsic)))
+			.collect();
+		let mut nonces = BTreeMap::new();
+		let (mut ready, mut weight) = (Vec::new(), 0);
+		loop {
+			// Unsigned extrinsics have no nonce, so they are always ready.
+			let is_ready = |(valid, extrinsic): &(ValidTransaction, types::Extrinsic)| {
+				let in_order = extrinsic.caller.as_ref().is_none_or(|caller| {
+					let next = nonces.get(caller).copied();
+					let next = next.unwrap_or_else(|| state.system.get_nonce(caller));
+					let nonce = extrinsic.signature.as_ref().map(|(_, extra)| extra.nonce);
+					nonce == Some(next)
+						&& next - state.system.get_nonce(caller)
+							< <Runtime as system::Config>::MAX_EXTRINSICS_PER_ACCOUNT
+				});
+				in_order && weight + valid.weight <= weight_limit
+			};
+			// Of the same priority, the one submitted first goes first.
+			let Some((i, _)) = pending
+				.iter()
+				.enumerate()
+				.filter(|(_, pending)| is_ready(pending))
+				.min_by_key(|(i, (valid, _))| (Reverse(valid.priority), *i))
+			else {
+				break;
+			};
+			let (valid, extrinsic) = pending.remove(i);
+			weight += valid.weight;
+			if let (Some(caller), Some((_, extra))) = (&extrinsic.caller, &extrinsic.signature) {
+				nonces.insert(caller.clone(), extra.nonce + 1);
+			}
+			ready.push(extrinsic);
+		}
+		self.extrinsics = pending.into_iter().map(|(_, extrinsic)| extrinsic).collect();
+		ready
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::TransactionPool;
+	use crate::{
+		balances, genesis,
+		genesis::GenesisBuilder,
+		support::InvalidTransaction,
+		wallet::{dev_account, dev_signer, UnsignedExtrinsic},
+		Runtime, RuntimeCall,
+	};
+
+	fn transfer(state: &Runtime, from: &str, nonce: u32, tip: u128) -> crate::types::Extrinsic {
+		let call = RuntimeCall::Balances(balances::Call::Transfer {
+			to: dev_account("charlie").into(),
+			amount: 1,
+		});
+		let unsigned = UnsignedExtrinsic::new(dev_account(from), call, nonce, state.genesis_hash())
+			.with_tip(tip);
+		let signature = dev_signer(from).sign_payload(&unsigned.signing_payload());
+		unsigned.attach(signature).unwrap()
+	}
+
+	#[test]
+	fn ready_extrinsics_go_by_tip_but_keep_their_nonce_order() {
+		let mut state = Runtime::new();
+		state.balances.set_balance(&dev_account("alice"), 100);
+		state.balances.set_balance(&dev_account("bob"), 100);
+		// As after the first block, so the genesis hash stays when the state changes below.
+		let genesis_hash = state.genesis_hash();
+		state.system.set_genesis_hash(genesis_hash);
+		let mut pool = TransactionPool::new();
+		for (from, nonce, tip) in [("alice", 0, 0), ("alice", 1, 50), ("bob", 0, 5), ("bob", 2, 9)]
+		{
+			pool.submit(&state, transfer(&state, from, nonce, tip)).unwrap();
+		}
+		// Nothing is executed yet, so a second extrinsic at the same nonce is valid too.
+		pool.submit(&state, transfer(&state, "alice", 0, 0)).unwrap();
+		let unfunded = pool.submit(&state, transfer(&state, "charlie", 0, 0));
+		assert_eq!(unfunded, Err(InvalidTransaction::Payment));
+
+		let ready = pool.take_ready(&state, u64::MAX);
+		let order: Vec<_> = ready
+			.iter()
+			.map(|extrinsic| {
+				let (_, extra) = extrinsic.signature.as_ref().unwrap();
+				(extrinsic.caller.clone().unwrap(), extra.nonce)
+			})
+			.collect();
+		// Bob tips more than the first extrinsic of alice, which the second one waits for. The
+		// other extrinsic of alice at nonce 0 and the one of bob at nonce 2 aren't ready.
+		let (alice, bob) = (dev_account("alice"), dev_account("bob"));
+		assert_eq!(order, vec![(bob.clone(), 0), (alice.clone(), 0), (alice.clone(), 1)]);
+		assert_eq!(pool.len(), 2);
+
+		// Blocks only take what fits, and extrinsics that are no longer valid are dropped.
+		assert!(pool.take_ready(&state, 0).is_empty());
+		assert_eq!(pool.len(), 2);
+		state.system.inc_nonce(&alice).unwrap();
+		state.system.inc_nonce(&bob).unwrap();
+		assert!(pool.take_ready(&state, u64::MAX).is_empty());
+		assert_eq!(pool.len(), 1);
+	}
+
+	#[test]
+	fn blocks_take_a_limited_number_of_extrinsics_per_account() {
+		let alice = dev_account("alice");
+		let state = GenesisBuilder::new().endow(&alice, 10_000).build().unwrap();
+		let transfers = (0..101).map(|_| crate::wallet::transfer(dev_account("bob"), 1));
+		let extrinsics = genesis::signed(&state, "alice", transfers);
+		let mut pool = TransactionPool::new();
+		for extrinsic in extrinsics.clone() {
+			pool.submit(&state, extrinsic).unwrap();
+		}
+		let ready = pool.take_ready(&state, u64::MAX);
+		assert_eq!((ready.len(), pool.len()), (100, 1));
+
+		// Blocks built past the limit skip the extrinsics beyond it.
+		let mut next = state.clone();
+		next.execute_block(genesis::block(&state, "charlie", extrinsics).unwrap())
+			.unwrap();
+		assert_eq!(next.balances.balance(&dev_account("bob")), 100);
+		assert_eq!(next.system.get_nonce(&alice), 100);
+	}
+}
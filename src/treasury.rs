@@ -0,0 +1,402 @@
+use std::collections::BTreeMap;
+
+use num::{CheckedAdd, Zero};
+
+use crate::{
+	balances,
+	codec::{Decode, Encode},
+	metadata::{field, storage, variant, PalletMetadata},
+	migrations::{self, StorageVersion},
+	support::{ensure_root, ensure_signed, DispatchResult, Percent, Weight},
+	system::OriginFor,
+	trie::{self, Storage},
+};
+
+pub type ProposalIndex = u32;
+
+pub trait Config: balances::Config {
+	/// The share of a spend proposal's value that is reserved as its bond.
+	const PROPOSAL_BOND: Percent;
+	/// The minimum bond of a spend proposal.
+	const PROPOSAL_BOND_MINIMUM: Self::Balance;
+	/// How often (in blocks) approved proposals are paid out.
+	const SPEND_PERIOD: Self::BlockNumber;
+	/// The share of the funds left in the pot that is burned at the end of a spend period.
+	const BURN: Percent;
+	/// The maximum number of approved proposals waiting to be paid out.
+	const MAX_APPROVALS: usize;
+
+	/// The account holding the funds of the treasury.
+	fn account_id() -> Self::AccountId;
+
+	/// Check the origin of approving and rejecting proposals, root by default.
+	fn ensure_spend_origin(origin: OriginFor<Self>) -> DispatchResult {
+		ensure_root(origin)
+	}
+}
+
+#[derive(Debug, Clone)]
+pub enum Call<T: Config> {
+	ProposeSpend {
+		value: T::Balance,
+		beneficiary: T::AccountId,
+	},
+	/// `ensure_spend_origin` only: approve a proposal, to be paid out at the end of the spend period.
+	ApproveProposal {
+		proposal_id: ProposalIndex,
+	},
+	/// `ensure_spend_origin` only: reject a proposal, slashing its bond into the pot.
+	RejectProposal {
+		proposal_id: ProposalIndex,
+	},
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::ProposeSpend { value, beneficiary } => (0u8, value, beneficiary).encode_to(dest),
+			Call::ApproveProposal { proposal_id } => (1u8, proposal_id).encode_to(dest),
+			Call::RejectProposal { proposal_id } => (2u8, proposal_id).encode_to(dest),
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::ProposeSpend {
+				value: Decode::decode(input)?,
+				beneficiary: Decode::decode(input)?,
+			},
+			1 => Call::ApproveProposal { proposal_id: Decode::decode(input)? },
+			2 => Call::RejectProposal { proposal_id: Decode::decode(input)? },
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::ProposeSpend { .. } => 0,
+			Call::ApproveProposal { .. } => 1,
+			Call::RejectProposal { .. } => 2,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::ProposeSpend { .. } | Call::RejectProposal { .. } => 10_000,
+			Call::ApproveProposal { .. } => 5_000,
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Proposal<T: Config> {
+	pub proposer: T::AccountId,
+	pub value: T::Balance,
+	pub beneficiary: T::AccountId,
+	pub bond: T::Balance,
+}
+
+impl<T: Config> Encode for Proposal<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.proposer, &self.value, &self.beneficiary, &self.bond).encode_to(dest);
+	}
+}
+
+impl<T: Config> Decode for Proposal<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (proposer, value, beneficiary, bond) = Decode::decode(input)?;
+		Ok(Proposal { proposer, value, beneficiary, bond })
+	}
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	proposals: BTreeMap<ProposalIndex, Proposal<T>>,
+	proposal_count: ProposalIndex,
+	approvals: Vec<ProposalIndex>,
+	next_spend: T::BlockNumber,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self {
+			proposals: BTreeMap::new(),
+			proposal_count: 0,
+			approvals: Vec::new(),
+			next_spend: T::SPEND_PERIOD,
+		}
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Treasury",
+			index: None,
+			calls: vec![
+				variant(
+					"ProposeSpend",
+					0,
+					vec![field::<T::Balance>("value"), field::<T::AccountId>("beneficiary")],
+				),
+				variant("ApproveProposal", 1, vec![field::<ProposalIndex>("proposal_id")]),
+				variant("RejectProposal", 2, vec![field::<ProposalIndex>("proposal_id")]),
+			],
+			storage: vec![
+				storage::<BTreeMap<ProposalIndex, Proposal<T>>>("proposals"),
+				storage::<ProposalIndex>("proposal_count"),
+				storage::<Vec<ProposalIndex>>("approvals"),
+				storage::<T::BlockNumber>("next_spend"),
+			],
+			events: Vec::new(),
+			errors: vec![
+				"Proposal already approved",
+				"Proposal does not exist",
+				"Too many approvals",
+			],
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Treasury", STORAGE_VERSION);
+		trie::insert_entries(storage, "Treasury", "proposals", &self.proposals);
+		trie::insert_value(storage, "Treasury", "proposal_count", &self.proposal_count);
+		trie::insert_value(storage, "Treasury", "approvals", &self.approvals);
+		trie::insert_value(storage, "Treasury", "next_spend", &self.next_spend);
+	}
+
+	/// Restore the proposals `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult {
+		migrations::ensure_version(storage, "Treasury", STORAGE_VERSION)?;
+		self.proposals =
+			trie::decode_entries(storage, "Treasury", "proposals")?.into_iter().collect();
+		trie::restore_value(storage, "Treasury", "proposal_count", &mut self.proposal_count)?;
+		trie::restore_value(storage, "Treasury", "approvals", &mut self.approvals)?;
+		trie::restore_value(storage, "Treasury", "next_spend", &mut self.next_spend)?;
+		Ok(())
+	}
+
+	/// The treasury moves funds in and out of the pot, so calls are dispatched together with
+	/// the balances pallet.
+	pub fn dispatch(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		origin: OriginFor<T>,
+		call: Call<T>,
+	) -> DispatchResult {
+		match call {
+			Call::ProposeSpend { value, beneficiary } => self
+				.propose_spend(balances, ensure_signed(origin)?, value, beneficiary)
+				.map(|_| ()),
+			Call::ApproveProposal { proposal_id } => {
+				T::ensure_spend_origin(origin)?;
+				self.approve_proposal(proposal_id)
+			},
+			Call::RejectProposal { proposal_id } => {
+				T::ensure_spend_origin(origin)?;
+				self.reject_proposal(balances, proposal_id)
+			},
+		}
+	}
+
+	pub fn pot(&self, balances: &balances::Pallet<T>) -> T::Balance {
+		balances.balance(&T::account_id())
+	}
+
+	pub fn proposal(&self, proposal_id: ProposalIndex) -> Option<&Proposal<T>> {
+		self.proposals.get(&proposal_id)
+	}
+
+	pub fn approvals(&self) -> &[ProposalIndex] {
+		&self.approvals
+	}
+
+	/// Put funds that left other accounts, e.g. transaction fees, into the pot. They are burned
+	/// if the pot can't take them.
+	pub fn on_unbalanced(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		imbalance: balances::NegativeImbalance<T>,
+	) -> DispatchResult {
+		balances.resolve(&T::account_id(), imbalance)
+	}
+
+	pub fn propose_spend(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		proposer: T::AccountId,
+		value: T::Balance,
+		beneficiary: T::AccountId,
+	) -> Result<ProposalIndex, &'static str> {
+		let bond = T::PROPOSAL_BOND.mul_floor(value);
+		let bond = if bond < T::PROPOSAL_BOND_MINIMUM { T::PROPOSAL_BOND_MINIMUM } else { bond };
+		balances.reserve(&proposer, bond)?;
+
+		let proposal_id = self.proposal_count;
+		self.proposal_count += 1;
+		self.proposals
+			.insert(proposal_id, Proposal { proposer, value, beneficiary, bond });
+		Ok(proposal_id)
+	}
+
+	pub fn approve_proposal(&mut self, proposal_id: ProposalIndex) -> DispatchResult {
+		if !self.proposals.contains_key(&proposal_id) {
+			return Err("Proposal does not exist");
+		}
+		if self.approvals.contains(&proposal_id) {
+			return Err("Proposal already approved");
+		}
+		if self.approvals.len() >= T::MAX_APPROVALS {
+			return Err("Too many approvals");
+		}
+
+		self.approvals.push(proposal_id);
+		Ok(())
+	}
+
+	pub fn reject_proposal(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		proposal_id: ProposalIndex,
+	) -> DispatchResult {
+		let proposal = self.proposals.remove(&proposal_id).ok_or("Proposal does not exist")?;
+		self.approvals.retain(|id| *id != proposal_id);
+
+		let slashed = balances.slash_reserved(&proposal.proposer, proposal.bond);
+		self.on_unbalanced(balances, slashed)
+	}
+
+	/// Called at the end of every block: at the end of a spend period the approved proposals
+	/// are paid out, as long as the pot can afford them, and part of what is left is burned.
+	pub fn on_finalize(&mut self, balances: &mut balances::Pallet<T>, now: T::BlockNumber) {
+		if now < self.next_spend {
+			return;
+		}
+		self.next_spend = now.checked_add(&T::SPEND_PERIOD).unwrap_or(now);
+
+		let pot = T::account_id();
+		let mut remaining = Vec::new();
+		for proposal_id in core::mem::take(&mut self.approvals) {
+			let Some(proposal) = self.proposals.get(&proposal_id) else {
+				continue;
+			};
+			if balances
+				.transfer(pot.clone(), proposal.beneficiary.clone(), proposal.value)
+				.is_err()
+			{
+				remaining.push(proposal_id);
+				continue;
+			}
+			let _ = balances.unreserve(&proposal.proposer, proposal.bond);
+			self.proposals.remove(&proposal_id);
+		}
+		self.approvals = remaining;
+
+		let burn = T::BURN.mul_floor(balances.balance(&pot));
+		if !burn.is_zero() {
+			if let Ok(burned) = balances.withdraw(&pot, burn) {
+				balances.burn(burned);
+			}
+		}
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{
+		balances,
+		mock::Test,
+		support::{Percent, RawOrigin},
+	};
+
+	impl super::Config for Test {
+		const PROPOSAL_BOND: Percent = Percent(5);
+		const PROPOSAL_BOND_MINIMUM: u128 = 2;
+		const SPEND_PERIOD: u32 = 2;
+		const BURN: Percent = Percent(50);
+		const MAX_APPROVALS: usize = 2;
+
+		fn account_id() -> String {
+			"treasury".to_string()
+		}
+	}
+
+	#[test]
+	fn propose_reserves_bond() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut balances: balances::Pallet<Test> = balances::Pallet::new();
+		let mut treasury: super::Pallet<Test> = super::Pallet::new();
+		balances.set_balance(&alice, 100);
+
+		assert_eq!(treasury.propose_spend(&mut balances, alice.clone(), 400, bob.clone()), Ok(0));
+		assert_eq!(treasury.propose_spend(&mut balances, alice.clone(), 10, bob.clone()), Ok(1));
+		assert_eq!(balances.reserved_balance(&alice), 22);
+		assert_eq!(
+			treasury.propose_spend(&mut balances, bob.clone(), 10, alice.clone()),
+			Err("Insufficient balance")
+		);
+
+		// A rejected proposal loses its bond to the pot.
+		assert_eq!(
+			treasury.dispatch(
+				&mut balances,
+				RawOrigin::Signed(alice.clone()),
+				super::Call::RejectProposal { proposal_id: 0 }
+			),
+			Err("Bad origin")
+		);
+		assert_eq!(treasury.reject_proposal(&mut balances, 0), Ok(()));
+		assert_eq!(treasury.reject_proposal(&mut balances, 0), Err("Proposal does not exist"));
+		assert_eq!(balances.reserved_balance(&alice), 2);
+		assert_eq!(treasury.pot(&balances), 20);
+	}
+
+	#[test]
+	fn approved_proposals_are_paid_out_each_spend_period() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut balances: balances::Pallet<Test> = balances::Pallet::new();
+		let mut treasury: super::Pallet<Test> = super::Pallet::new();
+		balances.set_balance(&alice, 100);
+		let issued = balances.issue(100);
+		let _ = treasury.on_unbalanced(&mut balances, issued);
+
+		let _ = treasury.propose_spend(&mut balances, alice.clone(), 40, bob.clone());
+		let _ = treasury.propose_spend(&mut balances, alice.clone(), 70, bob.clone());
+		assert_eq!(treasury.approve_proposal(0), Ok(()));
+		assert_eq!(treasury.approve_proposal(0), Err("Proposal already approved"));
+		assert_eq!(treasury.approve_proposal(1), Ok(()));
+		assert_eq!(treasury.approve_proposal(5), Err("Proposal does not exist"));
+
+		treasury.on_finalize(&mut balances, 1);
+		assert_eq!(treasury.pot(&balances), 100);
+
+		// Only the first proposal fits in the pot; half of the rest is burned.
+		treasury.on_finalize(&mut balances, 2);
+		assert_eq!(balances.balance(&bob), 40);
+		assert_eq!(treasury.pot(&balances), 30);
+		assert_eq!(treasury.approvals(), &[1]);
+		assert!(treasury.proposal(0).is_none());
+		assert_eq!(balances.reserved_balance(&alice), 3);
+	}
+
+	#[test]
+	fn percent_of_balance() {
+		assert_eq!(Percent(50).mul_floor(31u128), 15);
+		assert_eq!(Percent(5).mul_floor(1_000u128), 50);
+		assert_eq!(Percent(100).mul_floor(u128::MAX), u128::MAX);
+		assert_eq!(Percent(0).mul_floor(u128::MAX), 0);
+	}
+}
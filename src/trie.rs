@@ -0,0 +1,218 @@
+//! A binary Merkle tree over the storage of the runtime, with the entries sorted by key. Its
+//! root is the state root in block headers, so a node can prove that an entry is part of the
+//! state of a block to a client that only knows the header.
+
+use std::collections::BTreeMap;
+
+use crate::{
+	codec::{self, Decode, Encode},
+	hashing::{self, Hash},
+};
+
+/// The storage of a runtime, as encoded keys and values.
+pub type Storage = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// The root of an empty tree.
+pub const EMPTY_ROOT: Hash = [0; 32];
+
+/// The path from a leaf to the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proof {
+	/// The position of the leaf among all leaves.
+	pub index: usize,
+	pub leaves: usize,
+	/// The hashes combined with the leaf on the way up, lowest first. A node without a sibling
+	/// is moved up as is.
+	pub siblings: Vec<Hash>,
+}
+
+impl Encode for Proof {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(self.index as u64, self.leaves as u64, &self.siblings).encode_to(dest);
+	}
+}
+
+/// Where the entry `key` of the storage item `item` of `pallet` is stored. Plain values use
+/// `()` as key.
+pub fn storage_key<K: Encode + ?Sized>(pallet: &str, item: &str, key: &K) -> Vec<u8> {
+	(pallet, item, key).encode()
+}
+
+/// Put `entries` into the storage item `item` of `pallet`, one entry per key.
+pub fn insert_entries<K: Encode, V: Encode>(
+	storage: &mut Storage,
+	pallet: &str,
+	item: &str,
+	entries: impl IntoIterator<Item = (K, V)>,
+) {
+	for (key, value) in entries {
+		storage.insert(storage_key(pallet, item, &key), value.encode());
+	}
+}
+
+/// Put the plain value `value` into the storage item `item` of `pallet`.
+pub fn insert_value<V: Encode + ?Sized>(
+	storage: &mut Storage,
+	pallet: &str,
+	item: &str,
+	value: &V,
+) {
+	storage.insert(storage_key(pallet, item, &()), value.encode());
+}
+
+/// The decoded keys and values of the storage item `item` of `pallet`.
+pub fn decode_entries<K: Decode, V: Decode>(
+	storage: &Storage,
+	pallet: &str,
+	item: &str,
+) -> Result<Vec<(K, V)>, &'static str> {
+	// Both names are length-prefixed, so no other item shares the prefix.
+	let prefix = (pallet, item).encode();
+	storage
+		.iter()
+		.filter_map(|(key, value)| key.strip_prefix(prefix.as_slice()).map(|key| (key, value)))
+		.map(|(key, value)| Ok((codec::decode_all(key)?, codec::decode_all(value)?)))
+		.collect()
+}
+
+/// The decoded plain value of the storage item `item` of `pallet`, if it is stored.
+pub fn decode_value<V: Decode>(
+	storage: &Storage,
+	pallet: &str,
+	item: &str,
+) -> Result<Option<V>, &'static str> {
+	Ok(decode_entries::<(), V>(storage, pallet, item)?.pop().map(|((), value)| value))
+}
+
+/// Overwrite `value` with the plain value of the storage item `item` of `pallet`, if it is
+/// stored.
+pub fn restore_value<V: Decode>(
+	storage: &Storage,
+	pallet: &str,
+	item: &str,
+	value: &mut V,
+) -> Result<(), &'static str> {
+	if let Some(stored) = decode_value(storage, pallet, item)? {
+		*value = stored;
+	}
+	Ok(())
+}
+
+fn leaf(key: &[u8], value: &[u8]) -> Hash {
+	hashing::blake2_256(&(key, value).encode())
+}
+
+fn node(left: &Hash, right: &Hash) -> Hash {
+	hashing::blake2_256(&(left, right).encode())
+}
+
+fn parents(level: &[Hash]) -> Vec<Hash> {
+	level
+		.chunks(2)
+		.map(|pair| match pair {
+			[left, right] => node(left, right),
+			[single] => *single,
+			_ => unreachable!(),
+		})
+		.collect()
+}
+
+pub fn root(storage: &Storage) -> Hash {
+	let mut level: Vec<Hash> = storage.iter().map(|(key, value)| leaf(key, value)).collect();
+	if level.is_empty() {
+		return EMPTY_ROOT;
+	}
+	while level.len() > 1 {
+		level = parents(&level);
+	}
+	level[0]
+}
+
+/// The value stored under `key`, with the proof that it is part of `storage`.
+pub fn prove(storage: &Storage, key: &[u8]) -> Option<(Vec<u8>, Proof)> {
+	let index = storage.keys().position(|k| k.as_slice() == key)?;
+	Some((storage[key].clone(), prove_index(storage, index)))
+}
+
+/// The proof for the leaf at `index`, which must be in `storage`.
+pub fn prove_index(storage: &Storage, index: usize) -> Proof {
+	let mut level: Vec<Hash> = storage.iter().map(|(key, value)| leaf(key, value)).collect();
+	let mut proof = Proof { index, leaves: level.len(), siblings: Vec::new() };
+
+	let mut position = index;
+	while level.len() > 1 {
+		if let Some(sibling) = level.get(position ^ 1) {
+			proof.siblings.push(*sibling);
+		}
+		level = parents(&level);
+		position /= 2;
+	}
+	proof
+}
+
+/// Whether `proof` shows that `value` is stored under `key` in the state with root `root`.
+pub fn verify(root: &Hash, key: &[u8], value: &[u8], proof: &Proof) -> bool {
+	if proof.index >= proof.leaves {
+		return false;
+	}
+	let mut hash = leaf(key, value);
+	let mut siblings = proof.siblings.iter();
+	let (mut position, mut width) = (proof.index, proof.leaves);
+	while width > 1 {
+		let has_sibling = position % 2 == 1 || position + 1 < width;
+		if has_sibling {
+			let Some(sibling) = siblings.next() else { return false };
+			hash = match position % 2 {
+				0 => node(&hash, sibling),
+				_ => node(sibling, &hash),
+			};
+		}
+		position /= 2;
+		width = width.div_ceil(2);
+	}
+	siblings.next().is_none() && hash == *root
+}
+
+#[cfg(test)]
+mod test {
+	use super::Storage;
+	use crate::codec::Encode;
+
+	fn storage(n: u8) -> Storage {
+		(0..n).map(|i| (vec![i], vec![i, i])).collect()
+	}
+
+	#[test]
+	fn proofs_verify_against_the_root() {
+		for n in 1..=7 {
+			let storage = storage(n);
+			let root = super::root(&storage);
+			for key in storage.keys() {
+				let (value, proof) = super::prove(&storage, key).unwrap();
+				assert!(super::verify(&root, key, &value, &proof));
+				assert!(!super::verify(&root, key, &[9], &proof));
+			}
+		}
+		assert_eq!(super::root(&Storage::new()), super::EMPTY_ROOT);
+		assert_eq!(super::prove(&storage(3), &[5]), None);
+	}
+
+	#[test]
+	fn root_changes_with_the_state() {
+		let mut storage = storage(4);
+		let root = super::root(&storage);
+		storage.insert(vec![1], vec![0]);
+		assert_ne!(super::root(&storage), root);
+	}
+
+	#[test]
+	fn entries_are_decoded_by_item() {
+		let mut storage = Storage::new();
+		storage.insert(super::storage_key("Balances", "free", "bob"), 5u128.encode());
+		storage.insert(super::storage_key("Balances", "reserved", "bob"), 1u128.encode());
+		let free = super::decode_entries::<String, u128>(&storage, "Balances", "free");
+		assert_eq!(free, Ok(vec![("bob".to_string(), 5)]));
+		let wrong_type = super::decode_entries::<String, u32>(&storage, "Balances", "free");
+		assert_eq!(wrong_type, Err("Trailing bytes"));
+	}
+}
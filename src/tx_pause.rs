@@ -0,0 +1,200 @@
+//! An emergency brake: root, or the collective, pauses single calls by the names of their pallet
+//! and call, e.g. `Balances` and `Transfer`, and the runtime rejects them when they are validated
+//! and dispatched until they are unpaused, without a runtime upgrade. Root itself is never
+//! paused, as with the call filter of system.
+
+use std::{collections::BTreeSet, marker::PhantomData};
+
+use crate::{
+	codec::{Decode, Encode},
+	metadata::{field, storage, variant, PalletMetadata},
+	migrations::{self, StorageVersion},
+	support::{ensure_root, DispatchResult, Weight},
+	system::{self, OriginFor},
+	trie::{self, storage_key, Storage},
+};
+
+/// The names of a pallet and one of its calls, as in the metadata.
+pub type CallName = (String, String);
+
+pub trait Config: system::Config {
+	/// The maximum number of calls paused at once.
+	const MAX_PAUSED: usize;
+
+	/// Whether the runtime has the call `call` of the pallet `pallet`.
+	fn call_exists(pallet: &str, call: &str) -> bool;
+
+	/// Check the origin of pausing and unpausing calls, root by default.
+	fn ensure_pause_origin(origin: OriginFor<Self>) -> DispatchResult {
+		ensure_root(origin)
+	}
+}
+
+#[derive(Debug, Clone)]
+pub enum Call {
+	Pause { pallet: String, call: String },
+	Unpause { pallet: String, call: String },
+}
+
+impl Encode for Call {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::Pause { pallet, call } => (0u8, pallet, call).encode_to(dest),
+			Call::Unpause { pallet, call } => (1u8, pallet, call).encode_to(dest),
+		}
+	}
+}
+
+impl Decode for Call {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => {
+				let (pallet, call) = Decode::decode(input)?;
+				Call::Pause { pallet, call }
+			},
+			1 => {
+				let (pallet, call) = Decode::decode(input)?;
+				Call::Unpause { pallet, call }
+			},
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl Call {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::Pause { .. } => 0,
+			Call::Unpause { .. } => 1,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::Pause { .. } | Call::Unpause { .. } => 1_000,
+		}
+	}
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	paused: BTreeSet<CallName>,
+	_config: PhantomData<T>,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self { paused: BTreeSet::new(), _config: PhantomData }
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		let name = || vec![field::<String>("pallet"), field::<String>("call")];
+		PalletMetadata {
+			name: "TxPause",
+			index: None,
+			calls: vec![variant("Pause", 0, name()), variant("Unpause", 1, name())],
+			storage: vec![storage::<BTreeSet<CallName>>("paused")],
+			events: Vec::new(),
+			errors: vec!["Already paused", "Not paused", "Too many paused calls", "Unknown call"],
+		}
+	}
+
+	pub fn is_paused(&self, pallet: &str, call: &str) -> bool {
+		self.paused.contains(&(pallet.to_string(), call.to_string()))
+	}
+
+	pub fn paused(&self) -> impl Iterator<Item = &CallName> {
+		self.paused.iter()
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "TxPause", STORAGE_VERSION);
+		for name in &self.paused {
+			storage.insert(storage_key("TxPause", "paused", name), ().encode());
+		}
+	}
+
+	/// Restore the paused calls `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult {
+		migrations::ensure_version(storage, "TxPause", STORAGE_VERSION)?;
+		let paused = trie::decode_entries::<CallName, ()>(storage, "TxPause", "paused")?;
+		self.paused = paused.into_iter().map(|(name, ())| name).collect();
+		Ok(())
+	}
+
+	pub fn pause(&mut self, pallet: String, call: String) -> DispatchResult {
+		if !T::call_exists(&pallet, &call) {
+			return Err("Unknown call");
+		}
+		if self.paused.len() >= T::MAX_PAUSED {
+			return Err("Too many paused calls");
+		}
+		if !self.paused.insert((pallet, call)) {
+			return Err("Already paused");
+		}
+		Ok(())
+	}
+
+	pub fn unpause(&mut self, pallet: String, call: String) -> DispatchResult {
+		if !self.paused.remove(&(pallet, call)) {
+			return Err("Not paused");
+		}
+		Ok(())
+	}
+
+	pub fn dispatch(&mut self, origin: OriginFor<T>, call: Call) -> DispatchResult {
+		T::ensure_pause_origin(origin)?;
+		match call {
+			Call::Pause { pallet, call } => self.pause(pallet, call),
+			Call::Unpause { pallet, call } => self.unpause(pallet, call),
+		}
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{mock::Test, support::RawOrigin};
+
+	impl super::Config for Test {
+		const MAX_PAUSED: usize = 2;
+
+		fn call_exists(pallet: &str, _call: &str) -> bool {
+			pallet == "Balances"
+		}
+	}
+
+	#[test]
+	fn root_pauses_and_unpauses_calls() {
+		let mut tx_pause: super::Pallet<Test> = super::Pallet::new();
+		let pause = |pallet: &str, call: &str| super::Call::Pause {
+			pallet: pallet.to_string(),
+			call: call.to_string(),
+		};
+		let signed = RawOrigin::Signed("alice".to_string());
+		assert_eq!(tx_pause.dispatch(signed, pause("Balances", "Transfer")), Err("Bad origin"));
+		assert_eq!(tx_pause.dispatch(RawOrigin::Root, pause("Balances", "Transfer")), Ok(()));
+		assert!(tx_pause.is_paused("Balances", "Transfer"));
+		assert!(!tx_pause.is_paused("Balances", "TransferAll"));
+		let again = tx_pause.dispatch(RawOrigin::Root, pause("Balances", "Transfer"));
+		assert_eq!(again, Err("Already paused"));
+		let unknown = tx_pause.dispatch(RawOrigin::Root, pause("Nothing", "Transfer"));
+		assert_eq!(unknown, Err("Unknown call"));
+		tx_pause.pause("Balances".into(), "TransferAll".into()).unwrap();
+		let full = tx_pause.pause("Balances".into(), "Reserve".into());
+		assert_eq!(full, Err("Too many paused calls"));
+
+		let unpause = super::Call::Unpause { pallet: "Balances".into(), call: "Transfer".into() };
+		assert_eq!(tx_pause.dispatch(RawOrigin::Root, unpause.clone()), Ok(()));
+		assert!(!tx_pause.is_paused("Balances", "Transfer"));
+		assert_eq!(tx_pause.dispatch(RawOrigin::Root, unpause), Err("Not paused"));
+	}
+}
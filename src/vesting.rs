@@ -0,0 +1,381 @@
+use std::collections::BTreeMap;
+
+use num::{CheckedAdd, CheckedMul, CheckedSub, Zero};
+
+use crate::{
+	balances::{self, LockIdentifier},
+	codec::{Decode, Encode},
+	metadata::{field, storage, variant, PalletMetadata, Registry, TypeDef, TypeInfo},
+	migrations::{self, StorageVersion},
+	support::{ensure_signed, DispatchResult, Weight},
+	system::OriginFor,
+	trie::{self, Storage},
+};
+
+const VESTING_ID: LockIdentifier = *b"vesting ";
+
+pub trait Config: balances::Config {
+	/// The minimum amount that can be transferred under a vesting schedule.
+	const MIN_VESTED_TRANSFER: Self::Balance;
+	/// The maximum number of vesting schedules an account can have.
+	const MAX_SCHEDULES: usize;
+
+	fn block_number_to_balance(block_number: Self::BlockNumber) -> Self::Balance;
+}
+
+/// `locked` funds, unlocking by `per_block` every block after `starting_block`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VestingInfo<Balance, BlockNumber> {
+	pub locked: Balance,
+	pub per_block: Balance,
+	pub starting_block: BlockNumber,
+}
+
+impl<Balance: Encode, BlockNumber: Encode> Encode for VestingInfo<Balance, BlockNumber> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(&self.locked, &self.per_block, &self.starting_block).encode_to(dest);
+	}
+}
+
+impl<Balance: Decode, BlockNumber: Decode> Decode for VestingInfo<Balance, BlockNumber> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		let (locked, per_block, starting_block) = Decode::decode(input)?;
+		Ok(VestingInfo { locked, per_block, starting_block })
+	}
+}
+
+impl<Balance: TypeInfo, BlockNumber: TypeInfo> TypeInfo for VestingInfo<Balance, BlockNumber> {
+	fn type_def(registry: &mut Registry) -> TypeDef {
+		TypeDef::Composite(vec![
+			registry.field::<Balance>("locked"),
+			registry.field::<Balance>("per_block"),
+			registry.field::<BlockNumber>("starting_block"),
+		])
+	}
+}
+
+pub type VestingInfoOf<T> =
+	VestingInfo<<T as balances::Config>::Balance, <T as crate::system::Config>::BlockNumber>;
+
+#[derive(Debug, Clone)]
+pub enum Call<T: Config> {
+	Vest,
+	VestOther { target: T::AccountId },
+	VestedTransfer { target: T::AccountId, schedule: VestingInfoOf<T> },
+}
+
+impl<T: Config> Encode for Call<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			Call::Vest => 0u8.encode_to(dest),
+			Call::VestOther { target } => (1u8, target).encode_to(dest),
+			Call::VestedTransfer { target, schedule } => (2u8, target, schedule).encode_to(dest),
+		}
+	}
+}
+
+impl<T: Config> Decode for Call<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, &'static str> {
+		Ok(match u8::decode(input)? {
+			0 => Call::Vest,
+			1 => Call::VestOther { target: Decode::decode(input)? },
+			2 => Call::VestedTransfer {
+				target: Decode::decode(input)?,
+				schedule: Decode::decode(input)?,
+			},
+			_ => return Err("Invalid call"),
+		})
+	}
+}
+
+impl<T: Config> Call<T> {
+	/// The index of the call in the pallet, the first byte of its encoding.
+	pub fn index(&self) -> u8 {
+		match self {
+			Call::Vest => 0,
+			Call::VestOther { .. } => 1,
+			Call::VestedTransfer { .. } => 2,
+		}
+	}
+
+	pub fn weight(&self) -> Weight {
+		match self {
+			Call::Vest | Call::VestOther { .. } | Call::VestedTransfer { .. } => 10_000,
+		}
+	}
+}
+
+pub const STORAGE_VERSION: StorageVersion = 0;
+
+#[derive(Debug, Clone)]
+pub struct Pallet<T: Config> {
+	vesting: BTreeMap<T::AccountId, Vec<VestingInfoOf<T>>>,
+}
+
+impl<T: Config> Pallet<T> {
+	pub fn new() -> Self {
+		Self { vesting: BTreeMap::new() }
+	}
+
+	pub fn metadata() -> PalletMetadata {
+		PalletMetadata {
+			name: "Vesting",
+			index: None,
+			calls: vec![
+				variant("Vest", 0, Vec::new()),
+				variant("VestOther", 1, vec![field::<T::AccountId>("target")]),
+				variant(
+					"VestedTransfer",
+					2,
+					vec![field::<T::AccountId>("target"), field::<VestingInfoOf<T>>("schedule")],
+				),
+			],
+			storage: vec![storage::<BTreeMap<T::AccountId, Vec<VestingInfoOf<T>>>>("vesting")],
+			events: Vec::new(),
+			errors: vec![
+				"Account is not vesting",
+				"Amount vested below minimum",
+				"Invalid vesting schedule",
+				"Too many vesting schedules",
+			],
+		}
+	}
+
+	pub fn storage_entries(&self, storage: &mut Storage) {
+		migrations::set_version(storage, "Vesting", STORAGE_VERSION);
+		trie::insert_entries(storage, "Vesting", "vesting", &self.vesting);
+	}
+
+	/// Restore the vesting schedules `storage_entries` put in `storage`.
+	pub fn restore_entries(&mut self, storage: &Storage) -> DispatchResult {
+		migrations::ensure_version(storage, "Vesting", STORAGE_VERSION)?;
+		self.vesting = trie::decode_entries(storage, "Vesting", "vesting")?.into_iter().collect();
+		Ok(())
+	}
+
+	/// Vesting moves funds and updates locks, so calls are dispatched together with the
+	/// balances pallet and the current block number.
+	pub fn dispatch(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		now: T::BlockNumber,
+		origin: OriginFor<T>,
+		call: Call<T>,
+	) -> DispatchResult {
+		let caller = ensure_signed(origin)?;
+		match call {
+			Call::Vest => self.vest(balances, now, caller),
+			Call::VestOther { target } => self.vest(balances, now, target),
+			Call::VestedTransfer { target, schedule } => {
+				self.vested_transfer(balances, now, caller, target, schedule)
+			},
+		}
+	}
+
+	pub fn vesting(&self, who: &T::AccountId) -> &[VestingInfoOf<T>] {
+		self.vesting.get(who).map(|schedules| schedules.as_slice()).unwrap_or_default()
+	}
+
+	/// The amount of a schedule that is still locked at block `now`.
+	pub fn locked_at(schedule: &VestingInfoOf<T>, now: T::BlockNumber) -> T::Balance {
+		let elapsed = now.checked_sub(&schedule.starting_block).unwrap_or(T::BlockNumber::zero());
+		let vested = T::block_number_to_balance(elapsed)
+			.checked_mul(&schedule.per_block)
+			.unwrap_or(schedule.locked);
+		schedule.locked.checked_sub(&vested).unwrap_or(T::Balance::zero())
+	}
+
+	/// The total amount still locked by vesting for `who` at block `now`.
+	pub fn vesting_balance(&self, who: &T::AccountId, now: T::BlockNumber) -> T::Balance {
+		self.vesting(who).iter().fold(T::Balance::zero(), |total, schedule| {
+			total.checked_add(&Self::locked_at(schedule, now)).unwrap_or(total)
+		})
+	}
+
+	/// Release the funds of `who` that have vested by block `now`.
+	pub fn vest(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		now: T::BlockNumber,
+		who: T::AccountId,
+	) -> DispatchResult {
+		if !self.vesting.contains_key(&who) {
+			return Err("Account is not vesting");
+		}
+		self.update_lock(balances, now, &who);
+		Ok(())
+	}
+
+	/// Transfer `schedule.locked` from `caller` to `target`, locked under `schedule`.
+	pub fn vested_transfer(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		now: T::BlockNumber,
+		caller: T::AccountId,
+		target: T::AccountId,
+		schedule: VestingInfoOf<T>,
+	) -> DispatchResult {
+		if schedule.locked < T::MIN_VESTED_TRANSFER {
+			return Err("Amount vested below minimum");
+		}
+		if self.vesting(&target).len() >= T::MAX_SCHEDULES {
+			return Err("Too many vesting schedules");
+		}
+
+		balances.transfer(caller, target.clone(), schedule.locked)?;
+		self.add_vesting_schedule(balances, now, &target, schedule)
+	}
+
+	/// Lock funds `who` already owns under `schedule`, e.g. for genesis allocations.
+	pub fn add_vesting_schedule(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		now: T::BlockNumber,
+		who: &T::AccountId,
+		schedule: VestingInfoOf<T>,
+	) -> DispatchResult {
+		if schedule.locked.is_zero() || schedule.per_block.is_zero() {
+			return Err("Invalid vesting schedule");
+		}
+		if self.vesting(who).len() >= T::MAX_SCHEDULES {
+			return Err("Too many vesting schedules");
+		}
+
+		self.vesting.entry(who.clone()).or_default().push(schedule);
+		self.update_lock(balances, now, who);
+		Ok(())
+	}
+
+	/// Drop schedules that fully vested and lock whatever is still vesting.
+	fn update_lock(
+		&mut self,
+		balances: &mut balances::Pallet<T>,
+		now: T::BlockNumber,
+		who: &T::AccountId,
+	) {
+		let locked = self.vesting_balance(who, now);
+		if let Some(schedules) = self.vesting.get_mut(who) {
+			schedules.retain(|schedule| !Self::locked_at(schedule, now).is_zero());
+			if schedules.is_empty() {
+				self.vesting.remove(who);
+			}
+		}
+
+		if locked.is_zero() {
+			balances.remove_lock(VESTING_ID, who);
+		} else {
+			balances.set_lock(VESTING_ID, who, locked);
+		}
+	}
+}
+
+impl<T: Config> Default for Pallet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::VestingInfo;
+	use crate::{balances, mock::Test};
+
+	impl super::Config for Test {
+		const MIN_VESTED_TRANSFER: u128 = 10;
+		const MAX_SCHEDULES: usize = 2;
+
+		fn block_number_to_balance(block_number: u32) -> u128 {
+			block_number.into()
+		}
+	}
+
+	fn schedule(locked: u128, per_block: u128, starting_block: u32) -> VestingInfo<u128, u32> {
+		VestingInfo { locked, per_block, starting_block }
+	}
+
+	#[test]
+	fn locked_amount_decreases_linearly() {
+		type Vesting = super::Pallet<Test>;
+		let info = schedule(100, 10, 5);
+
+		assert_eq!(Vesting::locked_at(&info, 0), 100);
+		assert_eq!(Vesting::locked_at(&info, 5), 100);
+		assert_eq!(Vesting::locked_at(&info, 8), 70);
+		assert_eq!(Vesting::locked_at(&info, 15), 0);
+		assert_eq!(Vesting::locked_at(&info, 100), 0);
+	}
+
+	#[test]
+	fn vested_transfer_locks_funds() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut balances: balances::Pallet<Test> = balances::Pallet::new();
+		let mut vesting: super::Pallet<Test> = super::Pallet::new();
+		balances.set_balance(&alice, 200);
+
+		assert_eq!(
+			vesting.vested_transfer(
+				&mut balances,
+				0,
+				alice.clone(),
+				bob.clone(),
+				schedule(5, 1, 0)
+			),
+			Err("Amount vested below minimum")
+		);
+		assert_eq!(
+			vesting.vested_transfer(
+				&mut balances,
+				0,
+				alice.clone(),
+				bob.clone(),
+				schedule(100, 10, 0)
+			),
+			Ok(())
+		);
+		assert_eq!(balances.balance(&bob), 100);
+		assert_eq!(balances.locked_balance(&bob), 100);
+		assert_eq!(balances.transfer(bob.clone(), alice.clone(), 1), Err("Balance is locked"));
+	}
+
+	#[test]
+	fn vest_releases_matured_funds() {
+		let alice = "alice".to_string();
+		let bob = "bob".to_string();
+		let mut balances: balances::Pallet<Test> = balances::Pallet::new();
+		let mut vesting: super::Pallet<Test> = super::Pallet::new();
+		balances.set_balance(&alice, 100);
+
+		assert_eq!(vesting.vest(&mut balances, 0, alice.clone()), Err("Account is not vesting"));
+		let _ = vesting.add_vesting_schedule(&mut balances, 0, &alice, schedule(100, 10, 0));
+
+		assert_eq!(vesting.vest(&mut balances, 4, alice.clone()), Ok(()));
+		assert_eq!(balances.locked_balance(&alice), 60);
+		assert_eq!(balances.transfer(alice.clone(), bob.clone(), 40), Ok(()));
+		assert_eq!(balances.transfer(alice.clone(), bob.clone(), 1), Err("Balance is locked"));
+
+		assert_eq!(vesting.vest(&mut balances, 10, alice.clone()), Ok(()));
+		assert_eq!(balances.locked_balance(&alice), 0);
+		assert!(vesting.vesting(&alice).is_empty());
+	}
+
+	#[test]
+	fn multiple_schedules_add_up() {
+		let alice = "alice".to_string();
+		let mut balances: balances::Pallet<Test> = balances::Pallet::new();
+		let mut vesting: super::Pallet<Test> = super::Pallet::new();
+		balances.set_balance(&alice, 300);
+
+		let _ = vesting.add_vesting_schedule(&mut balances, 0, &alice, schedule(100, 10, 0));
+		let _ = vesting.add_vesting_schedule(&mut balances, 0, &alice, schedule(100, 50, 0));
+		assert_eq!(
+			vesting.add_vesting_schedule(&mut balances, 0, &alice, schedule(100, 1, 0)),
+			Err("Too many vesting schedules")
+		);
+
+		assert_eq!(vesting.vesting_balance(&alice, 1), 140);
+		let _ = vesting.vest(&mut balances, 2, alice.clone());
+		assert_eq!(vesting.vesting(&alice).len(), 1);
+		assert_eq!(balances.locked_balance(&alice), 80);
+	}
+}
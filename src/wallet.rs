@@ -0,0 +1,306 @@
+//! What end users need to use the chain without writing Rust: secret seeds, the extrinsics of
+//! common calls, and a client for the REST API of a node, see the `rest` module.
+//!
+//! Extrinsics are signed with the keys of the `keystore`, or by an external signer, e.g. a
+//! hardware wallet: an `UnsignedExtrinsic` gives the exact bytes to sign, and takes the detached
+//! signature back, so the key never has to be on the machine that submits it.
+
+use std::{
+	io::{self, Read, Write},
+	net::TcpStream,
+};
+
+use crate::{
+	balances,
+	codec::Encode,
+	crypto,
+	hashing::{self, Hash},
+	keystore::Signer,
+	proof_of_existence,
+	signature::{Scheme, Verify},
+	support, types, Runtime, RuntimeCall,
+};
+
+/// A new random secret seed.
+pub fn generate_seed() -> io::Result<[u8; 32]> {
+	crypto::random_bytes()
+}
+
+/// A hex-encoded secret seed, as printed by `node key generate`.
+pub fn parse_seed(seed: &str) -> Result<[u8; 32], &'static str> {
+	hashing::from_hex(seed.trim_start_matches("0x"))
+		.and_then(|seed| seed.try_into().ok())
+		.ok_or("Invalid seed")
+}
+
+/// The seed of the development account `name`. Everyone knows it, so the account must only hold
+/// funds on development chains.
+pub fn dev_seed(name: &str) -> [u8; 32] {
+	hashing::blake2_256(format!("//{}", name).as_bytes())
+}
+
+/// The sr25519 key of the development account `name`.
+pub fn dev_signer(name: &str) -> Signer {
+	Signer::new(Scheme::Sr25519, dev_seed(name))
+}
+
+pub fn dev_account(name: &str) -> types::AccountId {
+	dev_signer(name).account().clone()
+}
+
+/// A transfer to `to`, an account or any other address, e.g. a registered name.
+pub fn transfer(to: impl Into<types::Address>, amount: types::Balance) -> RuntimeCall {
+	RuntimeCall::Balances(balances::Call::Transfer { to: to.into(), amount })
+}
+
+pub fn create_claim(claim: types::Content) -> RuntimeCall {
+	RuntimeCall::ProofOfExistence(proof_of_existence::Call::CreateClaim { claim, note: None })
+}
+
+/// How many blocks the extrinsics a `Client` prepares can be included in.
+pub const MORTAL_PERIOD: u64 = 64;
+
+/// An extrinsic waiting for the signature of its caller.
+#[derive(Clone)]
+pub struct UnsignedExtrinsic {
+	pub caller: types::AccountId,
+	pub call: RuntimeCall,
+	pub extra: types::SignedExtra,
+	pub genesis_hash: Hash,
+	/// The hash of the birth block of a mortal era, the genesis hash otherwise.
+	pub birth_hash: Hash,
+}
+
+impl UnsignedExtrinsic {
+	/// `call` from `caller` at `nonce`, on the chain with `genesis_hash`. It is immortal unless
+	/// made `mortal`.
+	pub fn new(
+		caller: types::AccountId,
+		call: RuntimeCall,
+		nonce: types::Nonce,
+		genesis_hash: Hash,
+	) -> Self {
+		let extra = support::SignedExtra { nonce, era: support::Era::Immortal, tip: 0 };
+		Self { caller, call, extra, genesis_hash, birth_hash: genesis_hash }
+	}
+
+	/// The extrinsic, valid only in the `period` blocks after `birth_block`, whose hash is
+	/// `birth_hash` as `Runtime::recent_block_hash` gives it.
+	pub fn mortal(self, birth_block: types::BlockNumber, birth_hash: Hash, period: u64) -> Self {
+		let era = support::Era::Mortal { birth_block: birth_block.into(), period };
+		Self { extra: support::SignedExtra { era, ..self.extra }, birth_hash, ..self }
+	}
+
+	/// The extrinsic with `tip` paid to the block author, for a higher priority.
+	pub fn with_tip(self, tip: types::Balance) -> Self {
+		Self { extra: support::SignedExtra { tip, ..self.extra }, ..self }
+	}
+
+	/// The exact bytes the caller signs.
+	pub fn signing_payload(&self) -> Vec<u8> {
+		support::signing_payload(&self.call, &self.extra, &self.genesis_hash, &self.birth_hash)
+	}
+
+	/// The extrinsic with the detached `signature`. It is checked, so a signature of another
+	/// key or payload is noticed before the extrinsic is submitted.
+	pub fn attach(self, signature: types::Signature) -> Result<types::Extrinsic, &'static str> {
+		if !signature.verify(&self.signing_payload(), &self.caller) {
+			return Err("Bad signature");
+		}
+		let signature = Some((signature, self.extra));
+		Ok(support::Extrinsic { caller: Some(self.caller), call: self.call, signature })
+	}
+}
+
+/// The value of `name` in a flat JSON object of numbers and plain strings, as the REST API
+/// answers with.
+pub(crate) fn field<'a>(json: &'a str, name: &str) -> Option<&'a str> {
+	let (_, rest) = json.split_once(&format!("\"{}\":", name))?;
+	let end = rest.find([',', '}']).unwrap_or(rest.len());
+	Some(rest[..end].trim().trim_matches('"'))
+}
+
+/// Talks to the REST API of a node.
+pub struct Client {
+	address: String,
+}
+
+impl Client {
+	pub fn new(address: impl Into<String>) -> Self {
+		Self { address: address.into() }
+	}
+
+	/// The body of the response, which is an error unless the node accepted the request.
+	fn request(&self, method: &str, path: &str, body: &str) -> io::Result<String> {
+		let mut stream = TcpStream::connect(&self.address)?;
+		write!(
+			stream,
+			"{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+			method,
+			path,
+			self.address,
+			body.len(),
+			body
+		)?;
+		let mut response = String::new();
+		stream.read_to_string(&mut response)?;
+
+		let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+		let status = head.split_whitespace().nth(1).and_then(|status| status.parse::<u16>().ok());
+		match status {
+			Some(200..=299) => Ok(body.to_string()),
+			Some(_) => Err(io::Error::other(body.to_string())),
+			None => Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid response")),
+		}
+	}
+
+	/// The balances of `account`, as JSON.
+	pub fn balance(&self, account: &types::AccountId) -> io::Result<String> {
+		self.request("GET", &format!("/balance/{}", account), "")
+	}
+
+	/// The block `number` of the best chain, with its calls and events, as JSON.
+	pub fn block(&self, number: types::BlockNumber) -> io::Result<String> {
+		self.request("GET", &format!("/block/{}", number), "")
+	}
+
+	/// The claim of `content`, as JSON.
+	pub fn claim(&self, content: &types::Content) -> io::Result<String> {
+		let hash = proof_of_existence::Pallet::<Runtime>::hash_content(content);
+		self.request("GET", &format!("/claim/{}", hashing::hex(&hash)), "")
+	}
+
+	/// The nonce the next extrinsic of `account` is signed at, counting the ones in the pool.
+	pub fn nonce(&self, account: &types::AccountId) -> io::Result<types::Nonce> {
+		let body = self.request("GET", &format!("/nonce/{}", account), "")?;
+		field(&body, "nonce")
+			.and_then(|nonce| nonce.parse().ok())
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid nonce"))
+	}
+
+	/// The genesis hash of the chain of the node, which signatures commit to.
+	pub fn genesis_hash(&self) -> io::Result<Hash> {
+		let body = self.request("GET", "/genesis", "")?;
+		field(&body, "hash")
+			.and_then(hashing::from_hex)
+			.and_then(|hash| hash.try_into().ok())
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid genesis hash"))
+	}
+
+	/// The number of the best block of the node, and its hash as mortal eras commit to it.
+	pub fn best_block(&self) -> io::Result<(types::BlockNumber, Hash)> {
+		let body = self.request("GET", "/best", "")?;
+		let number = field(&body, "number").and_then(|number| number.parse().ok());
+		let hash = field(&body, "hash")
+			.and_then(hashing::from_hex)
+			.and_then(|hash| hash.try_into().ok());
+		number
+			.zip(hash)
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid best block"))
+	}
+
+	/// The extrinsic of `call` from `caller` to sign, at its next nonce on the chain of the node.
+	/// It is mortal, born at the best block, so it isn't included once it's `MORTAL_PERIOD`
+	/// blocks old.
+	pub fn prepare(
+		&self,
+		caller: types::AccountId,
+		call: RuntimeCall,
+	) -> io::Result<UnsignedExtrinsic> {
+		let nonce = self.nonce(&caller)?;
+		let unsigned = UnsignedExtrinsic::new(caller, call, nonce, self.genesis_hash()?);
+		let (best, best_hash) = self.best_block()?;
+		Ok(unsigned.mortal(best, best_hash, MORTAL_PERIOD))
+	}
+
+	/// Submit `extrinsic` to the transaction pool of the node. Returns its hash, as JSON.
+	pub fn submit(&self, extrinsic: &types::Extrinsic) -> io::Result<String> {
+		self.request("POST", "/extrinsic", &hashing::hex(&extrinsic.encode()))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::{net::TcpListener, thread};
+
+	use super::{dev_account, dev_signer, Client, UnsignedExtrinsic};
+	use crate::{
+		chain::Chain, codec::Encode, indexer::Indexer, metrics::Metrics, rest, support,
+		transaction_pool::TransactionPool, Runtime,
+	};
+
+	#[test]
+	fn seeds_round_trip_through_hex() {
+		let seed = super::generate_seed().unwrap();
+		let hex = format!("0x{}", crate::hashing::hex(&seed));
+		assert_eq!(super::parse_seed(&hex), Ok(seed));
+		assert_eq!(super::parse_seed("0x1234"), Err("Invalid seed"));
+		assert_ne!(dev_account("alice"), dev_account("bob"));
+	}
+
+	#[test]
+	fn extrinsics_are_submitted_to_a_node() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let client = Client::new(listener.local_addr().unwrap().to_string());
+		let node = thread::spawn(move || {
+			let mut genesis = Runtime::new();
+			genesis.balances.set_balance(&dev_account("alice"), 100);
+			let (chain, mut pool) = (Chain::new(genesis), TransactionPool::new());
+			for _ in 0..7 {
+				let (stream, _) = listener.accept().unwrap();
+				rest::handle_connection(
+					&chain,
+					&Metrics::new(),
+					&Indexer::new(),
+					&mut pool,
+					stream,
+				)
+				.unwrap();
+			}
+			pool
+		});
+
+		let balance = client.balance(&dev_account("alice")).unwrap();
+		let expected =
+			format!(r#"{{"account":"{}","free":100,"reserved":0}}"#, dev_account("alice"));
+		assert_eq!(balance, expected);
+		let transfer = client.prepare(dev_account("alice"), super::transfer(dev_account("bob"), 5));
+		let transfer = transfer.unwrap();
+		assert_eq!(transfer.extra.nonce, 0);
+		let era = support::Era::Mortal { birth_block: 0, period: super::MORTAL_PERIOD };
+		assert_eq!((transfer.extra.era, transfer.birth_hash), (era, transfer.genesis_hash));
+		let signature = dev_signer("alice").sign_payload(&transfer.signing_payload());
+		let genesis_hash = transfer.genesis_hash;
+		let submitted = client.submit(&transfer.attach(signature).unwrap()).unwrap();
+		assert!(submitted.starts_with(r#"{"hash":"#));
+		assert_eq!(client.nonce(&dev_account("alice")).unwrap(), 1);
+		let unfunded =
+			dev_signer("bob").sign(super::create_claim("document".to_string()), 0, genesis_hash);
+		let error = client.submit(&unfunded).unwrap_err();
+		assert_eq!(error.to_string(), r#"{"error":"Invalid transaction: Payment"}"#);
+		assert_eq!(node.join().unwrap().len(), 1);
+	}
+
+	#[test]
+	fn detached_signatures_are_attached_only_if_they_verify() {
+		let alice = dev_account("alice");
+		let unsigned = UnsignedExtrinsic::new(
+			alice.clone(),
+			super::transfer(dev_account("bob"), 5),
+			4,
+			[1; 32],
+		);
+		let payload = unsigned.signing_payload();
+		let wrong_key = dev_signer("bob").sign_payload(&payload);
+		assert_eq!(unsigned.clone().attach(wrong_key).err(), Some("Bad signature"));
+		let mut other = unsigned.clone();
+		other.extra.nonce = 5;
+		let wrong_payload = dev_signer("alice").sign_payload(&other.signing_payload());
+		assert_eq!(unsigned.clone().attach(wrong_payload).err(), Some("Bad signature"));
+
+		let signature = dev_signer("alice").sign_payload(&payload);
+		let extrinsic = unsigned.attach(signature).unwrap();
+		let signed = dev_signer("alice").sign(super::transfer(dev_account("bob"), 5), 4, [1; 32]);
+		assert_eq!(extrinsic.encode(), signed.encode());
+	}
+}
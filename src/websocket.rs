@@ -0,0 +1,198 @@
+//! The subset of WebSocket (RFC 6455) the `rpc` module serves subscriptions over: the opening
+//! handshake of a client over plain TCP, and unfragmented frames. Clients mask the frames they
+//! send and the server doesn't, as the protocol requires.
+//!
+//! Frames are parsed from a buffer rather than read from the connection, so a server can read
+//! what a client sent so far without waiting for the rest of a frame.
+
+use std::io::{self, Read, Write};
+
+use crate::hashing;
+
+/// What the key of a client is hashed with to answer its handshake.
+pub const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+pub const TEXT: u8 = 0x1;
+pub const BINARY: u8 = 0x2;
+pub const CLOSE: u8 = 0x8;
+pub const PING: u8 = 0x9;
+pub const PONG: u8 = 0xa;
+
+/// The flag of the last frame of a message, which every frame of the server is.
+const FIN: u8 = 0x80;
+const MASKED: u8 = 0x80;
+/// The longest handshake read from a client.
+const MAX_HANDSHAKE_LEN: usize = 8_192;
+/// The largest frame a client may send, which is plenty for a subscription request.
+pub const MAX_FRAME_LEN: usize = 1 << 16;
+
+fn invalid(error: &'static str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Standard base64 with padding, as the handshake answer is written.
+fn base64(bytes: &[u8]) -> String {
+	const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+	let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+	for chunk in bytes.chunks(3) {
+		let group = chunk
+			.iter()
+			.enumerate()
+			.fold(0u32, |group, (i, byte)| group | u32::from(*byte) << (16 - 8 * i));
+		for i in 0..4 {
+			if i <= chunk.len() {
+				out.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+			} else {
+				out.push('=');
+			}
+		}
+	}
+	out
+}
+
+/// The `Sec-WebSocket-Accept` answer to the `Sec-WebSocket-Key` of a client.
+pub fn accept_key(key: &str) -> String {
+	base64(&hashing::sha1(format!("{}{}", key.trim(), GUID).as_bytes()))
+}
+
+/// Read the opening handshake of a client from `stream` and accept it. Requests that aren't a
+/// WebSocket upgrade are answered with an error.
+pub fn handshake(stream: &mut (impl Read + Write)) -> io::Result<()> {
+	// Read a byte at a time, so nothing after the handshake is read with it.
+	let mut request = Vec::new();
+	while !request.ends_with(b"\r\n\r\n") {
+		if request.len() == MAX_HANDSHAKE_LEN {
+			return Err(invalid("Handshake too long"));
+		}
+		let mut byte = [0];
+		stream.read_exact(&mut byte)?;
+		request.push(byte[0]);
+	}
+	let request = String::from_utf8_lossy(&request);
+	let mut lines = request.split("\r\n");
+	let is_get = lines.next().is_some_and(|line| line.starts_with("GET "));
+	let headers: Vec<_> = lines.filter_map(|line| line.split_once(':')).collect();
+	let header = |name: &str| {
+		let mut matching = headers.iter().filter(|(key, _)| key.trim().eq_ignore_ascii_case(name));
+		matching.next().map(|(_, value)| value.trim())
+	};
+	let upgrade = header("upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+	match header("sec-websocket-key") {
+		Some(key) if is_get && upgrade => write!(
+			stream,
+			"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+			 Sec-WebSocket-Accept: {}\r\n\r\n",
+			accept_key(key)
+		),
+		_ => {
+			stream.write_all(b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n")?;
+			Err(invalid("Not a WebSocket handshake"))
+		},
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+	pub opcode: u8,
+	pub payload: Vec<u8>,
+}
+
+impl Frame {
+	pub fn text(text: &str) -> Self {
+		Self { opcode: TEXT, payload: text.as_bytes().to_vec() }
+	}
+
+	/// The frame a client sent at the start of `buffer` and its length, or `None` if it wasn't
+	/// received whole yet.
+	pub fn parse(buffer: &[u8]) -> Result<Option<(Self, usize)>, &'static str> {
+		let [first, second, rest @ ..] = buffer else {
+			return Ok(None);
+		};
+		if first & FIN == 0 || first & 0x0f == 0 {
+			return Err("Fragmented frame");
+		}
+		if second & MASKED == 0 {
+			return Err("Unmasked frame");
+		}
+		let (len, rest) = match second & 0x7f {
+			126 => match rest.split_first_chunk::<2>() {
+				Some((len, rest)) => (u16::from_be_bytes(*len) as u64, rest),
+				None => return Ok(None),
+			},
+			127 => match rest.split_first_chunk::<8>() {
+				Some((len, rest)) => (u64::from_be_bytes(*len), rest),
+				None => return Ok(None),
+			},
+			len => (u64::from(len), rest),
+		};
+		if len > MAX_FRAME_LEN as u64 {
+			return Err("Frame too long");
+		}
+		let len = len as usize;
+		let Some((mask, rest)) = rest.split_first_chunk::<4>() else {
+			return Ok(None);
+		};
+		let Some(payload) = rest.get(..len) else {
+			return Ok(None);
+		};
+		let payload = payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]).collect();
+		let frame = Self { opcode: first & 0x0f, payload };
+		Ok(Some((frame, buffer.len() - rest.len() + len)))
+	}
+
+	/// The encoding of the frame, masked with `mask` if a client sends it.
+	pub fn encode(&self, mask: Option<[u8; 4]>) -> Vec<u8> {
+		let mut out = vec![FIN | self.opcode];
+		let masked = if mask.is_some() { MASKED } else { 0 };
+		match self.payload.len() {
+			len @ 0..=125 => out.push(masked | len as u8),
+			len @ 126..=0xffff => {
+				out.push(masked | 126);
+				out.extend((len as u16).to_be_bytes());
+			},
+			len => {
+				out.push(masked | 127);
+				out.extend((len as u64).to_be_bytes());
+			},
+		}
+		match mask {
+			Some(mask) => {
+				out.extend(mask);
+				out.extend(self.payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+			},
+			None => out.extend(&self.payload),
+		}
+		out
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{Frame, TEXT};
+
+	#[test]
+	fn handshakes_are_answered_with_the_accept_key() {
+		// The example of RFC 6455.
+		assert_eq!(super::accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+		assert_eq!(super::base64(b"ab"), "YWI=");
+
+		let request = "GET /chat HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+			Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+		let mut stream = std::io::Cursor::new(request.as_bytes().to_vec());
+		super::handshake(&mut stream).unwrap();
+		let response = String::from_utf8(stream.into_inner()[request.len()..].to_vec()).unwrap();
+		assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+		assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+	}
+
+	#[test]
+	fn frames_are_parsed_once_received_whole() {
+		let frame = Frame::text(&"x".repeat(200));
+		let encoded = frame.encode(Some([1, 2, 3, 4]));
+		assert_eq!(Frame::parse(&encoded[..100]), Ok(None));
+		assert_eq!(Frame::parse(&encoded), Ok(Some((frame.clone(), encoded.len()))));
+		// The frames of the server aren't masked, which clients may send only masked.
+		assert_eq!(frame.encode(None)[..4], [0x80 | TEXT, 126, 0, 200]);
+		assert_eq!(Frame::parse(&frame.encode(None)), Err("Unmasked frame"));
+	}
+}
@@ -0,0 +1,26 @@
+//! The weights of the benchmarked calls, one unit being a nanosecond of execution, so a block
+//! of `MAX_BLOCK_WEIGHT` takes 20ms to execute. The weights decide how full blocks are, and so
+//! how fees move, see `transaction_payment`.
+//!
+//! Generated by `blockchain benchmark`, see `benchmarking`: regenerate it on the reference
+//! machine with `blockchain benchmark > src/weights.rs` after changing a benchmarked call.
+
+use crate::support::Weight;
+
+pub mod balances {
+	use super::Weight;
+
+	pub const TRANSFER: Weight = 10_000;
+	pub const FORCE_TRANSFER: Weight = 10_000;
+	pub const SET_BALANCE: Weight = 5_000;
+}
+
+pub mod proof_of_existence {
+	use super::Weight;
+
+	pub const CREATE_CLAIM: Weight = 5_000;
+	pub const REVOKE_CLAIM: Weight = 5_000;
+	pub const TRANSFER_CLAIM: Weight = 5_000;
+	pub const ATTEST: Weight = 5_000;
+	pub const UPDATE_CLAIM: Weight = 5_000;
+}